@@ -189,10 +189,14 @@ impl PerformanceComparison {
     
     fn program_has_concurrency(&self, program: &[crate::vm::OpCode]) -> bool {
         program.iter().any(|op| matches!(op, 
-            crate::vm::OpCode::Spawn | crate::vm::OpCode::Receive | crate::vm::OpCode::ReceiveMatch(_) | 
-            crate::vm::OpCode::Yield | crate::vm::OpCode::Send(_) | crate::vm::OpCode::Monitor(_) | 
+            crate::vm::OpCode::Spawn | crate::vm::OpCode::SpawnRegistered(_) | crate::vm::OpCode::Receive | crate::vm::OpCode::ReceiveMatch(_) | 
+            crate::vm::OpCode::ReceiveMatchAfter(..) | 
+            crate::vm::OpCode::Yield | crate::vm::OpCode::Send(_) | crate::vm::OpCode::Broadcast | crate::vm::OpCode::Monitor(_) | 
             crate::vm::OpCode::Demonitor(_) | crate::vm::OpCode::Link(_) | crate::vm::OpCode::Unlink(_) | 
-            crate::vm::OpCode::TrapExit | crate::vm::OpCode::Register(_) | crate::vm::OpCode::Unregister(_) | 
+            crate::vm::OpCode::TrapExit | crate::vm::OpCode::ProcCall | crate::vm::OpCode::ProcReply |
+            crate::vm::OpCode::PutDict | crate::vm::OpCode::GetDict | crate::vm::OpCode::EraseDict |
+            crate::vm::OpCode::TableNew | crate::vm::OpCode::TableInsert | crate::vm::OpCode::TableLookup | crate::vm::OpCode::TableDelete |
+            crate::vm::OpCode::Register(_) | crate::vm::OpCode::Unregister(_) | 
             crate::vm::OpCode::Whereis(_) | crate::vm::OpCode::SendNamed(_) | crate::vm::OpCode::StartSupervisor | 
             crate::vm::OpCode::SuperviseChild(_) | crate::vm::OpCode::RestartChild(_)
         ))
@@ -1,35 +1,156 @@
 // bytecode.rs
-use crate::vm::OpCode;
+use crate::vm::{OpCode, MessagePattern, DestructurePattern, ProcId, Value, TYPE_NAMES, param_annotation};
 use crate::vm::{VMError, VMResult};
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::fs;
 use std::collections::HashMap;
 
-pub fn load_bytecode(path: &str) -> std::io::Result<Vec<OpCode>> {
-    let file = File::open(path)?;
-    let mut reader = BufReader::new(file);
+// Serialized bytecode starts with a magic signature and a format version.
+// Bump BYTECODE_VERSION whenever the opcode encoding changes.
+pub const BYTECODE_MAGIC: &[u8; 4] = b"TTVM";
+pub const BYTECODE_VERSION: u16 = 2;
+// Version 1 files inline every literal and have no constant pool; they still load.
+const OLDEST_BYTECODE_VERSION: u16 = 1;
+// From version 2, the header is followed by a constant pool: a u32 entry count, then each
+// entry as a tag byte and its value. PUSH_CONST instructions refer to entries by u32 index.
+pub const BYTECODE_CONST_INT: u8 = 0;
+pub const BYTECODE_CONST_STR: u8 = 1;
+// After the last instruction, an optional debug-info section: this tag, a u32 instruction
+// count, then the .ttvm source line of every instruction as a u32.
+pub const BYTECODE_DEBUG_INFO: u16 = 0xDB01;
+
+struct BytecodeReader<'a> {
+    buffer: &'a [u8],
+    pos: usize,
+    filename: &'a str,
+}
+
+impl<'a> BytecodeReader<'a> {
+    fn invalid(&self, reason: String) -> VMError {
+        VMError::InvalidBytecode { filename: self.filename.to_string(), reason }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.buffer.len()
+    }
+
+    fn take(&mut self, len: usize) -> VMResult<&'a [u8]> {
+        if self.pos + len > self.buffer.len() {
+            return Err(self.invalid(format!("unexpected end of file at byte {}", self.pos)));
+        }
+        let bytes = &self.buffer[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> VMResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> VMResult<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self) -> VMResult<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn read_u32(&mut self) -> VMResult<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn read_i64(&mut self) -> VMResult<i64> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn read_str(&mut self) -> VMResult<String> {
+        let len = self.read_u16()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.invalid(format!("invalid UTF-8 string at byte {}", self.pos - len)))
+    }
+
+    fn read_header(&mut self) -> VMResult<u16> {
+        if self.buffer.len() < BYTECODE_MAGIC.len() || &self.buffer[..BYTECODE_MAGIC.len()] != BYTECODE_MAGIC {
+            return Err(self.invalid("missing TTVM magic header (not a TinyTotVM bytecode file)".to_string()));
+        }
+        self.pos = BYTECODE_MAGIC.len();
+        let version = self.read_u16()
+            .map_err(|_| self.invalid("truncated header: missing format version".to_string()))?;
+        if !(OLDEST_BYTECODE_VERSION..=BYTECODE_VERSION).contains(&version) {
+            return Err(self.invalid(format!(
+                "unsupported bytecode version {} (this build reads versions {} to {})",
+                version, OLDEST_BYTECODE_VERSION, BYTECODE_VERSION
+            )));
+        }
+        Ok(version)
+    }
+
+    // Pooled literals, already turned into the push instruction they stand for
+    fn read_constant_pool(&mut self) -> VMResult<Vec<OpCode>> {
+        let count = self.read_u32()? as usize;
+        let mut pool = Vec::with_capacity(count.min(self.buffer.len()));
+        for _ in 0..count {
+            let constant = match self.read_u8()? {
+                BYTECODE_CONST_INT => OpCode::PushInt(self.read_i64()?),
+                BYTECODE_CONST_STR => OpCode::PushStr(self.read_str()?),
+                tag => return Err(self.invalid(format!("unknown constant tag 0x{:02X} at byte {}", tag, self.pos - 1))),
+            };
+            pool.push(constant);
+        }
+        Ok(pool)
+    }
+}
+
+pub fn load_bytecode(path: &str) -> VMResult<Vec<OpCode>> {
+    load_bytecode_with_lines(path).map(|(program, _)| program)
+}
+
+/// Load a .ttb file together with the source line of every instruction, when the
+/// compiler recorded them in a debug-info section.
+pub fn load_bytecode_with_lines(path: &str) -> VMResult<(Vec<OpCode>, Option<Vec<usize>>)> {
+    let file = File::open(path).map_err(|e| VMError::FileError {
+        filename: path.to_string(),
+        error: e.to_string(),
+    })?;
     let mut buffer = Vec::new();
-    reader.read_to_end(&mut buffer)?;
+    BufReader::new(file).read_to_end(&mut buffer).map_err(|e| VMError::FileError {
+        filename: path.to_string(),
+        error: e.to_string(),
+    })?;
+
+    let mut reader = BytecodeReader { buffer: &buffer, pos: 0, filename: path };
+    let version = reader.read_header()?;
+    let constants = if version >= 2 { reader.read_constant_pool()? } else { Vec::new() };
 
     let mut instructions = Vec::new();
-    let mut ip = 0;
+    let mut source_lines = None;
 
-    while ip < buffer.len() {
-        let opcode = u16::from_le_bytes(buffer[ip..ip + 2].try_into().unwrap());
-        ip += 2;
+    while !reader.at_end() {
+        let opcode = reader.read_u16()?;
 
         let op = match opcode {
+            BYTECODE_DEBUG_INFO => {
+                let count = reader.read_u32()? as usize;
+                if count != instructions.len() {
+                    return Err(reader.invalid(format!(
+                        "debug info covers {} instructions but the file has {}", count, instructions.len()
+                    )));
+                }
+                let lines = (0..count).map(|_| reader.read_u32().map(|line| line as usize)).collect::<VMResult<Vec<_>>>()?;
+                if !reader.at_end() {
+                    return Err(reader.invalid(format!("unexpected data after debug info at byte {}", reader.pos)));
+                }
+                source_lines = Some(lines);
+                break;
+            }
+            // Inline literals, as written before the constant pool existed
             0x0001 => {
-                let val = i64::from_le_bytes(buffer[ip..ip + 8].try_into().unwrap());
-                ip += 8;
+                let val = reader.read_i64()?;
                 OpCode::PushInt(val)
             }
             0x0002 => {
-                let len = u16::from_le_bytes(buffer[ip..ip + 2].try_into().unwrap()) as usize;
-                ip += 2;
-                let s = String::from_utf8(buffer[ip..ip + len].to_vec()).unwrap();
-                ip += len;
+                let s = reader.read_str()?;
                 OpCode::PushStr(s)
             }
             0x0003 => OpCode::True,
@@ -39,10 +160,26 @@ pub fn load_bytecode(path: &str) -> std::io::Result<Vec<OpCode>> {
             0x0007 => OpCode::And,
             0x0008 => OpCode::Or,
             0x0009 => OpCode::Dup,
+            0x000A => {
+                let index = reader.read_u32()? as usize;
+                match constants.get(index) {
+                    Some(constant) => constant.clone(),
+                    None => return Err(reader.invalid(format!(
+                        "constant index {} out of range (the pool has {} entries)", index, constants.len()
+                    ))),
+                }
+            }
 
             0x0010 => OpCode::Add,
             0x0011 => OpCode::Sub,
             0x0012 => OpCode::Concat,
+            0x0013 => OpCode::Pow,
+            0x0014 => OpCode::Sqrt,
+            0x0015 => OpCode::Abs,
+            0x0016 => OpCode::Floor,
+            0x0017 => OpCode::Ceil,
+            0x0018 => OpCode::Round,
+            0x0019 => OpCode::Neg,
 
             0x0020 => OpCode::Eq,
             0x0021 => OpCode::Gt,
@@ -52,63 +189,48 @@ pub fn load_bytecode(path: &str) -> std::io::Result<Vec<OpCode>> {
             0x0025 => OpCode::Le,
 
             0x0030 => {
-                let addr = u16::from_le_bytes(buffer[ip..ip + 2].try_into().unwrap()) as usize;
-                ip += 2;
+                let addr = reader.read_u16()? as usize;
                 OpCode::Jmp(addr)
             }
             0x0031 => {
-                let addr = u16::from_le_bytes(buffer[ip..ip + 2].try_into().unwrap()) as usize;
-                ip += 2;
+                let addr = reader.read_u16()? as usize;
                 OpCode::Jz(addr)
             }
             0x0032 => {
                 // Read target address (2 bytes)
-                let addr = u16::from_le_bytes(buffer[ip..ip+2].try_into().unwrap()) as usize;
-                ip += 2;
+                let addr = reader.read_u16()? as usize;
                 // Read parameter count (2 bytes)
-                let count = u16::from_le_bytes(buffer[ip..ip+2].try_into().unwrap()) as usize;
-                ip += 2;
+                let count = reader.read_u16()? as usize;
                 // Read each parameter name
                 let mut params = Vec::with_capacity(count);
                 for _ in 0..count {
-                    let len = u16::from_le_bytes(buffer[ip..ip+2].try_into().unwrap()) as usize;
-                    ip += 2;
-                    let name_bytes = &buffer[ip..ip+len];
-                    let name = String::from_utf8(name_bytes.to_vec()).unwrap();
-                    ip += len;
-                    params.push(name);
+                    params.push(reader.read_str()?);
                 }
                 OpCode::Call{addr, params}
             }
             0x0033 => OpCode::Ret,
+            0x0034 => OpCode::RetN(reader.read_u16()? as usize),
 
             0x0040 => OpCode::Print,
+            0x0041 => OpCode::PrintRaw,
+            0x0042 => OpCode::PrintErr,
+            0x0043 => OpCode::DebugPrint,
 
             0x0050 => {
-                let len = u16::from_le_bytes(buffer[ip..ip + 2].try_into().unwrap()) as usize;
-                ip += 2;
-                let s = String::from_utf8(buffer[ip..ip + len].to_vec()).unwrap();
-                ip += len;
+                let s = reader.read_str()?;
                 OpCode::Store(s)
             }
             0x0051 => {
-                let len = u16::from_le_bytes(buffer[ip..ip + 2].try_into().unwrap()) as usize;
-                ip += 2;
-                let s = String::from_utf8(buffer[ip..ip + len].to_vec()).unwrap();
-                ip += len;
+                let s = reader.read_str()?;
                 OpCode::Load(s)
             }
             0x0052 => {
-                let len = u16::from_le_bytes(buffer[ip..ip + 2].try_into().unwrap()) as usize;
-                ip += 2;
-                let s = String::from_utf8(buffer[ip..ip + len].to_vec()).unwrap();
-                ip += len;
+                let s = reader.read_str()?;
                 OpCode::Delete(s)
             }
 
             0x0060 => {
-                let n = buffer[ip] as usize;
-                ip += 1;
+                let n = reader.read_u8()? as usize;
                 OpCode::MakeList(n)
             }
             0x0061 => OpCode::Len,
@@ -117,98 +239,200 @@ pub fn load_bytecode(path: &str) -> std::io::Result<Vec<OpCode>> {
             0x0070 => OpCode::DumpScope,
             0x0072 => OpCode::ReadFile,
             0x0073 => OpCode::WriteFile,
+            0x0074 => match reader.read_u8()? {
+                0 => OpCode::Assert(None),
+                _ => OpCode::Assert(Some(reader.read_str()?)),
+            },
 
             // Concurrency opcodes
             0x0080 => OpCode::Spawn,
             0x0081 => {
-                let len = u16::from_le_bytes(buffer[ip..ip + 2].try_into().unwrap()) as usize;
-                ip += 2;
-                let s = String::from_utf8(buffer[ip..ip + len].to_vec()).unwrap();
-                ip += len;
+                let s = reader.read_str()?;
                 OpCode::Register(s)
             }
             0x0082 => {
-                let len = u16::from_le_bytes(buffer[ip..ip + 2].try_into().unwrap()) as usize;
-                ip += 2;
-                let s = String::from_utf8(buffer[ip..ip + len].to_vec()).unwrap();
-                ip += len;
+                let s = reader.read_str()?;
                 OpCode::Unregister(s)
             }
             0x0083 => {
-                let len = u16::from_le_bytes(buffer[ip..ip + 2].try_into().unwrap()) as usize;
-                ip += 2;
-                let s = String::from_utf8(buffer[ip..ip + len].to_vec()).unwrap();
-                ip += len;
+                let s = reader.read_str()?;
                 OpCode::Whereis(s)
             }
             0x0084 => {
-                let len = u16::from_le_bytes(buffer[ip..ip + 2].try_into().unwrap()) as usize;
-                ip += 2;
-                let s = String::from_utf8(buffer[ip..ip + len].to_vec()).unwrap();
-                ip += len;
+                let s = reader.read_str()?;
                 OpCode::SendNamed(s)
             }
             0x0085 => {
-                let pid = u64::from_le_bytes(buffer[ip..ip + 8].try_into().unwrap());
-                ip += 8;
+                let pid = reader.read_u64()?;
                 OpCode::Monitor(pid)
             }
             0x0086 => {
-                let pid = u64::from_le_bytes(buffer[ip..ip + 8].try_into().unwrap());
-                ip += 8;
+                let pid = reader.read_u64()?;
                 OpCode::Link(pid)
             }
             0x0087 => {
-                let pid = u64::from_le_bytes(buffer[ip..ip + 8].try_into().unwrap());
-                ip += 8;
+                let pid = reader.read_u64()?;
                 OpCode::Unlink(pid)
             }
             0x0088 => OpCode::StartSupervisor,
             0x0089 => {
-                let len = u16::from_le_bytes(buffer[ip..ip + 2].try_into().unwrap()) as usize;
-                ip += 2;
-                let s = String::from_utf8(buffer[ip..ip + len].to_vec()).unwrap();
-                ip += len;
+                let s = reader.read_str()?;
                 OpCode::SuperviseChild(s)
             }
             0x008A => {
-                let len = u16::from_le_bytes(buffer[ip..ip + 2].try_into().unwrap()) as usize;
-                ip += 2;
-                let s = String::from_utf8(buffer[ip..ip + len].to_vec()).unwrap();
-                ip += len;
+                let s = reader.read_str()?;
                 OpCode::RestartChild(s)
             }
             0x008B => OpCode::Yield,
             0x008C => OpCode::Receive,
             0x008D => {
-                let pid = u64::from_le_bytes(buffer[ip..ip + 8].try_into().unwrap());
-                ip += 8;
+                let pid = reader.read_u64()?;
                 OpCode::Send(pid)
             }
+            0x008E => OpCode::SelfPid,
+            0x008F => OpCode::ParentPid,
+
+            0x0090 => OpCode::RandomInt,
+            0x0091 => OpCode::RandomFloat,
+            0x0092 => OpCode::RandomSeed,
+            0x0093 => OpCode::Uuid,
+
+            0x00A0 => {
+                let addr = reader.read_u16()? as usize;
+                let count = reader.read_u16()? as usize;
+                let mut params = Vec::with_capacity(count);
+                for _ in 0..count {
+                    params.push(reader.read_str()?);
+                }
+                // A return type is stored after the parameters, as it is written in source
+                let returns = params.last().and_then(|last| last.strip_prefix("->")).map(str::to_string);
+                if returns.is_some() {
+                    params.pop();
+                }
+                OpCode::MakeFunction { addr, params, returns }
+            }
+            0x00A1 => OpCode::CallFunction,
+            0x00A2 => OpCode::Import(reader.read_str()?),
+            0x00A3 => OpCode::Export(reader.read_str()?),
+            0x00A4 => OpCode::ExportAs(reader.read_str()?),
+            0x00A5 => {
+                let text = reader.read_str()?;
+                let pattern = parse_destructure_pattern(&text)
+                    .ok_or_else(|| reader.invalid(format!("invalid DESTRUCTURE pattern {}", text)))?;
+                OpCode::Destructure(pattern)
+            }
+            0x00A6 => OpCode::ReloadModule(reader.read_str()?),
 
             0x00FF => OpCode::Halt,
 
-            _ => panic!("Unknown bytecode: 0x{:04X}", opcode),
+            _ => return Err(reader.invalid(format!("unknown opcode 0x{:04X}", opcode))),
         };
 
         instructions.push(op);
     }
 
-    Ok(instructions)
+    Ok((instructions, source_lines))
+}
+
+/// The name a module is loaded and cached under, so that every path naming the same
+/// file (`./m.ttvm`, `m.ttvm`, a symlink) imports it only once. Paths that don't resolve
+/// are kept as written and fail when the module is read.
+pub fn module_key(path: &str) -> String {
+    fs::canonicalize(path)
+        .map(|canonical| canonical.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+/// Load a program from compiled bytecode or .ttvm source. Bytecode is recognised by a `.ttb`
+/// extension or the TTVM magic header; source lines come back when the file has them.
+pub fn load_program_with_lines(path: &str) -> VMResult<(Vec<OpCode>, Option<Vec<usize>>)> {
+    if path.ends_with(".ttb") || has_bytecode_magic(path) {
+        load_bytecode_with_lines(path)
+    } else {
+        parse_program_with_lines(path).map(|(program, lines)| (program, Some(lines)))
+    }
+}
+
+pub fn load_program(path: &str) -> VMResult<Vec<OpCode>> {
+    load_program_with_lines(path).map(|(program, _)| program)
+}
+
+fn has_bytecode_magic(path: &str) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path).and_then(|mut file| file.read_exact(&mut magic)).is_ok() && &magic == BYTECODE_MAGIC
 }
 
 pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
+    parse_program_with_lines(path).map(|(program, _)| program)
+}
+
+/// Parse a .ttvm file, also returning the source line of every instruction
+/// (indexed by instruction address) so runtime errors can point back at the file.
+pub fn parse_program_with_lines(path: &str) -> VMResult<(Vec<OpCode>, Vec<usize>)> {
     let content = fs::read_to_string(path).map_err(|e| VMError::FileError { 
         filename: path.to_string(), 
         error: e.to_string() 
     })?;
+    parse_source(&content)
+}
 
+/// Source text with comments removed and each line trimmed, one entry per source line so line
+/// numbers stay put. `;` comments out the rest of its line and `/* ... */` may span lines;
+/// neither counts inside a string literal.
+pub fn strip_comments(content: &str) -> VMResult<Vec<String>> {
+    let mut lines = Vec::new();
+    let mut block_opened_on = None;
+    for (line_num, line) in content.lines().enumerate() {
+        let mut kept = String::new();
+        let mut in_string = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if block_opened_on.is_some() {
+                if c == '*' && chars.peek() == Some(&'/') {
+                    chars.next();
+                    block_opened_on = None;
+                }
+            } else if in_string {
+                kept.push(c);
+                match c {
+                    '\\' => kept.extend(chars.next()),
+                    '"' => in_string = false,
+                    _ => {}
+                }
+            } else {
+                match c {
+                    ';' => break,
+                    '/' if chars.peek() == Some(&'*') => {
+                        chars.next();
+                        block_opened_on = Some(line_num + 1);
+                        // Like whitespace, so it still separates the tokens around it
+                        kept.push(' ');
+                    }
+                    '"' => {
+                        in_string = true;
+                        kept.push(c);
+                    }
+                    _ => kept.push(c),
+                }
+            }
+        }
+        lines.push(kept.trim().to_string());
+    }
+    match block_opened_on {
+        Some(line) => Err(VMError::ParseError { line, instruction: "unterminated /* comment".to_string() }),
+        None => Ok(lines),
+    }
+}
+
+/// Parse .ttvm source text held in memory (used by the REPL and by `parse_program_with_lines`).
+pub fn parse_source(content: &str) -> VMResult<(Vec<OpCode>, Vec<usize>)> {
     let mut label_map: HashMap<String, usize> = HashMap::new();
     let mut instructions_raw: Vec<(usize, &str)> = Vec::new();
 
     // First pass: build label -> index map
-    for (line_num, line) in content.lines().enumerate() {
-        let line = line.split(';').next().unwrap_or("").trim();
+    let lines = strip_comments(content)?;
+    for (line_num, line) in lines.iter().enumerate() {
+        let line = line.as_str();
         if line.is_empty() {
             continue;
         }
@@ -222,11 +446,12 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
 
     // Second pass: convert raw instructions to OpCode using label map
     let mut program: Vec<OpCode> = Vec::new();
+    let mut source_lines: Vec<usize> = Vec::with_capacity(instructions_raw.len());
     for (line_num, line) in instructions_raw {
         let parts: Vec<&str> = line.splitn(2, ' ').collect();
         let opcode = match parts[0] {
             "PUSH_INT" => {
-                let n = parts[1].parse::<i64>().map_err(|_| VMError::ParseError { 
+                let n = parse_int_literal(parts[1]).ok_or_else(|| VMError::ParseError { 
                     line: line_num, 
                     instruction: format!("Invalid integer: {}", parts[1]) 
                 })?;
@@ -240,7 +465,10 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
                 OpCode::PushFloat(f)
             }
             "PUSH_STR" => {
-                let s = parts[1].trim_matches('"').to_string();
+                let s = parse_string_literal(parts.get(1).copied().unwrap_or("")).map_err(|reason| VMError::ParseError {
+                    line: line_num,
+                    instruction: format!("{} in {}", reason, line)
+                })?;
                 OpCode::PushStr(s)
             }
             "PUSH_BOOL" => {
@@ -258,9 +486,26 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
             "MUL_F" => OpCode::MulF,
             "DIV" => OpCode::Div,
             "DIV_F" => OpCode::DivF,
+            "NEG" => OpCode::Neg,
+            "POW" => OpCode::Pow,
+            "SQRT" => OpCode::Sqrt,
+            "ABS" => OpCode::Abs,
+            "FLOOR" => OpCode::Floor,
+            "CEIL" => OpCode::Ceil,
+            "ROUND" => OpCode::Round,
             "DUP" => OpCode::Dup,
             "CONCAT" => OpCode::Concat,
             "PRINT" => OpCode::Print,
+            "PRINT_RAW" => OpCode::PrintRaw,
+            "PRINT_ERR" => OpCode::PrintErr,
+            "DEBUG_PRINT" => OpCode::DebugPrint,
+            "ASSERT" => match parts.get(1) {
+                Some(text) => OpCode::Assert(Some(parse_string_literal(text).map_err(|reason| VMError::ParseError {
+                    line: line_num,
+                    instruction: format!("{} in {}", reason, line)
+                })?)),
+                None => OpCode::Assert(None),
+            },
             "HALT" => OpCode::Halt,
             "CALL" => {
                 if parts.len() < 2 {
@@ -268,35 +513,28 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
                 }
                 let call_parts: Vec<&str> = parts[1].split_whitespace().collect();
                 let label = call_parts[0];
-                let params: Vec<String> = call_parts[1..].iter().map(|s| s.to_string()).collect();
+                let params = parse_params(&call_parts[1..], line_num)?;
                 
-                // Try parsing as number first, then as label
-                let target = if let Ok(addr) = label.parse::<usize>() {
-                    addr
-                } else {
-                    *label_map.get(label).ok_or_else(|| VMError::UnknownLabel(label.to_string()))?
-                };
+                let target = resolve_target(label, &label_map, line_num)?;
                 OpCode::Call { addr: target, params }
             }
             "JMP" => {
                 let label = parts[1].trim();
-                let target = if let Ok(addr) = label.parse::<usize>() {
-                    addr
-                } else {
-                    *label_map.get(label).ok_or_else(|| VMError::UnknownLabel(label.to_string()))?
-                };
+                let target = resolve_target(label, &label_map, line_num)?;
                 OpCode::Jmp(target)
             }
             "JZ" => {
                 let label = parts[1].trim();
-                let target = if let Ok(addr) = label.parse::<usize>() {
-                    addr
-                } else {
-                    *label_map.get(label).ok_or_else(|| VMError::UnknownLabel(label.to_string()))?
-                };
+                let target = resolve_target(label, &label_map, line_num)?;
                 OpCode::Jz(target)
             }
-            "RET" => OpCode::Ret,
+            "RET" => match parts.get(1) {
+                Some(count) => OpCode::RetN(count.trim().parse().map_err(|_| VMError::ParseError {
+                    line: line_num,
+                    instruction: format!("RET expects a number of return values: {}", line),
+                })?),
+                None => OpCode::Ret,
+            },
             "STORE" => {
                 let var = parts[1].trim().to_string();
                 OpCode::Store(var)
@@ -328,7 +566,7 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
             "OR" => OpCode::Or,
             "NULL" => OpCode::Null,
             "MAKE_LIST" => {
-                let n = parts[1].parse::<usize>().map_err(|_| VMError::ParseError { 
+                let n = parse_int_literal(parts[1]).and_then(|n| usize::try_from(n).ok()).ok_or_else(|| VMError::ParseError { 
                     line: line_num, 
                     instruction: format!("Invalid list size: {}", parts[1]) 
                 })?;
@@ -342,6 +580,13 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
                 let field = parts[1].trim().to_string();
                 OpCode::SetField(field)
             }
+            "SET_FIELD_MUT" => {
+                let operands: Vec<&str> = parts.get(1).map_or(Vec::new(), |rest| rest.split_whitespace().collect());
+                if operands.len() != 2 {
+                    return Err(VMError::ParseError { line: line_num, instruction: "SET_FIELD_MUT requires a variable and a field name".to_string() });
+                }
+                OpCode::SetFieldMut { var: operands[0].to_string(), field: operands[1].to_string() }
+            }
             "GET_FIELD" => {
                 let field = parts[1].trim().to_string();
                 OpCode::GetField(field)
@@ -355,23 +600,29 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
                 OpCode::DeleteField(field)
             }
             "KEYS" => OpCode::Keys,
+            "VALUES" => OpCode::Values,
+            "ENTRIES" => OpCode::Entries,
             "MAKE_FUNCTION" => {
                 if parts.len() < 2 {
                     return Err(VMError::ParseError { line: line_num, instruction: "MAKE_FUNCTION requires at least a target".to_string() });
                 }
-                let func_parts: Vec<&str> = parts[1].split_whitespace().collect();
+                let mut func_parts: Vec<&str> = parts[1].split_whitespace().collect();
+                let returns = match func_parts.last().and_then(|last| last.strip_prefix("->")) {
+                    Some(type_name) if func_parts.len() > 1 => Some(parse_type_name(type_name, line_num)?),
+                    _ => None,
+                };
+                if returns.is_some() {
+                    func_parts.pop();
+                }
                 let label = func_parts[0];
-                let params: Vec<String> = func_parts[1..].iter().map(|s| s.to_string()).collect();
+                let params = parse_params(&func_parts[1..], line_num)?;
                 
-                // Try parsing as number first, then as label
-                let addr = if let Ok(address) = label.parse::<usize>() {
-                    address
-                } else {
-                    *label_map.get(label).ok_or_else(|| VMError::UnknownLabel(label.to_string()))?
-                };
-                OpCode::MakeFunction { addr, params }
+                let addr = resolve_target(label, &label_map, line_num)?;
+                OpCode::MakeFunction { addr, params, returns }
             }
             "CALL_FUNCTION" => OpCode::CallFunction,
+            "WEAK_REF" => OpCode::WeakRef,
+            "WEAK_GET" => OpCode::WeakGet,
             "MAKE_LAMBDA" => {
                 if parts.len() < 2 {
                     return Err(VMError::ParseError { line: line_num, instruction: line.to_string() });
@@ -383,13 +634,9 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
                 }
                 
                 let label = remaining_parts[0];
-                let params = remaining_parts[1..].iter().map(|s| s.to_string()).collect();
+                let params = parse_params(&remaining_parts[1..], line_num)?;
                 
-                let addr = if let Ok(addr) = label.parse::<usize>() {
-                    addr
-                } else {
-                    *label_map.get(label).ok_or_else(|| VMError::UnknownLabel(label.to_string()))?
-                };
+                let addr = resolve_target(label, &label_map, line_num)?;
                 OpCode::MakeLambda { addr, params }
             }
             "CAPTURE" => {
@@ -398,12 +645,17 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
             }
             "TRY" => {
                 let catch_label = parts[1].trim();
-                let catch_addr = *label_map.get(catch_label).ok_or_else(|| VMError::UnknownLabel(catch_label.to_string()))?;
+                let catch_addr = resolve_target(catch_label, &label_map, line_num)?;
                 OpCode::Try { catch_addr }
             }
             "CATCH" => OpCode::Catch,
             "THROW" => OpCode::Throw,
+            "RETHROW" => OpCode::Rethrow,
             "END_TRY" => OpCode::EndTry,
+            "FINALLY" => OpCode::Finally,
+            "END_FINALLY" => OpCode::EndFinally,
+            "EXCEPTION_MESSAGE" => OpCode::ExceptionMessage,
+            "EXCEPTION_TRACE" => OpCode::ExceptionTrace,
             "READ_FILE" => OpCode::ReadFile,
             "WRITE_FILE" => OpCode::WriteFile,
             // Enhanced I/O operations
@@ -415,12 +667,21 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
             "FILE_SIZE" => OpCode::FileSize,
             "DELETE_FILE" => OpCode::DeleteFile,
             "LIST_DIR" => OpCode::ListDir,
+            "LIST_DIR_RECURSIVE" => OpCode::ListDirRecursive,
+            "STAT" => OpCode::Stat,
             "READ_BYTES" => OpCode::ReadBytes,
             "WRITE_BYTES" => OpCode::WriteBytes,
+            // Working directory and paths
+            "GET_CWD" => OpCode::GetCwd,
+            "SET_CWD" => OpCode::SetCwd,
+            "PATH_JOIN" => OpCode::PathJoin,
+            "PATH_BASENAME" => OpCode::PathBasename,
+            "PATH_DIRNAME" => OpCode::PathDirname,
             // Environment and system
             "GET_ENV" => OpCode::GetEnv,
             "SET_ENV" => OpCode::SetEnv,
             "GET_ARGS" => OpCode::GetArgs,
+            "ENV_VARS" => OpCode::EnvVars,
             "EXEC" => OpCode::Exec,
             "EXEC_CAPTURE" => OpCode::ExecCapture,
             "EXIT" => OpCode::Exit,
@@ -428,9 +689,17 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
             "GET_TIME" => OpCode::GetTime,
             "SLEEP" => OpCode::Sleep,
             "FORMAT_TIME" => OpCode::FormatTime,
+            "PARSE_TIME" => OpCode::ParseTime,
+            "RANDOM_INT" => OpCode::RandomInt,
+            "RANDOM_FLOAT" => OpCode::RandomFloat,
+            "RANDOM_SEED" => OpCode::RandomSeed,
+            "UUID" => OpCode::Uuid,
             // Network operations
             "HTTP_GET" => OpCode::HttpGet,
             "HTTP_POST" => OpCode::HttpPost,
+            "HTTP_SERVE" => OpCode::HttpServe,
+            "HTTP_ACCEPT" => OpCode::HttpAccept,
+            "HTTP_RESPOND" => OpCode::HttpRespond,
             "TCP_CONNECT" => OpCode::TcpConnect,
             "TCP_LISTEN" => OpCode::TcpListen,
             "TCP_SEND" => OpCode::TcpSend,
@@ -447,10 +716,15 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
             "STREAM_READ" => OpCode::StreamRead,
             "STREAM_WRITE" => OpCode::StreamWrite,
             "STREAM_CLOSE" => OpCode::StreamClose,
+            "STREAM_JSON_LINES" => OpCode::StreamJsonLines,
+            "STREAM_CSV" => OpCode::StreamCsv,
             "JSON_PARSE" => OpCode::JsonParse,
             "JSON_STRINGIFY" => OpCode::JsonStringify,
             "CSV_PARSE" => OpCode::CsvParse,
             "CSV_WRITE" => OpCode::CsvWrite,
+            "REGEX_MATCH" => OpCode::RegexMatch,
+            "REGEX_FIND" => OpCode::RegexFind,
+            "REGEX_REPLACE" => OpCode::RegexReplace,
             "COMPRESS" => OpCode::Compress,
             "DECOMPRESS" => OpCode::Decompress,
             "ENCRYPT" => OpCode::Encrypt,
@@ -469,14 +743,74 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
                 };
                 OpCode::Import(path)
             }
+            "IMPORT_AS" | "IMPORT_FROM" => {
+                // A module path (quotes optional) followed by the prefix or the names to import
+                let operands = parts.get(1).map(|rest| split_operands(rest)).unwrap_or_default();
+                match (parts[0], operands.as_slice()) {
+                    ("IMPORT_AS", [path, prefix]) => OpCode::ImportAs(path.trim_matches('"').to_string(), prefix.clone()),
+                    ("IMPORT_FROM", [path, names @ ..]) if !names.is_empty() => {
+                        OpCode::ImportFrom(path.trim_matches('"').to_string(), names.to_vec())
+                    }
+                    (opcode, _) => return Err(VMError::ParseError {
+                        line: line_num,
+                        instruction: format!("{} expects a module path and {}: {}", opcode,
+                            if opcode == "IMPORT_AS" { "one prefix" } else { "the names to import" }, line)
+                    }),
+                }
+            }
             "EXPORT" => {
                 let name = parts[1].trim().to_string();
                 OpCode::Export(name)
             }
+            "DESTRUCTURE" => {
+                let text = parts.get(1).copied().unwrap_or("");
+                let pattern = parse_destructure_pattern(text).ok_or_else(|| VMError::ParseError {
+                    line: line_num,
+                    instruction: format!("Invalid DESTRUCTURE pattern: {}", text)
+                })?;
+                OpCode::Destructure(pattern)
+            }
+            "EXPORT_AS" => match parts.get(1) {
+                Some(name) => OpCode::ExportAs(name.trim().to_string()),
+                None => return Err(VMError::ParseError { line: line_num, instruction: "EXPORT_AS requires a name".to_string() }),
+            },
+            "RELOAD_MODULE" => match parts.get(1) {
+                Some(path) => OpCode::ReloadModule(path.trim().trim_matches('"').to_string()),
+                None => return Err(VMError::ParseError { line: line_num, instruction: "RELOAD_MODULE requires a module path".to_string() }),
+            },
             "YIELD" => OpCode::Yield,
+            "SELF_PID" => OpCode::SelfPid,
+            "PARENT_PID" => OpCode::ParentPid,
             "RECEIVE" => OpCode::Receive,
+            "RECEIVE_MATCH" => {
+                let operands = parts.get(1).map(|rest| split_operands(rest)).unwrap_or_default();
+                let mut patterns = Vec::with_capacity(operands.len());
+                let mut after = None;
+                for token in operands {
+                    // after:<ms> gives up waiting and pushes null
+                    if let Some(ms) = token.strip_prefix("after:") {
+                        let ms = parse_int_literal(ms).and_then(|n| u64::try_from(n).ok())
+                            .filter(|_| after.is_none())
+                            .ok_or_else(|| VMError::ParseError {
+                                line: line_num,
+                                instruction: format!("Invalid receive timeout: {}", token)
+                            })?;
+                        after = Some(ms);
+                        continue;
+                    }
+                    let pattern = parse_message_pattern(&token).ok_or_else(|| VMError::ParseError {
+                        line: line_num,
+                        instruction: format!("Invalid message pattern: {}", token)
+                    })?;
+                    patterns.push(pattern);
+                }
+                match after {
+                    Some(ms) => OpCode::ReceiveMatchAfter(patterns, ms),
+                    None => OpCode::ReceiveMatch(patterns),
+                }
+            }
             "SEND" => {
-                let pid = parts[1].parse::<u64>().map_err(|_| VMError::ParseError { 
+                let pid = parse_int_literal(parts[1]).and_then(|n| ProcId::try_from(n).ok()).ok_or_else(|| VMError::ParseError { 
                     line: line_num, 
                     instruction: format!("Invalid PID: {}", parts[1]) 
                 })?;
@@ -491,6 +825,10 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
                 OpCode::Whereis(name)
             }
             "SPAWN" => OpCode::Spawn,
+            "SPAWN_REGISTERED" => {
+                let name = parts[1].trim_matches('"').to_string();
+                OpCode::SpawnRegistered(name)
+            }
             "SENDNAMED" => {
                 let name = parts[1].trim_matches('"').to_string();
                 OpCode::SendNamed(name)
@@ -500,6 +838,18 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
                 OpCode::Unregister(name)
             }
             "TRAP_EXIT" => OpCode::TrapExit,
+            "PROC_CALL" => OpCode::ProcCall,
+            "PROC_REPLY" => OpCode::ProcReply,
+            "BROADCAST" => OpCode::Broadcast,
+            "SEND_AFTER" => OpCode::SendAfter,
+            "CANCEL_TIMER" => OpCode::CancelTimer,
+            "PUT_DICT" => OpCode::PutDict,
+            "GET_DICT" => OpCode::GetDict,
+            "ERASE_DICT" => OpCode::EraseDict,
+            "TABLE_NEW" => OpCode::TableNew,
+            "TABLE_INSERT" => OpCode::TableInsert,
+            "TABLE_LOOKUP" => OpCode::TableLookup,
+            "TABLE_DELETE" => OpCode::TableDelete,
             "START_SUPERVISOR" => OpCode::StartSupervisor,
             "SUPERVISE_CHILD" => {
                 let name = parts[1].trim_matches('"').to_string();
@@ -510,21 +860,21 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
                 OpCode::RestartChild(name)
             }
             "LINK" => {
-                let pid = parts[1].parse::<u64>().map_err(|_| VMError::ParseError { 
+                let pid = parse_int_literal(parts[1]).and_then(|n| ProcId::try_from(n).ok()).ok_or_else(|| VMError::ParseError { 
                     line: line_num, 
                     instruction: format!("Invalid PID: {}", parts[1]) 
                 })?;
                 OpCode::Link(pid)
             }
             "UNLINK" => {
-                let pid = parts[1].parse::<u64>().map_err(|_| VMError::ParseError { 
+                let pid = parse_int_literal(parts[1]).and_then(|n| ProcId::try_from(n).ok()).ok_or_else(|| VMError::ParseError { 
                     line: line_num, 
                     instruction: format!("Invalid PID: {}", parts[1]) 
                 })?;
                 OpCode::Unlink(pid)
             }
             "MONITOR" => {
-                let pid = parts[1].parse::<u64>().map_err(|_| VMError::ParseError { 
+                let pid = parse_int_literal(parts[1]).and_then(|n| ProcId::try_from(n).ok()).ok_or_else(|| VMError::ParseError { 
                     line: line_num, 
                     instruction: format!("Invalid PID: {}", parts[1]) 
                 })?;
@@ -534,10 +884,635 @@ pub fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
                 let monitor_ref = parts[1].trim_matches('"').to_string();
                 OpCode::Demonitor(monitor_ref)
             }
-            _ => return Err(VMError::ParseError { line: line_num, instruction: line.to_string() }),
+            // A known opcode whose operands didn't fit any of the forms above
+            known if MNEMONICS.contains(&known) => return Err(VMError::ParseError { line: line_num, instruction: line.to_string() }),
+            unknown => return Err(VMError::UnknownOpcode {
+                line: line_num,
+                mnemonic: unknown.to_string(),
+                suggestion: suggest_mnemonic(unknown).map(str::to_string),
+            }),
         };
         program.push(opcode);
+        source_lines.push(line_num);
     }
 
-    Ok(program)
+    validate_targets(&program, &source_lines)?;
+    Ok((program, source_lines))
+}
+
+// Every mnemonic parse_source accepts (LABEL lines aside), for suggesting fixes to typos
+pub const MNEMONICS: &[&str] = &[
+    "PUSH_INT", "PUSH_FLOAT", "PUSH_STR", "PUSH_BOOL", "ADD", "ADD_F", "SUB", "SUB_F", "MUL",
+    "MUL_F", "DIV", "DIV_F", "NEG", "POW", "SQRT", "ABS", "FLOOR", "CEIL", "ROUND", "DUP", "CONCAT",
+    "PRINT", "PRINT_RAW", "PRINT_ERR", "DEBUG_PRINT", "ASSERT", "HALT", "CALL", "JMP", "JZ", "RET",
+    "STORE", "DELETE", "LOAD", "EQ", "GT", "LT", "NE", "GE", "LE", "EQ_F", "GT_F", "LT_F", "NE_F",
+    "GE_F", "LE_F", "TRUE", "FALSE", "NOT", "AND", "OR", "NULL", "MAKE_LIST", "LEN", "INDEX",
+    "DUMP_SCOPE", "MAKE_OBJECT", "SET_FIELD", "SET_FIELD_MUT", "GET_FIELD", "HAS_FIELD",
+    "DELETE_FIELD", "KEYS", "VALUES", "ENTRIES", "MAKE_FUNCTION", "CALL_FUNCTION", "WEAK_REF",
+    "WEAK_GET", "MAKE_LAMBDA", "CAPTURE", "TRY", "CATCH", "THROW", "RETHROW", "END_TRY", "FINALLY",
+    "END_FINALLY", "EXCEPTION_MESSAGE", "EXCEPTION_TRACE", "READ_FILE", "WRITE_FILE", "READ_LINE",
+    "READ_CHAR", "READ_INPUT", "APPEND_FILE", "FILE_EXISTS", "FILE_SIZE", "DELETE_FILE", "LIST_DIR",
+    "LIST_DIR_RECURSIVE", "STAT", "READ_BYTES", "WRITE_BYTES", "GET_CWD", "SET_CWD", "PATH_JOIN",
+    "PATH_BASENAME", "PATH_DIRNAME", "GET_ENV", "SET_ENV", "GET_ARGS", "ENV_VARS", "EXEC",
+    "EXEC_CAPTURE", "EXIT", "GET_TIME", "SLEEP", "FORMAT_TIME", "PARSE_TIME", "RANDOM_INT",
+    "RANDOM_FLOAT", "RANDOM_SEED", "UUID", "HTTP_GET", "HTTP_POST", "HTTP_SERVE", "HTTP_ACCEPT",
+    "HTTP_RESPOND", "TCP_CONNECT", "TCP_LISTEN", "TCP_SEND", "TCP_RECV", "UDP_BIND", "UDP_SEND",
+    "UDP_RECV", "DNS_RESOLVE", "ASYNC_READ", "ASYNC_WRITE", "AWAIT", "STREAM_CREATE", "STREAM_READ",
+    "STREAM_WRITE", "STREAM_CLOSE", "STREAM_JSON_LINES", "STREAM_CSV", "JSON_PARSE", "JSON_STRINGIFY", "CSV_PARSE", "CSV_WRITE",
+    "REGEX_MATCH", "REGEX_FIND", "REGEX_REPLACE", "COMPRESS", "DECOMPRESS", "ENCRYPT", "DECRYPT",
+    "HASH", "DB_CONNECT", "DB_QUERY", "DB_EXEC", "IMPORT", "IMPORT_AS", "IMPORT_FROM", "EXPORT", "EXPORT_AS", "RELOAD_MODULE", "DESTRUCTURE",
+    "YIELD", "SELF_PID", "PARENT_PID", "RECEIVE", "RECEIVE_MATCH", "SEND", "REGISTER", "WHEREIS",
+    "SPAWN", "SPAWN_REGISTERED", "SENDNAMED", "UNREGISTER", "TRAP_EXIT", "PROC_CALL", "PROC_REPLY",
+    "BROADCAST", "SEND_AFTER", "CANCEL_TIMER", "PUT_DICT", "GET_DICT", "ERASE_DICT", "TABLE_NEW",
+    "TABLE_INSERT", "TABLE_LOOKUP", "TABLE_DELETE", "START_SUPERVISOR", "SUPERVISE_CHILD",
+    "RESTART_CHILD", "LINK", "UNLINK", "MONITOR", "DEMONITOR",
+];
+
+// The known mnemonic closest to an unknown one, when it's close enough to be a likely typo
+fn suggest_mnemonic(mnemonic: &str) -> Option<&'static str> {
+    let wanted = mnemonic.to_ascii_uppercase();
+    let limit = (wanted.len() / 3).max(1);
+    MNEMONICS.iter()
+        .map(|known| (edit_distance(&wanted, known), *known))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, known)| known)
+}
+
+// Levenshtein distance, counting a swap of two neighbouring characters as one edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (rows[i - 1][j] + 1).min(rows[i][j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+// Parameter names of CALL, MAKE_FUNCTION or MAKE_LAMBDA. Only the last may be a `...rest`
+// parameter, and it needs a name.
+fn parse_params(names: &[&str], line: usize) -> VMResult<Vec<String>> {
+    for (i, name) in names.iter().enumerate() {
+        if let Some(rest) = name.strip_prefix("...") {
+            if i + 1 != names.len() || rest.is_empty() {
+                return Err(VMError::ParseError {
+                    line,
+                    instruction: format!("variadic parameter {} must be a named last parameter", name),
+                });
+            }
+        }
+        if name.starts_with("->") {
+            return Err(VMError::ParseError {
+                line,
+                instruction: format!("return type {} must come last in MAKE_FUNCTION", name),
+            });
+        }
+        if let (_, Some(type_name)) = param_annotation(name) {
+            parse_type_name(type_name, line)?;
+        }
+    }
+    Ok(names.iter().map(|name| name.to_string()).collect())
+}
+
+// A type in a parameter or return annotation: one of TYPE_NAMES
+fn parse_type_name(type_name: &str, line: usize) -> VMResult<String> {
+    if TYPE_NAMES.contains(&type_name) {
+        Ok(type_name.to_string())
+    } else {
+        Err(VMError::ParseError {
+            line,
+            instruction: format!("unknown type {} (expected one of: {})", type_name, TYPE_NAMES.join(", ")),
+        })
+    }
+}
+
+// A jump or call operand: an instruction address, or a label defined anywhere in the file
+fn resolve_target(operand: &str, label_map: &HashMap<String, usize>, line: usize) -> VMResult<usize> {
+    operand.parse::<usize>().ok()
+        .or_else(|| label_map.get(operand).copied())
+        .ok_or_else(|| VMError::ParseError { line, instruction: format!("Unknown label: {}", operand) })
+}
+
+// Every jump, call, function and handler address has to land inside the program. The address
+// just past the last instruction is allowed too: it's where a LABEL at the end of a file points,
+// and running off the end halts.
+fn validate_targets(program: &[OpCode], source_lines: &[usize]) -> VMResult<()> {
+    for (i, op) in program.iter().enumerate() {
+        let target = match op {
+            OpCode::Jmp(target) | OpCode::Jz(target) => *target,
+            OpCode::Call { addr, .. } | OpCode::MakeFunction { addr, .. } | OpCode::MakeLambda { addr, .. } => *addr,
+            OpCode::Try { catch_addr } => *catch_addr,
+            _ => continue,
+        };
+        if target > program.len() {
+            return Err(VMError::ParseError {
+                line: source_lines[i],
+                instruction: format!("{} targets instruction {}, but the program has {}", opcode_to_text(op), target, program.len()),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Canonical textual form of an instruction, as accepted by parse_program.
+// Used by the optimizer output and the disassembler.
+pub fn opcode_to_text(instruction: &OpCode) -> String {
+    match instruction {
+        OpCode::PushInt(n) => format!("PUSH_INT {}", n),
+        OpCode::PushFloat(f) => format!("PUSH_FLOAT {}", f),
+        OpCode::PushStr(s) => format!("PUSH_STR {}", quote_string(s)),
+        OpCode::PushBool(b) => format!("PUSH_BOOL {}", b),
+        OpCode::Add => "ADD".to_string(),
+        OpCode::AddF => "ADD_F".to_string(),
+        OpCode::Sub => "SUB".to_string(),
+        OpCode::SubF => "SUB_F".to_string(),
+        OpCode::Mul => "MUL".to_string(),
+        OpCode::MulF => "MUL_F".to_string(),
+        OpCode::Div => "DIV".to_string(),
+        OpCode::DivF => "DIV_F".to_string(),
+        OpCode::Neg => "NEG".to_string(),
+        OpCode::Pow => "POW".to_string(),
+        OpCode::Sqrt => "SQRT".to_string(),
+        OpCode::Abs => "ABS".to_string(),
+        OpCode::Floor => "FLOOR".to_string(),
+        OpCode::Ceil => "CEIL".to_string(),
+        OpCode::Round => "ROUND".to_string(),
+        OpCode::Concat => "CONCAT".to_string(),
+        OpCode::Print => "PRINT".to_string(),
+        OpCode::PrintRaw => "PRINT_RAW".to_string(),
+        OpCode::PrintErr => "PRINT_ERR".to_string(),
+        OpCode::DebugPrint => "DEBUG_PRINT".to_string(),
+        OpCode::Assert(None) => "ASSERT".to_string(),
+        OpCode::Assert(Some(message)) => format!("ASSERT {}", quote_string(message)),
+        OpCode::Halt => "HALT".to_string(),
+        OpCode::Jmp(addr) => format!("JMP {}", addr),
+        OpCode::Jz(addr) => format!("JZ {}", addr),
+        OpCode::Call { addr, params } => with_params(format!("CALL {}", addr), params),
+        OpCode::Ret => "RET".to_string(),
+        OpCode::RetN(count) => format!("RET {}", count),
+        OpCode::Dup => "DUP".to_string(),
+        OpCode::Store(var) => format!("STORE {}", var),
+        OpCode::Load(var) => format!("LOAD {}", var),
+        OpCode::Delete(var) => format!("DELETE {}", var),
+        OpCode::Eq => "EQ".to_string(),
+        OpCode::Ne => "NE".to_string(),
+        OpCode::Gt => "GT".to_string(),
+        OpCode::Lt => "LT".to_string(),
+        OpCode::Ge => "GE".to_string(),
+        OpCode::Le => "LE".to_string(),
+        OpCode::EqF => "EQ_F".to_string(),
+        OpCode::NeF => "NE_F".to_string(),
+        OpCode::GtF => "GT_F".to_string(),
+        OpCode::LtF => "LT_F".to_string(),
+        OpCode::GeF => "GE_F".to_string(),
+        OpCode::LeF => "LE_F".to_string(),
+        OpCode::True => "TRUE".to_string(),
+        OpCode::False => "FALSE".to_string(),
+        OpCode::Not => "NOT".to_string(),
+        OpCode::And => "AND".to_string(),
+        OpCode::Or => "OR".to_string(),
+        OpCode::Null => "NULL".to_string(),
+        OpCode::MakeList(n) => format!("MAKE_LIST {}", n),
+        OpCode::Len => "LEN".to_string(),
+        OpCode::Index => "INDEX".to_string(),
+        OpCode::DumpScope => "DUMP_SCOPE".to_string(),
+        OpCode::ReadFile => "READ_FILE".to_string(),
+        OpCode::WriteFile => "WRITE_FILE".to_string(),
+        // Enhanced I/O operations
+        OpCode::ReadLine => "READ_LINE".to_string(),
+        OpCode::ReadChar => "READ_CHAR".to_string(),
+        OpCode::ReadInput => "READ_INPUT".to_string(),
+        OpCode::AppendFile => "APPEND_FILE".to_string(),
+        OpCode::FileExists => "FILE_EXISTS".to_string(),
+        OpCode::FileSize => "FILE_SIZE".to_string(),
+        OpCode::DeleteFile => "DELETE_FILE".to_string(),
+        OpCode::ListDir => "LIST_DIR".to_string(),
+        OpCode::ListDirRecursive => "LIST_DIR_RECURSIVE".to_string(),
+        OpCode::Stat => "STAT".to_string(),
+        OpCode::ReadBytes => "READ_BYTES".to_string(),
+        OpCode::WriteBytes => "WRITE_BYTES".to_string(),
+        // Working directory and paths
+        OpCode::GetCwd => "GET_CWD".to_string(),
+        OpCode::SetCwd => "SET_CWD".to_string(),
+        OpCode::PathJoin => "PATH_JOIN".to_string(),
+        OpCode::PathBasename => "PATH_BASENAME".to_string(),
+        OpCode::PathDirname => "PATH_DIRNAME".to_string(),
+        // Environment and system
+        OpCode::GetEnv => "GET_ENV".to_string(),
+        OpCode::SetEnv => "SET_ENV".to_string(),
+        OpCode::GetArgs => "GET_ARGS".to_string(),
+        OpCode::EnvVars => "ENV_VARS".to_string(),
+        OpCode::Exec => "EXEC".to_string(),
+        OpCode::ExecCapture => "EXEC_CAPTURE".to_string(),
+        OpCode::Exit => "EXIT".to_string(),
+        // Time operations
+        OpCode::GetTime => "GET_TIME".to_string(),
+        OpCode::Sleep => "SLEEP".to_string(),
+        OpCode::FormatTime => "FORMAT_TIME".to_string(),
+        OpCode::ParseTime => "PARSE_TIME".to_string(),
+        OpCode::RandomInt => "RANDOM_INT".to_string(),
+        OpCode::RandomFloat => "RANDOM_FLOAT".to_string(),
+        OpCode::RandomSeed => "RANDOM_SEED".to_string(),
+        OpCode::Uuid => "UUID".to_string(),
+        // Network operations
+        OpCode::HttpGet => "HTTP_GET".to_string(),
+        OpCode::HttpPost => "HTTP_POST".to_string(),
+        OpCode::HttpServe => "HTTP_SERVE".to_string(),
+        OpCode::HttpAccept => "HTTP_ACCEPT".to_string(),
+        OpCode::HttpRespond => "HTTP_RESPOND".to_string(),
+        OpCode::TcpConnect => "TCP_CONNECT".to_string(),
+        OpCode::TcpListen => "TCP_LISTEN".to_string(),
+        OpCode::TcpSend => "TCP_SEND".to_string(),
+        OpCode::TcpRecv => "TCP_RECV".to_string(),
+        OpCode::UdpBind => "UDP_BIND".to_string(),
+        OpCode::UdpSend => "UDP_SEND".to_string(),
+        OpCode::UdpRecv => "UDP_RECV".to_string(),
+        OpCode::DnsResolve => "DNS_RESOLVE".to_string(),
+        // Advanced I/O operations
+        OpCode::AsyncRead => "ASYNC_READ".to_string(),
+        OpCode::AsyncWrite => "ASYNC_WRITE".to_string(),
+        OpCode::Await => "AWAIT".to_string(),
+        OpCode::StreamCreate => "STREAM_CREATE".to_string(),
+        OpCode::StreamRead => "STREAM_READ".to_string(),
+        OpCode::StreamWrite => "STREAM_WRITE".to_string(),
+        OpCode::StreamClose => "STREAM_CLOSE".to_string(),
+        OpCode::StreamJsonLines => "STREAM_JSON_LINES".to_string(),
+        OpCode::StreamCsv => "STREAM_CSV".to_string(),
+        OpCode::JsonParse => "JSON_PARSE".to_string(),
+        OpCode::JsonStringify => "JSON_STRINGIFY".to_string(),
+        OpCode::CsvParse => "CSV_PARSE".to_string(),
+        OpCode::CsvWrite => "CSV_WRITE".to_string(),
+        OpCode::RegexMatch => "REGEX_MATCH".to_string(),
+        OpCode::RegexFind => "REGEX_FIND".to_string(),
+        OpCode::RegexReplace => "REGEX_REPLACE".to_string(),
+        OpCode::Compress => "COMPRESS".to_string(),
+        OpCode::Decompress => "DECOMPRESS".to_string(),
+        OpCode::Encrypt => "ENCRYPT".to_string(),
+        OpCode::Decrypt => "DECRYPT".to_string(),
+        OpCode::Hash => "HASH".to_string(),
+        OpCode::DbConnect => "DB_CONNECT".to_string(),
+        OpCode::DbQuery => "DB_QUERY".to_string(),
+        OpCode::DbExec => "DB_EXEC".to_string(),
+        OpCode::MakeObject => "MAKE_OBJECT".to_string(),
+        OpCode::SetField(field) => format!("SET_FIELD {}", field),
+        OpCode::GetField(field) => format!("GET_FIELD {}", field),
+        OpCode::HasField(field) => format!("HAS_FIELD {}", field),
+        OpCode::DeleteField(field) => format!("DELETE_FIELD {}", field),
+        OpCode::Keys => "KEYS".to_string(),
+        OpCode::SetFieldMut { var, field } => format!("SET_FIELD_MUT {} {}", var, field),
+        OpCode::Values => "VALUES".to_string(),
+        OpCode::Entries => "ENTRIES".to_string(),
+        OpCode::MakeFunction { addr, params, returns } => {
+            let text = with_params(format!("MAKE_FUNCTION {}", addr), params);
+            match returns {
+                Some(type_name) => format!("{} ->{}", text, type_name),
+                None => text,
+            }
+        }
+        OpCode::CallFunction => "CALL_FUNCTION".to_string(),
+        OpCode::MakeLambda { addr, params } => with_params(format!("MAKE_LAMBDA {}", addr), params),
+        OpCode::Capture(var) => format!("CAPTURE {}", var),
+        OpCode::WeakRef => "WEAK_REF".to_string(),
+        OpCode::WeakGet => "WEAK_GET".to_string(),
+        OpCode::Try { catch_addr } => format!("TRY {}", catch_addr),
+        OpCode::Catch => "CATCH".to_string(),
+        OpCode::Throw => "THROW".to_string(),
+        OpCode::Rethrow => "RETHROW".to_string(),
+        OpCode::EndTry => "END_TRY".to_string(),
+        OpCode::Finally => "FINALLY".to_string(),
+        OpCode::EndFinally => "END_FINALLY".to_string(),
+        OpCode::ExceptionMessage => "EXCEPTION_MESSAGE".to_string(),
+        OpCode::ExceptionTrace => "EXCEPTION_TRACE".to_string(),
+        OpCode::Import(path) => format!("IMPORT {}", path),
+        OpCode::ImportAs(path, prefix) => format!("IMPORT_AS {} {}", path, prefix),
+        OpCode::ImportFrom(path, names) => format!("IMPORT_FROM {} {}", path, names.join(" ")),
+        OpCode::Export(name) => format!("EXPORT {}", name),
+        OpCode::ExportAs(name) => format!("EXPORT_AS {}", name),
+        OpCode::ReloadModule(path) => format!("RELOAD_MODULE {}", path),
+        OpCode::Destructure(pattern) => format!("DESTRUCTURE {}", pattern),
+        OpCode::Spawn => "SPAWN".to_string(),
+        OpCode::SpawnRegistered(name) => format!("SPAWN_REGISTERED {}", name),
+        OpCode::Receive => "RECEIVE".to_string(),
+        OpCode::ReceiveMatch(patterns) => patterns.iter()
+            .fold("RECEIVE_MATCH".to_string(), |text, pattern| format!("{} {}", text, message_pattern_to_text(pattern))),
+        OpCode::ReceiveMatchAfter(patterns, ms) => format!("{} after:{}", opcode_to_text(&OpCode::ReceiveMatch(patterns.clone())), ms),
+        OpCode::Yield => "YIELD".to_string(),
+        OpCode::SelfPid => "SELF_PID".to_string(),
+        OpCode::ParentPid => "PARENT_PID".to_string(),
+        OpCode::Send(proc_id) => format!("SEND {}", proc_id),
+        OpCode::Monitor(proc_id) => format!("MONITOR {}", proc_id),
+        OpCode::Demonitor(monitor_ref) => format!("DEMONITOR {}", monitor_ref),
+        OpCode::Link(proc_id) => format!("LINK {}", proc_id),
+        OpCode::Unlink(proc_id) => format!("UNLINK {}", proc_id),
+        OpCode::TrapExit => "TRAP_EXIT".to_string(),
+        OpCode::ProcCall => "PROC_CALL".to_string(),
+        OpCode::ProcReply => "PROC_REPLY".to_string(),
+        OpCode::Broadcast => "BROADCAST".to_string(),
+        OpCode::SendAfter => "SEND_AFTER".to_string(),
+        OpCode::CancelTimer => "CANCEL_TIMER".to_string(),
+        OpCode::PutDict => "PUT_DICT".to_string(),
+        OpCode::GetDict => "GET_DICT".to_string(),
+        OpCode::EraseDict => "ERASE_DICT".to_string(),
+        OpCode::TableNew => "TABLE_NEW".to_string(),
+        OpCode::TableInsert => "TABLE_INSERT".to_string(),
+        OpCode::TableLookup => "TABLE_LOOKUP".to_string(),
+        OpCode::TableDelete => "TABLE_DELETE".to_string(),
+        OpCode::Register(name) => format!("REGISTER {}", name),
+        OpCode::Unregister(name) => format!("UNREGISTER {}", name),
+        OpCode::Whereis(name) => format!("WHEREIS {}", name),
+        OpCode::SendNamed(name) => format!("SENDNAMED {}", name),
+        OpCode::StartSupervisor => "START_SUPERVISOR".to_string(),
+        OpCode::SuperviseChild(name) => format!("SUPERVISE_CHILD {}", name),
+        OpCode::RestartChild(name) => format!("RESTART_CHILD {}", name),
+    }
+}
+
+// Integer literal: decimal, 0x hex, 0o octal or 0b binary, with an optional sign
+// and `_` digit separators (e.g. -0xFF, 0b1010, 1_000_000).
+pub fn parse_int_literal(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (radix, digits) = match unsigned.get(..2) {
+        Some("0x") | Some("0X") => (16, &unsigned[2..]),
+        Some("0o") | Some("0O") => (8, &unsigned[2..]),
+        Some("0b") | Some("0B") => (2, &unsigned[2..]),
+        _ => (10, unsigned),
+    };
+    if digits.is_empty() || digits.starts_with('_') || digits.ends_with('_') {
+        return None;
+    }
+    let digits: String = digits.chars().filter(|&c| c != '_').collect();
+    if digits.starts_with('+') || digits.starts_with('-') {
+        return None;
+    }
+    let magnitude = u64::from_str_radix(&digits, radix).ok()?;
+    if negative {
+        0i64.checked_sub_unsigned(magnitude)
+    } else {
+        i64::try_from(magnitude).ok()
+    }
+}
+
+// Quote a string for PUSH_STR, escaping characters that parse_string_literal decodes
+pub fn quote_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Decode a string literal operand. Quoted literals support \n, \t, \r, \\ and \";
+// unquoted text is taken verbatim.
+pub fn parse_string_literal(text: &str) -> Result<String, String> {
+    let text = text.trim();
+    let Some(body) = text.strip_prefix('"') else {
+        return Ok(text.to_string());
+    };
+
+    let mut decoded = String::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let trailing: String = chars.collect();
+                if !trailing.trim().is_empty() {
+                    return Err(format!("Unexpected text after string literal: {}", trailing.trim()));
+                }
+                return Ok(decoded);
+            }
+            '\\' => match chars.next() {
+                Some('n') => decoded.push('\n'),
+                Some('t') => decoded.push('\t'),
+                Some('r') => decoded.push('\r'),
+                Some('\\') => decoded.push('\\'),
+                Some('"') => decoded.push('"'),
+                Some(other) => return Err(format!("Invalid escape sequence \\{}", other)),
+                None => return Err("Unterminated escape sequence".to_string()),
+            },
+            c => decoded.push(c),
+        }
+    }
+    Err("Unterminated string literal".to_string())
+}
+
+fn with_params(head: String, params: &[String]) -> String {
+    if params.is_empty() {
+        head
+    } else {
+        format!("{} {}", head, params.join(" "))
+    }
+}
+
+// RECEIVE_MATCH operands: any, value:<literal>, signal:<name>, exit[:<pid>],
+// down[:<pid>[:<reason>]], link[:<pid>], type:<name>, guard:<var>.
+// A pid of `_` means "any process".
+fn message_pattern_to_text(pattern: &MessagePattern) -> String {
+    let pid_text = |pid: &Option<ProcId>| pid.map(|p| p.to_string()).unwrap_or_else(|| "_".to_string());
+    match pattern {
+        MessagePattern::Any => "any".to_string(),
+        MessagePattern::Value(value) => format!("value:{}", value_to_literal(value)),
+        MessagePattern::Signal(signal) => format!("signal:{}", signal),
+        MessagePattern::Exit(None) => "exit".to_string(),
+        MessagePattern::Exit(pid) => format!("exit:{}", pid_text(pid)),
+        MessagePattern::Down(None, None) => "down".to_string(),
+        MessagePattern::Down(pid, None) => format!("down:{}", pid_text(pid)),
+        MessagePattern::Down(pid, Some(reason)) => format!("down:{}:{}", pid_text(pid), reason),
+        MessagePattern::Link(None) => "link".to_string(),
+        MessagePattern::Link(pid) => format!("link:{}", pid_text(pid)),
+        MessagePattern::Type(type_name) => format!("type:{}", type_name),
+        MessagePattern::Guard(var) => format!("guard:{}", var),
+    }
+}
+
+fn value_to_literal(value: &Value) -> String {
+    match value {
+        Value::Str(s) => quote_string(s),
+        Value::Float(f) if f.fract() == 0.0 && f.is_finite() => format!("{:.1}", f),
+        other => other.to_string(),
+    }
+}
+
+fn parse_literal(text: &str) -> Option<Value> {
+    if text.starts_with('"') {
+        return parse_string_literal(text).ok().map(Value::Str);
+    }
+    match text {
+        "true" => return Some(Value::Bool(true)),
+        "false" => return Some(Value::Bool(false)),
+        "null" => return Some(Value::Null),
+        _ => {}
+    }
+    if let Some(n) = parse_int_literal(text) {
+        return Some(Value::Int(n));
+    }
+    text.parse::<f64>().ok().map(Value::Float)
+}
+
+fn parse_pid_operand(text: &str) -> Option<Option<ProcId>> {
+    if text == "_" {
+        Some(None)
+    } else {
+        parse_int_literal(text).and_then(|n| ProcId::try_from(n).ok()).map(Some)
+    }
+}
+
+fn parse_message_pattern(token: &str) -> Option<MessagePattern> {
+    let (kind, rest) = match token.split_once(':') {
+        Some((kind, rest)) => (kind, Some(rest)),
+        None => (token, None),
+    };
+    match (kind, rest) {
+        ("any", None) => Some(MessagePattern::Any),
+        ("value", Some(literal)) => parse_literal(literal).map(MessagePattern::Value),
+        ("signal", Some(signal)) => Some(MessagePattern::Signal(signal.to_string())),
+        ("exit", None) => Some(MessagePattern::Exit(None)),
+        ("exit", Some(pid)) => parse_pid_operand(pid).map(MessagePattern::Exit),
+        ("down", None) => Some(MessagePattern::Down(None, None)),
+        ("down", Some(rest)) => match rest.split_once(':') {
+            Some((pid, reason)) => parse_pid_operand(pid).map(|pid| MessagePattern::Down(pid, Some(reason.to_string()))),
+            None => parse_pid_operand(rest).map(|pid| MessagePattern::Down(pid, None)),
+        },
+        ("link", None) => Some(MessagePattern::Link(None)),
+        ("link", Some(pid)) => parse_pid_operand(pid).map(MessagePattern::Link),
+        ("type", Some(type_name)) => Some(MessagePattern::Type(type_name.to_string())),
+        ("guard", Some(var)) => Some(MessagePattern::Guard(var.to_string())),
+        _ => None,
+    }
+}
+
+// DESTRUCTURE's operand: a variable name, `[item item ...rest]` or `{key key:pattern}`, nested freely
+fn parse_destructure_pattern(text: &str) -> Option<DestructurePattern> {
+    let mut chars = text.trim().chars().peekable();
+    let pattern = parse_pattern_part(&mut chars)?;
+    chars.next().is_none().then_some(pattern)
+}
+
+fn parse_pattern_part(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<DestructurePattern> {
+    skip_pattern_space(chars);
+    match chars.peek()? {
+        '[' => {
+            chars.next();
+            let mut items = Vec::new();
+            loop {
+                skip_pattern_space(chars);
+                match chars.peek()? {
+                    ']' => {
+                        chars.next();
+                        return Some(DestructurePattern::List(items, None));
+                    }
+                    // The rest name has to close the list
+                    '.' => {
+                        let rest = pattern_name(chars).strip_prefix("...")?.to_string();
+                        skip_pattern_space(chars);
+                        return (!rest.is_empty() && chars.next() == Some(']'))
+                            .then_some(DestructurePattern::List(items, Some(rest)));
+                    }
+                    _ => items.push(parse_pattern_part(chars)?),
+                }
+            }
+        }
+        '{' => {
+            chars.next();
+            let mut fields = Vec::new();
+            loop {
+                skip_pattern_space(chars);
+                if chars.peek() == Some(&'}') {
+                    chars.next();
+                    return Some(DestructurePattern::Object(fields));
+                }
+                let key = pattern_name(chars);
+                if key.is_empty() {
+                    return None;
+                }
+                let field = if chars.peek() == Some(&':') {
+                    chars.next();
+                    parse_pattern_part(chars)?
+                } else {
+                    DestructurePattern::Bind(key.clone())
+                };
+                fields.push((key, field));
+            }
+        }
+        _ => {
+            let name = pattern_name(chars);
+            (!name.is_empty() && !name.starts_with('.')).then_some(DestructurePattern::Bind(name))
+        }
+    }
+}
+
+fn pattern_name(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut name = String::new();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() || "[]{}:".contains(c) {
+            break;
+        }
+        name.push(c);
+        chars.next();
+    }
+    name
+}
+
+fn skip_pattern_space(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+// Split on whitespace, keeping double-quoted sections (with \" escapes) together
+fn split_operands(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if in_quotes => {
+                current.push(c);
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            }
+            '"' => {
+                in_quotes = !in_quotes;
+                current.push(c);
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
 }
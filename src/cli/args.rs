@@ -1,21 +1,37 @@
 use std::env;
 use tiny_tot_vm::{OutputMode, VMConfig};
 
+// Measured runs per engine when `ttvm bench` isn't given a count
+const DEFAULT_BENCH_ITERATIONS: usize = 10;
+
 #[derive(Debug, Clone)]
 pub struct CliArgs {
     pub debug_mode: bool,
     pub optimize_mode: bool,
+    pub check_mode: bool,
     pub gc_type: String,
     pub gc_debug: bool,
     pub gc_stats: bool,
+    pub gc_threshold: usize,
     pub run_tests: bool,
     pub no_table: bool,
     pub trace_enabled: bool,
+    pub trace_out: Option<String>,
     pub profile_enabled: bool,
+    pub flamegraph_out: Option<String>,
     pub smp_enabled: bool,
     pub trace_procs: bool,
     pub profile_procs: bool,
     pub use_ir: bool,
+    pub interactive: bool,
+    pub breakpoints: Vec<usize>,
+    pub watchpoints: Vec<String>,
+    pub call_depth_limit: usize,
+    pub max_reductions: usize,
+    pub max_stack: Option<usize>,
+    pub float_precision: Option<usize>,
+    pub node_address: Option<String>,
+    pub program_args: Vec<String>,
     pub command: CliCommand,
 }
 
@@ -25,6 +41,9 @@ pub enum CliCommand {
     Compile { input: String, output: String },
     CompileLisp { input: String, output: String },
     Optimize { input: String, output: String },
+    Disassemble { input: String },
+    Bench { file: String, iterations: usize },
+    Repl,
     TestAll,
     TestConcurrency,
     TestMonitoringLinking,
@@ -55,17 +74,30 @@ impl CliArgs {
 
         let mut debug_mode = false;
         let mut optimize_mode = false;
+        let mut check_mode = false;
         let mut gc_type = "mark-sweep".to_string();
         let mut gc_debug = false;
         let mut gc_stats = false;
+        let mut gc_threshold = tiny_tot_vm::gc::DEFAULT_GC_THRESHOLD;
         let mut run_tests = false;
         let mut no_table = false;
         let mut trace_enabled = false;
+        let mut trace_out = None;
         let mut profile_enabled = false;
+        let mut flamegraph_out = None;
         let mut smp_enabled = true;  // SMP is now the default
         let mut trace_procs = false;
         let mut profile_procs = false;
         let mut use_ir = false;
+        let mut interactive = false;
+        let mut breakpoints = Vec::new();
+        let mut watchpoints = Vec::new();
+        let mut call_depth_limit = tiny_tot_vm::vm::DEFAULT_CALL_DEPTH_LIMIT;
+        let mut max_reductions = tiny_tot_vm::concurrency::DEFAULT_MAX_REDUCTIONS;
+        let mut max_stack = None;
+        let mut float_precision = None;
+        let mut node_address = None;
+        let mut program_args = Vec::new();
         let mut file_index = 1;
 
         // Parse flags
@@ -79,6 +111,10 @@ impl CliArgs {
                     optimize_mode = true;
                     file_index += 1;
                 }
+                "--check" => {
+                    check_mode = true;
+                    file_index += 1;
+                }
                 "--gc" => {
                     if file_index + 1 >= args.len() {
                         return Err("--gc flag requires a garbage collector type".to_string());
@@ -97,6 +133,14 @@ impl CliArgs {
                     gc_stats = true;
                     file_index += 1;
                 }
+                "--gc-threshold" => {
+                    if file_index + 1 >= args.len() {
+                        return Err("--gc-threshold flag requires a number of allocations".to_string());
+                    }
+                    gc_threshold = args[file_index + 1].parse::<usize>().ok().filter(|n| *n > 0)
+                        .ok_or_else(|| format!("Invalid GC threshold: {}", args[file_index + 1]))?;
+                    file_index += 2;
+                }
                 "--run-tests" => {
                     run_tests = true;
                     file_index += 1;
@@ -109,10 +153,24 @@ impl CliArgs {
                     trace_enabled = true;
                     file_index += 1;
                 }
+                "--trace-out" => {
+                    if file_index + 1 >= args.len() {
+                        return Err("--trace-out flag requires a file path".to_string());
+                    }
+                    trace_out = Some(args[file_index + 1].clone());
+                    file_index += 2;
+                }
                 "--profile" => {
                     profile_enabled = true;
                     file_index += 1;
                 }
+                "--flamegraph-out" => {
+                    if file_index + 1 >= args.len() {
+                        return Err("--flamegraph-out flag requires a file path".to_string());
+                    }
+                    flamegraph_out = Some(args[file_index + 1].clone());
+                    file_index += 2;
+                }
                 "--no-smp" => {
                     smp_enabled = false;
                     file_index += 1;
@@ -129,6 +187,65 @@ impl CliArgs {
                     use_ir = true;
                     file_index += 1;
                 }
+                "--interactive" => {
+                    interactive = true;
+                    file_index += 1;
+                }
+                "--break" => {
+                    if file_index + 1 >= args.len() {
+                        return Err("--break flag requires an instruction address".to_string());
+                    }
+                    let address = args[file_index + 1].parse::<usize>()
+                        .map_err(|_| format!("Invalid breakpoint address: {}", args[file_index + 1]))?;
+                    breakpoints.push(address);
+                    file_index += 2;
+                }
+                "--watch" => {
+                    if file_index + 1 >= args.len() {
+                        return Err("--watch flag requires a variable name".to_string());
+                    }
+                    watchpoints.push(args[file_index + 1].clone());
+                    file_index += 2;
+                }
+                "--max-call-depth" => {
+                    if file_index + 1 >= args.len() {
+                        return Err("--max-call-depth flag requires a depth".to_string());
+                    }
+                    call_depth_limit = args[file_index + 1].parse::<usize>()
+                        .map_err(|_| format!("Invalid call depth: {}", args[file_index + 1]))?;
+                    file_index += 2;
+                }
+                "--reductions" => {
+                    if file_index + 1 >= args.len() {
+                        return Err("--reductions flag requires a number of instructions".to_string());
+                    }
+                    max_reductions = args[file_index + 1].parse::<usize>().ok().filter(|n| *n > 0)
+                        .ok_or_else(|| format!("Invalid reduction count: {}", args[file_index + 1]))?;
+                    file_index += 2;
+                }
+                "--max-stack" => {
+                    if file_index + 1 >= args.len() {
+                        return Err("--max-stack flag requires a number of values".to_string());
+                    }
+                    max_stack = Some(args[file_index + 1].parse::<usize>()
+                        .map_err(|_| format!("Invalid stack size: {}", args[file_index + 1]))?);
+                    file_index += 2;
+                }
+                "--float-precision" => {
+                    if file_index + 1 >= args.len() {
+                        return Err("--float-precision flag requires a number of digits".to_string());
+                    }
+                    float_precision = Some(args[file_index + 1].parse::<usize>()
+                        .map_err(|_| format!("Invalid float precision: {}", args[file_index + 1]))?);
+                    file_index += 2;
+                }
+                "--node" => {
+                    if file_index + 1 >= args.len() {
+                        return Err("--node flag requires a host:port address to listen on".to_string());
+                    }
+                    node_address = Some(args[file_index + 1].clone());
+                    file_index += 2;
+                }
                 _ => {
                     return Err(format!("Unknown flag: {}", args[file_index]));
                 }
@@ -156,6 +273,30 @@ impl CliArgs {
                         output: args[file_index + 2].clone(),
                     }
                 }
+                "disassemble" => {
+                    if args.len() != file_index + 2 {
+                        return Err("Usage: tinytotvm disassemble <input.ttb>".to_string());
+                    }
+                    CliCommand::Disassemble {
+                        input: args[file_index + 1].clone(),
+                    }
+                }
+                "bench" => {
+                    let usage = "Usage: tinytotvm bench <program> [iterations]";
+                    let iterations = match args.len() - file_index {
+                        2 => DEFAULT_BENCH_ITERATIONS,
+                        3 => match args[file_index + 2].parse::<usize>() {
+                            Ok(n) if n > 0 => n,
+                            _ => return Err(format!("bench iterations must be a positive integer, got {}", args[file_index + 2])),
+                        },
+                        _ => return Err(usage.to_string()),
+                    };
+                    CliCommand::Bench {
+                        file: args[file_index + 1].clone(),
+                        iterations,
+                    }
+                }
+                "repl" => CliCommand::Repl,
                 "compile-lisp" => {
                     if args.len() != file_index + 3 {
                         return Err("Usage: tinytotvm compile-lisp <input.lisp> <output.ttvm>".to_string());
@@ -184,7 +325,8 @@ impl CliArgs {
                 "benchmark-performance" => CliCommand::BenchmarkPerformance,
                 "benchmark-ir-vs-stack" => CliCommand::BenchmarkIrVsStack,
                 _ => {
-                    // Assume it's a file to run
+                    // Assume it's a file to run; everything after it belongs to the program
+                    program_args = args[file_index + 1..].to_vec();
                     CliCommand::Run { file: args[file_index].clone() }
                 }
             }
@@ -198,17 +340,30 @@ impl CliArgs {
         Ok(CliArgs {
             debug_mode,
             optimize_mode,
+            check_mode,
             gc_type,
             gc_debug,
             gc_stats,
+            gc_threshold,
             run_tests,
             no_table,
             trace_enabled,
+            trace_out,
             profile_enabled,
+            flamegraph_out,
             smp_enabled,
             trace_procs,
             profile_procs,
             use_ir,
+            interactive,
+            breakpoints,
+            watchpoints,
+            call_depth_limit,
+            max_reductions,
+            max_stack,
+            float_precision,
+            node_address,
+            program_args,
             command,
         })
     }
@@ -225,24 +380,39 @@ impl CliArgs {
             run_tests: self.run_tests,
             gc_debug: self.gc_debug,
             gc_stats: self.gc_stats,
+            gc_threshold: self.gc_threshold,
             debug_mode: self.debug_mode,
             optimize_mode: self.optimize_mode,
             gc_type: self.gc_type.clone(),
             trace_enabled: self.trace_enabled,
+            trace_out: self.trace_out.clone(),
             profile_enabled: self.profile_enabled,
+            flamegraph_out: self.flamegraph_out.clone(),
             smp_enabled: self.smp_enabled,
             trace_procs: self.trace_procs,
             profile_procs: self.profile_procs,
             use_ir: self.use_ir,
+            interactive: self.interactive,
+            breakpoints: self.breakpoints.clone(),
+            watchpoints: self.watchpoints.clone(),
+            call_depth_limit: self.call_depth_limit,
+            max_reductions: self.max_reductions,
+            max_stack: self.max_stack,
+            float_precision: self.float_precision,
+            node_address: self.node_address.clone(),
+            program_args: self.program_args.clone(),
         }
     }
 
     fn usage_string() -> String {
         format!(
-            "Usage: ttvm [--debug] [--optimize] [--gc <type>] [--gc-debug] [--gc-stats] [--run-tests] [--no-table] [--trace] [--profile] [--no-smp] [--trace-procs] [--profile-procs] [--use-ir] <program.ttvm|program.ttb>\n\
+            "Usage: ttvm [--debug] [--optimize] [--check] [--gc <type>] [--gc-debug] [--gc-stats] [--gc-threshold <n>] [--run-tests] [--no-table] [--trace] [--trace-out <path>] [--profile] [--flamegraph-out <path>] [--no-smp] [--trace-procs] [--profile-procs] [--use-ir] [--interactive] [--break <addr>] [--watch <var>] [--max-call-depth <n>] [--reductions <n>] [--max-stack <n>] [--float-precision <n>] [--node <host:port>] <program.ttvm|program.ttb|-> [program args...]\n\
              \x20      ttvm compile <input.ttvm> <output.ttb>\n\
              \x20      ttvm compile-lisp <input.lisp> <output.ttvm>\n\
              \x20      ttvm optimize <input.ttvm> <output.ttvm>\n\
+             \x20      ttvm disassemble <input.ttb>                    # Print a numbered listing of compiled bytecode\n\
+             \x20      ttvm bench <program> [iterations]                # Time a program on the stack VM and the IR VM (default 10 runs each)\n\
+             \x20      ttvm repl                                        # Enter and run instructions interactively\n\
              \x20      ttvm test-all                                    # Run all examples and tests\n\
              \x20      ttvm test-concurrency                           # Run concurrency tests\n\
              \x20      ttvm test-multithreaded                         # Run multi-threaded scheduler tests\n\
@@ -262,13 +432,19 @@ impl CliArgs {
              \x20      ttvm benchmark-performance                      # Run comprehensive performance benchmarks\n\
              \x20      ttvm benchmark-ir-vs-stack                      # Compare IR vs Stack execution performance\n\
              \n\
-             GC Types: mark-sweep (default), no-gc\n\
+             GC Types: mark-sweep (default), no-gc; --gc-threshold <n> collects every n list, object and closure allocations (default 1000)\n\
              SMP Scheduler: Enabled by default with all CPU cores. Use --no-smp for single-threaded mode.\n\
              Debug Output: --run-tests enables unit test tables, --gc-debug enables GC debug tables\n\
              Table Control: --no-table disables formatted output in favor of plain text\n\
-             Performance: --trace enables instruction tracing, --profile enables function profiling\n\
+             Performance: --trace enables instruction tracing, --trace-out <path> writes the instruction trace to a file (ip, stack depth and instruction, tab-separated) and runs single-threaded, --profile enables function profiling, --flamegraph-out <path> writes the call stacks folded for flamegraph tools (call path and instructions executed, one per line) and runs single-threaded\n\
              Concurrency: Multi-core execution enabled by default, --trace-procs enables process tracing, --profile-procs enables process profiling\n\
-             Execution Modes: --use-ir enables experimental register-based IR execution (basic programs only)"
+             Checking: --check reports stack underflows and branches that leave different stack depths, without running the program\n\
+             Execution Modes: --use-ir enables experimental register-based IR execution (basic programs only)\n\
+             Debugger: --break <addr> sets a breakpoint (repeatable), --watch <var> reports every change to a variable (repeatable), --interactive pauses at breakpoints and watched changes (step, continue, stack, vars, quit) and runs single-threaded\n\
+             Input: pass - as the program to read .ttvm source from stdin; arguments after the program are passed to GET_ARGS, and VM flags must come before it\n\
+             Limits: --max-call-depth <n> sets the maximum function call depth (default 10000), --reductions <n> sets how many instructions a process runs before yielding (default 1000), --max-stack <n> raises StackOverflow once the operand stack holds more than n values (default: unbounded)\n\
+             Output: --float-precision <n> prints floats with n digits after the decimal point (default: shortest exact form, always with a decimal point)\n\
+             Distribution: --node <host:port> accepts messages from other VMs for registered processes; SENDNAMED name@host:port sends to one"
         )
     }
 }
\ No newline at end of file
@@ -1,4 +1,3 @@
-use std::collections::HashMap;
 use std::fs;
 use std::time::Duration;
 use std::thread;
@@ -7,8 +6,8 @@ use crossbeam::channel::Sender;
 use comfy_table::{Table, Cell, presets::UTF8_FULL, modifiers::UTF8_SOLID_INNER_BORDERS, Color, Attribute};
 use colored::*;
 
-use crate::vm::{VMError, VMResult, OpCode, ProcId, MessagePattern, VM};
-use crate::concurrency::{Message, RestartStrategy, ChildType, Shutdown, ChildSpec, RestartPolicy, SupervisorSpec, TinyProc, ProcessSpawner, SchedulerPool};
+use crate::vm::{OpCode, ProcId, MessagePattern, VM, VMError};
+use crate::concurrency::{lock, Message, RestartStrategy, ChildType, Shutdown, ChildSpec, RestartPolicy, SupervisorSpec, TinyProc, ProcessSpawner, SchedulerPool, NodeListener};
 use crate::testing::{TestResult, run_vm_tests, report_gc_stats};
 use crate::cli::args::{CliArgs, CliCommand};
 use crate::compiler;
@@ -16,40 +15,60 @@ use crate::lisp_compiler;
 use crate::optimizer;
 use crate::bytecode;
 use crate::ProcState;
+use crate::ir::analysis::StackProblem;
 
 // Single-threaded scheduler implementation
 pub struct SingleThreadScheduler {
     processes: Vec<Arc<Mutex<TinyProc>>>,
+    spawned: Arc<Mutex<SpawnQueue>>, // shared with the spawners handed to supervisors
+}
+
+// Process ids and the processes spawned from inside a running process. They wait here until the
+// scheduler takes them at the start of its next round, so nothing touches `processes` mid-round.
+#[derive(Debug)]
+struct SpawnQueue {
     next_proc_id: ProcId,
+    pending: Vec<Arc<Mutex<TinyProc>>>,
+}
+
+impl SpawnQueue {
+    fn spawn(&mut self, instructions: Vec<OpCode>, parent: Option<ProcId>) -> (ProcId, Sender<Message>) {
+        let proc_id = self.next_id();
+        let (mut proc, sender) = TinyProc::new(proc_id, instructions);
+        proc.parent_pid = parent;
+        self.pending.push(Arc::new(Mutex::new(proc)));
+        (proc_id, sender)
+    }
+
+    fn next_id(&mut self) -> ProcId {
+        let proc_id = self.next_proc_id;
+        self.next_proc_id += 1;
+        proc_id
+    }
 }
 
 impl SingleThreadScheduler {
     pub fn new() -> Self {
         SingleThreadScheduler {
             processes: Vec::new(),
-            next_proc_id: 1,
+            spawned: Arc::new(Mutex::new(SpawnQueue { next_proc_id: 1, pending: Vec::new() })),
         }
     }
     
     pub fn spawn_process(&mut self, instructions: Vec<OpCode>) -> (ProcId, Sender<Message>) {
-        let proc_id = self.next_proc_id;
-        self.next_proc_id += 1;
-        
-        let (proc, sender) = TinyProc::new(proc_id, instructions);
-        self.processes.push(Arc::new(Mutex::new(proc)));
-        
-        (proc_id, sender)
+        let spawned = lock(&self.spawned).spawn(instructions, None);
+        self.admit_spawned();
+        spawned
     }
     
     pub fn spawn_supervisor(&mut self, spec: SupervisorSpec) -> (ProcId, Sender<Message>) {
-        let proc_id = self.next_proc_id;
-        self.next_proc_id += 1;
+        let proc_id = lock(&self.spawned).next_id();
         
         let (mut proc, sender) = TinyProc::new_supervisor(proc_id, spec);
         
         // Set up process spawner for the supervisor
         proc.process_spawner = Some(Arc::new(SingleThreadSchedulerProcessSpawner {
-            scheduler: Arc::new(Mutex::new(self as *mut SingleThreadScheduler)),
+            spawned: self.spawned.clone(),
         }));
         
         // Start all children
@@ -57,12 +76,22 @@ impl SingleThreadScheduler {
             eprintln!("Failed to start supervisor children: {}", e);
         }
         
+        self.admit_spawned();
         self.processes.push(Arc::new(Mutex::new(proc)));
         (proc_id, sender)
     }
     
+    // Move processes spawned since the last round into the run list; true if there were any
+    fn admit_spawned(&mut self) -> bool {
+        let pending = std::mem::take(&mut lock(&self.spawned).pending);
+        let admitted = !pending.is_empty();
+        self.processes.extend(pending);
+        admitted
+    }
+    
     pub fn run_step(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         // Run one step of the scheduler
+        self.admit_spawned();
         if self.processes.is_empty() {
             return Ok(());
         }
@@ -70,7 +99,7 @@ impl SingleThreadScheduler {
         let mut processes_to_remove = Vec::new();
         
         for (i, proc_arc) in self.processes.iter().enumerate() {
-            let mut proc = proc_arc.lock().unwrap();
+            let mut proc = lock(proc_arc);
             
             match proc.state {
                 ProcState::Ready | ProcState::Running => {
@@ -125,7 +154,7 @@ impl SingleThreadScheduler {
             let mut processes_to_remove = Vec::new();
             
             for (i, proc_arc) in self.processes.iter().enumerate() {
-                let mut proc = proc_arc.lock().unwrap();
+                let mut proc = lock(proc_arc);
                 
                 match proc.state {
                     ProcState::Ready => {
@@ -169,6 +198,11 @@ impl SingleThreadScheduler {
                 self.processes.remove(i);
             }
             
+            // Processes spawned this round run from the next one
+            if self.admit_spawned() {
+                active_processes = true;
+            }
+            
             if self.processes.is_empty() {
                 break;
             }
@@ -181,19 +215,20 @@ impl SingleThreadScheduler {
 // Process spawner implementation for SingleThreadScheduler
 #[derive(Debug)]
 pub struct SingleThreadSchedulerProcessSpawner {
-    scheduler: Arc<Mutex<*mut SingleThreadScheduler>>,
+    spawned: Arc<Mutex<SpawnQueue>>,
 }
 
-unsafe impl Send for SingleThreadSchedulerProcessSpawner {}
-unsafe impl Sync for SingleThreadSchedulerProcessSpawner {}
-
 impl ProcessSpawner for SingleThreadSchedulerProcessSpawner {
     fn spawn_process(&self, instructions: Vec<OpCode>) -> (ProcId, Sender<Message>) {
-        unsafe {
-            let scheduler_ptr = *self.scheduler.lock().unwrap();
-            let scheduler = &mut *scheduler_ptr;
-            scheduler.spawn_process(instructions)
-        }
+        lock(&self.spawned).spawn(instructions, None)
+    }
+    
+    fn spawn_child(&self, parent: ProcId, instructions: Vec<OpCode>) -> (ProcId, Sender<Message>) {
+        lock(&self.spawned).spawn(instructions, Some(parent))
+    }
+    
+    fn spawn_registered(&self, _parent: ProcId, name: &str, _instructions: Vec<OpCode>) -> Result<(ProcId, Sender<Message>), String> {
+        Err(format!("cannot register '{}': the single-threaded scheduler has no name registry", name))
     }
 }
 
@@ -225,6 +260,9 @@ pub fn execute_command(args: &CliArgs) -> Result<(), Box<dyn std::error::Error>>
             optimize_program(input, output);
             Ok(())
         }
+        CliCommand::Disassemble { input } => disassemble_program(input),
+        CliCommand::Bench { file, iterations } => bench_program(file, *iterations, args),
+        CliCommand::Repl => run_repl(args),
         CliCommand::TestAll => {
             run_comprehensive_tests();
             Ok(())
@@ -336,10 +374,12 @@ pub fn execute_command(args: &CliArgs) -> Result<(), Box<dyn std::error::Error>>
 
 fn program_has_concurrency_ops(program: &[OpCode]) -> bool {
     program.iter().any(|op| matches!(op, 
-        OpCode::Spawn | OpCode::Receive | OpCode::ReceiveMatch(_) | 
-        OpCode::Yield | OpCode::Send(_) | OpCode::Monitor(_) | 
+        OpCode::Spawn | OpCode::SpawnRegistered(_) | OpCode::Receive | OpCode::ReceiveMatch(_) | OpCode::ReceiveMatchAfter(..) | 
+        OpCode::Yield | OpCode::Send(_) | OpCode::Broadcast | OpCode::Monitor(_) | 
         OpCode::Demonitor(_) | OpCode::Link(_) | OpCode::Unlink(_) | 
-        OpCode::TrapExit | OpCode::Register(_) | OpCode::Unregister(_) | 
+        OpCode::SelfPid | OpCode::ParentPid | OpCode::TrapExit | OpCode::ProcCall | OpCode::ProcReply | OpCode::SendAfter | OpCode::CancelTimer | OpCode::Register(_) | OpCode::Unregister(_) | 
+        OpCode::PutDict | OpCode::GetDict | OpCode::EraseDict | 
+        OpCode::TableNew | OpCode::TableInsert | OpCode::TableLookup | OpCode::TableDelete | 
         OpCode::Whereis(_) | OpCode::SendNamed(_) | OpCode::StartSupervisor | 
         OpCode::SuperviseChild(_) | OpCode::RestartChild(_)
     ))
@@ -348,13 +388,21 @@ fn program_has_concurrency_ops(program: &[OpCode]) -> bool {
 fn execute_program_file(file: &str, args: &CliArgs) -> Result<(), Box<dyn std::error::Error>> {
     let config = args.to_vm_config();
 
-    // Load program
-    let mut program = if file.ends_with(".ttb") {
-        bytecode::load_bytecode(file)?
+    // Load program, with source lines when the file has them (bytecode only does if the
+    // compiler recorded debug info). A file name of `-` reads .ttvm source text from stdin.
+    let (mut program, mut source_lines) = if file == "-" {
+        let mut source = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut source)?;
+        let (program, lines) = bytecode::parse_source(&source)?;
+        (program, Some(lines))
     } else {
-        parse_program(file)?
+        bytecode::load_program_with_lines(file)?
     };
 
+    if args.check_mode {
+        return check_program(&program, source_lines.as_deref());
+    }
+
     // Apply optimizations if requested
     if args.optimize_mode {
         let mut optimizer = optimizer::Optimizer::new(optimizer::OptimizationOptions::default());
@@ -362,6 +410,8 @@ fn execute_program_file(file: &str, args: &CliArgs) -> Result<(), Box<dyn std::e
         
         let (optimized_program, stats) = optimizer.optimize(program);
         program = optimized_program;
+        // Optimization rewrites instruction addresses, so the line mapping no longer applies
+        source_lines = None;
         
         let analysis_after = optimizer.analyze_program(&program);
         
@@ -378,6 +428,10 @@ fn execute_program_file(file: &str, args: &CliArgs) -> Result<(), Box<dyn std::e
         println!("Constants propagated: {}", stats.constants_propagated);
         println!("Instructions combined: {}", stats.instructions_combined);
         println!("Jumps threaded: {}", stats.jumps_threaded);
+        println!("Common subexpressions eliminated: {}", stats.common_subexpressions_eliminated);
+        println!("Calls inlined: {}", stats.inlined_calls);
+        println!("Dead stores removed: {}", stats.dead_stores_removed);
+        println!("Unreachable instructions: {}", stats.unreachable_instructions);
         println!();
     }
 
@@ -385,45 +439,97 @@ fn execute_program_file(file: &str, args: &CliArgs) -> Result<(), Box<dyn std::e
     if config.use_ir {
         println!("Running with IR (Intermediate Representation) execution...");
         
-        // Convert stack-based bytecode to register-based IR first
-        use crate::ir::lowering::StackToRegisterLowering;
-        let ir_block = StackToRegisterLowering::lower(&program)?;
-        
-        // For programs with concurrency operations, we need to use the scheduler
-        // For now, fall back to regular scheduler since full IR scheduler integration
-        // requires more complex changes. The IR translation was performed, showing
-        // that concurrency operations can be compiled to register form.
+        // Programs with concurrency operations need the scheduler, which runs TinyProc
+        // processes rather than register IR
         if program_has_concurrency_ops(&program) {
             println!("Program contains concurrency operations - using SMP scheduler...");
-            println!("Note: IR translation performed, but using TinyProc execution for full concurrency support");
             
-            // Use regular scheduler with original program 
             let mut scheduler_pool = SchedulerPool::new_with_default_threads();
+            scheduler_pool.program_args = Arc::new(config.program_args.clone());
+            scheduler_pool.max_reductions = config.max_reductions;
+            scheduler_pool.stack_limit = config.max_stack;
+            scheduler_pool.float_precision = config.float_precision;
             let (main_proc_id, _main_sender) = scheduler_pool.spawn_process(program);
-            println!("Process spawned with ID: {} (IR translation shown, TinyProc execution)", main_proc_id);
+            println!("Process spawned with ID: {} (TinyProc execution)", main_proc_id);
             
             scheduler_pool.run()?;
             scheduler_pool.wait_for_completion();
             
-            println!("Concurrency execution completed (IR translation capability demonstrated)");
-        } else {
-            // Simple programs without concurrency can use standalone IR VM
-            use crate::ir::vm::RegisterVM;
-            
-            // Execute using register-based IR VM
-            let mut ir_vm = RegisterVM::new(ir_block);
-            ir_vm.run()?;
-            
-            println!("IR execution completed successfully");
+            println!("Concurrency execution completed");
+            return Ok(());
+        }
+        
+        // Convert stack-based bytecode to register-based IR
+        use crate::ir::lowering::StackToRegisterLowering;
+        match StackToRegisterLowering::lower(&program) {
+            Ok(mut ir_block) => {
+                use crate::ir::vm::RegisterVM;
+                
+                if args.optimize_mode {
+                    use crate::ir::optimizer::IrOptimizer;
+
+                    let instructions_before = ir_block.instructions.len();
+                    let (optimized_block, stats) = IrOptimizer::new().optimize(ir_block);
+                    ir_block = optimized_block;
+
+                    println!("=== IR Optimization Results ===");
+                    println!("Instructions: {} -> {} ({})",
+                        instructions_before,
+                        ir_block.instructions.len(),
+                        instructions_before as i32 - ir_block.instructions.len() as i32);
+                    println!("Constants folded: {}", stats.constants_folded);
+                    println!("Dead instructions removed: {}", stats.dead_instructions_removed);
+                    println!();
+                }
+
+                if config.trace_out.is_some() {
+                    eprintln!("Note: --trace-out traces the stack VM and is ignored with --use-ir");
+                }
+                if config.flamegraph_out.is_some() {
+                    eprintln!("Note: --flamegraph-out profiles the stack VM and is ignored with --use-ir");
+                }
+                let mut ir_vm = RegisterVM::new(ir_block);
+                ir_vm.call_depth_limit = config.call_depth_limit;
+                ir_vm.program_args = config.program_args.clone();
+                ir_vm.float_precision = config.float_precision;
+                if let Some(lines) = source_lines {
+                    ir_vm.set_source_lines(lines);
+                }
+                ir_vm.run()?;
+                
+                println!("IR execution completed successfully");
+                return Ok(());
+            }
+            // Fall through to the stack VM below
+            Err(e) => println!("Note: {} - running on the stack VM instead", e),
         }
-    } else if config.smp_enabled {
-        // Use SMP scheduler if enabled
+    }
+
+    if config.smp_enabled && !config.interactive && config.trace_out.is_none() && config.flamegraph_out.is_none() && !config.use_ir {
+        // Use SMP scheduler if enabled (the interactive debugger, trace files and flamegraphs need the single-threaded VM)
         println!("Running with BEAM-style SMP scheduler...");
         println!("SMP enabled flag: {}", config.smp_enabled);
         println!("Debug: About to create SMP scheduler pool");
         
         // Create SMP scheduler pool with default number of threads (CPU cores)
         let mut scheduler_pool = SchedulerPool::new_with_default_threads();
+        scheduler_pool.program_args = Arc::new(config.program_args.clone());
+        scheduler_pool.max_reductions = config.max_reductions;
+        scheduler_pool.stack_limit = config.max_stack;
+        scheduler_pool.float_precision = config.float_precision;
+
+        // Accept messages from other nodes for processes registered here
+        let node = match &config.node_address {
+            Some(address) => {
+                let node = NodeListener::start(address, scheduler_pool.process_registry.clone())
+                    .map_err(|e| format!("Cannot listen on {}: {}", address, e))?;
+                println!("Node listening on {}", node.address);
+                // Other nodes can wake a process that looks stuck here
+                scheduler_pool.detect_deadlocks = false;
+                Some(node)
+            }
+            None => None,
+        };
         
         // Spawn the main process with the program
         let (main_proc_id, _main_sender) = scheduler_pool.spawn_process(program);
@@ -434,15 +540,38 @@ fn execute_program_file(file: &str, args: &CliArgs) -> Result<(), Box<dyn std::e
         
         // Wait for schedulers to finish (run() already sets shutdown flag)
         scheduler_pool.wait_for_completion();
+        if let Some(node) = node {
+            node.shutdown();
+        }
         
         println!("SMP scheduler shutdown complete");
     } else {
         // Regular single-threaded VM execution
+        if let Some(address) = &config.node_address {
+            eprintln!("Note: --node {} needs the SMP scheduler and is ignored", address);
+        }
         println!("Debug: Using regular VM (SMP disabled)");
         println!("SMP enabled flag: {}", config.smp_enabled);
-        let mut vm = VM::new_with_config(program, &config.gc_type, config.debug_mode || config.gc_debug, config.gc_stats, config.trace_enabled, config.profile_enabled);
+        let mut vm = VM::with_config(program, &config);
+        if let Some(lines) = source_lines {
+            vm.set_source_lines(lines);
+        }
+        for &address in &config.breakpoints {
+            vm.add_breakpoint(address);
+        }
+        for name in &config.watchpoints {
+            vm.add_watchpoint(name);
+        }
+        if let Some(path) = &config.trace_out {
+            vm.set_trace_file(path)?;
+        }
         vm.run()?;
         
+        if let (Some(path), Some(profiler)) = (&config.flamegraph_out, &vm.profiler) {
+            profiler.write_folded_stacks(path)
+                .map_err(|e| format!("Cannot write flamegraph file {}: {}", path, e))?;
+        }
+
         // Output profiling results if enabled (only for regular VM mode)
         if config.profile_enabled {
             if let Some(profiler) = &vm.profiler {
@@ -451,9 +580,9 @@ fn execute_program_file(file: &str, args: &CliArgs) -> Result<(), Box<dyn std::e
         }
         
         if args.debug_mode {
-            let (instructions, max_stack, final_stack) = vm.get_stats();
-            println!("Performance stats - Instructions: {}, Max stack: {}, Final stack: {}", 
-                instructions, max_stack, final_stack);
+            let (instructions, max_stack, final_stack, live_objects, heap_bytes) = vm.get_stats();
+            println!("Performance stats - Instructions: {}, Max stack: {}, Final stack: {}, Heap objects: {}, Heap bytes: ~{}", 
+                instructions, max_stack, final_stack, live_objects, heap_bytes);
         }
         
         if config.gc_stats {
@@ -465,353 +594,39 @@ fn execute_program_file(file: &str, args: &CliArgs) -> Result<(), Box<dyn std::e
     Ok(())
 }
 
-fn parse_program(path: &str) -> VMResult<Vec<OpCode>> {
-    let content = fs::read_to_string(path).map_err(|e| VMError::FileError { 
-        filename: path.to_string(), 
-        error: e.to_string() 
-    })?;
-
-    let mut label_map: HashMap<String, usize> = HashMap::new();
-    let mut instructions_raw: Vec<(usize, &str)> = Vec::new();
 
-    // First pass: build label -> index map
-    for (line_num, line) in content.lines().enumerate() {
-        let line = line.split(';').next().unwrap_or("").trim();
-        if line.is_empty() {
-            continue;
-        }
-        if line.starts_with("LABEL ") {
-            let label_name = line[6..].trim();
-            label_map.insert(label_name.to_string(), instructions_raw.len());
-        } else {
-            instructions_raw.push((line_num + 1, line)); // save for second pass
-        }
-    }
-
-    // Second pass: convert raw instructions to OpCode using label map
-    let mut program: Vec<OpCode> = Vec::new();
-    for (line_num, line) in instructions_raw {
-        let parts: Vec<&str> = line.splitn(2, ' ').collect();
-        let opcode = match parts[0] {
-            "PUSH_INT" => {
-                let n = parts[1].parse::<i64>().map_err(|_| VMError::ParseError { 
-                    line: line_num, 
-                    instruction: format!("Invalid integer: {}", parts[1]) 
-                })?;
-                OpCode::PushInt(n)
-            }
-            "PUSH_FLOAT" => {
-                let f = parts[1].parse::<f64>().map_err(|_| VMError::ParseError { 
-                    line: line_num, 
-                    instruction: format!("Invalid float: {}", parts[1]) 
-                })?;
-                OpCode::PushFloat(f)
-            }
-            "PUSH_STR" => {
-                let s = parts[1].trim_matches('"').to_string();
-                OpCode::PushStr(s)
-            }
-            "PUSH_BOOL" => {
-                let b = parts[1].parse::<bool>().map_err(|_| VMError::ParseError { 
-                    line: line_num, 
-                    instruction: format!("Invalid boolean: {}", parts[1]) 
-                })?;
-                OpCode::PushBool(b)
-            }
-            "ADD" => OpCode::Add,
-            "ADD_F" => OpCode::AddF,
-            "SUB" => OpCode::Sub,
-            "SUB_F" => OpCode::SubF,
-            "MUL" => OpCode::Mul,
-            "MUL_F" => OpCode::MulF,
-            "DIV" => OpCode::Div,
-            "DIV_F" => OpCode::DivF,
-            "DUP" => OpCode::Dup,
-            "CONCAT" => OpCode::Concat,
-            "PRINT" => OpCode::Print,
-            "HALT" => OpCode::Halt,
-            "CALL" => {
-                if parts.len() < 2 {
-                    return Err(VMError::ParseError { line: line_num, instruction: "CALL requires at least a target".to_string() });
-                }
-                let call_parts: Vec<&str> = parts[1].split_whitespace().collect();
-                let label = call_parts[0];
-                let params: Vec<String> = call_parts[1..].iter().map(|s| s.to_string()).collect();
-                
-                // Try parsing as number first, then as label
-                let target = if let Ok(addr) = label.parse::<usize>() {
-                    addr
-                } else {
-                    *label_map.get(label).ok_or_else(|| VMError::UnknownLabel(label.to_string()))?
-                };
-                OpCode::Call { addr: target, params }
-            }
-            "JMP" => {
-                let label = parts[1].trim();
-                let target = if let Ok(addr) = label.parse::<usize>() {
-                    addr
-                } else {
-                    *label_map.get(label).ok_or_else(|| VMError::UnknownLabel(label.to_string()))?
-                };
-                OpCode::Jmp(target)
-            }
-            "JZ" => {
-                let label = parts[1].trim();
-                let target = if let Ok(addr) = label.parse::<usize>() {
-                    addr
-                } else {
-                    *label_map.get(label).ok_or_else(|| VMError::UnknownLabel(label.to_string()))?
-                };
-                OpCode::Jz(target)
-            }
-            "RET" => OpCode::Ret,
-            "STORE" => {
-                let var = parts[1].trim().to_string();
-                OpCode::Store(var)
-            }
-            "DELETE" => {
-                let var = parts[1].trim().to_string();
-                OpCode::Delete(var)
-            }
-            "LOAD" => {
-                let var = parts[1].trim().to_string();
-                OpCode::Load(var)
-            }
-            "EQ" => OpCode::Eq,
-            "GT" => OpCode::Gt,
-            "LT" => OpCode::Lt,
-            "NE" => OpCode::Ne,
-            "GE" => OpCode::Ge,
-            "LE" => OpCode::Le,
-            "EQ_F" => OpCode::EqF,
-            "GT_F" => OpCode::GtF,
-            "LT_F" => OpCode::LtF,
-            "NE_F" => OpCode::NeF,
-            "GE_F" => OpCode::GeF,
-            "LE_F" => OpCode::LeF,
-            "TRUE" => OpCode::True,
-            "FALSE" => OpCode::False,
-            "NOT" => OpCode::Not,
-            "AND" => OpCode::And,
-            "OR" => OpCode::Or,
-            "NULL" => OpCode::Null,
-            "MAKE_LIST" => {
-                let n = parts[1].parse::<usize>().map_err(|_| VMError::ParseError { 
-                    line: line_num, 
-                    instruction: format!("Invalid list size: {}", parts[1]) 
-                })?;
-                OpCode::MakeList(n)
-            }
-            "LEN" => OpCode::Len,
-            "INDEX" => OpCode::Index,
-            "DUMP_SCOPE" => OpCode::DumpScope,
-            "MAKE_OBJECT" => OpCode::MakeObject,
-            "SET_FIELD" => {
-                let field = parts[1].trim().to_string();
-                OpCode::SetField(field)
-            }
-            "GET_FIELD" => {
-                let field = parts[1].trim().to_string();
-                OpCode::GetField(field)
-            }
-            "HAS_FIELD" => {
-                let field = parts[1].trim().to_string();
-                OpCode::HasField(field)
-            }
-            "DELETE_FIELD" => {
-                let field = parts[1].trim().to_string();
-                OpCode::DeleteField(field)
-            }
-            "KEYS" => OpCode::Keys,
-            "MAKE_FUNCTION" => {
-                if parts.len() < 2 {
-                    return Err(VMError::ParseError { line: line_num, instruction: "MAKE_FUNCTION requires at least a target".to_string() });
-                }
-                let func_parts: Vec<&str> = parts[1].split_whitespace().collect();
-                let label = func_parts[0];
-                let params: Vec<String> = func_parts[1..].iter().map(|s| s.to_string()).collect();
-                
-                // Try parsing as number first, then as label
-                let addr = if let Ok(address) = label.parse::<usize>() {
-                    address
-                } else {
-                    *label_map.get(label).ok_or_else(|| VMError::UnknownLabel(label.to_string()))?
-                };
-                OpCode::MakeFunction { addr, params }
-            }
-            "CALL_FUNCTION" => OpCode::CallFunction,
-            "MAKE_LAMBDA" => {
-                if parts.len() < 2 {
-                    return Err(VMError::ParseError { line: line_num, instruction: line.to_string() });
-                }
-                
-                let remaining_parts: Vec<&str> = parts[1].split_whitespace().collect();
-                if remaining_parts.is_empty() {
-                    return Err(VMError::ParseError { line: line_num, instruction: line.to_string() });
-                }
-                
-                let label = remaining_parts[0];
-                let params = remaining_parts[1..].iter().map(|s| s.to_string()).collect();
-                
-                let addr = if let Ok(addr) = label.parse::<usize>() {
-                    addr
-                } else {
-                    *label_map.get(label).ok_or_else(|| VMError::UnknownLabel(label.to_string()))?
-                };
-                OpCode::MakeLambda { addr, params }
-            }
-            "CAPTURE" => {
-                let var = parts[1].trim().to_string();
-                OpCode::Capture(var)
-            }
-            "TRY" => {
-                let catch_label = parts[1].trim();
-                let catch_addr = *label_map.get(catch_label).ok_or_else(|| VMError::UnknownLabel(catch_label.to_string()))?;
-                OpCode::Try { catch_addr }
-            }
-            "CATCH" => OpCode::Catch,
-            "THROW" => OpCode::Throw,
-            "END_TRY" => OpCode::EndTry,
-            "READ_FILE" => OpCode::ReadFile,
-            "WRITE_FILE" => OpCode::WriteFile,
-            // Enhanced I/O operations
-            "READ_LINE" => OpCode::ReadLine,
-            "READ_CHAR" => OpCode::ReadChar,
-            "READ_INPUT" => OpCode::ReadInput,
-            "APPEND_FILE" => OpCode::AppendFile,
-            "FILE_EXISTS" => OpCode::FileExists,
-            "FILE_SIZE" => OpCode::FileSize,
-            "DELETE_FILE" => OpCode::DeleteFile,
-            "LIST_DIR" => OpCode::ListDir,
-            "READ_BYTES" => OpCode::ReadBytes,
-            "WRITE_BYTES" => OpCode::WriteBytes,
-            // Environment and system
-            "GET_ENV" => OpCode::GetEnv,
-            "SET_ENV" => OpCode::SetEnv,
-            "GET_ARGS" => OpCode::GetArgs,
-            "EXEC" => OpCode::Exec,
-            "EXEC_CAPTURE" => OpCode::ExecCapture,
-            "EXIT" => OpCode::Exit,
-            // Time operations
-            "GET_TIME" => OpCode::GetTime,
-            "SLEEP" => OpCode::Sleep,
-            "FORMAT_TIME" => OpCode::FormatTime,
-            // Network operations
-            "HTTP_GET" => OpCode::HttpGet,
-            "HTTP_POST" => OpCode::HttpPost,
-            "TCP_CONNECT" => OpCode::TcpConnect,
-            "TCP_LISTEN" => OpCode::TcpListen,
-            "TCP_SEND" => OpCode::TcpSend,
-            "TCP_RECV" => OpCode::TcpRecv,
-            "UDP_BIND" => OpCode::UdpBind,
-            "UDP_SEND" => OpCode::UdpSend,
-            "UDP_RECV" => OpCode::UdpRecv,
-            "DNS_RESOLVE" => OpCode::DnsResolve,
-            // Advanced I/O operations
-            "ASYNC_READ" => OpCode::AsyncRead,
-            "ASYNC_WRITE" => OpCode::AsyncWrite,
-            "AWAIT" => OpCode::Await,
-            "STREAM_CREATE" => OpCode::StreamCreate,
-            "STREAM_READ" => OpCode::StreamRead,
-            "STREAM_WRITE" => OpCode::StreamWrite,
-            "STREAM_CLOSE" => OpCode::StreamClose,
-            "JSON_PARSE" => OpCode::JsonParse,
-            "JSON_STRINGIFY" => OpCode::JsonStringify,
-            "CSV_PARSE" => OpCode::CsvParse,
-            "CSV_WRITE" => OpCode::CsvWrite,
-            "COMPRESS" => OpCode::Compress,
-            "DECOMPRESS" => OpCode::Decompress,
-            "ENCRYPT" => OpCode::Encrypt,
-            "DECRYPT" => OpCode::Decrypt,
-            "HASH" => OpCode::Hash,
-            "DB_CONNECT" => OpCode::DbConnect,
-            "DB_QUERY" => OpCode::DbQuery,
-            "DB_EXEC" => OpCode::DbExec,
-            "IMPORT" => {
-                let path = parts[1].trim();
-                // Remove quotes if present
-                let path = if path.starts_with('"') && path.ends_with('"') {
-                    path[1..path.len()-1].to_string()
-                } else {
-                    path.to_string()
-                };
-                OpCode::Import(path)
-            }
-            "EXPORT" => {
-                let name = parts[1].trim().to_string();
-                OpCode::Export(name)
-            }
-            "YIELD" => OpCode::Yield,
-            "RECEIVE" => OpCode::Receive,
-            "SEND" => {
-                let pid = parts[1].parse::<u64>().map_err(|_| VMError::ParseError { 
-                    line: line_num, 
-                    instruction: format!("Invalid PID: {}", parts[1]) 
-                })?;
-                OpCode::Send(pid)
-            }
-            "REGISTER" => {
-                let name = parts[1].trim_matches('"').to_string();
-                OpCode::Register(name)
-            }
-            "WHEREIS" => {
-                let name = parts[1].trim_matches('"').to_string();
-                OpCode::Whereis(name)
-            }
-            "SPAWN" => OpCode::Spawn,
-            "SENDNAMED" => {
-                let name = parts[1].trim_matches('"').to_string();
-                OpCode::SendNamed(name)
-            }
-            "UNREGISTER" => {
-                let name = parts[1].trim_matches('"').to_string();
-                OpCode::Unregister(name)
-            }
-            "TRAP_EXIT" => OpCode::TrapExit,
-            "START_SUPERVISOR" => OpCode::StartSupervisor,
-            "SUPERVISE_CHILD" => {
-                let name = parts[1].trim_matches('"').to_string();
-                OpCode::SuperviseChild(name)
-            }
-            "RESTART_CHILD" => {
-                let name = parts[1].trim_matches('"').to_string();
-                OpCode::RestartChild(name)
-            }
-            "LINK" => {
-                let pid = parts[1].parse::<u64>().map_err(|_| VMError::ParseError { 
-                    line: line_num, 
-                    instruction: format!("Invalid PID: {}", parts[1]) 
-                })?;
-                OpCode::Link(pid)
-            }
-            "UNLINK" => {
-                let pid = parts[1].parse::<u64>().map_err(|_| VMError::ParseError { 
-                    line: line_num, 
-                    instruction: format!("Invalid PID: {}", parts[1]) 
-                })?;
-                OpCode::Unlink(pid)
+// Reports stack-balance problems instead of running the program
+fn check_program(program: &[OpCode], source_lines: Option<&[usize]>) -> Result<(), Box<dyn std::error::Error>> {
+    let problems = crate::ir::analysis::check_stack_balance(program);
+    for problem in &problems {
+        let addr = problem.addr();
+        let location = match source_lines.and_then(|lines| lines.get(addr)) {
+            Some(line) => format!("line {}, instruction {}", line, addr),
+            None => format!("instruction {}", addr),
+        };
+        let text = bytecode::opcode_to_text(&program[addr]);
+        match problem {
+            StackProblem::Underflow { needed, depth, .. } => {
+                println!("{} ({}): needs {} value(s) but the stack holds {}", location, text, needed, depth);
             }
-            "MONITOR" => {
-                let pid = parts[1].parse::<u64>().map_err(|_| VMError::ParseError { 
-                    line: line_num, 
-                    instruction: format!("Invalid PID: {}", parts[1]) 
-                })?;
-                OpCode::Monitor(pid)
+            StackProblem::DepthMismatch { from, expected, depth, .. } => {
+                println!("{} ({}): reached with {} value(s) on the stack from instruction {}, but with {} along another path",
+                    location, text, depth, from, expected);
             }
-            "DEMONITOR" => {
-                let monitor_ref = parts[1].trim_matches('"').to_string();
-                OpCode::Demonitor(monitor_ref)
+            StackProblem::ReturnCount { declared, depth, .. } => {
+                println!("{} ({}): declares {} return value(s) but the function leaves {}", location, text, declared, depth);
             }
-            _ => return Err(VMError::ParseError { line: line_num, instruction: line.to_string() }),
-        };
-        program.push(opcode);
+        }
     }
-
-    Ok(program)
+    if !problems.is_empty() {
+        return Err(format!("{} stack problem(s) found", problems.len()).into());
+    }
+    println!("No stack problems found in {} instructions", program.len());
+    Ok(())
 }
 
 fn optimize_program(input_file: &str, output_file: &str) {
-    let program = match parse_program(input_file) {
+    let program = match bytecode::parse_program(input_file) {
         Ok(p) => p,
         Err(e) => {
             eprintln!("Parse error: {}", e);
@@ -830,6 +645,10 @@ fn optimize_program(input_file: &str, output_file: &str) {
     println!("Jumps: {}", analysis_before.jump_count);
     println!();
 
+    for index in optimizer.unreachable_instructions(&program) {
+        println!("Warning: instruction {} ({}) is unreachable", index, bytecode::opcode_to_text(&program[index]));
+    }
+
     let (optimized_program, stats) = optimizer.optimize(program);
     let analysis_after = optimizer.analyze_program(&optimized_program);
 
@@ -846,6 +665,10 @@ fn optimize_program(input_file: &str, output_file: &str) {
     println!("Constants propagated: {}", stats.constants_propagated);
     println!("Instructions combined: {}", stats.instructions_combined);
     println!("Jumps threaded: {}", stats.jumps_threaded);
+    println!("Common subexpressions eliminated: {}", stats.common_subexpressions_eliminated);
+    println!("Calls inlined: {}", stats.inlined_calls);
+    println!("Dead stores removed: {}", stats.dead_stores_removed);
+    println!("Unreachable instructions: {}", stats.unreachable_instructions);
     println!();
 
     // Write optimized program to file
@@ -861,149 +684,231 @@ fn optimize_program(input_file: &str, output_file: &str) {
 fn write_optimized_program(program: &[OpCode], output_file: &str) -> std::io::Result<()> {
     let mut output = String::new();
     
-    for (_i, instruction) in program.iter().enumerate() {
-        let line = match instruction {
-            OpCode::PushInt(n) => format!("PUSH_INT {}", n),
-            OpCode::PushFloat(f) => format!("PUSH_FLOAT {}", f),
-            OpCode::PushStr(s) => format!("PUSH_STR \"{}\"", s.replace("\"", "\\\"")),
-            OpCode::PushBool(b) => format!("PUSH_BOOL {}", b),
-            OpCode::Add => "ADD".to_string(),
-            OpCode::AddF => "ADD_F".to_string(),
-            OpCode::Sub => "SUB".to_string(),
-            OpCode::SubF => "SUB_F".to_string(),
-            OpCode::Mul => "MUL".to_string(),
-            OpCode::MulF => "MUL_F".to_string(),
-            OpCode::Div => "DIV".to_string(),
-            OpCode::DivF => "DIV_F".to_string(),
-            OpCode::Concat => "CONCAT".to_string(),
-            OpCode::Print => "PRINT".to_string(),
-            OpCode::Halt => "HALT".to_string(),
-            OpCode::Jmp(addr) => format!("JMP {}", addr),
-            OpCode::Jz(addr) => format!("JZ {}", addr),
-            OpCode::Call { addr, params } => format!("CALL {} {}", addr, params.join(" ")),
-            OpCode::Ret => "RET".to_string(),
-            OpCode::Dup => "DUP".to_string(),
-            OpCode::Store(var) => format!("STORE {}", var),
-            OpCode::Load(var) => format!("LOAD {}", var),
-            OpCode::Delete(var) => format!("DELETE {}", var),
-            OpCode::Eq => "EQ".to_string(),
-            OpCode::Ne => "NE".to_string(),
-            OpCode::Gt => "GT".to_string(),
-            OpCode::Lt => "LT".to_string(),
-            OpCode::Ge => "GE".to_string(),
-            OpCode::Le => "LE".to_string(),
-            OpCode::EqF => "EQ_F".to_string(),
-            OpCode::NeF => "NE_F".to_string(),
-            OpCode::GtF => "GT_F".to_string(),
-            OpCode::LtF => "LT_F".to_string(),
-            OpCode::GeF => "GE_F".to_string(),
-            OpCode::LeF => "LE_F".to_string(),
-            OpCode::True => "TRUE".to_string(),
-            OpCode::False => "FALSE".to_string(),
-            OpCode::Not => "NOT".to_string(),
-            OpCode::And => "AND".to_string(),
-            OpCode::Or => "OR".to_string(),
-            OpCode::Null => "NULL".to_string(),
-            OpCode::MakeList(n) => format!("MAKE_LIST {}", n),
-            OpCode::Len => "LEN".to_string(),
-            OpCode::Index => "INDEX".to_string(),
-            OpCode::DumpScope => "DUMP_SCOPE".to_string(),
-            OpCode::ReadFile => "READ_FILE".to_string(),
-            OpCode::WriteFile => "WRITE_FILE".to_string(),
-            // Enhanced I/O operations
-            OpCode::ReadLine => "READ_LINE".to_string(),
-            OpCode::ReadChar => "READ_CHAR".to_string(),
-            OpCode::ReadInput => "READ_INPUT".to_string(),
-            OpCode::AppendFile => "APPEND_FILE".to_string(),
-            OpCode::FileExists => "FILE_EXISTS".to_string(),
-            OpCode::FileSize => "FILE_SIZE".to_string(),
-            OpCode::DeleteFile => "DELETE_FILE".to_string(),
-            OpCode::ListDir => "LIST_DIR".to_string(),
-            OpCode::ReadBytes => "READ_BYTES".to_string(),
-            OpCode::WriteBytes => "WRITE_BYTES".to_string(),
-            // Environment and system
-            OpCode::GetEnv => "GET_ENV".to_string(),
-            OpCode::SetEnv => "SET_ENV".to_string(),
-            OpCode::GetArgs => "GET_ARGS".to_string(),
-            OpCode::Exec => "EXEC".to_string(),
-            OpCode::ExecCapture => "EXEC_CAPTURE".to_string(),
-            OpCode::Exit => "EXIT".to_string(),
-            // Time operations
-            OpCode::GetTime => "GET_TIME".to_string(),
-            OpCode::Sleep => "SLEEP".to_string(),
-            OpCode::FormatTime => "FORMAT_TIME".to_string(),
-            // Network operations
-            OpCode::HttpGet => "HTTP_GET".to_string(),
-            OpCode::HttpPost => "HTTP_POST".to_string(),
-            OpCode::TcpConnect => "TCP_CONNECT".to_string(),
-            OpCode::TcpListen => "TCP_LISTEN".to_string(),
-            OpCode::TcpSend => "TCP_SEND".to_string(),
-            OpCode::TcpRecv => "TCP_RECV".to_string(),
-            OpCode::UdpBind => "UDP_BIND".to_string(),
-            OpCode::UdpSend => "UDP_SEND".to_string(),
-            OpCode::UdpRecv => "UDP_RECV".to_string(),
-            OpCode::DnsResolve => "DNS_RESOLVE".to_string(),
-            // Advanced I/O operations
-            OpCode::AsyncRead => "ASYNC_READ".to_string(),
-            OpCode::AsyncWrite => "ASYNC_WRITE".to_string(),
-            OpCode::Await => "AWAIT".to_string(),
-            OpCode::StreamCreate => "STREAM_CREATE".to_string(),
-            OpCode::StreamRead => "STREAM_READ".to_string(),
-            OpCode::StreamWrite => "STREAM_WRITE".to_string(),
-            OpCode::StreamClose => "STREAM_CLOSE".to_string(),
-            OpCode::JsonParse => "JSON_PARSE".to_string(),
-            OpCode::JsonStringify => "JSON_STRINGIFY".to_string(),
-            OpCode::CsvParse => "CSV_PARSE".to_string(),
-            OpCode::CsvWrite => "CSV_WRITE".to_string(),
-            OpCode::Compress => "COMPRESS".to_string(),
-            OpCode::Decompress => "DECOMPRESS".to_string(),
-            OpCode::Encrypt => "ENCRYPT".to_string(),
-            OpCode::Decrypt => "DECRYPT".to_string(),
-            OpCode::Hash => "HASH".to_string(),
-            OpCode::DbConnect => "DB_CONNECT".to_string(),
-            OpCode::DbQuery => "DB_QUERY".to_string(),
-            OpCode::DbExec => "DB_EXEC".to_string(),
-            OpCode::MakeObject => "MAKE_OBJECT".to_string(),
-            OpCode::SetField(field) => format!("SET_FIELD {}", field),
-            OpCode::GetField(field) => format!("GET_FIELD {}", field),
-            OpCode::HasField(field) => format!("HAS_FIELD {}", field),
-            OpCode::DeleteField(field) => format!("DELETE_FIELD {}", field),
-            OpCode::Keys => "KEYS".to_string(),
-            OpCode::MakeFunction { addr, params } => format!("MAKE_FUNCTION {} {}", addr, params.join(" ")),
-            OpCode::CallFunction => "CALL_FUNCTION".to_string(),
-            OpCode::MakeLambda { addr, params } => format!("MAKE_LAMBDA {} {}", addr, params.join(" ")),
-            OpCode::Capture(var) => format!("CAPTURE {}", var),
-            OpCode::Try { catch_addr } => format!("TRY {}", catch_addr),
-            OpCode::Catch => "CATCH".to_string(),
-            OpCode::Throw => "THROW".to_string(),
-            OpCode::EndTry => "END_TRY".to_string(),
-            OpCode::Import(path) => format!("IMPORT {}", path),
-            OpCode::Export(name) => format!("EXPORT {}", name),
-            OpCode::Spawn => format!("SPAWN"),
-            OpCode::Receive => format!("RECEIVE"),
-            OpCode::ReceiveMatch(_patterns) => format!("RECEIVE_MATCH"),
-            OpCode::Yield => format!("YIELD"),
-            OpCode::Send(proc_id) => format!("SEND {}", proc_id),
-            OpCode::Monitor(proc_id) => format!("MONITOR {}", proc_id),
-            OpCode::Demonitor(monitor_ref) => format!("DEMONITOR {}", monitor_ref),
-            OpCode::Link(proc_id) => format!("LINK {}", proc_id),
-            OpCode::Unlink(proc_id) => format!("UNLINK {}", proc_id),
-            OpCode::TrapExit => "TRAP_EXIT".to_string(),
-            OpCode::Register(name) => format!("REGISTER {}", name),
-            OpCode::Unregister(name) => format!("UNREGISTER {}", name),
-            OpCode::Whereis(name) => format!("WHEREIS {}", name),
-            OpCode::SendNamed(name) => format!("SENDNAMED {}", name),
-            OpCode::StartSupervisor => "STARTSUPERVISOR".to_string(),
-            OpCode::SuperviseChild(name) => format!("SUPERVISECHILD {}", name),
-            OpCode::RestartChild(name) => format!("RESTARTCHILD {}", name),
-        };
-        output.push_str(&line);
+    for instruction in program {
+        output.push_str(&bytecode::opcode_to_text(instruction));
         output.push('\n');
     }
     
     std::fs::write(output_file, output)
 }
 
+fn disassemble_program(input_file: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let program = bytecode::load_bytecode(input_file)?;
+    let width = program.len().saturating_sub(1).to_string().len().max(4);
+
+    println!("; Disassembly of {} ({} instructions)", input_file, program.len());
+    for (addr, instruction) in program.iter().enumerate() {
+        let text = bytecode::opcode_to_text(instruction);
+        let target = match instruction {
+            OpCode::Jmp(target) | OpCode::Jz(target) => Some(*target),
+            OpCode::Call { addr, .. } | OpCode::MakeFunction { addr, .. } | OpCode::MakeLambda { addr, .. } => Some(*addr),
+            OpCode::Try { catch_addr } => Some(*catch_addr),
+            _ => None,
+        };
+        match target {
+            Some(target) => {
+                let resolved = program.get(target)
+                    .map(bytecode::opcode_to_text)
+                    .unwrap_or_else(|| "<out of range>".to_string());
+                println!("{:0width$}  {:<24} ; -> {:0width$}: {}", addr, text, target, resolved, width = width);
+            }
+            None => println!("{:0width$}  {}", addr, text, width = width),
+        }
+    }
+    Ok(())
+}
+
+// Untimed runs of each engine before `ttvm bench` starts measuring
+const BENCH_WARMUP_RUNS: usize = 2;
+
+// Wall-clock times of one engine's measured runs of a program
+struct EngineTimings {
+    engine: &'static str,
+    runs: Vec<Duration>, // fastest first
+    instructions: usize, // instructions executed by one run
+}
+
+impl EngineTimings {
+    // The middle run, or the mean of the middle two
+    fn median(&self) -> Duration {
+        let mid = self.runs.len() / 2;
+        if self.runs.len() % 2 == 1 {
+            self.runs[mid]
+        } else {
+            (self.runs[mid - 1] + self.runs[mid]) / 2
+        }
+    }
+
+    // Throughput of the median run
+    fn instructions_per_second(&self) -> f64 {
+        let seconds = self.median().as_secs_f64();
+        if seconds > 0.0 { self.instructions as f64 / seconds } else { 0.0 }
+    }
+}
+
+fn time_engine(
+    engine: &'static str,
+    iterations: usize,
+    mut run: impl FnMut() -> Result<usize, Box<dyn std::error::Error>>,
+) -> Result<EngineTimings, Box<dyn std::error::Error>> {
+    for _ in 0..BENCH_WARMUP_RUNS {
+        run()?;
+    }
+    let mut runs = Vec::with_capacity(iterations);
+    let mut instructions = 0;
+    for _ in 0..iterations {
+        let start = std::time::Instant::now();
+        instructions = run()?;
+        runs.push(start.elapsed());
+    }
+    runs.sort();
+    Ok(EngineTimings { engine, runs, instructions })
+}
+
+// Run a program `iterations` times on the stack VM and on the register IR VM and compare
+// their timings. Program output is discarded, and lowering to IR happens once, untimed.
+fn bench_program(file: &str, iterations: usize, args: &CliArgs) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::ir::lowering::StackToRegisterLowering;
+    use crate::ir::vm::RegisterVM;
+    use crate::vm::OutputSink;
+
+    let program = bytecode::load_program(file)?;
+    if program_has_concurrency_ops(&program) {
+        return Err(format!("{} uses concurrency instructions, which only the scheduler runs; bench needs a single-process program", file).into());
+    }
+    let config = args.to_vm_config();
+
+    let stack = time_engine("Stack VM", iterations, || {
+        let mut vm = VM::new(program.clone());
+        vm.call_depth_limit = config.call_depth_limit;
+        vm.program_args = config.program_args.clone();
+        vm.set_output(OutputSink::new(std::io::sink()));
+        vm.run()?;
+        Ok(vm.instruction_count)
+    })?;
+    let block = StackToRegisterLowering::lower(&program)?;
+    let ir = time_engine("Register IR VM", iterations, || {
+        let mut vm = RegisterVM::new(block.clone());
+        vm.call_depth_limit = config.call_depth_limit;
+        vm.program_args = config.program_args.clone();
+        vm.set_output(OutputSink::new(std::io::sink()));
+        vm.run()?;
+        Ok(vm.instruction_count)
+    })?;
+
+    println!("Benchmark of {}: {} runs per engine after {} warm-up runs", file, iterations, BENCH_WARMUP_RUNS);
+    let micros = |duration: Duration| format!("{:.1}", duration.as_secs_f64() * 1e6);
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL)
+         .apply_modifier(UTF8_SOLID_INNER_BORDERS);
+    table.set_header(vec![
+        Cell::new("Engine").add_attribute(Attribute::Bold).fg(Color::Cyan),
+        Cell::new("Median (μs)").add_attribute(Attribute::Bold).fg(Color::Yellow),
+        Cell::new("Min (μs)").add_attribute(Attribute::Bold).fg(Color::Yellow),
+        Cell::new("Max (μs)").add_attribute(Attribute::Bold).fg(Color::Yellow),
+        Cell::new("Instructions").add_attribute(Attribute::Bold).fg(Color::Blue),
+        Cell::new("Instructions/sec").add_attribute(Attribute::Bold).fg(Color::Green),
+    ]);
+    for timings in [&stack, &ir] {
+        table.add_row(vec![
+            Cell::new(timings.engine).fg(Color::White),
+            Cell::new(micros(timings.median())),
+            Cell::new(micros(timings.runs[0])),
+            Cell::new(micros(timings.runs[timings.runs.len() - 1])),
+            Cell::new(timings.instructions.to_string()),
+            Cell::new(format!("{:.0}", timings.instructions_per_second())),
+        ]);
+    }
+    println!("{}", table);
+
+    let (stack_median, ir_median) = (stack.median().as_secs_f64(), ir.median().as_secs_f64());
+    if stack_median > 0.0 && ir_median > 0.0 {
+        if ir_median < stack_median {
+            println!("Register IR VM is {:.2}x faster (median)", stack_median / ir_median);
+        } else {
+            println!("Stack VM is {:.2}x faster (median)", ir_median / stack_median);
+        }
+    }
+    Ok(())
+}
+
+// Read instructions from stdin one line at a time and run them against a single VM,
+// so the stack and variables persist between inputs. Each line is parsed on its own, so
+// its labels and jump targets count from the start of that line and are shifted to where
+// the line lands in the program.
+fn run_repl(args: &CliArgs) -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::{BufRead, Write};
+
+    let config = args.to_vm_config();
+    let mut vm = VM::new_with_config(Vec::new(), &config.gc_type, config.debug_mode || config.gc_debug, config.gc_stats, config.trace_enabled, false);
+    vm.call_depth_limit = config.call_depth_limit;
+    vm.stack_limit = config.max_stack;
+    vm.set_gc_threshold(config.gc_threshold);
+    vm.float_precision = config.float_precision;
+
+    println!("TinyTotVM REPL - enter instructions, .stack or .vars to inspect, .exit to quit");
+    let stdin = std::io::stdin();
+    loop {
+        print!("ttvm> ");
+        std::io::stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            println!();
+            break;
+        }
+
+        match line.trim() {
+            "" => continue,
+            ".exit" | ".quit" => break,
+            ".stack" => {
+                println!("Stack: {:?}", vm.stack);
+                continue;
+            }
+            ".vars" => {
+                println!("Variables: {:?}", vm.variables.last());
+                continue;
+            }
+            _ => {}
+        }
+
+        let instructions = match bytecode::parse_source(&line) {
+            Ok((instructions, _)) => instructions,
+            Err(e) => {
+                println!("Error: {}", e);
+                continue;
+            }
+        };
+        if instructions.is_empty() {
+            continue;
+        }
+
+        let start = vm.instructions.len();
+        vm.instructions.extend(instructions.into_iter().map(|mut instruction| {
+            optimizer::retarget(&mut instruction, |target| target + start);
+            instruction
+        }));
+        vm.ip = start;
+        match vm.run() {
+            Ok(()) => match vm.stack.last() {
+                Some(top) => println!("=> {}", top),
+                None => println!("=> (empty stack)"),
+            },
+            Err(exit @ VMError::ProcessExit(_)) => return Err(Box::new(exit)),
+            Err(e) => {
+                // Skip whatever is left of the failed input and unwind any calls it made,
+                // so the next line starts fresh in the global frame
+                vm.ip = vm.instructions.len();
+                vm.call_stack.clear();
+                vm.variables.truncate(1);
+                vm.try_stack.clear();
+                println!("Error: {}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
 fn run_smp_test(program: Vec<OpCode>) -> Result<(), Box<dyn std::error::Error>> {
     // Create SMP scheduler pool with reduced verbosity for testing
     let mut scheduler_pool = SchedulerPool::new_with_default_threads();
@@ -1025,17 +930,30 @@ fn run_ir_test(program: Vec<OpCode>) -> Result<(), Box<dyn std::error::Error>> {
     let args = crate::cli::args::CliArgs {
         debug_mode: false,
         optimize_mode: false,
+        check_mode: false,
         gc_type: "mark-sweep".to_string(),
         gc_debug: false,
         gc_stats: false,
+        gc_threshold: crate::gc::DEFAULT_GC_THRESHOLD,
         run_tests: false,
         no_table: false,
         trace_enabled: false,
+        trace_out: None,
         profile_enabled: false,
+        flamegraph_out: None,
         smp_enabled: true,
         trace_procs: false,
         profile_procs: false,
         use_ir: true,
+        interactive: false,
+        breakpoints: Vec::new(),
+        watchpoints: Vec::new(),
+        call_depth_limit: crate::vm::DEFAULT_CALL_DEPTH_LIMIT,
+        max_reductions: crate::concurrency::DEFAULT_MAX_REDUCTIONS,
+        max_stack: None,
+        float_precision: None,
+        node_address: None,
+        program_args: Vec::new(),
         command: crate::cli::args::CliCommand::Run { file: "".to_string() },
     };
     
@@ -1044,12 +962,8 @@ fn run_ir_test(program: Vec<OpCode>) -> Result<(), Box<dyn std::error::Error>> {
     
     // Check if IR execution is requested
     if config.use_ir {
-        // Convert stack-based bytecode to register-based IR first
-        use crate::ir::lowering::StackToRegisterLowering;
-        let _ir_block = StackToRegisterLowering::lower(&program)?;
-        
         // For programs with concurrency operations, we use the SMP scheduler
-        // with the proven TinyProc execution system while showing IR translation capability
+        // with the proven TinyProc execution system
         if program_has_concurrency_ops(&program) {
             // Create SMP scheduler pool for IR concurrency testing
             let mut scheduler_pool = SchedulerPool::new_with_default_threads();
@@ -1064,6 +978,7 @@ fn run_ir_test(program: Vec<OpCode>) -> Result<(), Box<dyn std::error::Error>> {
             scheduler_pool.wait_for_completion();
         } else {
             // For non-concurrent programs, use direct IR execution
+            use crate::ir::lowering::StackToRegisterLowering;
             use crate::ir::vm::RegisterVM;
             let ir_block = StackToRegisterLowering::lower(&program)?;
             let mut ir_vm = RegisterVM::new(ir_block);
@@ -1254,6 +1169,11 @@ fn run_comprehensive_tests() {
     let expected_failures = std::collections::HashMap::from([
         ("circular_a.ttvm", "Circular dependency detected"),
         ("circular_b.ttvm", "Circular dependency detected"),
+        // Each waits for a message no other process sends
+        ("05_trap_exit_test.ttvm", "Deadlock"),
+        ("barista_worker.ttvm", "Deadlock"),
+        ("cashier_worker.ttvm", "Deadlock"),
+        ("test_examples.ttvm", "Deadlock"),
     ]);
     
     // Files that require concurrency features (should be run with SMP)
@@ -1315,7 +1235,7 @@ fn run_comprehensive_tests() {
         Write::flush(&mut std::io::stdout()).unwrap();
         
         // Parse and run the test
-        match parse_program(&path) {
+        match bytecode::parse_program(&path) {
             Ok(program) => {
                 let test_result = if use_ir {
                     // Run with IR mode (includes SMP for concurrency)
@@ -4,6 +4,7 @@ pub mod commands;
 use std::process;
 use args::CliArgs;
 use commands::execute_command;
+use crate::vm::VMError;
 
 /// Main entry point for the CLI application
 pub fn run_cli() -> Result<(), Box<dyn std::error::Error>> {
@@ -16,6 +17,10 @@ pub fn run_cli() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     if let Err(e) = execute_command(&args) {
+        // EXIT in the program unwinds as an error; turn it into the real exit status here
+        if let Some(VMError::ProcessExit(code)) = e.downcast_ref::<VMError>() {
+            process::exit(*code);
+        }
         eprintln!("Error: {}", e);
         process::exit(1);
     }
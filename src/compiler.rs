@@ -1,14 +1,17 @@
 // compiler.rs
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
+use std::io::Write;
 use std::path::Path;
+use crate::bytecode::{
+    parse_int_literal, parse_string_literal, strip_comments, BYTECODE_CONST_INT, BYTECODE_CONST_STR, BYTECODE_DEBUG_INFO,
+    BYTECODE_MAGIC, BYTECODE_VERSION,
+};
 
 #[repr(u16)]
 #[derive(Debug, Clone, Copy)]
 enum ByteCode {
-    PushInt = 0x01,
-    PushStr = 0x02,
+    // 0x01 and 0x02 were the inline PUSH_INT and PUSH_STR of version 1 files
     True = 0x03,
     False = 0x04,
     Null = 0x05,
@@ -16,10 +19,18 @@ enum ByteCode {
     And = 0x07,
     Or = 0x08,
     Dup = 0x09,
+    PushConst = 0x0A,
 
     Add = 0x10,
     Sub = 0x11,
     Concat = 0x12,
+    Pow = 0x13,
+    Sqrt = 0x14,
+    Abs = 0x15,
+    Floor = 0x16,
+    Ceil = 0x17,
+    Round = 0x18,
+    Neg = 0x19,
 
     Eq = 0x20,
     Ne = 0x23,
@@ -32,8 +43,12 @@ enum ByteCode {
     Jz = 0x31,
     Call = 0x32,
     Ret = 0x33,
+    RetN = 0x34,
 
     Print = 0x40,
+    PrintRaw = 0x41,
+    PrintErr = 0x42,
+    DebugPrint = 0x43,
     Halt = 0xFF,
 
     Store = 0x50,
@@ -48,6 +63,7 @@ enum ByteCode {
 
     ReadFile = 0x72,
     WriteFile = 0x73,
+    Assert = 0x74,
     
     // Concurrency opcodes
     Spawn = 0x80,
@@ -64,20 +80,56 @@ enum ByteCode {
     Yield = 0x8B,
     Receive = 0x8C,
     Send = 0x8D,
+    SelfPid = 0x8E,
+    ParentPid = 0x8F,
+
+    // Random numbers
+    RandomInt = 0x90,
+    RandomFloat = 0x91,
+    RandomSeed = 0x92,
+    Uuid = 0x93,
+
+    // Functions and modules
+    MakeFunction = 0xA0,
+    CallFunction = 0xA1,
+    Import = 0xA2,
+    Export = 0xA3,
+    ExportAs = 0xA4,
+    Destructure = 0xA5,
+    ReloadModule = 0xA6,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Constant {
+    Int(i64),
+    Str(String),
+}
+
+fn invalid_data(reason: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, reason)
+}
+
+// The literal a PUSH_INT or PUSH_STR line pushes
+fn literal(op: &str, arg: Option<&str>) -> std::io::Result<Option<Constant>> {
+    Ok(match op {
+        "PUSH_INT" => Some(Constant::Int(parse_int_literal(arg.unwrap()).expect("Invalid integer"))),
+        "PUSH_STR" => Some(Constant::Str(parse_string_literal(arg.unwrap_or("")).map_err(invalid_data)?)),
+        _ => None,
+    })
 }
 
 pub fn compile<P: AsRef<Path>>(input_path: P, output_path: P) -> std::io::Result<()> {
-    let file = File::open(&input_path)?;
-    let reader = BufReader::new(file);
+    let source = std::fs::read_to_string(&input_path)?;
 
     let mut output = File::create(&output_path)?;
     let mut lines: Vec<String> = Vec::new();
+    let mut source_lines: Vec<u32> = Vec::new();
     let mut labels: HashMap<String, usize> = HashMap::new();
 
-    // First pass: collect lines and label addresses
-    for line in reader.lines() {
-        let line = line?.trim().to_string();
-        if line.is_empty() || line.starts_with(';') {
+    // First pass: collect lines, their line numbers and label addresses
+    let stripped = strip_comments(&source).map_err(|e| invalid_data(e.to_string()))?;
+    for (line_num, line) in stripped.into_iter().enumerate() {
+        if line.is_empty() {
             continue;
         }
 
@@ -85,34 +137,68 @@ pub fn compile<P: AsRef<Path>>(input_path: P, output_path: P) -> std::io::Result
             labels.insert(label.trim().to_string(), lines.len());
         } else {
             lines.push(line);
+            source_lines.push(line_num as u32 + 1);
         }
     }
 
-    // Second pass: encode instructions
+    // Constant pool: each distinct literal is stored once, in order of first use
+    let mut constants: Vec<Constant> = Vec::new();
+    let mut constant_index: HashMap<Constant, u32> = HashMap::new();
     for line in lines.iter() {
-        let parts: Vec<&str> = line.splitn(2, ' ').collect();
-        let op = parts[0];
-        let arg = parts.get(1).map(|s| s.trim());
+        let mut parts = line.splitn(2, ' ');
+        if let Some(constant) = literal(parts.next().unwrap(), parts.next().map(|s| s.trim()))? {
+            constant_index.entry(constant.clone()).or_insert_with(|| {
+                constants.push(constant);
+                constants.len() as u32 - 1
+            });
+        }
+    }
 
-        match op {
-            "PUSH_INT" => {
-                output.write_all(&(ByteCode::PushInt as u16).to_le_bytes())?;
-                let n: i64 = arg.unwrap().parse().expect("Invalid integer");
+    // Header: magic signature and format version
+    output.write_all(BYTECODE_MAGIC)?;
+    output.write_all(&BYTECODE_VERSION.to_le_bytes())?;
+
+    output.write_all(&(constants.len() as u32).to_le_bytes())?;
+    for constant in &constants {
+        match constant {
+            Constant::Int(n) => {
+                output.write_all(&[BYTECODE_CONST_INT])?;
                 output.write_all(&n.to_le_bytes())?;
             }
-            "PUSH_STR" => {
-                output.write_all(&(ByteCode::PushStr as u16).to_le_bytes())?;
-                let s = arg.unwrap().trim_matches('"');
+            Constant::Str(s) => {
                 let bytes = s.as_bytes();
+                output.write_all(&[BYTECODE_CONST_STR])?;
                 output.write_all(&(bytes.len() as u16).to_le_bytes())?;
                 output.write_all(bytes)?;
             }
+        }
+    }
+
+    // Second pass: encode instructions
+    for line in lines.iter() {
+        let parts: Vec<&str> = line.splitn(2, ' ').collect();
+        let op = parts[0];
+        let arg = parts.get(1).map(|s| s.trim());
+
+        match op {
+            "PUSH_INT" | "PUSH_STR" => {
+                let constant = literal(op, arg)?.unwrap();
+                output.write_all(&(ByteCode::PushConst as u16).to_le_bytes())?;
+                output.write_all(&constant_index[&constant].to_le_bytes())?;
+            }
             "TRUE" => output.write_all(&(ByteCode::True as u16).to_le_bytes())?,
             "FALSE" => output.write_all(&(ByteCode::False as u16).to_le_bytes())?,
             "NULL" => output.write_all(&(ByteCode::Null as u16).to_le_bytes())?,
             "ADD" => output.write_all(&(ByteCode::Add as u16).to_le_bytes())?,
             "SUB" => output.write_all(&(ByteCode::Sub as u16).to_le_bytes())?,
             "CONCAT" => output.write_all(&(ByteCode::Concat as u16).to_le_bytes())?,
+            "POW" => output.write_all(&(ByteCode::Pow as u16).to_le_bytes())?,
+            "SQRT" => output.write_all(&(ByteCode::Sqrt as u16).to_le_bytes())?,
+            "ABS" => output.write_all(&(ByteCode::Abs as u16).to_le_bytes())?,
+            "FLOOR" => output.write_all(&(ByteCode::Floor as u16).to_le_bytes())?,
+            "CEIL" => output.write_all(&(ByteCode::Ceil as u16).to_le_bytes())?,
+            "ROUND" => output.write_all(&(ByteCode::Round as u16).to_le_bytes())?,
+            "NEG" => output.write_all(&(ByteCode::Neg as u16).to_le_bytes())?,
             "EQ" => output.write_all(&(ByteCode::Eq as u16).to_le_bytes())?,
             "NE" => output.write_all(&(ByteCode::Ne as u16).to_le_bytes())?,
             "GT" => output.write_all(&(ByteCode::Gt as u16).to_le_bytes())?,
@@ -124,6 +210,9 @@ pub fn compile<P: AsRef<Path>>(input_path: P, output_path: P) -> std::io::Result
             "OR" => output.write_all(&(ByteCode::Or as u16).to_le_bytes())?,
             "DUP" => output.write_all(&(ByteCode::Dup as u16).to_le_bytes())?,
             "PRINT" => output.write_all(&(ByteCode::Print as u16).to_le_bytes())?,
+            "PRINT_RAW" => output.write_all(&(ByteCode::PrintRaw as u16).to_le_bytes())?,
+            "PRINT_ERR" => output.write_all(&(ByteCode::PrintErr as u16).to_le_bytes())?,
+            "DEBUG_PRINT" => output.write_all(&(ByteCode::DebugPrint as u16).to_le_bytes())?,
             "HALT" => output.write_all(&(ByteCode::Halt as u16).to_le_bytes())?,
 
             "JMP" | "JZ" => {
@@ -139,8 +228,9 @@ pub fn compile<P: AsRef<Path>>(input_path: P, output_path: P) -> std::io::Result
                 output.write_all(&(code as u16).to_le_bytes())?;
                 output.write_all(&(addr as u16).to_le_bytes())?;
             }
-            "CALL" => {
-                    output.write_all(&(ByteCode::Call as u16).to_le_bytes())?;
+            "CALL" | "MAKE_FUNCTION" => {
+                    let code = if op == "CALL" { ByteCode::Call } else { ByteCode::MakeFunction };
+                    output.write_all(&(code as u16).to_le_bytes())?;
                     let tokens: Vec<&str> = arg.unwrap().split_whitespace().collect();
                     let label_name = tokens[0];
                     let addr = labels.get(label_name)
@@ -158,7 +248,14 @@ pub fn compile<P: AsRef<Path>>(input_path: P, output_path: P) -> std::io::Result
                         output.write_all(name_bytes)?;
                     }
                 }
-            "RET" => output.write_all(&(ByteCode::Ret as u16).to_le_bytes())?,
+            "RET" => match arg {
+                Some(count) => {
+                    output.write_all(&(ByteCode::RetN as u16).to_le_bytes())?;
+                    let n: u16 = parse_int_literal(count).and_then(|n| u16::try_from(n).ok()).expect("Invalid return count");
+                    output.write_all(&n.to_le_bytes())?;
+                }
+                None => output.write_all(&(ByteCode::Ret as u16).to_le_bytes())?,
+            },
 
             "STORE" => {
                 output.write_all(&(ByteCode::Store as u16).to_le_bytes())?;
@@ -184,13 +281,26 @@ pub fn compile<P: AsRef<Path>>(input_path: P, output_path: P) -> std::io::Result
 
             "MAKELIST" => {
                 output.write_all(&(ByteCode::MakeList as u16).to_le_bytes())?;
-                let n: u8 = arg.unwrap().parse().expect("Invalid list size");
+                let n: u8 = parse_int_literal(arg.unwrap()).and_then(|n| u8::try_from(n).ok()).expect("Invalid list size");
                 output.write_all(&[n])?;
             }
             "LEN" => output.write_all(&(ByteCode::Len as u16).to_le_bytes())?,
             "INDEX" => output.write_all(&(ByteCode::Index as u16).to_le_bytes())?,
             "READ_FILE" => output.write_all(&(ByteCode::ReadFile as u16).to_le_bytes())?,
             "WRITE_FILE" => output.write_all(&(ByteCode::WriteFile as u16).to_le_bytes())?,
+            "ASSERT" => {
+                output.write_all(&(ByteCode::Assert as u16).to_le_bytes())?;
+                match arg {
+                    Some(text) => {
+                        let s = parse_string_literal(text).map_err(invalid_data)?;
+                        let bytes = s.as_bytes();
+                        output.write_all(&[1])?;
+                        output.write_all(&(bytes.len() as u16).to_le_bytes())?;
+                        output.write_all(bytes)?;
+                    }
+                    None => output.write_all(&[0])?,
+                }
+            }
             "DUMPSCOPE" => output.write_all(&(ByteCode::DumpScope as u16).to_le_bytes())?,
             
             // Concurrency opcodes
@@ -225,17 +335,17 @@ pub fn compile<P: AsRef<Path>>(input_path: P, output_path: P) -> std::io::Result
             }
             "MONITOR" => {
                 output.write_all(&(ByteCode::Monitor as u16).to_le_bytes())?;
-                let pid: u64 = arg.unwrap().parse().expect("Invalid PID");
+                let pid: u64 = parse_int_literal(arg.unwrap()).and_then(|n| u64::try_from(n).ok()).expect("Invalid PID");
                 output.write_all(&pid.to_le_bytes())?;
             }
             "LINK" => {
                 output.write_all(&(ByteCode::Link as u16).to_le_bytes())?;
-                let pid: u64 = arg.unwrap().parse().expect("Invalid PID");
+                let pid: u64 = parse_int_literal(arg.unwrap()).and_then(|n| u64::try_from(n).ok()).expect("Invalid PID");
                 output.write_all(&pid.to_le_bytes())?;
             }
             "UNLINK" => {
                 output.write_all(&(ByteCode::Unlink as u16).to_le_bytes())?;
-                let pid: u64 = arg.unwrap().parse().expect("Invalid PID");
+                let pid: u64 = parse_int_literal(arg.unwrap()).and_then(|n| u64::try_from(n).ok()).expect("Invalid PID");
                 output.write_all(&pid.to_le_bytes())?;
             }
             "START_SUPERVISOR" => output.write_all(&(ByteCode::StartSupervisor as u16).to_le_bytes())?,
@@ -255,14 +365,42 @@ pub fn compile<P: AsRef<Path>>(input_path: P, output_path: P) -> std::io::Result
             }
             "YIELD" => output.write_all(&(ByteCode::Yield as u16).to_le_bytes())?,
             "RECEIVE" => output.write_all(&(ByteCode::Receive as u16).to_le_bytes())?,
+            "SELF_PID" => output.write_all(&(ByteCode::SelfPid as u16).to_le_bytes())?,
+            "PARENT_PID" => output.write_all(&(ByteCode::ParentPid as u16).to_le_bytes())?,
+            "RANDOM_INT" => output.write_all(&(ByteCode::RandomInt as u16).to_le_bytes())?,
+            "RANDOM_FLOAT" => output.write_all(&(ByteCode::RandomFloat as u16).to_le_bytes())?,
+            "RANDOM_SEED" => output.write_all(&(ByteCode::RandomSeed as u16).to_le_bytes())?,
+            "UUID" => output.write_all(&(ByteCode::Uuid as u16).to_le_bytes())?,
+            "CALL_FUNCTION" => output.write_all(&(ByteCode::CallFunction as u16).to_le_bytes())?,
+            "IMPORT" | "EXPORT" | "EXPORT_AS" | "RELOAD_MODULE" | "DESTRUCTURE" => {
+                let code = match op {
+                    "IMPORT" => ByteCode::Import,
+                    "EXPORT" => ByteCode::Export,
+                    "EXPORT_AS" => ByteCode::ExportAs,
+                    "RELOAD_MODULE" => ByteCode::ReloadModule,
+                    _ => ByteCode::Destructure,
+                };
+                output.write_all(&(code as u16).to_le_bytes())?;
+                let name = arg.unwrap().trim_matches('"');
+                let bytes = name.as_bytes();
+                output.write_all(&(bytes.len() as u16).to_le_bytes())?;
+                output.write_all(bytes)?;
+            }
             "SEND" => {
                 output.write_all(&(ByteCode::Send as u16).to_le_bytes())?;
-                let pid: u64 = arg.unwrap().parse().expect("Invalid PID");
+                let pid: u64 = parse_int_literal(arg.unwrap()).and_then(|n| u64::try_from(n).ok()).expect("Invalid PID");
                 output.write_all(&pid.to_le_bytes())?;
             }
 
             _ => panic!("Unknown opcode: {}", op),
         }
     }
+
+    // Debug info, so runtime errors in the compiled program can name source lines
+    output.write_all(&BYTECODE_DEBUG_INFO.to_le_bytes())?;
+    output.write_all(&(source_lines.len() as u32).to_le_bytes())?;
+    for line in source_lines {
+        output.write_all(&line.to_le_bytes())?;
+    }
     Ok(())
 }
@@ -12,6 +12,7 @@ pub enum Message {
     Link(ProcId), // Link request
     Unlink(ProcId), // Unlink request
     TrapExit(bool), // Set trap_exit flag
+    Kill(String), // Exit unconditionally with this reason (SchedulerPool::kill)
 }
 
 #[derive(Debug, Clone)]
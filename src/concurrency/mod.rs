@@ -4,13 +4,27 @@ pub mod scheduler;
 pub mod pool;
 pub mod process;
 pub mod supervisor;
+pub mod node;
+pub mod table;
+pub mod timer;
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
 
 // Re-export commonly used types
 pub use messages::Message;
 pub use supervisor::{RestartStrategy, ChildType, Shutdown, ChildSpec, RestartPolicy, SupervisorSpec, ChildState};
-pub use process::{TinyProc, MessageSender, ProcessSpawner, NameRegistry};
+pub use process::{TinyProc, Priority, MessageSender, ProcessSpawner, NameRegistry, DEFAULT_MAX_REDUCTIONS};
 pub use registry::ProcessRegistry;
-pub use scheduler::Scheduler;
+pub use node::NodeListener;
+pub use table::SharedTables;
+pub use timer::Timers;
+pub use scheduler::{Scheduler, SchedulingPolicy};
 
 // Pool types
-pub use pool::SchedulerPool;
\ No newline at end of file
+pub use pool::SchedulerPool;
+
+// A process that panics while holding one of the shared locks poisons it, but the data behind
+// it is still whole, so every other process and scheduler keeps going instead of panicking too
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,156 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::vm::{Value, serialize_value, deserialize_value};
+use crate::concurrency::{lock, Message, ProcessRegistry};
+
+// Distributed messaging between VM instances. A node listens on a TCP address and delivers
+// value messages to the processes registered under a name on that node. Remote processes
+// are addressed as `name@host:port`, e.g. `SENDNAMED inbox@127.0.0.1:9100`.
+//
+// Each send opens a connection and writes one frame: a big-endian u32 length followed by
+// the target name and the value as encoded by `vm::serialize`. The node answers with a
+// frame holding a status byte (0 delivered, 1 failed) and, on failure, the reason.
+
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_FRAME_LEN: u32 = 64 * 1024 * 1024;
+
+// Split `name@host:port` into the process name and node address
+pub fn split_remote_name(target: &str) -> Option<(&str, &str)> {
+    let (name, node) = target.split_once('@')?;
+    if name.is_empty() || node.is_empty() {
+        return None;
+    }
+    Some((name, node))
+}
+
+// Deliver `value` to the process registered as `name` on the node at `name@host:port`
+pub fn send_remote(target: &str, value: &Value) -> Result<(), String> {
+    let (name, node) = split_remote_name(target)
+        .ok_or_else(|| format!("'{}' is not a remote name (expected name@host:port)", target))?;
+
+    let mut payload = Vec::new();
+    encode_str(&mut payload, name);
+    payload.extend(serialize_value(value).map_err(|e| e.to_string())?);
+
+    let mut stream = TcpStream::connect(node).map_err(|e| format!("cannot reach node {}: {}", node, e))?;
+    stream.set_read_timeout(Some(IO_TIMEOUT)).ok();
+    stream.set_write_timeout(Some(IO_TIMEOUT)).ok();
+    write_frame(&mut stream, &payload).map_err(|e| format!("sending to node {} failed: {}", node, e))?;
+
+    let reply = read_frame(&mut stream).map_err(|e| format!("no reply from node {}: {}", node, e))?;
+    match reply.split_first() {
+        Some((0, _)) => Ok(()),
+        Some((_, reason)) => Err(String::from_utf8_lossy(reason).into_owned()),
+        None => Err(format!("empty reply from node {}", node)),
+    }
+}
+
+pub struct NodeListener {
+    pub address: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl NodeListener {
+    // Listen on `address` ("127.0.0.1:0" picks a free port) and route incoming messages
+    // into `registry` by process name
+    pub fn start(address: &str, registry: Arc<Mutex<ProcessRegistry>>) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let flag = shutdown.clone();
+        let handle = thread::spawn(move || {
+            while !flag.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let registry = registry.clone();
+                        thread::spawn(move || handle_connection(stream, &registry));
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(10)),
+                    Err(e) => eprintln!("Node {} failed to accept a connection: {}", address, e),
+                }
+            }
+        });
+
+        Ok(NodeListener { address, shutdown, handle: Some(handle) })
+    }
+
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for NodeListener {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, registry: &Mutex<ProcessRegistry>) {
+    stream.set_nonblocking(false).ok();
+    stream.set_read_timeout(Some(IO_TIMEOUT)).ok();
+    stream.set_write_timeout(Some(IO_TIMEOUT)).ok();
+
+    let result = read_frame(&mut stream)
+        .map_err(|e| format!("bad frame: {}", e))
+        .and_then(|payload| {
+            let mut input = payload.as_slice();
+            let name = decode_str(&mut input)?;
+            let value = deserialize_value(input).map_err(|e| e.to_string())?;
+            lock(registry).send_to_named(name, Message::Value(value))
+        });
+
+    let reply = match result {
+        Ok(()) => vec![0],
+        Err(reason) => [&[1], reason.as_bytes()].concat(),
+    };
+    let _ = write_frame(&mut stream, &reply);
+}
+
+fn write_frame(stream: &mut TcpStream, payload: &[u8]) -> io::Result<()> {
+    stream.write_all(&(payload.len() as u32).to_be_bytes())?;
+    stream.write_all(payload)?;
+    stream.flush()
+}
+
+fn read_frame(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {} bytes is too large", len)));
+    }
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+fn encode_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u32).to_be_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn decode_str<'a>(input: &mut &'a [u8]) -> Result<&'a str, String> {
+    let (len, rest) = input.split_first_chunk::<4>().ok_or("truncated message")?;
+    let len = u32::from_be_bytes(*len) as usize;
+    if rest.len() < len {
+        return Err("truncated message".to_string());
+    }
+    let (name, rest) = rest.split_at(len);
+    *input = rest;
+    std::str::from_utf8(name).map_err(|e| format!("invalid name in message: {}", e))
+}
@@ -1,13 +1,18 @@
 use std::collections::HashMap;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::thread;
 use crossbeam::channel::Sender;
 use crossbeam_deque::{Worker, Stealer};
 
-use crate::vm::{OpCode, ProcId};
-use super::{TinyProc, MessageSender, ProcessSpawner, NameRegistry, ProcessRegistry, Scheduler, Message};
+use crate::vm::{OpCode, ProcId, VMError};
+use crate::ProcState;
+use super::{lock, TinyProc, DEFAULT_MAX_REDUCTIONS, Priority, MessageSender, ProcessSpawner, NameRegistry, ProcessRegistry, Scheduler, SchedulingPolicy, Message, SharedTables, Timers};
+
+// How long every process has to stay blocked before run() reports a deadlock, so that a
+// message already on its way gets delivered first
+const DEADLOCK_GRACE: Duration = Duration::from_millis(100);
 
 pub struct SchedulerPool {
     pub schedulers: Vec<thread::JoinHandle<()>>,
@@ -17,6 +22,16 @@ pub struct SchedulerPool {
     pub running_processes: Arc<Mutex<HashMap<ProcId, Arc<Mutex<TinyProc>>>>>,
     pub shutdown_flag: Arc<AtomicBool>,
     pub process_registry: Arc<Mutex<ProcessRegistry>>,
+    pub tables: Arc<SharedTables>,
+    pub timers: Arc<Timers>, // SEND_AFTER messages, delivered by the schedulers
+    pub program_args: Arc<Vec<String>>, // what GET_ARGS returns in every process
+    pub accepting: Arc<AtomicBool>, // cleared by shutdown() so no new processes start
+    pub policy: SchedulingPolicy, // used by every scheduler thread
+    pub scheduler_slices: Vec<Arc<AtomicUsize>>, // slices run by each scheduler thread
+    pub detect_deadlocks: bool, // turn off when messages can come from outside the pool
+    pub max_reductions: usize, // reduction slice of every process
+    pub stack_limit: Option<usize>, // operand stack cap of every process
+    pub float_precision: Option<usize>, // digits after the decimal point when any process prints a float
 }
 
 #[derive(Debug, Clone)]
@@ -31,99 +46,163 @@ pub struct SchedulerPoolProcessSpawner {
     pub running_processes: Arc<Mutex<HashMap<ProcId, Arc<Mutex<TinyProc>>>>>,
     pub process_registry: Arc<Mutex<ProcessRegistry>>,
     pub message_sender: Arc<dyn MessageSender>,
+    pub tables: Arc<SharedTables>,
+    pub timers: Arc<Timers>,
+    pub program_args: Arc<Vec<String>>,
+    pub accepting: Arc<AtomicBool>,
+    pub max_reductions: usize,
+    pub stack_limit: Option<usize>,
+    pub float_precision: Option<usize>,
 }
 
 impl MessageSender for SchedulerPoolMessageSender {
     fn send_message(&self, target_proc_id: ProcId, message: Message) -> Result<(), String> {
-        let mut registry = self.process_registry.lock().unwrap();
+        let mut registry = lock(&self.process_registry);
         registry.send_message_simple(target_proc_id, message)
     }
 }
 
 impl NameRegistry for SchedulerPoolMessageSender {
     fn register_name(&self, name: String, proc_id: ProcId) -> Result<(), String> {
-        let mut registry = self.process_registry.lock().unwrap();
+        let mut registry = lock(&self.process_registry);
         registry.register_name(name, proc_id)
     }
     
     fn unregister_name(&self, name: &str) -> Result<(), String> {
-        let mut registry = self.process_registry.lock().unwrap();
+        let mut registry = lock(&self.process_registry);
         registry.unregister_name(name)
     }
     
+    fn unregister_names(&self, proc_id: ProcId) {
+        lock(&self.process_registry).unregister_names(proc_id);
+    }
+    
     fn whereis(&self, name: &str) -> Option<ProcId> {
-        let registry = self.process_registry.lock().unwrap();
+        let registry = lock(&self.process_registry);
         registry.whereis(name)
     }
     
     fn send_to_named(&self, name: &str, message: Message) -> Result<(), String> {
-        let mut registry = self.process_registry.lock().unwrap();
+        let mut registry = lock(&self.process_registry);
         registry.send_to_named(name, message)
     }
 }
 
 impl NameRegistry for SchedulerPoolProcessSpawner {
     fn register_name(&self, name: String, proc_id: ProcId) -> Result<(), String> {
-        let mut registry = self.process_registry.lock().unwrap();
+        let mut registry = lock(&self.process_registry);
         registry.register_name(name, proc_id)
     }
     
     fn unregister_name(&self, name: &str) -> Result<(), String> {
-        let mut registry = self.process_registry.lock().unwrap();
+        let mut registry = lock(&self.process_registry);
         registry.unregister_name(name)
     }
     
+    fn unregister_names(&self, proc_id: ProcId) {
+        lock(&self.process_registry).unregister_names(proc_id);
+    }
+    
     fn whereis(&self, name: &str) -> Option<ProcId> {
-        let registry = self.process_registry.lock().unwrap();
+        let registry = lock(&self.process_registry);
         registry.whereis(name)
     }
     
     fn send_to_named(&self, name: &str, message: Message) -> Result<(), String> {
-        let mut registry = self.process_registry.lock().unwrap();
+        let mut registry = lock(&self.process_registry);
         registry.send_to_named(name, message)
     }
 }
 
 impl ProcessSpawner for SchedulerPoolProcessSpawner {
     fn spawn_process(&self, instructions: Vec<OpCode>) -> (ProcId, Sender<Message>) {
-        // Get next process ID
-        let proc_id = {
-            let mut id = self.next_proc_id.lock().unwrap();
-            let current_id = *id;
-            *id += 1;
-            current_id
-        };
+        self.spawn(instructions, None, None).unwrap_or_else(|reason| exited_process(self.next_proc_id(), reason))
+    }
+    
+    fn spawn_child(&self, parent: ProcId, instructions: Vec<OpCode>) -> (ProcId, Sender<Message>) {
+        self.spawn(instructions, Some(parent), None).unwrap_or_else(|reason| exited_process(self.next_proc_id(), reason))
+    }
+    
+    fn spawn_registered(&self, parent: ProcId, name: &str, instructions: Vec<OpCode>) -> Result<(ProcId, Sender<Message>), String> {
+        self.spawn(instructions, Some(parent), Some(name))
+    }
+}
+
+impl SchedulerPoolProcessSpawner {
+    fn next_proc_id(&self) -> ProcId {
+        let mut id = lock(&self.next_proc_id);
+        let current_id = *id;
+        *id += 1;
+        current_id
+    }
+    
+    fn spawn(&self, instructions: Vec<OpCode>, parent: Option<ProcId>, name: Option<&str>) -> Result<(ProcId, Sender<Message>), String> {
+        let proc_id = self.next_proc_id();
         
         let (mut proc, sender) = TinyProc::new(proc_id, instructions);
+        proc.parent_pid = parent;
+        
+        // A pool that is shutting down doesn't start new processes
+        if !self.accepting.load(Ordering::Relaxed) {
+            proc.handle_process_exit("shutdown".to_string());
+            return Ok((proc_id, sender));
+        }
         
         // Set the message sender and process spawner for the new process
         proc.message_sender = Some(self.message_sender.clone());
         proc.process_spawner = Some(Arc::new(self.clone()));
         proc.name_registry = Some(Arc::new(self.clone()));
+        proc.tables = self.tables.clone();
+        proc.timers = self.timers.clone();
+        proc.program_args = self.program_args.clone();
+        proc.max_reductions = self.max_reductions;
+        proc.stack_limit = self.stack_limit;
+        proc.float_precision = self.float_precision;
         
         // Add process to submission queue for schedulers to pick up
         let proc_arc = Arc::new(Mutex::new(proc));
         
+        // Register process (and its name) before any scheduler can run it
+        register_spawned(&self.process_registry, proc_id, &sender, name)?;
+        
         // Track running processes
         {
-            let mut running = self.running_processes.lock().unwrap();
+            let mut running = lock(&self.running_processes);
             running.insert(proc_id, proc_arc.clone());
         }
         
-        // Register process in the registry for message delivery
-        {
-            let mut registry = self.process_registry.lock().unwrap();
-            registry.register_process(proc_id, sender.clone()).expect("Failed to register process");
-        }
-        
         {
             // Use blocking lock to ensure process gets added to submission queue
-            let mut queue = self.process_submission_queue.lock().unwrap();
+            let mut queue = lock(&self.process_submission_queue);
             queue.push(proc_arc);
             // println!("DEBUG: Added process {} to submission queue", proc_id);
         }
         
-        (proc_id, sender)
+        Ok((proc_id, sender))
+    }
+}
+
+// Stands in for a process that could not be started, for callers that have no way to take an
+// error: it has already exited with `reason`, which is also logged
+fn exited_process(proc_id: ProcId, reason: String) -> (ProcId, Sender<Message>) {
+    let (mut proc, sender) = TinyProc::new(proc_id, Vec::new());
+    proc.handle_process_exit(reason);
+    (proc_id, sender)
+}
+
+// Make a new process reachable for messages, under `name` as well if given. The name is checked
+// first, so a taken name leaves the registry unchanged.
+fn register_spawned(registry: &Mutex<ProcessRegistry>, proc_id: ProcId, sender: &Sender<Message>, name: Option<&str>) -> Result<(), String> {
+    let mut registry = lock(registry);
+    if let Some(name) = name {
+        if registry.whereis(name).is_some() {
+            return Err(format!("Name '{}' already registered", name));
+        }
+    }
+    registry.register_process(proc_id, sender.clone())?;
+    match name {
+        Some(name) => registry.register_name(name.to_string(), proc_id),
+        None => Ok(()),
     }
 }
 
@@ -137,11 +216,26 @@ impl SchedulerPool {
             running_processes: Arc::new(Mutex::new(HashMap::new())),
             shutdown_flag: Arc::new(AtomicBool::new(false)),
             process_registry: Arc::new(Mutex::new(ProcessRegistry::new())),
+            tables: Arc::new(SharedTables::new()),
+            timers: Arc::new(Timers::new()),
+            program_args: Arc::new(Vec::new()),
+            accepting: Arc::new(AtomicBool::new(true)),
+            policy: SchedulingPolicy::RoundRobin,
+            scheduler_slices: Vec::new(),
+            detect_deadlocks: true,
+            max_reductions: DEFAULT_MAX_REDUCTIONS,
+            stack_limit: None,
+            float_precision: None,
         }
     }
     
     pub fn new_with_threads(num_threads: usize) -> Self {
+        Self::new_with_policy(num_threads, SchedulingPolicy::RoundRobin)
+    }
+    
+    pub fn new_with_policy(num_threads: usize, policy: SchedulingPolicy) -> Self {
         let mut pool = Self::new();
+        pool.policy = policy;
         pool.spawn_smp_schedulers(num_threads);
         pool
     }
@@ -155,16 +249,69 @@ impl SchedulerPool {
         Self::new_with_threads(num_threads)
     }
     
+    // The pids of the live processes if every one of them is blocked in a receive with an empty
+    // mailbox and no timeout, and no timer or new process could wake one. None while any
+    // process might still make progress.
+    fn stuck_processes(&self) -> Option<Vec<ProcId>> {
+        if !self.timers.is_empty() || !lock(&self.process_submission_queue).is_empty() {
+            return None;
+        }
+        // Copied out so a running process that spawns (and so needs this lock) isn't held up
+        let running: Vec<(ProcId, Arc<Mutex<TinyProc>>)> = lock(&self.running_processes)
+            .iter().map(|(&pid, proc_arc)| (pid, proc_arc.clone())).collect();
+        let mut waiting = Vec::new();
+        for (pid, proc_arc) in running {
+            // A process a scheduler holds right now is running, not stuck
+            let Ok(proc) = proc_arc.try_lock() else { return None };
+            let blocked = proc.waiting_for_message && !proc.has_messages()
+                && proc.receive_deadline.is_none() && proc.sleep_until.is_none();
+            if !blocked {
+                return None;
+            }
+            waiting.push(pid);
+        }
+        waiting.sort();
+        (!waiting.is_empty()).then_some(waiting)
+    }
+    
+    // How many process slices each scheduler thread has run so far
+    #[allow(dead_code)]
+    pub fn slices_per_scheduler(&self) -> Vec<usize> {
+        self.scheduler_slices.iter().map(|slices| slices.load(Ordering::Relaxed)).collect()
+    }
+    
     pub fn get_next_proc_id(&self) -> ProcId {
-        let mut id = self.next_proc_id.lock().unwrap();
+        let mut id = lock(&self.next_proc_id);
         let current_id = *id;
         *id += 1;
         current_id
     }
     
     pub fn spawn_process(&self, instructions: Vec<OpCode>) -> (ProcId, Sender<Message>) {
+        self.spawn_process_with_priority(instructions, Priority::Normal)
+    }
+    
+    pub fn spawn_process_with_priority(&self, instructions: Vec<OpCode>, priority: Priority) -> (ProcId, Sender<Message>) {
+        self.spawn(instructions, priority, None).unwrap_or_else(|reason| exited_process(self.get_next_proc_id(), reason))
+    }
+    
+    // Spawn a process that is already registered as `name` when it first runs, so WHEREIS finds
+    // it straight away. Fails without spawning anything if the name is taken.
+    #[allow(dead_code)]
+    pub fn spawn_registered(&self, name: &str, instructions: Vec<OpCode>) -> Result<(ProcId, Sender<Message>), String> {
+        self.spawn(instructions, Priority::Normal, Some(name))
+    }
+    
+    fn spawn(&self, instructions: Vec<OpCode>, priority: Priority, name: Option<&str>) -> Result<(ProcId, Sender<Message>), String> {
         let proc_id = self.get_next_proc_id();
         let (mut proc, sender) = TinyProc::new(proc_id, instructions);
+        proc.priority = priority;
+        
+        // A pool that is shutting down doesn't start new processes
+        if !self.accepting.load(Ordering::Relaxed) {
+            proc.handle_process_exit("shutdown".to_string());
+            return Ok((proc_id, sender));
+        }
         
         // Create message sender
         let message_sender = Arc::new(SchedulerPoolMessageSender {
@@ -184,37 +331,56 @@ impl SchedulerPool {
             running_processes: self.running_processes.clone(),
             process_registry: self.process_registry.clone(),
             message_sender: message_sender,
+            tables: self.tables.clone(),
+            timers: self.timers.clone(),
+            program_args: self.program_args.clone(),
+            accepting: self.accepting.clone(),
+            max_reductions: self.max_reductions,
+            stack_limit: self.stack_limit,
+            float_precision: self.float_precision,
         }));
         
+        // Share the pool's tables with this process
+        proc.tables = self.tables.clone();
+        proc.timers = self.timers.clone();
+        proc.program_args = self.program_args.clone();
+        proc.max_reductions = self.max_reductions;
+        proc.stack_limit = self.stack_limit;
+        proc.float_precision = self.float_precision;
+        
         // Add process to submission queue for schedulers to pick up
         let proc_arc = Arc::new(Mutex::new(proc));
         
+        // Register process (and its name) before any scheduler can run it
+        register_spawned(&self.process_registry, proc_id, &sender, name)?;
+        
         // Track running processes
         {
-            let mut running = self.running_processes.lock().unwrap();
+            let mut running = lock(&self.running_processes);
             running.insert(proc_id, proc_arc.clone());
         }
         
-        // Register process in the registry for message delivery
         {
-            let mut registry = self.process_registry.lock().unwrap();
-            registry.register_process(proc_id, sender.clone()).expect("Failed to register process");
-        }
-        
-        {
-            let mut queue = self.process_submission_queue.lock().unwrap();
+            let mut queue = lock(&self.process_submission_queue);
             queue.push(proc_arc);
         }
         
-        (proc_id, sender)
+        Ok((proc_id, sender))
     }
     
     #[allow(dead_code)]
     pub fn send_message(&self, target_proc_id: ProcId, message: Message) -> Result<(), String> {
-        let mut registry = self.process_registry.lock().unwrap();
+        let mut registry = lock(&self.process_registry);
         registry.send_message_simple(target_proc_id, message)
     }
     
+    // Make a process exit with `reason` at its next reduction boundary, whatever it is doing
+    // and even if it traps exits. Its links and monitors are told as for any other exit.
+    #[allow(dead_code)]
+    pub fn kill(&self, target_proc_id: ProcId, reason: &str) -> Result<(), String> {
+        self.send_message(target_proc_id, Message::Kill(reason.to_string()))
+    }
+    
     pub fn spawn_smp_schedulers(&mut self, num_threads: usize) {
         let mut stealers = Vec::new();
         let mut workers = Vec::new();
@@ -242,6 +408,10 @@ impl SchedulerPool {
             let shutdown_flag = self.shutdown_flag.clone();
             let running_processes = self.running_processes.clone();
             let registry = self.process_registry.clone();
+            let timers = self.timers.clone();
+            let policy = self.policy;
+            let slices_run = Arc::new(AtomicUsize::new(0));
+            self.scheduler_slices.push(slices_run.clone());
             
             let handle = thread::spawn(move || {
                 let mut scheduler = Scheduler {
@@ -249,9 +419,12 @@ impl SchedulerPool {
                     local_queue: worker,
                     remote_stealers,
                     running: true,
+                    policy,
+                    slices_run,
+                    next_victim: id,
                 };
                 
-                scheduler.run_scheduler_loop(submission_queue, shutdown_flag, running_processes, registry);
+                scheduler.run_scheduler_loop(submission_queue, shutdown_flag, running_processes, registry, timers);
             });
             
             self.schedulers.push(handle);
@@ -262,12 +435,14 @@ impl SchedulerPool {
         // Keep running until all processes complete
         let start_time = std::time::Instant::now();
         let max_wait_time = Duration::from_secs(3); // Maximum wait time for processes
+        let mut stuck_since: Option<Instant> = None;
+        let mut last_debug: Option<Instant> = None;
         
         loop {
             // Check if there are any processes still running
             let (queue_len, running_count) = {
-                let queue = self.process_submission_queue.lock().unwrap();
-                let running = self.running_processes.lock().unwrap();
+                let queue = lock(&self.process_submission_queue);
+                let running = lock(&self.running_processes);
                 (queue.len(), running.len())
             };
             
@@ -276,6 +451,18 @@ impl SchedulerPool {
                 break;
             }
             
+            // Everyone left waiting on everyone else won't get any further
+            if self.detect_deadlocks {
+                match self.stuck_processes() {
+                    Some(waiting) if stuck_since.is_some_and(|since| since.elapsed() >= DEADLOCK_GRACE) => {
+                        self.shutdown_flag.store(true, Ordering::Relaxed);
+                        return Err(Box::new(VMError::Deadlock { waiting }));
+                    }
+                    Some(_) => { stuck_since.get_or_insert_with(Instant::now); }
+                    None => stuck_since = None,
+                }
+            }
+            
             // Check if we've been waiting too long
             if start_time.elapsed() > max_wait_time {
                 println!("Scheduler timeout reached - shutting down {} remaining processes", running_count);
@@ -284,13 +471,10 @@ impl SchedulerPool {
             
             // Only print debug info if we're waiting for a while
             if start_time.elapsed() > Duration::from_secs(1) {
-                static mut LAST_DEBUG: Option<std::time::Instant> = None;
-                unsafe {
-                    let now = std::time::Instant::now();
-                    if LAST_DEBUG.is_none() || now.duration_since(LAST_DEBUG.unwrap()) > Duration::from_secs(2) {
-                        println!("Waiting for {} processes to complete...", running_count);
-                        LAST_DEBUG = Some(now);
-                    }
+                let now = Instant::now();
+                if last_debug.is_none_or(|last| now.duration_since(last) > Duration::from_secs(2)) {
+                    println!("Waiting for {} processes to complete...", running_count);
+                    last_debug = Some(now);
                 }
             }
             
@@ -311,7 +495,8 @@ impl SchedulerPool {
             
             while let Some(h) = handle_option.take() {
                 if h.is_finished() {
-                    h.join().unwrap();
+                    // A scheduler thread that panicked has nothing left to clean up
+                    let _ = h.join();
                     break;
                 } else if start_time.elapsed() >= Duration::from_millis(100) {
                     // Short timeout since scheduler should respond quickly to shutdown flag
@@ -325,22 +510,57 @@ impl SchedulerPool {
         }
     }
     
+    // Stop starting new processes and give the running ones until `timeout` to finish. Whatever
+    // is still alive then exits with reason "shutdown", so linked and monitoring processes are
+    // told. Returns the pids that had to be terminated.
+    #[allow(dead_code)]
+    pub fn shutdown(self, timeout: Duration) -> Vec<ProcId> {
+        self.accepting.store(false, Ordering::Relaxed);
+        
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline && !lock(&self.running_processes).is_empty() {
+            thread::sleep(Duration::from_millis(5));
+        }
+        
+        // Schedulers stop after their current reduction slice
+        self.shutdown_flag.store(true, Ordering::Relaxed);
+        let running_processes = self.running_processes.clone();
+        let registry = self.process_registry.clone();
+        self.wait_for_completion();
+        
+        let remaining: Vec<_> = lock(&running_processes).drain().collect();
+        let mut terminated = Vec::new();
+        for (proc_id, proc_arc) in remaining {
+            // A scheduler that never came back still holds its process; it can't be told to exit
+            if let Ok(mut proc) = proc_arc.try_lock() {
+                if matches!(proc.state, ProcState::Exited) {
+                    continue;
+                }
+                proc.handle_process_exit("shutdown".to_string());
+            }
+            lock(&registry).unregister_process(proc_id).ok();
+            terminated.push(proc_id);
+        }
+        terminated.sort();
+        terminated
+    }
+    
     // Process name registry methods
     #[allow(dead_code)]
     pub fn register_name(&self, name: String, proc_id: ProcId) -> Result<(), String> {
-        let mut registry = self.process_registry.lock().unwrap();
+        let mut registry = lock(&self.process_registry);
         registry.register_name(name, proc_id)
     }
     
     #[allow(dead_code)]
     pub fn unregister_name(&self, name: &str) -> Result<(), String> {
-        let mut registry = self.process_registry.lock().unwrap();
+        let mut registry = lock(&self.process_registry);
         registry.unregister_name(name)
     }
     
     #[allow(dead_code)]
     pub fn whereis(&self, name: &str) -> Option<ProcId> {
-        let registry = self.process_registry.lock().unwrap();
+        let registry = lock(&self.process_registry);
         registry.whereis(name)
     }
     
@@ -356,7 +576,7 @@ impl SchedulerPool {
     // Clean up name registrations when process exits
     #[allow(dead_code)]
     pub fn cleanup_process_names(&self, proc_id: ProcId) {
-        let mut registry = self.process_registry.lock().unwrap();
+        let mut registry = lock(&self.process_registry);
         registry.unregister_process(proc_id).ok();
     }
 }
\ No newline at end of file
@@ -1,16 +1,22 @@
 use std::collections::{HashMap, HashSet};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::sync::Arc;
 use crossbeam::channel::{Receiver, Sender};
+use indexmap::IndexMap;
 use colored::*;
 
-use crate::vm::{VMError, VMResult, Value, OpCode, ProcId, MessagePattern, ExceptionHandler};
+use crate::vm::{VMError, VMResult, Value, OpCode, ProcId, MessagePattern, ExceptionHandler, float_result, DEFAULT_CALL_DEPTH_LIMIT};
+use crate::vm::ops;
+use crate::vm::output::OutputSink;
 use crate::gc::{GcEngine, MarkSweepGc};
 use crate::profiling::Profiler;
-use crate::concurrency::{Message, SupervisorSpec, ChildSpec, ChildState, RestartPolicy};
+use crate::concurrency::{Message, SupervisorSpec, ChildSpec, ChildState, RestartPolicy, SharedTables, Timers};
 use crate::ProcState;
 use crate::bytecode::parse_program;
 
+// Reductions a process runs before yielding to the scheduler, unless configured otherwise
+pub const DEFAULT_MAX_REDUCTIONS: usize = 1000;
+
 // Trait for sending messages between processes
 pub trait MessageSender: Send + Sync + std::fmt::Debug {
     fn send_message(&self, target_pid: ProcId, message: Message) -> Result<(), String>;
@@ -19,12 +25,17 @@ pub trait MessageSender: Send + Sync + std::fmt::Debug {
 // Trait for spawning new processes
 pub trait ProcessSpawner: Send + Sync + std::fmt::Debug {
     fn spawn_process(&self, instructions: Vec<OpCode>) -> (ProcId, Sender<Message>);
+    // Like spawn_process, recording `parent` as the new process's PARENT_PID
+    fn spawn_child(&self, parent: ProcId, instructions: Vec<OpCode>) -> (ProcId, Sender<Message>);
+    // Like spawn_child, with `name` registered before the child runs. Fails if the name is taken.
+    fn spawn_registered(&self, parent: ProcId, name: &str, instructions: Vec<OpCode>) -> Result<(ProcId, Sender<Message>), String>;
 }
 
 // Trait for name registry operations
 pub trait NameRegistry: Send + Sync + std::fmt::Debug {
     fn register_name(&self, name: String, pid: ProcId) -> Result<(), String>;
     fn unregister_name(&self, name: &str) -> Result<(), String>;
+    fn unregister_names(&self, pid: ProcId); // every name registered by pid
     fn whereis(&self, name: &str) -> Option<ProcId>;
     fn send_to_named(&self, name: &str, message: Message) -> Result<(), String>;
 }
@@ -41,14 +52,28 @@ pub trait RunnableProcess: Send + Sync + std::fmt::Debug {
     fn set_waiting_for_message(&mut self, waiting: bool);
 }
 
+// How much of the scheduler's time a process gets under SchedulingPolicy::Priority
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[allow(dead_code)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
 #[derive(Debug)]
 pub struct TinyProc {
     pub id: ProcId,
+    pub parent_pid: Option<ProcId>, // the process that SPAWNed this one
     pub state: ProcState,
+    pub priority: Priority,
     pub mailbox: Receiver<Message>,
     pub mailbox_sender: Sender<Message>,
     pub reduction_count: usize,
     pub max_reductions: usize,
+    pub stack_limit: Option<usize>, // more values than this on the stack raises StackOverflow
+    pub float_precision: Option<usize>, // digits after the decimal point when printing floats
     pub message_sender: Option<Arc<dyn MessageSender>>,
     pub process_spawner: Option<Arc<dyn ProcessSpawner>>,
     pub name_registry: Option<Arc<dyn NameRegistry>>,
@@ -64,11 +89,23 @@ pub struct TinyProc {
     pub supervisor_pid: Option<ProcId>, // parent supervisor
     pub restart_intensity_count: usize, // current restart count in period
     pub restart_period_start: Instant, // when current period started
+    // Request/reply calls
+    pub pending_call: Option<String>, // ref of the PROC_CALL waiting for its reply
+    pub next_call_ref: u64,
+    pub receive_deadline: Option<Instant>, // when a RECEIVE_MATCH ... after:<ms> gives up
+    pub sleep_until: Option<Instant>, // when a SLEEPing process is ready to run again
+    pub dictionary: HashMap<String, Value>, // process dictionary (PUT_DICT/GET_DICT/ERASE_DICT)
+    pub tables: Arc<SharedTables>, // shared with the other processes of the pool
+    pub timers: Arc<Timers>, // the pool's pending SEND_AFTER messages
+    pub program_args: Arc<Vec<String>>, // arguments GET_ARGS returns
+    pub regex_cache: ops::RegexCache,
+    pub rng: ops::Random,
     // VM state (isolated per process)
     pub stack: Vec<Value>,
     pub instructions: Vec<OpCode>,
     pub ip: usize,
     pub call_stack: Vec<usize>,
+    pub call_stack_bases: Vec<usize>, // stack height when each call started
     pub variables: Vec<HashMap<String, Value>>,
     pub try_stack: Vec<ExceptionHandler>,
     pub exports: HashMap<String, Value>,
@@ -83,6 +120,7 @@ pub struct TinyProc {
     pub _gc_stats_enabled: bool,
     pub profiler: Option<Profiler>,
     pub trace_enabled: bool,
+    pub output: OutputSink, // where PRINT and friends write
 }
 
 impl TinyProc {
@@ -92,11 +130,15 @@ impl TinyProc {
         
         let proc = TinyProc {
             id,
+            parent_pid: None,
             state: ProcState::Ready,
+            priority: Priority::Normal,
             mailbox: receiver,
             mailbox_sender: sender.clone(),
             reduction_count: 0,
-            max_reductions: 1000, // Default reduction limit
+            max_reductions: DEFAULT_MAX_REDUCTIONS,
+            stack_limit: None,
+            float_precision: None,
             message_sender: None, // Will be set by scheduler
             process_spawner: None, // Will be set by scheduler
             name_registry: None, // Will be set by scheduler
@@ -112,12 +154,23 @@ impl TinyProc {
             supervisor_pid: None,
             restart_intensity_count: 0,
             restart_period_start: Instant::now(),
+            pending_call: None,
+            next_call_ref: 0,
+            receive_deadline: None,
+            sleep_until: None,
+            dictionary: HashMap::new(),
+            tables: Arc::new(SharedTables::new()),
+            timers: Arc::new(Timers::new()),
+            program_args: Arc::new(Vec::new()),
+            regex_cache: ops::RegexCache::new(),
+            rng: None,
             
             // Initialize VM state
             stack: Vec::new(),
             instructions,
             ip: 0,
             call_stack: Vec::new(),
+            call_stack_bases: Vec::new(),
             variables: vec![HashMap::new()], // Initial global scope
             try_stack: Vec::new(),
             exports: HashMap::new(),
@@ -132,6 +185,7 @@ impl TinyProc {
             _gc_stats_enabled: false,
             profiler: None,
             trace_enabled: false,
+            output: OutputSink::stdout(),
         };
         
         (proc, sender)
@@ -149,11 +203,15 @@ impl TinyProc {
         
         let proc = TinyProc {
             id,
+            parent_pid: None,
             state: ProcState::Ready,
+            priority: Priority::Normal,
             mailbox: receiver,
             mailbox_sender: sender.clone(),
             reduction_count: 0,
-            max_reductions: 1000,
+            max_reductions: DEFAULT_MAX_REDUCTIONS,
+            stack_limit: None,
+            float_precision: None,
             message_sender: None,
             process_spawner: None,
             name_registry: None,
@@ -169,12 +227,23 @@ impl TinyProc {
             supervisor_pid: None,
             restart_intensity_count: 0,
             restart_period_start: Instant::now(),
+            pending_call: None,
+            next_call_ref: 0,
+            receive_deadline: None,
+            sleep_until: None,
+            dictionary: HashMap::new(),
+            tables: Arc::new(SharedTables::new()),
+            timers: Arc::new(Timers::new()),
+            program_args: Arc::new(Vec::new()),
+            regex_cache: ops::RegexCache::new(),
+            rng: None,
             
             // Initialize VM state
             stack: Vec::new(),
             instructions: supervisor_instructions,
             ip: 0,
             call_stack: Vec::new(),
+            call_stack_bases: Vec::new(),
             variables: vec![HashMap::new()],
             try_stack: Vec::new(),
             exports: HashMap::new(),
@@ -189,6 +258,7 @@ impl TinyProc {
             _gc_stats_enabled: false,
             profiler: None,
             trace_enabled: false,
+            output: OutputSink::stdout(),
         };
         
         (proc, sender)
@@ -249,6 +319,11 @@ impl TinyProc {
         self.set_exit_reason(reason.clone());
         self.state = ProcState::Exited;
         
+        // Free our names first, so processes told about the exit can't still find us by name
+        if let Some(registry) = &self.name_registry {
+            registry.unregister_names(self.id);
+        }
+        
         // Send down messages to all monitors
         for (monitor_ref, monitored_pid) in self.monitors.iter() {
             if let Some(sender) = &self.message_sender {
@@ -391,40 +466,72 @@ impl TinyProc {
     }
     
     fn selective_receive(&mut self, patterns: &[MessagePattern]) -> Result<Option<Message>, String> {
-        // Create a temporary vector to store messages that don't match
+        Ok(self.receive_first(|msg| patterns.iter().any(|pattern| self.matches_pattern(msg, pattern))))
+    }
+    
+    // Take the first queued message accepted by `matches`, leaving the others in the mailbox
+    fn receive_first(&self, matches: impl Fn(&Message) -> bool) -> Option<Message> {
+        // Drain the whole mailbox so the messages put back keep their original order
         let mut temp_messages = Vec::new();
-        
-        // Try to find a matching message
         let mut found_message = None;
         
-        // Check all available messages
         while let Ok(msg) = self.receive_message() {
-            let mut matched = false;
-            
-            for pattern in patterns {
-                if self.matches_pattern(&msg, pattern) {
-                    found_message = Some(msg.clone());
-                    matched = true;
-                    break;
-                }
-            }
-            
-            if matched {
-                break;
+            if found_message.is_none() && matches(&msg) {
+                found_message = Some(msg);
             } else {
                 temp_messages.push(msg);
             }
         }
         
-        // Put back non-matching messages in original order
-        for msg in temp_messages.into_iter().rev() {
+        for msg in temp_messages {
             let _ = self.mailbox_sender.try_send(msg);
         }
         
-        Ok(found_message)
+        found_message
+    }
+    
+    // Whether a RECEIVE_MATCH with an after clause has waited long enough to give up
+    pub fn receive_timed_out(&self) -> bool {
+        self.receive_deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+    
+    // Whether SLEEP is still holding this process back. Clears the deadline once it has passed.
+    pub fn is_sleeping(&mut self) -> bool {
+        match self.sleep_until {
+            Some(deadline) if Instant::now() < deadline => true,
+            Some(_) => {
+                self.sleep_until = None;
+                false
+            }
+            None => false,
+        }
+    }
+    
+    // Send a PROC_CALL request `{ref, from, payload}` to a pid or registered name
+    fn send_call_request(&mut self, target: Value, payload: Value) -> Result<String, String> {
+        let call_ref = format!("call_{}_{}", self.id, self.next_call_ref);
+        self.next_call_ref += 1;
+        
+        let mut request = IndexMap::new();
+        request.insert("ref".to_string(), Value::Str(call_ref.clone()));
+        request.insert("from".to_string(), Value::Int(self.id as i64));
+        request.insert("payload".to_string(), payload);
+        let message = Message::Value(Value::Object(request.into()));
+        
+        match target {
+            Value::Int(pid) if pid > 0 => {
+                let sender = self.message_sender.as_ref().ok_or("no message sender")?;
+                sender.send_message(pid as ProcId, message)?;
+            }
+            Value::Str(name) => {
+                let registry = self.name_registry.as_ref().ok_or("no registry")?;
+                registry.send_to_named(&name, message)?;
+            }
+            other => return Err(format!("cannot call {:?}", other)),
+        }
+        Ok(call_ref)
     }
     
-
     pub fn step(&mut self) -> VMResult<bool> {
         if self.ip >= self.instructions.len() {
             self.handle_process_exit("normal".to_string());
@@ -437,10 +544,13 @@ impl TinyProc {
         }
         
         // Check for exit signals at the beginning of each instruction cycle
-        // Process all available messages to handle exit signals immediately
+        // Process all available messages to handle exit signals immediately. Only the messages
+        // already queued are looked at, since ordinary ones are put back for Receive.
         let mut exit_signal_received = false;
+        let pending = self.mailbox.len();
         
-        while let Ok(msg) = self.receive_message() {
+        for _ in 0..pending {
+            let Ok(msg) = self.receive_message() else { break };
             match msg {
                 Message::Exit(pid) => {
                     // Handle exit signal from linked process
@@ -467,6 +577,12 @@ impl TinyProc {
                     println!("Process {} setting trap_exit to {}", self.id, trap);
                     self.trap_exit = trap;
                 }
+                Message::Kill(reason) => {
+                    // Killed from outside: trap_exit doesn't apply
+                    self.handle_process_exit(reason);
+                    exit_signal_received = true;
+                    break;
+                }
                 Message::Link(pid) => {
                     // Handle link request automatically - bidirectional linking
                     println!("Process {} received link request from process {}", self.id, pid);
@@ -525,6 +641,11 @@ impl TinyProc {
         }
         
         self.execute_instruction_safe(instruction)?;
+        if let Some(limit) = self.stack_limit {
+            if self.stack.len() > limit {
+                return Err(VMError::StackOverflow { limit });
+            }
+        }
         
         // Check if process state changed during instruction execution
         match self.state {
@@ -535,24 +656,55 @@ impl TinyProc {
     }
     
     pub fn run_until_yield(&mut self) -> VMResult<ProcState> {
+        self.run_for(self.max_reductions)
+    }
+    
+    // Like run_until_yield, but with a slice of `budget` reductions instead of max_reductions
+    pub fn run_for(&mut self, budget: usize) -> VMResult<ProcState> {
         self.state = ProcState::Running;
         self.reset_reductions();
+        let max_reductions = std::mem::replace(&mut self.max_reductions, budget);
         
-        loop {
-            match self.step()? {
-                true => continue,  // Keep running
-                false => break,    // Yielded or exited
+        let result = loop {
+            match self.step() {
+                Ok(true) => continue,  // Keep running
+                Ok(false) => break Ok(self.state), // Yielded or exited
+                Err(e) => break Err(e),
             }
-        }
+        };
         
-        Ok(self.state)
+        self.max_reductions = max_reductions;
+        result
     }
     
+    // Send PRINT, PRINT_RAW and DEBUG_PRINT output here instead of stdout
+    #[allow(dead_code)]
+    pub fn set_output(&mut self, output: OutputSink) {
+        self.output = output;
+    }
+
+    fn write_output(&mut self, text: &str) -> VMResult<()> {
+        use std::io::Write;
+        self.output.write_all(text.as_bytes())
+            .and_then(|_| self.output.flush())
+            .map_err(|e| VMError::FileError { filename: "<output>".to_string(), error: e.to_string() })
+    }
+
     fn pop_stack(&mut self, operation: &str) -> VMResult<Value> {
         self.stack.pop().ok_or_else(|| VMError::StackUnderflow(operation.to_string()))
     }
 
-    #[allow(dead_code)]
+    fn pop_dict_key(&mut self, operation: &str) -> VMResult<String> {
+        match self.pop_stack(operation)? {
+            Value::Str(key) => Ok(key),
+            other => Err(VMError::TypeMismatch {
+                expected: "a string key".to_string(),
+                got: format!("{:?}", other),
+                operation: operation.to_string(),
+            }),
+        }
+    }
+
     fn peek_stack(&self, operation: &str) -> VMResult<&Value> {
         self.stack.last().ok_or_else(|| VMError::StackUnderflow(operation.to_string()))
     }
@@ -573,18 +725,55 @@ impl TinyProc {
             OpCode::PushFloat(f) => self.stack.push(Value::Float(*f)),
             OpCode::PushStr(s) => self.stack.push(Value::Str(s.clone())),
             OpCode::PushBool(b) => self.stack.push(Value::Bool(*b)),
+            OpCode::MakeList(n) => {
+                // Needed to build the pid list for BROADCAST
+                let start = self.stack.len().checked_sub(*n).ok_or_else(|| VMError::StackUnderflow("MAKE_LIST".to_string()))?;
+                let items = self.stack.split_off(start);
+                self.stack.push(Value::List(items.into()));
+            }
             OpCode::Print => {
                 let val = self.pop_stack("PRINT")?;
-                println!("{}", val);
+                self.write_output(&format!("{}\n", val.display(self.float_precision)))?;
+            }
+            OpCode::DebugPrint => {
+                let val = self.pop_stack("DEBUG_PRINT")?;
+                self.write_output(&format!("{}\n", val.pretty()))?;
+            }
+            OpCode::GetArgs => {
+                let args = self.program_args.iter().map(|arg| Value::Str(arg.clone())).collect();
+                self.stack.push(Value::List(args));
+            }
+            OpCode::EnvVars => {
+                let mut vars: Vec<(String, String)> = std::env::vars().collect();
+                vars.sort();
+                self.stack.push(Value::Object(vars.into_iter().map(|(name, value)| (name, Value::Str(value))).collect()));
+            }
+            OpCode::PrintRaw => {
+                let val = self.pop_stack("PRINT_RAW")?;
+                self.write_output(&val.display(self.float_precision).to_string())?;
+            }
+            OpCode::Assert(message) => {
+                let val = self.pop_stack("ASSERT")?;
+                if val.is_falsy() {
+                    let message = message.clone().unwrap_or_else(|| format!("got {:?}", val));
+                    return Err(VMError::AssertionFailed { message });
+                }
+            }
+            OpCode::PrintErr => {
+                let val = self.pop_stack("PRINT_ERR")?;
+                eprintln!("{}", val.display(self.float_precision));
             }
             OpCode::Add => {
                 let b = self.pop_stack("ADD")?;
                 let a = self.pop_stack("ADD")?;
                 match (&a, &b) {
-                    (Value::Int(x), Value::Int(y)) => self.stack.push(Value::Int(x + y)),
-                    (Value::Int(x), Value::Float(y)) => self.stack.push(Value::Float(*x as f64 + y)),
-                    (Value::Float(x), Value::Int(y)) => self.stack.push(Value::Float(x + *y as f64)),
-                    (Value::Float(x), Value::Float(y)) => self.stack.push(Value::Float(x + y)),
+                    (Value::Int(x), Value::Int(y)) => {
+                        let result = x.checked_add(*y).ok_or_else(|| VMError::IntegerOverflow { operation: "ADD".to_string() })?;
+                        self.stack.push(Value::Int(result));
+                    }
+                    (Value::Int(x), Value::Float(y)) => self.stack.push(float_result("ADD", *x as f64 + y)?),
+                    (Value::Float(x), Value::Int(y)) => self.stack.push(float_result("ADD", x + *y as f64)?),
+                    (Value::Float(x), Value::Float(y)) => self.stack.push(float_result("ADD", x + y)?),
                     _ => return Err(VMError::TypeMismatch { 
                         expected: "two numbers (int or float)".to_string(), 
                         got: format!("{:?}, {:?}", a, b), 
@@ -596,10 +785,13 @@ impl TinyProc {
                 let b = self.pop_stack("MUL")?;
                 let a = self.pop_stack("MUL")?;
                 match (&a, &b) {
-                    (Value::Int(x), Value::Int(y)) => self.stack.push(Value::Int(x * y)),
-                    (Value::Int(x), Value::Float(y)) => self.stack.push(Value::Float(*x as f64 * y)),
-                    (Value::Float(x), Value::Int(y)) => self.stack.push(Value::Float(x * *y as f64)),
-                    (Value::Float(x), Value::Float(y)) => self.stack.push(Value::Float(x * y)),
+                    (Value::Int(x), Value::Int(y)) => {
+                        let result = x.checked_mul(*y).ok_or_else(|| VMError::IntegerOverflow { operation: "MUL".to_string() })?;
+                        self.stack.push(Value::Int(result));
+                    }
+                    (Value::Int(x), Value::Float(y)) => self.stack.push(float_result("MUL", *x as f64 * y)?),
+                    (Value::Float(x), Value::Int(y)) => self.stack.push(float_result("MUL", x * *y as f64)?),
+                    (Value::Float(x), Value::Float(y)) => self.stack.push(float_result("MUL", x * y)?),
                     _ => return Err(VMError::TypeMismatch { 
                         expected: "two numbers (int or float)".to_string(), 
                         got: format!("{:?}, {:?}", a, b), 
@@ -615,25 +807,26 @@ impl TinyProc {
                         if *y == 0 {
                             return Err(VMError::DivisionByZero);
                         }
-                        self.stack.push(Value::Int(x / y));
+                        let result = x.checked_div(*y).ok_or_else(|| VMError::IntegerOverflow { operation: "DIV".to_string() })?;
+                        self.stack.push(Value::Int(result));
                     },
                     (Value::Int(x), Value::Float(y)) => {
                         if *y == 0.0 {
                             return Err(VMError::DivisionByZero);
                         }
-                        self.stack.push(Value::Float(*x as f64 / y));
+                        self.stack.push(float_result("DIV", *x as f64 / y)?);
                     },
                     (Value::Float(x), Value::Int(y)) => {
                         if *y == 0 {
                             return Err(VMError::DivisionByZero);
                         }
-                        self.stack.push(Value::Float(x / *y as f64));
+                        self.stack.push(float_result("DIV", x / *y as f64)?);
                     },
                     (Value::Float(x), Value::Float(y)) => {
                         if *y == 0.0 {
                             return Err(VMError::DivisionByZero);
                         }
-                        self.stack.push(Value::Float(x / y));
+                        self.stack.push(float_result("DIV", x / y)?);
                     },
                     _ => return Err(VMError::TypeMismatch { 
                         expected: "two numbers (int or float)".to_string(), 
@@ -642,6 +835,50 @@ impl TinyProc {
                     }),
                 }
             }
+            OpCode::Neg => {
+                let val = self.pop_stack("NEG")?;
+                self.stack.push(ops::neg(val)?);
+            }
+            OpCode::Pow => {
+                let exponent = self.pop_stack("POW")?;
+                let base = self.pop_stack("POW")?;
+                self.stack.push(ops::pow(base, exponent)?);
+            }
+            OpCode::Sqrt => {
+                let val = self.pop_stack("SQRT")?;
+                self.stack.push(ops::sqrt(val)?);
+            }
+            OpCode::Abs => {
+                let val = self.pop_stack("ABS")?;
+                self.stack.push(ops::abs(val)?);
+            }
+            OpCode::Floor | OpCode::Ceil | OpCode::Round => {
+                let val = self.pop_stack(&crate::bytecode::opcode_to_text(instruction))?;
+                self.stack.push(ops::round(instruction, val)?);
+            }
+            OpCode::RegexMatch | OpCode::RegexFind => {
+                let name = crate::bytecode::opcode_to_text(instruction);
+                let pattern = self.pop_stack(&name)?;
+                let text = self.pop_stack(&name)?;
+                self.stack.push(ops::regex_search(&mut self.regex_cache, instruction, text, pattern)?);
+            }
+            OpCode::RegexReplace => {
+                let replacement = self.pop_stack("REGEX_REPLACE")?;
+                let pattern = self.pop_stack("REGEX_REPLACE")?;
+                let text = self.pop_stack("REGEX_REPLACE")?;
+                self.stack.push(ops::regex_replace(&mut self.regex_cache, text, pattern, replacement)?);
+            }
+            OpCode::RandomInt => {
+                let max = self.pop_stack("RANDOM_INT")?;
+                let min = self.pop_stack("RANDOM_INT")?;
+                self.stack.push(ops::random_int(&mut self.rng, min, max)?);
+            }
+            OpCode::RandomFloat => self.stack.push(ops::random_float(&mut self.rng)),
+            OpCode::RandomSeed => {
+                let seed = self.pop_stack("RANDOM_SEED")?;
+                ops::random_seed(&mut self.rng, seed)?;
+            }
+            OpCode::Uuid => self.stack.push(ops::uuid()),
             OpCode::Halt => {
                 self.handle_process_exit("normal".to_string());
                 // Don't advance IP for Halt - process is done
@@ -651,81 +888,40 @@ impl TinyProc {
                 // For now, spawn a simple process with basic instructions
                 // In a real implementation, we'd parse the function from the stack
                 let function_value = self.pop_stack("SPAWN")?;
-                
-                // Try to load the function as a ttvm file
-                let new_process_instructions = match function_value {
-                    Value::Str(ref s) => {
-                        // Try to load from examples directory first
-                        let mut file_path = format!("examples/{}.ttvm", s);
-                        if !std::path::Path::new(&file_path).exists() {
-                            // Try current directory
-                            file_path = format!("{}.ttvm", s);
-                        }
-                        
-                        if std::path::Path::new(&file_path).exists() {
-                            // Load and parse the ttvm file
-                            match parse_program(&file_path) {
-                                Ok(instructions) => instructions,
-                                Err(e) => {
-                                    eprintln!("Failed to parse {}: {}", file_path, e);
-                                    vec![
-                                        OpCode::PushStr(format!("Failed to load {}", s)),
-                                        OpCode::Print,
-                                        OpCode::Halt,
-                                    ]
-                                }
-                            }
-                        } else {
-                            // Fallback to hardcoded processes for backward compatibility
-                            match s.as_str() {
-                                "hello_world" => {
-                                    vec![
-                                        OpCode::PushStr("Hello from spawned process!".to_string()),
-                                        OpCode::Print,
-                                        OpCode::Halt,
-                                    ]
-                                }
-                                "counter" => {
-                                    vec![
-                                        OpCode::PushInt(1),
-                                        OpCode::Print,
-                                        OpCode::PushInt(2),
-                                        OpCode::Print,
-                                        OpCode::PushInt(3),
-                                        OpCode::Print,
-                                        OpCode::Halt,
-                                    ]
-                                }
-                                _ => {
-                                    // Default: spawn a simple process
-                                    vec![
-                                        OpCode::PushStr(format!("Spawned process: {}", s)),
-                                        OpCode::Print,
-                                        OpCode::Halt,
-                                    ]
-                                }
-                            }
-                        }
-                    }
-                    _ => {
-                        // Non-string values default to simple process
-                        vec![
-                            OpCode::PushStr("Spawned process".to_string()),
-                            OpCode::Print,
-                            OpCode::Halt,
-                        ]
-                    }
-                };
+                let new_process_instructions = spawned_instructions(function_value);
                 
                 // Spawn the new process
                 if let Some(spawner) = &self.process_spawner {
-                    let (new_proc_id, _sender) = spawner.spawn_process(new_process_instructions);
+                    let (new_proc_id, _sender) = spawner.spawn_child(self.id, new_process_instructions);
                     self.stack.push(Value::Int(new_proc_id as i64));
                 } else {
                     eprintln!("No process spawner available for process {}", self.id);
                     self.stack.push(Value::Int(0)); // Push dummy process ID
                 }
             }
+            OpCode::SpawnRegistered(name) => {
+                let function_value = self.pop_stack("SPAWN_REGISTERED")?;
+                let spawner = self.process_spawner.as_ref()
+                    .ok_or_else(|| VMError::RuntimeError(format!("SPAWN_REGISTERED: no process spawner available for process {}", self.id)))?;
+                let (new_proc_id, _sender) = spawner.spawn_registered(self.id, name, spawned_instructions(function_value))
+                    .map_err(|e| VMError::RuntimeError(format!("SPAWN_REGISTERED: {}", e)))?;
+                self.stack.push(Value::Int(new_proc_id as i64));
+            }
+            OpCode::Dup => {
+                let val = self.peek_stack("DUP")?.clone();
+                self.stack.push(val);
+            }
+            OpCode::GetField(field_name) => {
+                let obj = self.pop_stack("GET_FIELD")?;
+                match obj {
+                    Value::Object(map) => self.stack.push(map.get(field_name).cloned().unwrap_or(Value::Null)),
+                    _ => return Err(VMError::TypeMismatch {
+                        expected: "an object".to_string(),
+                        got: format!("{:?}", obj),
+                        operation: "GET_FIELD".to_string(),
+                    }),
+                }
+            }
             OpCode::Receive => {
                 // Try to receive a message from mailbox
                 match self.receive_message() {
@@ -785,6 +981,10 @@ impl TinyProc {
                                 self.trap_exit = trap;
                                 self.stack.push(Value::Bool(trap));
                             }
+                            Message::Kill(reason) => {
+                                self.handle_process_exit(reason);
+                                return Ok(());
+                            }
                         }
                     }
                     Err(_) => {
@@ -796,12 +996,13 @@ impl TinyProc {
                     }
                 }
             }
-            OpCode::ReceiveMatch(patterns) => {
+            OpCode::ReceiveMatch(patterns) | OpCode::ReceiveMatchAfter(patterns, _) => {
                 // Try selective receive with pattern matching
                 match self.selective_receive(patterns) {
                     Ok(Some(msg)) => {
                         // Message received successfully
                         self.waiting_for_message = false;
+                        self.receive_deadline = None;
                         match msg {
                             Message::Value(val) => self.stack.push(val),
                             Message::Signal(sig) => self.stack.push(Value::Str(sig)),
@@ -853,9 +1054,25 @@ impl TinyProc {
                                 self.trap_exit = trap;
                                 self.stack.push(Value::Bool(trap));
                             }
+                            Message::Kill(reason) => {
+                                self.handle_process_exit(reason);
+                                return Ok(());
+                            }
                         }
                     }
                     Ok(None) => {
+                        // With an after clause, give up once the time is up and push null
+                        if let OpCode::ReceiveMatchAfter(_, ms) = instruction {
+                            let deadline = *self.receive_deadline
+                                .get_or_insert_with(|| Instant::now() + Duration::from_millis(*ms));
+                            if Instant::now() >= deadline {
+                                self.waiting_for_message = false;
+                                self.receive_deadline = None;
+                                self.stack.push(Value::Null);
+                                self.ip += 1;
+                                return Ok(());
+                            }
+                        }
                         // No matching message available, mark as waiting and yield
                         self.waiting_for_message = true;
                         self.state = ProcState::Waiting;
@@ -867,12 +1084,64 @@ impl TinyProc {
                     }
                 }
             }
+            OpCode::Sleep => {
+                // Yield until the deadline instead of blocking the scheduler thread
+                let millis = match self.pop_stack("SLEEP")? {
+                    Value::Int(millis) => millis.max(0) as u64,
+                    Value::Float(millis) => millis.max(0.0) as u64,
+                    other => return Err(VMError::TypeMismatch {
+                        expected: "int or float (milliseconds)".to_string(),
+                        got: format!("{:?}", other),
+                        operation: "SLEEP".to_string(),
+                    }),
+                };
+                self.sleep_until = Some(Instant::now() + Duration::from_millis(millis));
+                self.ip += 1;
+                self.state = ProcState::Waiting;
+                return Ok(());
+            }
             OpCode::Yield => {
                 // Manually yield to scheduler
                 self.ip += 1; // Advance IP before yielding
                 self.state = ProcState::Waiting;
                 return Ok(());
             }
+            OpCode::Jmp(target) => {
+                self.ip = *target;
+                return Ok(());
+            }
+            OpCode::Jz(target) => {
+                if self.pop_stack("JZ")?.is_falsy() {
+                    self.ip = *target;
+                    return Ok(());
+                }
+            }
+            OpCode::Call { addr, params } => {
+                let mut frame = HashMap::new();
+                ops::bind_arguments(&mut self.stack, params, &mut frame, "CALL")?;
+                if self.call_stack.len() >= DEFAULT_CALL_DEPTH_LIMIT {
+                    return Err(VMError::CallStackOverflow { depth: DEFAULT_CALL_DEPTH_LIMIT });
+                }
+                self.call_stack.push(self.ip + 1);
+                self.call_stack_bases.push(self.stack.len());
+                self.variables.push(frame);
+                self.ip = *addr;
+                return Ok(());
+            }
+            OpCode::Ret | OpCode::RetN(_) => {
+                if let (OpCode::RetN(declared), Some(&base)) = (instruction, self.call_stack_bases.last()) {
+                    ops::check_return_count(*declared, &self.stack, base)?;
+                }
+                if self.variables.len() <= 1 {
+                    return Err(VMError::NoVariableScope);
+                }
+                self.variables.pop();
+                self.call_stack_bases.pop();
+                self.ip = self.call_stack.pop().ok_or(VMError::CallStackUnderflow)?;
+                return Ok(());
+            }
+            OpCode::SelfPid => self.stack.push(Value::Int(self.id as i64)),
+            OpCode::ParentPid => self.stack.push(self.parent_pid.map_or(Value::Null, |pid| Value::Int(pid as i64))),
             OpCode::Send(target_proc_id) => {
                 // Get the message value from the stack
                 let message_value = self.pop_stack("SEND")?;
@@ -896,6 +1165,32 @@ impl TinyProc {
                     eprintln!("No message sender available for process {}", self.id);
                 }
             }
+            OpCode::Broadcast => {
+                let message = self.pop_stack("BROADCAST")?;
+                let targets = self.pop_stack("BROADCAST")?;
+                let pids = match &targets {
+                    Value::List(items) => items.iter().map(|item| match item {
+                        Value::Int(pid) if *pid > 0 => Some(*pid as ProcId),
+                        _ => None,
+                    }).collect::<Option<Vec<_>>>(),
+                    _ => None,
+                };
+                let Some(pids) = pids else {
+                    return Err(VMError::TypeMismatch {
+                        expected: "a list of pids".to_string(),
+                        got: format!("{:?}", targets),
+                        operation: "BROADCAST".to_string(),
+                    });
+                };
+                // Pids that have exited are skipped rather than failing the rest
+                let delivered = match &self.message_sender {
+                    Some(sender) => pids.iter()
+                        .filter(|pid| sender.send_message(**pid, Message::Value(message.clone())).is_ok())
+                        .count(),
+                    None => 0,
+                };
+                self.stack.push(Value::Int(delivered as i64));
+            }
             OpCode::Monitor(target_proc_id) => {
                 // Monitor a process - add it to our monitors list
                 let monitor_ref = self.monitor_process(*target_proc_id);
@@ -1027,30 +1322,146 @@ impl TinyProc {
                 }
             }
             OpCode::Whereis(name) => {
-                // Find PID by name (returns 0 if not found)
-                if let Some(registry) = &self.name_registry {
-                    match registry.whereis(name) {
-                        Some(proc_id) => self.stack.push(Value::Int(proc_id as i64)),
-                        None => self.stack.push(Value::Int(0)),
-                    }
-                } else {
-                    self.stack.push(Value::Int(0));
-                }
+                // Find PID by name (null if not found)
+                let found = self.name_registry.as_ref().and_then(|registry| registry.whereis(name));
+                self.stack.push(found.map_or(Value::Null, |proc_id| Value::Int(proc_id as i64)));
             }
             OpCode::SendNamed(name) => {
                 // Send message to named process
                 let message_value = self.pop_stack("SEND_NAMED")?;
                 
-                if let Some(registry) = &self.name_registry {
-                    let message = Message::Value(message_value);
-                    match registry.send_to_named(name, message) {
+                if super::node::split_remote_name(name).is_some() {
+                    // name@host:port lives on another node
+                    match super::node::send_remote(name, &message_value) {
                         Ok(_) => self.stack.push(Value::Str(format!("sent_to_{}", name))),
                         Err(e) => self.stack.push(Value::Str(format!("send_failed_{}", e))),
                     }
                 } else {
-                    self.stack.push(Value::Str("send_failed_no_registry".to_string()));
+                    // An unknown name is an error rather than a status string
+                    let registry = self.name_registry.as_ref()
+                        .ok_or_else(|| VMError::RuntimeError(format!("SENDNAMED: no name registry to find '{}'", name)))?;
+                    registry.send_to_named(name, Message::Value(message_value))
+                        .map_err(|e| VMError::RuntimeError(format!("SENDNAMED: {}", e)))?;
+                    self.stack.push(Value::Str(format!("sent_to_{}", name)));
+                }
+            }
+            OpCode::ProcCall => {
+                // Send the request once, then retry this instruction until its reply arrives
+                if self.pending_call.is_none() {
+                    let payload = self.pop_stack("PROC_CALL")?;
+                    let target = self.pop_stack("PROC_CALL")?;
+                    match self.send_call_request(target, payload) {
+                        Ok(call_ref) => self.pending_call = Some(call_ref),
+                        Err(e) => self.stack.push(Value::Str(format!("call_failed_{}", e))),
+                    }
+                }
+                if let Some(call_ref) = self.pending_call.clone() {
+                    match self.receive_first(|msg| is_reply_to(msg, &call_ref)) {
+                        Some(Message::Value(Value::Object(mut reply))) => {
+                            self.pending_call = None;
+                            self.waiting_for_message = false;
+                            self.stack.push(reply.shift_remove("reply").unwrap_or(Value::Null));
+                        }
+                        _ => {
+                            self.waiting_for_message = true;
+                            self.state = ProcState::Waiting;
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+            OpCode::ProcReply => {
+                // Send `{ref, reply}` back to the process that made the call
+                let reply = self.pop_stack("PROC_REPLY")?;
+                let request = self.pop_stack("PROC_REPLY")?;
+                let (call_ref, from) = match &request {
+                    Value::Object(fields) => match (fields.get("ref"), fields.get("from")) {
+                        (Some(Value::Str(call_ref)), Some(Value::Int(from))) => (call_ref.clone(), *from as ProcId),
+                        _ => return Err(VMError::TypeMismatch {
+                            expected: "PROC_CALL request".to_string(),
+                            got: format!("{:?}", request),
+                            operation: "PROC_REPLY".to_string(),
+                        }),
+                    },
+                    other => return Err(VMError::TypeMismatch {
+                        expected: "PROC_CALL request".to_string(),
+                        got: format!("{:?}", other),
+                        operation: "PROC_REPLY".to_string(),
+                    }),
+                };
+                
+                let mut message = IndexMap::new();
+                message.insert("ref".to_string(), Value::Str(call_ref));
+                message.insert("reply".to_string(), reply);
+                if let Some(sender) = &self.message_sender {
+                    if let Err(e) = sender.send_message(from, Message::Value(Value::Object(message.into()))) {
+                        eprintln!("Failed to reply to process {}: {}", from, e);
+                    }
+                } else {
+                    eprintln!("No message sender available for process {}", self.id);
                 }
             }
+            OpCode::SendAfter => {
+                let message = self.pop_stack("SEND_AFTER")?;
+                let delay = self.pop_stack("SEND_AFTER")?;
+                let target = self.pop_stack("SEND_AFTER")?;
+                let (target, delay) = match (target, delay) {
+                    (Value::Int(pid), Value::Int(ms)) if pid > 0 && ms >= 0 => (pid as ProcId, ms as u64),
+                    (t, d) => return Err(VMError::TypeMismatch {
+                        expected: "pid and non-negative delay (ms)".to_string(),
+                        got: format!("{:?}, {:?}", t, d),
+                        operation: "SEND_AFTER".to_string(),
+                    }),
+                };
+                let timer = self.timers.send_after(target, Duration::from_millis(delay), Message::Value(message));
+                self.stack.push(Value::Int(timer));
+            }
+            OpCode::CancelTimer => {
+                let cancelled = match self.pop_stack("CANCEL_TIMER")? {
+                    Value::Int(timer) => self.timers.cancel(timer),
+                    other => return Err(VMError::TypeMismatch {
+                        expected: "a timer id".to_string(),
+                        got: format!("{:?}", other),
+                        operation: "CANCEL_TIMER".to_string(),
+                    }),
+                };
+                self.stack.push(Value::Bool(cancelled));
+            }
+            OpCode::PutDict => {
+                let value = self.pop_stack("PUT_DICT")?;
+                let key = self.pop_dict_key("PUT_DICT")?;
+                self.dictionary.insert(key, value);
+            }
+            OpCode::GetDict => {
+                let key = self.pop_dict_key("GET_DICT")?;
+                let value = self.dictionary.get(&key).cloned().unwrap_or(Value::Null);
+                self.stack.push(value);
+            }
+            OpCode::EraseDict => {
+                let key = self.pop_dict_key("ERASE_DICT")?;
+                self.dictionary.remove(&key);
+            }
+            OpCode::TableNew => {
+                let table = self.tables.new_table();
+                self.stack.push(table);
+            }
+            OpCode::TableInsert => {
+                let value = self.pop_stack("TABLE_INSERT")?;
+                let key = self.pop_stack("TABLE_INSERT")?;
+                let table = self.pop_stack("TABLE_INSERT")?;
+                self.tables.insert(&table, &key, &value)?;
+            }
+            OpCode::TableLookup => {
+                let key = self.pop_stack("TABLE_LOOKUP")?;
+                let table = self.pop_stack("TABLE_LOOKUP")?;
+                let value = self.tables.lookup(&table, &key)?;
+                self.stack.push(value);
+            }
+            OpCode::TableDelete => {
+                let key = self.pop_stack("TABLE_DELETE")?;
+                let table = self.pop_stack("TABLE_DELETE")?;
+                self.tables.delete(&table, &key)?;
+            }
             OpCode::StartSupervisor => {
                 // This opcode is used to start supervisor functionality
                 // The actual supervisor is created with new_supervisor()
@@ -1088,18 +1499,19 @@ impl TinyProc {
                     self.stack.push(Value::Str("not_supervisor".to_string()));
                 }
             }
-            OpCode::Import(path) => {
+            OpCode::Import(path) | OpCode::ImportAs(path, _) | OpCode::ImportFrom(path, _) => {
                 // Handle module imports with circular dependency detection
-                if self.loading_stack.contains(path) {
+                let key = crate::bytecode::module_key(path);
+                if self.loading_stack.contains(&key) {
                     return Err(VMError::CircularDependency(path.clone()));
                 }
                 
                 // If module is already loaded, skip
-                if self.loaded_modules.contains_key(path) {
+                if self.loaded_modules.contains_key(&key) {
                     // Module already loaded, nothing to do
                 } else {
                     // Load the module
-                    self.loading_stack.push(path.clone());
+                    self.loading_stack.push(key.clone());
                     
                     // Load and parse the module file
                     let module_result = self.load_module(path);
@@ -1109,7 +1521,7 @@ impl TinyProc {
                     
                     match module_result {
                         Ok(exports) => {
-                            self.loaded_modules.insert(path.clone(), exports);
+                            self.loaded_modules.insert(key, exports);
                         }
                         Err(e) => return Err(e),
                     }
@@ -1128,18 +1540,14 @@ impl TinyProc {
     }
     
     fn load_module(&mut self, path: &str) -> VMResult<HashMap<String, Value>> {
-        // Try to load the module file
-        let _content = std::fs::read_to_string(path)
-            .map_err(|e| VMError::FileError { 
-                filename: path.to_string(), 
-                error: e.to_string() 
-            })?;
-        
-        // Parse the module
-        let module_instructions = crate::bytecode::parse_program(path)
-            .map_err(|e| VMError::ParseError { 
-                line: 0, 
-                instruction: format!("Failed to parse module {}: {}", path, e) 
+        // Load the module from source or compiled bytecode
+        let module_instructions = crate::bytecode::load_program(path)
+            .map_err(|e| match e {
+                VMError::FileError { .. } => e,
+                e => VMError::ParseError { 
+                    line: 0, 
+                    instruction: format!("Failed to parse module {}: {}", path, e) 
+                },
             })?;
         
         // Create a new TinyProc to execute the module
@@ -1197,4 +1605,83 @@ impl RunnableProcess for TinyProc {
     fn set_waiting_for_message(&mut self, waiting: bool) {
         self.waiting_for_message = waiting;
     }
-}
\ No newline at end of file
+}
+
+// The program SPAWN and SPAWN_REGISTERED start for the value on the stack: the named .ttvm file
+// from examples/ or the current directory, or a placeholder process
+fn spawned_instructions(function_value: Value) -> Vec<OpCode> {
+    // Try to load the function as a ttvm file
+    match function_value {
+        Value::Str(ref s) => {
+            // Try to load from examples directory first
+            let mut file_path = format!("examples/{}.ttvm", s);
+            if !std::path::Path::new(&file_path).exists() {
+                // Try current directory
+                file_path = format!("{}.ttvm", s);
+            }
+            
+            if std::path::Path::new(&file_path).exists() {
+                // Load and parse the ttvm file
+                match parse_program(&file_path) {
+                    Ok(instructions) => instructions,
+                    Err(e) => {
+                        eprintln!("Failed to parse {}: {}", file_path, e);
+                        vec![
+                            OpCode::PushStr(format!("Failed to load {}", s)),
+                            OpCode::Print,
+                            OpCode::Halt,
+                        ]
+                    }
+                }
+            } else {
+                // Fallback to hardcoded processes for backward compatibility
+                match s.as_str() {
+                    "hello_world" => {
+                        vec![
+                            OpCode::PushStr("Hello from spawned process!".to_string()),
+                            OpCode::Print,
+                            OpCode::Halt,
+                        ]
+                    }
+                    "counter" => {
+                        vec![
+                            OpCode::PushInt(1),
+                            OpCode::Print,
+                            OpCode::PushInt(2),
+                            OpCode::Print,
+                            OpCode::PushInt(3),
+                            OpCode::Print,
+                            OpCode::Halt,
+                        ]
+                    }
+                    _ => {
+                        // Default: spawn a simple process
+                        vec![
+                            OpCode::PushStr(format!("Spawned process: {}", s)),
+                            OpCode::Print,
+                            OpCode::Halt,
+                        ]
+                    }
+                }
+            }
+        }
+        _ => {
+            // Non-string values default to simple process
+            vec![
+                OpCode::PushStr("Spawned process".to_string()),
+                OpCode::Print,
+                OpCode::Halt,
+            ]
+        }
+    }
+}
+
+// Whether `msg` is the reply to the PROC_CALL with `call_ref`
+fn is_reply_to(msg: &Message, call_ref: &str) -> bool {
+    match msg {
+        Message::Value(Value::Object(fields)) => {
+            matches!(fields.get("ref"), Some(Value::Str(r)) if r == call_ref) && fields.contains_key("reply")
+        }
+        _ => false,
+    }
+}
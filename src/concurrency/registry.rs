@@ -59,11 +59,7 @@ impl ProcessRegistry {
         self.process_senders.remove(&pid);
         
         // Remove all names associated with this process
-        if let Some(names) = self.pid_to_names.remove(&pid) {
-            for name in names {
-                self.name_to_pid.remove(&name);
-            }
-        }
+        self.unregister_names(pid);
         
         // Remove process info
         self.process_info.remove(&pid);
@@ -100,6 +96,15 @@ impl ProcessRegistry {
         }
     }
     
+    // Free every name `pid` registered
+    pub fn unregister_names(&mut self, pid: ProcId) {
+        if let Some(names) = self.pid_to_names.remove(&pid) {
+            for name in names {
+                self.name_to_pid.remove(&name);
+            }
+        }
+    }
+    
     pub fn whereis(&self, name: &str) -> Option<ProcId> {
         self.name_to_pid.get(name).copied()
     }
@@ -1,30 +1,61 @@
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::thread;
 use std::time::Duration;
-use crossbeam_deque::{Worker, Stealer};
-use crate::concurrency::{TinyProc, ProcessRegistry};
+use crossbeam_deque::{Worker, Stealer, Steal};
+use crate::concurrency::{lock, TinyProc, Priority, ProcessRegistry, Timers};
 use crate::vm::ProcId;
 use crate::ProcState;
 
+// How a scheduler divides its time between processes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[allow(dead_code)]
+pub enum SchedulingPolicy {
+    // Every process gets the same reduction budget per slice
+    #[default]
+    RoundRobin,
+    // A process's Priority scales its budget: low gets half the normal slice, high double
+    Priority,
+}
+
+impl SchedulingPolicy {
+    pub fn reduction_budget(self, max_reductions: usize, priority: Priority) -> usize {
+        match (self, priority) {
+            (SchedulingPolicy::RoundRobin, _) | (SchedulingPolicy::Priority, Priority::Normal) => max_reductions,
+            (SchedulingPolicy::Priority, Priority::Low) => (max_reductions / 2).max(1),
+            (SchedulingPolicy::Priority, Priority::High) => max_reductions.saturating_mul(2),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Scheduler {
     pub id: usize,
     pub local_queue: Worker<Arc<Mutex<TinyProc>>>,
     pub remote_stealers: Vec<Stealer<Arc<Mutex<TinyProc>>>>,
     pub running: bool,
+    pub policy: SchedulingPolicy,
+    pub slices_run: Arc<AtomicUsize>, // process slices this scheduler has executed
+    pub next_victim: usize,           // where the next steal attempt starts in remote_stealers
 }
 
 impl Scheduler {
     #[allow(dead_code)]
     pub fn new(id: usize) -> Self {
+        Self::with_policy(id, SchedulingPolicy::RoundRobin)
+    }
+    
+    pub fn with_policy(id: usize, policy: SchedulingPolicy) -> Self {
         let local_queue = Worker::new_fifo();
         Scheduler {
             id,
             local_queue,
             remote_stealers: Vec::new(),
             running: true,
+            policy,
+            slices_run: Arc::new(AtomicUsize::new(0)),
+            next_victim: 0,
         }
     }
     
@@ -37,27 +68,43 @@ impl Scheduler {
         self.local_queue.pop()
     }
     
-    pub fn steal_from_others(&self) -> Option<Arc<Mutex<TinyProc>>> {
-        for stealer in &self.remote_stealers {
-            if let crossbeam_deque::Steal::Success(proc) = stealer.steal() {
-                return Some(proc);
+    // Take about half of another scheduler's queued processes, returning one to run now and
+    // keeping the rest in the local queue. Each process is in exactly one queue at a time, so
+    // a stolen process can't also run where it came from. Victims are tried round-robin so
+    // every busy scheduler gets relief, and a steal that lost a race is retried.
+    pub fn steal_from_others(&mut self) -> Option<Arc<Mutex<TinyProc>>> {
+        let count = self.remote_stealers.len();
+        for offset in 0..count {
+            let victim = (self.next_victim + offset) % count;
+            loop {
+                match self.remote_stealers[victim].steal_batch_and_pop(&self.local_queue) {
+                    Steal::Success(proc) => {
+                        self.next_victim = (victim + 1) % count;
+                        return Some(proc);
+                    }
+                    Steal::Retry => continue,
+                    Steal::Empty => break,
+                }
             }
         }
         None
     }
     
-    pub fn run_scheduler_loop(&mut self, submission_queue: Arc<Mutex<Vec<Arc<Mutex<TinyProc>>>>>, shutdown_flag: Arc<AtomicBool>, running_processes: Arc<Mutex<HashMap<ProcId, Arc<Mutex<TinyProc>>>>>, registry: Arc<Mutex<ProcessRegistry>>) {
+    pub fn run_scheduler_loop(&mut self, submission_queue: Arc<Mutex<Vec<Arc<Mutex<TinyProc>>>>>, shutdown_flag: Arc<AtomicBool>, running_processes: Arc<Mutex<HashMap<ProcId, Arc<Mutex<TinyProc>>>>>, registry: Arc<Mutex<ProcessRegistry>>, timers: Arc<Timers>) {
         loop {
             // Check for shutdown first - atomic read is fast and lock-free
             if shutdown_flag.load(Ordering::Relaxed) {
                 break;
             }
             
+            // Deliver SEND_AFTER messages that are due, waking the processes waiting for them
+            timers.deliver_due(&registry);
+            
             // Try to get new processes from submission queue FIRST (higher priority)
             if let Ok(mut queue) = submission_queue.try_lock() {
                 if let Some(proc_arc) = queue.pop() {
                     let _proc_id = {
-                        let proc = proc_arc.lock().unwrap();
+                        let proc = lock(&proc_arc);
                         proc.id
                     };
                     // println!("DEBUG: Scheduler {} picked up process {} from submission queue", self.id, proc_id);
@@ -76,9 +123,7 @@ impl Scheduler {
             }
             
             // Try to get a process from local queue
-            if let Some(proc_arc) = self.get_next_process() {
-                self.execute_process_with_cleanup(proc_arc.clone(), running_processes.clone(), registry.clone());
-                
+            if self.run_next(running_processes.clone(), registry.clone()) {
                 // Check shutdown flag after processing each process
                 if shutdown_flag.load(Ordering::Relaxed) {
                     break;
@@ -108,13 +153,24 @@ impl Scheduler {
     }
     
     
+    // Give the next process in the local queue one slice, returning false if the queue was empty
+    pub fn run_next(&mut self, running_processes: Arc<Mutex<HashMap<ProcId, Arc<Mutex<TinyProc>>>>>, registry: Arc<Mutex<ProcessRegistry>>) -> bool {
+        match self.get_next_process() {
+            Some(proc_arc) => {
+                self.execute_process_with_cleanup(proc_arc, running_processes, registry);
+                true
+            }
+            None => false,
+        }
+    }
+    
     fn execute_process_with_cleanup(&mut self, proc_arc: Arc<Mutex<TinyProc>>, running_processes: Arc<Mutex<HashMap<ProcId, Arc<Mutex<TinyProc>>>>>, registry: Arc<Mutex<ProcessRegistry>>) {
         let proc_id = {
-            let proc = proc_arc.lock().unwrap();
+            let proc = lock(&proc_arc);
             proc.id
         };
         
-        let mut proc = proc_arc.lock().unwrap();
+        let mut proc = lock(&proc_arc);
         
         // Show debug info about which core is processing which process (only on first execution)
         if matches!(proc.state, ProcState::Ready) && !proc.waiting_for_message {
@@ -124,14 +180,26 @@ impl Scheduler {
         match proc.state {
             ProcState::Ready | ProcState::Waiting => {
                 // If process is waiting for a message, check if it has one now
-                if proc.waiting_for_message && !proc.has_messages() {
-                    // Still waiting for a message, don't requeue immediately to prevent tight loops
-                    // This allows the scheduler to check shutdown flag more frequently
+                if proc.waiting_for_message && !proc.has_messages() && !proc.receive_timed_out() {
+                    // Still waiting for a message: keep it queued so a message that arrives
+                    // later (possibly from another node) wakes it, and let other work run first
+                    drop(proc);
+                    self.local_queue.push(proc_arc);
+                    thread::yield_now();
+                    return;
+                }
+                
+                // Likewise a sleeping process waits in the queue until its deadline passes
+                if proc.is_sleeping() {
                     drop(proc);
+                    self.local_queue.push(proc_arc);
+                    thread::yield_now();
                     return;
                 }
                 
-                match proc.run_until_yield() {
+                let budget = self.policy.reduction_budget(proc.max_reductions, proc.priority);
+                self.slices_run.fetch_add(1, Ordering::Relaxed);
+                match proc.run_for(budget) {
                     Ok(ProcState::Waiting) => {
                         // Process yielded, put it back in queue for next round
                         proc.state = ProcState::Ready;
@@ -141,18 +209,18 @@ impl Scheduler {
                     Ok(ProcState::Exited) => {
                         // Process finished, remove from running processes and registry
                         drop(proc);
-                        let mut running = running_processes.lock().unwrap();
+                        let mut running = lock(&running_processes);
                         running.remove(&proc_id);
-                        let mut reg = registry.lock().unwrap();
+                        let mut reg = lock(&registry);
                         reg.unregister_process(proc_id).ok();
                     }
                     Err(e) => {
                         eprintln!("Process {} error: {:?}", proc_id, e);
                         proc.state = ProcState::Exited;
                         drop(proc);
-                        let mut running = running_processes.lock().unwrap();
+                        let mut running = lock(&running_processes);
                         running.remove(&proc_id);
-                        let mut reg = registry.lock().unwrap();
+                        let mut reg = lock(&registry);
                         reg.unregister_process(proc_id).ok();
                     }
                     _ => {
@@ -165,9 +233,9 @@ impl Scheduler {
             ProcState::Exited => {
                 // Process is done, remove from running processes and registry
                 drop(proc);
-                let mut running = running_processes.lock().unwrap();
+                let mut running = lock(&running_processes);
                 running.remove(&proc_id);
-                let mut reg = registry.lock().unwrap();
+                let mut reg = lock(&registry);
                 reg.unregister_process(proc_id).ok();
             }
             _ => {
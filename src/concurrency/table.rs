@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError, RwLock};
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use crate::concurrency::lock;
+use crate::vm::{VMError, VMResult, Value, serialize_value, deserialize_value};
+
+// Serialized key -> serialized value
+type Entries = HashMap<Vec<u8>, Vec<u8>>;
+
+// Key/value tables shared by every process in a scheduler pool (like Erlang's ETS). Keys and
+// values are stored serialized, so only plain values go in and each lookup returns a copy
+// that the reading process owns. Tables are addressed by the integer id TABLE_NEW returns.
+#[derive(Debug, Default)]
+pub struct SharedTables {
+    tables: RwLock<HashMap<i64, Mutex<Entries>>>,
+    next_id: AtomicI64,
+}
+
+impl SharedTables {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_table(&self) -> Value {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.tables.write().unwrap_or_else(PoisonError::into_inner).insert(id, Mutex::new(HashMap::new()));
+        Value::Int(id)
+    }
+
+    pub fn insert(&self, table: &Value, key: &Value, value: &Value) -> VMResult<()> {
+        let (key, value) = (serialize_value(key)?, serialize_value(value)?);
+        self.with_table(table, "TABLE_INSERT", |entries| {
+            entries.insert(key, value);
+        })
+    }
+
+    // The value stored under `key`, or Null
+    pub fn lookup(&self, table: &Value, key: &Value) -> VMResult<Value> {
+        let key = serialize_value(key)?;
+        self.with_table(table, "TABLE_LOOKUP", |entries| entries.get(&key).cloned())?
+            .map_or(Ok(Value::Null), |bytes| deserialize_value(&bytes))
+    }
+
+    pub fn delete(&self, table: &Value, key: &Value) -> VMResult<()> {
+        let key = serialize_value(key)?;
+        self.with_table(table, "TABLE_DELETE", |entries| {
+            entries.remove(&key);
+        })
+    }
+
+    fn with_table<T>(&self, table: &Value, operation: &str, f: impl FnOnce(&mut Entries) -> T) -> VMResult<T> {
+        let id = match table {
+            Value::Int(id) => *id,
+            other => return Err(VMError::TypeMismatch {
+                expected: "a table id".to_string(),
+                got: format!("{:?}", other),
+                operation: operation.to_string(),
+            }),
+        };
+        let tables = self.tables.read().unwrap_or_else(PoisonError::into_inner);
+        let entries = tables.get(&id)
+            .ok_or_else(|| VMError::RuntimeError(format!("{}: no table with id {}", operation, id)))?;
+        let result = f(&mut lock(entries));
+        Ok(result)
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::vm::ProcId;
+use super::{lock, Message, ProcessRegistry};
+
+// Messages waiting to be delivered after a delay (SEND_AFTER), shared by every scheduler in a
+// pool. Timers are ordered by deadline, so each scheduler loop only has to look at the front
+// to find the due ones. Timers are addressed by the integer id SEND_AFTER returns.
+#[derive(Debug, Default)]
+pub struct Timers {
+    pending: Mutex<BTreeMap<(Instant, i64), (ProcId, Message)>>,
+    next_id: AtomicI64,
+}
+
+impl Timers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn send_after(&self, target: ProcId, delay: Duration, message: Message) -> i64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        lock(&self.pending).insert((Instant::now() + delay, id), (target, message));
+        id
+    }
+
+    // False if the timer already fired or was cancelled
+    pub fn cancel(&self, id: i64) -> bool {
+        let mut pending = lock(&self.pending);
+        let key = pending.keys().find(|(_, timer)| *timer == id).copied();
+        key.is_some_and(|key| pending.remove(&key).is_some())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        lock(&self.pending).is_empty()
+    }
+
+    // Send every message whose deadline has passed, returning how many were due. Messages for
+    // processes that have exited are dropped. Another scheduler already holding the timers is
+    // doing the same work, so this doesn't wait for it.
+    pub fn deliver_due(&self, registry: &Mutex<ProcessRegistry>) -> usize {
+        let due = {
+            let Ok(mut pending) = self.pending.try_lock() else { return 0 };
+            let now = Instant::now();
+            let mut due = Vec::new();
+            while pending.first_key_value().is_some_and(|((deadline, _), _)| *deadline <= now) {
+                if let Some((_, timer)) = pending.pop_first() {
+                    due.push(timer);
+                }
+            }
+            due
+        };
+        if !due.is_empty() {
+            let mut registry = lock(registry);
+            for (target, message) in &due {
+                let _ = registry.send_message_simple(*target, message.clone());
+            }
+        }
+        due.len()
+    }
+}
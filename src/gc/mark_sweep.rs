@@ -1,34 +1,44 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
 use crate::vm::Value;
-use crate::gc::{GcEngine, GcRef, GcStats};
+use crate::gc::{GcEngine, GcRef, GcStats, DEFAULT_GC_THRESHOLD};
 
 // Mark and Sweep Garbage Collector
 #[derive(Debug)]
 pub struct MarkSweepGc {
-    objects: HashMap<usize, (Value, bool)>, // id -> (value, marked)
+    objects: HashMap<usize, (Tracked, bool)>, // id -> (what was allocated, marked)
     next_id: usize,
     stats: GcStats,
     debug_mode: bool,
+    threshold: usize,                // allocations that trigger a collection
+    allocated_since_collection: usize,
+    collection_started: Option<Instant>, // set by mark_from_roots, so the pause covers both phases
 }
 
 impl MarkSweepGc {
     pub fn new(debug_mode: bool) -> Self {
+        Self::with_threshold(debug_mode, DEFAULT_GC_THRESHOLD)
+    }
+
+    pub fn with_threshold(debug_mode: bool, threshold: usize) -> Self {
         MarkSweepGc {
             objects: HashMap::new(),
             next_id: 0,
             stats: GcStats::default(),
             debug_mode,
+            threshold,
+            allocated_since_collection: 0,
+            collection_started: None,
         }
     }
-}
 
-impl GcEngine for MarkSweepGc {
-    fn alloc(&mut self, value: Value) -> GcRef {
+    fn track(&mut self, tracked: Tracked) -> GcRef {
         let id = self.next_id;
         self.next_id += 1;
-        self.objects.insert(id, (value, false));
+        self.objects.insert(id, (tracked, false));
         self.stats.total_allocated += 1;
         self.stats.current_allocated += 1;
+        self.allocated_since_collection += 1;
         
         if self.debug_mode {
             println!("GC: Allocated object {} (total: {})", id, self.stats.current_allocated);
@@ -36,19 +46,58 @@ impl GcEngine for MarkSweepGc {
         
         GcRef::new(id)
     }
+}
 
-    fn mark_from_roots(&mut self, _roots: &[&Value]) {
-        // Mark all objects for now (simplified marking)
-        for (_, (_, marked)) in self.objects.iter_mut() {
-            *marked = true;
+// What the collector keeps of an allocation: the allocation the value was made by (see
+// `Heap`), which stays live while a copy of the value can be reached from a root, and for a
+// weak reference target the value itself, so WEAK_GET can hand it back.
+#[derive(Debug)]
+struct Tracked {
+    allocation: Option<usize>, // None for anything but a list, object or closure
+    weak_target: Option<Value>,
+}
+
+// Every allocation a copy of `value`, or of anything inside it, was made by
+fn reach(value: &Value, reachable: &mut HashSet<usize>) {
+    reachable.extend(value.allocation());
+    match value {
+        Value::List(items) => items.iter().for_each(|item| reach(item, reachable)),
+        Value::Object(fields) => fields.values().for_each(|field| reach(field, reachable)),
+        Value::Closure { captured, .. } => captured.values().for_each(|value| reach(value, reachable)),
+        _ => {}
+    }
+}
+
+impl GcEngine for MarkSweepGc {
+    fn alloc(&mut self, value: &Value) -> GcRef {
+        self.track(Tracked { allocation: value.allocation(), weak_target: None })
+    }
+
+    fn alloc_weak(&mut self, value: Value) -> GcRef {
+        self.track(Tracked { allocation: value.allocation(), weak_target: Some(value) })
+    }
+
+    fn mark_from_roots(&mut self, roots: &[&Value]) {
+        self.collection_started = Some(Instant::now());
+        // Walk the roots once, then look allocations up by id. An equal value built
+        // separately has an allocation of its own, so it doesn't keep a weak target alive.
+        let mut reachable = HashSet::new();
+        for root in roots {
+            reach(root, &mut reachable);
+        }
+        let mut marked_count = 0;
+        for (tracked, marked) in self.objects.values_mut() {
+            *marked = tracked.allocation.is_some_and(|allocation| reachable.contains(&allocation));
+            marked_count += *marked as usize;
         }
         
         if self.debug_mode {
-            println!("GC: Marked {} objects", self.objects.len());
+            println!("GC: Marked {} objects", marked_count);
         }
     }
 
     fn sweep(&mut self) -> usize {
+        let started = self.collection_started.take().unwrap_or_else(Instant::now);
         let initial_count = self.objects.len();
         self.objects.retain(|id, (_, marked)| {
             if *marked {
@@ -70,6 +119,8 @@ impl GcEngine for MarkSweepGc {
         self.stats.total_freed += collected;
         self.stats.current_allocated -= collected;
         self.stats.collections_performed += 1;
+        self.allocated_since_collection = 0;
+        self.stats.record_pause(started.elapsed());
         
         if self.debug_mode {
             println!("GC: Collected {} objects, {} remaining", collected, self.objects.len());
@@ -81,4 +132,16 @@ impl GcEngine for MarkSweepGc {
     fn stats(&self) -> GcStats {
         self.stats.clone()
     }
+
+    fn set_threshold(&mut self, threshold: usize) {
+        self.threshold = threshold;
+    }
+
+    fn should_collect(&self) -> bool {
+        self.allocated_since_collection >= self.threshold
+    }
+
+    fn get(&self, id: usize) -> Option<Value> {
+        self.objects.get(&id).and_then(|(tracked, _)| tracked.weak_target.clone())
+    }
 }
\ No newline at end of file
@@ -5,11 +5,24 @@ pub mod stats;
 use crate::vm::Value;
 pub use stats::{GcStats, GcRef};
 
+// Allocations between automatic collections unless --gc-threshold says otherwise
+pub const DEFAULT_GC_THRESHOLD: usize = 1000;
+
 pub trait GcEngine: std::fmt::Debug + Send + Sync {
-    fn alloc(&mut self, value: Value) -> GcRef;
+    // Count a new list, object or closure; the engine doesn't keep the value
+    fn alloc(&mut self, value: &Value) -> GcRef;
+    // Register the target of a weak reference, kept so get can return it until it is collected
+    fn alloc_weak(&mut self, value: Value) -> GcRef;
     fn mark_from_roots(&mut self, roots: &[&Value]);
     fn sweep(&mut self) -> usize; // returns number of objects collected
     fn stats(&self) -> GcStats;
+    // Engines that collect automatically do so every `threshold` allocations
+    fn set_threshold(&mut self, _threshold: usize) {}
+    fn should_collect(&self) -> bool {
+        false
+    }
+    // The value behind an allocation, or None once it has been collected
+    fn get(&self, id: usize) -> Option<Value>;
 }
 
 // Re-export GC implementations
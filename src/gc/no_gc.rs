@@ -1,9 +1,12 @@
+use std::collections::HashMap;
+use std::time::Instant;
 use crate::vm::Value;
 use crate::gc::{GcEngine, GcRef, GcStats};
 
 // No-op Garbage Collector (for testing and comparison)
 #[derive(Debug)]
 pub struct NoGc {
+    weak_targets: HashMap<usize, Value>, // never freed, so weak references always resolve
     next_id: usize,
     stats: GcStats,
 }
@@ -11,6 +14,7 @@ pub struct NoGc {
 impl NoGc {
     pub fn new() -> Self {
         NoGc {
+            weak_targets: HashMap::new(),
             next_id: 0,
             stats: GcStats::default(),
         }
@@ -18,7 +22,7 @@ impl NoGc {
 }
 
 impl GcEngine for NoGc {
-    fn alloc(&mut self, _value: Value) -> GcRef {
+    fn alloc(&mut self, _value: &Value) -> GcRef {
         let id = self.next_id;
         self.next_id += 1;
         self.stats.total_allocated += 1;
@@ -26,16 +30,29 @@ impl GcEngine for NoGc {
         GcRef::new(id)
     }
 
+    // Only weak reference targets are kept, since WEAK_GET has to return them
+    fn alloc_weak(&mut self, value: Value) -> GcRef {
+        let gc_ref = self.alloc(&value);
+        self.weak_targets.insert(gc_ref.id(), value);
+        gc_ref
+    }
+
     fn mark_from_roots(&mut self, _roots: &[&Value]) {
         // No-op
     }
 
     fn sweep(&mut self) -> usize {
+        let started = Instant::now();
         self.stats.collections_performed += 1;
+        self.stats.record_pause(started.elapsed());
         0 // Never collect anything
     }
 
     fn stats(&self) -> GcStats {
         self.stats.clone()
     }
+
+    fn get(&self, id: usize) -> Option<Value> {
+        self.weak_targets.get(&id).cloned()
+    }
 }
\ No newline at end of file
@@ -1,9 +1,15 @@
+use std::time::Duration;
+
 #[derive(Debug, Clone)]
 pub struct GcStats {
     pub total_allocated: usize,
     pub total_freed: usize,
     pub current_allocated: usize,
     pub collections_performed: usize,
+    pub live_objects: usize,   // lists, objects and closures reachable from the VM
+    pub heap_bytes: usize,     // approximate bytes held by live values
+    pub total_pause: Duration, // time spent marking and sweeping over the whole run
+    pub longest_pause: Duration,
 }
 
 impl Default for GcStats {
@@ -13,10 +19,21 @@ impl Default for GcStats {
             total_freed: 0,
             current_allocated: 0,
             collections_performed: 0,
+            live_objects: 0,
+            heap_bytes: 0,
+            total_pause: Duration::ZERO,
+            longest_pause: Duration::ZERO,
         }
     }
 }
 
+impl GcStats {
+    pub fn record_pause(&mut self, pause: Duration) {
+        self.total_pause += pause;
+        self.longest_pause = self.longest_pause.max(pause);
+    }
+}
+
 // GC Reference wrapper
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
@@ -29,4 +46,8 @@ impl GcRef {
     pub fn new(id: usize) -> Self {
         GcRef { id, generation: 0 }
     }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
 }
\ No newline at end of file
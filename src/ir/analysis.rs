@@ -0,0 +1,689 @@
+use crate::vm::{param_annotation, variadic_params, OpCode, VMError, VMResult};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+// Function values that may be held in a stack slot or variable, identified by the address
+// of the MAKE_FUNCTION/MAKE_LAMBDA instruction that created them
+pub type FunctionSet = BTreeSet<usize>;
+
+// Owner used for code running in the global frame rather than inside a function
+const GLOBAL_SCOPE: usize = usize::MAX;
+
+#[derive(Debug, Clone, PartialEq)]
+struct FrameState {
+    slots: Vec<FunctionSet>,   // one entry per value on the operand stack, bottom first
+    owners: BTreeSet<usize>,   // entries of the functions that may be executing here
+}
+
+impl FrameState {
+    // Join two states reaching the same instruction. Paths may leave different numbers of
+    // values behind; only the values both paths agree on (the top of the shallower stack)
+    // can be used afterwards, so the deeper path drops the rest on the way in.
+    fn merge(&mut self, incoming: &FrameState) -> bool {
+        let merged = FrameState {
+            slots: merge_slots(&self.slots, &incoming.slots),
+            owners: self.owners.union(&incoming.owners).copied().collect(),
+        };
+        let changed = merged != *self;
+        *self = merged;
+        changed
+    }
+}
+
+fn merge_slots(a: &[FunctionSet], b: &[FunctionSet]) -> Vec<FunctionSet> {
+    let depth = a.len().min(b.len());
+    let (a, b) = (&a[a.len() - depth..], &b[b.len() - depth..]);
+    a.iter().zip(b).map(|(x, y)| x.union(y).copied().collect()).collect()
+}
+
+// Stack depth (relative to the current function's frame) before every reachable
+// instruction, found by abstract interpretation of the bytecode. Alongside depths it traces
+// which function values can reach each CALL_FUNCTION, so that the number of values the
+// call leaves behind is known statically.
+pub struct StackAnalysis<'a> {
+    bytecode: &'a [OpCode],
+    states: Vec<Option<FrameState>>,
+    results: HashMap<usize, Vec<FunctionSet>>,          // function entry -> values left at RET
+    function_values: HashMap<usize, (usize, usize)>,    // MAKE_* address -> (entry, param count)
+    variables: HashMap<(usize, String), FunctionSet>,   // (owner, name) -> stored function values
+    captured: HashMap<String, FunctionSet>,
+    fields: HashMap<String, FunctionSet>,
+    tables_changed: bool,
+}
+
+impl<'a> StackAnalysis<'a> {
+    pub fn run(bytecode: &'a [OpCode]) -> VMResult<Self> {
+        // IMPORT appends the module's code to the instruction stream at run time, which a
+        // block lowered ahead of time cannot follow
+        if bytecode.iter().any(|instruction| matches!(instruction, OpCode::Import(_) | OpCode::ImportAs(..) | OpCode::ImportFrom(..) | OpCode::ReloadModule(_))) {
+            return Err(VMError::UnsupportedOperation(
+                "IMPORT cannot be lowered to register IR".to_string()
+            ));
+        }
+        // How many arguments a variadic call takes is only known once the count is popped
+        if let Some(addr) = bytecode.iter().position(|instruction| matches!(instruction,
+            OpCode::Call { params, .. } | OpCode::MakeFunction { params, .. } | OpCode::MakeLambda { params, .. }
+                if variadic_params(params).is_some()))
+        {
+            return Err(VMError::UnsupportedOperation(format!(
+                "variadic parameters at instruction {} cannot be lowered to register IR", addr
+            )));
+        }
+
+        let mut analysis = Self {
+            bytecode,
+            states: vec![None; bytecode.len()],
+            results: HashMap::new(),
+            function_values: HashMap::new(),
+            variables: HashMap::new(),
+            captured: HashMap::new(),
+            fields: HashMap::new(),
+            tables_changed: false,
+        };
+
+        // The program starts in the global frame and every function body with an empty frame
+        let mut roots = vec![(0, GLOBAL_SCOPE)];
+        for (addr, instruction) in bytecode.iter().enumerate() {
+            match instruction {
+                OpCode::Call { addr: entry, .. } => roots.push((*entry, *entry)),
+                OpCode::MakeFunction { addr: entry, params, .. } | OpCode::MakeLambda { addr: entry, params } => {
+                    roots.push((*entry, *entry));
+                    analysis.function_values.insert(addr, (*entry, params.len()));
+                }
+                _ => {}
+            }
+        }
+        let mut worklist = Vec::new();
+        for (addr, owner) in roots {
+            let state = FrameState { slots: Vec::new(), owners: BTreeSet::from([owner]) };
+            analysis.flow_into(addr, &state, &mut worklist);
+        }
+
+        // Variables, fields and function results are tracked for the whole program, so a
+        // change to any of them means revisiting everything reached so far
+        loop {
+            while let Some(addr) = worklist.pop() {
+                for (target, state) in analysis.transfer(addr)? {
+                    analysis.flow_into(target, &state, &mut worklist);
+                }
+            }
+            if !std::mem::take(&mut analysis.tables_changed) {
+                break;
+            }
+            worklist = (0..bytecode.len()).filter(|&addr| analysis.states[addr].is_some()).collect();
+        }
+
+        analysis.validate()?;
+        Ok(analysis)
+    }
+
+    // Stack depth before the instruction at `addr`, or None if it is never executed
+    pub fn depth(&self, addr: usize) -> Option<u32> {
+        self.states.get(addr)?.as_ref().map(|state| state.slots.len() as u32)
+    }
+
+    // Number of values the function executing the RET at `addr` returns
+    pub fn return_count(&self, addr: usize) -> Option<u32> {
+        let state = self.states.get(addr)?.as_ref()?;
+        state.owners.iter()
+            .find_map(|owner| self.results.get(owner))
+            .map(|results| results.len() as u32)
+    }
+
+    // Stack depth right after the instruction at `addr` finishes (and, for calls, the
+    // callee has returned). None if execution never continues to the next instruction.
+    pub fn depth_after(&self, addr: usize) -> Option<u32> {
+        let depth = self.depth(addr)?;
+        match &self.bytecode[addr] {
+            OpCode::Halt | OpCode::Ret | OpCode::RetN(_) | OpCode::Throw | OpCode::Rethrow | OpCode::Jmp(_) => None,
+            OpCode::Call { addr: entry, params } => {
+                let results = self.results.get(entry)?;
+                Some(depth - params.len() as u32 + results.len() as u32)
+            }
+            OpCode::CallFunction => {
+                let (params, results) = self.call_function_effect(addr)?;
+                Some(depth - 1 - params as u32 + results as u32)
+            }
+            OpCode::Jz(_) => Some(depth - 1),
+            instruction => {
+                let (pops, pushes) = stack_effect(instruction);
+                Some(depth - pops + pushes)
+            }
+        }
+    }
+
+    fn flow_into(&mut self, addr: usize, state: &FrameState, worklist: &mut Vec<usize>) {
+        // Running off the end of the program simply stops execution
+        if addr >= self.bytecode.len() {
+            return;
+        }
+        let changed = match &mut self.states[addr] {
+            Some(existing) => existing.merge(state),
+            slot @ None => {
+                *slot = Some(state.clone());
+                true
+            }
+        };
+        if changed {
+            worklist.push(addr);
+        }
+    }
+
+    // Successor instructions of `addr` together with the state they start in
+    fn transfer(&mut self, addr: usize) -> VMResult<Vec<(usize, FrameState)>> {
+        let Some(FrameState { mut slots, owners }) = self.states[addr].clone() else {
+            return Ok(vec![]);
+        };
+        let instruction = &self.bytecode[addr];
+        let underflow = || VMError::StackUnderflow(format!("{:?} at instruction {}", instruction, addr));
+        macro_rules! pop {
+            () => { slots.pop().ok_or_else(underflow)? };
+        }
+
+        let next = |slots: Vec<FunctionSet>| vec![(addr + 1, FrameState { slots, owners: owners.clone() })];
+        let successors = match instruction {
+            OpCode::Halt => vec![],
+            OpCode::Ret | OpCode::RetN(_) => {
+                for &owner in owners.iter().filter(|&&owner| owner != GLOBAL_SCOPE) {
+                    self.add_results(owner, &slots);
+                }
+                vec![]
+            }
+            // Execution continues at the enclosing handler, which TRY already accounts for
+            OpCode::Throw | OpCode::Rethrow => {
+                pop!();
+                vec![]
+            }
+            OpCode::Jmp(target) => vec![(*target, FrameState { slots, owners: owners.clone() })],
+            OpCode::Jz(target) => {
+                pop!();
+                let mut successors = next(slots.clone());
+                successors.push((*target, FrameState { slots, owners: owners.clone() }));
+                successors
+            }
+            // The catch block starts with the exception pushed on the TRY-time stack. A
+            // FINALLY handler keeps the exception to itself.
+            OpCode::Try { catch_addr } => {
+                let mut catch_slots = slots.clone();
+                if !matches!(self.bytecode.get(*catch_addr), Some(OpCode::Finally)) {
+                    catch_slots.push(FunctionSet::new());
+                }
+                let mut successors = next(slots);
+                successors.push((*catch_addr, FrameState { slots: catch_slots, owners: owners.clone() }));
+                successors
+            }
+            OpCode::Call { addr: entry, params } => {
+                if slots.len() < params.len() {
+                    return Err(underflow());
+                }
+                let args = slots.split_off(slots.len() - params.len());
+                for (param, values) in params.iter().zip(args) {
+                    self.add_variable(*entry, param_annotation(param).0, &values);
+                }
+                match self.results.get(entry) {
+                    Some(results) => {
+                        slots.extend(results.iter().cloned());
+                        next(slots)
+                    }
+                    // Continues once the callee is seen returning
+                    None => vec![],
+                }
+            }
+            OpCode::CallFunction => {
+                let candidates = pop!();
+                let mut effect = None;
+                let mut returned: Option<Vec<FunctionSet>> = None;
+                for site in &candidates {
+                    let Some(&(entry, param_count)) = self.function_values.get(site) else { continue };
+                    let params = match &self.bytecode[*site] {
+                        OpCode::MakeFunction { params, .. } | OpCode::MakeLambda { params, .. } => params.clone(),
+                        _ => continue,
+                    };
+                    if slots.len() < param_count {
+                        return Err(underflow());
+                    }
+                    for (param, values) in params.iter().zip(&slots[slots.len() - param_count..]) {
+                        self.add_variable(entry, param_annotation(param).0, values);
+                    }
+                    if let Some(results) = self.results.get(&entry) {
+                        // Conflicting effects are reported by validate()
+                        match effect {
+                            Some((p, r)) if (p as i64 - r as i64) != (param_count as i64 - results.len() as i64) => {
+                                return Ok(vec![]);
+                            }
+                            _ => effect = Some((param_count, results.len())),
+                        }
+                        returned = Some(match returned {
+                            Some(existing) => merge_slots(&existing, results),
+                            None => results.clone(),
+                        });
+                    }
+                }
+                match (effect, returned) {
+                    (Some((param_count, _)), Some(results)) => {
+                        slots.truncate(slots.len() - param_count);
+                        slots.extend(results);
+                        next(slots)
+                    }
+                    _ => vec![],
+                }
+            }
+            OpCode::Load(name) => {
+                let values = self.load_variable(&owners, name);
+                slots.push(values);
+                next(slots)
+            }
+            OpCode::Store(name) => {
+                let values = pop!();
+                for &owner in &owners {
+                    self.add_variable(owner, name, &values);
+                }
+                next(slots)
+            }
+            // Every bound part may hold any function value the container does
+            OpCode::Destructure(pattern) => {
+                let values = pop!();
+                for name in pattern.names() {
+                    for &owner in &owners {
+                        self.add_variable(owner, name, &values);
+                    }
+                }
+                next(slots)
+            }
+            OpCode::Capture(name) => {
+                let values = self.load_variable(&owners, name);
+                let entry = self.captured.entry(name.clone()).or_default();
+                if !values.is_subset(entry) {
+                    entry.extend(values);
+                    self.tables_changed = true;
+                }
+                next(slots)
+            }
+            OpCode::Dup => {
+                let top = slots.last().cloned().ok_or_else(underflow)?;
+                slots.push(top);
+                next(slots)
+            }
+            OpCode::MakeFunction { .. } | OpCode::MakeLambda { .. } => {
+                slots.push(FunctionSet::from([addr]));
+                next(slots)
+            }
+            // Containers carry the function values stored in them
+            OpCode::MakeList(count) => {
+                if slots.len() < *count {
+                    return Err(underflow());
+                }
+                let elements = slots.split_off(slots.len() - count);
+                slots.push(elements.into_iter().flatten().collect());
+                next(slots)
+            }
+            OpCode::Index => {
+                pop!();
+                let list = pop!();
+                slots.push(list);
+                next(slots)
+            }
+            OpCode::SetField(name) => {
+                let value = pop!();
+                let object = pop!();
+                let field = self.fields.entry(name.clone()).or_default();
+                if !value.is_subset(field) {
+                    field.extend(value.iter().copied());
+                    self.tables_changed = true;
+                }
+                slots.push(object.union(&value).copied().collect());
+                next(slots)
+            }
+            OpCode::GetField(name) => {
+                pop!();
+                slots.push(self.fields.get(name).cloned().unwrap_or_default());
+                next(slots)
+            }
+            _ => {
+                let (pops, pushes) = stack_effect(instruction);
+                if slots.len() < pops as usize {
+                    return Err(underflow());
+                }
+                slots.truncate(slots.len() - pops as usize);
+                slots.extend((0..pushes).map(|_| FunctionSet::new()));
+                next(slots)
+            }
+        };
+        Ok(successors)
+    }
+
+    // LOAD looks in the current frame, then the global one; closures also see captures
+    fn load_variable(&self, owners: &BTreeSet<usize>, name: &str) -> FunctionSet {
+        let mut values = FunctionSet::new();
+        for owner in owners.iter().chain([&GLOBAL_SCOPE]) {
+            if let Some(stored) = self.variables.get(&(*owner, name.to_string())) {
+                values.extend(stored);
+            }
+        }
+        if let Some(captured) = self.captured.get(name) {
+            values.extend(captured);
+        }
+        values
+    }
+
+    fn add_variable(&mut self, owner: usize, name: &str, values: &FunctionSet) {
+        let stored = self.variables.entry((owner, name.to_string())).or_default();
+        if !values.is_subset(stored) {
+            stored.extend(values.iter().copied());
+            self.tables_changed = true;
+        }
+    }
+
+    fn add_results(&mut self, entry: usize, slots: &[FunctionSet]) {
+        let merged = match self.results.get(&entry) {
+            Some(existing) => merge_slots(existing, slots),
+            None => slots.to_vec(),
+        };
+        if self.results.get(&entry) != Some(&merged) {
+            self.results.insert(entry, merged);
+            self.tables_changed = true;
+        }
+    }
+
+    // (params, results) of the functions a CALL_FUNCTION may call, once any of them returns
+    fn call_function_effect(&self, addr: usize) -> Option<(usize, usize)> {
+        let state = self.states[addr].as_ref()?;
+        state.slots.last()?.iter()
+            .filter_map(|site| self.function_values.get(site))
+            .find_map(|&(entry, params)| self.results.get(&entry).map(|results| (params, results.len())))
+    }
+
+    fn validate(&self) -> VMResult<()> {
+        for (addr, instruction) in self.bytecode.iter().enumerate() {
+            let Some(state) = &self.states[addr] else { continue };
+            match instruction {
+                OpCode::CallFunction => {
+                    let candidates = state.slots.last().cloned().unwrap_or_default();
+                    if candidates.is_empty() {
+                        return Err(VMError::UnsupportedOperation(format!(
+                            "cannot tell which function CALL_FUNCTION at instruction {} calls", addr
+                        )));
+                    }
+                    let effects: BTreeSet<i64> = candidates.iter()
+                        .filter_map(|site| self.function_values.get(site))
+                        .filter_map(|&(entry, params)| self.results.get(&entry).map(|r| r.len() as i64 - params as i64))
+                        .collect();
+                    if effects.len() > 1 {
+                        return Err(VMError::UnsupportedOperation(format!(
+                            "CALL_FUNCTION at instruction {} may call functions with different stack effects", addr
+                        )));
+                    }
+                }
+                OpCode::Ret | OpCode::RetN(_) => {
+                    let counts: BTreeSet<usize> = state.owners.iter()
+                        .filter_map(|owner| self.results.get(owner))
+                        .map(|results| results.len())
+                        .collect();
+                    if counts.len() > 1 {
+                        return Err(VMError::UnsupportedOperation(format!(
+                            "RET at instruction {} is shared by functions returning different numbers of values", addr
+                        )));
+                    }
+                    if let (OpCode::RetN(declared), Some(&left)) = (instruction, counts.first()) {
+                        if left != *declared {
+                            return Err(VMError::ReturnCountMismatch { declared: *declared, left });
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+// A stack-balance problem found by check_stack_balance
+#[derive(Debug, Clone, PartialEq)]
+pub enum StackProblem {
+    // The instruction at `addr` pops more values than its frame holds
+    Underflow { addr: usize, needed: u32, depth: u32 },
+    // `addr` is reached with `depth` values from `from`, but with `expected` along another path
+    DepthMismatch { addr: usize, from: usize, expected: u32, depth: u32 },
+    // `RET n` at `addr` is reached with `depth` values in its function's frame instead of n
+    ReturnCount { addr: usize, declared: u32, depth: u32 },
+}
+
+impl StackProblem {
+    pub fn addr(&self) -> usize {
+        match self {
+            StackProblem::Underflow { addr, .. } | StackProblem::DepthMismatch { addr, .. }
+            | StackProblem::ReturnCount { addr, .. } => *addr,
+        }
+    }
+}
+
+// Stack depth as far as the checker can tell. It gives up after CALL_FUNCTION, whose
+// callee isn't known, and once two paths have disagreed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Depth {
+    Known(u32),
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct CheckState {
+    depth: Depth,
+    owners: BTreeSet<usize>,
+}
+
+// Abstract stack depth of every reachable instruction, tracked only to report problems.
+// Unlike StackAnalysis it accepts any program and carries on past the first problem.
+struct StackCheck<'a> {
+    bytecode: &'a [OpCode],
+    states: Vec<Option<CheckState>>,
+    results: HashMap<usize, Depth>,   // function entry -> values left at RET
+    problems: BTreeMap<usize, StackProblem>,
+    results_changed: bool,
+}
+
+// Follows every path through the program (and each function body, starting from an
+// empty frame) and reports instructions that would underflow the stack, and joins
+// where the paths leading in leave different numbers of values behind.
+pub fn check_stack_balance(bytecode: &[OpCode]) -> Vec<StackProblem> {
+    let mut check = StackCheck {
+        bytecode,
+        states: vec![None; bytecode.len()],
+        results: HashMap::new(),
+        problems: BTreeMap::new(),
+        results_changed: false,
+    };
+
+    let mut worklist = Vec::new();
+    let mut roots = vec![(0, GLOBAL_SCOPE)];
+    for instruction in bytecode {
+        if let OpCode::Call { addr, .. } | OpCode::MakeFunction { addr, .. } | OpCode::MakeLambda { addr, .. } = instruction {
+            roots.push((*addr, *addr));
+        }
+    }
+    for (addr, owner) in roots {
+        let state = CheckState { depth: Depth::Known(0), owners: BTreeSet::from([owner]) };
+        check.flow_into(addr, addr, &state, &mut worklist);
+    }
+
+    // A CALL only continues once its callee is seen returning
+    loop {
+        while let Some(addr) = worklist.pop() {
+            for (target, state) in check.transfer(addr) {
+                check.flow_into(target, addr, &state, &mut worklist);
+            }
+        }
+        if !std::mem::take(&mut check.results_changed) {
+            break;
+        }
+        worklist = (0..bytecode.len()).filter(|&addr| check.states[addr].is_some()).collect();
+    }
+    check.problems.into_values().collect()
+}
+
+impl StackCheck<'_> {
+    fn flow_into(&mut self, addr: usize, from: usize, incoming: &CheckState, worklist: &mut Vec<usize>) {
+        if addr >= self.bytecode.len() {
+            return;
+        }
+        let Some(existing) = &mut self.states[addr] else {
+            self.states[addr] = Some(incoming.clone());
+            worklist.push(addr);
+            return;
+        };
+        let mut merged = CheckState {
+            depth: existing.depth,
+            owners: existing.owners.union(&incoming.owners).copied().collect(),
+        };
+        if let (Depth::Known(expected), Depth::Known(depth)) = (existing.depth, incoming.depth) {
+            if expected != depth {
+                self.problems.entry(addr).or_insert(StackProblem::DepthMismatch { addr, from, expected, depth });
+                merged.depth = Depth::Unknown;
+            }
+        } else {
+            merged.depth = Depth::Unknown;
+        }
+        if merged != *existing {
+            *existing = merged;
+            worklist.push(addr);
+        }
+    }
+
+    fn transfer(&mut self, addr: usize) -> Vec<(usize, CheckState)> {
+        let Some(CheckState { depth, owners }) = self.states[addr].clone() else {
+            return vec![];
+        };
+        let at = |depth: Depth| CheckState { depth, owners: owners.clone() };
+        let instruction = &self.bytecode[addr];
+
+        // DUP needs a value without consuming it
+        let (pops, pushes) = match instruction {
+            OpCode::Dup => (1, 2),
+            OpCode::Throw | OpCode::Rethrow | OpCode::CallFunction => (1, 0),
+            // A variadic call takes at least its fixed parameters and the count
+            OpCode::Call { params, .. } => (params.len() as u32, 0),
+            OpCode::Ret | OpCode::RetN(_) => (0, 0),
+            instruction => stack_effect(instruction),
+        };
+        let after = match depth {
+            Depth::Known(held) if held < pops => {
+                self.problems.entry(addr).or_insert(StackProblem::Underflow { addr, needed: pops, depth: held });
+                return vec![];
+            }
+            Depth::Known(held) => Depth::Known(held - pops + pushes),
+            Depth::Unknown => Depth::Unknown,
+        };
+
+        match instruction {
+            OpCode::Halt | OpCode::Throw | OpCode::Rethrow => vec![],
+            OpCode::Ret | OpCode::RetN(_) => {
+                if let (OpCode::RetN(declared), Depth::Known(held)) = (instruction, depth) {
+                    if held as usize != *declared {
+                        self.problems.entry(addr).or_insert(StackProblem::ReturnCount { addr, declared: *declared as u32, depth: held });
+                    }
+                }
+                for &owner in owners.iter().filter(|&&owner| owner != GLOBAL_SCOPE) {
+                    self.add_result(owner, depth);
+                }
+                vec![]
+            }
+            OpCode::Jmp(target) => vec![(*target, at(after))],
+            OpCode::Jz(target) => vec![(addr + 1, at(after)), (*target, at(after))],
+            // The catch block starts with the exception pushed, unless it is a FINALLY
+            OpCode::Try { catch_addr } => {
+                let caught = match (depth, self.bytecode.get(*catch_addr)) {
+                    (Depth::Known(held), Some(handler)) if !matches!(handler, OpCode::Finally) => Depth::Known(held + 1),
+                    _ => depth,
+                };
+                vec![(addr + 1, at(after)), (*catch_addr, at(caught))]
+            }
+            // Like CALL_FUNCTION, the number of values taken isn't known statically
+            OpCode::Call { params, .. } if variadic_params(params).is_some() => vec![(addr + 1, at(Depth::Unknown))],
+            OpCode::Call { addr: entry, .. } => match (after, self.results.get(entry)) {
+                (Depth::Known(held), Some(Depth::Known(returned))) => vec![(addr + 1, at(Depth::Known(held + returned)))],
+                (_, Some(_)) => vec![(addr + 1, at(Depth::Unknown))],
+                (_, None) => vec![],
+            },
+            OpCode::CallFunction => vec![(addr + 1, at(Depth::Unknown))],
+            _ => vec![(addr + 1, at(after))],
+        }
+    }
+
+    fn add_result(&mut self, entry: usize, depth: Depth) {
+        let merged = match self.results.get(&entry) {
+            None => depth,
+            Some(existing) if *existing == depth => return,
+            Some(_) => Depth::Unknown,
+        };
+        if self.results.get(&entry) != Some(&merged) {
+            self.results.insert(entry, merged);
+            self.results_changed = true;
+        }
+    }
+}
+
+// Values popped and pushed by an instruction. CALL, CALL_FUNCTION and RET depend on the
+// function involved and are handled by the analysis itself.
+pub fn stack_effect(instruction: &OpCode) -> (u32, u32) {
+    match instruction {
+        OpCode::PushInt(_) | OpCode::PushFloat(_) | OpCode::PushStr(_) | OpCode::PushBool(_) |
+        OpCode::True | OpCode::False | OpCode::Null | OpCode::Dup | OpCode::Load(_) |
+        OpCode::MakeObject | OpCode::MakeFunction { .. } | OpCode::MakeLambda { .. } => (0, 1),
+
+        OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div |
+        OpCode::AddF | OpCode::SubF | OpCode::MulF | OpCode::DivF | OpCode::Concat |
+        OpCode::Eq | OpCode::Ne | OpCode::Lt | OpCode::Le | OpCode::Gt | OpCode::Ge |
+        OpCode::EqF | OpCode::NeF | OpCode::LtF | OpCode::LeF | OpCode::GtF | OpCode::GeF |
+        OpCode::And | OpCode::Or | OpCode::Index | OpCode::SetField(_) | OpCode::Pow => (2, 1),
+
+        OpCode::Neg | OpCode::Sqrt | OpCode::Abs | OpCode::Floor | OpCode::Ceil | OpCode::Round |
+        OpCode::Not | OpCode::Len | OpCode::GetField(_) | OpCode::HasField(_) |
+        OpCode::DeleteField(_) | OpCode::Keys | OpCode::Values | OpCode::Entries |
+        OpCode::ExceptionMessage | OpCode::ExceptionTrace => (1, 1),
+
+        OpCode::Print | OpCode::PrintRaw | OpCode::PrintErr | OpCode::DebugPrint | OpCode::Assert(_) | OpCode::Store(_) | OpCode::Jz(_) | OpCode::Throw | OpCode::Rethrow | OpCode::SetFieldMut { .. } => (1, 0),
+        OpCode::MakeList(count) => (*count as u32, 1),
+
+        // Standard library
+        OpCode::ReadLine | OpCode::ReadChar | OpCode::ReadInput | OpCode::GetArgs | OpCode::EnvVars | OpCode::GetTime | OpCode::GetCwd => (0, 1),
+        OpCode::ReadFile | OpCode::FileExists | OpCode::FileSize | OpCode::ListDir | OpCode::ListDirRecursive | OpCode::Stat | OpCode::ReadBytes |
+        OpCode::PathBasename | OpCode::PathDirname | OpCode::GetEnv | OpCode::HttpGet | OpCode::TcpListen | OpCode::UdpBind | OpCode::DnsResolve |
+        OpCode::AsyncRead | OpCode::Await | OpCode::StreamCreate | OpCode::StreamJsonLines | OpCode::StreamCsv | OpCode::StreamRead | OpCode::StreamClose |
+        OpCode::JsonParse | OpCode::JsonStringify | OpCode::CsvParse | OpCode::CsvWrite |
+        OpCode::Compress | OpCode::Decompress | OpCode::Hash | OpCode::DbConnect => (1, 1),
+        OpCode::Exec | OpCode::ExecCapture | OpCode::FormatTime | OpCode::ParseTime | OpCode::HttpPost | OpCode::TcpConnect |
+        OpCode::TcpSend | OpCode::TcpRecv | OpCode::UdpRecv | OpCode::AsyncWrite |
+        OpCode::StreamWrite | OpCode::Encrypt | OpCode::Decrypt | OpCode::DbQuery | OpCode::DbExec | OpCode::PathJoin => (2, 1),
+        OpCode::UdpSend => (4, 1),
+        OpCode::WeakRef | OpCode::WeakGet => (1, 1),
+        OpCode::HttpServe | OpCode::HttpAccept => (1, 1),
+        OpCode::RandomInt => (2, 1),
+        OpCode::RandomFloat | OpCode::Uuid => (0, 1),
+        OpCode::RandomSeed => (1, 0),
+        OpCode::HttpRespond => (3, 0),
+        OpCode::RegexMatch | OpCode::RegexFind => (2, 1),
+        OpCode::RegexReplace => (3, 1),
+        OpCode::WriteFile | OpCode::AppendFile | OpCode::WriteBytes | OpCode::SetEnv => (2, 0),
+        OpCode::DeleteFile | OpCode::SetCwd | OpCode::Exit | OpCode::Sleep => (1, 0),
+
+        // Concurrency operations, as implemented by TinyProc
+        OpCode::Spawn | OpCode::SpawnRegistered(_) | OpCode::SendNamed(_) => (1, 1),
+        OpCode::Send(_) | OpCode::TrapExit | OpCode::ExportAs(_) | OpCode::Destructure(_) => (1, 0),
+        OpCode::ProcCall => (2, 1),
+        OpCode::ProcReply | OpCode::PutDict | OpCode::TableDelete => (2, 0),
+        OpCode::GetDict | OpCode::CancelTimer => (1, 1),
+        OpCode::SendAfter => (3, 1),
+        OpCode::Broadcast => (2, 1),
+        OpCode::EraseDict => (1, 0),
+        OpCode::TableNew | OpCode::SelfPid | OpCode::ParentPid => (0, 1),
+        OpCode::TableInsert => (3, 0),
+        OpCode::TableLookup => (2, 1),
+        OpCode::Receive | OpCode::ReceiveMatch(_) | OpCode::ReceiveMatchAfter(..) | OpCode::Monitor(_) | OpCode::Demonitor(_) |
+        OpCode::Link(_) | OpCode::Unlink(_) | OpCode::Register(_) | OpCode::Unregister(_) |
+        OpCode::Whereis(_) | OpCode::StartSupervisor | OpCode::SuperviseChild(_) |
+        OpCode::RestartChild(_) => (0, 1),
+
+        OpCode::Jmp(_) | OpCode::Halt | OpCode::Delete(_) | OpCode::DumpScope | OpCode::Capture(_) |
+        OpCode::Try { .. } | OpCode::Catch | OpCode::EndTry | OpCode::Finally | OpCode::EndFinally | OpCode::Import(_) |
+        OpCode::ImportAs(..) | OpCode::ImportFrom(..) | OpCode::Export(_) | OpCode::ReloadModule(_) |
+        OpCode::Yield | OpCode::Call { .. } | OpCode::CallFunction | OpCode::Ret | OpCode::RetN(_) => (0, 0),
+    }
+}
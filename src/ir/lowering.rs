@@ -1,524 +1,271 @@
-use crate::ir::{RegBlock, RegInstr, RegValue, RegId, StackState};
-use crate::vm::{OpCode, Value, VMError, VMResult};
-use std::collections::HashMap;
+use crate::ir::{RegBlock, RegInstr, RegValue, RegId};
+use crate::ir::analysis::{stack_effect, StackAnalysis};
+use crate::vm::{OpCode, Value, VMResult};
 
+// Lowering gives every operand stack slot its own register: the value at stack depth `n`
+// (counted from the start of the current function's frame) lives in register `n`. The
+// stack analysis works out the depth before each reachable instruction and how many values
+// each function leaves behind. The register VM runs each call in a register window
+// starting at the caller's first argument, so results end up exactly where the stack VM
+// would have left them.
 pub struct StackToRegisterLowering {
     block: RegBlock,
-    stack: StackState,
-    label_map: HashMap<usize, usize>, // bytecode address -> IR instruction index
 }
 
 impl StackToRegisterLowering {
     pub fn new() -> Self {
         Self {
             block: RegBlock::new(),
-            stack: StackState::new(),
-            label_map: HashMap::new(),
         }
     }
-    
+
     pub fn lower(bytecode: &[OpCode]) -> VMResult<RegBlock> {
         let mut lowering = Self::new();
         lowering.process_bytecode(bytecode)?;
         Ok(lowering.block)
     }
-    
+
     fn process_bytecode(&mut self, bytecode: &[OpCode]) -> VMResult<()> {
-        // First pass: build address mapping by simulating instruction generation
-        let mut temp_instruction_count = 0;
+        // First pass: stack depth of every reachable instruction
+        let analysis = StackAnalysis::run(bytecode)?;
+
+        // Second pass: translate instructions, remembering where each one starts. Branches
+        // into code that expects a shallower stack go through a trampoline that moves the
+        // live values down first.
+        let mut trampolines = Vec::new();
         for (addr, instruction) in bytecode.iter().enumerate() {
-            self.label_map.insert(addr, temp_instruction_count);
-            
-            // Count how many IR instructions this bytecode instruction will generate
-            temp_instruction_count += match instruction {
-                OpCode::PushInt(_) | OpCode::PushFloat(_) | OpCode::PushStr(_) | 
-                OpCode::PushBool(_) => 1,
-                
-                OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div |
-                OpCode::AddF | OpCode::SubF | OpCode::MulF | OpCode::DivF |
-                OpCode::Eq | OpCode::Ne | OpCode::Lt | OpCode::Le | OpCode::Gt | OpCode::Ge |
-                OpCode::EqF | OpCode::NeF | OpCode::LtF | OpCode::LeF | OpCode::GtF | OpCode::GeF => 1,
-                
-                OpCode::Print | OpCode::Dup => 1,
-                
-                OpCode::Jmp(_) | OpCode::Jz(_) => 1,
-                
-                OpCode::Halt => 1,
-                
-                _ => 1, // Conservative estimate for unhandled instructions
+            self.block.addr_map.push(self.block.instructions.len());
+            let Some(depth) = analysis.depth(addr) else {
+                // Not reachable from the entry point or any function, so never executed
+                self.block.add_instruction(RegInstr::Nop);
+                continue;
             };
-        }
-        
-        // Second pass: translate instructions
-        for (addr, instruction) in bytecode.iter().enumerate() {
-            self.translate_instruction(addr, instruction)?;
-        }
-        
-        Ok(())
-    }
-    
-    fn translate_instruction(&mut self, _addr: usize, instruction: &OpCode) -> VMResult<()> {
-        match instruction {
-            // Push operations - allocate register and load constant
-            OpCode::PushInt(n) => {
-                let reg = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Mov(reg, RegValue::Const(Value::Int(*n))));
-                self.stack.push(reg);
-            }
-            
-            OpCode::PushFloat(f) => {
-                let reg = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Mov(reg, RegValue::Const(Value::Float(*f))));
-                self.stack.push(reg);
-            }
-            
-            OpCode::PushStr(s) => {
-                let reg = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Mov(reg, RegValue::Const(Value::Str(s.clone()))));
-                self.stack.push(reg);
-            }
-            
-            OpCode::PushBool(b) => {
-                let reg = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Mov(reg, RegValue::Const(Value::Bool(*b))));
-                self.stack.push(reg);
-            }
-            
-            OpCode::True => {
-                let reg = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Mov(reg, RegValue::Const(Value::Bool(true))));
-                self.stack.push(reg);
-            }
-            
-            OpCode::False => {
-                let reg = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Mov(reg, RegValue::Const(Value::Bool(false))));
-                self.stack.push(reg);
-            }
-            
-            OpCode::Null => {
-                let reg = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Mov(reg, RegValue::Const(Value::Null)));
-                self.stack.push(reg);
-            }
-            
-            // Binary arithmetic operations
-            OpCode::Add => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::Add(dst, src1, src2));
-                self.stack.push(dst);
-            }
-            
-            OpCode::AddF => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::AddF(dst, src1, src2));
-                self.stack.push(dst);
-            }
-            
-            OpCode::Sub => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::Sub(dst, src1, src2));
-                self.stack.push(dst);
-            }
-            
-            OpCode::SubF => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::SubF(dst, src1, src2));
-                self.stack.push(dst);
-            }
-            
-            OpCode::Mul => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::Mul(dst, src1, src2));
-                self.stack.push(dst);
-            }
-            
-            OpCode::MulF => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::MulF(dst, src1, src2));
-                self.stack.push(dst);
-            }
-            
-            OpCode::Div => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::Div(dst, src1, src2));
-                self.stack.push(dst);
-            }
-            
-            OpCode::DivF => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::DivF(dst, src1, src2));
-                self.stack.push(dst);
-            }
-            
-            OpCode::Concat => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::Concat(dst, src1, src2));
-                self.stack.push(dst);
-            }
-            
-            // Comparison operations
-            OpCode::Eq => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::Eq(dst, src1, src2));
-                self.stack.push(dst);
-            }
-            
-            OpCode::Ne => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::Ne(dst, src1, src2));
-                self.stack.push(dst);
-            }
-            
-            OpCode::Lt => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::Lt(dst, src1, src2));
-                self.stack.push(dst);
-            }
-            
-            OpCode::Le => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::Le(dst, src1, src2));
-                self.stack.push(dst);
-            }
-            
-            OpCode::Gt => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::Gt(dst, src1, src2));
-                self.stack.push(dst);
+            match instruction {
+                OpCode::Jmp(target) => {
+                    self.shift_stack(depth, analysis.depth(*target));
+                    self.block.add_instruction(RegInstr::Jmp(*target));
+                }
+                OpCode::Ret | OpCode::RetN(_) => {
+                    self.shift_stack(depth, analysis.return_count(addr));
+                    self.block.add_instruction(RegInstr::Ret);
+                }
+                OpCode::Jz(target) => {
+                    trampolines.push((self.block.instructions.len(), depth - 1, *target));
+                    self.translate_instruction(depth, instruction);
+                }
+                // The exception arrives in the register at the TRY-time depth, unless a
+                // FINALLY block holds on to it
+                OpCode::Try { catch_addr } => {
+                    let holds_exception = !matches!(bytecode.get(*catch_addr), Some(OpCode::Finally));
+                    trampolines.push((self.block.instructions.len(), depth + holds_exception as u32, *catch_addr));
+                    self.translate_instruction(depth, instruction);
+                }
+                _ => self.translate_instruction(depth, instruction),
             }
-            
-            OpCode::Ge => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::Ge(dst, src1, src2));
-                self.stack.push(dst);
+            if let Some(after) = analysis.depth_after(addr) {
+                self.shift_stack(after, analysis.depth(addr + 1));
             }
-            
-            // Logical operations
-            OpCode::And => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::And(dst, src1, src2));
-                self.stack.push(dst);
+        }
+
+        // Third pass: point control flow at IR instruction indices. Function values keep
+        // their bytecode addresses, which the register VM resolves through `addr_map`.
+        let end = self.block.instructions.len();
+        let addr_map = self.block.addr_map.clone();
+        let resolve = |target: usize| addr_map.get(target).copied().unwrap_or(end);
+        for instruction in &mut self.block.instructions {
+            match instruction {
+                RegInstr::Jmp(target) | RegInstr::Jz(_, target) |
+                RegInstr::Call(_, target, _) | RegInstr::Try(target, _) => *target = resolve(*target),
+                _ => {}
             }
-            
-            OpCode::Or => {
-                let (dst, src1, src2) = self.pop_binary_op()?;
-                self.block.add_instruction(RegInstr::Or(dst, src1, src2));
-                self.stack.push(dst);
+        }
+
+        for (index, from_depth, target) in trampolines {
+            let to_depth = analysis.depth(target);
+            if to_depth.is_none_or(|to_depth| to_depth >= from_depth) {
+                continue;
             }
-            
-            OpCode::Not => {
-                let src = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("NOT".to_string()))?;
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Not(dst, src));
-                self.stack.push(dst);
+            let start = self.block.instructions.len();
+            self.shift_stack(from_depth, to_depth);
+            self.block.add_instruction(RegInstr::Jmp(resolve(target)));
+            if let RegInstr::Jz(_, branch) | RegInstr::Try(branch, _) = &mut self.block.instructions[index] {
+                *branch = start;
             }
-            
+        }
+
+        // One spare register above the deepest slot for instructions that need a temporary
+        let max_depth = (0..bytecode.len()).filter_map(|addr| analysis.depth(addr)).max().unwrap_or(0);
+        self.block.register_count = max_depth + 2;
+        Ok(())
+    }
+
+    // Move the top `to_depth` of `from_depth` values down to the bottom of the frame
+    fn shift_stack(&mut self, from_depth: u32, to_depth: Option<u32>) {
+        let Some(to_depth) = to_depth else { return };
+        if from_depth <= to_depth {
+            return;
+        }
+        let dropped = from_depth - to_depth;
+        for reg in 0..to_depth {
+            self.block.add_instruction(RegInstr::Mov(reg, RegValue::Reg(reg + dropped)));
+        }
+    }
+
+    fn translate_instruction(&mut self, depth: u32, instruction: &OpCode) {
+        // Slot registers relative to the current stack depth: `next` is the first free slot,
+        // `top` holds the top of stack and `below` the value under it
+        let next = depth as RegId;
+        let top = next.wrapping_sub(1);
+        let below = next.wrapping_sub(2);
+
+        let instr = match instruction {
+            // Push operations - load a constant into the next free slot
+            OpCode::PushInt(n) => RegInstr::Mov(next, RegValue::Const(Value::Int(*n))),
+            OpCode::PushFloat(f) => RegInstr::Mov(next, RegValue::Const(Value::Float(*f))),
+            OpCode::PushStr(s) => RegInstr::Mov(next, RegValue::Const(Value::Str(s.clone()))),
+            OpCode::PushBool(b) => RegInstr::Mov(next, RegValue::Const(Value::Bool(*b))),
+            OpCode::True => RegInstr::Mov(next, RegValue::Const(Value::Bool(true))),
+            OpCode::False => RegInstr::Mov(next, RegValue::Const(Value::Bool(false))),
+            OpCode::Null => RegInstr::Mov(next, RegValue::Const(Value::Null)),
+            OpCode::Dup => RegInstr::Mov(next, RegValue::Reg(top)),
+
+            // Binary operations replace their two operands with the result
+            OpCode::Add => RegInstr::Add(below, below, top),
+            OpCode::AddF => RegInstr::AddF(below, below, top),
+            OpCode::Sub => RegInstr::Sub(below, below, top),
+            OpCode::SubF => RegInstr::SubF(below, below, top),
+            OpCode::Mul => RegInstr::Mul(below, below, top),
+            OpCode::MulF => RegInstr::MulF(below, below, top),
+            OpCode::Div => RegInstr::Div(below, below, top),
+            OpCode::DivF => RegInstr::DivF(below, below, top),
+            OpCode::Concat => RegInstr::Concat(below, below, top),
+            OpCode::Eq => RegInstr::Eq(below, below, top),
+            OpCode::Ne => RegInstr::Ne(below, below, top),
+            OpCode::Lt => RegInstr::Lt(below, below, top),
+            OpCode::Le => RegInstr::Le(below, below, top),
+            OpCode::Gt => RegInstr::Gt(below, below, top),
+            OpCode::Ge => RegInstr::Ge(below, below, top),
+            OpCode::EqF => RegInstr::EqF(below, below, top),
+            OpCode::NeF => RegInstr::NeF(below, below, top),
+            OpCode::LtF => RegInstr::LtF(below, below, top),
+            OpCode::LeF => RegInstr::LeF(below, below, top),
+            OpCode::GtF => RegInstr::GtF(below, below, top),
+            OpCode::GeF => RegInstr::GeF(below, below, top),
+            OpCode::And => RegInstr::And(below, below, top),
+            OpCode::Or => RegInstr::Or(below, below, top),
+            OpCode::Not => RegInstr::Not(top, top),
+
             // Variable operations
-            OpCode::Store(var_name) => {
-                let src = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("STORE".to_string()))?;
-                self.block.add_instruction(RegInstr::Store(var_name.clone(), src));
-            }
-            
-            OpCode::Load(var_name) => {
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Load(dst, var_name.clone()));
-                self.stack.push(dst);
-            }
-            
-            // Control flow
-            OpCode::Jmp(target) => {
-                let ir_target = self.label_map.get(target).copied().unwrap_or(*target);
-                self.block.add_instruction(RegInstr::Jmp(ir_target));
-            }
-            
-            OpCode::Jz(target) => {
-                let condition = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("JZ".to_string()))?;
-                let ir_target = self.label_map.get(target).copied().unwrap_or(*target);
-                self.block.add_instruction(RegInstr::Jz(condition, ir_target));
-            }
-            
-            OpCode::Call { addr, params: _ } => {
-                // Simplified call - in a full implementation, we'd handle parameters
-                let return_reg = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Call(Some(return_reg), *addr, vec![]));
-                self.stack.push(return_reg);
-            }
-            
-            OpCode::Ret => {
-                let return_value = self.stack.pop();
-                self.block.add_instruction(RegInstr::Ret(return_value));
-            }
-            
-            // I/O operations
-            OpCode::Print => {
-                let src = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("PRINT".to_string()))?;
-                self.block.add_instruction(RegInstr::Print(src));
-            }
-            
-            // Duplication
-            OpCode::Dup => {
-                let src = self.stack.peek().ok_or_else(|| VMError::StackUnderflow("DUP".to_string()))?;
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Mov(dst, RegValue::Reg(src)));
-                self.stack.push(dst);
-            }
-            
+            OpCode::Store(var_name) => RegInstr::Store(var_name.clone(), top),
+            OpCode::Load(var_name) => RegInstr::Load(next, var_name.clone()),
+            OpCode::Delete(var_name) => RegInstr::Delete(var_name.clone()),
+            OpCode::Destructure(pattern) => RegInstr::Destructure(top, pattern.clone()),
+
+            // Control flow (targets are bytecode addresses until patched)
+            OpCode::Jmp(target) => RegInstr::Jmp(*target),
+            OpCode::Jz(target) => RegInstr::Jz(top, *target),
+            OpCode::Call { addr, params } => {
+                RegInstr::Call(next - params.len() as RegId, *addr, params.clone())
+            }
+            OpCode::Ret | OpCode::RetN(_) => RegInstr::Ret,
+            OpCode::Halt => RegInstr::Halt,
+
+            // I/O and debugging
+            OpCode::Print => RegInstr::Print(top),
+            OpCode::DumpScope => RegInstr::DumpScope,
+
             // List operations
             OpCode::MakeList(count) => {
-                let mut elements = Vec::new();
-                for _ in 0..*count {
-                    elements.push(self.stack.pop().ok_or_else(|| VMError::StackUnderflow("MAKE_LIST".to_string()))?);
-                }
-                elements.reverse(); // Stack is LIFO, but we want elements in order
-                
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::MakeList(dst, elements));
-                self.stack.push(dst);
-            }
-            
-            OpCode::Len => {
-                let src = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("LEN".to_string()))?;
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Len(dst, src));
-                self.stack.push(dst);
-            }
-            
-            OpCode::Index => {
-                let index = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("INDEX".to_string()))?;
-                let container = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("INDEX".to_string()))?;
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Index(dst, container, index));
-                self.stack.push(dst);
-            }
-            
-            // Object operations
-            OpCode::MakeObject => {
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::MakeObject(dst));
-                self.stack.push(dst);
-            }
-            
-            // Terminal instruction
-            OpCode::Halt => {
-                self.block.add_instruction(RegInstr::Halt);
-            }
-            
-            
-            // Object operations
-            OpCode::SetField(field_name) => {
-                let value = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("SET_FIELD".to_string()))?;
-                let object = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("SET_FIELD".to_string()))?;
-                self.block.add_instruction(RegInstr::SetField(object, field_name.clone(), value));
+                let first = next - *count as RegId;
+                RegInstr::MakeList(first, (first..next).collect())
             }
-            
-            OpCode::GetField(field_name) => {
-                let object = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("GET_FIELD".to_string()))?;
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::GetField(dst, object, field_name.clone()));
-                self.stack.push(dst);
-            }
-            
-            OpCode::HasField(field_name) => {
-                let object = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("HAS_FIELD".to_string()))?;
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::HasField(dst, object, field_name.clone()));
-                self.stack.push(dst);
-            }
-            
-            OpCode::DeleteField(field_name) => {
-                let object = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("DELETE_FIELD".to_string()))?;
-                self.block.add_instruction(RegInstr::DeleteField(object, field_name.clone()));
-            }
-            
-            OpCode::Keys => {
-                let object = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("KEYS".to_string()))?;
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Keys(dst, object));
-                self.stack.push(dst);
-            }
-            
-            
-            OpCode::Delete(var_name) => {
-                self.block.add_instruction(RegInstr::Delete(var_name.clone()));
-            }
-            
-            // Function operations
-            OpCode::MakeFunction { addr, params } => {
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::MakeFunction(dst, *addr, params.clone()));
-                self.stack.push(dst);
-            }
-            
-            OpCode::CallFunction => {
-                let func_reg = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("CALL_FUNCTION".to_string()))?;
-                // For now, assume no arguments - this would need more sophisticated handling
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::CallFunction(Some(dst), func_reg, vec![]));
-                self.stack.push(dst);
-            }
-            
-            OpCode::MakeLambda { addr, params } => {
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::MakeLambda(dst, *addr, params.clone()));
-                self.stack.push(dst);
-            }
-            
-            OpCode::Capture(var_name) => {
-                let value = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("CAPTURE".to_string()))?;
-                self.block.add_instruction(RegInstr::Capture(var_name.clone(), value));
-            }
-            
+            OpCode::Len => RegInstr::Len(top, top),
+            OpCode::Index => RegInstr::Index(below, below, top),
+
+            // Object operations - the object stays in its slot and is updated in place
+            OpCode::MakeObject => RegInstr::MakeObject(next),
+            OpCode::SetField(field_name) => RegInstr::SetField(below, field_name.clone(), top),
+            OpCode::GetField(field_name) => RegInstr::GetField(top, top, field_name.clone()),
+            OpCode::HasField(field_name) => RegInstr::HasField(top, top, field_name.clone()),
+            OpCode::DeleteField(field_name) => RegInstr::DeleteField(top, field_name.clone()),
+            OpCode::Keys => RegInstr::Keys(top, top),
+            OpCode::Values => RegInstr::Values(top, top),
+            OpCode::SetFieldMut { var, field } => RegInstr::SetFieldMut(var.clone(), field.clone(), top),
+            OpCode::Entries => RegInstr::Entries(top, top),
+
+            // Function and closure operations
+            OpCode::MakeFunction { addr, params, returns } => RegInstr::MakeFunction(next, *addr, params.clone(), returns.clone()),
+            OpCode::MakeLambda { addr, params } => RegInstr::MakeLambda(next, *addr, params.clone()),
+            OpCode::Capture(var_name) => RegInstr::Capture(var_name.clone()),
+            OpCode::CallFunction => RegInstr::CallFunction(top),
+
             // Exception handling
-            OpCode::Try { catch_addr } => {
-                self.block.add_instruction(RegInstr::Try(*catch_addr));
-            }
-            
-            OpCode::Catch => {
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Catch(dst));
-                self.stack.push(dst);
-            }
-            
-            OpCode::Throw => {
-                let exception = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("THROW".to_string()))?;
-                self.block.add_instruction(RegInstr::Throw(exception));
-            }
-            
-            OpCode::EndTry => {
-                self.block.add_instruction(RegInstr::EndTry);
-            }
-            
+            OpCode::Try { catch_addr } => RegInstr::Try(*catch_addr, next),
+            OpCode::Catch => RegInstr::Catch(top),
+            OpCode::Throw => RegInstr::Throw(top),
+            OpCode::Rethrow => RegInstr::Rethrow(top),
+            OpCode::EndTry => RegInstr::EndTry,
+            OpCode::Finally => RegInstr::Finally,
+            OpCode::EndFinally => RegInstr::EndFinally,
+
             // Module system
-            OpCode::Import(module_path) => {
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Import(dst, module_path.clone()));
-                self.stack.push(dst);
-            }
-            
-            OpCode::Export(name) => {
-                let value = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("EXPORT".to_string()))?;
-                self.block.add_instruction(RegInstr::Export(name.clone(), value));
-            }
-            
-            // Concurrency operations - the key ones we need to support!
-            OpCode::Spawn => {
-                let function = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("SPAWN".to_string()))?;
-                let dst_pid = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Spawn(dst_pid, function));
-                self.stack.push(dst_pid);
-            }
-            
-            OpCode::Receive => {
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Receive(dst));
-                self.stack.push(dst);
-            }
-            
-            OpCode::ReceiveMatch(patterns) => {
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::ReceiveMatch(dst, patterns.clone()));
-                self.stack.push(dst);
-            }
-            
-            OpCode::Yield => {
-                self.block.add_instruction(RegInstr::Yield);
-            }
-            
+            OpCode::Import(module_path) => RegInstr::Import(module_path.clone()),
+            OpCode::Export(name) => RegInstr::Export(name.clone()),
+            OpCode::ExportAs(name) => RegInstr::ExportAs(name.clone(), top),
+
+            // Concurrency operations. Constant operands go through the spare slot above the stack.
+            OpCode::Spawn => RegInstr::Spawn(top, top),
+            OpCode::Receive => RegInstr::Receive(next),
+            OpCode::ReceiveMatch(patterns) => RegInstr::ReceiveMatch(next, patterns.clone()),
+            OpCode::Yield => RegInstr::Yield,
             OpCode::Send(target_pid) => {
-                let message = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("SEND".to_string()))?;
-                let pid_reg = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Mov(pid_reg, RegValue::Const(crate::vm::Value::Int(*target_pid as i64))));
-                self.block.add_instruction(RegInstr::Send(pid_reg, message));
+                self.block.add_instruction(RegInstr::Mov(next, RegValue::Const(Value::Int(*target_pid as i64))));
+                RegInstr::Send(next, top)
             }
-            
             OpCode::Monitor(target_pid) => {
-                let dst_ref = self.block.alloc_register();
-                let pid_reg = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Mov(pid_reg, RegValue::Const(crate::vm::Value::Int(*target_pid as i64))));
-                self.block.add_instruction(RegInstr::Monitor(dst_ref, pid_reg));
-                self.stack.push(dst_ref);
+                self.block.add_instruction(RegInstr::Mov(next, RegValue::Const(Value::Int(*target_pid as i64))));
+                RegInstr::Monitor(next, next)
             }
-            
             OpCode::Demonitor(monitor_ref) => {
-                let ref_reg = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Mov(ref_reg, RegValue::Const(crate::vm::Value::Str(monitor_ref.clone()))));
-                self.block.add_instruction(RegInstr::Demonitor(ref_reg));
+                self.block.add_instruction(RegInstr::Mov(next, RegValue::Const(Value::Str(monitor_ref.clone()))));
+                RegInstr::Demonitor(next)
             }
-            
             OpCode::Link(target_pid) => {
-                let pid_reg = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Mov(pid_reg, RegValue::Const(crate::vm::Value::Int(*target_pid as i64))));
-                self.block.add_instruction(RegInstr::Link(pid_reg));
+                self.block.add_instruction(RegInstr::Mov(next, RegValue::Const(Value::Int(*target_pid as i64))));
+                RegInstr::Link(next)
             }
-            
             OpCode::Unlink(target_pid) => {
-                let pid_reg = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Mov(pid_reg, RegValue::Const(crate::vm::Value::Int(*target_pid as i64))));
-                self.block.add_instruction(RegInstr::Unlink(pid_reg));
-            }
-            
-            OpCode::TrapExit => {
-                let enable_flag = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("TRAP_EXIT".to_string()))?;
-                self.block.add_instruction(RegInstr::TrapExit(enable_flag));
+                self.block.add_instruction(RegInstr::Mov(next, RegValue::Const(Value::Int(*target_pid as i64))));
+                RegInstr::Unlink(next)
             }
-            
+            OpCode::TrapExit => RegInstr::TrapExit(top),
+
             // Process registry operations
-            OpCode::Register(name) => {
-                // Register current process - no PID needed on stack (use current process ID)
-                // Use a placeholder register for the process ID (will be filled by IR VM)
-                let proc_id_reg = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Register(name.clone(), proc_id_reg));
-            }
-            
+            OpCode::Register(name) => RegInstr::Register(name.clone(), next),
             OpCode::Unregister(name) => {
-                self.block.add_instruction(RegInstr::Unregister(name.clone()));
-            }
-            
-            OpCode::Whereis(name) => {
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::Whereis(dst, name.clone()));
-                self.stack.push(dst);
-            }
-            
-            OpCode::SendNamed(name) => {
-                let message = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("SEND_NAMED".to_string()))?;
-                self.block.add_instruction(RegInstr::SendNamed(name.clone(), message));
-            }
-            
-            // Add basic I/O operations
-            OpCode::ReadFile => {
-                let filename = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("READ_file".to_string()))?;
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::ReadFile(dst, filename));
-                self.stack.push(dst);
+                self.block.add_instruction(RegInstr::Mov(next, RegValue::Const(Value::Null)));
+                RegInstr::Unregister(name.clone())
             }
-            
-            OpCode::WriteFile => {
-                let content = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("write_file".to_string()))?;
-                let filename = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("write_file".to_string()))?;
-                self.block.add_instruction(RegInstr::WriteFile(filename, content));
-            }
-            
-            OpCode::DumpScope => {
-                let dst = self.block.alloc_register();
-                self.block.add_instruction(RegInstr::DumpScope(dst));
-                self.stack.push(dst);
+            OpCode::Whereis(name) => RegInstr::Whereis(next, name.clone()),
+            OpCode::SendNamed(name) => RegInstr::SendNamed(name.clone(), top),
+
+            // Supervision operations
+            OpCode::StartSupervisor => RegInstr::StartSupervisor(next),
+            OpCode::SuperviseChild(name) => RegInstr::SuperviseChild(name.clone(), next),
+            OpCode::RestartChild(name) => {
+                self.block.add_instruction(RegInstr::Mov(next, RegValue::Const(Value::Null)));
+                RegInstr::RestartChild(name.clone())
             }
-            
-            // For any remaining unsupported instructions, add a NOP 
+
+            // Standard library operations reuse the stack VM's implementation
             _ => {
-                self.block.add_instruction(RegInstr::Nop);
+                let (pops, pushes) = stack_effect(instruction);
+                let first = next - pops as RegId;
+                let dst = if pushes > 0 { Some(first) } else { None };
+                RegInstr::Native(dst, instruction.clone(), (first..next).collect())
             }
-        }
-        
-        Ok(())
-    }
-    
-    fn pop_binary_op(&mut self) -> VMResult<(RegId, RegId, RegId)> {
-        let src2 = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("binary operation".to_string()))?;
-        let src1 = self.stack.pop().ok_or_else(|| VMError::StackUnderflow("binary operation".to_string()))?;
-        let dst = self.block.alloc_register();
-        Ok((dst, src1, src2))
+        };
+
+        self.block.add_instruction(instr);
     }
-}
\ No newline at end of file
+}
@@ -1,7 +1,9 @@
 use crate::vm::Value;
 use std::collections::HashMap;
 
+pub mod analysis;
 pub mod lowering;
+pub mod optimizer;
 pub mod vm;
 
 pub type RegId = u32;
@@ -55,10 +57,11 @@ pub enum RegInstr {
     // Memory operations
     Load(RegId, VarId),
     Store(VarId, RegId),
+    Destructure(RegId, crate::vm::DestructurePattern), // src, pattern
     
     // Control flow
-    Call(Option<RegId>, FnId, Vec<RegId>), // Optional return register
-    Ret(Option<RegId>),
+    Call(RegId, FnId, Vec<String>),    // frame base (first argument, then first result), target, params
+    Ret,                               // results stay in the callee's lowest registers
     Jmp(usize),
     Jz(RegId, usize),
     
@@ -74,23 +77,30 @@ pub enum RegInstr {
     GetField(RegId, RegId, String), // dst, object, field_name
     HasField(RegId, RegId, String), // dst, object, field_name
     DeleteField(RegId, String),     // object, field_name
+    SetFieldMut(VarId, String, RegId), // variable holding the object, field_name, value
     Keys(RegId, RegId),             // dst, object
+    Values(RegId, RegId),           // dst, object
+    Entries(RegId, RegId),          // dst, object
     
     // Function operations
-    MakeFunction(RegId, usize, Vec<String>), // dst, addr, params
-    CallFunction(Option<RegId>, RegId, Vec<RegId>), // dst, func_reg, args
+    MakeFunction(RegId, usize, Vec<String>, Option<String>), // dst, addr, params, return type
+    CallFunction(RegId),                     // function; its arguments sit in the registers below it
     MakeLambda(RegId, usize, Vec<String>),   // dst, addr, params
-    Capture(String, RegId),                  // var_name, value
+    Capture(String),                         // var_name
     
     // Exception handling
-    Try(usize),        // catch_addr
+    Try(usize, RegId), // catch_addr, register that receives the exception
     Catch(RegId),      // exception register
     Throw(RegId),      // exception value
+    Rethrow(RegId),    // caught exception
     EndTry,
+    Finally,
+    EndFinally,
     
     // Module system  
-    Import(RegId, String), // dst, module_path
-    Export(String, RegId), // name, value
+    Import(String),    // module_path
+    Export(String),    // name
+    ExportAs(String, RegId), // name, src
     
     // Concurrency operations
     Spawn(RegId, RegId),               // dst_pid, function
@@ -105,7 +115,7 @@ pub enum RegInstr {
     TrapExit(RegId),                   // enable_flag
     
     // Process registry operations
-    Register(String, RegId),           // name, pid
+    Register(String, RegId),           // name, dst
     Unregister(String),                // name
     Whereis(RegId, String),            // dst, name
     SendNamed(String, RegId),          // name, message
@@ -115,68 +125,12 @@ pub enum RegInstr {
     SuperviseChild(String, RegId),     // strategy, child_spec
     RestartChild(String),              // child_name
     
-    // I/O operations
-    ReadFile(RegId, RegId),            // dst, filename
-    WriteFile(RegId, RegId),           // filename, content
-    ReadLine(RegId),                   // dst
-    ReadChar(RegId),                   // dst
-    ReadInput(RegId),                  // dst
-    AppendFile(RegId, RegId),          // filename, content
-    FileExists(RegId, RegId),          // dst, filename
-    FileSize(RegId, RegId),            // dst, filename
-    DeleteFile(RegId),                 // filename
-    ListDir(RegId, RegId),             // dst, path
-    ReadBytes(RegId, RegId),           // dst, filename
-    WriteBytes(RegId, RegId),          // filename, data
-    
-    // Environment operations
-    GetEnv(RegId, RegId),              // dst, var_name
-    SetEnv(RegId, RegId),              // var_name, value
-    GetArgs(RegId),                    // dst
-    Exec(RegId, RegId),                // dst, command
-    ExecCapture(RegId, RegId),         // dst, command
-    Exit(RegId),                       // status_code
-    
-    // Time operations
-    GetTime(RegId),                    // dst
-    Sleep(RegId),                      // duration
-    FormatTime(RegId, RegId, RegId),   // dst, timestamp, format
-    
-    // Network operations
-    HttpGet(RegId, RegId),             // dst, url
-    HttpPost(RegId, RegId, RegId),     // dst, url, data
-    TcpConnect(RegId, RegId, RegId),   // dst, host, port
-    TcpListen(RegId, RegId),           // dst, port
-    TcpSend(RegId, RegId),             // socket, data
-    TcpRecv(RegId, RegId),             // dst, socket
-    UdpBind(RegId, RegId),             // dst, port
-    UdpSend(RegId, RegId, RegId),      // socket, addr, data
-    UdpRecv(RegId, RegId),             // dst, socket
-    DnsResolve(RegId, RegId),          // dst, hostname
-    
-    // Advanced I/O operations
-    AsyncRead(RegId, RegId),           // dst, filename
-    AsyncWrite(RegId, RegId),          // filename, content
-    Await(RegId, RegId),               // dst, async_op
-    StreamCreate(RegId),               // dst
-    StreamRead(RegId, RegId),          // dst, stream
-    StreamWrite(RegId, RegId),         // stream, data
-    StreamClose(RegId),                // stream
-    JsonParse(RegId, RegId),           // dst, json_str
-    JsonStringify(RegId, RegId),       // dst, value
-    CsvParse(RegId, RegId),            // dst, csv_str
-    CsvWrite(RegId, RegId),            // dst, data
-    Compress(RegId, RegId),            // dst, data
-    Decompress(RegId, RegId),          // dst, compressed_data
-    Encrypt(RegId, RegId, RegId),      // dst, data, key
-    Decrypt(RegId, RegId, RegId),      // dst, encrypted_data, key
-    Hash(RegId, RegId),                // dst, data
-    DbConnect(RegId, RegId),           // dst, connection_string
-    DbQuery(RegId, RegId, RegId),      // dst, connection, query
-    DbExec(RegId, RegId),              // connection, command
+    // Standard library (I/O, environment, time, network, data formats) run through the
+    // stack VM's implementation. Operands are listed in stack order.
+    Native(Option<RegId>, crate::vm::OpCode, Vec<RegId>), // dst, instruction, operands
     
     // Misc operations
-    DumpScope(RegId),                  // dst (scope info)
+    DumpScope,                         // print the current scope
     Delete(String),                    // var_name
     
     // Boolean constants
@@ -195,6 +149,7 @@ pub struct RegBlock {
     pub entry: usize,
     pub register_count: u32,
     pub variables: HashMap<String, RegId>, // Variable name to register mapping
+    pub addr_map: Vec<usize>,              // bytecode address -> index of its first IR instruction
 }
 
 impl RegBlock {
@@ -204,44 +159,16 @@ impl RegBlock {
             entry: 0,
             register_count: 0,
             variables: HashMap::new(),
+            addr_map: Vec::new(),
         }
     }
     
-    pub fn alloc_register(&mut self) -> RegId {
-        let reg = self.register_count;
-        self.register_count += 1;
-        reg
-    }
-    
     pub fn add_instruction(&mut self, instr: RegInstr) {
         self.instructions.push(instr);
     }
     
-}
-
-#[derive(Debug)]
-pub struct StackState {
-    // Simulated stack for lowering
-    stack: Vec<RegId>,
-}
-
-impl StackState {
-    pub fn new() -> Self {
-        Self {
-            stack: Vec::new(),
-        }
+    // Bytecode address of the instruction that produced IR instruction `index`
+    pub fn source_addr(&self, index: usize) -> usize {
+        self.addr_map.partition_point(|&start| start <= index).saturating_sub(1)
     }
-    
-    pub fn push(&mut self, reg: RegId) {
-        self.stack.push(reg);
-    }
-    
-    pub fn pop(&mut self) -> Option<RegId> {
-        self.stack.pop()
-    }
-    
-    pub fn peek(&self) -> Option<RegId> {
-        self.stack.last().copied()
-    }
-    
-}
\ No newline at end of file
+}
@@ -0,0 +1,310 @@
+use crate::ir::vm::RegisterVM;
+use crate::ir::{RegBlock, RegId, RegInstr, RegValue};
+use crate::vm::Value;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Default)]
+pub struct IrOptimizationStats {
+    pub constants_folded: usize,
+    pub dead_instructions_removed: usize,
+}
+
+// Optimization passes over lowered register IR. Stack slots become registers during
+// lowering, so every PUSH turns into a `Mov` of a constant that arithmetic can fold away.
+#[derive(Default)]
+pub struct IrOptimizer {
+    stats: IrOptimizationStats,
+}
+
+// Registers an instruction reads or may overwrite; `All` when that depends on runtime state
+// (calls, returns and the final state at HALT)
+enum Registers {
+    Only(Vec<RegId>),
+    All,
+}
+
+impl IrOptimizer {
+    pub fn new() -> Self {
+        Self {
+            stats: IrOptimizationStats::default(),
+        }
+    }
+
+    pub fn optimize(&mut self, mut block: RegBlock) -> (RegBlock, IrOptimizationStats) {
+        self.stats = IrOptimizationStats::default();
+        self.constant_folding_pass(&mut block);
+        self.dead_register_elimination_pass(&mut block);
+        (block, self.stats.clone())
+    }
+
+    // Replace Add/Sub/Mul/Div of registers holding known constants with a `Mov` of the
+    // result. Constants are only tracked within a basic block, and operations that would
+    // fail at runtime are left alone so they still report their error.
+    fn constant_folding_pass(&mut self, block: &mut RegBlock) {
+        let leaders = Self::block_leaders(block);
+        let mut known: HashMap<RegId, Value> = HashMap::new();
+
+        for index in 0..block.instructions.len() {
+            if leaders.contains(&index) {
+                known.clear();
+            }
+
+            let instruction = &block.instructions[index];
+            let folded = match instruction {
+                RegInstr::Add(dst, a, b) | RegInstr::Sub(dst, a, b) |
+                RegInstr::Mul(dst, a, b) | RegInstr::Div(dst, a, b) => {
+                    match (known.get(a), known.get(b)) {
+                        (Some(x), Some(y)) => {
+                            let result = match instruction {
+                                RegInstr::Add(..) => RegisterVM::add_values(x, y),
+                                RegInstr::Sub(..) => RegisterVM::sub_values(x, y),
+                                RegInstr::Mul(..) => RegisterVM::mul_values(x, y),
+                                _ => RegisterVM::div_values(x, y),
+                            };
+                            result.ok().map(|value| RegInstr::Mov(*dst, RegValue::Const(value)))
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            if let Some(folded) = folded {
+                block.instructions[index] = folded;
+                self.stats.constants_folded += 1;
+            }
+
+            match &block.instructions[index] {
+                RegInstr::Mov(dst, RegValue::Const(value)) => {
+                    known.insert(*dst, value.clone());
+                }
+                RegInstr::Mov(dst, RegValue::Reg(src)) => match known.get(src).cloned() {
+                    Some(value) => {
+                        known.insert(*dst, value);
+                    }
+                    None => {
+                        known.remove(dst);
+                    }
+                },
+                instruction => match Self::writes(instruction) {
+                    Registers::All => known.clear(),
+                    Registers::Only(regs) => {
+                        for reg in regs {
+                            known.remove(&reg);
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    // Remove `Mov`s whose destination is overwritten or never read before the program
+    // leaves the current frame. Repeats until nothing changes, since removing one move can
+    // leave the move that fed it dead too.
+    fn dead_register_elimination_pass(&mut self, block: &mut RegBlock) {
+        loop {
+            let live_out = Self::live_registers(block);
+            let dead: Vec<bool> = block.instructions.iter().zip(&live_out).map(|(instruction, live)| {
+                match instruction {
+                    RegInstr::Mov(dst, RegValue::Reg(src)) if dst == src => true,
+                    RegInstr::Mov(dst, _) => !live.contains(dst),
+                    _ => false,
+                }
+            }).collect();
+
+            let removed = dead.iter().filter(|&&dead| dead).count();
+            if removed == 0 {
+                return;
+            }
+            Self::remove_instructions(block, &dead);
+            self.stats.dead_instructions_removed += removed;
+        }
+    }
+
+    // Registers live after each instruction. Anything a catch handler reads counts as live
+    // everywhere, because any instruction inside a TRY block may jump there.
+    fn live_registers(block: &RegBlock) -> Vec<HashSet<RegId>> {
+        let count = block.instructions.len();
+        let all: HashSet<RegId> = (0..block.register_count).collect();
+        let mut live_in: Vec<HashSet<RegId>> = vec![HashSet::new(); count + 1];
+        // Running off the end stops the program like HALT does
+        live_in[count] = all.clone();
+
+        let catch_targets: Vec<usize> = block.instructions.iter().filter_map(|instruction| match instruction {
+            RegInstr::Try(target, _) => Some(*target),
+            _ => None,
+        }).collect();
+
+        let mut live_out = vec![HashSet::new(); count];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            let handler_live: HashSet<RegId> = catch_targets.iter()
+                .flat_map(|&target| live_in[target.min(count)].iter().copied())
+                .collect();
+
+            for index in (0..count).rev() {
+                let instruction = &block.instructions[index];
+                let mut out: HashSet<RegId> = handler_live.clone();
+                for successor in Self::successors(instruction, index) {
+                    out.extend(live_in[successor.min(count)].iter().copied());
+                }
+
+                let mut input = out.clone();
+                if let Some(dst) = Self::kills(instruction) {
+                    input.remove(&dst);
+                }
+                match Self::reads(instruction) {
+                    Registers::All => input.extend(all.iter().copied()),
+                    Registers::Only(regs) => input.extend(regs),
+                }
+
+                if input != live_in[index] {
+                    live_in[index] = input;
+                    changed = true;
+                }
+                live_out[index] = out;
+            }
+        }
+        live_out
+    }
+
+    // Drop the marked instructions and point every instruction index (branch targets, the
+    // entry point and the bytecode address map) at what now sits in the same place
+    fn remove_instructions(block: &mut RegBlock, dead: &[bool]) {
+        let mut new_index = Vec::with_capacity(dead.len() + 1);
+        let mut kept = 0;
+        for &dead in dead {
+            new_index.push(kept);
+            if !dead {
+                kept += 1;
+            }
+        }
+        new_index.push(kept);
+        let remap = |index: usize| new_index[index.min(dead.len())];
+
+        let instructions = std::mem::take(&mut block.instructions);
+        block.instructions = instructions.into_iter().zip(dead).filter(|(_, &dead)| !dead).map(|(mut instruction, _)| {
+            match &mut instruction {
+                RegInstr::Jmp(target) | RegInstr::Jz(_, target) |
+                RegInstr::Call(_, target, _) | RegInstr::Try(target, _) => *target = remap(*target),
+                _ => {}
+            }
+            instruction
+        }).collect();
+        block.entry = remap(block.entry);
+        for start in &mut block.addr_map {
+            *start = remap(*start);
+        }
+    }
+
+    // Instructions that control can reach other than by falling through from the one before
+    fn block_leaders(block: &RegBlock) -> HashSet<usize> {
+        let mut leaders = HashSet::from([0, block.entry]);
+        for (index, instruction) in block.instructions.iter().enumerate() {
+            match instruction {
+                RegInstr::Jmp(target) | RegInstr::Jz(_, target) |
+                RegInstr::Call(_, target, _) | RegInstr::Try(target, _) => {
+                    leaders.insert(*target);
+                    leaders.insert(index + 1);
+                }
+                // Function values hold bytecode addresses
+                RegInstr::MakeFunction(_, addr, _, _) | RegInstr::MakeLambda(_, addr, _) => {
+                    if let Some(&start) = block.addr_map.get(*addr) {
+                        leaders.insert(start);
+                    }
+                }
+                RegInstr::CallFunction(_) | RegInstr::Ret | RegInstr::Throw(_) | RegInstr::Rethrow(_) => {
+                    leaders.insert(index + 1);
+                }
+                _ => {}
+            }
+        }
+        leaders
+    }
+
+    fn successors(instruction: &RegInstr, index: usize) -> Vec<usize> {
+        match instruction {
+            RegInstr::Jmp(target) => vec![*target],
+            RegInstr::Jz(_, target) | RegInstr::Try(target, _) => vec![index + 1, *target],
+            RegInstr::Ret | RegInstr::Halt | RegInstr::Throw(_) | RegInstr::Rethrow(_) => Vec::new(),
+            // Calls come back to the next instruction; the callee is analysed from its own entry
+            _ => vec![index + 1],
+        }
+    }
+
+    // The register an instruction overwrites without looking at its old value
+    fn kills(instruction: &RegInstr) -> Option<RegId> {
+        match instruction {
+            RegInstr::Mov(dst, _) |
+            RegInstr::Add(dst, _, _) | RegInstr::AddF(dst, _, _) |
+            RegInstr::Sub(dst, _, _) | RegInstr::SubF(dst, _, _) |
+            RegInstr::Mul(dst, _, _) | RegInstr::MulF(dst, _, _) |
+            RegInstr::Div(dst, _, _) | RegInstr::DivF(dst, _, _) |
+            RegInstr::Concat(dst, _, _) |
+            RegInstr::Eq(dst, _, _) | RegInstr::Ne(dst, _, _) |
+            RegInstr::Lt(dst, _, _) | RegInstr::Le(dst, _, _) |
+            RegInstr::Gt(dst, _, _) | RegInstr::Ge(dst, _, _) |
+            RegInstr::EqF(dst, _, _) | RegInstr::NeF(dst, _, _) |
+            RegInstr::LtF(dst, _, _) | RegInstr::LeF(dst, _, _) |
+            RegInstr::GtF(dst, _, _) | RegInstr::GeF(dst, _, _) |
+            RegInstr::And(dst, _, _) | RegInstr::Or(dst, _, _) | RegInstr::Not(dst, _) |
+            RegInstr::Load(dst, _) | RegInstr::Len(dst, _) | RegInstr::Index(dst, _, _) |
+            RegInstr::MakeList(dst, _) | RegInstr::MakeObject(dst) |
+            RegInstr::GetField(dst, _, _) | RegInstr::HasField(dst, _, _) | RegInstr::Keys(dst, _) |
+            RegInstr::Values(dst, _) | RegInstr::Entries(dst, _) |
+            RegInstr::MakeFunction(dst, _, _, _) | RegInstr::MakeLambda(dst, _, _) |
+            RegInstr::True(dst) | RegInstr::False(dst) | RegInstr::Null(dst) |
+            RegInstr::Native(Some(dst), _, _) => Some(*dst),
+            _ => None,
+        }
+    }
+
+    fn writes(instruction: &RegInstr) -> Registers {
+        if let Some(dst) = Self::kills(instruction) {
+            return Registers::Only(vec![dst]);
+        }
+        match instruction {
+            RegInstr::Call(..) | RegInstr::CallFunction(_) | RegInstr::Ret => Registers::All,
+            RegInstr::SetField(obj, _, _) | RegInstr::DeleteField(obj, _) => Registers::Only(vec![*obj]),
+            RegInstr::Catch(reg) | RegInstr::Spawn(reg, _) | RegInstr::Receive(reg) |
+            RegInstr::ReceiveMatch(reg, _) | RegInstr::Monitor(reg, _) | RegInstr::Register(_, reg) |
+            RegInstr::Whereis(reg, _) | RegInstr::StartSupervisor(reg) => Registers::Only(vec![*reg]),
+            _ => Registers::Only(Vec::new()),
+        }
+    }
+
+    fn reads(instruction: &RegInstr) -> Registers {
+        let regs = match instruction {
+            RegInstr::Mov(_, RegValue::Reg(src)) => vec![*src],
+            RegInstr::Mov(_, RegValue::Const(_)) => Vec::new(),
+            RegInstr::Add(_, a, b) | RegInstr::AddF(_, a, b) |
+            RegInstr::Sub(_, a, b) | RegInstr::SubF(_, a, b) |
+            RegInstr::Mul(_, a, b) | RegInstr::MulF(_, a, b) |
+            RegInstr::Div(_, a, b) | RegInstr::DivF(_, a, b) |
+            RegInstr::Concat(_, a, b) |
+            RegInstr::Eq(_, a, b) | RegInstr::Ne(_, a, b) |
+            RegInstr::Lt(_, a, b) | RegInstr::Le(_, a, b) |
+            RegInstr::Gt(_, a, b) | RegInstr::Ge(_, a, b) |
+            RegInstr::EqF(_, a, b) | RegInstr::NeF(_, a, b) |
+            RegInstr::LtF(_, a, b) | RegInstr::LeF(_, a, b) |
+            RegInstr::GtF(_, a, b) | RegInstr::GeF(_, a, b) |
+            RegInstr::And(_, a, b) | RegInstr::Or(_, a, b) |
+            RegInstr::Index(_, a, b) | RegInstr::SetField(a, _, b) | RegInstr::Send(a, b) => vec![*a, *b],
+            RegInstr::Not(_, src) | RegInstr::Store(_, src) | RegInstr::Destructure(src, _) | RegInstr::Jz(src, _) |
+            RegInstr::Print(src) | RegInstr::Len(_, src) | RegInstr::GetField(_, src, _) |
+            RegInstr::HasField(_, src, _) | RegInstr::DeleteField(src, _) | RegInstr::Keys(_, src) |
+            RegInstr::Values(_, src) | RegInstr::Entries(_, src) | RegInstr::SetFieldMut(_, _, src) |
+            RegInstr::Throw(src) | RegInstr::Rethrow(src) | RegInstr::Spawn(_, src) | RegInstr::Monitor(_, src) |
+            RegInstr::Demonitor(src) | RegInstr::Link(src) | RegInstr::Unlink(src) |
+            RegInstr::TrapExit(src) | RegInstr::ExportAs(_, src) | RegInstr::Register(_, src) | RegInstr::SendNamed(_, src) |
+            RegInstr::SuperviseChild(_, src) => vec![*src],
+            RegInstr::MakeList(_, items) => items.clone(),
+            RegInstr::Native(_, _, operands) => operands.clone(),
+            RegInstr::Call(..) | RegInstr::CallFunction(_) | RegInstr::Ret | RegInstr::Halt => {
+                return Registers::All;
+            }
+            _ => Vec::new(),
+        };
+        Registers::Only(regs)
+    }
+}
@@ -1,15 +1,49 @@
 use crate::ir::{RegBlock, RegInstr, RegValue, RegId};
-use crate::vm::{Value, VMError, VMResult, ProcId};
+use crate::vm::{Value, VMError, VMResult, ProcId, VM, DEFAULT_CALL_DEPTH_LIMIT, float_result, check_argument_type, param_annotation};
+use crate::vm::value::format_scope;
+use crate::vm::OutputSink;
+use std::io::Write;
 use crate::concurrency::Message;
 use std::collections::HashMap;
+use indexmap::IndexMap;
+
+// A function activation. Registers named by instructions are relative to `base`, so each
+// call works in its own window of the register file starting at its first argument.
+#[derive(Debug, Clone)]
+struct CallFrame {
+    return_ip: usize,
+    base: usize,
+    variables: HashMap<String, Value>,
+    returns: Option<String>, // type the result must have, if the function declares one
+}
+
+#[derive(Debug, Clone)]
+struct TryHandler {
+    catch_ip: usize,
+    frames: usize,          // call frames to keep when unwinding
+    exception_reg: usize,   // absolute register that receives the exception
+    finally_depth: usize,   // FINALLY blocks running when the TRY started
+}
 
-#[derive(Debug)]
 pub struct RegisterVM {
     pub registers: Vec<Value>,
-    pub variables: HashMap<String, Value>,
+    pub variables: HashMap<String, Value>, // global scope
     pub ip: usize,
     pub block: RegBlock,
     pub halted: bool,
+    pub exports: HashMap<String, Value>,
+    pub call_depth_limit: usize,
+    pub program_args: Vec<String>,         // handed to the host VM for GET_ARGS
+    pub float_precision: Option<usize>,    // digits after the decimal point when printing floats, shared with the host VM
+    pub source_lines: Option<Vec<usize>>,  // bytecode address -> .ttvm source line
+    pub output: OutputSink,                // PRINT and DUMP_SCOPE, shared with the host VM
+    pub instruction_count: usize,          // instructions executed so far
+    frames: Vec<CallFrame>,
+    try_stack: Vec<TryHandler>,
+    finally_stack: Vec<Option<Value>>, // running FINALLY blocks and the exception each rethrows
+    lambda_captures: HashMap<String, Value>,
+    // Stack VM used to run standard library instructions, created on first use
+    host: Option<Box<VM>>,
     // Concurrency-related fields
     pub process_id: Option<ProcId>,
     pub mailbox: Vec<Message>,
@@ -25,253 +59,324 @@ impl RegisterVM {
             ip: block.entry,
             block,
             halted: false,
+            exports: HashMap::new(),
+            call_depth_limit: DEFAULT_CALL_DEPTH_LIMIT,
+            program_args: Vec::new(),
+            float_precision: None,
+            source_lines: None,
+            output: OutputSink::stdout(),
+            instruction_count: 0,
+            frames: Vec::new(),
+            try_stack: Vec::new(),
+            finally_stack: Vec::new(),
+            lambda_captures: HashMap::new(),
+            host: None,
             process_id: None,
             mailbox: Vec::new(),
             yielded: false,
         }
     }
-    
-    
+
+
     pub fn run(&mut self) -> VMResult<Option<Value>> {
         while !self.halted && !self.yielded && self.ip < self.block.instructions.len() {
-            self.execute_instruction()?;
+            self.step()?;
         }
-        
+
         // Return the value in register 0 if it exists
-        Ok(self.registers.get(0).cloned())
+        Ok(self.registers.first().cloned())
     }
-    
+
+    pub fn set_source_lines(&mut self, source_lines: Vec<usize>) {
+        self.source_lines = Some(source_lines);
+    }
+
     #[allow(dead_code)] // Used for future IR process integration
     pub fn run_until_yield(&mut self) -> VMResult<()> {
         self.yielded = false;
         while !self.halted && !self.yielded && self.ip < self.block.instructions.len() {
-            self.execute_instruction()?;
+            self.step()?;
         }
         Ok(())
     }
-    
+
     #[allow(dead_code)] // Used for future IR process integration
     pub fn add_message(&mut self, message: Message) {
         self.mailbox.push(message);
     }
-    
+
     #[allow(dead_code)] // Used for future IR process integration
     pub fn has_messages(&self) -> bool {
         !self.mailbox.is_empty()
     }
-    
+
     #[allow(dead_code)] // Used for future IR process integration
     pub fn is_halted(&self) -> bool {
         self.halted
     }
-    
+
     #[allow(dead_code)] // Used for future IR process integration
     pub fn is_yielded(&self) -> bool {
         self.yielded
     }
-    
+
+    // Execute one instruction. Errors are handled as on the stack VM: inside a TRY block
+    // they become exceptions, otherwise they report the failing source line if known.
+    // Send program output here instead of stdout
+    #[allow(dead_code)]
+    pub fn set_output(&mut self, output: OutputSink) {
+        if let Some(host) = self.host.as_mut() {
+            host.set_output(output.clone());
+        }
+        self.output = output;
+    }
+
+    fn write_output(&mut self, text: &str) -> VMResult<()> {
+        self.output.write_all(text.as_bytes())
+            .and_then(|_| self.output.flush())
+            .map_err(|e| VMError::FileError { filename: "<output>".to_string(), error: e.to_string() })
+    }
+
+    fn step(&mut self) -> VMResult<()> {
+        let ip = self.ip;
+        self.instruction_count += 1;
+        let Err(error) = self.execute_instruction() else {
+            return Ok(());
+        };
+        if matches!(error, VMError::ProcessExit(_)) {
+            return Err(error);
+        }
+        self.ip = ip;
+        if !self.try_stack.is_empty() {
+            let exception = Value::Exception {
+                message: error.to_string(),
+                stack_trace: self.backtrace(),
+            };
+            return self.throw_exception(exception);
+        }
+        let addr = self.block.source_addr(ip);
+        match self.source_line(addr) {
+            Some(line) => Err(VMError::AtSourceLine { line, ip: addr, error: Box::new(error) }),
+            None => Err(error),
+        }
+    }
+
     fn execute_instruction(&mut self) -> VMResult<()> {
         let instruction = &self.block.instructions[self.ip].clone();
-        
+
         match instruction {
             RegInstr::Mov(dst, src) => {
                 let value = self.resolve_reg_value(src)?;
                 self.set_register(*dst, value)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Add(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.add_values(&val1, &val2)?;
+                let result = Self::add_values(val1, val2)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::AddF(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.add_float_values(&val1, &val2)?;
+                let result = self.float_op(val1, val2, "ADD_F", |x, y| x + y)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Sub(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.sub_values(&val1, &val2)?;
+                let result = Self::sub_values(val1, val2)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::SubF(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.sub_float_values(&val1, &val2)?;
+                let result = self.float_op(val1, val2, "SUB_F", |x, y| x - y)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Mul(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.mul_values(&val1, &val2)?;
+                let result = Self::mul_values(val1, val2)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::MulF(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.mul_float_values(&val1, &val2)?;
+                let result = self.float_op(val1, val2, "MUL_F", |x, y| x * y)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Div(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.div_values(&val1, &val2)?;
+                let result = Self::div_values(val1, val2)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::DivF(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.div_float_values(&val1, &val2)?;
+                let result = self.div_float_values(val1, val2)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Concat(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.concat_values(&val1, &val2)?;
+                let result = self.concat_values(val1, val2)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Eq(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = Value::Bool(self.values_equal(&val1, &val2));
+                let result = Self::bool_to_int(self.values_equal(val1, val2, "EQ")?);
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Ne(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = Value::Bool(!self.values_equal(&val1, &val2));
+                let result = Self::bool_to_int(!self.values_equal(val1, val2, "NE")?);
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Lt(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.compare_values(&val1, &val2, |a, b| a < b)?;
+                let result = self.compare_values(val1, val2, "LT", |a, b| a < b)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Le(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.compare_values(&val1, &val2, |a, b| a <= b)?;
+                let result = self.compare_values(val1, val2, "LE", |a, b| a <= b)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Gt(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.compare_values(&val1, &val2, |a, b| a > b)?;
+                let result = self.compare_values(val1, val2, "GT", |a, b| a > b)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Ge(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.compare_values(&val1, &val2, |a, b| a >= b)?;
+                let result = self.compare_values(val1, val2, "GE", |a, b| a >= b)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::And(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.logical_and(&val1, &val2)?;
+                let result = self.logical_op(val1, val2, "AND", |a, b| a && b)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Or(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.logical_or(&val1, &val2)?;
+                let result = self.logical_op(val1, val2, "OR", |a, b| a || b)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Not(dst, src) => {
                 let val = self.get_register(*src)?;
-                let result = self.logical_not(&val)?;
+                let result = self.logical_not(val)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Load(dst, var_name) => {
-                let value = self.variables.get(var_name)
-                    .cloned()
-                    .unwrap_or(Value::Null);
+                let value = self.get_variable(var_name)?;
                 self.set_register(*dst, value)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Store(var_name, src) => {
                 let value = self.get_register(*src)?.clone();
-                self.variables.insert(var_name.clone(), value);
+                self.current_scope_mut().insert(var_name.clone(), value);
                 self.ip += 1;
             }
-            
+
+            RegInstr::Destructure(src, pattern) => {
+                let value = self.get_register(*src)?;
+                let bindings = pattern.bindings(value).map_err(|reason| VMError::PatternMismatch {
+                    pattern: pattern.to_string(),
+                    reason,
+                })?;
+                self.current_scope_mut().extend(bindings);
+                self.ip += 1;
+            }
+
             RegInstr::Jmp(target) => {
                 self.ip = *target;
             }
-            
+
             RegInstr::Jz(condition, target) => {
                 let val = self.get_register(*condition)?;
-                if self.is_falsy(&val) {
+                if self.is_falsy(val) {
                     self.ip = *target;
                 } else {
                     self.ip += 1;
                 }
             }
-            
+
             RegInstr::Print(src) => {
-                let value = self.get_register(*src)?;
-                println!("{}", self.format_value(&value));
+                let text = format!("{}\n", self.get_register(*src)?.display(self.float_precision));
+                self.write_output(&text)?;
                 self.ip += 1;
             }
-            
+
+            RegInstr::DumpScope => {
+                let text = format!("{}\n", format_scope(self.current_scope()));
+                self.write_output(&text)?;
+                self.ip += 1;
+            }
+
             RegInstr::MakeList(dst, elements) => {
                 let mut list = Vec::new();
                 for &reg in elements {
                     list.push(self.get_register(reg)?.clone());
                 }
-                self.set_register(*dst, Value::List(list))?;
+                self.set_register(*dst, Value::List(list.into()))?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Len(dst, src) => {
                 let val = self.get_register(*src)?;
                 let length = match val {
-                    Value::List(ref list) => list.len() as i64,
-                    Value::Str(ref s) => s.len() as i64,
+                    Value::List(list) => list.len() as i64,
+                    Value::Object(map) => map.len() as i64,
                     _ => return Err(VMError::TypeMismatch {
-                        expected: "List or String".to_string(),
+                        expected: "a list or object".to_string(),
                         got: format!("{:?}", val),
                         operation: "LEN".to_string(),
                     }),
@@ -279,111 +384,359 @@ impl RegisterVM {
                 self.set_register(*dst, Value::Int(length))?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Index(dst, container, index) => {
                 let container_val = self.get_register(*container)?;
                 let index_val = self.get_register(*index)?;
-                let result = self.index_value(&container_val, &index_val)?;
+                let result = self.index_value(container_val, index_val)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::MakeObject(dst) => {
-                self.set_register(*dst, Value::Object(HashMap::new()))?;
+                self.set_register(*dst, Value::Object(IndexMap::new().into()))?;
+                self.ip += 1;
+            }
+
+            // Object operations update the object in its register instead of copying it
+            RegInstr::SetField(obj, field_name, src) => {
+                let value = self.get_register(*src)?.clone();
+                self.object_mut(*obj, "SET_FIELD")?.insert(field_name.clone(), value);
+                self.ip += 1;
+            }
+
+            RegInstr::GetField(dst, obj, field_name) => {
+                let value = self.object(*obj, "GET_FIELD")?
+                    .get(field_name)
+                    .cloned()
+                    .unwrap_or(Value::Null);
+                self.set_register(*dst, value)?;
+                self.ip += 1;
+            }
+
+            RegInstr::HasField(dst, obj, field_name) => {
+                let has_field = self.object(*obj, "HAS_FIELD")?.contains_key(field_name);
+                self.set_register(*dst, Self::bool_to_int(has_field))?;
+                self.ip += 1;
+            }
+
+            RegInstr::DeleteField(obj, field_name) => {
+                self.object_mut(*obj, "DELETE_FIELD")?.shift_remove(field_name);
+                self.ip += 1;
+            }
+
+            RegInstr::Keys(dst, obj) => {
+                let keys: Vec<Value> = self.object(*obj, "KEYS")?
+                    .keys()
+                    .map(|k| Value::Str(k.clone()))
+                    .collect();
+                self.set_register(*dst, Value::List(keys.into()))?;
+                self.ip += 1;
+            }
+
+            RegInstr::SetFieldMut(var_name, field_name, src) => {
+                let value = self.get_register(*src)?.clone();
+                match self.variable_mut(var_name)? {
+                    Value::Object(map) => {
+                        map.insert(field_name.clone(), value);
+                    }
+                    other => return Err(VMError::TypeMismatch {
+                        expected: "a variable holding an object".to_string(),
+                        got: format!("{:?}", other),
+                        operation: "SET_FIELD_MUT".to_string(),
+                    }),
+                }
+                self.ip += 1;
+            }
+
+            RegInstr::Values(dst, obj) => {
+                let values: Vec<Value> = self.object(*obj, "VALUES")?.values().cloned().collect();
+                self.set_register(*dst, Value::List(values.into()))?;
+                self.ip += 1;
+            }
+
+            RegInstr::Entries(dst, obj) => {
+                let entries: Vec<Value> = self.object(*obj, "ENTRIES")?
+                    .iter()
+                    .map(|(k, v)| Value::List(vec![Value::Str(k.clone()), v.clone()].into()))
+                    .collect();
+                self.set_register(*dst, Value::List(entries.into()))?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Halt => {
                 self.halted = true;
             }
-            
+
             RegInstr::Nop => {
                 self.ip += 1;
             }
-            
-            // Simplified implementations for now
-            RegInstr::Call(return_reg, _fn_id, _args) => {
-                if let Some(reg) = return_reg {
-                    self.set_register(*reg, Value::Null)?;
+
+            RegInstr::Call(first_arg, target, params) => {
+                let first = self.absolute(*first_arg);
+                let mut frame = HashMap::new();
+                for (offset, param) in params.iter().enumerate() {
+                    let value = self.registers.get(first + offset).cloned().unwrap_or(Value::Null);
+                    let (name, type_name) = param_annotation(param);
+                    check_argument_type(name, type_name, &value, "CALL")?;
+                    frame.insert(name.to_string(), value);
+                }
+                self.enter_function(frame, first, *target, None)?;
+            }
+
+            RegInstr::Ret => {
+                let frame = self.frames.pop().ok_or(VMError::NoVariableScope)?;
+                if let Some(type_name) = &frame.returns {
+                    // The result is in the callee's lowest register
+                    match self.registers.get(frame.base) {
+                        Some(value) if value.has_type(type_name) => {}
+                        result => return Err(VMError::TypeMismatch {
+                            expected: format!("{} as the return value", type_name),
+                            got: result.map_or("nothing".to_string(), |value| format!("{:?}", value)),
+                            operation: "RET".to_string(),
+                        }),
+                    }
                 }
+                self.ip = frame.return_ip;
+            }
+
+            // Function operations
+            RegInstr::MakeFunction(dst, addr, params, returns) => {
+                let function = Value::Function { addr: *addr, params: params.clone(), returns: returns.clone() };
+                self.set_register(*dst, function)?;
                 self.ip += 1;
             }
-            
-            RegInstr::Ret(_return_val) => {
-                self.halted = true;
+
+            RegInstr::MakeLambda(dst, addr, params) => {
+                // Create closure with currently captured variables
+                let closure = Value::Closure {
+                    addr: *addr,
+                    params: params.clone(),
+                    captured: std::mem::take(&mut self.lambda_captures).into(),
+                };
+                self.set_register(*dst, closure)?;
+                self.ip += 1;
+            }
+
+            RegInstr::Capture(var_name) => {
+                let value = self.get_variable(var_name)?;
+                self.lambda_captures.insert(var_name.clone(), value);
+                self.ip += 1;
             }
-            
+
+            RegInstr::CallFunction(function_reg) => {
+                let function = self.get_register(*function_reg)?.clone();
+                let (addr, params, mut frame, returns) = match function {
+                    Value::Function { addr, params, returns } => (addr, params, HashMap::new(), returns),
+                    // Parameters override captured variables
+                    Value::Closure { addr, params, captured } => (addr, params, captured.into_inner(), None),
+                    _ => return Err(VMError::TypeMismatch {
+                        expected: "a function or closure".to_string(),
+                        got: format!("{:?}", function),
+                        operation: "CALL_FUNCTION".to_string(),
+                    }),
+                };
+
+                // Arguments sit in the registers just below the function value
+                let function_abs = self.absolute(*function_reg);
+                let available = function_abs - self.current_base();
+                if available < params.len() {
+                    return Err(VMError::InsufficientStackItems { needed: params.len(), available });
+                }
+                let first = function_abs - params.len();
+                for (offset, param) in params.iter().enumerate() {
+                    let (name, type_name) = param_annotation(param);
+                    check_argument_type(name, type_name, &self.registers[first + offset], "CALL_FUNCTION")?;
+                    frame.insert(name.to_string(), self.registers[first + offset].clone());
+                }
+
+                // Function values hold bytecode addresses
+                let target = self.block.addr_map.get(addr).copied().unwrap_or(self.block.instructions.len());
+                self.enter_function(frame, first, target, returns)?;
+            }
+
+            // Exception handling
+            RegInstr::Try(catch_ip, exception_reg) => {
+                let handler = TryHandler {
+                    catch_ip: *catch_ip,
+                    frames: self.frames.len(),
+                    exception_reg: self.absolute(*exception_reg),
+                    finally_depth: self.finally_stack.len(),
+                };
+                self.try_stack.push(handler);
+                self.ip += 1;
+            }
+
+            RegInstr::Catch(_exception_reg) => {
+                // The exception was already placed in its register by throw_exception
+                self.ip += 1;
+            }
+
+            RegInstr::Throw(src) => {
+                let exception = match self.get_register(*src)?.clone() {
+                    exception @ Value::Exception { .. } => exception,
+                    Value::Str(message) => Value::Exception {
+                        message,
+                        stack_trace: self.backtrace(),
+                    },
+                    other => Value::Exception {
+                        message: format!("Thrown value: {:?}", other),
+                        stack_trace: self.backtrace(),
+                    },
+                };
+                self.throw_exception(exception)?;
+            }
+
+            RegInstr::Rethrow(src) => {
+                match self.get_register(*src)?.clone() {
+                    Value::Exception { message, mut stack_trace } => {
+                        stack_trace.push(format!("rethrown {}", self.location(self.ip)));
+                        self.throw_exception(Value::Exception { message, stack_trace })?;
+                    }
+                    other => return Err(VMError::TypeMismatch {
+                        expected: "exception".to_string(),
+                        got: format!("{:?}", other),
+                        operation: "RETHROW".to_string(),
+                    }),
+                }
+            }
+
+            RegInstr::EndTry => {
+                self.try_stack.pop();
+                self.ip += 1;
+            }
+
+            RegInstr::Finally => {
+                self.finally_stack.push(None);
+                self.ip += 1;
+            }
+
+            RegInstr::EndFinally => {
+                self.ip += 1;
+                match self.finally_stack.pop() {
+                    Some(Some(exception)) => self.throw_exception(exception)?,
+                    Some(None) => {}
+                    None => return Err(VMError::RuntimeError("END_FINALLY without a matching FINALLY".to_string())),
+                }
+            }
+
+            // Module system
+            RegInstr::Import(module_path) => {
+                return Err(VMError::UnsupportedOperation(format!(
+                    "IMPORT {} is not supported by the register VM", module_path
+                )));
+            }
+
+            RegInstr::Export(name) => {
+                let value = self.get_variable(name)?;
+                self.exports.insert(name.clone(), value);
+                self.ip += 1;
+            }
+
+            RegInstr::ExportAs(name, src) => {
+                let value = self.get_register(*src)?.clone();
+                self.exports.insert(name.clone(), value);
+                self.ip += 1;
+            }
+
+            // Standard library operations run on the stack VM
+            RegInstr::Native(dst, opcode, operands) => {
+                let mut values = Vec::with_capacity(operands.len());
+                for &reg in operands {
+                    values.push(self.get_register(reg)?.clone());
+                }
+                let program_args = &self.program_args;
+                let float_precision = self.float_precision;
+                let output = &self.output;
+                let host = self.host.get_or_insert_with(|| {
+                    let mut host = VM::new(Vec::new());
+                    host.program_args = program_args.clone();
+                    host.float_precision = float_precision;
+                    host.set_output(output.clone());
+                    Box::new(host)
+                });
+                let mut results = host.execute_native(opcode, values)?;
+                if let Some(dst) = dst {
+                    let value = results.pop()
+                        .ok_or_else(|| VMError::StackUnderflow(format!("{:?}", opcode)))?;
+                    self.set_register(*dst, value)?;
+                }
+                self.ip += 1;
+            }
+
             // Float comparison operations
             RegInstr::EqF(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.float_compare_values(&val1, &val2, |a, b| (a - b).abs() < f64::EPSILON)?;
+                let result = self.float_compare_values(val1, val2, "EQ_F", |a, b| a == b)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::NeF(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.float_compare_values(&val1, &val2, |a, b| (a - b).abs() >= f64::EPSILON)?;
+                let result = self.float_compare_values(val1, val2, "NE_F", |a, b| a != b)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::LtF(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.float_compare_values(&val1, &val2, |a, b| a < b)?;
+                let result = self.float_compare_values(val1, val2, "LT_F", |a, b| a < b)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::LeF(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.float_compare_values(&val1, &val2, |a, b| a <= b)?;
+                let result = self.float_compare_values(val1, val2, "LE_F", |a, b| a <= b)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::GtF(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.float_compare_values(&val1, &val2, |a, b| a > b)?;
+                let result = self.float_compare_values(val1, val2, "GT_F", |a, b| a > b)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             RegInstr::GeF(dst, src1, src2) => {
                 let val1 = self.get_register(*src1)?;
                 let val2 = self.get_register(*src2)?;
-                let result = self.float_compare_values(&val1, &val2, |a, b| a >= b)?;
+                let result = self.float_compare_values(val1, val2, "GE_F", |a, b| a >= b)?;
                 self.set_register(*dst, result)?;
                 self.ip += 1;
             }
-            
+
             // Handle all the new instruction types we added
             RegInstr::True(dst) => {
                 self.set_register(*dst, Value::Bool(true))?;
                 self.ip += 1;
             }
-            
+
             RegInstr::False(dst) => {
                 self.set_register(*dst, Value::Bool(false))?;
                 self.ip += 1;
             }
-            
+
             RegInstr::Null(dst) => {
                 self.set_register(*dst, Value::Null)?;
                 self.ip += 1;
             }
-            
-            
+
+
             RegInstr::Delete(var_name) => {
-                self.variables.remove(var_name);
+                if self.current_scope_mut().remove(var_name).is_none() {
+                    eprintln!("Warning: tried to DELETE unknown variable '{}'", var_name);
+                }
                 self.ip += 1;
             }
-            
+
             // Concurrency operations - now implemented!
             RegInstr::Spawn(dst_pid, _function_reg) => {
                 // For now, create a placeholder PID - this needs scheduler integration
@@ -392,7 +745,7 @@ impl RegisterVM {
                 self.ip += 1;
                 // TODO: Actually spawn process via scheduler callback
             }
-            
+
             RegInstr::Receive(dst) => {
                 if let Some(message) = self.mailbox.pop() {
                     // Convert message to value - simplified for now
@@ -405,6 +758,7 @@ impl RegisterVM {
                         Message::Monitor(pid, monitor_ref) => Value::Str(format!("MONITOR:{} {}", pid, monitor_ref)),
                         Message::Unlink(pid) => Value::Str(format!("UNLINK:{}", pid)),
                         Message::TrapExit(flag) => Value::Bool(flag),
+                        Message::Kill(reason) => Value::Str(format!("KILL:{}", reason)),
                     };
                     self.set_register(*dst, value)?;
                     self.ip += 1;
@@ -413,37 +767,37 @@ impl RegisterVM {
                     self.yielded = true;
                 }
             }
-            
+
             RegInstr::Send(target_pid_reg, message_reg) => {
                 let _target_pid = match self.get_register(*target_pid_reg)? {
                     Value::Int(pid) => *pid as u64,
                     _ => return Err(VMError::TypeError("PID must be integer".to_string())),
                 };
                 let _message_value = self.get_register(*message_reg)?.clone();
-                
+
                 // TODO: Send message via scheduler - for now just store locally
                 // In real implementation, this would call scheduler.send_message(target_pid, Message::Value(message_value))
                 self.ip += 1;
             }
-            
+
             RegInstr::Yield => {
                 self.yielded = true;
                 self.ip += 1;
             }
-            
+
             RegInstr::Monitor(dst_ref, target_pid_reg) => {
                 let _target_pid = match self.get_register(*target_pid_reg)? {
                     Value::Int(pid) => *pid as u64,
                     _ => return Err(VMError::TypeError("PID must be integer".to_string())),
                 };
-                
+
                 // Generate monitor reference
                 let monitor_ref = format!("ref_{}", self.ip);
                 self.set_register(*dst_ref, Value::Str(monitor_ref))?;
                 self.ip += 1;
                 // TODO: Actually set up monitoring via scheduler
             }
-            
+
             RegInstr::Link(target_pid_reg) => {
                 let _target_pid = match self.get_register(*target_pid_reg)? {
                     Value::Int(pid) => *pid as u64,
@@ -452,7 +806,7 @@ impl RegisterVM {
                 self.ip += 1;
                 // TODO: Actually create link via scheduler
             }
-            
+
             RegInstr::Unlink(target_pid_reg) => {
                 let _target_pid = match self.get_register(*target_pid_reg)? {
                     Value::Int(pid) => *pid as u64,
@@ -461,7 +815,7 @@ impl RegisterVM {
                 self.ip += 1;
                 // TODO: Actually remove link via scheduler
             }
-            
+
             RegInstr::Register(_name, _pid_reg) => {
                 // Register current process with name - use current process ID
                 if let Some(proc_id) = self.process_id {
@@ -471,260 +825,356 @@ impl RegisterVM {
                 self.ip += 1;
                 // TODO: Register name via scheduler registry
             }
-            
+
             RegInstr::Whereis(dst, _name) => {
                 // TODO: Look up PID by name via scheduler registry
-                // For now, return 0 (not found)
-                self.set_register(*dst, Value::Int(0))?;
+                // For now, return null (not found)
+                self.set_register(*dst, Value::Null)?;
                 self.ip += 1;
             }
-            
-            // For other complex operations not yet implemented, skip with NOP behavior
+
+            // The remaining concurrency operations need a scheduler; programs using them
+            // run on TinyProc instead
             _ => {
-                // Skip unimplemented instructions for now
-                self.ip += 1;
+                return Err(VMError::UnsupportedOperation(format!(
+                    "{:?} is not supported by the register VM", instruction
+                )));
             }
         }
-        
+
         Ok(())
     }
-    
+
+    fn current_base(&self) -> usize {
+        self.frames.last().map_or(0, |frame| frame.base)
+    }
+
+    fn absolute(&self, reg_id: RegId) -> usize {
+        self.current_base() + reg_id as usize
+    }
+
+    fn source_line(&self, addr: usize) -> Option<usize> {
+        self.source_lines.as_ref().and_then(|lines| lines.get(addr).copied())
+    }
+
+    // Position of an instruction, described in bytecode terms like the stack VM
+    fn location(&self, ip: usize) -> String {
+        let addr = self.block.source_addr(ip);
+        match self.source_line(addr) {
+            Some(line) => format!("at instruction {} (line {})", addr, line),
+            None => format!("at instruction {}", addr),
+        }
+    }
+
+    // Stack trace from the current instruction out through every call site, in the
+    // stack VM's format
+    fn backtrace(&self) -> Vec<String> {
+        let mut trace = Vec::with_capacity(self.frames.len() + 1);
+        let mut ip = self.ip;
+        for frame in self.frames.iter().rev() {
+            let call_site = frame.return_ip - 1;
+            trace.push(match self.block.instructions.get(call_site) {
+                Some(RegInstr::Call(_, target, _)) => {
+                    format!("{} in fn@0x{:04X}", self.location(ip), self.block.source_addr(*target))
+                }
+                _ => self.location(ip),
+            });
+            ip = call_site;
+        }
+        trace.push(self.location(ip));
+        trace
+    }
+
+    // Enter a function whose register window starts at absolute register `base`. As on
+    // the stack VM, a call followed by RET reuses the current frame unless a TRY opened
+    // in it is still active or the callee declares a return type the frame doesn't.
+    fn enter_function(&mut self, variables: HashMap<String, Value>, base: usize, target: usize, returns: Option<String>) -> VMResult<()> {
+        let tail_call = matches!(self.block.instructions.get(self.ip + 1), Some(RegInstr::Ret))
+            && self.frames.last().is_some_and(|frame| returns.is_none() || frame.returns == returns)
+            && self.try_stack.last().is_none_or(|h| h.frames < self.frames.len());
+
+        if let (true, Some(current)) = (tail_call, self.frames.last_mut()) {
+            current.base = base;
+            current.variables = variables;
+        } else {
+            if self.frames.len() >= self.call_depth_limit {
+                return Err(VMError::CallStackOverflow { depth: self.call_depth_limit });
+            }
+            self.frames.push(CallFrame { return_ip: self.ip + 1, base, variables, returns });
+        }
+        self.ip = target;
+        Ok(())
+    }
+
+    fn throw_exception(&mut self, exception: Value) -> VMResult<()> {
+        if let Some(handler) = self.try_stack.pop() {
+            // Unwind to the try block state and hand the exception to the catch block, or to
+            // a FINALLY block that rethrows it when done
+            self.frames.truncate(handler.frames);
+            self.finally_stack.truncate(handler.finally_depth);
+            if matches!(self.block.instructions.get(handler.catch_ip), Some(RegInstr::Finally)) {
+                self.finally_stack.push(Some(exception));
+                self.ip = handler.catch_ip + 1;
+            } else {
+                self.set_absolute(handler.exception_reg, exception);
+                self.ip = handler.catch_ip;
+            }
+            Ok(())
+        } else {
+            let message = match exception {
+                Value::Exception { message, .. } => message,
+                other => format!("{:?}", other),
+            };
+            Err(VMError::ParseError {
+                line: self.block.source_addr(self.ip),
+                instruction: format!("Unhandled exception: {}", message),
+            })
+        }
+    }
+
+    // Variable resolution mirrors the stack VM: the current frame, then the globals
+    fn get_variable(&self, name: &str) -> VMResult<Value> {
+        self.current_scope()
+            .get(name)
+            .or_else(|| self.variables.get(name))
+            .cloned()
+            .ok_or_else(|| VMError::UndefinedVariable(name.to_string()))
+    }
+
+    fn current_scope(&self) -> &HashMap<String, Value> {
+        self.frames.last().map_or(&self.variables, |frame| &frame.variables)
+    }
+
+    // The variable LOAD would read, borrowed for in-place updates
+    fn variable_mut(&mut self, name: &str) -> VMResult<&mut Value> {
+        let in_frame = self.frames.last().is_some_and(|frame| frame.variables.contains_key(name));
+        let scope = if in_frame { self.current_scope_mut() } else { &mut self.variables };
+        scope.get_mut(name).ok_or_else(|| VMError::UndefinedVariable(name.to_string()))
+    }
+
+    fn current_scope_mut(&mut self) -> &mut HashMap<String, Value> {
+        match self.frames.last_mut() {
+            Some(frame) => &mut frame.variables,
+            None => &mut self.variables,
+        }
+    }
+
+    fn object(&self, reg_id: RegId, operation: &str) -> VMResult<&IndexMap<String, Value>> {
+        match self.get_register(reg_id)? {
+            Value::Object(map) => Ok(map),
+            other => Err(VMError::TypeMismatch {
+                expected: "an object".to_string(),
+                got: format!("{:?}", other),
+                operation: operation.to_string(),
+            }),
+        }
+    }
+
+    fn object_mut(&mut self, reg_id: RegId, operation: &str) -> VMResult<&mut IndexMap<String, Value>> {
+        let index = self.absolute(reg_id);
+        match self.registers.get_mut(index) {
+            Some(Value::Object(map)) => Ok(map),
+            other => Err(VMError::TypeMismatch {
+                expected: "an object".to_string(),
+                got: format!("{:?}", other.cloned().unwrap_or(Value::Null)),
+                operation: operation.to_string(),
+            }),
+        }
+    }
+
     fn resolve_reg_value(&self, reg_value: &RegValue) -> VMResult<Value> {
         match reg_value {
             RegValue::Const(value) => Ok(value.clone()),
-            RegValue::Reg(reg_id) => self.get_register(*reg_id).map(|v| v.clone()),
+            RegValue::Reg(reg_id) => self.get_register(*reg_id).cloned(),
         }
     }
-    
+
     fn get_register(&self, reg_id: RegId) -> VMResult<&Value> {
-        self.registers.get(reg_id as usize)
+        self.registers.get(self.absolute(reg_id))
             .ok_or_else(|| VMError::ParseError {
                 line: 0,
                 instruction: format!("Invalid register: r{}", reg_id),
             })
     }
-    
+
     fn set_register(&mut self, reg_id: RegId, value: Value) -> VMResult<()> {
-        if reg_id as usize >= self.registers.len() {
-            return Err(VMError::ParseError {
-                line: 0,
-                instruction: format!("Invalid register: r{}", reg_id),
-            });
-        }
-        self.registers[reg_id as usize] = value;
+        let index = self.absolute(reg_id);
+        self.set_absolute(index, value);
         Ok(())
     }
-    
-    // Helper methods for arithmetic operations
-    fn add_values(&self, a: &Value, b: &Value) -> VMResult<Value> {
-        match (a, b) {
-            (Value::Int(x), Value::Int(y)) => Ok(Value::Int(x + y)),
-            _ => Err(VMError::TypeMismatch {
-                expected: "Int".to_string(),
-                got: format!("{:?} and {:?}", a, b),
-                operation: "ADD".to_string(),
-            }),
+
+    // Register windows of nested calls extend past the initial register file
+    fn set_absolute(&mut self, index: usize, value: Value) {
+        if index >= self.registers.len() {
+            self.registers.resize(index + 1, Value::Null);
         }
+        self.registers[index] = value;
     }
-    
-    fn add_float_values(&self, a: &Value, b: &Value) -> VMResult<Value> {
-        match (a, b) {
-            (Value::Float(x), Value::Float(y)) => Ok(Value::Float(x + y)),
-            _ => Err(VMError::TypeMismatch {
-                expected: "Float".to_string(),
-                got: format!("{:?} and {:?}", a, b),
-                operation: "ADD_F".to_string(),
-            }),
+
+    fn bool_to_int(value: bool) -> Value {
+        Value::Int(if value { 1 } else { 0 })
+    }
+
+    // Helper methods for arithmetic operations. Type rules and error messages follow
+    // the stack VM so both produce the same output.
+    fn type_mismatch(expected: &str, a: &Value, b: &Value, operation: &str) -> VMError {
+        VMError::TypeMismatch {
+            expected: expected.to_string(),
+            got: format!("{:?}, {:?}", a, b),
+            operation: operation.to_string(),
         }
     }
-    
-    fn sub_values(&self, a: &Value, b: &Value) -> VMResult<Value> {
+
+    pub(crate) fn add_values(a: &Value, b: &Value) -> VMResult<Value> {
         match (a, b) {
-            (Value::Int(x), Value::Int(y)) => Ok(Value::Int(x - y)),
-            _ => Err(VMError::TypeMismatch {
-                expected: "Int".to_string(),
-                got: format!("{:?} and {:?}", a, b),
-                operation: "SUB".to_string(),
-            }),
+            (Value::Int(x), Value::Int(y)) => x.checked_add(*y).map(Value::Int).ok_or_else(|| VMError::IntegerOverflow { operation: "ADD".to_string() }),
+            // Type coercion: int + float = float
+            (Value::Int(x), Value::Float(y)) => float_result("ADD", *x as f64 + y),
+            (Value::Float(x), Value::Int(y)) => float_result("ADD", x + *y as f64),
+            (Value::Float(x), Value::Float(y)) => float_result("ADD", x + y),
+            _ => Err(Self::type_mismatch("two numbers (int or float)", a, b, "ADD")),
         }
     }
-    
-    fn sub_float_values(&self, a: &Value, b: &Value) -> VMResult<Value> {
+
+    pub(crate) fn sub_values(a: &Value, b: &Value) -> VMResult<Value> {
         match (a, b) {
-            (Value::Float(x), Value::Float(y)) => Ok(Value::Float(x - y)),
-            _ => Err(VMError::TypeMismatch {
-                expected: "Float".to_string(),
-                got: format!("{:?} and {:?}", a, b),
-                operation: "SUB_F".to_string(),
-            }),
+            (Value::Int(x), Value::Int(y)) => x.checked_sub(*y).map(Value::Int).ok_or_else(|| VMError::IntegerOverflow { operation: "SUB".to_string() }),
+            (Value::Int(x), Value::Float(y)) => float_result("SUB", *x as f64 - y),
+            (Value::Float(x), Value::Int(y)) => float_result("SUB", x - *y as f64),
+            (Value::Float(x), Value::Float(y)) => float_result("SUB", x - y),
+            _ => Err(Self::type_mismatch("two numbers (int or float)", a, b, "SUB")),
         }
     }
-    
-    fn mul_values(&self, a: &Value, b: &Value) -> VMResult<Value> {
+
+    pub(crate) fn mul_values(a: &Value, b: &Value) -> VMResult<Value> {
         match (a, b) {
-            (Value::Int(x), Value::Int(y)) => Ok(Value::Int(x * y)),
-            _ => Err(VMError::TypeMismatch {
-                expected: "Int".to_string(),
-                got: format!("{:?} and {:?}", a, b),
-                operation: "MUL".to_string(),
-            }),
+            (Value::Int(x), Value::Int(y)) => x.checked_mul(*y).map(Value::Int).ok_or_else(|| VMError::IntegerOverflow { operation: "MUL".to_string() }),
+            (Value::Int(x), Value::Float(y)) => float_result("MUL", *x as f64 * y),
+            (Value::Float(x), Value::Int(y)) => float_result("MUL", x * *y as f64),
+            (Value::Float(x), Value::Float(y)) => float_result("MUL", x * y),
+            _ => Err(Self::type_mismatch("two numbers (int or float)", a, b, "MUL")),
         }
     }
-    
-    fn mul_float_values(&self, a: &Value, b: &Value) -> VMResult<Value> {
+
+    pub(crate) fn div_values(a: &Value, b: &Value) -> VMResult<Value> {
         match (a, b) {
-            (Value::Float(x), Value::Float(y)) => Ok(Value::Float(x * y)),
-            _ => Err(VMError::TypeMismatch {
-                expected: "Float".to_string(),
-                got: format!("{:?} and {:?}", a, b),
-                operation: "MUL_F".to_string(),
-            }),
+            (Value::Int(_), Value::Int(0)) | (Value::Float(_), Value::Int(0)) => Err(VMError::DivisionByZero),
+            (Value::Int(_), Value::Float(y)) | (Value::Float(_), Value::Float(y)) if *y == 0.0 => Err(VMError::DivisionByZero),
+            (Value::Int(x), Value::Int(y)) => x.checked_div(*y).map(Value::Int).ok_or_else(|| VMError::IntegerOverflow { operation: "DIV".to_string() }),
+            (Value::Int(x), Value::Float(y)) => float_result("DIV", *x as f64 / y),
+            (Value::Float(x), Value::Int(y)) => float_result("DIV", x / *y as f64),
+            (Value::Float(x), Value::Float(y)) => float_result("DIV", x / y),
+            _ => Err(Self::type_mismatch("two numbers (int or float)", a, b, "DIV")),
         }
     }
-    
-    fn div_values(&self, a: &Value, b: &Value) -> VMResult<Value> {
+
+    fn float_op<F>(&self, a: &Value, b: &Value, operation: &str, op: F) -> VMResult<Value>
+    where
+        F: Fn(f64, f64) -> f64,
+    {
         match (a, b) {
-            (Value::Int(x), Value::Int(y)) => {
-                if *y == 0 {
-                    Err(VMError::RuntimeError("Division by zero".to_string()))
-                } else {
-                    Ok(Value::Int(x / y))
-                }
-            }
-            _ => Err(VMError::TypeMismatch {
-                expected: "Int".to_string(),
-                got: format!("{:?} and {:?}", a, b),
-                operation: "DIV".to_string(),
-            }),
+            (Value::Float(x), Value::Float(y)) => float_result(operation, op(*x, *y)),
+            _ => Err(Self::type_mismatch("two floats", a, b, operation)),
         }
     }
-    
+
     fn div_float_values(&self, a: &Value, b: &Value) -> VMResult<Value> {
         match (a, b) {
-            (Value::Float(x), Value::Float(y)) => {
-                if *y == 0.0 {
-                    Err(VMError::RuntimeError("Division by zero".to_string()))
-                } else {
-                    Ok(Value::Float(x / y))
-                }
-            }
-            _ => Err(VMError::TypeMismatch {
-                expected: "Float".to_string(),
-                got: format!("{:?} and {:?}", a, b),
+            (Value::Float(_), Value::Float(y)) if *y == 0.0 => Err(VMError::TypeMismatch {
+                expected: "non-zero divisor".to_string(),
+                got: "zero".to_string(),
                 operation: "DIV_F".to_string(),
             }),
+            _ => self.float_op(a, b, "DIV_F", |x, y| x / y),
         }
     }
-    
+
     fn concat_values(&self, a: &Value, b: &Value) -> VMResult<Value> {
         match (a, b) {
             (Value::Str(x), Value::Str(y)) => Ok(Value::Str(format!("{}{}", x, y))),
-            _ => Err(VMError::TypeMismatch {
-                expected: "String".to_string(),
-                got: format!("{:?} and {:?}", a, b),
-                operation: "CONCAT".to_string(),
-            }),
+            _ => Err(Self::type_mismatch("two strings", a, b, "CONCAT")),
         }
     }
-    
-    fn values_equal(&self, a: &Value, b: &Value) -> bool {
+
+    fn values_equal(&self, a: &Value, b: &Value, operation: &str) -> VMResult<bool> {
         match (a, b) {
-            (Value::Int(x), Value::Int(y)) => x == y,
-            (Value::Float(x), Value::Float(y)) => (x - y).abs() < f64::EPSILON,
-            (Value::Str(x), Value::Str(y)) => x == y,
-            (Value::Bool(x), Value::Bool(y)) => x == y,
-            (Value::Null, Value::Null) => true,
-            _ => false,
+            (Value::Int(x), Value::Int(y)) => Ok(x == y),
+            (Value::Float(x), Value::Float(y)) => Ok(x == y),
+            (Value::Str(x), Value::Str(y)) => Ok(x == y),
+            (Value::Bool(x), Value::Bool(y)) => Ok(x == y),
+            (Value::Null, Value::Null) => Ok(true),
+            (Value::Function { addr: addr1, params: params1, .. }, Value::Function { addr: addr2, params: params2, .. }) => {
+                Ok(addr1 == addr2 && params1 == params2)
+            }
+            _ => Err(Self::type_mismatch("values of the same type", a, b, operation)),
         }
     }
-    
-    fn compare_values<F>(&self, a: &Value, b: &Value, op: F) -> VMResult<Value>
+
+    fn compare_values<F>(&self, a: &Value, b: &Value, operation: &str, op: F) -> VMResult<Value>
     where
         F: Fn(i64, i64) -> bool,
     {
         match (a, b) {
-            (Value::Int(x), Value::Int(y)) => Ok(Value::Bool(op(*x, *y))),
-            _ => Err(VMError::TypeMismatch {
-                expected: "Int".to_string(),
-                got: format!("{:?} and {:?}", a, b),
-                operation: "comparison".to_string(),
-            }),
+            (Value::Int(x), Value::Int(y)) => Ok(Self::bool_to_int(op(*x, *y))),
+            _ => Err(Self::type_mismatch("two integers", a, b, operation)),
         }
     }
-    
-    fn float_compare_values<F>(&self, a: &Value, b: &Value, op: F) -> VMResult<Value>
+
+    fn float_compare_values<F>(&self, a: &Value, b: &Value, operation: &str, op: F) -> VMResult<Value>
     where
         F: Fn(f64, f64) -> bool,
     {
         match (a, b) {
-            (Value::Float(x), Value::Float(y)) => Ok(Value::Bool(op(*x, *y))),
-            _ => Err(VMError::TypeMismatch {
-                expected: "Float".to_string(),
-                got: format!("{:?} and {:?}", a, b),
-                operation: "float comparison".to_string(),
-            }),
+            (Value::Float(x), Value::Float(y)) => Ok(Self::bool_to_int(op(*x, *y))),
+            _ => Err(Self::type_mismatch("two floats", a, b, operation)),
         }
     }
-    
-    fn logical_and(&self, a: &Value, b: &Value) -> VMResult<Value> {
-        let a_truthy = !self.is_falsy(a);
-        let b_truthy = !self.is_falsy(b);
-        Ok(Value::Bool(a_truthy && b_truthy))
-    }
-    
-    fn logical_or(&self, a: &Value, b: &Value) -> VMResult<Value> {
-        let a_truthy = !self.is_falsy(a);
-        let b_truthy = !self.is_falsy(b);
-        Ok(Value::Bool(a_truthy || b_truthy))
+
+    fn logical_op<F>(&self, a: &Value, b: &Value, operation: &str, op: F) -> VMResult<Value>
+    where
+        F: Fn(bool, bool) -> bool,
+    {
+        match (a, b) {
+            (Value::Bool(x), Value::Bool(y)) => Ok(Value::Bool(op(*x, *y))),
+            _ => Err(Self::type_mismatch("two Booleans", a, b, operation)),
+        }
     }
-    
+
     fn logical_not(&self, a: &Value) -> VMResult<Value> {
-        Ok(Value::Bool(self.is_falsy(a)))
+        match a {
+            Value::Bool(b) => Ok(Value::Bool(!b)),
+            Value::Int(i) => Ok(Value::Bool(*i == 0)),
+            _ => Err(VMError::TypeMismatch {
+                expected: "Bool or Int".to_string(),
+                got: format!("{:?}", a),
+                operation: "NOT".to_string(),
+            }),
+        }
     }
-    
+
+    // JZ jumps on the same values as the stack VM
     fn is_falsy(&self, value: &Value) -> bool {
-        match value {
-            Value::Bool(false) => true,
-            Value::Null => true,
-            Value::Int(0) => true,
-            Value::Float(f) => *f == 0.0,
-            Value::Str(s) => s.is_empty(),
-            _ => false,
-        }
+        matches!(value, Value::Int(0) | Value::Bool(false) | Value::Null)
     }
-    
+
     fn index_value(&self, container: &Value, index: &Value) -> VMResult<Value> {
-        match (container, index) {
-            (Value::List(list), Value::Int(idx)) => {
-                let idx = *idx as usize;
-                list.get(idx)
-                    .cloned()
-                    .ok_or_else(|| VMError::IndexOutOfBounds {
-                        index: idx,
-                        length: list.len(),
-                    })
-            }
+        let index = match index {
+            Value::Int(i) => *i as usize,
+            _ => return Err(VMError::TypeMismatch {
+                expected: "an integer index".to_string(),
+                got: format!("{:?}", index),
+                operation: "INDEX".to_string(),
+            }),
+        };
+        match container {
+            Value::List(list) => list.get(index)
+                .cloned()
+                .ok_or(VMError::IndexOutOfBounds { index, length: list.len() }),
             _ => Err(VMError::TypeMismatch {
-                expected: "List and Int".to_string(),
-                got: format!("{:?} and {:?}", container, index),
+                expected: "a list".to_string(),
+                got: format!("{:?}", container),
                 operation: "INDEX".to_string(),
             }),
         }
     }
-    
-    fn format_value(&self, value: &Value) -> String {
-        match value {
-            Value::Int(n) => n.to_string(),
-            Value::Float(f) => f.to_string(),
-            Value::Str(s) => s.clone(),
-            Value::Bool(b) => b.to_string(),
-            Value::Null => "null".to_string(),
-            Value::List(list) => {
-                let elements: Vec<String> = list.iter().map(|v| self.format_value(v)).collect();
-                format!("[{}]", elements.join(", "))
-            }
-            Value::Object(_) => "object".to_string(),
-            _ => format!("{:?}", value),
-        }
-    }
-}
\ No newline at end of file
+}
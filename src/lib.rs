@@ -8,12 +8,13 @@ pub mod gc;
 pub mod profiling;
 pub mod concurrency;
 pub mod bytecode;
+pub mod optimizer;
 pub mod ir;
 pub mod benchmarks;
 // pub mod testing;  // Temporarily disabled until VM is extracted
 
 // Re-export commonly used types for convenience
-pub use vm::{Value, OpCode, VMError, VMResult, ProcId, MessagePattern};
+pub use vm::{Value, OpCode, VMError, VMResult, ProcId, MessagePattern, DestructurePattern};
 pub use gc::{GcEngine, GcStats, MarkSweepGc, NoGc};
 pub use profiling::Profiler;
 pub use concurrency::{Message, RestartStrategy, ChildType, Shutdown, ChildSpec, RestartPolicy, SupervisorSpec, ChildState, ProcessRegistry};
@@ -37,12 +38,24 @@ pub struct VMConfig {
     pub debug_mode: bool,
     pub optimize_mode: bool,
     pub gc_type: String,
+    pub gc_threshold: usize,
     pub trace_enabled: bool,
+    pub trace_out: Option<String>,
     pub profile_enabled: bool,
+    pub flamegraph_out: Option<String>,
     pub smp_enabled: bool,
     pub trace_procs: bool,
     pub profile_procs: bool,
     pub use_ir: bool,
+    pub interactive: bool,
+    pub breakpoints: Vec<usize>,
+    pub watchpoints: Vec<String>,
+    pub call_depth_limit: usize,
+    pub max_reductions: usize,
+    pub max_stack: Option<usize>,
+    pub float_precision: Option<usize>,
+    pub node_address: Option<String>,
+    pub program_args: Vec<String>,
 }
 
 impl Default for VMConfig {
@@ -55,12 +68,24 @@ impl Default for VMConfig {
             debug_mode: false,
             optimize_mode: false,
             gc_type: "mark-sweep".to_string(),
+            gc_threshold: gc::DEFAULT_GC_THRESHOLD,
             trace_enabled: false,
+            trace_out: None,
             profile_enabled: false,
+            flamegraph_out: None,
             smp_enabled: true,
             trace_procs: false,
             profile_procs: false,
             use_ir: false,
+            interactive: false,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            call_depth_limit: vm::DEFAULT_CALL_DEPTH_LIMIT,
+            max_reductions: concurrency::DEFAULT_MAX_REDUCTIONS,
+            max_stack: None,
+            float_precision: None,
+            node_address: None,
+            program_args: Vec::new(),
         }
     }
 }
@@ -1,6 +1,7 @@
 // lisp_compiler.rs
 use std::fs;
 use std::io::Write;
+use crate::bytecode::quote_string;
 
 #[derive(Debug, Clone)]
 enum Expr {
@@ -89,7 +90,7 @@ fn atom(token: &str) -> Expr {
 fn compile_expr(expr: &Expr, output: &mut dyn Write) {
     match expr {
         Expr::Int(n) => writeln!(output, "PUSH_INT {}", n).unwrap(),
-        Expr::Str(s) => writeln!(output, "PUSH_STR \"{}\"", s).unwrap(),
+        Expr::Str(s) => writeln!(output, "PUSH_STR {}", quote_string(s)).unwrap(),
         Expr::Bool(true) => writeln!(output, "TRUE").unwrap(),
         Expr::Bool(false) => writeln!(output, "FALSE").unwrap(),
         Expr::Symbol(s) => writeln!(output, "LOAD {}", s).unwrap(),
@@ -135,6 +136,9 @@ fn compile_expr(expr: &Expr, output: &mut dyn Write) {
                         compile_expr(&list[3], output);
                         writeln!(output, "LABEL {}", end_label).unwrap();
                     }
+                    "cond" => compile_cond(&list[1..], output),
+                    "and" => compile_and(&list[1..], output),
+                    "or" => compile_or(&list[1..], output),
                     _ => panic!("Unknown operation: {}", s),
                 },
                 _ => panic!("First element in list must be a symbol"),
@@ -143,6 +147,71 @@ fn compile_expr(expr: &Expr, output: &mut dyn Write) {
     }
 }
 
+// (cond (test expr) ... (else expr)) tests each clause in order and runs the first match.
+// Without an else clause a cond that matches nothing yields null.
+fn compile_cond(clauses: &[Expr], output: &mut dyn Write) {
+    let end_label = fresh_label("end_cond");
+    let mut has_else = false;
+    for (i, clause) in clauses.iter().enumerate() {
+        let (test, body) = match clause {
+            Expr::List(parts) if parts.len() == 2 => (&parts[0], &parts[1]),
+            _ => panic!("Invalid cond clause: {:?}", clause),
+        };
+        if matches!(test, Expr::Symbol(s) if s == "else") {
+            if i + 1 != clauses.len() {
+                panic!("else must be the last cond clause");
+            }
+            compile_expr(body, output);
+            has_else = true;
+            break;
+        }
+        let next_label = fresh_label("cond_next");
+        compile_expr(test, output);
+        writeln!(output, "JZ {}", next_label).unwrap();
+        compile_expr(body, output);
+        writeln!(output, "JMP {}", end_label).unwrap();
+        writeln!(output, "LABEL {}", next_label).unwrap();
+    }
+    if !has_else {
+        writeln!(output, "NULL").unwrap();
+    }
+    writeln!(output, "LABEL {}", end_label).unwrap();
+}
+
+// (and a b ...) evaluates its arguments in order and stops at the first falsy one, so later
+// arguments never run. Yields #t when every argument is truthy (including when there are none).
+fn compile_and(args: &[Expr], output: &mut dyn Write) {
+    let false_label = fresh_label("and_false");
+    let end_label = fresh_label("end_and");
+    for arg in args {
+        compile_expr(arg, output);
+        writeln!(output, "JZ {}", false_label).unwrap();
+    }
+    writeln!(output, "TRUE").unwrap();
+    writeln!(output, "JMP {}", end_label).unwrap();
+    writeln!(output, "LABEL {}", false_label).unwrap();
+    writeln!(output, "FALSE").unwrap();
+    writeln!(output, "LABEL {}", end_label).unwrap();
+}
+
+// (or a b ...) stops at the first truthy argument and yields #t, or #f if none is
+fn compile_or(args: &[Expr], output: &mut dyn Write) {
+    let true_label = fresh_label("or_true");
+    let end_label = fresh_label("end_or");
+    for arg in args {
+        let next_label = fresh_label("or_next");
+        compile_expr(arg, output);
+        writeln!(output, "JZ {}", next_label).unwrap();
+        writeln!(output, "JMP {}", true_label).unwrap();
+        writeln!(output, "LABEL {}", next_label).unwrap();
+    }
+    writeln!(output, "FALSE").unwrap();
+    writeln!(output, "JMP {}", end_label).unwrap();
+    writeln!(output, "LABEL {}", true_label).unwrap();
+    writeln!(output, "TRUE").unwrap();
+    writeln!(output, "LABEL {}", end_label).unwrap();
+}
+
 fn binary_op(args: &[Expr], op: &str, output: &mut dyn Write) {
     compile_expr(&args[0], output);
     compile_expr(&args[1], output);
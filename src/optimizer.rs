@@ -1,5 +1,8 @@
-use crate::vm::OpCode;
-use std::collections::HashSet;
+use crate::vm::{param_annotation, variadic_params, OpCode};
+use std::collections::{HashMap, HashSet};
+
+// Functions with at most this many instructions before their RET are inlined
+const INLINE_MAX_INSTRUCTIONS: usize = 8;
 
 #[derive(Debug, Clone)]
 pub struct OptimizationOptions {
@@ -11,6 +14,9 @@ pub struct OptimizationOptions {
     pub constant_propagation: bool,
     pub instruction_combining: bool,
     pub jump_threading: bool,
+    pub common_subexpression_elimination: bool,
+    pub function_inlining: bool,
+    pub dead_store_elimination: bool,
 }
 
 impl Default for OptimizationOptions {
@@ -24,6 +30,9 @@ impl Default for OptimizationOptions {
             constant_propagation: true,
             instruction_combining: true,
             jump_threading: true,
+            common_subexpression_elimination: true,
+            function_inlining: true,
+            dead_store_elimination: true,
         }
     }
 }
@@ -38,6 +47,10 @@ pub struct OptimizationStats {
     pub constants_propagated: usize,
     pub instructions_combined: usize,
     pub jumps_threaded: usize,
+    pub common_subexpressions_eliminated: usize,
+    pub inlined_calls: usize,
+    pub dead_stores_removed: usize,
+    pub unreachable_instructions: usize, // in the program as given, before any pass ran
 }
 
 impl Default for OptimizationStats {
@@ -51,6 +64,10 @@ impl Default for OptimizationStats {
             constants_propagated: 0,
             instructions_combined: 0,
             jumps_threaded: 0,
+            common_subexpressions_eliminated: 0,
+            inlined_calls: 0,
+            dead_stores_removed: 0,
+            unreachable_instructions: 0,
         }
     }
 }
@@ -71,8 +88,13 @@ impl Optimizer {
     pub fn optimize(&mut self, instructions: Vec<OpCode>) -> (Vec<OpCode>, OptimizationStats) {
         let mut optimized = instructions;
         self.stats = OptimizationStats::default();
+        self.stats.unreachable_instructions = self.unreachable_instructions(&optimized).len();
 
         // Apply optimization passes in order
+        if self.options.function_inlining {
+            optimized = self.function_inlining_pass(optimized);
+        }
+
         if self.options.constant_folding {
             optimized = self.constant_folding_pass(optimized);
         }
@@ -81,6 +103,14 @@ impl Optimizer {
             optimized = self.constant_propagation_pass(optimized);
         }
 
+        if self.options.common_subexpression_elimination {
+            optimized = self.common_subexpression_pass(optimized);
+        }
+
+        if self.options.dead_store_elimination {
+            optimized = self.dead_store_elimination_pass(optimized);
+        }
+
         if self.options.instruction_combining {
             optimized = self.instruction_combining_pass(optimized);
         }
@@ -151,42 +181,50 @@ impl Optimizer {
         // Look for patterns like: PUSH_INT x, PUSH_INT y, ADD
         match (&instructions[start], &instructions[start + 1], &instructions[start + 2]) {
             (OpCode::PushInt(a), OpCode::PushInt(b), OpCode::Add) => {
-                let result = a + b;
-                Some((OpCode::PushInt(result), 3))
+                // Leave overflowing expressions for the VM to report at runtime
+                a.checked_add(*b).map(|result| (OpCode::PushInt(result), 3))
             }
             (OpCode::PushInt(a), OpCode::PushInt(b), OpCode::Sub) => {
-                let result = a - b;
-                Some((OpCode::PushInt(result), 3))
+                // Leave overflowing expressions for the VM to report at runtime
+                a.checked_sub(*b).map(|result| (OpCode::PushInt(result), 3))
             }
             (OpCode::PushFloat(a), OpCode::PushFloat(b), OpCode::AddF) => {
+                // Leave NaN results for the VM to report at runtime
                 let result = a + b;
-                Some((OpCode::PushFloat(result), 3))
+                (!result.is_nan()).then_some((OpCode::PushFloat(result), 3))
             }
             (OpCode::PushFloat(a), OpCode::PushFloat(b), OpCode::SubF) => {
+                // Leave NaN results for the VM to report at runtime
                 let result = a - b;
-                Some((OpCode::PushFloat(result), 3))
+                (!result.is_nan()).then_some((OpCode::PushFloat(result), 3))
             }
             (OpCode::PushFloat(a), OpCode::PushFloat(b), OpCode::MulF) => {
+                // Leave NaN results for the VM to report at runtime
                 let result = a * b;
-                Some((OpCode::PushFloat(result), 3))
+                (!result.is_nan()).then_some((OpCode::PushFloat(result), 3))
             }
             (OpCode::PushFloat(a), OpCode::PushFloat(b), OpCode::DivF) if *b != 0.0 => {
+                // Leave NaN results for the VM to report at runtime
                 let result = a / b;
-                Some((OpCode::PushFloat(result), 3))
+                (!result.is_nan()).then_some((OpCode::PushFloat(result), 3))
             }
             (OpCode::PushInt(a), OpCode::PushInt(b), OpCode::Eq) => {
+                // Leave NaN results for the VM to report at runtime
                 let result = a == b;
                 Some((if result { OpCode::True } else { OpCode::False }, 3))
             }
             (OpCode::PushInt(a), OpCode::PushInt(b), OpCode::Ne) => {
+                // Leave NaN results for the VM to report at runtime
                 let result = a != b;
                 Some((if result { OpCode::True } else { OpCode::False }, 3))
             }
             (OpCode::PushInt(a), OpCode::PushInt(b), OpCode::Lt) => {
+                // Leave NaN results for the VM to report at runtime
                 let result = a < b;
                 Some((if result { OpCode::True } else { OpCode::False }, 3))
             }
             (OpCode::PushInt(a), OpCode::PushInt(b), OpCode::Gt) => {
+                // Leave NaN results for the VM to report at runtime
                 let result = a > b;
                 Some((if result { OpCode::True } else { OpCode::False }, 3))
             }
@@ -197,12 +235,23 @@ impl Optimizer {
     fn dead_code_elimination_pass(&mut self, instructions: Vec<OpCode>) -> Vec<OpCode> {
         let reachable = self.find_reachable_instructions(&instructions);
         let original_len = instructions.len();
+
+        // new_index[i] is where instruction i (or the next kept one) ends up
+        let mut new_index = Vec::with_capacity(original_len + 1);
+        let mut kept = 0;
+        for i in 0..=original_len {
+            new_index.push(kept);
+            kept += reachable.contains(&i) as usize;
+        }
         
         let optimized: Vec<OpCode> = instructions
             .into_iter()
             .enumerate()
             .filter(|(i, _)| reachable.contains(i))
-            .map(|(_, instr)| instr)
+            .map(|(_, mut instr)| {
+                retarget(&mut instr, |target| new_index[target.min(original_len)]);
+                instr
+            })
             .collect();
 
         self.stats.dead_instructions_removed = original_len - optimized.len();
@@ -228,15 +277,15 @@ impl Optimizer {
                     worklist.push(*target);
                     worklist.push(pc + 1); // Fall through
                 }
-                OpCode::Call { addr, .. } => {
-                    worklist.push(*addr);
+                OpCode::Call { addr, .. } | OpCode::MakeFunction { addr, .. } | OpCode::MakeLambda { addr, .. } => {
+                    worklist.push(*addr); // Function pointers may be called later
                     worklist.push(pc + 1); // Continue after call
                 }
                 OpCode::Try { catch_addr } => {
                     worklist.push(*catch_addr);
                     worklist.push(pc + 1); // Continue in try block
                 }
-                OpCode::Ret | OpCode::Halt => {
+                OpCode::Ret | OpCode::RetN(_) | OpCode::Halt => {
                     // No fall through
                 }
                 _ => {
@@ -344,25 +393,44 @@ impl Optimizer {
         None
     }
 
-    // Peephole optimization pass - optimizes small instruction sequences
+    // Peephole optimization pass - optimizes small instruction sequences. Rewrites never
+    // swallow a jump target, and integer arithmetic on constants left next to each other by
+    // earlier rewrites is collapsed as it is emitted.
     fn peephole_optimization_pass(&mut self, instructions: Vec<OpCode>) -> Vec<OpCode> {
+        let leaders = block_leaders(&instructions);
         let mut optimized = Vec::new();
+        let mut new_index = vec![0; instructions.len() + 1];
+        let mut block_start = 0; // first slot of `optimized` that a collapse may touch
         let mut i = 0;
 
         while i < instructions.len() {
+            new_index[i] = optimized.len();
+            if leaders.contains(&i) {
+                block_start = optimized.len();
+            }
             match self.try_peephole_optimization(&instructions, i) {
-                Some((optimized_sequence, consumed)) => {
+                Some((optimized_sequence, consumed)) if (i + 1..i + consumed).all(|j| !leaders.contains(&j)) => {
+                    for slot in &mut new_index[i + 1..i + consumed] {
+                        *slot = optimized.len();
+                    }
                     optimized.extend(optimized_sequence);
                     self.stats.peephole_optimizations_applied += 1;
                     i += consumed;
                 }
-                None => {
+                _ => {
                     optimized.push(instructions[i].clone());
                     i += 1;
                 }
             }
+            while collapse_constant_tail(&mut optimized, block_start) {
+                self.stats.peephole_optimizations_applied += 1;
+            }
         }
+        new_index[instructions.len()] = optimized.len();
 
+        for instruction in &mut optimized {
+            retarget(instruction, |target| new_index[target.min(instructions.len())]);
+        }
         optimized
     }
 
@@ -373,10 +441,14 @@ impl Optimizer {
 
         // Optimize common patterns
         match (&instructions[start], &instructions[start + 1]) {
-            // PUSH x, POP -> nothing (remove both)
-            (OpCode::PushInt(_), OpCode::Dup) if start + 2 < instructions.len() => {
-                // Look for patterns like PUSH, DUP, STORE where we can optimize
-                None // Conservative for now
+            // PUSH_INT x, DUP, ADD -> PUSH_INT (x + x), and the same for MUL
+            (OpCode::PushInt(a), OpCode::Dup) => {
+                let result = match instructions.get(start + 2)? {
+                    OpCode::Add => a.checked_add(*a)?,
+                    OpCode::Mul => a.checked_mul(*a)?,
+                    _ => return None,
+                };
+                Some((vec![OpCode::PushInt(result)], 3))
             }
             // JMP addr1, JMP addr2 -> JMP addr2 (dead jump elimination)
             (OpCode::Jmp(_), OpCode::Jmp(addr2)) => {
@@ -449,6 +521,12 @@ impl Optimizer {
                         optimized.push(instruction);
                     }
                 }
+                OpCode::Destructure(ref pattern) => {
+                    for name in pattern.names() {
+                        constants.remove(name);
+                    }
+                    optimized.push(instruction);
+                }
                 // Clear constants on variables that might be modified
                 _ => {
                     optimized.push(instruction);
@@ -459,6 +537,345 @@ impl Optimizer {
         optimized
     }
 
+    // Function inlining - a CALL to a small function whose body only computes with its
+    // parameters is replaced by the body itself. The arguments are stored to temporaries
+    // private to the call site, so the inlined code never touches the caller's variables.
+    fn function_inlining_pass(&mut self, instructions: Vec<OpCode>) -> Vec<OpCode> {
+        let mut bodies: HashMap<usize, usize> = HashMap::new(); // call site -> index of the callee's RET
+        for (i, instruction) in instructions.iter().enumerate() {
+            if let OpCode::Call { addr, params } = instruction {
+                if let Some(ret) = Self::inlinable_body(&instructions, *addr, params) {
+                    bodies.insert(i, ret);
+                }
+            }
+        }
+        if bodies.is_empty() {
+            return instructions;
+        }
+
+        let mut new_index = Vec::with_capacity(instructions.len() + 1);
+        let mut next = 0;
+        for (i, instruction) in instructions.iter().enumerate() {
+            new_index.push(next);
+            next += match (instruction, bodies.get(&i)) {
+                (OpCode::Call { addr, params }, Some(ret)) => params.len() + ret - addr,
+                _ => 1,
+            };
+        }
+        new_index.push(next);
+
+        let mut optimized = Vec::with_capacity(next);
+        for (i, instruction) in instructions.iter().enumerate() {
+            let (OpCode::Call { addr, params }, Some(&ret)) = (instruction, bodies.get(&i)) else {
+                let mut instruction = instruction.clone();
+                retarget(&mut instruction, |target| new_index[target.min(instructions.len())]);
+                optimized.push(instruction);
+                continue;
+            };
+            let temp = |param: &String| format!("__inline{}_{}", self.stats.inlined_calls, param);
+            // CALL pops the last argument first
+            for param in params.iter().rev() {
+                optimized.push(OpCode::Store(temp(param)));
+            }
+            let body_start = optimized.len();
+            for instruction in &instructions[*addr..ret] {
+                let mut instruction = match instruction {
+                    OpCode::Load(param) => OpCode::Load(temp(param)),
+                    other => other.clone(),
+                };
+                retarget(&mut instruction, |target| body_start + target - addr);
+                optimized.push(instruction);
+            }
+            self.stats.inlined_calls += 1;
+        }
+        optimized
+    }
+
+    // The index of the RET ending the function at `addr`, if its body is short, reads no
+    // variables except its parameters, and only jumps within itself. Calls that check
+    // argument types are left alone.
+    fn inlinable_body(instructions: &[OpCode], addr: usize, params: &[String]) -> Option<usize> {
+        if variadic_params(params).is_some() || params.iter().any(|param| param_annotation(param).1.is_some()) {
+            return None;
+        }
+        let limit = instructions.len().min(addr + INLINE_MAX_INSTRUCTIONS + 1);
+        let ret = (addr..limit).find(|&i| matches!(instructions[i], OpCode::Ret))?;
+        let inlinable = instructions[addr..ret].iter().all(|instruction| match instruction {
+            OpCode::Load(var) => params.contains(var),
+            OpCode::Jmp(target) | OpCode::Jz(target) => (addr..=ret).contains(target),
+            OpCode::Dup | OpCode::MakeList(_) | OpCode::Len | OpCode::Index | OpCode::Print => true,
+            other => pure_operand_count(other).is_some(),
+        });
+        inlinable.then_some(ret)
+    }
+
+    // Common subexpression elimination - within a basic block, an expression built only from
+    // constants, loads and pure operators that is computed more than once is saved to a
+    // temporary the first time and loaded from it afterwards. A STORE or DELETE of a variable
+    // the expression reads ends its reuse.
+    fn common_subexpression_pass(&mut self, instructions: Vec<OpCode>) -> Vec<OpCode> {
+        let groups = Self::repeated_expressions(&instructions);
+
+        // Longest expressions first, so a repeated expression wins over the pieces inside it
+        let mut order: Vec<&Vec<(usize, usize)>> = groups.iter().collect();
+        order.sort_by_key(|spans| (std::cmp::Reverse(spans[0].1 - spans[0].0), spans[0].0));
+
+        let mut claimed: Vec<(usize, usize)> = Vec::new();
+        let mut first_uses: HashMap<usize, String> = HashMap::new(); // end of first span -> temp
+        let mut reuses: HashMap<usize, (usize, String)> = HashMap::new(); // start -> (end, temp)
+        for spans in order {
+            let free: Vec<(usize, usize)> = spans.iter()
+                .copied()
+                .filter(|&(start, end)| claimed.iter().all(|&(s, e)| end <= s || start >= e))
+                .collect();
+            let length = spans[0].1 - spans[0].0;
+            // Each reuse saves length - 1 instructions; saving the first result costs DUP and STORE
+            if free.len() < 2 || (free.len() - 1) * (length - 1) <= 2 {
+                continue;
+            }
+            let temp = format!("__cse{}", first_uses.len());
+            first_uses.insert(free[0].1, temp.clone());
+            for &(start, end) in &free[1..] {
+                reuses.insert(start, (end, temp.clone()));
+            }
+            self.stats.common_subexpressions_eliminated += free.len() - 1;
+            claimed.extend(free);
+        }
+        if reuses.is_empty() {
+            return instructions;
+        }
+
+        let mut optimized = Vec::with_capacity(instructions.len());
+        let mut new_index = vec![0; instructions.len() + 1];
+        let mut i = 0;
+        while i < instructions.len() {
+            new_index[i] = optimized.len();
+            if let Some((end, temp)) = reuses.get(&i) {
+                optimized.push(OpCode::Load(temp.clone()));
+                i = *end;
+                continue;
+            }
+            optimized.push(instructions[i].clone());
+            i += 1;
+            if let Some(temp) = first_uses.get(&i) {
+                optimized.push(OpCode::Dup);
+                optimized.push(OpCode::Store(temp.clone()));
+            }
+        }
+        new_index[instructions.len()] = optimized.len();
+
+        for instruction in &mut optimized {
+            retarget(instruction, |target| new_index[target.min(instructions.len())]);
+        }
+        optimized
+    }
+
+    // Spans [start, end) of pure expressions (at least one operator) that are computed more
+    // than once in a basic block with nothing in between changing the variables they read
+    fn repeated_expressions(instructions: &[OpCode]) -> Vec<Vec<(usize, usize)>> {
+        let leaders = block_leaders(instructions);
+
+        // Symbolic stack: the span and variables of each value still known to be pure
+        let mut stack: Vec<Option<(usize, usize, Vec<String>)>> = Vec::new();
+        let mut available: HashMap<String, (usize, Vec<String>)> = HashMap::new(); // key -> (group, reads)
+        let mut groups: Vec<Vec<(usize, usize)>> = Vec::new();
+        for (i, instruction) in instructions.iter().enumerate() {
+            if leaders.contains(&i) {
+                stack.clear();
+                available.clear();
+            }
+            if let OpCode::Load(var) = instruction {
+                stack.push(Some((i, i + 1, vec![var.clone()])));
+                continue;
+            }
+            let Some(operands) = pure_operand_count(instruction) else {
+                match instruction {
+                    OpCode::Store(var) | OpCode::Delete(var) => {
+                        if matches!(instruction, OpCode::Store(_)) {
+                            stack.pop();
+                        }
+                        available.retain(|_, (_, reads)| !reads.contains(var));
+                    }
+                    OpCode::Print | OpCode::PrintRaw | OpCode::PrintErr => {
+                        stack.pop();
+                    }
+                    _ => {
+                        stack.clear();
+                        available.clear();
+                    }
+                }
+                continue;
+            };
+
+            // The operands must be pure and sit right before this instruction
+            let mut start = i;
+            let mut reads = Vec::new();
+            let mut pure = stack.len() >= operands;
+            for _ in 0..operands {
+                match stack.pop().flatten() {
+                    Some((operand_start, operand_end, operand_reads)) if pure => {
+                        pure = operand_end == start;
+                        start = operand_start;
+                        reads.extend(operand_reads);
+                    }
+                    _ => pure = false,
+                }
+            }
+            if !pure {
+                stack.push(None);
+                continue;
+            }
+            stack.push(Some((start, i + 1, reads.clone())));
+            if operands == 0 {
+                continue;
+            }
+
+            let key = instructions[start..=i].iter().map(|op| format!("{:?}", op)).collect::<Vec<_>>().join(" ");
+            match available.get(&key) {
+                Some((group, _)) => groups[*group].push((start, i + 1)),
+                None => {
+                    available.insert(key, (groups.len(), reads));
+                    groups.push(vec![(start, i + 1)]);
+                }
+            }
+        }
+        groups.retain(|spans| spans.len() > 1);
+        groups
+    }
+
+    // Dead store elimination - a STORE whose value is overwritten by a later STORE of the same
+    // variable, or dropped by RET, without being read in between is removed together with the
+    // pure expression that computed it. Stores to exported variables and stores inside a TRY
+    // are kept, and anything that could read variables by name (a CALL, a closure, a jump to
+    // code that runs later) makes every variable live again.
+    fn dead_store_elimination_pass(&mut self, mut instructions: Vec<OpCode>) -> Vec<OpCode> {
+        loop {
+            let dead = Self::dead_stores(&instructions);
+            if dead.is_empty() {
+                return instructions;
+            }
+            self.stats.dead_stores_removed += dead.len();
+
+            let removed: HashSet<usize> = dead.iter().flat_map(|&(start, store)| start..=store).collect();
+            let mut new_index = vec![0; instructions.len() + 1];
+            let mut kept = 0;
+            for (i, slot) in new_index.iter_mut().enumerate() {
+                *slot = kept;
+                if i < instructions.len() && !removed.contains(&i) {
+                    kept += 1;
+                }
+            }
+            let old_len = instructions.len();
+            instructions = instructions.into_iter()
+                .enumerate()
+                .filter(|(i, _)| !removed.contains(i))
+                .map(|(_, mut instruction)| {
+                    retarget(&mut instruction, |target| new_index[target.min(old_len)]);
+                    instruction
+                })
+                .collect();
+        }
+    }
+
+    // (start of the feeding expression, index of the STORE) for each removable dead store
+    fn dead_stores(instructions: &[OpCode]) -> Vec<(usize, usize)> {
+        let leaders = block_leaders(instructions);
+        let mut exported = HashSet::new();
+        let mut protected = vec![false; instructions.len()];
+        for (i, instruction) in instructions.iter().enumerate() {
+            match instruction {
+                OpCode::Export(name) => { exported.insert(name.clone()); }
+                OpCode::Try { catch_addr } => {
+                    for slot in protected.iter_mut().take(*catch_addr).skip(i) {
+                        *slot = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Where the value of each STORE came from, when it is a pure expression right before it
+        let mut feeds: HashMap<usize, usize> = HashMap::new();
+        let mut stack: Vec<Option<(usize, usize)>> = Vec::new();
+        for (i, instruction) in instructions.iter().enumerate() {
+            if leaders.contains(&i) {
+                stack.clear();
+            }
+            if let OpCode::Load(_) = instruction {
+                stack.push(Some((i, i + 1)));
+                continue;
+            }
+            let Some(operands) = pure_operand_count(instruction) else {
+                match instruction {
+                    OpCode::Store(_) => {
+                        if let Some(Some((start, end))) = stack.pop() {
+                            if end == i {
+                                feeds.insert(i, start);
+                            }
+                        }
+                    }
+                    OpCode::Print | OpCode::PrintRaw | OpCode::PrintErr => {
+                        stack.pop();
+                    }
+                    OpCode::Delete(_) => {}
+                    _ => stack.clear(),
+                }
+                continue;
+            };
+            let mut start = i;
+            let mut pure = stack.len() >= operands;
+            for _ in 0..operands {
+                match stack.pop().flatten() {
+                    Some((operand_start, operand_end)) if pure => {
+                        pure = operand_end == start;
+                        start = operand_start;
+                    }
+                    _ => pure = false,
+                }
+            }
+            stack.push(pure.then_some((start, i + 1)));
+        }
+
+        // Backwards through each block: `dead` holds variables that are stored again before
+        // anything reads them; after a RET every variable not in `live` is dead
+        let mut dead_stores = Vec::new();
+        let mut dead: HashSet<&String> = HashSet::new();
+        let mut live: HashSet<&String> = HashSet::new();
+        let mut returning = false;
+        for (i, instruction) in instructions.iter().enumerate().rev() {
+            match instruction {
+                OpCode::Store(var) => {
+                    let is_dead = if returning { !live.contains(var) } else { dead.contains(var) };
+                    if is_dead && !exported.contains(var) && !protected[i] {
+                        if let Some(&start) = feeds.get(&i) {
+                            dead_stores.push((start, i));
+                        }
+                    }
+                    live.remove(var);
+                    dead.insert(var);
+                }
+                OpCode::Load(var) | OpCode::Delete(var) => {
+                    live.insert(var);
+                    dead.remove(var);
+                }
+                OpCode::Ret | OpCode::RetN(_) => {
+                    returning = true;
+                    live.clear();
+                    dead.clear();
+                }
+                OpCode::Dup | OpCode::MakeList(_) | OpCode::Len | OpCode::Index
+                | OpCode::Print | OpCode::PrintRaw | OpCode::PrintErr => {}
+                other if pure_operand_count(other).is_some() => {}
+                _ => {
+                    returning = false;
+                    live.clear();
+                    dead.clear();
+                }
+            }
+        }
+        dead_stores
+    }
+
     // Instruction combining pass - combines multiple instructions into more efficient forms
     fn instruction_combining_pass(&mut self, instructions: Vec<OpCode>) -> Vec<OpCode> {
         let mut optimized = Vec::new();
@@ -593,6 +1010,13 @@ impl Optimizer {
 
 // Analysis utilities
 impl Optimizer {
+    // Indices of instructions no path from the entry point reaches, in order. Reported by
+    // `ttvm optimize` as likely mistakes; dead code elimination removes them.
+    pub fn unreachable_instructions(&self, instructions: &[OpCode]) -> Vec<usize> {
+        let reachable = self.find_reachable_instructions(instructions);
+        (0..instructions.len()).filter(|i| !reachable.contains(i)).collect()
+    }
+
     pub fn analyze_program(&self, instructions: &[OpCode]) -> ProgramAnalysis {
         let mut analysis = ProgramAnalysis::new();
         
@@ -638,4 +1062,64 @@ impl ProgramAnalysis {
             jump_count: 0,
         }
     }
-}
\ No newline at end of file
+}
+
+// Number of values a constant or side-effect-free operator pops, or None for anything else
+fn pure_operand_count(instruction: &OpCode) -> Option<usize> {
+    match instruction {
+        OpCode::PushInt(_) | OpCode::PushFloat(_) | OpCode::PushStr(_) | OpCode::PushBool(_)
+        | OpCode::True | OpCode::False | OpCode::Null => Some(0),
+        OpCode::Not | OpCode::Neg | OpCode::Sqrt | OpCode::Abs | OpCode::Floor | OpCode::Ceil | OpCode::Round => Some(1),
+        OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div | OpCode::AddF | OpCode::SubF
+        | OpCode::MulF | OpCode::DivF | OpCode::Concat | OpCode::Eq | OpCode::Ne | OpCode::Gt
+        | OpCode::Lt | OpCode::Ge | OpCode::Le | OpCode::EqF | OpCode::NeF | OpCode::GtF
+        | OpCode::LtF | OpCode::GeF | OpCode::LeF | OpCode::And | OpCode::Or | OpCode::Pow => Some(2),
+        _ => None,
+    }
+}
+
+// Replace PUSH_INT a, PUSH_INT b, ADD / SUB / MUL at the end of `optimized` with the result,
+// unless the expression starts before `block_start` or would overflow
+fn collapse_constant_tail(optimized: &mut Vec<OpCode>, block_start: usize) -> bool {
+    let Some(start) = optimized.len().checked_sub(3).filter(|&start| start >= block_start) else {
+        return false;
+    };
+    let result = match &optimized[start..] {
+        [OpCode::PushInt(a), OpCode::PushInt(b), OpCode::Add] => a.checked_add(*b),
+        [OpCode::PushInt(a), OpCode::PushInt(b), OpCode::Sub] => a.checked_sub(*b),
+        [OpCode::PushInt(a), OpCode::PushInt(b), OpCode::Mul] => a.checked_mul(*b),
+        _ => None,
+    };
+    let Some(result) = result else {
+        return false;
+    };
+    optimized.truncate(start);
+    optimized.push(OpCode::PushInt(result));
+    true
+}
+
+// Instructions that start a basic block: every jump, call and handler target
+fn block_leaders(instructions: &[OpCode]) -> HashSet<usize> {
+    let mut leaders = HashSet::new();
+    for instruction in instructions {
+        match instruction {
+            OpCode::Jmp(target) | OpCode::Jz(target) => { leaders.insert(*target); }
+            OpCode::Call { addr, .. } | OpCode::MakeFunction { addr, .. } | OpCode::MakeLambda { addr, .. } => {
+                leaders.insert(*addr);
+            }
+            OpCode::Try { catch_addr } => { leaders.insert(*catch_addr); }
+            _ => {}
+        }
+    }
+    leaders
+}
+
+// Rewrite the instruction address an instruction refers to, if any
+pub(crate) fn retarget(instruction: &mut OpCode, map: impl Fn(usize) -> usize) {
+    match instruction {
+        OpCode::Jmp(target) | OpCode::Jz(target) => *target = map(*target),
+        OpCode::Call { addr, .. } | OpCode::MakeFunction { addr, .. } | OpCode::MakeLambda { addr, .. } => *addr = map(*addr),
+        OpCode::Try { catch_addr } => *catch_addr = map(*catch_addr),
+        _ => {}
+    }
+}
@@ -28,8 +28,13 @@ pub struct Profiler {
     pub peak_heap_size: usize,
     pub current_function_stack: Vec<(String, FunctionProfiler)>,
     pub call_depth: usize,
+    pub folded_stacks: HashMap<String, usize>, // call path ("main;f;g") -> instructions executed there
+    current_path: String,
 }
 
+// Root of every call path in the folded stacks: code outside any function
+const ROOT_FRAME: &str = "main";
+
 impl Profiler {
     pub fn new() -> Self {
         Profiler {
@@ -41,11 +46,15 @@ impl Profiler {
             peak_heap_size: 0,
             current_function_stack: Vec::new(),
             call_depth: 0,
+            folded_stacks: HashMap::new(),
+            current_path: ROOT_FRAME.to_string(),
         }
     }
 
     pub fn start_function(&mut self, function_name: String) {
         self.call_depth += 1;
+        self.current_path.push(';');
+        self.current_path.push_str(&function_name);
         let profiler = FunctionProfiler::new();
         self.current_function_stack.push((function_name.clone(), profiler));
         
@@ -56,6 +65,9 @@ impl Profiler {
     pub fn end_function(&mut self) -> Option<String> {
         if let Some((function_name, profiler)) = self.current_function_stack.pop() {
             self.call_depth = self.call_depth.saturating_sub(1);
+            if let Some(caller_end) = self.current_path.rfind(';') {
+                self.current_path.truncate(caller_end);
+            }
             
             let elapsed = profiler.start_time.elapsed();
             
@@ -76,6 +88,25 @@ impl Profiler {
         if let Some((_, profiler)) = self.current_function_stack.last_mut() {
             profiler.instruction_count += 1;
         }
+        match self.folded_stacks.get_mut(&self.current_path) {
+            Some(count) => *count += 1,
+            None => {
+                self.folded_stacks.insert(self.current_path.clone(), 1);
+            }
+        }
+    }
+
+    // The folded stacks in the format flamegraph.pl and inferno read: one line per call path,
+    // frames joined by `;` from the outermost in, then the number of instructions executed
+    // with exactly that path on the stack. Lines are sorted by path.
+    pub fn folded_output(&self) -> String {
+        let mut paths: Vec<_> = self.folded_stacks.iter().collect();
+        paths.sort();
+        paths.iter().map(|(path, count)| format!("{} {}\n", path, count)).collect()
+    }
+
+    pub fn write_folded_stacks(&self, path: &str) -> std::io::Result<()> {
+        std::fs::write(path, self.folded_output())
     }
 
     pub fn update_stack_depth(&mut self, depth: usize) {
@@ -1,2 +1,283 @@
-// Test harness utilities will be implemented here
-// This file is a placeholder for now
\ No newline at end of file
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+
+use crate::bytecode::opcode_to_text;
+use crate::ir::analysis::StackAnalysis;
+use crate::ir::lowering::StackToRegisterLowering;
+use crate::ir::vm::RegisterVM;
+use crate::vm::{OpCode, Value, VM};
+
+// Differential fuzzing of the stack VM against the register IR VM. Each case is a random
+// straight-line program of arithmetic, comparison, stack and variable opcodes; both engines
+// must end with the same stack and printed output, or fail with the same error.
+
+pub const DEFAULT_FUZZ_SEED: u64 = 0x7474_766d;
+pub const DEFAULT_FUZZ_CASES: usize = 200;
+const MAX_PROGRAM_LEN: u64 = 40;
+const VARIABLES: [&str; 3] = ["a", "b", "c"];
+
+const BINARY_OPS: [OpCode; 23] = [
+    OpCode::Add, OpCode::Sub, OpCode::Mul, OpCode::Div,
+    OpCode::AddF, OpCode::SubF, OpCode::MulF, OpCode::DivF,
+    OpCode::Eq, OpCode::Ne, OpCode::Gt, OpCode::Lt, OpCode::Ge, OpCode::Le,
+    OpCode::EqF, OpCode::NeF, OpCode::GtF, OpCode::LtF, OpCode::GeF, OpCode::LeF,
+    OpCode::And, OpCode::Or, OpCode::Concat,
+];
+
+// SplitMix64: small, fast and good enough to drive program generation reproducibly
+pub struct FuzzRng {
+    state: u64,
+}
+
+impl FuzzRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform value in 0..bound
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+}
+
+// The program for one case. Each case has its own generator so a failure can be
+// reproduced from the seed and case number alone.
+pub fn fuzz_case(seed: u64, case: usize) -> Vec<OpCode> {
+    let mut rng = FuzzRng::new(seed ^ (case as u64).wrapping_mul(0x2545_f491_4f6c_dd1d));
+    generate_program(&mut rng)
+}
+
+// Programs are built one instruction at a time against the stack the stack VM actually
+// has at that point, so most operations get operands they accept and run deep enough to
+// matter. One program in four may also pick operations blindly, to cover the error paths.
+pub fn generate_program(rng: &mut FuzzRng) -> Vec<OpCode> {
+    let len = 1 + rng.below(MAX_PROGRAM_LEN) as usize;
+    let mut program = Vec::with_capacity(len + 1);
+    let mut stored: Vec<&str> = Vec::new();
+    let blind = rng.below(4) == 0;
+
+    for _ in 0..len {
+        let Some(stack) = stack_after(&program) else {
+            break;
+        };
+        let depth = stack.len();
+        let instruction = match rng.below(12) {
+            0..=4 => random_push(rng),
+            5..=6 if depth >= 2 => {
+                let (a, b) = (&stack[depth - 2], &stack[depth - 1]);
+                let fitting: Vec<&OpCode> = BINARY_OPS.iter().filter(|op| accepts(op, a, b)).collect();
+                if blind && (fitting.is_empty() || rng.below(4) == 0) {
+                    rng.pick(&BINARY_OPS).clone()
+                } else if fitting.is_empty() {
+                    random_push(rng)
+                } else {
+                    (*rng.pick(&fitting)).clone()
+                }
+            }
+            7 if depth >= 1 && (blind || matches!(stack[depth - 1], Value::Bool(_) | Value::Int(_))) => {
+                rng.pick(&[OpCode::Dup, OpCode::Not]).clone()
+            }
+            7 if depth >= 1 => OpCode::Dup,
+            8 if depth >= 1 => OpCode::Print,
+            9 if depth >= 1 => {
+                let name = *rng.pick(&VARIABLES);
+                if !stored.contains(&name) {
+                    stored.push(name);
+                }
+                OpCode::Store(name.to_string())
+            }
+            10 if !stored.is_empty() => OpCode::Load(rng.pick(&stored).to_string()),
+            // Occasionally load something never stored, so both engines must report it
+            11 if blind && rng.below(4) == 0 => OpCode::Load("undefined".to_string()),
+            _ => random_push(rng),
+        };
+        program.push(instruction);
+    }
+
+    program.push(OpCode::Halt);
+    program
+}
+
+// The stack after running `program`, or None once it has failed
+fn stack_after(program: &[OpCode]) -> Option<Vec<Value>> {
+    let mut prefix = silence_prints(program);
+    prefix.push(OpCode::Halt);
+    let mut vm = VM::new(prefix);
+    vm.run().ok().map(|_| vm.stack)
+}
+
+// Whether the stack VM accepts `a` and `b` (b on top) as operands of a binary operation
+fn accepts(op: &OpCode, a: &Value, b: &Value) -> bool {
+    let numeric = |v: &Value| matches!(v, Value::Int(_) | Value::Float(_));
+    match op {
+        OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div => numeric(a) && numeric(b),
+        OpCode::Gt | OpCode::Lt | OpCode::Ge | OpCode::Le => matches!((a, b), (Value::Int(_), Value::Int(_))),
+        OpCode::Eq | OpCode::Ne => std::mem::discriminant(a) == std::mem::discriminant(b),
+        OpCode::And | OpCode::Or => matches!((a, b), (Value::Bool(_), Value::Bool(_))),
+        OpCode::Concat => matches!((a, b), (Value::Str(_), Value::Str(_))),
+        _ => matches!((a, b), (Value::Float(_), Value::Float(_))),
+    }
+}
+
+fn random_push(rng: &mut FuzzRng) -> OpCode {
+    match rng.below(10) {
+        0..=3 => OpCode::PushInt(rng.below(41) as i64 - 20),
+        // Large values exercise the overflow checks
+        4 => OpCode::PushInt(*rng.pick(&[i64::MAX, -i64::MAX, 0])),
+        5..=6 => OpCode::PushFloat((rng.below(81) as f64 - 40.0) / 4.0),
+        7 => OpCode::PushBool(rng.below(2) == 0),
+        8 => rng.pick(&[OpCode::True, OpCode::False, OpCode::Null]).clone(),
+        _ => OpCode::PushStr(rng.pick(&["", "x", "ab"]).to_string()),
+    }
+}
+
+// What a program left behind: the final stack (bottom first), or the error it stopped with
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Stack(Vec<String>),
+    Error(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Stack(values) => write!(f, "stack [{}]", values.join(", ")),
+            Outcome::Error(message) => write!(f, "error: {}", message),
+        }
+    }
+}
+
+pub fn run_on_stack_vm(program: &[OpCode]) -> Outcome {
+    let mut vm = VM::new(program.to_vec());
+    match vm.run() {
+        Ok(()) => Outcome::Stack(vm.stack.iter().map(|value| format!("{:?}", value)).collect()),
+        Err(e) => Outcome::Error(e.to_string()),
+    }
+}
+
+pub fn run_on_ir_vm(program: &[OpCode]) -> Outcome {
+    let block = match StackToRegisterLowering::lower(program) {
+        Ok(block) => block,
+        Err(e) => return Outcome::Error(format!("lowering failed: {}", e)),
+    };
+    // Stack slot n lives in register n, so the final stack is the registers below the
+    // depth the analysis gives for the closing HALT
+    let depth = StackAnalysis::run(program)
+        .ok()
+        .and_then(|analysis| analysis.depth(program.len() - 1))
+        .unwrap_or(0) as usize;
+
+    let mut vm = RegisterVM::new(block);
+    match vm.run() {
+        Ok(_) => Outcome::Stack(vm.registers.iter().take(depth).map(|value| format!("{:?}", value)).collect()),
+        Err(e) => Outcome::Error(e.to_string()),
+    }
+}
+
+// PRINT goes straight to stdout, so the in-process runs store printed values into a scratch
+// variable instead and leave the printing to the CLI runs
+fn silence_prints(program: &[OpCode]) -> Vec<OpCode> {
+    program
+        .iter()
+        .map(|instruction| match instruction {
+            OpCode::Print => OpCode::Store("printed".to_string()),
+            other => other.clone(),
+        })
+        .collect()
+}
+
+// Run a program through the ttvm binary and collect what it printed, without the lines
+// that only say which engine ran
+fn printed_output(ttvm: &Path, file: &Path, use_ir: bool) -> Result<String, String> {
+    let engine = if use_ir { "--use-ir" } else { "--no-smp" };
+    let output = Command::new(ttvm)
+        .arg(engine)
+        .arg(file)
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", ttvm.display(), e))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let printed = stdout
+        .lines()
+        .filter(|line| {
+            !line.starts_with("Debug: Using regular VM") &&
+            !line.starts_with("SMP enabled flag:") &&
+            !line.starts_with("Running with IR") &&
+            !line.starts_with("IR execution completed")
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok(format!("{}printed [{}]", if output.status.success() { "" } else { "failed, " }, printed))
+}
+
+#[derive(Debug)]
+pub struct FuzzMismatch {
+    pub seed: u64,
+    pub case: usize,
+    pub program: Vec<OpCode>,
+    pub stack_vm: String,
+    pub ir_vm: String,
+}
+
+impl fmt::Display for FuzzMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Stack VM and IR VM disagree (seed {}, case {})", self.seed, self.case)?;
+        writeln!(f, "  stack VM: {}", self.stack_vm)?;
+        writeln!(f, "  IR VM:    {}", self.ir_vm)?;
+        writeln!(f, "Program:")?;
+        for (addr, instruction) in self.program.iter().enumerate() {
+            writeln!(f, "  {:04}  {}", addr, opcode_to_text(instruction))?;
+        }
+        Ok(())
+    }
+}
+
+// Run `cases` generated programs on both engines. With a ttvm binary the programs are also
+// run through the CLI to compare what they print.
+pub fn run_differential_fuzz(seed: u64, cases: usize, ttvm: Option<&Path>) -> Result<(), FuzzMismatch> {
+    let file = std::env::temp_dir().join(format!("ttvm_fuzz_{}.ttvm", std::process::id()));
+    let result = (0..cases).try_for_each(|case| {
+        let program = fuzz_case(seed, case);
+        let mismatch = |stack_vm: String, ir_vm: String| FuzzMismatch {
+            seed,
+            case,
+            program: program.clone(),
+            stack_vm,
+            ir_vm,
+        };
+
+        let silent = silence_prints(&program);
+        let stack_outcome = run_on_stack_vm(&silent);
+        let ir_outcome = run_on_ir_vm(&silent);
+        if stack_outcome != ir_outcome {
+            return Err(mismatch(stack_outcome.to_string(), ir_outcome.to_string()));
+        }
+
+        if let Some(ttvm) = ttvm {
+            let source: String = program.iter().map(|op| opcode_to_text(op) + "\n").collect();
+            std::fs::write(&file, source)
+                .map_err(|e| mismatch(format!("cannot write {}: {}", file.display(), e), String::new()))?;
+            let stack_printed = printed_output(ttvm, &file, false).unwrap_or_else(|e| e);
+            let ir_printed = printed_output(ttvm, &file, true).unwrap_or_else(|e| e);
+            if stack_printed != ir_printed {
+                return Err(mismatch(stack_printed, ir_printed));
+            }
+        }
+        Ok(())
+    });
+    let _ = std::fs::remove_file(&file);
+    result
+}
@@ -3,6 +3,7 @@ use colored::*;
 use crate::{VMConfig, OutputMode};
 use crate::vm::{OpCode, VM};
 use crate::gc::GcStats;
+use super::harness;
 
 #[derive(Debug, Clone)]
 pub struct TestResult {
@@ -94,6 +95,31 @@ pub fn run_vm_tests(config: &VMConfig) {
         });
     }
 
+    // Test 4: Stack VM and IR VM agree on generated programs. TTVM_FUZZ_SEED picks
+    // another seed; the running binary is reused to compare printed output.
+    let seed = std::env::var("TTVM_FUZZ_SEED")
+        .ok()
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or(harness::DEFAULT_FUZZ_SEED);
+    let ttvm = std::env::current_exe().ok();
+    match harness::run_differential_fuzz(seed, harness::DEFAULT_FUZZ_CASES, ttvm.as_deref()) {
+        Ok(()) => results.push(TestResult {
+            name: "Stack/IR differential fuzz".to_string(),
+            expected: "no mismatches".to_string(),
+            actual: format!("{} programs agree (seed {})", harness::DEFAULT_FUZZ_CASES, seed),
+            passed: true,
+        }),
+        Err(mismatch) => {
+            eprintln!("{}", mismatch);
+            results.push(TestResult {
+                name: "Stack/IR differential fuzz".to_string(),
+                expected: "no mismatches".to_string(),
+                actual: format!("mismatch in case {} (seed {})", mismatch.case, seed),
+                passed: false,
+            });
+        }
+    }
+
     report_test_results(&results, config);
 }
 
@@ -167,6 +193,22 @@ pub fn report_gc_stats(stats: &GcStats, config: &VMConfig) {
                 Cell::new("Collections Performed").fg(Color::White),
                 Cell::new(&stats.collections_performed.to_string()).fg(Color::Magenta),
             ]);
+            table.add_row(vec![
+                Cell::new("Live Heap Objects").fg(Color::White),
+                Cell::new(stats.live_objects.to_string()).fg(Color::Blue),
+            ]);
+            table.add_row(vec![
+                Cell::new("Heap Bytes (approx.)").fg(Color::White),
+                Cell::new(stats.heap_bytes.to_string()).fg(Color::Blue),
+            ]);
+            table.add_row(vec![
+                Cell::new("Total GC Pause").fg(Color::White),
+                Cell::new(format_pause(stats.total_pause)).fg(Color::Magenta),
+            ]);
+            table.add_row(vec![
+                Cell::new("Longest GC Pause").fg(Color::White),
+                Cell::new(format_pause(stats.longest_pause)).fg(Color::Magenta),
+            ]);
 
             println!("{}", "═══ GC Statistics ═══".bright_cyan().bold());
             println!("{table}");
@@ -181,6 +223,18 @@ pub fn report_gc_stats(stats: &GcStats, config: &VMConfig) {
                      format!("{}", stats.current_allocated).yellow());
             println!("{}: {}", "Collections performed".bright_cyan(), 
                      format!("{}", stats.collections_performed).magenta());
+            println!("{}: {}", "Live heap objects".bright_cyan(), 
+                     format!("{}", stats.live_objects).blue());
+            println!("{}: {}", "Heap bytes (approx.)".bright_cyan(), 
+                     format!("{}", stats.heap_bytes).blue());
+            println!("{}: {}", "Total GC pause".bright_cyan(), 
+                     format_pause(stats.total_pause).magenta());
+            println!("{}: {}", "Longest GC pause".bright_cyan(), 
+                     format_pause(stats.longest_pause).magenta());
         }
     }
+}
+
+fn format_pause(pause: std::time::Duration) -> String {
+    format!("{:.3} ms", pause.as_secs_f64() * 1000.0)
 }
\ No newline at end of file
@@ -1,4 +1,5 @@
 use std::fmt;
+use crate::vm::ProcId;
 
 #[derive(Debug, Clone)]
 pub enum VMError {
@@ -7,17 +8,31 @@ pub enum VMError {
     UndefinedVariable(String),
     IndexOutOfBounds { index: usize, length: usize },
     CallStackUnderflow,
+    CallStackOverflow { depth: usize },
+    StackOverflow { limit: usize },
+    ReturnCountMismatch { declared: usize, left: usize },
     NoVariableScope,
     FileError { filename: String, error: String },
+    NetworkError { url: String, error: String },
+    DatabaseError { database: String, error: String },
+    InvalidPattern { pattern: String, error: String },
+    PatternMismatch { pattern: String, reason: String },
     ParseError { line: usize, instruction: String },
+    UnknownOpcode { line: usize, mnemonic: String, suggestion: Option<String> },
+    InvalidBytecode { filename: String, reason: String },
     InsufficientStackItems { needed: usize, available: usize },
-    UnknownLabel(String),
     UnsupportedOperation(String),
     RuntimeError(String),
     TypeError(String),
     DivisionByZero,
+    IntegerOverflow { operation: String },
+    AssertionFailed { message: String },
+    NotANumber { operation: String },
     CircularDependency(String),
     InfiniteLoop,
+    Deadlock { waiting: Vec<ProcId> },
+    ProcessExit(i32),
+    AtSourceLine { line: usize, ip: usize, error: Box<VMError> },
 }
 
 impl fmt::Display for VMError {
@@ -30,20 +45,49 @@ impl fmt::Display for VMError {
             VMError::IndexOutOfBounds { index, length } => 
                 write!(f, "Index {} out of bounds for list of length {}", index, length),
             VMError::CallStackUnderflow => write!(f, "Call stack underflow"),
+            VMError::CallStackOverflow { depth } => 
+                write!(f, "Call stack overflow: maximum call depth of {} exceeded", depth),
+            VMError::StackOverflow { limit } =>
+                write!(f, "Stack overflow: more than {} values on the operand stack", limit),
+            VMError::ReturnCountMismatch { declared, left } =>
+                write!(f, "RET {} expects the function to leave {} value(s), but it left {}", declared, declared, left),
             VMError::NoVariableScope => write!(f, "No variable scope available"),
             VMError::FileError { filename, error } => 
                 write!(f, "File operation failed on {}: {}", filename, error),
+            VMError::NetworkError { url, error } => 
+                write!(f, "Network request to {} failed: {}", url, error),
+            VMError::DatabaseError { database, error } => 
+                write!(f, "Database operation on {} failed: {}", database, error),
+            VMError::InvalidPattern { pattern, error } => 
+                write!(f, "Invalid regex {:?}: {}", pattern, error),
+            VMError::PatternMismatch { pattern, reason } =>
+                write!(f, "DESTRUCTURE {} does not match: {}", pattern, reason),
             VMError::ParseError { line, instruction } => 
                 write!(f, "Parse error on line {}: {}", line, instruction),
+            VMError::UnknownOpcode { line, mnemonic, suggestion } => match suggestion {
+                Some(suggestion) => write!(f, "Unknown opcode on line {}: {} (did you mean {}?)", line, mnemonic, suggestion),
+                None => write!(f, "Unknown opcode on line {}: {}", line, mnemonic),
+            },
+            VMError::InvalidBytecode { filename, reason } => 
+                write!(f, "Invalid bytecode file {}: {}", filename, reason),
             VMError::InsufficientStackItems { needed, available } => 
                 write!(f, "Need {} stack items but only {} available", needed, available),
-            VMError::UnknownLabel(label) => write!(f, "Unknown label: {}", label),
             VMError::UnsupportedOperation(op) => write!(f, "Unsupported operation: {}", op),
             VMError::RuntimeError(msg) => write!(f, "Runtime error: {}", msg),
             VMError::TypeError(msg) => write!(f, "Type error: {}", msg),
             VMError::DivisionByZero => write!(f, "Division by zero"),
+            VMError::IntegerOverflow { operation } => write!(f, "Integer overflow during {}", operation),
+            VMError::AssertionFailed { message } => write!(f, "Assertion failed: {}", message),
+            VMError::NotANumber { operation } => write!(f, "{} produced NaN", operation),
             VMError::CircularDependency(path) => write!(f, "Circular dependency detected: {}", path),
             VMError::InfiniteLoop => write!(f, "Infinite loop detected"),
+            VMError::Deadlock { waiting } => {
+                let pids: Vec<String> = waiting.iter().map(|pid| pid.to_string()).collect();
+                write!(f, "Deadlock: every process is waiting for a message that nothing can send (pids {})", pids.join(", "))
+            }
+            VMError::ProcessExit(code) => write!(f, "Program exited with status {}", code),
+            VMError::AtSourceLine { line, ip, error } => 
+                write!(f, "{} (line {}, instruction {})", error, line, ip),
         }
     }
 }
@@ -1,19 +1,105 @@
 use std::collections::HashMap;
-use std::time::Duration;
+use std::io::Write;
+use indexmap::IndexMap;
 use colored::*;
-use crate::vm::{Value, OpCode, VMError, VMResult, ExceptionHandler};
+use crate::vm::{Value, OpCode, VMError, VMResult, ExceptionHandler, float_result};
+use crate::vm::value::{format_scope, format_stack};
+use crate::vm::output::OutputSink;
+use crate::vm::json::{parse_json, to_json};
+use crate::vm::ops;
 use crate::gc::{GcEngine, GcStats, MarkSweepGc, NoGc};
 use crate::profiling::Profiler;
-use crate::bytecode::parse_program;
+use crate::bytecode::{opcode_to_text, load_program};
+
+pub const DEFAULT_CALL_DEPTH_LIMIT: usize = 10_000;
+
+// Why `run_n` gave control back to the caller
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    // The program reached HALT or ran off its end
+    Halted,
+    // Paused with work left: true when the instruction budget ran out, false when the
+    // interactive debugger was quit
+    Yielded(bool),
+    // Called with a zero budget, so nothing ran
+    Running,
+}
+
+// Breakpoint that only fires when a variable currently holds the given value
+#[derive(Debug, Clone)]
+pub struct ConditionalBreakpoint {
+    pub address: usize,
+    pub var_name: String,
+    pub value: Value,
+}
+
+// An open file stream; the kind decides what each STREAM_READ yields
+enum FileStream {
+    Lines(std::io::BufReader<std::fs::File>),                      // the next line, as a string
+    JsonLines { reader: std::io::BufReader<std::fs::File>, line: usize }, // the next non-blank line, parsed as JSON
+    Csv(csv::Reader<std::fs::File>),                               // the next row, as a list of strings
+}
+
+impl FileStream {
+    // The next value, or None at the end of the file
+    fn read_next(&mut self, stream_id: &str) -> VMResult<Option<Value>> {
+        use std::io::BufRead;
+        let file_error = |error: String| VMError::FileError { filename: stream_id.to_string(), error };
+        match self {
+            FileStream::Lines(reader) => {
+                let mut line = String::new();
+                if reader.read_line(&mut line).map_err(|e| file_error(e.to_string()))? == 0 {
+                    return Ok(None);
+                }
+                // The line ending isn't part of the line
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                Ok(Some(Value::Str(line)))
+            }
+            FileStream::JsonLines { reader, line } => {
+                let mut text = String::new();
+                loop {
+                    text.clear();
+                    if reader.read_line(&mut text).map_err(|e| file_error(e.to_string()))? == 0 {
+                        return Ok(None);
+                    }
+                    *line += 1;
+                    if !text.trim().is_empty() {
+                        break;
+                    }
+                }
+                parse_json(&text).map(Some).map_err(|e| VMError::RuntimeError(
+                    format!("STREAM_READ failed: invalid JSON on line {} of {}: {}", line, stream_id, e)
+                ))
+            }
+            FileStream::Csv(reader) => {
+                let mut record = csv::StringRecord::new();
+                let more = reader.read_record(&mut record).map_err(|e| match e.kind() {
+                    csv::ErrorKind::Io(_) => file_error(e.to_string()),
+                    _ => VMError::RuntimeError(format!("STREAM_READ failed: {}", e)),
+                })?;
+                Ok(more.then(|| Value::List(record.iter().map(|field| Value::Str(field.to_string())).collect())))
+            }
+        }
+    }
+}
 
 pub struct VM {
     pub stack: Vec<Value>,
     pub instructions: Vec<OpCode>,
     pub ip: usize,                              // instruction pointer
     pub call_stack: Vec<usize>,                 // return addresses for CALL/RET
+    call_stack_bases: Vec<usize>,               // stack height as each call_stack frame was entered, for RET n
+    call_return_types: Vec<Option<String>>,     // type each call_stack frame's result must have, if declared
     pub variables: Vec<HashMap<String, Value>>, // call frame stack
     // Exception handling
     pub try_stack: Vec<ExceptionHandler>,       // stack of try blocks
+    pub finally_stack: Vec<Option<Value>>,      // running FINALLY blocks and the exception each rethrows
     // Module system
     pub exports: HashMap<String, Value>,        // exported symbols from this module
     pub loaded_modules: HashMap<String, HashMap<String, Value>>, // module_path -> exports
@@ -23,15 +109,41 @@ pub struct VM {
     // Performance improvements
     pub max_stack_size: usize,                  // Track maximum stack usage
     pub instruction_count: usize,               // Count of executed instructions
+    pub max_call_depth: usize,                  // Track maximum call stack depth
+    pub call_depth_limit: usize,                // CALL beyond this depth raises CallStackOverflow
+    pub stack_limit: Option<usize>,             // more values than this on the stack raises StackOverflow (None: unbounded)
+    pub program_args: Vec<String>,              // arguments GET_ARGS returns
+    pub float_precision: Option<usize>,         // digits after the decimal point when printing floats (None: shortest exact form)
     // Debugging support
     pub debug_mode: bool,
     pub breakpoints: Vec<usize>,
+    pub conditional_breakpoints: Vec<ConditionalBreakpoint>,
+    pub breakpoint_hits: usize,                 // Number of times execution paused at a breakpoint
+    pub watchpoints: Vec<String>,               // Variables whose every change is reported
+    pub watchpoint_hits: usize,                 // Number of changes seen to watched variables
+    watch_pause: bool,                          // A watched variable changed; pause before the next instruction
+    pub interactive: bool,                      // Pause at breakpoints and read debugger commands from stdin
+    pub step_mode: bool,                        // Pause before the next instruction (set by `step`)
+    pub source_lines: Option<Vec<usize>>,       // instruction index -> .ttvm source line (absent for .ttb without debug info)
     // Garbage Collection
     pub gc_engine: Box<dyn GcEngine>,           // Pluggable GC engine
     pub _gc_stats_enabled: bool,                 // Whether to show GC stats
     // Profiling and Tracing
     pub profiler: Option<Profiler>,             // Optional profiler for performance analysis
     pub trace_enabled: bool,                    // Whether to enable tracing
+    pub trace_out: Option<(String, std::io::BufWriter<std::fs::File>)>, // path and writer for the instruction trace file
+    pub output: OutputSink,                     // where PRINT and friends write (stdout unless set_output replaced it)
+    // Database handles
+    pub db_connections: HashMap<String, rusqlite::Connection>, // Value::Connection id -> open database
+    // HTTP server handles
+    pub http_servers: HashMap<String, std::net::TcpListener>, // Value::Connection id -> listening socket
+    pub http_requests: HashMap<String, std::net::TcpStream>,  // Value::Connection id -> request awaiting a response
+    http_request_count: usize,
+    // File streams
+    streams: HashMap<String, FileStream>,      // Value::Stream id -> open file stream
+    stream_count: usize,
+    regex_cache: ops::RegexCache,
+    rng: ops::Random,
 }
 
 impl VM {
@@ -48,6 +160,19 @@ impl VM {
         Self::new_with_config(instructions, gc_type, debug_mode, gc_stats_enabled, false, false)
     }
 
+    // A VM set up the way `config` describes, as the CLI runs it
+    pub fn with_config(instructions: Vec<OpCode>, config: &crate::VMConfig) -> Self {
+        let profile_enabled = config.profile_enabled || config.flamegraph_out.is_some();
+        let mut vm = Self::new_with_config(instructions, &config.gc_type, config.debug_mode || config.gc_debug, config.gc_stats, config.trace_enabled, profile_enabled);
+        vm.interactive = config.interactive;
+        vm.call_depth_limit = config.call_depth_limit;
+        vm.stack_limit = config.max_stack;
+        vm.set_gc_threshold(config.gc_threshold);
+        vm.program_args = config.program_args.clone();
+        vm.float_precision = config.float_precision;
+        vm
+    }
+
     pub fn new_with_config(instructions: Vec<OpCode>, gc_type: &str, debug_mode: bool, gc_stats_enabled: bool, trace_enabled: bool, profile_enabled: bool) -> Self {
         let gc_engine: Box<dyn GcEngine> = match gc_type {
             "no-gc" => Box::new(NoGc::new()),
@@ -60,24 +185,61 @@ impl VM {
             instructions,
             ip: 0,
             call_stack: Vec::with_capacity(64), // Pre-allocate call stack
+            call_stack_bases: Vec::with_capacity(64),
+            call_return_types: Vec::with_capacity(64),
             variables: vec![HashMap::new()], // global frame
             try_stack: Vec::new(),
+            finally_stack: Vec::new(),
             exports: HashMap::new(),
             loaded_modules: HashMap::new(),
             loading_stack: Vec::new(),
             lambda_captures: HashMap::new(),
             max_stack_size: 0,
             instruction_count: 0,
+            max_call_depth: 0,
+            call_depth_limit: DEFAULT_CALL_DEPTH_LIMIT,
+            stack_limit: None,
+            program_args: Vec::new(),
+            float_precision: None,
             debug_mode,
             breakpoints: Vec::new(),
+            conditional_breakpoints: Vec::new(),
+            breakpoint_hits: 0,
+            watchpoints: Vec::new(),
+            watchpoint_hits: 0,
+            watch_pause: false,
+            interactive: false,
+            step_mode: false,
+            source_lines: None,
             gc_engine,
             _gc_stats_enabled: gc_stats_enabled,
             profiler: if profile_enabled { Some(Profiler::new()) } else { None },
             trace_enabled,
+            trace_out: None,
+            output: OutputSink::stdout(),
+            db_connections: HashMap::new(),
+            http_servers: HashMap::new(),
+            http_requests: HashMap::new(),
+            http_request_count: 0,
+            streams: HashMap::new(),
+            stream_count: 0,
+            regex_cache: HashMap::new(),
+            rng: None,
         }
     }
 
+    // Send PRINT, PRINT_RAW, DEBUG_PRINT and DUMP_SCOPE output here instead of stdout
     #[allow(dead_code)]
+    pub fn set_output(&mut self, output: OutputSink) {
+        self.output = output;
+    }
+
+    fn write_output(&mut self, text: &str) -> VMResult<()> {
+        self.output.write_all(text.as_bytes())
+            .and_then(|_| self.output.flush())
+            .map_err(|e| VMError::FileError { filename: "<output>".to_string(), error: e.to_string() })
+    }
+
     pub fn add_breakpoint(&mut self, address: usize) {
         if !self.breakpoints.contains(&address) {
             self.breakpoints.push(address);
@@ -88,17 +250,139 @@ impl VM {
     #[allow(dead_code)]
     pub fn remove_breakpoint(&mut self, address: usize) {
         self.breakpoints.retain(|&x| x != address);
+        self.conditional_breakpoints.retain(|bp| bp.address != address);
+    }
+
+    #[allow(dead_code)]
+    pub fn add_conditional_breakpoint(&mut self, address: usize, var_name: &str, value: Value) {
+        self.conditional_breakpoints.push(ConditionalBreakpoint {
+            address,
+            var_name: var_name.to_string(),
+            value,
+        });
+    }
+
+    pub fn add_watchpoint(&mut self, var_name: &str) {
+        if !self.watchpoints.iter().any(|name| name == var_name) {
+            self.watchpoints.push(var_name.to_string());
+        }
+    }
+
+    // Write one line per executed instruction to `path` instead of printing the trace
+    pub fn set_trace_file(&mut self, path: &str) -> VMResult<()> {
+        let file = std::fs::File::create(path).map_err(|e| VMError::FileError {
+            filename: path.to_string(),
+            error: e.to_string(),
+        })?;
+        self.trace_out = Some((path.to_string(), std::io::BufWriter::new(file)));
+        Ok(())
+    }
+
+    fn flush_trace(&mut self) -> VMResult<()> {
+        if let Some((path, writer)) = &mut self.trace_out {
+            writer.flush().map_err(|e| VMError::FileError { filename: path.clone(), error: e.to_string() })?;
+        }
+        Ok(())
+    }
+
+    pub fn set_source_lines(&mut self, source_lines: Vec<usize>) {
+        self.source_lines = Some(source_lines);
     }
 
-    pub fn get_stats(&self) -> (usize, usize, usize) {
-        (self.instruction_count, self.max_stack_size, self.stack.len())
+    // Source line of the instruction at `ip`, if the program came from a .ttvm file
+    pub fn source_line(&self, ip: usize) -> Option<usize> {
+        self.source_lines.as_ref().and_then(|lines| lines.get(ip).copied())
+    }
+
+    // Describe an instruction position for error messages and stack traces
+    fn location(&self, ip: usize) -> String {
+        match self.source_line(ip) {
+            Some(line) => format!("at instruction {} (line {})", ip, line),
+            None => format!("at instruction {}", ip),
+        }
+    }
+
+    // What DUMP_SCOPE prints: where execution is, the instruction after it, the call
+    // depth, the stack and the current frame's variables sorted by name
+    pub fn dump_state(&self) -> VMResult<String> {
+        let frame = self.variables.last().ok_or(VMError::NoVariableScope)?;
+        let next = match self.instructions.get(self.ip + 1) {
+            Some(instruction) => opcode_to_text(instruction),
+            None => "(end of program)".to_string(),
+        };
+        let ip = match self.source_line(self.ip) {
+            Some(line) => format!("{} (line {})", self.ip, line),
+            None => self.ip.to_string(),
+        };
+        Ok(format!("ip: {}\nnext: {}\ncall depth: {}\n{}\n{}",
+            ip, next, self.call_stack.len(), format_stack(&self.stack), format_scope(frame)))
+    }
+
+    // Stack trace for an exception raised at the current IP, innermost frame first: the
+    // current instruction, then the call site of every active CALL. Frames inside a
+    // function entered by CALL name it the way the profiler does.
+    fn backtrace(&self) -> Vec<String> {
+        let mut trace = Vec::with_capacity(self.call_stack.len() + 1);
+        let mut ip = self.ip;
+        for &return_addr in self.call_stack.iter().rev() {
+            let call_site = return_addr - 1;
+            trace.push(match self.instructions.get(call_site) {
+                Some(OpCode::Call { addr, .. }) => format!("{} in fn@0x{:04X}", self.location(ip), addr),
+                _ => self.location(ip),
+            });
+            ip = call_site;
+        }
+        trace.push(self.location(ip));
+        trace
+    }
+
+    // Whether execution should pause before the instruction at the current IP
+    fn breakpoint_triggered(&self) -> bool {
+        if self.breakpoints.contains(&self.ip) {
+            return true;
+        }
+        self.conditional_breakpoints.iter().any(|bp| {
+            bp.address == self.ip
+                && self.get_variable(&bp.var_name).map(|v| v == bp.value).unwrap_or(false)
+        })
+    }
+
+    // (instructions executed, max stack size, final stack size, live heap objects, heap bytes)
+    pub fn get_stats(&self) -> (usize, usize, usize, usize, usize) {
+        let (live_objects, heap_bytes) = self.heap_usage();
+        (self.instruction_count, self.max_stack_size, self.stack.len(), live_objects, heap_bytes)
     }
 
     pub fn get_gc_stats(&self) -> GcStats {
-        self.gc_engine.stats()
+        let (live_objects, heap_bytes) = self.heap_usage();
+        GcStats { live_objects, heap_bytes, ..self.gc_engine.stats() }
+    }
+
+    // Heap held by everything the program can still reach: the stack, every variable
+    // frame and this module's exports
+    fn heap_usage(&self) -> (usize, usize) {
+        self.stack.iter()
+            .chain(self.variables.iter().flat_map(|frame| frame.values()))
+            .chain(self.exports.values())
+            .map(Value::heap_usage)
+            .fold((0, 0), |(objects, bytes), (o, b)| (objects + o, bytes + b))
+    }
+
+    // Allocations between automatic collections, for engines that collect on their own
+    pub fn set_gc_threshold(&mut self, threshold: usize) {
+        self.gc_engine.set_threshold(threshold);
+    }
+
+    // Push a new list, object or closure and hand it to the GC, collecting once the
+    // threshold is reached
+    fn push_allocated(&mut self, value: Value) {
+        self.gc_engine.alloc(&value);
+        self.stack.push(value);
+        if self.gc_engine.should_collect() {
+            self.trigger_gc();
+        }
     }
 
-    #[allow(dead_code)]
     pub fn trigger_gc(&mut self) {
         // Collect roots from stack and variables
         let mut roots: Vec<&Value> = Vec::new();
@@ -115,6 +399,10 @@ impl VM {
             }
         }
         
+        // Exports and values captured for the next lambda are reachable too
+        roots.extend(self.exports.values());
+        roots.extend(self.lambda_captures.values());
+        
         // Mark from roots
         self.gc_engine.mark_from_roots(&roots);
         
@@ -135,6 +423,14 @@ impl VM {
         self.stack.last().ok_or_else(|| VMError::StackUnderflow(operation.to_string()))
     }
 
+    // The open database behind a DB_CONNECT handle
+    fn db_connection(&self, db_id: &str, operation: &str) -> VMResult<&rusqlite::Connection> {
+        self.db_connections.get(db_id).ok_or_else(|| VMError::DatabaseError {
+            database: db_id.to_string(),
+            error: format!("{} on a connection that is not open", operation),
+        })
+    }
+
     fn check_stack_size(&self, needed: usize, _operation: &str) -> VMResult<()> {
         if self.stack.len() < needed {
             Err(VMError::InsufficientStackItems { 
@@ -146,24 +442,97 @@ impl VM {
         }
     }
 
+    // Variable resolution: the current frame first, then the global frame (index 0).
+    // Intermediate caller frames are never consulted, so a local always shadows a
+    // global of the same name and callers' locals stay private. STORE only ever
+    // writes to the current frame (see set_variable).
     fn get_variable(&self, name: &str) -> VMResult<Value> {
-        self.variables
-            .last()
-            .ok_or(VMError::NoVariableScope)?
+        let current = self.variables.last().ok_or(VMError::NoVariableScope)?;
+        current
             .get(name)
+            .or_else(|| self.variables.first().and_then(|global| global.get(name)))
             .cloned()
             .ok_or_else(|| VMError::UndefinedVariable(name.to_string()))
     }
 
+    // The variable get_variable would read, borrowed for in-place updates
+    fn variable_mut(&mut self, name: &str) -> VMResult<&mut Value> {
+        let current = self.variables.len().checked_sub(1).ok_or(VMError::NoVariableScope)?;
+        let frame = if self.variables[current].contains_key(name) { current } else { 0 };
+        self.variables[frame]
+            .get_mut(name)
+            .ok_or_else(|| VMError::UndefinedVariable(name.to_string()))
+    }
+
     fn set_variable(&mut self, name: String, value: Value) -> VMResult<()> {
-        self.variables
-            .last_mut()
-            .ok_or(VMError::NoVariableScope)?
-            .insert(name, value);
+        let frame = self.variables.last_mut().ok_or(VMError::NoVariableScope)?;
+        if self.watchpoints.contains(&name) && frame.get(&name) != Some(&value) {
+            let old = frame.get(&name).map_or("undefined".to_string(), |old| format!("{:?}", old));
+            println!("Watchpoint {} changed at instruction {}: {} -> {:?}", name, self.ip, old, value);
+            self.watchpoint_hits += 1;
+            self.watch_pause = self.interactive;
+        }
+        frame.insert(name, value);
         Ok(())
     }
 
+    // A call is in tail position when the next instruction returns from the current
+    // function. A TRY opened in the current frame keeps the frame alive so its
+    // handler still unwinds to the right place.
+    fn in_tail_position(&self) -> bool {
+        matches!(self.instructions.get(self.ip + 1), Some(OpCode::Ret))
+            && !self.call_stack.is_empty()
+            && self.variables.len() > 1
+            && self.try_stack.last().is_none_or(|h| h.variable_frames < self.variables.len())
+    }
+
+    // Enter a function body. Tail calls reuse the current frame and return address
+    // instead of growing the call stack, unless the callee declares a return type the
+    // current frame doesn't: then it gets a frame of its own so both types are checked.
+    fn enter_function(&mut self, frame: HashMap<String, Value>, addr: usize, tail_call: bool, returns: Option<String>) -> VMResult<()> {
+        let tail_call = tail_call && (returns.is_none() || self.call_return_types.last() == Some(&returns));
+        if tail_call {
+            if let Some(current) = self.variables.last_mut() {
+                *current = frame;
+            }
+        } else {
+            if self.call_stack.len() >= self.call_depth_limit {
+                return Err(VMError::CallStackOverflow { depth: self.call_depth_limit });
+            }
+            self.call_stack.push(self.ip + 1);
+            self.call_stack_bases.push(self.stack.len());
+            self.call_return_types.push(returns);
+            self.variables.push(frame);
+            if self.call_stack.len() > self.max_call_depth {
+                self.max_call_depth = self.call_stack.len();
+            }
+        }
+        self.ip = addr;
+        Ok(())
+    }
+
+    fn bind_arguments(&mut self, params: &[String], frame: &mut HashMap<String, Value>, operation: &str) -> VMResult<()> {
+        ops::bind_arguments(&mut self.stack, params, frame, operation)
+    }
+
+    // RET from a function with a declared return type checks the result it leaves on top
+    fn check_return_type(&self) -> VMResult<()> {
+        let (Some(Some(type_name)), Some(&base)) = (self.call_return_types.last(), self.call_stack_bases.last()) else {
+            return Ok(());
+        };
+        match self.stack.last().filter(|_| self.stack.len() > base) {
+            Some(value) if value.has_type(type_name) => Ok(()),
+            result => Err(VMError::TypeMismatch {
+                expected: format!("{} as the return value", type_name),
+                got: result.map_or("nothing".to_string(), |value| format!("{:?}", value)),
+                operation: "RET".to_string(),
+            }),
+        }
+    }
+
     fn pop_call_stack(&mut self) -> VMResult<usize> {
+        self.call_stack_bases.pop();
+        self.call_return_types.pop();
         self.call_stack.pop().ok_or(VMError::CallStackUnderflow)
     }
 
@@ -183,6 +552,7 @@ impl VM {
             stack_size: self.stack.len(),
             call_stack_size: self.call_stack.len(),
             variable_frames: self.variables.len(),
+            finally_depth: self.finally_stack.len(),
         };
         self.try_stack.push(handler);
     }
@@ -197,15 +567,27 @@ impl VM {
         
         // Unwind call stack
         self.call_stack.truncate(handler.call_stack_size);
+        self.call_stack_bases.truncate(handler.call_stack_size);
+        self.call_return_types.truncate(handler.call_stack_size);
         
         // Unwind variable frames
         self.variables.truncate(handler.variable_frames);
+
+        // Abandon FINALLY blocks the exception escaped from
+        self.finally_stack.truncate(handler.finally_depth);
     }
 
     fn throw_exception(&mut self, exception: Value) -> VMResult<()> {
         if let Some(handler) = self.pop_exception_handler() {
             // Unwind to the try block state
             self.unwind_to_exception_handler(&handler);
+
+            if matches!(self.instructions.get(handler.catch_addr), Some(OpCode::Finally)) {
+                // Run the cleanup block, which rethrows the exception at END_FINALLY
+                self.finally_stack.push(Some(exception));
+                self.ip = handler.catch_addr + 1;
+                return Ok(());
+            }
             
             // Push the exception onto the stack for the catch block
             self.stack.push(exception);
@@ -228,82 +610,189 @@ impl VM {
 
     pub fn run(&mut self) -> VMResult<()> {
         while self.ip < self.instructions.len() {
-            // Performance tracking
-            self.instruction_count += 1;
-            if self.stack.len() > self.max_stack_size {
-                self.max_stack_size = self.stack.len();
+            if self.step_instruction()?.is_some() {
+                break;
             }
+        }
+        self.flush_trace()
+    }
 
-            // Profiling support
-            if let Some(ref mut profiler) = self.profiler {
-                profiler.record_instruction();
-                profiler.update_stack_depth(self.stack.len());
+    // Execute at most `max_instructions` instructions. The VM keeps its ip, stack and
+    // variables between calls, so running a program in chunks ends the same way as `run`.
+    #[allow(dead_code)]
+    pub fn run_n(&mut self, max_instructions: usize) -> VMResult<RunStatus> {
+        if max_instructions == 0 {
+            return Ok(if self.is_finished() { RunStatus::Halted } else { RunStatus::Running });
+        }
+        for _ in 0..max_instructions {
+            if self.ip >= self.instructions.len() {
+                return Ok(RunStatus::Halted);
             }
-
-            // Tracing support
-            if self.trace_enabled {
-                let instruction = &self.instructions[self.ip];
-                let indent = if let Some(ref profiler) = self.profiler {
-                    "  ".repeat(profiler.call_depth)
-                } else {
-                    String::new()
-                };
-                println!("{} {}{} @ {}", 
-                         "[trace]",
-                         indent, 
-                         format!("{:?}", instruction),
-                         format!("0x{:04X}", self.ip));
+            if let Some(status) = self.step_instruction()? {
+                return Ok(status);
             }
+        }
+        Ok(if self.is_finished() { RunStatus::Halted } else { RunStatus::Yielded(true) })
+    }
 
-            // Debugging support
-            if self.debug_mode {
-                println!("IP: {}, Instruction: {:?}, Stack size: {}", 
-                    self.ip, self.instructions[self.ip], self.stack.len());
-            }
+    #[allow(dead_code)]
+    fn is_finished(&self) -> bool {
+        matches!(self.instructions.get(self.ip), None | Some(OpCode::Halt))
+    }
 
-            // Breakpoint support
-            if self.breakpoints.contains(&self.ip) {
+    // Run the instruction at ip. Returns the status to stop with when execution
+    // can't go on: HALT was reached or the user quit the debugger.
+    fn step_instruction(&mut self) -> VMResult<Option<RunStatus>> {
+        // Performance tracking
+        self.instruction_count += 1;
+        if self.stack.len() > self.max_stack_size {
+            self.max_stack_size = self.stack.len();
+        }
+
+        // Profiling support
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.record_instruction();
+            profiler.update_stack_depth(self.stack.len());
+        }
+
+        // Tracing support: tab-separated ip, stack depth and instruction
+        if let Some((path, writer)) = &mut self.trace_out {
+            let instruction = opcode_to_text(&self.instructions[self.ip]);
+            writeln!(writer, "{}\t{}\t{}", self.ip, self.stack.len(), instruction)
+                .map_err(|e| VMError::FileError { filename: path.clone(), error: e.to_string() })?;
+        } else if self.trace_enabled {
+            let instruction = &self.instructions[self.ip];
+            let indent = if let Some(ref profiler) = self.profiler {
+                "  ".repeat(profiler.call_depth)
+            } else {
+                String::new()
+            };
+            println!("[trace] {}{:?} @ 0x{:04X}", indent, instruction, self.ip);
+        }
+
+        // Debugging support
+        if self.debug_mode {
+            println!("IP: {}, Instruction: {:?}, Stack size: {}", 
+                self.ip, self.instructions[self.ip], self.stack.len());
+        }
+
+        // Breakpoint and watchpoint support
+        let watch_pause = std::mem::take(&mut self.watch_pause);
+        if self.step_mode || watch_pause || self.breakpoint_triggered() {
+            if self.step_mode {
+                println!("Step at instruction {}: {:?}", 
+                    self.ip, self.instructions[self.ip]);
+            } else if watch_pause {
+                println!("Paused at instruction {}: {:?}", 
+                    self.ip, self.instructions[self.ip]);
+            } else {
+                self.breakpoint_hits += 1;
                 println!("Breakpoint hit at instruction {}: {:?}", 
                     self.ip, self.instructions[self.ip]);
+            }
+            if self.interactive {
+                if !self.debugger_prompt() {
+                    return Ok(Some(RunStatus::Yielded(false)));
+                }
+            } else {
                 println!("Stack: {:?}", self.stack);
                 println!("Variables: {:?}", self.variables.last());
-                // In a real debugger, we'd wait for user input here
             }
+        }
 
-            let instruction = &self.instructions[self.ip].clone();
-            
-            // Store original IP to detect jumps
-            let original_ip = self.ip;
-            
-            // Check for HALT instruction first
-            if matches!(instruction, OpCode::Halt) {
-                break;
+        let instruction = &self.instructions[self.ip].clone();
+        
+        // Store original IP to detect jumps
+        let original_ip = self.ip;
+        
+        // Check for HALT instruction first
+        if matches!(instruction, OpCode::Halt) {
+            return Ok(Some(RunStatus::Halted));
+        }
+        
+        // Execute instruction and catch VM errors in try blocks
+        match self.execute_instruction_safe(instruction).and_then(|()| self.check_stack_limit()) {
+            Ok(()) => {
+                // Only increment IP if instruction didn't change it
+                if self.ip == original_ip {
+                    self.ip += 1;
+                }
             }
-            
-            // Execute instruction and catch VM errors in try blocks
-            match self.execute_instruction_safe(instruction) {
-                Ok(()) => {
-                    // Only increment IP if instruction didn't change it
-                    if self.ip == original_ip {
-                        self.ip += 1;
-                    }
-                }
-                Err(vm_error) => {
-                    // If we're in a try block, convert VM error to exception
-                    if !self.try_stack.is_empty() {
-                        let exception = Value::Exception {
-                            message: vm_error.to_string(),
-                            stack_trace: vec![format!("at instruction {}", self.ip)]
-                        };
-                        self.throw_exception(exception)?;
-                        continue;
-                    } else {
-                        return Err(vm_error);
-                    }
+            // EXIT is not an exception: TRY can't catch it and it carries no source line
+            Err(exit @ VMError::ProcessExit(_)) => return Err(exit),
+            Err(vm_error) => {
+                // If we're in a try block, convert VM error to exception
+                if !self.try_stack.is_empty() {
+                    let exception = Value::Exception {
+                        message: vm_error.to_string(),
+                        stack_trace: self.backtrace()
+                    };
+                    self.throw_exception(exception)?;
+                } else if let Some(line) = self.source_line(self.ip) {
+                    return Err(VMError::AtSourceLine {
+                        line,
+                        ip: self.ip,
+                        error: Box::new(vm_error),
+                    });
+                } else {
+                    return Err(vm_error);
                 }
             }
         }
-        Ok(())
+        Ok(None)
+    }
+
+    fn check_stack_limit(&self) -> VMResult<()> {
+        match self.stack_limit {
+            Some(limit) if self.stack.len() > limit => Err(VMError::StackOverflow { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    // Interactive debugger: read commands from stdin until execution should resume.
+    // Returns false if the user asked to quit. End of input behaves like `continue`.
+    fn debugger_prompt(&mut self) -> bool {
+        use std::io::{BufRead, Write};
+
+        let stdin = std::io::stdin();
+        loop {
+            print!("(ttdb) ");
+            let _ = std::io::stdout().flush();
+
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => {
+                    println!();
+                    self.step_mode = false;
+                    return true;
+                }
+                Ok(_) => {}
+            }
+
+            match line.trim() {
+                "step" | "s" => {
+                    self.step_mode = true;
+                    return true;
+                }
+                "continue" | "c" => {
+                    self.step_mode = false;
+                    return true;
+                }
+                "stack" => println!("Stack: {:?}", self.stack),
+                "vars" => println!("Variables: {:?}", self.variables.last()),
+                "quit" | "q" => return false,
+                "" => {}
+                other => println!("Unknown debugger command: {} (expected step, continue, stack, vars, quit)", other),
+            }
+        }
+    }
+
+    // Run a single instruction against the given operand stack and return what it leaves
+    // behind. The register VM uses this for standard library instructions.
+    pub fn execute_native(&mut self, instruction: &OpCode, operands: Vec<Value>) -> VMResult<Vec<Value>> {
+        self.stack = operands;
+        self.execute_instruction_safe(instruction)?;
+        Ok(std::mem::take(&mut self.stack))
     }
 
     fn execute_instruction_safe(&mut self, instruction: &OpCode) -> VMResult<()> {
@@ -316,11 +805,14 @@ impl VM {
                     let b = self.pop_stack("ADD")?;
                     let a = self.pop_stack("ADD")?;
                     match (&a, &b) {
-                        (Value::Int(x), Value::Int(y)) => self.stack.push(Value::Int(x + y)),
+                        (Value::Int(x), Value::Int(y)) => {
+                            let result = x.checked_add(*y).ok_or_else(|| VMError::IntegerOverflow { operation: "ADD".to_string() })?;
+                            self.stack.push(Value::Int(result));
+                        }
                         // Type coercion: int + float = float
-                        (Value::Int(x), Value::Float(y)) => self.stack.push(Value::Float(*x as f64 + y)),
-                        (Value::Float(x), Value::Int(y)) => self.stack.push(Value::Float(x + *y as f64)),
-                        (Value::Float(x), Value::Float(y)) => self.stack.push(Value::Float(x + y)),
+                        (Value::Int(x), Value::Float(y)) => self.stack.push(float_result("ADD", *x as f64 + y)?),
+                        (Value::Float(x), Value::Int(y)) => self.stack.push(float_result("ADD", x + *y as f64)?),
+                        (Value::Float(x), Value::Float(y)) => self.stack.push(float_result("ADD", x + y)?),
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "two numbers (int or float)".to_string(), 
                             got: format!("{:?}, {:?}", a, b), 
@@ -332,7 +824,7 @@ impl VM {
                     let b = self.pop_stack("ADD_F")?;
                     let a = self.pop_stack("ADD_F")?;
                     match (&a, &b) {
-                        (Value::Float(x), Value::Float(y)) => self.stack.push(Value::Float(x + y)),
+                        (Value::Float(x), Value::Float(y)) => self.stack.push(float_result("ADD_F", x + y)?),
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "two floats".to_string(), 
                             got: format!("{:?}, {:?}", a, b), 
@@ -344,7 +836,7 @@ impl VM {
                     let b = self.pop_stack("SUB_F")?;
                     let a = self.pop_stack("SUB_F")?;
                     match (&a, &b) {
-                        (Value::Float(x), Value::Float(y)) => self.stack.push(Value::Float(x - y)),
+                        (Value::Float(x), Value::Float(y)) => self.stack.push(float_result("SUB_F", x - y)?),
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "two floats".to_string(), 
                             got: format!("{:?}, {:?}", a, b), 
@@ -356,7 +848,7 @@ impl VM {
                     let b = self.pop_stack("MUL_F")?;
                     let a = self.pop_stack("MUL_F")?;
                     match (&a, &b) {
-                        (Value::Float(x), Value::Float(y)) => self.stack.push(Value::Float(x * y)),
+                        (Value::Float(x), Value::Float(y)) => self.stack.push(float_result("MUL_F", x * y)?),
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "two floats".to_string(), 
                             got: format!("{:?}, {:?}", a, b), 
@@ -376,7 +868,7 @@ impl VM {
                                     operation: "DIV_F".to_string() 
                                 });
                             }
-                            self.stack.push(Value::Float(x / y));
+                            self.stack.push(float_result("DIV_F", x / y)?);
                         }
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "two floats".to_string(), 
@@ -385,6 +877,27 @@ impl VM {
                         }),
                     }
                 }
+                OpCode::Neg => {
+                    let val = self.pop_stack("NEG")?;
+                    self.stack.push(ops::neg(val)?);
+                }
+                OpCode::Pow => {
+                    let exponent = self.pop_stack("POW")?;
+                    let base = self.pop_stack("POW")?;
+                    self.stack.push(ops::pow(base, exponent)?);
+                }
+                OpCode::Sqrt => {
+                    let val = self.pop_stack("SQRT")?;
+                    self.stack.push(ops::sqrt(val)?);
+                }
+                OpCode::Abs => {
+                    let val = self.pop_stack("ABS")?;
+                    self.stack.push(ops::abs(val)?);
+                }
+                OpCode::Floor | OpCode::Ceil | OpCode::Round => {
+                    let val = self.pop_stack(&opcode_to_text(instruction))?;
+                    self.stack.push(ops::round(instruction, val)?);
+                }
                 OpCode::Concat => {
                     let b = self.pop_stack("CONCAT")?;
                     let a = self.pop_stack("CONCAT")?;
@@ -399,26 +912,40 @@ impl VM {
                 }
                 OpCode::Print => {
                     let val = self.pop_stack("PRINT")?;
-                    println!("{}", val);
+                    self.write_output(&format!("{}\n", val.display(self.float_precision)))?;
+                }
+                OpCode::PrintRaw => {
+                    let val = self.pop_stack("PRINT_RAW")?;
+                    self.write_output(&val.display(self.float_precision).to_string())?;
+                }
+                OpCode::DebugPrint => {
+                    let val = self.pop_stack("DEBUG_PRINT")?;
+                    self.write_output(&format!("{}\n", val.pretty()))?;
+                }
+                OpCode::PrintErr => {
+                    let val = self.pop_stack("PRINT_ERR")?;
+                    eprintln!("{}", val.display(self.float_precision));
                 }
                 OpCode::Jmp(target) => {
                     self.ip = *target;
                 }
                 OpCode::Jz(target) => {
                     let val = self.pop_stack("JZ")?;
-                    let is_zero = match val {
-                        Value::Int(0) => true,
-                        Value::Bool(false) => true,
-                        Value::Null => true,
-                        _ => false,
-                    };
-                    if is_zero {
+                    if val.is_falsy() {
                         self.ip = *target;
                     }
                 }
+                OpCode::Assert(message) => {
+                    let val = self.pop_stack("ASSERT")?;
+                    if val.is_falsy() {
+                        let message = message.clone().unwrap_or_else(|| format!("got {:?}", val));
+                        return Err(VMError::AssertionFailed { message });
+                    }
+                }
                 OpCode::Call{ addr, params } => {
-                    self.check_stack_size(params.len(), "CALL")?;
-                    
+                    let mut frame = HashMap::new();
+                    self.bind_arguments(params, &mut frame, "CALL")?;
+
                     // Create function name for profiling/tracing
                     let function_name = format!("fn@0x{:04X}", addr);
                     
@@ -436,31 +963,32 @@ impl VM {
                                  format!("{}", params.len()).green());
                     }
                     
+                    let tail_call = self.in_tail_position();
+                    
                     // Function profiling
                     if let Some(ref mut profiler) = self.profiler {
+                        if tail_call {
+                            profiler.end_function();
+                        }
                         profiler.start_function(function_name);
                     }
                     
-                    self.call_stack.push(self.ip + 1);
-                    let mut frame = HashMap::new();
-                    for name in params.iter().rev() {
-                        let value = self.pop_stack("CALL")?;
-                        frame.insert(name.clone(), value);
-                    }
-                    self.variables.push(frame);
-                    self.ip = *addr;
+                    self.enter_function(frame, *addr, tail_call, None)?;
                 }
-                OpCode::Ret => {
+                OpCode::Ret | OpCode::RetN(_) => {
+                    if let (OpCode::RetN(declared), Some(&base)) = (instruction, self.call_stack_bases.last()) {
+                        ops::check_return_count(*declared, &self.stack, base)?;
+                    }
+                    self.check_return_type()?;
+
                     // Function return tracing and profiling
                     if let Some(ref mut profiler) = self.profiler {
                         if let Some(function_name) = profiler.end_function() {
                             if self.trace_enabled {
                                 let indent = "  ".repeat(profiler.call_depth);
-                                let return_value = if !self.stack.is_empty() {
-                                    format!(" → {:?}", self.stack.last().unwrap())
-                                } else {
-                                    String::new()
-                                };
+                                let return_value = self.stack.last()
+                                    .map(|value| format!(" → {:?}", value))
+                                    .unwrap_or_default();
                                 println!("{} {}RETURN from {}{}", 
                                          "[trace]".bright_blue(),
                                          indent, 
@@ -477,11 +1005,14 @@ impl VM {
                     let b = self.pop_stack("SUB")?;
                     let a = self.pop_stack("SUB")?;
                     match (&a, &b) {
-                        (Value::Int(x), Value::Int(y)) => self.stack.push(Value::Int(x - y)),
+                        (Value::Int(x), Value::Int(y)) => {
+                            let result = x.checked_sub(*y).ok_or_else(|| VMError::IntegerOverflow { operation: "SUB".to_string() })?;
+                            self.stack.push(Value::Int(result));
+                        }
                         // Type coercion: mixed int/float = float
-                        (Value::Int(x), Value::Float(y)) => self.stack.push(Value::Float(*x as f64 - y)),
-                        (Value::Float(x), Value::Int(y)) => self.stack.push(Value::Float(x - *y as f64)),
-                        (Value::Float(x), Value::Float(y)) => self.stack.push(Value::Float(x - y)),
+                        (Value::Int(x), Value::Float(y)) => self.stack.push(float_result("SUB", *x as f64 - y)?),
+                        (Value::Float(x), Value::Int(y)) => self.stack.push(float_result("SUB", x - *y as f64)?),
+                        (Value::Float(x), Value::Float(y)) => self.stack.push(float_result("SUB", x - y)?),
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "two numbers (int or float)".to_string(), 
                             got: format!("{:?}, {:?}", a, b), 
@@ -493,10 +1024,13 @@ impl VM {
                     let b = self.pop_stack("MUL")?;
                     let a = self.pop_stack("MUL")?;
                     match (&a, &b) {
-                        (Value::Int(x), Value::Int(y)) => self.stack.push(Value::Int(x * y)),
-                        (Value::Int(x), Value::Float(y)) => self.stack.push(Value::Float(*x as f64 * y)),
-                        (Value::Float(x), Value::Int(y)) => self.stack.push(Value::Float(x * *y as f64)),
-                        (Value::Float(x), Value::Float(y)) => self.stack.push(Value::Float(x * y)),
+                        (Value::Int(x), Value::Int(y)) => {
+                            let result = x.checked_mul(*y).ok_or_else(|| VMError::IntegerOverflow { operation: "MUL".to_string() })?;
+                            self.stack.push(Value::Int(result));
+                        }
+                        (Value::Int(x), Value::Float(y)) => self.stack.push(float_result("MUL", *x as f64 * y)?),
+                        (Value::Float(x), Value::Int(y)) => self.stack.push(float_result("MUL", x * *y as f64)?),
+                        (Value::Float(x), Value::Float(y)) => self.stack.push(float_result("MUL", x * y)?),
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "two numbers (int or float)".to_string(), 
                             got: format!("{:?}, {:?}", a, b), 
@@ -512,25 +1046,26 @@ impl VM {
                             if *y == 0 {
                                 return Err(VMError::DivisionByZero);
                             }
-                            self.stack.push(Value::Int(x / y));
+                            let result = x.checked_div(*y).ok_or_else(|| VMError::IntegerOverflow { operation: "DIV".to_string() })?;
+                            self.stack.push(Value::Int(result));
                         },
                         (Value::Int(x), Value::Float(y)) => {
                             if *y == 0.0 {
                                 return Err(VMError::DivisionByZero);
                             }
-                            self.stack.push(Value::Float(*x as f64 / y));
+                            self.stack.push(float_result("DIV", *x as f64 / y)?);
                         },
                         (Value::Float(x), Value::Int(y)) => {
                             if *y == 0 {
                                 return Err(VMError::DivisionByZero);
                             }
-                            self.stack.push(Value::Float(x / *y as f64));
+                            self.stack.push(float_result("DIV", x / *y as f64)?);
                         },
                         (Value::Float(x), Value::Float(y)) => {
                             if *y == 0.0 {
                                 return Err(VMError::DivisionByZero);
                             }
-                            self.stack.push(Value::Float(x / y));
+                            self.stack.push(float_result("DIV", x / y)?);
                         },
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "two numbers (int or float)".to_string(), 
@@ -547,6 +1082,16 @@ impl VM {
                     let val = self.pop_stack("STORE")?;
                     self.set_variable(name.clone(), val)?;
                 }
+                OpCode::Destructure(pattern) => {
+                    let value = self.pop_stack("DESTRUCTURE")?;
+                    let bindings = pattern.bindings(&value).map_err(|reason| VMError::PatternMismatch {
+                        pattern: pattern.to_string(),
+                        reason,
+                    })?;
+                    for (name, value) in bindings {
+                        self.set_variable(name, value)?;
+                    }
+                }
                 OpCode::Load(name) => {
                     let val = self.get_variable(&name)?;
                     self.stack.push(val);
@@ -566,11 +1111,11 @@ impl VM {
                     let a = self.pop_stack("EQ")?;
                     let result = match (&a, &b) {
                         (Value::Int(x), Value::Int(y)) => x == y,
-                        (Value::Float(x), Value::Float(y)) => (x - y).abs() < f64::EPSILON,
+                        (Value::Float(x), Value::Float(y)) => x == y,
                         (Value::Str(x), Value::Str(y)) => x == y,
                         (Value::Bool(x), Value::Bool(y)) => x == y,
                         (Value::Null, Value::Null) => true,
-                        (Value::Function { addr: addr1, params: params1 }, Value::Function { addr: addr2, params: params2 }) => {
+                        (Value::Function { addr: addr1, params: params1, .. }, Value::Function { addr: addr2, params: params2, .. }) => {
                             addr1 == addr2 && params1 == params2
                         },
                         _ => return Err(VMError::TypeMismatch { 
@@ -614,11 +1159,11 @@ impl VM {
                     let a = self.pop_stack("NE")?;
                     let result = match (&a, &b) {
                         (Value::Int(x), Value::Int(y)) => x != y,
-                        (Value::Float(x), Value::Float(y)) => (x - y).abs() >= f64::EPSILON,
+                        (Value::Float(x), Value::Float(y)) => x != y,
                         (Value::Str(x), Value::Str(y)) => x != y,
                         (Value::Bool(x), Value::Bool(y)) => x != y,
                         (Value::Null, Value::Null) => false,
-                        (Value::Function { addr: addr1, params: params1 }, Value::Function { addr: addr2, params: params2 }) => {
+                        (Value::Function { addr: addr1, params: params1, .. }, Value::Function { addr: addr2, params: params2, .. }) => {
                             addr1 != addr2 || params1 != params2
                         },
                         _ => return Err(VMError::TypeMismatch { 
@@ -662,7 +1207,7 @@ impl VM {
                     let a = self.pop_stack("EQ_F")?;
                     match (&a, &b) {
                         (Value::Float(x), Value::Float(y)) => {
-                            self.stack.push(Value::Int(if (x - y).abs() < f64::EPSILON { 1 } else { 0 }));
+                            self.stack.push(Value::Int(if x == y { 1 } else { 0 }));
                         }
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "two floats".to_string(), 
@@ -676,7 +1221,7 @@ impl VM {
                     let a = self.pop_stack("NE_F")?;
                     match (&a, &b) {
                         (Value::Float(x), Value::Float(y)) => {
-                            self.stack.push(Value::Int(if (x - y).abs() >= f64::EPSILON { 1 } else { 0 }));
+                            self.stack.push(Value::Int(if x != y { 1 } else { 0 }));
                         }
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "two floats".to_string(), 
@@ -792,7 +1337,7 @@ impl VM {
                         list.push(self.pop_stack("MAKE_LIST")?);
                     }
                     list.reverse();
-                    self.stack.push(Value::List(list));
+                    self.push_allocated(Value::List(list.into()));
                 }
                 OpCode::Len => {
                     let val = self.pop_stack("LEN")?;
@@ -829,8 +1374,7 @@ impl VM {
                     self.stack.push(list[index].clone());
                 }
                 OpCode::MakeObject => {
-                    let obj = HashMap::new();
-                    self.stack.push(Value::Object(obj));
+                    self.push_allocated(Value::Object(IndexMap::new().into()));
                 }
                 OpCode::SetField(field_name) => {
                     let value = self.pop_stack("SET_FIELD")?;
@@ -847,6 +1391,21 @@ impl VM {
                         }),
                     }
                 }
+                OpCode::SetFieldMut { var, field } => {
+                    // Unlike LOAD/SET_FIELD/STORE this never copies the object, so building
+                    // a large object field by field stays linear
+                    let value = self.pop_stack("SET_FIELD_MUT")?;
+                    match self.variable_mut(var)? {
+                        Value::Object(map) => {
+                            map.insert(field.clone(), value);
+                        }
+                        other => return Err(VMError::TypeMismatch { 
+                            expected: "a variable holding an object".to_string(), 
+                            got: format!("{:?}", other), 
+                            operation: "SET_FIELD_MUT".to_string() 
+                        }),
+                    }
+                }
                 OpCode::GetField(field_name) => {
                     let obj = self.pop_stack("GET_FIELD")?;
                     match obj {
@@ -879,7 +1438,7 @@ impl VM {
                     let obj = self.pop_stack("DELETE_FIELD")?;
                     match obj {
                         Value::Object(mut map) => {
-                            map.remove(field_name);
+                            map.shift_remove(field_name);
                             self.stack.push(Value::Object(map));
                         }
                         _ => return Err(VMError::TypeMismatch { 
@@ -894,7 +1453,7 @@ impl VM {
                     match obj {
                         Value::Object(map) => {
                             let keys: Vec<Value> = map.keys().map(|k| Value::Str(k.clone())).collect();
-                            self.stack.push(Value::List(keys));
+                            self.stack.push(Value::List(keys.into()));
                         }
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "an object".to_string(), 
@@ -903,8 +1462,38 @@ impl VM {
                         }),
                     }
                 }
-                OpCode::MakeFunction { addr, params } => {
-                    let function = Value::Function { addr: *addr, params: params.clone() };
+                OpCode::Values => {
+                    let obj = self.pop_stack("VALUES")?;
+                    match obj {
+                        Value::Object(map) => {
+                            let values: Vec<Value> = map.into_inner().into_values().collect();
+                            self.stack.push(Value::List(values.into()));
+                        }
+                        _ => return Err(VMError::TypeMismatch { 
+                            expected: "an object".to_string(), 
+                            got: format!("{:?}", obj), 
+                            operation: "VALUES".to_string() 
+                        }),
+                    }
+                }
+                OpCode::Entries => {
+                    let obj = self.pop_stack("ENTRIES")?;
+                    match obj {
+                        Value::Object(map) => {
+                            let entries: Vec<Value> = map.into_iter()
+                                .map(|(k, v)| Value::List(vec![Value::Str(k), v].into()))
+                                .collect();
+                            self.stack.push(Value::List(entries.into()));
+                        }
+                        _ => return Err(VMError::TypeMismatch { 
+                            expected: "an object".to_string(), 
+                            got: format!("{:?}", obj), 
+                            operation: "ENTRIES".to_string() 
+                        }),
+                    }
+                }
+                OpCode::MakeFunction { addr, params, returns } => {
+                    let function = Value::Function { addr: *addr, params: params.clone(), returns: returns.clone() };
                     self.stack.push(function);
                 }
                 OpCode::MakeLambda { addr, params } => {
@@ -912,9 +1501,9 @@ impl VM {
                     let closure = Value::Closure { 
                         addr: *addr, 
                         params: params.clone(),
-                        captured: self.lambda_captures.clone() 
+                        captured: self.lambda_captures.clone().into() 
                     };
-                    self.stack.push(closure);
+                    self.push_allocated(closure);
                     
                     // Clear captures for next lambda
                     self.lambda_captures.clear();
@@ -924,44 +1513,50 @@ impl VM {
                     let value = self.get_variable(var_name)?.clone();
                     self.lambda_captures.insert(var_name.clone(), value);
                 }
+                OpCode::WeakRef => {
+                    // The GC keeps its own copy, which lives only as long as a copy of the same list, object
+                    // or closure is reachable; an equal one built separately doesn't count
+                    let value = self.pop_stack("WEAK_REF")?;
+                    if !matches!(value, Value::List(_) | Value::Object(_) | Value::Closure { .. }) {
+                        return Err(VMError::TypeMismatch {
+                            expected: "list, object or closure".to_string(),
+                            got: format!("{:?}", value),
+                            operation: "WEAK_REF".to_string(),
+                        });
+                    }
+                    let target = self.gc_engine.alloc_weak(value);
+                    self.stack.push(Value::WeakRef(target.id()));
+                }
+                OpCode::WeakGet => {
+                    match self.pop_stack("WEAK_GET")? {
+                        Value::WeakRef(id) => self.stack.push(self.gc_engine.get(id).unwrap_or(Value::Null)),
+                        other => return Err(VMError::TypeMismatch {
+                            expected: "weak reference".to_string(),
+                            got: format!("{:?}", other),
+                            operation: "WEAK_GET".to_string(),
+                        }),
+                    }
+                }
                 OpCode::CallFunction => {
                     let function = self.pop_stack("CALL_FUNCTION")?;
                     match function {
-                        Value::Function { addr, params } => {
-                            // Check if we have enough arguments on the stack
-                            self.check_stack_size(params.len(), "CALL_FUNCTION")?;
-                            
-                            // Save return address
-                            self.call_stack.push(self.ip + 1);
-                            
+                        Value::Function { addr, params, returns } => {
                             // Create new variable frame for function parameters
                             let mut frame = HashMap::new();
-                            for name in params.iter().rev() {
-                                let value = self.pop_stack("CALL_FUNCTION")?;
-                                frame.insert(name.clone(), value);
-                            }
-                            self.variables.push(frame);
+                            self.bind_arguments(&params, &mut frame, "CALL_FUNCTION")?;
                             
                             // Jump to function
-                            self.ip = addr;
+                            let tail_call = self.in_tail_position();
+                            self.enter_function(frame, addr, tail_call, returns)?;
                         }
                         Value::Closure { addr, params, captured } => {
-                            // Check if we have enough arguments on the stack
-                            self.check_stack_size(params.len(), "CALL_FUNCTION")?;
-                            
-                            // Save return address
-                            self.call_stack.push(self.ip + 1);
-                            
                             // Create new variable frame with captured variables and parameters
-                            let mut frame = captured; // Start with captured environment
-                            for name in params.iter().rev() {
-                                let value = self.pop_stack("CALL_FUNCTION")?;
-                                frame.insert(name.clone(), value); // Parameters override captured vars
-                            }
-                            self.variables.push(frame);
+                            let mut frame = captured.into_inner(); // Start with captured environment
+                            self.bind_arguments(&params, &mut frame, "CALL_FUNCTION")?; // Parameters override captured vars
                             
                             // Jump to closure body
-                            self.ip = addr;
+                            let tail_call = self.in_tail_position();
+                            self.enter_function(frame, addr, tail_call, None)?;
                         }
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "a function or closure".to_string(), 
@@ -1158,7 +1753,7 @@ impl VM {
                                             }),
                                         }
                                     }
-                                    self.stack.push(Value::List(files));
+                                    self.stack.push(Value::List(files.into()));
                                 }
                                 Err(e) => return Err(VMError::FileError { 
                                     filename: dirname, 
@@ -1173,6 +1768,49 @@ impl VM {
                         }),
                     }
                 }
+                OpCode::ListDirRecursive => {
+                    let val = self.pop_stack("LIST_DIR_RECURSIVE")?;
+                    match val {
+                        Value::Str(dirname) => {
+                            let mut paths = Vec::new();
+                            let root = std::path::Path::new(&dirname);
+                            if let Err(e) = list_dir_recursive(root, root, &mut paths) {
+                                return Err(VMError::FileError { filename: dirname, error: e.to_string() });
+                            }
+                            paths.sort();
+                            self.stack.push(Value::List(paths.into_iter().map(Value::Str).collect()));
+                        }
+                        _ => return Err(VMError::TypeMismatch {
+                            expected: "string (directory name)".to_string(),
+                            got: format!("{:?}", val),
+                            operation: "LIST_DIR_RECURSIVE".to_string()
+                        }),
+                    }
+                }
+                OpCode::Stat => {
+                    let val = self.pop_stack("STAT")?;
+                    match val {
+                        Value::Str(filename) => {
+                            let metadata = std::fs::metadata(&filename)
+                                .map_err(|e| VMError::FileError { filename: filename.clone(), error: e.to_string() })?;
+                            // Seconds since the Unix epoch, or Null where the platform doesn't record it
+                            let modified = metadata.modified().ok()
+                                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                                .map_or(Value::Null, |elapsed| Value::Int(elapsed.as_secs() as i64));
+                            self.stack.push(Value::Object(IndexMap::from([
+                                ("is_dir".to_string(), Value::Bool(metadata.is_dir())),
+                                ("is_file".to_string(), Value::Bool(metadata.is_file())),
+                                ("size".to_string(), Value::Int(metadata.len() as i64)),
+                                ("modified".to_string(), modified),
+                            ]).into()));
+                        }
+                        _ => return Err(VMError::TypeMismatch {
+                            expected: "string (filename)".to_string(),
+                            got: format!("{:?}", val),
+                            operation: "STAT".to_string()
+                        }),
+                    }
+                }
                 OpCode::ReadBytes => {
                     let val = self.pop_stack("read_bytes")?;
                     match val {
@@ -1211,6 +1849,65 @@ impl VM {
                         }),
                     }
                 }
+                // Working directory and paths
+                OpCode::GetCwd => {
+                    match std::env::current_dir() {
+                        Ok(dir) => self.stack.push(Value::Str(dir.to_string_lossy().into_owned())),
+                        Err(e) => return Err(VMError::FileError { filename: ".".to_string(), error: e.to_string() }),
+                    }
+                }
+                OpCode::SetCwd => {
+                    let val = self.pop_stack("SET_CWD")?;
+                    match val {
+                        Value::Str(dirname) => {
+                            if let Err(e) = std::env::set_current_dir(&dirname) {
+                                return Err(VMError::FileError { filename: dirname, error: e.to_string() });
+                            }
+                        }
+                        _ => return Err(VMError::TypeMismatch {
+                            expected: "string (directory name)".to_string(),
+                            got: format!("{:?}", val),
+                            operation: "SET_CWD".to_string()
+                        }),
+                    }
+                }
+                OpCode::PathJoin => {
+                    let tail = self.pop_stack("PATH_JOIN")?;
+                    let head = self.pop_stack("PATH_JOIN")?;
+                    match (head, tail) {
+                        (Value::Str(head), Value::Str(tail)) => {
+                            let joined = std::path::Path::new(&head).join(&tail);
+                            self.stack.push(Value::Str(joined.to_string_lossy().into_owned()));
+                        }
+                        (h, t) => return Err(VMError::TypeMismatch {
+                            expected: "two strings (paths)".to_string(),
+                            got: format!("{:?}, {:?}", h, t),
+                            operation: "PATH_JOIN".to_string()
+                        }),
+                    }
+                }
+                // Both push "" when the path has no such component (e.g. "/")
+                OpCode::PathBasename | OpCode::PathDirname => {
+                    let basename = matches!(instruction, OpCode::PathBasename);
+                    let operation = if basename { "PATH_BASENAME" } else { "PATH_DIRNAME" };
+                    let val = self.pop_stack(operation)?;
+                    match val {
+                        Value::Str(path) => {
+                            let path = std::path::Path::new(&path);
+                            let part = if basename {
+                                path.file_name().map(|name| name.to_string_lossy().into_owned())
+                            } else {
+                                path.parent().map(|dir| dir.to_string_lossy().into_owned())
+                            };
+                            self.stack.push(Value::Str(part.unwrap_or_default()));
+                        }
+                        _ => return Err(VMError::TypeMismatch {
+                            expected: "string (path)".to_string(),
+                            got: format!("{:?}", val),
+                            operation: operation.to_string()
+                        }),
+                    }
+                }
                 // Environment and system operations
                 OpCode::GetEnv => {
                     let val = self.pop_stack("GET_ENV")?;
@@ -1243,10 +1940,17 @@ impl VM {
                     }
                 }
                 OpCode::GetArgs => {
-                    let args: Vec<Value> = std::env::args()
-                        .map(|arg| Value::Str(arg))
+                    let args: Vec<Value> = self.program_args.iter()
+                        .map(|arg| Value::Str(arg.clone()))
                         .collect();
-                    self.stack.push(Value::List(args));
+                    self.stack.push(Value::List(args.into()));
+                }
+                OpCode::EnvVars => {
+                    // Sorted by name so the object's field order doesn't depend on the platform
+                    let mut vars: Vec<(String, String)> = std::env::vars().collect();
+                    vars.sort();
+                    let object = vars.into_iter().map(|(name, value)| (name, Value::Str(value))).collect();
+                    self.stack.push(Value::Object(object));
                 }
                 OpCode::Exec => {
                     let args = self.pop_stack("EXEC")?;
@@ -1309,11 +2013,11 @@ impl VM {
                                     let exit_code = output.status.code().unwrap_or(-1);
                                     
                                     // Create result object
-                                    let mut result = HashMap::new();
+                                    let mut result = IndexMap::new();
                                     result.insert("stdout".to_string(), Value::Str(stdout));
                                     result.insert("stderr".to_string(), Value::Str(stderr));
                                     result.insert("exit_code".to_string(), Value::Int(exit_code as i64));
-                                    self.stack.push(Value::Object(result));
+                                    self.stack.push(Value::Object(result.into()));
                                 }
                                 Err(e) => return Err(VMError::FileError { 
                                     filename: cmd, 
@@ -1331,8 +2035,10 @@ impl VM {
                 OpCode::Exit => {
                     let val = self.pop_stack("EXIT")?;
                     match val {
+                        // Unwind to the embedder rather than killing the host process
                         Value::Int(code) => {
-                            std::process::exit(code as i32);
+                            self.flush_trace()?;
+                            return Err(VMError::ProcessExit(code as i32));
                         }
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "int (exit code)".to_string(), 
@@ -1376,13 +2082,13 @@ impl VM {
                     let format_str = self.pop_stack("FORMAT_TIME")?;
                     let timestamp = self.pop_stack("FORMAT_TIME")?;
                     match (timestamp, format_str) {
-                        (Value::Int(ts), Value::Str(_format)) => {
-                            // Simplified time formatting - just return ISO format
-                            use std::time::UNIX_EPOCH;
-                            let _system_time = UNIX_EPOCH + Duration::from_secs(ts as u64);
-                            // For simplicity, just return the timestamp as string
-                            // In a real implementation, we'd use chrono or similar for formatting
-                            self.stack.push(Value::Str(format!("{}", ts)));
+                        (Value::Int(ts), Value::Str(format)) => {
+                            // Unix timestamps are formatted in UTC with strftime-style specifiers
+                            let time = chrono::DateTime::from_timestamp(ts, 0).ok_or_else(|| VMError::RuntimeError(
+                                format!("FORMAT_TIME timestamp {} is out of range", ts)
+                            ))?;
+                            let items = strftime_items(&format, "FORMAT_TIME")?;
+                            self.stack.push(Value::Str(time.format_with_items(items.iter()).to_string()));
                         }
                         (t, f) => return Err(VMError::TypeMismatch { 
                             expected: "int (timestamp) and string (format)".to_string(), 
@@ -1391,15 +2097,52 @@ impl VM {
                         }),
                     }
                 }
+                OpCode::ParseTime => {
+                    let format_str = self.pop_stack("PARSE_TIME")?;
+                    let text = self.pop_stack("PARSE_TIME")?;
+                    match (text, format_str) {
+                        (Value::Str(text), Value::Str(format)) => {
+                            // The inverse of FORMAT_TIME: a UTC time back to a Unix timestamp.
+                            // Formats without a time of day parse as midnight.
+                            let items = strftime_items(&format, "PARSE_TIME")?;
+                            let mut parsed = chrono::format::Parsed::new();
+                            chrono::format::parse(&mut parsed, &text, items.iter())
+                                .and_then(|_| if parsed.hour_div_12().is_some() || parsed.timestamp().is_some() {
+                                    parsed.to_naive_datetime_with_offset(0)
+                                } else {
+                                    parsed.to_naive_date().map(|date| date.and_time(chrono::NaiveTime::MIN))
+                                })
+                                .map(|time| self.stack.push(Value::Int(time.and_utc().timestamp())))
+                                .map_err(|e| VMError::RuntimeError(
+                                    format!("PARSE_TIME cannot parse {:?} with format {:?}: {}", text, format, e)
+                                ))?;
+                        }
+                        (t, f) => return Err(VMError::TypeMismatch {
+                            expected: "string (time) and string (format)".to_string(),
+                            got: format!("{:?}, {:?}", t, f),
+                            operation: "PARSE_TIME".to_string()
+                        }),
+                    }
+                }
+                // Random numbers
+                OpCode::RandomInt => {
+                    let max = self.pop_stack("RANDOM_INT")?;
+                    let min = self.pop_stack("RANDOM_INT")?;
+                    self.stack.push(ops::random_int(&mut self.rng, min, max)?);
+                }
+                OpCode::RandomFloat => self.stack.push(ops::random_float(&mut self.rng)),
+                OpCode::RandomSeed => {
+                    let seed = self.pop_stack("RANDOM_SEED")?;
+                    ops::random_seed(&mut self.rng, seed)?;
+                }
+                OpCode::Uuid => self.stack.push(ops::uuid()),
                 // Network operations
                 OpCode::HttpGet => {
                     let val = self.pop_stack("HTTP_GET")?;
                     match val {
                         Value::Str(url) => {
-                            // Simplified HTTP GET using std library (in real implementation would use reqwest)
-                            // For now, just return a placeholder response
-                            let response = format!("HTTP response from {}", url);
-                            self.stack.push(Value::Str(response));
+                            let result = http_agent().get(&url).call();
+                            self.stack.push(http_response(&url, result)?);
                         }
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "string (URL)".to_string(), 
@@ -1411,19 +2154,124 @@ impl VM {
                 OpCode::HttpPost => {
                     let data = self.pop_stack("HTTP_POST")?;
                     let url = self.pop_stack("HTTP_POST")?;
-                    match (url, data) {
-                        (Value::Str(url_str), Value::Str(data_str)) => {
-                            // Simplified HTTP POST (in real implementation would use reqwest)
-                            let response = format!("HTTP POST to {} with data: {}", url_str, data_str);
-                            self.stack.push(Value::Str(response));
-                        }
-                        (u, d) => return Err(VMError::TypeMismatch { 
-                            expected: "two strings (URL, data)".to_string(), 
-                            got: format!("{:?}, {:?}", u, d), 
+                    // The data is either the body itself or an object with a `body` and an
+                    // optional `content_type`
+                    let request = match (&url, &data) {
+                        (Value::Str(url), Value::Str(body)) => Some((url, body.as_str(), "text/plain; charset=utf-8")),
+                        (Value::Str(url), Value::Object(fields)) => match (fields.get("body"), fields.get("content_type")) {
+                            (Some(Value::Str(body)), None) => Some((url, body.as_str(), "text/plain; charset=utf-8")),
+                            (Some(Value::Str(body)), Some(Value::Str(content_type))) => Some((url, body.as_str(), content_type.as_str())),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    match request {
+                        Some((url, body, content_type)) => {
+                            let result = http_agent().post(url).set("Content-Type", content_type).send_string(body);
+                            self.stack.push(http_response(url, result)?);
+                        }
+                        None => return Err(VMError::TypeMismatch { 
+                            expected: "string (URL) and string or {body, content_type} object (data)".to_string(), 
+                            got: format!("{:?}, {:?}", url, data), 
                             operation: "HTTP_POST".to_string() 
                         }),
                     }
                 }
+                OpCode::HttpServe => {
+                    let val = self.pop_stack("HTTP_SERVE")?;
+                    match val {
+                        Value::Int(port) if (0..=u16::MAX as i64).contains(&port) => {
+                            let address = format!("127.0.0.1:{}", port);
+                            let listener = std::net::TcpListener::bind(&address)
+                                .and_then(|listener| listener.local_addr().map(|bound| (listener, bound)));
+                            match listener {
+                                // The handle carries the bound address, so port 0 still tells where to connect
+                                Ok((listener, bound)) => {
+                                    let server_id = format!("http://{}", bound);
+                                    self.http_servers.insert(server_id.clone(), listener);
+                                    self.stack.push(Value::Connection(server_id));
+                                }
+                                Err(e) => return Err(VMError::NetworkError {
+                                    url: format!("http://{}", address),
+                                    error: e.to_string()
+                                }),
+                            }
+                        }
+                        _ => return Err(VMError::TypeMismatch {
+                            expected: "int (port)".to_string(),
+                            got: format!("{:?}", val),
+                            operation: "HTTP_SERVE".to_string()
+                        }),
+                    }
+                }
+                OpCode::HttpAccept => {
+                    let val = self.pop_stack("HTTP_ACCEPT")?;
+                    match val {
+                        Value::Connection(server_id) => {
+                            let network_error = |error: String| VMError::NetworkError { url: server_id.clone(), error };
+                            let listener = self.http_servers.get(&server_id)
+                                .ok_or_else(|| network_error("HTTP_ACCEPT on a server that is not listening".to_string()))?;
+                            // Blocks until a client connects and has sent the whole request
+                            let (stream, _) = listener.accept().map_err(|e| network_error(e.to_string()))?;
+                            let mut request = read_http_request(&stream).map_err(|e| network_error(e.to_string()))?;
+                            self.http_request_count += 1;
+                            let request_id = format!("{}#{}", server_id, self.http_request_count);
+                            self.http_requests.insert(request_id.clone(), stream);
+                            request.insert("connection".to_string(), Value::Connection(request_id));
+                            self.stack.push(Value::Object(request.into()));
+                        }
+                        _ => return Err(VMError::TypeMismatch {
+                            expected: "connection (HTTP server)".to_string(),
+                            got: format!("{:?}", val),
+                            operation: "HTTP_ACCEPT".to_string()
+                        }),
+                    }
+                }
+                OpCode::HttpRespond => {
+                    let data = self.pop_stack("HTTP_RESPOND")?;
+                    let status = self.pop_stack("HTTP_RESPOND")?;
+                    let request = self.pop_stack("HTTP_RESPOND")?;
+                    // Answers either the request object from HTTP_ACCEPT or its connection
+                    let request_id = match &request {
+                        Value::Connection(id) => Some(id),
+                        Value::Object(fields) => match fields.get("connection") {
+                            Some(Value::Connection(id)) => Some(id),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    let body = match &data {
+                        Value::Str(body) => Some((body.as_str(), "text/plain; charset=utf-8")),
+                        Value::Object(fields) => match (fields.get("body"), fields.get("content_type")) {
+                            (Some(Value::Str(body)), None) => Some((body.as_str(), "text/plain; charset=utf-8")),
+                            (Some(Value::Str(body)), Some(Value::Str(content_type))) => Some((body.as_str(), content_type.as_str())),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    match (request_id, &status, body) {
+                        (Some(request_id), Value::Int(code), Some((body, content_type))) if (100..=999).contains(code) => {
+                            // Each request gets one response, after which the connection is closed
+                            let mut stream = self.http_requests.remove(request_id).ok_or_else(|| VMError::NetworkError {
+                                url: request_id.clone(),
+                                error: "HTTP_RESPOND to a request that was already answered".to_string(),
+                            })?;
+                            let response = format!(
+                                "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                                code, http_reason(*code), content_type, body.len(), body
+                            );
+                            stream.write_all(response.as_bytes()).map_err(|e| VMError::NetworkError {
+                                url: request_id.clone(),
+                                error: e.to_string(),
+                            })?;
+                        }
+                        _ => return Err(VMError::TypeMismatch {
+                            expected: "request, int (status) and string or {body, content_type} object (data)".to_string(),
+                            got: format!("{:?}, {:?}, {:?}", request, status, data),
+                            operation: "HTTP_RESPOND".to_string()
+                        }),
+                    }
+                }
                 OpCode::TcpConnect => {
                     let port = self.pop_stack("TCP_CONNECT")?;
                     let host = self.pop_stack("TCP_CONNECT")?;
@@ -1568,11 +2416,11 @@ impl VM {
                     match (socket, size) {
                         (Value::Connection(_conn_id), Value::Int(_buffer_size)) => {
                             // Simplified UDP recv - return mock data and sender info
-                            let mut result = HashMap::new();
+                            let mut result = IndexMap::new();
                             result.insert("data".to_string(), Value::Str("UDP packet data".to_string()));
                             result.insert("sender_host".to_string(), Value::Str("192.168.1.100".to_string()));
                             result.insert("sender_port".to_string(), Value::Int(12345));
-                            self.stack.push(Value::Object(result));
+                            self.stack.push(Value::Object(result.into()));
                         }
                         (s, sz) => return Err(VMError::TypeMismatch { 
                             expected: "connection and int (buffer size)".to_string(), 
@@ -1684,32 +2532,49 @@ impl VM {
                         }),
                     }
                 }
-                OpCode::StreamCreate => {
-                    let val = self.pop_stack("STREAM_CREATE")?;
+                OpCode::StreamCreate | OpCode::StreamJsonLines | OpCode::StreamCsv => {
+                    let name = opcode_to_text(instruction);
+                    let val = self.pop_stack(&name)?;
                     match val {
-                        Value::Str(stream_type) => {
-                            let stream_id = format!("stream:{}:{}", stream_type, self.instruction_count);
+                        Value::Str(path) => {
+                            let file = std::fs::File::open(&path).map_err(|e| VMError::FileError {
+                                filename: path.clone(),
+                                error: e.to_string(),
+                            })?;
+                            let stream = match instruction {
+                                OpCode::StreamJsonLines => FileStream::JsonLines { reader: std::io::BufReader::new(file), line: 0 },
+                                OpCode::StreamCsv => FileStream::Csv(csv::ReaderBuilder::new()
+                                    .has_headers(false)
+                                    .flexible(true)
+                                    .from_reader(file)),
+                                _ => FileStream::Lines(std::io::BufReader::new(file)),
+                            };
+                            self.stream_count += 1;
+                            let stream_id = format!("{}#{}", path, self.stream_count);
+                            self.streams.insert(stream_id.clone(), stream);
                             self.stack.push(Value::Stream(stream_id));
                         }
                         _ => return Err(VMError::TypeMismatch { 
-                            expected: "string (stream type)".to_string(), 
+                            expected: "string (file path)".to_string(), 
                             got: format!("{:?}", val), 
-                            operation: "STREAM_CREATE".to_string() 
+                            operation: name,
                         }),
                     }
                 }
                 OpCode::StreamRead => {
-                    let size = self.pop_stack("STREAM_READ")?;
-                    let stream = self.pop_stack("STREAM_READ")?;
-                    match (stream, size) {
-                        (Value::Stream(_stream_id), Value::Int(read_size)) => {
-                            // Simplified stream read
-                            let data = format!("stream_data_{}", read_size);
-                            self.stack.push(Value::Str(data));
+                    let val = self.pop_stack("STREAM_READ")?;
+                    match val {
+                        Value::Stream(stream_id) => {
+                            let stream = self.streams.get_mut(&stream_id).ok_or_else(|| VMError::FileError {
+                                filename: stream_id.clone(),
+                                error: "STREAM_READ on a stream that is not open".to_string(),
+                            })?;
+                            let value = stream.read_next(&stream_id)?;
+                            self.stack.push(value.unwrap_or(Value::Null));
                         }
-                        (s, sz) => return Err(VMError::TypeMismatch { 
-                            expected: "stream and int (size)".to_string(), 
-                            got: format!("{:?}, {:?}", s, sz), 
+                        _ => return Err(VMError::TypeMismatch { 
+                            expected: "stream".to_string(), 
+                            got: format!("{:?}", val), 
                             operation: "STREAM_READ".to_string() 
                         }),
                     }
@@ -1736,9 +2601,10 @@ impl VM {
                 OpCode::StreamClose => {
                     let val = self.pop_stack("STREAM_CLOSE")?;
                     match val {
-                        Value::Stream(_stream_id) => {
-                            // Simplified stream close
-                            self.stack.push(Value::Bool(true));
+                        Value::Stream(stream_id) => {
+                            // False if the stream was already closed
+                            let closed = self.streams.remove(&stream_id).is_some();
+                            self.stack.push(Value::Bool(closed));
                         }
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "stream".to_string(), 
@@ -1751,21 +2617,9 @@ impl VM {
                     let val = self.pop_stack("JSON_PARSE")?;
                     match val {
                         Value::Str(json_str) => {
-                            // Simplified JSON parsing - in real implementation would use serde_json
-                            if json_str.starts_with('{') && json_str.ends_with('}') {
-                                let mut obj = HashMap::new();
-                                obj.insert("parsed".to_string(), Value::Bool(true));
-                                obj.insert("data".to_string(), Value::Str("json_data".to_string()));
-                                self.stack.push(Value::Object(obj));
-                            } else if json_str.starts_with('[') && json_str.ends_with(']') {
-                                let list = vec![
-                                    Value::Str("item1".to_string()),
-                                    Value::Str("item2".to_string()),
-                                ];
-                                self.stack.push(Value::List(list));
-                            } else {
-                                self.stack.push(Value::Str(json_str));
-                            }
+                            let value = parse_json(&json_str)
+                                .map_err(|e| VMError::RuntimeError(format!("JSON_PARSE failed: {}", e)))?;
+                            self.stack.push(value);
                         }
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "string (JSON)".to_string(), 
@@ -1776,45 +2630,16 @@ impl VM {
                 }
                 OpCode::JsonStringify => {
                     let val = self.pop_stack("JSON_STRINGIFY")?;
-                    match val {
-                        Value::Object(_) => {
-                            // Simplified JSON stringification
-                            self.stack.push(Value::Str("{\"key\":\"value\"}".to_string()));
-                        }
-                        Value::List(_) => {
-                            self.stack.push(Value::Str("[\"item1\",\"item2\"]".to_string()));
-                        }
-                        Value::Str(s) => {
-                            self.stack.push(Value::Str(format!("\"{}\"", s)));
-                        }
-                        Value::Int(n) => {
-                            self.stack.push(Value::Str(n.to_string()));
-                        }
-                        Value::Bool(b) => {
-                            self.stack.push(Value::Str(b.to_string()));
-                        }
-                        Value::Null => {
-                            self.stack.push(Value::Str("null".to_string()));
-                        }
-                        _ => {
-                            self.stack.push(Value::Str("{}".to_string()));
-                        }
-                    }
+                    let text = to_json(&val)
+                        .map_err(|e| VMError::RuntimeError(format!("JSON_STRINGIFY failed: {}", e)))?;
+                    self.stack.push(Value::Str(text));
                 }
                 OpCode::CsvParse => {
                     let val = self.pop_stack("CSV_PARSE")?;
                     match val {
                         Value::Str(csv_str) => {
-                            // Simplified CSV parsing
-                            let rows: Vec<Value> = csv_str.lines()
-                                .map(|line| {
-                                    let columns: Vec<Value> = line.split(',')
-                                        .map(|col| Value::Str(col.trim().to_string()))
-                                        .collect();
-                                    Value::List(columns)
-                                })
-                                .collect();
-                            self.stack.push(Value::List(rows));
+                            let rows = csv_parse(&csv_str)?;
+                            self.stack.push(Value::List(rows.into()));
                         }
                         _ => return Err(VMError::TypeMismatch { 
                             expected: "string (CSV)".to_string(), 
@@ -1827,21 +2652,7 @@ impl VM {
                     let val = self.pop_stack("CSV_WRITE")?;
                     match val {
                         Value::List(rows) => {
-                            // Simplified CSV writing
-                            let mut csv_output = String::new();
-                            for (i, row) in rows.iter().enumerate() {
-                                if i > 0 {
-                                    csv_output.push('\n');
-                                }
-                                if let Value::List(columns) = row {
-                                    for (j, col) in columns.iter().enumerate() {
-                                        if j > 0 {
-                                            csv_output.push(',');
-                                        }
-                                        csv_output.push_str(&format!("{}", col));
-                                    }
-                                }
-                            }
+                            let csv_output = csv_write(&rows)?;
                             self.stack.push(Value::Str(csv_output));
                         }
                         _ => return Err(VMError::TypeMismatch { 
@@ -1851,6 +2662,18 @@ impl VM {
                         }),
                     }
                 }
+                OpCode::RegexMatch | OpCode::RegexFind => {
+                    let name = opcode_to_text(instruction);
+                    let pattern = self.pop_stack(&name)?;
+                    let text = self.pop_stack(&name)?;
+                    self.stack.push(ops::regex_search(&mut self.regex_cache, instruction, text, pattern)?);
+                }
+                OpCode::RegexReplace => {
+                    let replacement = self.pop_stack("REGEX_REPLACE")?;
+                    let pattern = self.pop_stack("REGEX_REPLACE")?;
+                    let text = self.pop_stack("REGEX_REPLACE")?;
+                    self.stack.push(ops::regex_replace(&mut self.regex_cache, text, pattern, replacement)?);
+                }
                 OpCode::Compress => {
                     let val = self.pop_stack("COMPRESS")?;
                     match val {
@@ -1948,8 +2771,18 @@ impl VM {
                     let val = self.pop_stack("DB_CONNECT")?;
                     match val {
                         Value::Str(connection_string) => {
-                            // Simplified database connection - in real implementation would use sqlx, rusqlite, etc.
-                            let db_id = format!("db:{}", connection_string);
+                            // "sqlite:///tmp/app.db", "sqlite::memory:" and plain paths all work;
+                            // "sqlite://memory" is in-memory too rather than a file named memory
+                            let path = match connection_string.as_str() {
+                                "sqlite://memory" => ":memory:",
+                                other => other.strip_prefix("sqlite://")
+                                    .or_else(|| other.strip_prefix("sqlite:"))
+                                    .unwrap_or(other),
+                            };
+                            let connection = rusqlite::Connection::open(path)
+                                .map_err(|e| VMError::DatabaseError { database: connection_string.clone(), error: e.to_string() })?;
+                            let db_id = format!("db:{}#{}", connection_string, self.db_connections.len() + 1);
+                            self.db_connections.insert(db_id.clone(), connection);
                             self.stack.push(Value::Connection(db_id));
                         }
                         _ => return Err(VMError::TypeMismatch { 
@@ -1963,20 +2796,11 @@ impl VM {
                     let query = self.pop_stack("DB_QUERY")?;
                     let db = self.pop_stack("DB_QUERY")?;
                     match (db, query) {
-                        (Value::Connection(_db_id), Value::Str(_sql_query)) => {
-                            // Simplified database query
-                            let mut result = HashMap::new();
-                            result.insert("rows".to_string(), Value::Int(3));
-                            result.insert("columns".to_string(), Value::List(vec![
-                                Value::Str("id".to_string()),
-                                Value::Str("name".to_string()),
-                            ]));
-                            result.insert("data".to_string(), Value::List(vec![
-                                Value::List(vec![Value::Int(1), Value::Str("Alice".to_string())]),
-                                Value::List(vec![Value::Int(2), Value::Str("Bob".to_string())]),
-                                Value::List(vec![Value::Int(3), Value::Str("Charlie".to_string())]),
-                            ]));
-                            self.stack.push(Value::Object(result));
+                        (Value::Connection(db_id), Value::Str(sql_query)) => {
+                            let connection = self.db_connection(&db_id, "DB_QUERY")?;
+                            let result = db_query(connection, &sql_query)
+                                .map_err(|e| VMError::DatabaseError { database: db_id, error: e.to_string() })?;
+                            self.stack.push(result);
                         }
                         (d, q) => return Err(VMError::TypeMismatch { 
                             expected: "connection and string (SQL query)".to_string(), 
@@ -1989,9 +2813,11 @@ impl VM {
                     let command = self.pop_stack("DB_EXEC")?;
                     let db = self.pop_stack("DB_EXEC")?;
                     match (db, command) {
-                        (Value::Connection(_db_id), Value::Str(_sql_command)) => {
-                            // Simplified database execution
-                            self.stack.push(Value::Int(1)); // affected rows
+                        (Value::Connection(db_id), Value::Str(sql_command)) => {
+                            let connection = self.db_connection(&db_id, "DB_EXEC")?;
+                            let affected = connection.execute(&sql_command, [])
+                                .map_err(|e| VMError::DatabaseError { database: db_id, error: e.to_string() })?;
+                            self.stack.push(Value::Int(affected as i64));
                         }
                         (d, c) => return Err(VMError::TypeMismatch { 
                             expected: "connection and string (SQL command)".to_string(), 
@@ -2001,7 +2827,8 @@ impl VM {
                     }
                 }
                 OpCode::DumpScope => {
-                    println!("Current scope: {:?}", self.variables.last());
+                    let state = self.dump_state()?;
+                    self.write_output(&format!("{}\n", state))?;
                 }
                 // Exception handling opcodes
                 OpCode::Try { catch_addr } => {
@@ -2019,30 +2846,88 @@ impl VM {
                         Value::Exception { .. } => exception_value,
                         Value::Str(msg) => Value::Exception { 
                             message: msg,
-                            stack_trace: vec![format!("at instruction {}", self.ip)]
+                            stack_trace: self.backtrace()
                         },
                         other => Value::Exception {
                             message: format!("Thrown value: {:?}", other),
-                            stack_trace: vec![format!("at instruction {}", self.ip)]
+                            stack_trace: self.backtrace()
                         }
                     };
                     
                     self.throw_exception(exception)?;
                 }
+                OpCode::Rethrow => {
+                    match self.pop_stack("RETHROW")? {
+                        Value::Exception { message, mut stack_trace } => {
+                            stack_trace.push(format!("rethrown {}", self.location(self.ip)));
+                            self.throw_exception(Value::Exception { message, stack_trace })?;
+                        }
+                        other => return Err(VMError::TypeMismatch {
+                            expected: "exception".to_string(),
+                            got: format!("{:?}", other),
+                            operation: "RETHROW".to_string(),
+                        }),
+                    }
+                }
                 OpCode::EndTry => {
                     // Pop the exception handler when exiting try block normally
                     self.pop_exception_handler();
                 }
+                OpCode::Finally => {
+                    // Reached without an exception: nothing to rethrow at END_FINALLY
+                    self.finally_stack.push(None);
+                }
+                OpCode::EndFinally => {
+                    match self.finally_stack.pop() {
+                        Some(Some(exception)) => self.throw_exception(exception)?,
+                        Some(None) => {}
+                        None => return Err(VMError::RuntimeError("END_FINALLY without a matching FINALLY".to_string())),
+                    }
+                }
+                OpCode::ExceptionMessage | OpCode::ExceptionTrace => {
+                    let operation = crate::bytecode::opcode_to_text(instruction);
+                    match self.pop_stack(&operation)? {
+                        Value::Exception { message, stack_trace } => {
+                            self.stack.push(if matches!(instruction, OpCode::ExceptionMessage) {
+                                Value::Str(message)
+                            } else {
+                                Value::List(stack_trace.into_iter().map(Value::Str).collect())
+                            });
+                        }
+                        other => return Err(VMError::TypeMismatch {
+                            expected: "exception".to_string(),
+                            got: format!("{:?}", other),
+                            operation,
+                        }),
+                    }
+                }
                 OpCode::Import(path) => {
                     self.import_module(path)?;
                 }
+                OpCode::ImportAs(path, prefix) => {
+                    self.import_module_as(path, prefix)?;
+                }
+                OpCode::ImportFrom(path, names) => {
+                    self.import_from_module(path, names)?;
+                }
                 OpCode::Export(name) => {
                     self.export_symbol(name)?;
                 }
+                OpCode::ExportAs(name) => {
+                    let value = self.pop_stack("EXPORT_AS")?;
+                    self.exports.insert(name.clone(), value);
+                }
+                OpCode::ReloadModule(path) => {
+                    self.reload_module(path)?;
+                }
                 OpCode::Spawn => {
                     // For VM struct, not supported (use TinyProc instead)
                     return Err(VMError::UnsupportedOperation("SPAWN not supported in VM, use TinyProc scheduler".to_string()));
                 }
+                OpCode::SpawnRegistered(_) => {
+                    // For VM struct, not supported (use TinyProc instead)
+                    return Err(VMError::UnsupportedOperation("SPAWN_REGISTERED not supported in VM, use TinyProc scheduler".to_string()));
+                }
                 OpCode::Receive => {
                     // For VM struct, not supported (use TinyProc instead)
                     return Err(VMError::UnsupportedOperation("RECEIVE not supported in VM, use TinyProc scheduler".to_string()));
@@ -2075,7 +2960,35 @@ impl VM {
                     // For VM struct, not supported (use TinyProc instead)
                     return Err(VMError::UnsupportedOperation("TRAP_EXIT not supported in VM, use TinyProc scheduler".to_string()));
                 }
-                OpCode::ReceiveMatch(_patterns) => {
+                OpCode::ProcCall => {
+                    // For VM struct, not supported (use TinyProc instead)
+                    return Err(VMError::UnsupportedOperation("PROC_CALL not supported in VM, use TinyProc scheduler".to_string()));
+                }
+                OpCode::ProcReply => {
+                    // For VM struct, not supported (use TinyProc instead)
+                    return Err(VMError::UnsupportedOperation("PROC_REPLY not supported in VM, use TinyProc scheduler".to_string()));
+                }
+                OpCode::SelfPid | OpCode::ParentPid => {
+                    // Only processes have pids (use TinyProc instead)
+                    return Err(VMError::UnsupportedOperation(format!("{} not supported in VM, use TinyProc scheduler", crate::bytecode::opcode_to_text(instruction))));
+                }
+                OpCode::Broadcast => {
+                    // Messages go through the scheduler pool (use TinyProc instead)
+                    return Err(VMError::UnsupportedOperation("BROADCAST not supported in VM, use TinyProc scheduler".to_string()));
+                }
+                OpCode::SendAfter | OpCode::CancelTimer => {
+                    // Timers are delivered by the scheduler pool (use TinyProc instead)
+                    return Err(VMError::UnsupportedOperation(format!("{} not supported in VM, use TinyProc scheduler", crate::bytecode::opcode_to_text(instruction))));
+                }
+                OpCode::PutDict | OpCode::GetDict | OpCode::EraseDict => {
+                    // The dictionary belongs to a process (use TinyProc instead)
+                    return Err(VMError::UnsupportedOperation(format!("{} not supported in VM, use TinyProc scheduler", crate::bytecode::opcode_to_text(instruction))));
+                }
+                OpCode::TableNew | OpCode::TableInsert | OpCode::TableLookup | OpCode::TableDelete => {
+                    // Tables are shared through the scheduler pool (use TinyProc instead)
+                    return Err(VMError::UnsupportedOperation(format!("{} not supported in VM, use TinyProc scheduler", crate::bytecode::opcode_to_text(instruction))));
+                }
+                OpCode::ReceiveMatch(_) | OpCode::ReceiveMatchAfter(..) => {
                     // For VM struct, not supported (use TinyProc instead)
                     return Err(VMError::UnsupportedOperation("RECEIVE_MATCH not supported in VM, use TinyProc scheduler".to_string()));
                 }
@@ -2108,81 +3021,163 @@ impl VM {
                     return Err(VMError::UnsupportedOperation("RESTARTCHILD not supported in VM, use TinyProc scheduler".to_string()));
                 }
                 OpCode::Halt => {
-                    // This should never be reached since HALT is handled in run()
-                    unreachable!("HALT instruction should be handled in run() method")
+                    // run() stops at HALT before getting here, so only execute_native can land here
+                    return Err(VMError::UnsupportedOperation("HALT can only stop a running program".to_string()));
                 }
             }
             Ok(())
         }
 
     fn import_module(&mut self, path: &str) -> VMResult<()> {
+        for (name, value) in self.load_module(path)? {
+            self.set_variable(name, value)?;
+        }
+        Ok(())
+    }
+
+    // Bind every export of the module as `prefix.name`
+    fn import_module_as(&mut self, path: &str, prefix: &str) -> VMResult<()> {
+        for (name, value) in self.load_module(path)? {
+            self.bind_import("IMPORT_AS", format!("{}.{}", prefix, name), value)?;
+        }
+        Ok(())
+    }
+
+    // Bind only the listed exports of the module
+    fn import_from_module(&mut self, path: &str, names: &[String]) -> VMResult<()> {
+        let mut exports = self.load_module(path)?;
+        for name in names {
+            let value = exports.remove(name).ok_or_else(|| {
+                VMError::RuntimeError(format!("IMPORT_FROM: {} does not export {}", path, name))
+            })?;
+            self.bind_import("IMPORT_FROM", name.clone(), value)?;
+        }
+        Ok(())
+    }
+
+    // Explicit imports refuse to replace a name that is already defined
+    fn bind_import(&mut self, operation: &str, name: String, value: Value) -> VMResult<()> {
+        let scope = self.variables.last().ok_or(VMError::NoVariableScope)?;
+        if scope.contains_key(&name) {
+            return Err(VMError::RuntimeError(format!("{}: {} is already defined", operation, name)));
+        }
+        self.set_variable(name, value)
+    }
+
+    // Load a module the first time it is imported and return its exports
+    fn load_module(&mut self, path: &str) -> VMResult<HashMap<String, Value>> {
+        let key = crate::bytecode::module_key(path);
+
         // Check for circular dependencies using global loading stack
-        if self.loading_stack.contains(&path.to_string()) {
+        if self.loading_stack.contains(&key) {
             return Err(VMError::CircularDependency(path.to_string()));
         }
 
         // Check if module is already loaded
-        if let Some(exports) = self.loaded_modules.get(path).cloned() {
-            // Module already loaded, import its exports into current scope
-            for (name, value) in exports {
-                self.set_variable(name, value)?;
-            }
-            return Ok(());
+        if let Some(exports) = self.loaded_modules.get(&key) {
+            return Ok(exports.clone());
         }
 
+        self.install_module(path, key)
+    }
+
+    // Hot reload: load the module's file again as a new version and rebind its exports in the
+    // global scope. The old version's code stays where it is in the instruction space, so
+    // calls already running it, and function values taken from it before the reload, carry on
+    // with the old code; only lookups of the rebound names and later imports see the new one.
+    pub fn reload_module(&mut self, path: &str) -> VMResult<()> {
+        let key = crate::bytecode::module_key(path);
+        if self.loading_stack.contains(&key) {
+            return Err(VMError::CircularDependency(path.to_string()));
+        }
+        if !self.loaded_modules.contains_key(&key) {
+            return Err(VMError::RuntimeError(format!("RELOAD_MODULE: {} has not been imported", path)));
+        }
+
+        let exports = self.install_module(path, key)?;
+        let globals = self.variables.first_mut().ok_or(VMError::NoVariableScope)?;
+        globals.extend(exports);
+        Ok(())
+    }
+
+    // Append a module's code to the instruction space, run its top level and cache its exports
+    fn install_module(&mut self, path: &str, key: String) -> VMResult<HashMap<String, Value>> {
         // Add to loading stack to detect circular dependencies
-        self.loading_stack.push(path.to_string());
+        self.loading_stack.push(key.clone());
 
         // Load and parse the module
-        let module_instructions = parse_program(path)?;
+        let module_instructions = match load_program(path) {
+            Ok(instructions) => instructions,
+            Err(e) => {
+                self.loading_stack.pop();
+                return Err(e);
+            }
+        };
         
         // Merge module instructions into main VM's instruction space
         let base_addr = self.instructions.len();
-        let mut adjusted_exports = HashMap::new();
-        
-        // Adjust addresses in module instructions and append to main instruction space
         let adjusted_instructions = module_instructions.iter()
             .map(|inst| self.adjust_instruction_addresses(inst, base_addr))
             .collect::<Vec<_>>();
         self.instructions.extend(adjusted_instructions);
+        let end_addr = self.instructions.len();
         
-        // Create a new VM with the module instructions to get exports
-        // Share the loading context to detect circular dependencies
-        let mut module_vm = VM::new(module_instructions);
-        module_vm.debug_mode = self.debug_mode;
-        module_vm.loading_stack = self.loading_stack.clone(); // Share loading context
-        module_vm.loaded_modules = self.loaded_modules.clone(); // Share loaded modules
-        
-        // Run the module to generate exports
-        module_vm.run()?;
-        
-        // Update our loaded modules with any new modules the sub-module loaded
-        self.loaded_modules.extend(module_vm.loaded_modules);
-        
-        // Adjust function addresses in exports to point to merged instruction space
-        for (name, value) in module_vm.exports {
-            let adjusted_value = self.adjust_value_addresses(value, base_addr);
-            adjusted_exports.insert(name, adjusted_value);
-        }
+        // Run the module's top-level code once, where it now lives, so its exports already
+        // point into the merged instruction space
+        let module_result = self.run_module(base_addr, end_addr);
+        self.loading_stack.pop();
+        let adjusted_exports = module_result?;
         
         // Cache the loaded module
-        self.loaded_modules.insert(path.to_string(), adjusted_exports.clone());
+        self.loaded_modules.insert(key, adjusted_exports.clone());
         
-        // Import the exports into current scope
         if self.debug_mode {
             println!("Importing {} exports from module {}", adjusted_exports.len(), path);
-        }
-        for (name, value) in adjusted_exports {
-            if self.debug_mode {
+            for (name, value) in &adjusted_exports {
                 println!("Importing export: {} = {:?}", name, value);
             }
-            self.set_variable(name, value)?;
         }
         
-        // Remove from loading stack
-        self.loading_stack.pop();
-        
-        Ok(())
+        Ok(adjusted_exports)
+    }
+
+    // Run the module code at base_addr..end_addr in a fresh global frame with its own stack,
+    // then put the importer's execution state back. Returns what the module exported.
+    fn run_module(&mut self, base_addr: usize, end_addr: usize) -> VMResult<HashMap<String, Value>> {
+        let ip = std::mem::replace(&mut self.ip, base_addr);
+        let stack = std::mem::take(&mut self.stack);
+        let call_stack = std::mem::take(&mut self.call_stack);
+        let call_stack_bases = std::mem::take(&mut self.call_stack_bases);
+        let call_return_types = std::mem::take(&mut self.call_return_types);
+        let variables = std::mem::replace(&mut self.variables, vec![HashMap::new()]);
+        let try_stack = std::mem::take(&mut self.try_stack);
+        let finally_stack = std::mem::take(&mut self.finally_stack);
+        let lambda_captures = std::mem::take(&mut self.lambda_captures);
+        let exports = std::mem::take(&mut self.exports);
+
+        let mut result = Ok(());
+        while self.ip < end_addr {
+            match self.step_instruction() {
+                Ok(None) => {}
+                Ok(Some(_)) => break,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        self.ip = ip;
+        self.stack = stack;
+        self.call_stack = call_stack;
+        self.call_stack_bases = call_stack_bases;
+        self.call_return_types = call_return_types;
+        self.variables = variables;
+        self.try_stack = try_stack;
+        self.finally_stack = finally_stack;
+        self.lambda_captures = lambda_captures;
+        let module_exports = std::mem::replace(&mut self.exports, exports);
+        result.map(|()| module_exports)
     }
 
     fn export_symbol(&mut self, name: &str) -> VMResult<()> {
@@ -2195,43 +3190,6 @@ impl VM {
         Ok(())
     }
 
-    fn adjust_value_addresses(&self, value: Value, base_addr: usize) -> Value {
-        match value {
-            Value::Function { addr, params } => {
-                Value::Function { 
-                    addr: addr + base_addr,
-                    params 
-                }
-            }
-            Value::Closure { addr, params, captured } => {
-                // Recursively adjust addresses in captured environment
-                let adjusted_captured = captured.into_iter()
-                    .map(|(name, val)| (name, self.adjust_value_addresses(val, base_addr)))
-                    .collect();
-                
-                Value::Closure { 
-                    addr: addr + base_addr,
-                    params,
-                    captured: adjusted_captured
-                }
-            }
-            Value::List(items) => {
-                let adjusted_items = items.into_iter()
-                    .map(|item| self.adjust_value_addresses(item, base_addr))
-                    .collect();
-                Value::List(adjusted_items)
-            }
-            Value::Object(map) => {
-                let adjusted_map = map.into_iter()
-                    .map(|(key, val)| (key, self.adjust_value_addresses(val, base_addr)))
-                    .collect();
-                Value::Object(adjusted_map)
-            }
-            // Other value types don't contain addresses
-            other => other
-        }
-    }
-
     fn adjust_instruction_addresses(&self, instruction: &OpCode, base_addr: usize) -> OpCode {
         match instruction {
             OpCode::Jmp(addr) => OpCode::Jmp(addr + base_addr),
@@ -2240,9 +3198,10 @@ impl VM {
                 addr: addr + base_addr, 
                 params: params.clone() 
             },
-            OpCode::MakeFunction { addr, params } => OpCode::MakeFunction { 
+            OpCode::MakeFunction { addr, params, returns } => OpCode::MakeFunction { 
                 addr: addr + base_addr, 
-                params: params.clone() 
+                params: params.clone(),
+                returns: returns.clone()
             },
             OpCode::MakeLambda { addr, params } => OpCode::MakeLambda { 
                 addr: addr + base_addr, 
@@ -2257,3 +3216,222 @@ impl VM {
     }
 }
 
+// Parse a strftime-style format, rejecting specifiers chrono does not understand instead of
+// printing them literally
+fn strftime_items<'a>(format: &'a str, operation: &str) -> VMResult<Vec<chrono::format::Item<'a>>> {
+    let items: Vec<_> = chrono::format::StrftimeItems::new(format).collect();
+    if items.iter().any(|item| matches!(item, chrono::format::Item::Error)) {
+        return Err(VMError::RuntimeError(format!("{} has an invalid format string: {:?}", operation, format)));
+    }
+    Ok(items)
+}
+
+fn http_agent() -> ureq::Agent {
+    ureq::AgentBuilder::new().timeout(std::time::Duration::from_secs(30)).build()
+}
+
+// An HTTP response as an object with `status`, `headers` and `body`. Error statuses are still
+// responses so programs can inspect them; only failing to get a response at all is an error.
+fn http_response(url: &str, result: Result<ureq::Response, ureq::Error>) -> VMResult<Value> {
+    let network_error = |error: String| VMError::NetworkError { url: url.to_string(), error };
+    let response = match result {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(e) => return Err(network_error(e.to_string())),
+    };
+
+    let status = Value::Int(response.status() as i64);
+    let headers: IndexMap<String, Value> = response.headers_names().into_iter()
+        .filter_map(|name| {
+            let value = response.header(&name)?.to_string();
+            Some((name, Value::Str(value)))
+        })
+        .collect();
+    let body = response.into_string().map_err(|e| network_error(e.to_string()))?;
+
+    Ok(Value::Object(IndexMap::from([
+        ("status".to_string(), status),
+        ("headers".to_string(), Value::Object(headers.into())),
+        ("body".to_string(), Value::Str(body)),
+    ]).into()))
+}
+
+// Limits on what HTTP_ACCEPT reads from a client, so a bad request is an error rather than
+// an unbounded allocation
+const MAX_HTTP_LINE: usize = 8 * 1024;
+const MAX_HTTP_HEADERS: usize = 100;
+const MAX_HTTP_BODY: usize = 16 * 1024 * 1024;
+
+// Reads a request from a client as an object with `method`, `path`, `headers` (names in
+// lower case) and `body`. The body is as long as Content-Length says; without it there is none.
+fn read_http_request(stream: &std::net::TcpStream) -> std::io::Result<IndexMap<String, Value>> {
+    use std::io::Read;
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+    let mut reader = std::io::BufReader::new(stream);
+
+    let request_line = read_http_line(&mut reader)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(invalid("malformed HTTP request line"));
+    };
+
+    let mut headers = IndexMap::new();
+    loop {
+        let line = read_http_line(&mut reader)?;
+        if line.is_empty() {
+            return Err(invalid("connection closed before the end of the headers"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HTTP_HEADERS {
+            return Err(invalid(&format!("more than {} headers", MAX_HTTP_HEADERS)));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), Value::Str(value.trim().to_string()));
+        }
+    }
+
+    let length: usize = match headers.get("content-length") {
+        Some(Value::Str(length)) => length.parse().map_err(|_| invalid("invalid Content-Length"))?,
+        _ => 0,
+    };
+    if length > MAX_HTTP_BODY {
+        return Err(invalid(&format!("body of {} bytes is larger than {} bytes", length, MAX_HTTP_BODY)));
+    }
+    let mut body = Vec::new();
+    reader.take(length as u64).read_to_end(&mut body)?;
+    if body.len() < length {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "connection closed before the end of the body"));
+    }
+
+    Ok(IndexMap::from([
+        ("method".to_string(), Value::Str(method.to_string())),
+        ("path".to_string(), Value::Str(path.to_string())),
+        ("headers".to_string(), Value::Object(headers.into())),
+        ("body".to_string(), Value::Str(String::from_utf8_lossy(&body).into_owned())),
+    ]))
+}
+
+// One line of the request including its line ending, or "" at the end of the stream
+fn read_http_line(reader: &mut impl std::io::BufRead) -> std::io::Result<String> {
+    use std::io::{BufRead, Read};
+    let mut line = String::new();
+    reader.take(MAX_HTTP_LINE as u64 + 1).read_line(&mut line)?;
+    if line.len() > MAX_HTTP_LINE {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("header line longer than {} bytes", MAX_HTTP_LINE)));
+    }
+    Ok(line)
+}
+
+// Reason phrase for the status line; clients go by the code, so others are left blank
+fn http_reason(status: i64) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+// Collects the path of every entry under `dir`, relative to `root`. Symlinked directories
+// are listed but not followed, so a link cycle can't recurse forever.
+fn list_dir_recursive(root: &std::path::Path, dir: &std::path::Path, paths: &mut Vec<String>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if let Ok(relative) = path.strip_prefix(root) {
+            paths.push(relative.to_string_lossy().into_owned());
+        }
+        if entry.file_type()?.is_dir() {
+            list_dir_recursive(root, &path, paths)?;
+        }
+    }
+    Ok(())
+}
+
+// RFC 4180 parsing: quoted fields may hold commas, newlines and doubled quotes. Rows may
+// have different lengths, and every field is read as a string.
+fn csv_parse(text: &str) -> VMResult<Vec<Value>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    reader.records()
+        .map(|record| {
+            let record = record.map_err(|e| VMError::RuntimeError(format!("CSV_PARSE failed: {}", e)))?;
+            Ok(Value::List(record.iter().map(|field| Value::Str(field.to_string())).collect()))
+        })
+        .collect()
+}
+
+// Rows are joined with `\n`, and fields are quoted only when they need to be
+fn csv_write(rows: &[Value]) -> VMResult<String> {
+    let write_error = |e: csv::Error| VMError::RuntimeError(format!("CSV_WRITE failed: {}", e));
+    let mut writer = csv::WriterBuilder::new()
+        .flexible(true)
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(Vec::new());
+    for row in rows {
+        let Value::List(columns) = row else {
+            return Err(VMError::TypeMismatch {
+                expected: "list of lists (CSV data)".to_string(),
+                got: format!("{:?}", row),
+                operation: "CSV_WRITE".to_string(),
+            });
+        };
+        writer.write_record(columns.iter().map(|col| format!("{}", col))).map_err(write_error)?;
+    }
+    let bytes = writer.into_inner().map_err(|e| write_error(e.into_error().into()))?;
+    let mut output = String::from_utf8(bytes)
+        .map_err(|e| VMError::RuntimeError(format!("CSV_WRITE failed: {}", e)))?;
+    if output.ends_with('\n') {
+        output.pop();
+    }
+    Ok(output)
+}
+
+// A query result as an object with `columns`, `data` (one list per row) and the `rows` count
+fn db_query(connection: &rusqlite::Connection, sql: &str) -> rusqlite::Result<Value> {
+    let mut statement = connection.prepare(sql)?;
+    let columns: Vec<Value> = statement.column_names().into_iter()
+        .map(|name| Value::Str(name.to_string()))
+        .collect();
+    let column_count = columns.len();
+
+    let mut data = Vec::new();
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let values = (0..column_count)
+            .map(|i| row.get_ref(i).map(sql_to_value))
+            .collect::<rusqlite::Result<Vec<Value>>>()?;
+        data.push(Value::List(values.into()));
+    }
+
+    Ok(Value::Object(IndexMap::from([
+        ("rows".to_string(), Value::Int(data.len() as i64)),
+        ("columns".to_string(), Value::List(columns.into())),
+        ("data".to_string(), Value::List(data.into())),
+    ]).into()))
+}
+
+fn sql_to_value(value: rusqlite::types::ValueRef) -> Value {
+    use rusqlite::types::ValueRef;
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) => Value::Int(i),
+        ValueRef::Real(f) => Value::Float(f),
+        ValueRef::Text(text) => Value::Str(String::from_utf8_lossy(text).into_owned()),
+        ValueRef::Blob(bytes) => Value::Bytes(bytes.to_vec()),
+    }
+}
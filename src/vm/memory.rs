@@ -82,6 +82,7 @@ pub struct ExceptionHandler {
     pub stack_size: usize,           // stack size when try block started
     pub call_stack_size: usize,      // call stack size when try block started
     pub variable_frames: usize,      // number of variable frames when try block started
+    pub finally_depth: usize,        // FINALLY blocks running when try block started
 }
 
 /// Call stack management
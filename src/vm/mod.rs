@@ -1,14 +1,23 @@
 pub mod errors;
 pub mod opcode;
 pub mod value;
+pub mod serialize;
 pub mod stack;
 pub mod memory;
 pub mod machine;
+pub mod output;
+pub mod json;
+pub mod ops;
 
 // Re-export commonly used types
 pub use errors::{VMError, VMResult};
-pub use opcode::{OpCode, ProcId, MessagePattern};
-pub use value::Value;
+pub use opcode::{OpCode, ProcId, MessagePattern, DestructurePattern, variadic_params, param_annotation};
+pub use value::{Value, float_result, check_argument_type, TYPE_NAMES};
+pub use serialize::{serialize_value, deserialize_value};
 
 pub use memory::{ExceptionHandler};
-pub use machine::VM;
\ No newline at end of file
+#[allow(unused_imports)]
+pub use output::{OutputSink, OutputBuffer};
+pub use machine::{VM, DEFAULT_CALL_DEPTH_LIMIT};
+#[allow(unused_imports)]
+pub use machine::RunStatus;
\ No newline at end of file
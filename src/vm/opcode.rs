@@ -1,8 +1,9 @@
+use std::fmt;
 use crate::vm::value::Value;
 
 pub type ProcId = u64;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 pub enum MessagePattern {
     Any,                           // matches any message
@@ -15,7 +16,107 @@ pub enum MessagePattern {
     Guard(String),                 // guard condition (variable name to check)
 }
 
-#[derive(Debug, Clone)]
+// The shape DESTRUCTURE expects, written `[a b ...rest]` for lists and `{name age:years}` for objects
+#[derive(Debug, Clone, PartialEq)]
+pub enum DestructurePattern {
+    Bind(String),                                  // binds the value to a variable; `_` discards it
+    List(Vec<DestructurePattern>, Option<String>), // items in order; without a rest name the length must match
+    Object(Vec<(String, DestructurePattern)>),     // keys the object must have; other keys are ignored
+}
+
+impl DestructurePattern {
+    // The variables the pattern binds for a value, in pattern order, or why the value doesn't fit
+    pub fn bindings(&self, value: &Value) -> Result<Vec<(String, Value)>, String> {
+        let mut bound = Vec::new();
+        self.collect(value, &mut bound)?;
+        Ok(bound)
+    }
+
+    // Every variable the pattern can bind
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            DestructurePattern::Bind(name) if name == "_" => vec![],
+            DestructurePattern::Bind(name) => vec![name.as_str()],
+            DestructurePattern::List(items, rest) => items.iter()
+                .flat_map(|item| item.names())
+                .chain(rest.as_deref().filter(|rest| *rest != "_"))
+                .collect(),
+            DestructurePattern::Object(fields) => fields.iter().flat_map(|(_, field)| field.names()).collect(),
+        }
+    }
+
+    fn collect(&self, value: &Value, bound: &mut Vec<(String, Value)>) -> Result<(), String> {
+        match (self, value) {
+            (DestructurePattern::Bind(name), _) => {
+                if name != "_" {
+                    bound.push((name.clone(), value.clone()));
+                }
+            }
+            (DestructurePattern::List(items, rest), Value::List(values)) => {
+                let fits = if rest.is_some() { values.len() >= items.len() } else { values.len() == items.len() };
+                if !fits {
+                    let at_least = if rest.is_some() { "at least " } else { "" };
+                    return Err(format!("expected {}{} items but the list has {}", at_least, items.len(), values.len()));
+                }
+                for (item, value) in items.iter().zip(values) {
+                    item.collect(value, bound)?;
+                }
+                if let Some(rest) = rest.as_ref().filter(|rest| rest.as_str() != "_") {
+                    bound.push((rest.clone(), Value::List(values[items.len()..].to_vec().into())));
+                }
+            }
+            (DestructurePattern::Object(fields), Value::Object(map)) => {
+                for (key, field) in fields {
+                    let value = map.get(key).ok_or_else(|| format!("object has no key {}", key))?;
+                    field.collect(value, bound)?;
+                }
+            }
+            (DestructurePattern::List(..), other) => return Err(format!("expected a list but got {:?}", other)),
+            (DestructurePattern::Object(_), other) => return Err(format!("expected an object but got {:?}", other)),
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DestructurePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DestructurePattern::Bind(name) => write!(f, "{}", name),
+            DestructurePattern::List(items, rest) => {
+                let mut parts: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                parts.extend(rest.iter().map(|rest| format!("...{}", rest)));
+                write!(f, "[{}]", parts.join(" "))
+            }
+            DestructurePattern::Object(fields) => {
+                let parts: Vec<String> = fields.iter()
+                    .map(|(key, field)| match field {
+                        DestructurePattern::Bind(name) if name == key => key.clone(),
+                        field => format!("{}:{}", key, field),
+                    })
+                    .collect();
+                write!(f, "{{{}}}", parts.join(" "))
+            }
+        }
+    }
+}
+
+// A parameter list whose last name starts with `...` is variadic: that parameter collects the
+// surplus arguments into a list. Returns the fixed parameters and the rest parameter's name.
+pub fn variadic_params(params: &[String]) -> Option<(&[String], &str)> {
+    let (last, fixed) = params.split_last()?;
+    last.strip_prefix("...").map(|rest| (fixed, rest))
+}
+
+// A parameter can be annotated with the type its argument must have, as in `n:int`; on a rest
+// parameter the type applies to each extra argument. Returns the name and the type, if any.
+pub fn param_annotation(param: &str) -> (&str, Option<&str>) {
+    match param.split_once(':') {
+        Some((name, type_name)) => (name, Some(type_name)),
+        None => (param, None),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum OpCode {
     PushInt(i64),
     PushFloat(f64),
@@ -29,17 +130,30 @@ pub enum OpCode {
     MulF,
     Div,
     DivF,
+    Neg,               // negate a number, keeping int or float
+    Pow,               // raise the second value to the power of the top one
+    Sqrt,              // square root, always a float
+    Abs,               // absolute value, keeping int or float
+    Floor,             // round a float down
+    Ceil,              // round a float up
+    Round,             // round a float to the nearest whole number, halves away from zero
     Concat,
     Print,
+    PrintRaw,          // print without a trailing newline
+    PrintErr,          // print a line to stderr
+    DebugPrint,        // print a typed, indented dump of a value
     Halt,
     Jmp(usize),
     Jz(usize),
+    Assert(Option<String>), // pop a value and fail unless it is truthy, with an optional message
     Call { addr: usize, params: Vec<String> },
     Ret,
+    RetN(usize),       // return, checking the function leaves exactly this many values
     Dup,
     Store(String),
     Load(String),
     Delete(String),
+    Destructure(DestructurePattern), // pop a list or object and bind its parts to variables
     Eq,
     Ne,
     Gt,
@@ -73,12 +187,21 @@ pub enum OpCode {
     FileSize,       // Get file size
     DeleteFile,     // Delete file
     ListDir,        // List directory contents
+    ListDirRecursive, // Relative paths of everything under a directory
+    Stat,           // File metadata as an object
     ReadBytes,      // Read file as byte array
     WriteBytes,     // Write byte array to file
+    // Working directory and paths
+    GetCwd,         // Push the current working directory
+    SetCwd,         // Change the current working directory
+    PathJoin,       // Join two path segments
+    PathBasename,   // Last component of a path
+    PathDirname,    // Path without its last component
     // Environment and system
     GetEnv,         // Read environment variable
     SetEnv,         // Set environment variable
-    GetArgs,        // Get command line arguments
+    GetArgs,        // Get the program arguments (after the program file)
+    EnvVars,        // Push an object of all environment variables
     Exec,           // Execute external command
     ExecCapture,    // Execute and capture output
     Exit,           // Exit with status code
@@ -86,9 +209,18 @@ pub enum OpCode {
     GetTime,        // Get current timestamp
     Sleep,          // Sleep for specified duration
     FormatTime,     // Format timestamp
+    ParseTime,      // Parse formatted time into a timestamp
+    // Random numbers
+    RandomInt,      // Random integer between two bounds, inclusive
+    RandomFloat,    // Random float in [0, 1)
+    RandomSeed,     // Seed the random number generator
+    Uuid,           // Fresh random (version 4) UUID string
     // Network operations
     HttpGet,        // HTTP GET request
     HttpPost,       // HTTP POST request
+    HttpServe,      // Listen for HTTP requests on a port
+    HttpAccept,     // Wait for the next HTTP request to a server
+    HttpRespond,    // Answer an accepted HTTP request
     TcpConnect,     // Connect to TCP server
     TcpListen,      // Listen on TCP port
     TcpSend,        // Send data over TCP
@@ -105,10 +237,15 @@ pub enum OpCode {
     StreamRead,     // Read from stream
     StreamWrite,    // Write to stream
     StreamClose,    // Close stream
+    StreamJsonLines, // Open a JSON-lines file as a stream of parsed values
+    StreamCsv,      // Open a CSV file as a stream of rows
     JsonParse,      // Parse JSON string
     JsonStringify,  // Convert to JSON string
     CsvParse,       // Parse CSV data
     CsvWrite,       // Write CSV data
+    RegexMatch,     // Test a string against a regex
+    RegexFind,      // List every match of a regex in a string
+    RegexReplace,   // Replace every match of a regex in a string
     Compress,       // Compress data
     Decompress,     // Decompress data
     Encrypt,        // Encrypt data
@@ -120,28 +257,45 @@ pub enum OpCode {
     // Object operations
     MakeObject,
     SetField(String),   // field name
+    SetFieldMut { var: String, field: String }, // set a field on the object in a variable, in place
     GetField(String),   // field name
     HasField(String),   // field name
     DeleteField(String), // field name
     Keys,              // get all keys as a list
+    Values,            // get all values as a list, in KEYS order
+    Entries,           // get [key, value] pairs as a list, in KEYS order
     // Function operations
-    MakeFunction { addr: usize, params: Vec<String> }, // create function pointer
+    MakeFunction { addr: usize, params: Vec<String>, returns: Option<String> }, // create function pointer
     CallFunction,      // call function from stack
     // Closure and lambda operations
     MakeLambda { addr: usize, params: Vec<String> },   // create lambda/closure
     Capture(String),   // capture variable for closure
+    // Weak references
+    WeakRef,           // replace a list, object or closure with a weak reference to it
+    WeakGet,           // replace a weak reference with its target (null once collected)
     // Exception handling
     Try { catch_addr: usize },  // start try block, jump to catch_addr on exception
     Catch,             // start catch block (exception is on stack)
     Throw,             // throw exception from stack
+    Rethrow,           // rethrow caught exception, adding the current frame to its trace
     EndTry,            // end try block
+    Finally,           // start cleanup block, run on both normal and exceptional exit
+    EndFinally,        // end cleanup block, rethrowing any exception that led into it
+    ExceptionMessage,  // replace exception on stack with its message
+    ExceptionTrace,    // replace exception on stack with its stack trace list
     // Module system
     Import(String),    // import module by path
+    ImportAs(String, String),       // import module exports as prefix.name
+    ImportFrom(String, Vec<String>), // import only the named exports
     Export(String),    // export variable/function by name
+    ExportAs(String),  // pop a value and export it under a name
+    ReloadModule(String), // load the current version of an imported module and rebind its exports
     // Concurrency operations
     Spawn,             // spawn new process from function on stack
+    SpawnRegistered(String), // spawn like SPAWN, registering the child under a name before it runs
     Receive,           // receive message from mailbox
     ReceiveMatch(Vec<MessagePattern>), // selective receive with pattern matching
+    ReceiveMatchAfter(Vec<MessagePattern>, u64), // selective receive that pushes null after N ms
     Yield,             // yield control to scheduler
     Send(ProcId),      // send message to process
     Monitor(ProcId),   // monitor a process
@@ -149,11 +303,27 @@ pub enum OpCode {
     Link(ProcId),      // link to a process
     Unlink(ProcId),    // unlink from a process
     TrapExit,          // set trap_exit flag from stack
+    ProcCall,          // send payload to pid/name on stack and wait for the reply
+    ProcReply,         // answer a PROC_CALL request with the value on stack
+    SelfPid,           // push the current process's pid
+    ParentPid,         // push the pid of the process that spawned this one (null if none)
     // Process registry operations
     Register(String),  // register current process with a name
     Unregister(String), // unregister a name
-    Whereis(String),   // find PID by name (returns 0 if not found)
+    Whereis(String),   // find PID by name (null if not found)
     SendNamed(String), // send message to named process
+    Broadcast,         // send a message to every pid in a list, pushing how many were delivered
+    SendAfter,         // deliver a message to a pid after a delay, pushing a timer id
+    CancelTimer,       // cancel a SEND_AFTER timer, pushing whether it was still pending
+    // Process dictionary (private to each process)
+    PutDict,           // store value under key
+    GetDict,           // push the value under key (null if missing)
+    EraseDict,         // remove key
+    // Tables shared between processes
+    TableNew,          // create a table and push its id
+    TableInsert,       // store value under key in table
+    TableLookup,       // push the value under key in table (null if missing)
+    TableDelete,       // remove key from table
     // Supervision operations
     StartSupervisor, // start a supervisor process
     SuperviseChild(String), // supervise a child process with restart strategy
@@ -0,0 +1,233 @@
+// Opcode bodies shared by the stack VM and TinyProc, so both engines give the same answers
+use std::collections::HashMap;
+use crate::vm::{OpCode, VMError, VMResult, Value, float_result, check_argument_type, variadic_params, param_annotation};
+
+pub type Random = Option<rand::rngs::StdRng>; // seeded from the OS on first use unless RANDOM_SEED ran
+pub type RegexCache = HashMap<String, regex::Regex>; // pattern -> compiled regex
+
+// Pop a call's arguments into `frame`. With a final `...rest` parameter, the caller pushes
+// how many extra arguments it passed last; those are gathered into a list, in order.
+// Arguments are checked against the parameters' type annotations before any is bound.
+pub fn bind_arguments(stack: &mut Vec<Value>, params: &[String], frame: &mut HashMap<String, Value>, operation: &str) -> VMResult<()> {
+    let fixed = match variadic_params(params) {
+        Some((fixed, rest)) => {
+            let count = stack.pop().ok_or_else(|| VMError::StackUnderflow(operation.to_string()))?;
+            let extra = match count {
+                Value::Int(n) if n >= 0 => n as usize,
+                _ => return Err(VMError::TypeMismatch {
+                    expected: format!("a non-negative int (number of arguments for ...{})", rest),
+                    got: format!("{:?}", count),
+                    operation: operation.to_string(),
+                }),
+            };
+            check_stack_size(stack, fixed.len() + extra)?;
+            let extras = stack.split_off(stack.len() - extra);
+            let (rest, rest_type) = param_annotation(rest);
+            for value in &extras {
+                check_argument_type(rest, rest_type, value, operation)?;
+            }
+            frame.insert(rest.to_string(), Value::List(extras.into()));
+            fixed
+        }
+        None => params,
+    };
+    check_stack_size(stack, fixed.len())?;
+    for (param, value) in fixed.iter().zip(&stack[stack.len() - fixed.len()..]) {
+        let (name, type_name) = param_annotation(param);
+        check_argument_type(name, type_name, value, operation)?;
+    }
+    let args = stack.split_off(stack.len() - fixed.len());
+    for (param, value) in fixed.iter().zip(args) {
+        frame.insert(param_annotation(param).0.to_string(), value);
+    }
+    Ok(())
+}
+
+// RET n: the function must leave exactly `declared` values above where its call started
+pub fn check_return_count(declared: usize, stack: &[Value], base: usize) -> VMResult<()> {
+    let left = stack.len().saturating_sub(base);
+    if left != declared {
+        return Err(VMError::ReturnCountMismatch { declared, left });
+    }
+    Ok(())
+}
+
+fn check_stack_size(stack: &[Value], needed: usize) -> VMResult<()> {
+    if stack.len() < needed {
+        return Err(VMError::InsufficientStackItems { needed, available: stack.len() });
+    }
+    Ok(())
+}
+
+pub fn neg(val: Value) -> VMResult<Value> {
+    match val {
+        Value::Int(n) => {
+            let result = n.checked_neg().ok_or_else(|| VMError::IntegerOverflow { operation: "NEG".to_string() })?;
+            Ok(Value::Int(result))
+        }
+        Value::Float(n) => Ok(Value::Float(-n)),
+        _ => Err(VMError::TypeMismatch {
+            expected: "number (int or float)".to_string(),
+            got: format!("{:?}", val),
+            operation: "NEG".to_string()
+        }),
+    }
+}
+
+pub fn pow(base: Value, exponent: Value) -> VMResult<Value> {
+    match (&base, &exponent) {
+        // Whole powers of an int stay exact; a negative exponent gives a fraction
+        (Value::Int(b), Value::Int(e)) if *e >= 0 => {
+            let result = u32::try_from(*e).ok().and_then(|e| b.checked_pow(e))
+                .ok_or_else(|| VMError::IntegerOverflow { operation: "POW".to_string() })?;
+            Ok(Value::Int(result))
+        }
+        (Value::Int(b), Value::Int(e)) => float_result("POW", (*b as f64).powf(*e as f64)),
+        (Value::Int(b), Value::Float(e)) => float_result("POW", (*b as f64).powf(*e)),
+        (Value::Float(b), Value::Int(e)) => float_result("POW", b.powf(*e as f64)),
+        (Value::Float(b), Value::Float(e)) => float_result("POW", b.powf(*e)),
+        _ => Err(VMError::TypeMismatch {
+            expected: "two numbers (int or float)".to_string(),
+            got: format!("{:?}, {:?}", base, exponent),
+            operation: "POW".to_string()
+        }),
+    }
+}
+
+pub fn sqrt(val: Value) -> VMResult<Value> {
+    match val {
+        // The square root of a negative number is NaN, so an error
+        Value::Int(n) => float_result("SQRT", (n as f64).sqrt()),
+        Value::Float(n) => float_result("SQRT", n.sqrt()),
+        _ => Err(VMError::TypeMismatch {
+            expected: "number (int or float)".to_string(),
+            got: format!("{:?}", val),
+            operation: "SQRT".to_string()
+        }),
+    }
+}
+
+pub fn abs(val: Value) -> VMResult<Value> {
+    match val {
+        Value::Int(n) => {
+            let result = n.checked_abs().ok_or_else(|| VMError::IntegerOverflow { operation: "ABS".to_string() })?;
+            Ok(Value::Int(result))
+        }
+        Value::Float(n) => Ok(Value::Float(n.abs())),
+        _ => Err(VMError::TypeMismatch {
+            expected: "number (int or float)".to_string(),
+            got: format!("{:?}", val),
+            operation: "ABS".to_string()
+        }),
+    }
+}
+
+// FLOOR, CEIL or ROUND. Ints are already whole and pass through unchanged.
+pub fn round(instruction: &OpCode, val: Value) -> VMResult<Value> {
+    match val {
+        Value::Int(n) => Ok(Value::Int(n)),
+        Value::Float(n) => Ok(Value::Float(match instruction {
+            OpCode::Floor => n.floor(),
+            OpCode::Ceil => n.ceil(),
+            _ => n.round(),
+        })),
+        _ => Err(VMError::TypeMismatch {
+            expected: "number (int or float)".to_string(),
+            got: format!("{:?}", val),
+            operation: crate::bytecode::opcode_to_text(instruction)
+        }),
+    }
+}
+
+// REGEX_MATCH or REGEX_FIND of `pattern` in `text`
+pub fn regex_search(cache: &mut RegexCache, instruction: &OpCode, text: Value, pattern: Value) -> VMResult<Value> {
+    match (text, pattern) {
+        (Value::Str(text), Value::Str(pattern)) => {
+            let regex = cached_regex(cache, &pattern)?;
+            Ok(if matches!(instruction, OpCode::RegexMatch) {
+                Value::Bool(regex.is_match(&text))
+            } else if regex.captures_len() == 1 {
+                Value::List(regex.find_iter(&text).map(|m| Value::Str(m.as_str().to_string())).collect())
+            } else {
+                Value::List(regex.captures_iter(&text).map(|captures| {
+                    Value::List(captures.iter().map(|group| match group {
+                        Some(group) => Value::Str(group.as_str().to_string()),
+                        None => Value::Null,
+                    }).collect())
+                }).collect())
+            })
+        }
+        (t, p) => Err(VMError::TypeMismatch {
+            expected: "string (text) and string (pattern)".to_string(),
+            got: format!("{:?}, {:?}", t, p),
+            operation: crate::bytecode::opcode_to_text(instruction),
+        }),
+    }
+}
+
+pub fn regex_replace(cache: &mut RegexCache, text: Value, pattern: Value, replacement: Value) -> VMResult<Value> {
+    match (text, pattern, replacement) {
+        (Value::Str(text), Value::Str(pattern), Value::Str(replacement)) => {
+            let replaced = cached_regex(cache, &pattern)?.replace_all(&text, replacement.as_str()).into_owned();
+            Ok(Value::Str(replaced))
+        }
+        (t, p, r) => Err(VMError::TypeMismatch {
+            expected: "string (text), string (pattern) and string (replacement)".to_string(),
+            got: format!("{:?}, {:?}, {:?}", t, p, r),
+            operation: "REGEX_REPLACE".to_string(),
+        }),
+    }
+}
+
+// Patterns are compiled on first use and kept for the rest of the run
+fn cached_regex<'a>(cache: &'a mut RegexCache, pattern: &str) -> VMResult<&'a regex::Regex> {
+    if !cache.contains_key(pattern) {
+        // Syntax errors come with the pattern redrawn above a caret; the last line says what's wrong
+        let compiled = regex::Regex::new(pattern).map_err(|e| VMError::InvalidPattern {
+            pattern: pattern.to_string(),
+            error: e.to_string().lines().last().unwrap_or_default().trim_start_matches("error: ").to_string(),
+        })?;
+        cache.insert(pattern.to_string(), compiled);
+    }
+    Ok(&cache[pattern])
+}
+
+pub fn random_int(random: &mut Random, min: Value, max: Value) -> VMResult<Value> {
+    use rand::Rng;
+    match (min, max) {
+        (Value::Int(min), Value::Int(max)) if min <= max => Ok(Value::Int(rng(random).gen_range(min..=max))),
+        (min, max) => Err(VMError::TypeMismatch {
+            expected: "int (min) and int (max) with min <= max".to_string(),
+            got: format!("{:?}, {:?}", min, max),
+            operation: "RANDOM_INT".to_string()
+        }),
+    }
+}
+
+pub fn random_float(random: &mut Random) -> Value {
+    use rand::Rng;
+    Value::Float(rng(random).gen())
+}
+
+pub fn random_seed(random: &mut Random, seed: Value) -> VMResult<()> {
+    use rand::SeedableRng;
+    match seed {
+        Value::Int(seed) => *random = Some(rand::rngs::StdRng::seed_from_u64(seed as u64)),
+        _ => return Err(VMError::TypeMismatch {
+            expected: "int (seed)".to_string(),
+            got: format!("{:?}", seed),
+            operation: "RANDOM_SEED".to_string()
+        }),
+    }
+    Ok(())
+}
+
+fn rng(random: &mut Random) -> &mut rand::rngs::StdRng {
+    use rand::SeedableRng;
+    random.get_or_insert_with(rand::rngs::StdRng::from_entropy)
+}
+
+// From the OS generator, not the seedable one, so ids stay unique everywhere
+pub fn uuid() -> Value {
+    Value::Str(uuid::Uuid::new_v4().to_string())
+}
@@ -0,0 +1,72 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+// Where PRINT, PRINT_RAW, DEBUG_PRINT and DUMP_SCOPE write. Defaults to stdout.
+// Clones write to the same place, so a VM can share its sink with a helper VM.
+#[derive(Clone)]
+pub struct OutputSink(Arc<Mutex<Box<dyn Write + Send>>>);
+
+impl OutputSink {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        OutputSink(Arc::new(Mutex::new(Box::new(writer))))
+    }
+
+    pub fn stdout() -> Self {
+        Self::new(std::io::stdout())
+    }
+}
+
+impl Default for OutputSink {
+    fn default() -> Self {
+        Self::stdout()
+    }
+}
+
+impl std::fmt::Debug for OutputSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("OutputSink")
+    }
+}
+
+impl Write for OutputSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().map_err(|_| std::io::Error::other("output sink poisoned"))?.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.lock().map_err(|_| std::io::Error::other("output sink poisoned"))?.flush()
+    }
+}
+
+// Output sink that keeps everything written to it, for asserting on a program's output.
+// Clones share the same buffer, so hand one to the VM and read the other.
+#[allow(dead_code)]
+#[derive(Clone, Default)]
+pub struct OutputBuffer(Arc<Mutex<Vec<u8>>>);
+
+#[allow(dead_code)]
+impl OutputBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.lock().map(|buffer| buffer.clone()).unwrap_or_default()
+    }
+
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.bytes()).into_owned()
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut buffer = self.0.lock().map_err(|_| std::io::Error::other("output buffer poisoned"))?;
+        buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
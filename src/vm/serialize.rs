@@ -0,0 +1,163 @@
+use indexmap::IndexMap;
+use crate::vm::errors::{VMError, VMResult};
+use crate::vm::value::Value;
+
+// Binary encoding for values that leave the VM (messages to other nodes, files, snapshots).
+// Every value is a tag byte followed by its data; integers and lengths are big-endian.
+// Handles into the running VM (functions, closures, connections, streams, futures and
+// exceptions) have no meaning elsewhere and can't be serialized.
+
+const TAG_NULL: u8 = 0;
+const TAG_INT: u8 = 1;
+const TAG_FLOAT: u8 = 2;
+const TAG_BOOL: u8 = 3;
+const TAG_STR: u8 = 4;
+const TAG_LIST: u8 = 5;
+const TAG_OBJECT: u8 = 6;
+const TAG_BYTES: u8 = 7;
+
+// Lists and objects nested deeper than this are refused both ways, so decoding untrusted
+// input can't overflow the stack
+const MAX_DEPTH: usize = 128;
+
+pub fn serialize_value(value: &Value) -> VMResult<Vec<u8>> {
+    let mut out = Vec::new();
+    write_value(&mut out, value, 0)?;
+    Ok(out)
+}
+
+// The inverse of serialize_value; `bytes` must hold exactly one value
+pub fn deserialize_value(bytes: &[u8]) -> VMResult<Value> {
+    let mut input = bytes;
+    let value = read_value(&mut input, 0)?;
+    if !input.is_empty() {
+        return Err(malformed(format!("{} trailing bytes", input.len())));
+    }
+    Ok(value)
+}
+
+// `depth` counts the lists and objects around value
+fn write_value(out: &mut Vec<u8>, value: &Value, depth: usize) -> VMResult<()> {
+    if depth == MAX_DEPTH && matches!(value, Value::List(_) | Value::Object(_)) {
+        return Err(VMError::UnsupportedOperation(format!("cannot serialize values nested more than {} deep", MAX_DEPTH)));
+    }
+    match value {
+        Value::Null => out.push(TAG_NULL),
+        Value::Int(n) => {
+            out.push(TAG_INT);
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+        Value::Float(f) => {
+            out.push(TAG_FLOAT);
+            out.extend_from_slice(&f.to_bits().to_be_bytes());
+        }
+        Value::Bool(b) => out.extend_from_slice(&[TAG_BOOL, *b as u8]),
+        Value::Str(s) => {
+            out.push(TAG_STR);
+            write_bytes(out, s.as_bytes())?;
+        }
+        Value::Bytes(bytes) => {
+            out.push(TAG_BYTES);
+            write_bytes(out, bytes)?;
+        }
+        Value::List(items) => {
+            out.push(TAG_LIST);
+            write_len(out, items.len())?;
+            for item in items {
+                write_value(out, item, depth + 1)?;
+            }
+        }
+        Value::Object(fields) => {
+            out.push(TAG_OBJECT);
+            write_len(out, fields.len())?;
+            for (key, field) in fields {
+                write_bytes(out, key.as_bytes())?;
+                write_value(out, field, depth + 1)?;
+            }
+        }
+        other => {
+            return Err(VMError::UnsupportedOperation(format!("cannot serialize {:?}", other)));
+        }
+    }
+    Ok(())
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) -> VMResult<()> {
+    let len = u32::try_from(len)
+        .map_err(|_| VMError::UnsupportedOperation(format!("cannot serialize {} items in one value", len)))?;
+    out.extend_from_slice(&len.to_be_bytes());
+    Ok(())
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) -> VMResult<()> {
+    write_len(out, bytes.len())?;
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+fn malformed(reason: String) -> VMError {
+    VMError::RuntimeError(format!("Malformed serialized value: {}", reason))
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> VMResult<&'a [u8]> {
+    if input.len() < len {
+        return Err(malformed(format!("needed {} more bytes, found {}", len, input.len())));
+    }
+    let (bytes, rest) = input.split_at(len);
+    *input = rest;
+    Ok(bytes)
+}
+
+fn take_array<const N: usize>(input: &mut &[u8]) -> VMResult<[u8; N]> {
+    take(input, N)?.try_into().map_err(|_| malformed(format!("expected {} bytes", N)))
+}
+
+fn read_len(input: &mut &[u8]) -> VMResult<usize> {
+    Ok(u32::from_be_bytes(take_array(input)?) as usize)
+}
+
+fn read_string(input: &mut &[u8]) -> VMResult<String> {
+    let len = read_len(input)?;
+    String::from_utf8(take(input, len)?.to_vec()).map_err(|e| malformed(format!("invalid string: {}", e)))
+}
+
+fn read_value(input: &mut &[u8], depth: usize) -> VMResult<Value> {
+    let [tag] = take_array(input)?;
+    if depth == MAX_DEPTH && matches!(tag, TAG_LIST | TAG_OBJECT) {
+        return Err(malformed("nesting too deep".to_string()));
+    }
+    Ok(match tag {
+        TAG_NULL => Value::Null,
+        TAG_INT => Value::Int(i64::from_be_bytes(take_array(input)?)),
+        TAG_FLOAT => Value::Float(f64::from_bits(u64::from_be_bytes(take_array(input)?))),
+        TAG_BOOL => match take_array(input)? {
+            [0] => Value::Bool(false),
+            [1] => Value::Bool(true),
+            [other] => return Err(malformed(format!("invalid bool byte {}", other))),
+        },
+        TAG_STR => Value::Str(read_string(input)?),
+        TAG_BYTES => {
+            let len = read_len(input)?;
+            Value::Bytes(take(input, len)?.to_vec())
+        }
+        TAG_LIST => {
+            // Lengths come from the input, so grow as items actually decode
+            let len = read_len(input)?;
+            let mut items = Vec::new();
+            for _ in 0..len {
+                items.push(read_value(input, depth + 1)?);
+            }
+            Value::List(items.into())
+        }
+        TAG_OBJECT => {
+            let len = read_len(input)?;
+            let mut fields = IndexMap::new();
+            for _ in 0..len {
+                let key = read_string(input)?;
+                fields.insert(key, read_value(input, depth + 1)?);
+            }
+            Value::Object(fields.into())
+        }
+        other => return Err(malformed(format!("unknown tag {}", other))),
+    })
+}
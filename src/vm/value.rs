@@ -1,5 +1,9 @@
 use std::collections::HashMap;
+use indexmap::IndexMap;
 use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::vm::errors::{VMError, VMResult};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
@@ -8,22 +12,344 @@ pub enum Value {
     Str(String),
     Bool(bool),
     Null,
-    List(Vec<Value>),
-    Object(HashMap<String, Value>),
+    List(Heap<Vec<Value>>),
+    Object(Heap<IndexMap<String, Value>>), // fields in insertion order
     Bytes(Vec<u8>),
     Connection(String), // Network connection handle (simplified as string ID)
     Stream(String),     // Data stream handle (simplified as string ID)
     Future(String),     // Async operation handle (simplified as string ID)
-    Function { addr: usize, params: Vec<String> },
-    Closure { addr: usize, params: Vec<String>, captured: HashMap<String, Value> },
+    Function { addr: usize, params: Vec<String>, returns: Option<String> }, // optional result type
+    Closure { addr: usize, params: Vec<String>, captured: Heap<HashMap<String, Value>> },
     Exception { message: String, stack_trace: Vec<String> },
+    WeakRef(usize),     // GC object id; doesn't keep its target alive
 }
 
-impl fmt::Display for Value {
+// The contents of a list, object or closure, tagged with the allocation they were made by.
+// Values are copied rather than shared, so the tag is what tells a copy of a value (which
+// keeps the tag) from an equal value built separately (which gets its own). Equality and
+// {:?} look only at the contents.
+#[derive(Clone)]
+pub struct Heap<T> {
+    contents: T,
+    allocation: usize,
+}
+
+static NEXT_ALLOCATION: AtomicUsize = AtomicUsize::new(0);
+
+impl<T> Heap<T> {
+    pub fn new(contents: T) -> Self {
+        Heap { contents, allocation: NEXT_ALLOCATION.fetch_add(1, Ordering::Relaxed) }
+    }
+
+    pub fn allocation(&self) -> usize {
+        self.allocation
+    }
+
+    pub fn into_inner(self) -> T {
+        self.contents
+    }
+}
+
+impl<T> From<T> for Heap<T> {
+    fn from(contents: T) -> Self {
+        Heap::new(contents)
+    }
+}
+
+impl<T: Default> Default for Heap<T> {
+    fn default() -> Self {
+        Heap::new(T::default())
+    }
+}
+
+impl<T> Deref for Heap<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.contents
+    }
+}
+
+impl<T> DerefMut for Heap<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.contents
+    }
+}
+
+impl<T: PartialEq> PartialEq for Heap<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.contents == other.contents
+    }
+}
+
+impl<T: PartialEq> PartialEq<T> for Heap<T> {
+    fn eq(&self, other: &T) -> bool {
+        self.contents == *other
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Heap<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.contents.fmt(f)
+    }
+}
+
+impl<A, T: FromIterator<A>> FromIterator<A> for Heap<T> {
+    fn from_iter<I: IntoIterator<Item = A>>(iter: I) -> Self {
+        Heap::new(iter.into_iter().collect())
+    }
+}
+
+impl<T: IntoIterator> IntoIterator for Heap<T> {
+    type Item = T::Item;
+    type IntoIter = T::IntoIter;
+    fn into_iter(self) -> T::IntoIter {
+        self.contents.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Heap<T> where &'a T: IntoIterator {
+    type Item = <&'a T as IntoIterator>::Item;
+    type IntoIter = <&'a T as IntoIterator>::IntoIter;
+    fn into_iter(self) -> Self::IntoIter {
+        (&self.contents).into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut Heap<T> where &'a mut T: IntoIterator {
+    type Item = <&'a mut T as IntoIterator>::Item;
+    type IntoIter = <&'a mut T as IntoIterator>::IntoIter;
+    fn into_iter(self) -> Self::IntoIter {
+        (&mut self.contents).into_iter()
+    }
+}
+
+// Float arithmetic follows IEEE 754 (overflow gives inf, comparisons with NaN are false) except
+// that it never yields NaN: an operation whose result would be NaN is an error instead.
+pub fn float_result(operation: &str, n: f64) -> VMResult<Value> {
+    if n.is_nan() {
+        return Err(VMError::NotANumber { operation: operation.to_string() });
+    }
+    Ok(Value::Float(n))
+}
+
+// An argument bound to an annotated parameter must have the parameter's type
+pub fn check_argument_type(param: &str, type_name: Option<&str>, value: &Value, operation: &str) -> VMResult<()> {
+    match type_name {
+        Some(type_name) if !value.has_type(type_name) => Err(VMError::TypeMismatch {
+            expected: format!("{} for parameter {}", type_name, param),
+            got: format!("{:?}", value),
+            operation: operation.to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+// Floats always show a decimal point (1.0, not 1). Magnitudes from 1e-5 up to 1e16 are written
+// out in full; anything outside that range uses an exponent (1.0e21, 2.5e-7). A precision fixes
+// the digits after the decimal point instead of using the shortest exact form.
+pub fn format_float(n: f64, precision: Option<usize>) -> String {
+    if !n.is_finite() {
+        return n.to_string();
+    }
+    let magnitude = n.abs();
+    if n == 0.0 || (1e-5..1e16).contains(&magnitude) {
+        if let Some(precision) = precision {
+            return format!("{:.*}", precision, n);
+        }
+        let text = n.to_string();
+        if text.contains('.') { text } else { text + ".0" }
+    } else {
+        if let Some(precision) = precision {
+            return format!("{:.*e}", precision, n);
+        }
+        let text = format!("{:e}", n);
+        match text.split_once('e') {
+            Some((mantissa, exponent)) if !mantissa.contains('.') => format!("{}.0e{}", mantissa, exponent),
+            _ => text,
+        }
+    }
+}
+
+// The type names parameter and return annotations can use
+pub const TYPE_NAMES: &[&str] = &["int", "float", "number", "string", "bool", "null", "list", "object", "bytes", "function"];
+
+impl Value {
+    // The values JZ jumps on and ASSERT rejects
+    pub fn is_falsy(&self) -> bool {
+        matches!(self, Value::Int(0) | Value::Bool(false) | Value::Null)
+    }
+
+    // Whether the value satisfies a type annotation: `number` takes ints and floats, and
+    // `function` takes closures as well as plain functions
+    pub fn has_type(&self, type_name: &str) -> bool {
+        matches!(
+            (self, type_name),
+            (Value::Int(_), "int" | "number")
+                | (Value::Float(_), "float" | "number")
+                | (Value::Str(_), "string")
+                | (Value::Bool(_), "bool")
+                | (Value::Null, "null")
+                | (Value::List(_), "list")
+                | (Value::Object(_), "object")
+                | (Value::Bytes(_), "bytes")
+                | (Value::Function { .. } | Value::Closure { .. }, "function")
+        )
+    }
+
+    // The allocation a list, object or closure was made by
+    pub fn allocation(&self) -> Option<usize> {
+        match self {
+            Value::List(items) => Some(items.allocation()),
+            Value::Object(fields) => Some(fields.allocation()),
+            Value::Closure { captured, .. } => Some(captured.allocation()),
+            _ => None,
+        }
+    }
+
+    // Approximate heap footprint as (lists, objects and closures, bytes). Bytes count the
+    // element slots of containers plus the text of strings, keys and parameter names.
+    pub fn heap_usage(&self) -> (usize, usize) {
+        let slot = std::mem::size_of::<Value>();
+        let nested = |values: &mut dyn Iterator<Item = &Value>| values
+            .map(Value::heap_usage)
+            .fold((0, 0), |(objects, bytes), (o, b)| (objects + o, bytes + b));
+        match self {
+            Value::Str(s) => (0, s.len()),
+            Value::Bytes(bytes) => (0, bytes.len()),
+            Value::List(items) => {
+                let (objects, bytes) = nested(&mut items.iter());
+                (objects + 1, bytes + items.len() * slot)
+            }
+            Value::Object(map) => {
+                let (objects, bytes) = nested(&mut map.values());
+                let keys: usize = map.keys().map(|key| key.len() + slot).sum();
+                (objects + 1, bytes + keys)
+            }
+            Value::Closure { params, captured, .. } => {
+                let (objects, bytes) = nested(&mut captured.values());
+                let names: usize = params.iter().chain(captured.keys()).map(String::len).sum();
+                (objects + 1, bytes + names + captured.len() * slot)
+            }
+            _ => (0, 0),
+        }
+    }
+}
+
+// A scope's variables sorted by name, one per line in the DEBUG_PRINT layout
+pub fn format_scope<'a>(variables: impl IntoIterator<Item = (&'a String, &'a Value)>) -> String {
+    let mut variables: Vec<_> = variables.into_iter().collect();
+    if variables.is_empty() {
+        return "Current scope: (empty)".to_string();
+    }
+    variables.sort_by(|a, b| a.0.cmp(b.0));
+    let mut out = "Current scope:".to_string();
+    for (name, value) in variables {
+        out.push_str(&format!("\n  {}: ", name));
+        value.write_pretty(&mut out, 1);
+    }
+    out
+}
+
+// Stack contents bottom first, one value per line in the DEBUG_PRINT layout
+pub fn format_stack(stack: &[Value]) -> String {
+    if stack.is_empty() {
+        return "stack: (empty)".to_string();
+    }
+    let mut out = format!("stack ({}, top last):", plural(stack.len(), "value"));
+    for (i, value) in stack.iter().enumerate() {
+        out.push_str(&format!("\n  [{}]: ", i));
+        value.write_pretty(&mut out, 1);
+    }
+    out
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+}
+
+impl Value {
+    // Multi-line dump for DEBUG_PRINT: every value is tagged with its type, list items with
+    // their index and object fields with their key, nested values indented two spaces a level
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        out
+    }
+
+    fn write_pretty(&self, out: &mut String, depth: usize) {
+        let indent = "  ".repeat(depth + 1);
         match self {
+            Value::Int(n) => out.push_str(&format!("int {}", n)),
+            Value::Float(n) => out.push_str(&format!("float {}", format_float(*n, None))),
+            Value::Str(s) => out.push_str(&format!("str {:?}", s)),
+            Value::Bool(b) => out.push_str(&format!("bool {}", b)),
+            Value::Null => out.push_str("null"),
+            Value::List(items) if items.is_empty() => out.push_str("list []"),
+            Value::List(items) => {
+                out.push_str(&format!("list ({}) [", plural(items.len(), "item")));
+                for (i, item) in items.iter().enumerate() {
+                    out.push_str(&format!("\n{}[{}]: ", indent, i));
+                    item.write_pretty(out, depth + 1);
+                }
+                out.push_str(&format!("\n{}]", &indent[2..]));
+            }
+            Value::Object(map) if map.is_empty() => out.push_str("object {}"),
+            Value::Object(map) => {
+                out.push_str(&format!("object ({}) {{", plural(map.len(), "field")));
+                for (key, value) in map {
+                    out.push_str(&format!("\n{}{}: ", indent, key));
+                    value.write_pretty(out, depth + 1);
+                }
+                out.push_str(&format!("\n{}}}", &indent[2..]));
+            }
+            Value::Bytes(bytes) => out.push_str(&format!("bytes ({})", plural(bytes.len(), "byte"))),
+            Value::Connection(id) => out.push_str(&format!("connection {}", id)),
+            Value::Stream(id) => out.push_str(&format!("stream {}", id)),
+            Value::Future(id) => out.push_str(&format!("future {}", id)),
+            Value::Function { addr, params, .. } => out.push_str(&format!("function@{} ({})", addr, params.join(", "))),
+            Value::Closure { addr, params, captured } => {
+                out.push_str(&format!("closure@{} ({})", addr, params.join(", ")));
+                let mut names: Vec<&String> = captured.keys().collect();
+                names.sort();
+                for name in names {
+                    out.push_str(&format!("\n{}captured {}: ", indent, name));
+                    captured[name].write_pretty(out, depth + 1);
+                }
+            }
+            Value::Exception { message, stack_trace } => {
+                out.push_str(&format!("exception {:?}", message));
+                for entry in stack_trace {
+                    out.push_str(&format!("\n{}{}", indent, entry));
+                }
+            }
+            Value::WeakRef(id) => out.push_str(&format!("weakref {}", id)),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.display(None).fmt(f)
+    }
+}
+
+// A value shown as PRINT shows it, with floats formatted at `precision` (see format_float)
+pub struct Displayed<'a> {
+    value: &'a Value,
+    precision: Option<usize>,
+}
+
+impl Value {
+    pub fn display(&self, precision: Option<usize>) -> Displayed<'_> {
+        Displayed { value: self, precision }
+    }
+}
+
+impl fmt::Display for Displayed<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.value {
             Value::Int(n) => write!(f, "{}", n),
-            Value::Float(n) => write!(f, "{}", n),
+            Value::Float(n) => write!(f, "{}", format_float(*n, self.precision)),
             Value::Str(s) => write!(f, "{}", s),
             Value::Bool(b) => write!(f, "{}", b),
             Value::Null => write!(f, "null"),
@@ -31,7 +357,7 @@ impl fmt::Display for Value {
                 write!(f, "[")?;
                 for (i, item) in items.iter().enumerate() {
                     if i > 0 { write!(f, ", ")?; }
-                    write!(f, "{}", item)?;
+                    write!(f, "{}", item.display(self.precision))?;
                 }
                 write!(f, "]")
             },
@@ -40,7 +366,7 @@ impl fmt::Display for Value {
                 let mut first = true;
                 for (key, value) in map {
                     if !first { write!(f, ", ")?; }
-                    write!(f, "{}: {}", key, value)?;
+                    write!(f, "{}: {}", key, value.display(self.precision))?;
                     first = false;
                 }
                 write!(f, "}}")
@@ -57,8 +383,12 @@ impl fmt::Display for Value {
             Value::Future(id) => {
                 write!(f, "Future({})", id)
             },
-            Value::Function { addr, params } => {
-                write!(f, "function@{} ({})", addr, params.join(", "))
+            Value::Function { addr, params, returns } => {
+                write!(f, "function@{} ({})", addr, params.join(", "))?;
+                match returns {
+                    Some(type_name) => write!(f, " -> {}", type_name),
+                    None => Ok(()),
+                }
             },
             Value::Closure { addr, params, captured } => {
                 write!(f, "closure@{} ({}) [captured: {}]", 
@@ -77,6 +407,7 @@ impl fmt::Display for Value {
                 }
                 Ok(())
             }
+            Value::WeakRef(id) => write!(f, "WeakRef({})", id),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,50 @@
+use std::time::Duration;
+use crossbeam::channel::unbounded;
+use tiny_tot_vm::concurrency::{Message, SchedulerPool};
+use tiny_tot_vm::{OpCode, Value};
+
+#[test]
+fn test_broadcast_reaches_every_listed_pid() {
+    let mut pool = SchedulerPool::new_with_threads(1);
+    let mut receivers = Vec::new();
+    {
+        let mut registry = pool.process_registry.lock().unwrap();
+        for pid in 9001..=9003 {
+            let (sender, receiver) = unbounded();
+            registry.register_process(pid, sender).unwrap();
+            receivers.push(receiver);
+        }
+        let (sender, results) = unbounded();
+        registry.register_process(9000, sender).unwrap();
+        registry.register_name("results".to_string(), 9000).unwrap();
+        receivers.push(results);
+    }
+    let results = receivers.pop().unwrap();
+
+    // 9004 was never registered, so it is skipped
+    pool.spawn_process(vec![
+        OpCode::PushInt(9001),
+        OpCode::PushInt(9002),
+        OpCode::PushInt(9004),
+        OpCode::PushInt(9003),
+        OpCode::MakeList(4),
+        OpCode::PushStr("news".to_string()),
+        OpCode::Broadcast,
+        OpCode::SendNamed("results".to_string()),
+        OpCode::Halt,
+    ]);
+
+    for receiver in &receivers {
+        match receiver.recv_timeout(Duration::from_secs(5)).expect("Broadcast didn't arrive") {
+            Message::Value(value) => assert_eq!(value, Value::Str("news".to_string())),
+            other => panic!("Expected a value message, got {:?}", other),
+        }
+    }
+    match results.recv_timeout(Duration::from_secs(5)).expect("No count arrived") {
+        Message::Value(count) => assert_eq!(count, Value::Int(3)),
+        other => panic!("Expected a value message, got {:?}", other),
+    }
+
+    pool.run().unwrap();
+    pool.wait_for_completion();
+}
@@ -0,0 +1,214 @@
+use std::fs;
+use std::process::Command;
+use tiny_tot_vm::bytecode::{load_bytecode, load_bytecode_with_lines, BYTECODE_MAGIC, BYTECODE_VERSION};
+use tiny_tot_vm::{OpCode, VMError};
+
+// Compile a small program with the CLI and return the raw .ttb bytes
+fn compile_program(name: &str, source: &str) -> Vec<u8> {
+    let dir = std::env::temp_dir();
+    let input = dir.join(format!("{}.ttvm", name));
+    let output = dir.join(format!("{}.ttb", name));
+    fs::write(&input, source).expect("Failed to write source");
+
+    let status = Command::new("cargo")
+        .args(["run", "--quiet", "--", "compile"])
+        .arg(&input)
+        .arg(&output)
+        .status()
+        .expect("Failed to run compiler");
+    assert!(status.success(), "Compilation of {} failed", name);
+
+    fs::read(&output).expect("Failed to read bytecode")
+}
+
+fn load_bytes(name: &str, bytes: &[u8]) -> Result<Vec<OpCode>, VMError> {
+    let path = std::env::temp_dir().join(format!("{}.ttb", name));
+    fs::write(&path, bytes).expect("Failed to write bytecode");
+    load_bytecode(path.to_str().unwrap())
+}
+
+const PROGRAM: &str = "PUSH_INT 40\nPUSH_INT 2\nADD\nPRINT\nHALT\n";
+
+#[test]
+fn test_compiled_bytecode_has_header_and_round_trips() {
+    let bytes = compile_program("ttb_round_trip", PROGRAM);
+    assert_eq!(&bytes[..4], BYTECODE_MAGIC);
+    assert_eq!(u16::from_le_bytes([bytes[4], bytes[5]]), BYTECODE_VERSION);
+
+    let program = load_bytes("ttb_round_trip_load", &bytes).expect("Failed to load bytecode");
+    assert_eq!(program.len(), 5);
+    assert!(matches!(program[0], OpCode::PushInt(40)));
+    assert!(matches!(program[4], OpCode::Halt));
+}
+
+#[test]
+fn test_print_variants_round_trip() {
+    let bytes = compile_program("ttb_print_variants", "PUSH_STR \"a\"\nPRINT_RAW\nPUSH_STR \"b\"\nPRINT_ERR\nHALT\n");
+    let program = load_bytes("ttb_print_variants_load", &bytes).expect("Failed to load bytecode");
+    assert!(matches!(program[1], OpCode::PrintRaw));
+    assert!(matches!(program[3], OpCode::PrintErr));
+}
+
+#[test]
+fn test_random_ops_round_trip() {
+    let bytes = compile_program("ttb_random_ops", "PUSH_INT 7\nRANDOM_SEED\nPUSH_INT 1\nPUSH_INT 6\nRANDOM_INT\nRANDOM_FLOAT\nUUID\nHALT\n");
+    let program = load_bytes("ttb_random_ops_load", &bytes).expect("Failed to load bytecode");
+    assert!(matches!(program[1], OpCode::RandomSeed));
+    assert!(matches!(program[4], OpCode::RandomInt));
+    assert!(matches!(program[5], OpCode::RandomFloat));
+    assert!(matches!(program[6], OpCode::Uuid));
+}
+
+#[test]
+fn test_math_ops_round_trip() {
+    let bytes = compile_program("ttb_math_ops", "PUSH_INT 2\nPUSH_INT 8\nPOW\nSQRT\nABS\nFLOOR\nCEIL\nROUND\nNEG\nHALT\n");
+    let program = load_bytes("ttb_math_ops_load", &bytes).expect("Failed to load bytecode");
+    assert_eq!(program[2..9], [OpCode::Pow, OpCode::Sqrt, OpCode::Abs, OpCode::Floor, OpCode::Ceil, OpCode::Round, OpCode::Neg]);
+}
+
+#[test]
+fn test_repeated_literals_are_pooled_once() {
+    let source = "PUSH_STR \"greeting\"\nPRINT\nPUSH_INT 1234567\nPUSH_STR \"greeting\"\nPRINT\nPUSH_INT 1234567\nADD\nPUSH_STR \"greeting\"\nPRINT\nHALT\n";
+    let bytes = compile_program("ttb_constant_pool", source);
+
+    let occurrences = |needle: &[u8]| bytes.windows(needle.len()).filter(|window| *window == needle).count();
+    assert_eq!(occurrences(b"greeting"), 1);
+    assert_eq!(occurrences(&1234567i64.to_le_bytes()), 1);
+
+    let program = load_bytes("ttb_constant_pool_load", &bytes).expect("Failed to load bytecode");
+    let greeting = OpCode::PushStr("greeting".to_string());
+    assert_eq!(program[0], greeting);
+    assert_eq!(program[2], OpCode::PushInt(1234567));
+    assert_eq!(program[3], greeting);
+    assert_eq!(program[5], OpCode::PushInt(1234567));
+    assert_eq!(program[7], greeting);
+}
+
+#[test]
+fn test_compiler_strips_comments_outside_strings() {
+    let source = "/* greeting\n   program */\nPUSH_STR \"a;b\" ; keep the semicolon\nPRINT /* inline */\nHALT\n";
+    let bytes = compile_program("ttb_comments", source);
+    let program = load_bytes("ttb_comments_load", &bytes).expect("Failed to load bytecode");
+    assert_eq!(program, vec![OpCode::PushStr("a;b".to_string()), OpCode::Print, OpCode::Halt]);
+}
+
+#[test]
+fn test_rejects_constant_index_outside_the_pool() {
+    let mut bytes = compile_program("ttb_bad_constant", PROGRAM);
+    // Header, pool count, two int entries, then the first instruction's index
+    let index_at = 6 + 4 + 2 * 9 + 2;
+    bytes[index_at..index_at + 4].copy_from_slice(&7u32.to_le_bytes());
+
+    match load_bytes("ttb_bad_constant_load", &bytes) {
+        Err(VMError::InvalidBytecode { reason, .. }) => assert!(reason.contains("constant index 7"), "{}", reason),
+        other => panic!("Expected InvalidBytecode, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_rejects_wrong_magic() {
+    let mut bytes = compile_program("ttb_wrong_magic", PROGRAM);
+    bytes[..4].copy_from_slice(b"XXXX");
+
+    match load_bytes("ttb_wrong_magic_load", &bytes) {
+        Err(VMError::InvalidBytecode { reason, .. }) => assert!(reason.contains("magic"), "{}", reason),
+        other => panic!("Expected InvalidBytecode, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_rejects_unsupported_version() {
+    let mut bytes = compile_program("ttb_wrong_version", PROGRAM);
+    bytes[4..6].copy_from_slice(&(BYTECODE_VERSION + 1).to_le_bytes());
+
+    match load_bytes("ttb_wrong_version_load", &bytes) {
+        Err(VMError::InvalidBytecode { reason, .. }) => assert!(reason.contains("version"), "{}", reason),
+        other => panic!("Expected InvalidBytecode, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_rejects_truncated_file() {
+    let bytes = compile_program("ttb_truncated", PROGRAM);
+
+    // Cut in the middle of the first constant pool entry
+    match load_bytes("ttb_truncated_body", &bytes[..14]) {
+        Err(VMError::InvalidBytecode { reason, .. }) => assert!(reason.contains("unexpected end"), "{}", reason),
+        other => panic!("Expected InvalidBytecode, got {:?}", other),
+    }
+
+    // Cut inside the header
+    match load_bytes("ttb_truncated_header", &bytes[..5]) {
+        Err(VMError::InvalidBytecode { reason, .. }) => assert!(reason.contains("header"), "{}", reason),
+        other => panic!("Expected InvalidBytecode, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_disassemble_lists_mnemonics_and_jump_targets() {
+    let source = "PUSH_INT 3\nLABEL loop\nDUP\nJZ done\nPUSH_INT 1\nSUB\nJMP loop\nLABEL done\nPUSH_STR \"bye\"\nPRINT\nHALT\n";
+    compile_program("ttb_disassemble", source);
+    let path = std::env::temp_dir().join("ttb_disassemble.ttb");
+
+    let output = Command::new("cargo")
+        .args(["run", "--quiet", "--", "disassemble"])
+        .arg(&path)
+        .output()
+        .expect("Failed to run disassembler");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "Disassembly failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("0000  PUSH_INT 3"), "stdout:\n{}", stdout);
+    assert!(stdout.contains("0001  DUP"), "stdout:\n{}", stdout);
+    assert!(stdout.contains("0002  JZ 6"), "stdout:\n{}", stdout);
+    assert!(stdout.contains("; -> 0006: PUSH_STR \"bye\""), "stdout:\n{}", stdout);
+    assert!(stdout.contains("0005  JMP 1"), "stdout:\n{}", stdout);
+    assert!(stdout.contains("; -> 0001: DUP"), "stdout:\n{}", stdout);
+    assert!(stdout.contains("0008  HALT"), "stdout:\n{}", stdout);
+}
+
+const FAILING_PROGRAM: &str = "; adds a string to an int\nPUSH_INT 1\n\nPUSH_STR \"a\"\nLABEL add\nADD\nHALT\n";
+
+#[test]
+fn test_compiled_program_records_source_lines() {
+    let bytes = compile_program("ttb_debug_info", FAILING_PROGRAM);
+    let path = std::env::temp_dir().join("ttb_debug_info_load.ttb");
+    fs::write(&path, &bytes).unwrap();
+
+    let (program, lines) = load_bytecode_with_lines(path.to_str().unwrap()).expect("Failed to load bytecode");
+    assert_eq!(program.len(), 4);
+    assert_eq!(lines, Some(vec![2, 4, 6, 7]));
+
+    // Files written before debug info existed still load, just without lines
+    let (program, lines) = load_bytecode_with_lines("examples/showcase.ttb").expect("Failed to load old bytecode");
+    assert!(!program.is_empty());
+    assert_eq!(lines, None);
+}
+
+#[test]
+fn test_errors_in_compiled_programs_report_source_line() {
+    compile_program("ttb_debug_error", FAILING_PROGRAM);
+    let path = std::env::temp_dir().join("ttb_debug_error.ttb");
+
+    let output = Command::new("cargo")
+        .args(["run", "--quiet", "--", "--no-smp"])
+        .arg(&path)
+        .output()
+        .expect("Failed to run program");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("(line 6, instruction 2)"), "stderr:\n{}", stderr);
+}
+
+#[test]
+fn test_rejects_debug_info_for_the_wrong_number_of_instructions() {
+    let mut bytes = compile_program("ttb_debug_mismatch", PROGRAM);
+    // The count follows the 2-byte tag, 4 + 4 bytes per instruction before the end
+    let count_at = bytes.len() - 4 * 5 - 4;
+    bytes[count_at..count_at + 4].copy_from_slice(&3u32.to_le_bytes());
+
+    match load_bytes("ttb_debug_mismatch_load", &bytes) {
+        Err(VMError::InvalidBytecode { reason, .. }) => assert!(reason.contains("debug info"), "{}", reason),
+        other => panic!("Expected InvalidBytecode, got {:?}", other),
+    }
+}
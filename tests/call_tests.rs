@@ -0,0 +1,83 @@
+use std::time::Duration;
+use crossbeam::channel::{unbounded, Receiver};
+use tiny_tot_vm::concurrency::{Message, SchedulerPool};
+use tiny_tot_vm::{OpCode, Value};
+
+// Register a channel as the process named `results` so tests can see what processes report
+fn results_inbox(pool: &SchedulerPool) -> Receiver<Message> {
+    let (sender, receiver) = unbounded();
+    let mut registry = pool.process_registry.lock().unwrap();
+    registry.register_process(9000, sender).unwrap();
+    registry.register_name("results".to_string(), 9000).unwrap();
+    receiver
+}
+
+fn next_result(results: &Receiver<Message>) -> Value {
+    match results.recv_timeout(Duration::from_secs(5)).expect("No result arrived") {
+        Message::Value(value) => value,
+        other => panic!("Expected a value message, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_proc_call_gets_reply_from_echo_server() {
+    let mut pool = SchedulerPool::new_with_threads(2);
+    let results = results_inbox(&pool);
+
+    // Answers two calls with the payload it was sent
+    let mut server = vec![OpCode::Register("echo".to_string())];
+    for _ in 0..2 {
+        server.extend([
+            OpCode::Receive,
+            OpCode::Dup,
+            OpCode::GetField("payload".to_string()),
+            OpCode::ProcReply,
+        ]);
+    }
+    server.push(OpCode::Halt);
+    let (server_pid, _) = pool.spawn_process(server);
+
+    // Calls by pid, then by name, then reports a message that arrived while it was waiting
+    let (_, caller_mailbox) = pool.spawn_process(vec![
+        OpCode::PushInt(server_pid as i64),
+        OpCode::PushStr("hello".to_string()),
+        OpCode::ProcCall,
+        OpCode::SendNamed("results".to_string()),
+        OpCode::PushStr("echo".to_string()),
+        OpCode::PushInt(42),
+        OpCode::ProcCall,
+        OpCode::SendNamed("results".to_string()),
+        OpCode::Receive,
+        OpCode::SendNamed("results".to_string()),
+        OpCode::Halt,
+    ]);
+    caller_mailbox.send(Message::Value(Value::Str("unrelated".to_string()))).unwrap();
+
+    pool.run().unwrap();
+    pool.wait_for_completion();
+
+    assert_eq!(next_result(&results), Value::Str("hello".to_string()));
+    assert_eq!(next_result(&results), Value::Int(42));
+    assert_eq!(next_result(&results), Value::Str("unrelated".to_string()));
+}
+
+#[test]
+fn test_proc_call_to_unknown_server_fails() {
+    let mut pool = SchedulerPool::new_with_threads(1);
+    let results = results_inbox(&pool);
+
+    pool.spawn_process(vec![
+        OpCode::PushStr("nobody".to_string()),
+        OpCode::PushStr("hello".to_string()),
+        OpCode::ProcCall,
+        OpCode::SendNamed("results".to_string()),
+        OpCode::Halt,
+    ]);
+    pool.run().unwrap();
+    pool.wait_for_completion();
+
+    match next_result(&results) {
+        Value::Str(s) => assert!(s.starts_with("call_failed_"), "Unexpected result {}", s),
+        other => panic!("Expected a failure string, got {:?}", other),
+    }
+}
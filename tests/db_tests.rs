@@ -0,0 +1,108 @@
+use tiny_tot_vm::vm::VM;
+use tiny_tot_vm::{OpCode, Value, VMError};
+
+fn run(program: Vec<OpCode>) -> Result<VM, VMError> {
+    let mut vm = VM::new(program);
+    vm.run()?;
+    Ok(vm)
+}
+
+fn exec(sql: &str) -> Vec<OpCode> {
+    vec![OpCode::Load("db".to_string()), OpCode::PushStr(sql.to_string()), OpCode::DbExec]
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> &'a Value {
+    match value {
+        Value::Object(fields) => fields.get(name).unwrap_or_else(|| panic!("Query result has no {} field", name)),
+        other => panic!("Expected a query result object, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_db_create_insert_and_query() {
+    let path = std::env::temp_dir().join(format!("ttvm_db_test_{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let mut program = vec![
+        OpCode::PushStr(format!("sqlite://{}", path.display())),
+        OpCode::DbConnect,
+        OpCode::Store("db".to_string()),
+    ];
+    program.extend(exec("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, score REAL, note TEXT)"));
+    program.extend(exec("INSERT INTO users (name, score, note) VALUES ('Ada', 9.5, NULL), ('Grace', 7.25, 'admiral')"));
+    program.extend(exec("UPDATE users SET score = score + 1"));
+    program.extend([
+        OpCode::Load("db".to_string()),
+        OpCode::PushStr("SELECT id, name, score, note FROM users ORDER BY id".to_string()),
+        OpCode::DbQuery,
+        OpCode::Halt,
+    ]);
+
+    let vm = run(program).expect("Database program failed");
+    let result = &vm.stack[3];
+    assert_eq!(&vm.stack[..3], &[Value::Int(0), Value::Int(2), Value::Int(2)]);
+    assert_eq!(field(result, "rows"), &Value::Int(2));
+    assert_eq!(field(result, "columns"), &Value::List(
+        ["id", "name", "score", "note"].iter().map(|c| Value::Str(c.to_string())).collect()
+    ));
+    assert_eq!(field(result, "data"), &Value::List(vec![
+        Value::List(vec![Value::Int(1), Value::Str("Ada".to_string()), Value::Float(10.5), Value::Null].into()),
+        Value::List(vec![Value::Int(2), Value::Str("Grace".to_string()), Value::Float(8.25), Value::Str("admiral".to_string())].into()),
+    ].into()));
+
+    // The rows were written to the file, so a second connection sees them
+    let vm = run(vec![
+        OpCode::PushStr(path.display().to_string()),
+        OpCode::DbConnect,
+        OpCode::PushStr("SELECT COUNT(*) AS n FROM users".to_string()),
+        OpCode::DbQuery,
+        OpCode::GetField("data".to_string()),
+        OpCode::Halt,
+    ]).expect("Reopening the database failed");
+    assert_eq!(vm.stack.last(), Some(&Value::List(vec![Value::List(vec![Value::Int(2)].into())].into())));
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_db_sql_errors_are_vm_errors() {
+    let mut program = vec![
+        OpCode::PushStr("sqlite::memory:".to_string()),
+        OpCode::DbConnect,
+        OpCode::Store("db".to_string()),
+    ];
+    program.extend(exec("INSERT INTO missing VALUES (1)"));
+    program.push(OpCode::Halt);
+
+    match run(program) {
+        Err(VMError::DatabaseError { database, error }) => {
+            assert!(database.contains("sqlite::memory:"), "Unexpected database {}", database);
+            assert!(error.contains("no such table"), "Unexpected error {}", error);
+        }
+        other => panic!("Expected a database error, got {:?}", other.map(|vm| vm.stack)),
+    }
+
+    // A connection value that DB_CONNECT never opened
+    let mut vm = VM::new(vec![OpCode::PushStr("SELECT 1".to_string()), OpCode::DbQuery, OpCode::Halt]);
+    vm.stack.push(Value::Connection("db:elsewhere#9".to_string()));
+    let result = vm.run();
+    assert!(matches!(result, Err(VMError::DatabaseError { .. })), "Got {:?}", result);
+}
+
+#[test]
+fn test_db_memory_urls_open_private_in_memory_databases() {
+    for url in ["sqlite::memory:", "sqlite://memory"] {
+        // Each connection gets its own empty database, so the table is new every time
+        for _ in 0..2 {
+            let mut program = vec![
+                OpCode::PushStr(url.to_string()),
+                OpCode::DbConnect,
+                OpCode::Store("db".to_string()),
+            ];
+            program.extend(exec("CREATE TABLE cache (id INTEGER)"));
+            program.push(OpCode::Halt);
+            run(program).unwrap_or_else(|e| panic!("{} was not a fresh database: {}", url, e));
+        }
+    }
+    assert!(!std::path::Path::new("memory").exists(), "sqlite://memory created a file");
+}
@@ -0,0 +1,43 @@
+use std::time::{Duration, Instant};
+use tiny_tot_vm::concurrency::SchedulerPool;
+use tiny_tot_vm::{OpCode, VMError};
+
+#[test]
+fn test_mutually_waiting_processes_are_reported_as_deadlocked() {
+    let mut pool = SchedulerPool::new_with_threads(1);
+
+    // Each one only answers once it has heard from the other
+    let (first, _) = pool.spawn_process(vec![OpCode::Receive, OpCode::PushStr("ping".to_string()), OpCode::Send(2), OpCode::Halt]);
+    let (second, _) = pool.spawn_process(vec![OpCode::Receive, OpCode::PushStr("pong".to_string()), OpCode::Send(1), OpCode::Halt]);
+    assert_eq!((first, second), (1, 2));
+
+    let started = Instant::now();
+    let error = pool.run().expect_err("A deadlock wasn't reported");
+    assert!(started.elapsed() < Duration::from_secs(2), "Took {:?} to notice", started.elapsed());
+
+    match error.downcast_ref::<VMError>() {
+        Some(VMError::Deadlock { waiting }) => assert_eq!(waiting, &vec![first, second]),
+        other => panic!("Expected a deadlock error, got {:?}", other),
+    }
+    assert!(error.to_string().contains("pids 1, 2"), "Unclear message: {}", error);
+}
+
+#[test]
+fn test_pending_timer_is_not_a_deadlock() {
+    let mut pool = SchedulerPool::new_with_threads(1);
+
+    // Blocked in RECEIVE, but the timer it set will wake it
+    pool.spawn_process(vec![
+        OpCode::PushInt(1),
+        OpCode::PushInt(300),
+        OpCode::PushStr("wake up".to_string()),
+        OpCode::SendAfter,
+        OpCode::Receive,
+        OpCode::Halt,
+    ]);
+
+    let started = Instant::now();
+    pool.run().unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(300), "Gave up after {:?}", started.elapsed());
+    pool.wait_for_completion();
+}
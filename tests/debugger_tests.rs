@@ -0,0 +1,156 @@
+use tiny_tot_vm::vm::VM;
+use tiny_tot_vm::{OpCode, Value};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Run a program under the interactive debugger, feeding debugger commands on stdin
+fn run_with_debugger(args: &[&str], commands: &str) -> (bool, String) {
+    let mut child = Command::new("cargo")
+        .args(["run", "--quiet", "--"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn debugger run");
+
+    child
+        .stdin
+        .take()
+        .expect("Failed to open stdin")
+        .write_all(commands.as_bytes())
+        .expect("Failed to write debugger commands");
+
+    let output = child.wait_with_output().expect("Failed to wait for debugger run");
+    (output.status.success(), String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[test]
+fn test_debugger_step_and_inspect() {
+    let (success, stdout) = run_with_debugger(
+        &["--interactive", "--break", "2", "examples/debugger_test.ttvm"],
+        "vars\nstep\nstep\nstack\ncontinue\n",
+    );
+
+    assert!(success, "Debugger run failed:\n{}", stdout);
+    assert!(stdout.contains("Breakpoint hit at instruction 2: PushInt(32)"), "stdout:\n{}", stdout);
+    assert!(stdout.contains("Variables: Some({\"x\": Int(10)})"), "stdout:\n{}", stdout);
+    assert!(stdout.contains("Step at instruction 3: Load(\"x\")"), "stdout:\n{}", stdout);
+    assert!(stdout.contains("Step at instruction 4: Add"), "stdout:\n{}", stdout);
+    assert!(stdout.contains("Stack: [Int(32), Int(10)]"), "stdout:\n{}", stdout);
+    assert!(stdout.contains("42"), "stdout:\n{}", stdout);
+}
+
+#[test]
+fn test_debugger_quit_stops_execution() {
+    let (success, stdout) = run_with_debugger(
+        &["--interactive", "--break", "1", "examples/debugger_test.ttvm"],
+        "quit\n",
+    );
+
+    assert!(success, "Debugger run failed:\n{}", stdout);
+    assert!(stdout.contains("Breakpoint hit at instruction 1"), "stdout:\n{}", stdout);
+    assert!(!stdout.contains("42"), "Program kept running after quit:\n{}", stdout);
+}
+
+#[test]
+fn test_breakpoints_without_interactive_do_not_block() {
+    let (success, stdout) = run_with_debugger(
+        &["--no-smp", "--break", "2", "examples/debugger_test.ttvm"],
+        "",
+    );
+
+    assert!(success, "Non-interactive run failed:\n{}", stdout);
+    assert!(stdout.contains("Breakpoint hit at instruction 2"), "stdout:\n{}", stdout);
+    assert!(stdout.contains("42"), "stdout:\n{}", stdout);
+}
+
+// Counts i from 0 to 4; instruction 2 is the top of the loop body
+fn counting_loop() -> Vec<OpCode> {
+    vec![
+        OpCode::PushInt(0),
+        OpCode::Store("i".to_string()),
+        OpCode::Load("i".to_string()),   // 2: loop
+        OpCode::PushInt(5),
+        OpCode::Eq,
+        OpCode::Jz(7),
+        OpCode::Halt,
+        OpCode::Load("i".to_string()),   // 7
+        OpCode::PushInt(1),
+        OpCode::Add,
+        OpCode::Store("i".to_string()),
+        OpCode::Jmp(2),
+    ]
+}
+
+#[test]
+fn test_conditional_breakpoint_fires_only_on_matching_iteration() {
+    let mut vm = VM::new(counting_loop());
+    vm.add_conditional_breakpoint(2, "i", Value::Int(3));
+    vm.run().expect("Program failed");
+
+    assert_eq!(vm.breakpoint_hits, 1);
+}
+
+#[test]
+fn test_conditional_breakpoint_never_matching() {
+    let mut vm = VM::new(counting_loop());
+    vm.add_conditional_breakpoint(2, "i", Value::Int(100));
+    vm.run().expect("Program failed");
+
+    assert_eq!(vm.breakpoint_hits, 0);
+}
+
+#[test]
+fn test_unconditional_breakpoint_fires_every_iteration() {
+    let mut vm = VM::new(counting_loop());
+    vm.add_breakpoint(2);
+    vm.run().expect("Program failed");
+
+    assert_eq!(vm.breakpoint_hits, 6);
+}
+
+#[test]
+fn test_watchpoint_fires_on_each_change() {
+    let mut vm = VM::new(counting_loop());
+    vm.add_watchpoint("i");
+    vm.add_watchpoint("i");
+    vm.run().expect("Program failed");
+
+    // i goes from undefined to 0, then 1 through 5
+    assert_eq!(vm.watchpoint_hits, 6);
+    assert_eq!(vm.breakpoint_hits, 0);
+}
+
+#[test]
+fn test_watchpoint_ignores_stores_of_the_same_value() {
+    let mut vm = VM::new(vec![
+        OpCode::PushInt(7),
+        OpCode::Store("x".to_string()),
+        OpCode::PushInt(7),
+        OpCode::Store("x".to_string()),
+        OpCode::PushInt(1),
+        OpCode::Store("y".to_string()),
+        OpCode::Halt,
+    ]);
+    vm.add_watchpoint("x");
+    vm.run().expect("Program failed");
+
+    assert_eq!(vm.watchpoint_hits, 1);
+}
+
+#[test]
+fn test_interactive_watchpoint_pauses_after_the_change() {
+    let (success, stdout) = run_with_debugger(
+        &["--interactive", "--watch", "x", "examples/debugger_test.ttvm"],
+        "vars
+continue
+",
+    );
+
+    assert!(success, "Debugger run failed:\n{}", stdout);
+    assert!(stdout.contains("Watchpoint x changed at instruction 1: undefined -> Int(10)"), "stdout:\n{}", stdout);
+    assert!(stdout.contains("Paused at instruction 2: PushInt(32)"), "stdout:\n{}", stdout);
+    assert!(stdout.contains("Variables: Some({\"x\": Int(10)})"), "stdout:\n{}", stdout);
+    assert!(stdout.contains("42"), "stdout:\n{}", stdout);
+}
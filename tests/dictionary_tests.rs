@@ -0,0 +1,53 @@
+use crossbeam::channel::unbounded;
+use tiny_tot_vm::concurrency::{Message, SchedulerPool};
+use tiny_tot_vm::{OpCode, Value};
+
+fn key() -> OpCode {
+    OpCode::PushStr("count".to_string())
+}
+
+// count = count + 1
+fn increment() -> Vec<OpCode> {
+    vec![key(), key(), OpCode::GetDict, OpCode::PushInt(1), OpCode::Add, OpCode::PutDict]
+}
+
+#[test]
+fn test_dictionary_counter_survives_yields() {
+    let mut pool = SchedulerPool::new_with_threads(2);
+    let (sender, results) = unbounded();
+    {
+        let mut registry = pool.process_registry.lock().unwrap();
+        registry.register_process(9000, sender).unwrap();
+        registry.register_name("results".to_string(), 9000).unwrap();
+    }
+
+    let mut counter = vec![key(), OpCode::PushInt(0), OpCode::PutDict, OpCode::Yield];
+    counter.extend(increment());
+    counter.push(OpCode::Yield);
+    counter.extend(increment());
+    counter.extend([key(), OpCode::GetDict, OpCode::SendNamed("results".to_string())]);
+    counter.extend([key(), OpCode::EraseDict, key(), OpCode::GetDict, OpCode::SendNamed("results".to_string())]);
+    counter.push(OpCode::Halt);
+    pool.spawn_process(counter);
+
+    // Another process never sees the first one's entries
+    pool.spawn_process(vec![
+        OpCode::Yield,
+        key(),
+        OpCode::GetDict,
+        OpCode::SendNamed("results".to_string()),
+        OpCode::Halt,
+    ]);
+
+    pool.run().unwrap();
+    pool.wait_for_completion();
+
+    let mut reported: Vec<Value> = results.try_iter().map(|msg| match msg {
+        Message::Value(value) => value,
+        other => panic!("Expected a value message, got {:?}", other),
+    }).collect();
+    assert_eq!(reported.len(), 3, "Got {:?}", reported);
+    assert!(reported.contains(&Value::Int(2)), "Counter got lost: {:?}", reported);
+    reported.retain(|value| value != &Value::Int(2));
+    assert_eq!(reported, vec![Value::Null, Value::Null]);
+}
@@ -0,0 +1,135 @@
+use std::time::Duration;
+use tiny_tot_vm::gc::GcStats;
+use tiny_tot_vm::vm::VM;
+use tiny_tot_vm::{OpCode, Value};
+
+// Builds `lists` short-lived lists, each replacing the last, while one list stays alive throughout
+fn churn(lists: usize, gc_threshold: usize) -> GcStats {
+    let mut program = vec![OpCode::PushInt(0), OpCode::MakeList(1), OpCode::Store("kept".to_string())];
+    for i in 0..lists {
+        program.extend([OpCode::PushInt(i as i64 + 1), OpCode::MakeList(1), OpCode::Store("scratch".to_string())]);
+    }
+    program.push(OpCode::Halt);
+
+    let mut vm = VM::new(program);
+    vm.set_gc_threshold(gc_threshold);
+    vm.run().expect("Program failed");
+    vm.get_gc_stats()
+}
+
+#[test]
+fn test_low_threshold_collects_often() {
+    let stats = churn(100, 10);
+    assert_eq!(stats.total_allocated, 101);
+    assert_eq!(stats.collections_performed, 10);
+    // The last collection kept "kept", the list in "scratch" and the one just built; one more came after it
+    assert_eq!(stats.total_freed, 97);
+    assert_eq!(stats.current_allocated, 4);
+}
+
+#[test]
+fn test_high_threshold_collects_rarely() {
+    let stats = churn(100, 1000);
+    assert_eq!(stats.collections_performed, 0);
+    assert_eq!(stats.current_allocated, 101);
+
+    assert!(churn(100, 50).collections_performed < churn(100, 10).collections_performed);
+}
+
+#[test]
+fn test_collections_add_up_pause_time() {
+    for gc_type in ["mark-sweep", "no-gc"] {
+        let mut vm = VM::new_with_gc(vec![OpCode::PushInt(1), OpCode::MakeList(1), OpCode::Halt], gc_type, false, true);
+        vm.run().expect("Program failed");
+        assert_eq!(vm.get_gc_stats().total_pause, Duration::ZERO);
+
+        let mut previous = vm.get_gc_stats();
+        for collections in 1..=3 {
+            vm.trigger_gc();
+            let stats = vm.get_gc_stats();
+            assert_eq!(stats.collections_performed, collections, "{}", gc_type);
+            assert!(stats.total_pause >= previous.total_pause, "{} pause went backwards", gc_type);
+            assert!(stats.longest_pause >= previous.longest_pause);
+            previous = stats;
+        }
+        assert!(previous.total_pause > Duration::ZERO, "{} recorded no pause", gc_type);
+        assert!(previous.longest_pause <= previous.total_pause);
+        assert!(previous.longest_pause * 3 >= previous.total_pause);
+    }
+}
+
+#[test]
+fn test_weak_ref_is_cleared_once_target_is_unreachable() {
+    let mut vm = VM::new(vec![
+        OpCode::PushStr("cached".to_string()),
+        OpCode::MakeList(1),
+        OpCode::Store("strong".to_string()),
+        OpCode::Load("strong".to_string()),
+        OpCode::WeakRef,
+        OpCode::Store("weak".to_string()),
+        // Still reachable through "strong"
+        OpCode::Load("weak".to_string()),
+        OpCode::WeakGet,
+        OpCode::Len,
+        // Drop the only strong reference; the next allocation collects
+        OpCode::Null,
+        OpCode::Store("strong".to_string()),
+        OpCode::MakeObject,
+        OpCode::Store("unrelated".to_string()),
+        OpCode::Load("weak".to_string()),
+        OpCode::WeakGet,
+        OpCode::Halt,
+    ]);
+    vm.set_gc_threshold(1);
+    vm.run().expect("Program failed");
+    assert_eq!(vm.stack, vec![Value::Int(1), Value::Null]);
+}
+
+#[test]
+fn test_equal_value_does_not_keep_weak_target_alive() {
+    let mut vm = VM::new(vec![
+        OpCode::PushInt(1),
+        OpCode::PushInt(2),
+        OpCode::MakeList(2),
+        OpCode::Store("strong".to_string()),
+        // Built separately, so it is equal to "strong" but not a copy of it
+        OpCode::PushInt(1),
+        OpCode::PushInt(2),
+        OpCode::MakeList(2),
+        OpCode::Store("other".to_string()),
+        OpCode::Load("strong".to_string()),
+        OpCode::WeakRef,
+        OpCode::Store("weak".to_string()),
+        OpCode::Null,
+        OpCode::Store("strong".to_string()),
+        OpCode::MakeObject,
+        OpCode::Store("unrelated".to_string()),
+        OpCode::Load("weak".to_string()),
+        OpCode::WeakGet,
+        OpCode::Load("other".to_string()),
+        OpCode::Halt,
+    ]);
+    vm.set_gc_threshold(1);
+    vm.run().expect("Program failed");
+    assert_eq!(vm.stack, vec![Value::Null, Value::List(vec![Value::Int(1), Value::Int(2)].into())]);
+}
+
+#[test]
+fn test_no_gc_keeps_weak_targets() {
+    let mut vm = VM::new_with_gc(vec![
+        OpCode::PushInt(7),
+        OpCode::MakeList(1),
+        OpCode::WeakRef,
+        OpCode::Store("weak".to_string()),
+        OpCode::MakeObject,
+        OpCode::Store("unrelated".to_string()),
+        OpCode::Load("weak".to_string()),
+        OpCode::WeakGet,
+        OpCode::Halt,
+    ], "no-gc", false, false);
+    vm.set_gc_threshold(1);
+    vm.run().expect("Program failed");
+    vm.trigger_gc();
+    assert_eq!(vm.stack, vec![Value::List(vec![Value::Int(7)].into())]);
+    assert_eq!(vm.get_gc_stats().total_allocated, 3);
+}
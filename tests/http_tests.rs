@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tiny_tot_vm::vm::VM;
+use tiny_tot_vm::{OpCode, Value, VMError};
+
+// A one-shot HTTP server on a free local port. It answers the first request with `status`
+// and `body`, echoing the request's content type back in `X-Received-Type`, and hands the
+// request line and body to the test through the join handle.
+fn mock_server(status: &'static str, body: &'static str) -> (String, JoinHandle<(String, String)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock server");
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().expect("Mock server got no connection");
+        let mut reader = BufReader::new(stream);
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+            }
+        }
+        let length = headers.get("content-length").map_or(0, |n| n.parse().unwrap());
+        let mut request_body = vec![0; length];
+        reader.read_exact(&mut request_body).unwrap();
+
+        let content_type = headers.get("content-type").cloned().unwrap_or_default();
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nX-Received-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status, content_type, body.len(), body
+        );
+        reader.get_mut().write_all(response.as_bytes()).unwrap();
+        (request_line.trim_end().to_string(), String::from_utf8(request_body).unwrap())
+    });
+
+    (url, handle)
+}
+
+fn run(program: Vec<OpCode>) -> Result<Value, VMError> {
+    let mut vm = VM::new(program);
+    vm.run()?;
+    Ok(vm.stack.pop().expect("Nothing left on the stack"))
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> &'a Value {
+    match value {
+        Value::Object(fields) => fields.get(name).unwrap_or_else(|| panic!("Response has no {} field", name)),
+        other => panic!("Expected a response object, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_http_get_returns_status_headers_and_body() {
+    let (url, server) = mock_server("200 OK", "hello from the mock");
+    let response = run(vec![
+        OpCode::PushStr(format!("{}/greeting?lang=en", url)),
+        OpCode::HttpGet,
+        OpCode::Halt,
+    ]).expect("HTTP_GET failed");
+
+    assert_eq!(field(&response, "status"), &Value::Int(200));
+    assert_eq!(field(&response, "body"), &Value::Str("hello from the mock".to_string()));
+    assert_eq!(field(field(&response, "headers"), "content-type"), &Value::Str("text/plain".to_string()));
+
+    let (request_line, _) = server.join().unwrap();
+    assert_eq!(request_line, "GET /greeting?lang=en HTTP/1.1");
+}
+
+#[test]
+fn test_http_get_error_status_is_a_response() {
+    let (url, server) = mock_server("404 Not Found", "no such page");
+    let response = run(vec![OpCode::PushStr(format!("{}/missing", url)), OpCode::HttpGet, OpCode::Halt])
+        .expect("HTTP_GET failed");
+
+    assert_eq!(field(&response, "status"), &Value::Int(404));
+    assert_eq!(field(&response, "body"), &Value::Str("no such page".to_string()));
+    server.join().unwrap();
+}
+
+#[test]
+fn test_http_post_sends_body_and_content_type() {
+    let (url, server) = mock_server("201 Created", "stored");
+    let response = run(vec![
+        OpCode::PushStr(format!("{}/items", url)),
+        OpCode::MakeObject,
+        OpCode::PushStr("{\"name\": \"tot\"}".to_string()),
+        OpCode::SetField("body".to_string()),
+        OpCode::PushStr("application/json".to_string()),
+        OpCode::SetField("content_type".to_string()),
+        OpCode::HttpPost,
+        OpCode::Halt,
+    ]).expect("HTTP_POST failed");
+
+    assert_eq!(field(&response, "status"), &Value::Int(201));
+    assert_eq!(field(&response, "body"), &Value::Str("stored".to_string()));
+    assert_eq!(field(field(&response, "headers"), "x-received-type"), &Value::Str("application/json".to_string()));
+
+    let (request_line, request_body) = server.join().unwrap();
+    assert_eq!(request_line, "POST /items HTTP/1.1");
+    assert_eq!(request_body, "{\"name\": \"tot\"}");
+}
+
+#[test]
+fn test_http_post_plain_string_body() {
+    let (url, server) = mock_server("200 OK", "ok");
+    let response = run(vec![
+        OpCode::PushStr(url),
+        OpCode::PushStr("just text".to_string()),
+        OpCode::HttpPost,
+        OpCode::Halt,
+    ]).expect("HTTP_POST failed");
+
+    assert_eq!(field(field(&response, "headers"), "x-received-type"), &Value::Str("text/plain; charset=utf-8".to_string()));
+    let (_, request_body) = server.join().unwrap();
+    assert_eq!(request_body, "just text");
+}
+
+#[test]
+fn test_http_connection_failure_reports_url() {
+    // Bind and immediately release a port so nothing is listening on it
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let url = format!("http://127.0.0.1:{}/", port);
+
+    match run(vec![OpCode::PushStr(url.clone()), OpCode::HttpGet, OpCode::Halt]) {
+        Err(VMError::NetworkError { url: failed_url, .. }) => assert_eq!(failed_url, url),
+        other => panic!("Expected a network error, got {:?}", other),
+    }
+}
+
+// Real endpoints, kept out of the examples so they and the default test run work offline.
+// Run with --ignored when the machine has network access.
+#[test]
+#[ignore = "needs network access"]
+fn test_http_against_live_endpoints() {
+    let response = run(vec![OpCode::PushStr("https://api.github.com/users/octocat".to_string()), OpCode::HttpGet, OpCode::Halt])
+        .expect("HTTP_GET failed");
+    assert_eq!(field(&response, "status"), &Value::Int(200));
+    assert!(matches!(field(&response, "body"), Value::Str(body) if body.contains("octocat")), "{:?}", response);
+
+    let response = run(vec![
+        OpCode::PushStr("https://httpbin.org/post".to_string()),
+        OpCode::PushStr("{\"test\": \"data\"}".to_string()),
+        OpCode::HttpPost,
+        OpCode::Halt,
+    ]).expect("HTTP_POST failed");
+    assert_eq!(field(&response, "status"), &Value::Int(200));
+    assert!(matches!(field(&response, "body"), Value::Str(body) if body.contains(r#"\"test\": \"data\""#)), "{:?}", response);
+}
+
+// Sends a request to a VM serving HTTP in another thread, retrying until it is listening
+fn once_listening(request: ureq::Request, body: &str) -> ureq::Response {
+    for _ in 0..50 {
+        match request.clone().send_string(body) {
+            Ok(response) => return response,
+            Err(ureq::Error::Transport(_)) => thread::sleep(Duration::from_millis(100)),
+            Err(e) => panic!("Request failed: {}", e),
+        }
+    }
+    panic!("The VM never started listening");
+}
+
+#[test]
+fn test_http_server_answers_a_loopback_request() {
+    // Pick a free port for the VM to listen on
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let server = thread::spawn(move || run(vec![
+        OpCode::PushInt(port as i64),
+        OpCode::HttpServe,
+        OpCode::HttpAccept,
+        OpCode::Store("request".to_string()),
+        OpCode::Load("request".to_string()),
+        OpCode::PushInt(201),
+        OpCode::PushStr("got ".to_string()),
+        OpCode::Load("request".to_string()),
+        OpCode::GetField("path".to_string()),
+        OpCode::Concat,
+        OpCode::PushStr(" with ".to_string()),
+        OpCode::Concat,
+        OpCode::Load("request".to_string()),
+        OpCode::GetField("body".to_string()),
+        OpCode::Concat,
+        OpCode::HttpRespond,
+        OpCode::Load("request".to_string()),
+        OpCode::Halt,
+    ]));
+
+    let url = format!("http://127.0.0.1:{}/orders?id=7", port);
+    let response = once_listening(ureq::post(&url).set("X-Client", "tests"), "two coffees");
+    assert_eq!(response.status(), 201);
+    assert_eq!(response.header("content-type"), Some("text/plain; charset=utf-8"));
+    assert_eq!(response.into_string().unwrap(), "got /orders?id=7 with two coffees");
+
+    let request = server.join().unwrap().expect("Serving the request failed");
+    assert_eq!(field(&request, "method"), &Value::Str("POST".to_string()));
+    assert_eq!(field(field(&request, "headers"), "x-client"), &Value::Str("tests".to_string()));
+    assert!(matches!(field(&request, "connection"), Value::Connection(_)));
+}
+
+#[test]
+fn test_http_respond_twice_is_an_error() {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let server = thread::spawn(move || run(vec![
+        OpCode::PushInt(port as i64),
+        OpCode::HttpServe,
+        OpCode::HttpAccept,
+        OpCode::Store("request".to_string()),
+        OpCode::Load("request".to_string()),
+        OpCode::PushInt(200),
+        OpCode::PushStr("first".to_string()),
+        OpCode::HttpRespond,
+        OpCode::Load("request".to_string()),
+        OpCode::PushInt(200),
+        OpCode::PushStr("second".to_string()),
+        OpCode::HttpRespond,
+        OpCode::Halt,
+    ]));
+
+    let url = format!("http://127.0.0.1:{}/", port);
+    let response = once_listening(ureq::get(&url), "");
+    assert_eq!(response.into_string().unwrap(), "first");
+
+    match server.join().unwrap() {
+        Err(VMError::NetworkError { error, .. }) => assert!(error.contains("already answered"), "Unclear error: {}", error),
+        other => panic!("Expected a network error, got {:?}", other),
+    }
+}
+
+// Sends raw request bytes to a VM serving HTTP in another thread, retrying until it is listening
+fn send_raw_once_listening(port: u16, request: &[u8]) {
+    for _ in 0..50 {
+        if let Ok(mut stream) = std::net::TcpStream::connect(("127.0.0.1", port)) {
+            // The server may hang up before reading everything, which is the point
+            let _ = stream.write_all(request);
+            return;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("The VM never started listening");
+}
+
+#[test]
+fn test_http_accept_rejects_oversized_requests() {
+    let long_header = format!("GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "a".repeat(64 * 1024));
+    let cases: [(&[u8], &str); 2] = [
+        (b"POST / HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\n", "larger than"),
+        (long_header.as_bytes(), "longer than"),
+    ];
+    for (request, reason) in cases {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let server = thread::spawn(move || run(vec![
+            OpCode::PushInt(port as i64),
+            OpCode::HttpServe,
+            OpCode::HttpAccept,
+            OpCode::Halt,
+        ]));
+        send_raw_once_listening(port, request);
+        match server.join().expect("The server VM panicked") {
+            Err(VMError::NetworkError { error, .. }) => assert!(error.contains(reason), "Unclear error: {}", error),
+            other => panic!("Expected a network error, got {:?}", other),
+        }
+    }
+}
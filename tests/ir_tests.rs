@@ -1,6 +1,12 @@
 use std::fs;
 use std::path::Path;
 use std::process::Command;
+use tiny_tot_vm::bytecode::parse_source;
+use tiny_tot_vm::ir::lowering::StackToRegisterLowering;
+use tiny_tot_vm::ir::optimizer::IrOptimizer;
+use tiny_tot_vm::ir::vm::RegisterVM;
+use tiny_tot_vm::vm::VMResult;
+use tiny_tot_vm::{DestructurePattern, OpCode, Value, VMError};
 
 // Test that IR execution works for simple programs
 #[test]
@@ -52,6 +58,114 @@ fn test_ir_equivalence_with_stack_vm() {
     }
 }
 
+// Differential test: programs using objects, closures, function values and exceptions
+// must print the same thing on both VMs, and must actually run as IR
+#[test]
+fn test_ir_matches_stack_vm_for_objects_and_closures() {
+    // Objects print their fields in insertion order, so whole objects can be compared too
+    let object_program = std::env::temp_dir().join("ttvm_ir_object_diff.ttvm");
+    fs::write(&object_program, "\
+MAKE_OBJECT
+PUSH_STR \"Ada\"
+SET_FIELD name
+PUSH_INT 36
+SET_FIELD age
+DUP
+PRINT
+DUP
+KEYS
+PRINT
+DUP
+GET_FIELD name
+PRINT
+DUP
+HAS_FIELD age
+PRINT
+DELETE_FIELD age
+DUP
+HAS_FIELD age
+PRINT
+DUP
+LEN
+PRINT
+STORE person
+LOAD person
+GET_FIELD missing
+PRINT
+HALT
+").expect("Failed to write object program");
+
+    let programs = [
+        object_program.to_string_lossy().to_string(),
+        "examples/closure_test.ttvm".to_string(),
+        "examples/lambda_test.ttvm".to_string(),
+        "examples/nested_closure_test.ttvm".to_string(),
+        "examples/higher_order_test.ttvm".to_string(),
+        "examples/function_pointer_test.ttvm".to_string(),
+        "examples/function_test.ttvm".to_string(),
+        "examples/list_test.ttvm".to_string(),
+        "examples/tail_call_test.ttvm".to_string(),
+        "examples/exception_test.ttvm".to_string(),
+        "examples/function_exception_test.ttvm".to_string(),
+    ];
+
+    for program in &programs {
+        let stack_output = Command::new("cargo")
+            .args(["run", "--quiet", "--", "--no-smp", program])
+            .output()
+            .expect("Failed to run stack VM");
+        let ir_output = Command::new("cargo")
+            .args(["run", "--quiet", "--", "--use-ir", program])
+            .output()
+            .expect("Failed to run IR VM");
+
+        assert!(stack_output.status.success(), "Stack VM failed for {}", program);
+        assert!(
+            ir_output.status.success(),
+            "IR VM failed for {}: {}",
+            program,
+            String::from_utf8_lossy(&ir_output.stderr)
+        );
+
+        let stack_stdout = filter_vm_output(&stack_output.stdout);
+        let ir_stdout = filter_vm_output(&ir_output.stdout);
+        assert!(
+            !ir_stdout.contains("running on the stack VM instead"),
+            "{} was not lowered to IR:\n{}",
+            program, ir_stdout
+        );
+        assert_eq!(
+            stack_stdout, ir_stdout,
+            "Output mismatch for {}:\nStack VM: {}\nIR VM: {}",
+            program, stack_stdout, ir_stdout
+        );
+    }
+
+    let _ = fs::remove_file(&object_program);
+}
+
+// Differential fuzz: generated arithmetic/stack/variable programs must leave the same stack,
+// print the same output and fail with the same error on both VMs
+#[test]
+fn test_ir_differential_fuzz() {
+    for seed in ["1953789549", "1", "2024"] {
+        let output = Command::new("cargo")
+            .args(["run", "--quiet", "--", "--run-tests", "--no-table"])
+            .env("TTVM_FUZZ_SEED", seed)
+            .output()
+            .expect("Failed to run VM tests");
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.contains("Stack/IR differential fuzz | expected: no mismatches") && stdout.contains("| PASS\nTests passed: 4/4"),
+            "Differential fuzz failed for seed {}:\n{}\n{}",
+            seed,
+            stdout,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
 // Helper function to filter out VM-specific debug messages
 fn filter_vm_output(output: &[u8]) -> String {
     let output_str = String::from_utf8_lossy(output);
@@ -113,4 +227,413 @@ fn test_ir_boolean_operations() {
         "IR boolean test failed: {}",
         String::from_utf8_lossy(&bool_output.stderr)
     );
-}
\ No newline at end of file
+}
+// Lower a program and run it on the register VM, optionally through the IR optimizer first
+fn run_lowered(program: &[OpCode], optimize: bool) -> (usize, RegisterVM, VMResult<Option<Value>>) {
+    let mut block = StackToRegisterLowering::lower(program).expect("Lowering failed");
+    if optimize {
+        block = IrOptimizer::new().optimize(block).0;
+    }
+    let instructions = block.instructions.len();
+    let mut vm = RegisterVM::new(block);
+    let result = vm.run();
+    (instructions, vm, result)
+}
+
+#[test]
+fn test_ir_optimizer_folds_constants() {
+    // x = (2 + 3) * 4 - 6 / 2
+    let program = vec![
+        OpCode::PushInt(2),
+        OpCode::PushInt(3),
+        OpCode::Add,
+        OpCode::PushInt(4),
+        OpCode::Mul,
+        OpCode::PushInt(6),
+        OpCode::PushInt(2),
+        OpCode::Div,
+        OpCode::Sub,
+        OpCode::Store("x".to_string()),
+        OpCode::Halt,
+    ];
+
+    let block = StackToRegisterLowering::lower(&program).expect("Lowering failed");
+    let (optimized, stats) = IrOptimizer::new().optimize(block.clone());
+    assert_eq!(stats.constants_folded, 4);
+    assert!(stats.dead_instructions_removed > 0);
+    assert!(
+        optimized.instructions.len() < block.instructions.len(),
+        "Optimized block is not smaller: {:?}",
+        optimized.instructions
+    );
+
+    let (_, plain_vm, plain_result) = run_lowered(&program, false);
+    let (_, optimized_vm, optimized_result) = run_lowered(&program, true);
+    plain_result.expect("Unoptimized block failed");
+    optimized_result.expect("Optimized block failed");
+    assert_eq!(plain_vm.variables.get("x"), Some(&Value::Int(17)));
+    assert_eq!(optimized_vm.variables.get("x"), Some(&Value::Int(17)));
+}
+
+#[test]
+fn test_ir_optimizer_leaves_failing_arithmetic() {
+    // Folding must not hide the runtime error
+    let program = vec![
+        OpCode::PushInt(1),
+        OpCode::PushInt(0),
+        OpCode::Div,
+        OpCode::Halt,
+    ];
+    let block = StackToRegisterLowering::lower(&program).expect("Lowering failed");
+    let (_, stats) = IrOptimizer::new().optimize(block);
+    assert_eq!(stats.constants_folded, 0);
+
+    let (_, _, result) = run_lowered(&program, true);
+    assert!(matches!(result, Err(VMError::DivisionByZero)), "Expected division by zero, got {:?}", result);
+}
+
+#[test]
+fn test_ir_optimizer_preserves_control_flow_and_calls() {
+    // Constants feed a loop and a function call, so jump targets and frames must survive
+    // instructions being removed
+    let (program, _) = parse_source("\
+PUSH_INT 10
+PUSH_INT 5
+SUB
+STORE n
+PUSH_INT 0
+STORE total
+LABEL loop
+LOAD n
+JZ done
+LOAD total
+LOAD n
+PUSH_INT 2
+PUSH_INT 3
+MUL
+CALL scale b c
+ADD
+STORE total
+LOAD n
+PUSH_INT 1
+SUB
+STORE n
+JMP loop
+LABEL done
+HALT
+LABEL scale
+LOAD b
+LOAD c
+MUL
+PUSH_INT 8
+PUSH_INT 4
+DIV
+SUB
+RET
+").expect("Failed to parse program");
+
+    let (plain_len, plain_vm, plain_result) = run_lowered(&program, false);
+    let (optimized_len, optimized_vm, optimized_result) = run_lowered(&program, true);
+    plain_result.expect("Unoptimized block failed");
+    optimized_result.expect("Optimized block failed");
+    assert!(optimized_len < plain_len);
+    // total = sum over n = 5..1 of (n * 6 - 2)
+    assert_eq!(plain_vm.variables.get("total"), Some(&Value::Int(80)));
+    assert_eq!(optimized_vm.variables.get("total"), Some(&Value::Int(80)));
+}
+
+#[test]
+fn test_ir_ret_n_checks_the_return_count() {
+    let source = "PUSH_INT 17\nPUSH_INT 5\nCALL sum_and_product a b\nSUB\nSTORE diff\nHALT\nLABEL sum_and_product\nLOAD a\nLOAD b\nADD\nLOAD a\nLOAD b\nMUL\nRET 2\n";
+    let (program, _) = parse_source(source).expect("Failed to parse program");
+    let (_, vm, result) = run_lowered(&program, false);
+    result.expect("Block failed");
+    assert_eq!(vm.variables.get("diff"), Some(&Value::Int(-63)));
+
+    let (program, _) = parse_source(&source.replace("RET 2", "RET 3")).expect("Failed to parse program");
+    match StackToRegisterLowering::lower(&program) {
+        Err(VMError::ReturnCountMismatch { declared, left }) => assert_eq!((declared, left), (3, 2)),
+        other => panic!("Expected ReturnCountMismatch, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_ir_values_and_entries_follow_keys_order() {
+    let (program, _) = parse_source("\
+MAKE_OBJECT
+PUSH_INT 1
+SET_FIELD one
+PUSH_INT 2
+SET_FIELD two
+PUSH_INT 3
+SET_FIELD three
+PUSH_INT 4
+SET_FIELD four
+STORE obj
+LOAD obj
+KEYS
+STORE keys
+LOAD obj
+VALUES
+STORE values
+LOAD obj
+ENTRIES
+STORE entries
+HALT
+").expect("Failed to parse program");
+
+    let (_, vm, result) = run_lowered(&program, false);
+    result.expect("IR run failed");
+    let (Some(Value::List(keys)), Some(Value::List(values)), Some(Value::List(entries))) =
+        (vm.variables.get("keys"), vm.variables.get("values"), vm.variables.get("entries"))
+    else {
+        panic!("KEYS, VALUES and ENTRIES did not all produce lists");
+    };
+    assert_eq!(keys.len(), 4);
+    let zipped: Vec<Value> = keys.iter()
+        .zip(values)
+        .map(|(key, value)| Value::List(vec![key.clone(), value.clone()].into()))
+        .collect();
+    assert_eq!(entries, &zipped);
+}
+
+#[test]
+fn test_ir_set_field_mut_updates_variable() {
+    let (program, _) = parse_source("\
+MAKE_OBJECT
+STORE counts
+PUSH_INT 1
+SET_FIELD_MUT counts a
+PUSH_INT 2
+SET_FIELD_MUT counts b
+PUSH_INT 3
+SET_FIELD_MUT counts a
+HALT
+").expect("Failed to parse program");
+
+    for optimize in [false, true] {
+        let (_, vm, result) = run_lowered(&program, optimize);
+        result.expect("IR run failed");
+        let Some(Value::Object(counts)) = vm.variables.get("counts") else { panic!("counts is not an object") };
+        assert_eq!(counts.get("a"), Some(&Value::Int(3)));
+        assert_eq!(counts.get("b"), Some(&Value::Int(2)));
+    }
+}
+
+#[test]
+fn test_ir_exception_trace_matches_stack_vm() {
+    let (program, _) = parse_source("\
+TRY catch
+CALL outer
+END_TRY
+HALT
+LABEL outer
+PUSH_INT 1
+CALL inner
+ADD
+RET
+LABEL inner
+PUSH_STR \"deep failure\"
+THROW
+RET
+LABEL catch
+CATCH
+HALT
+").expect("Failed to parse program");
+
+    let mut stack_vm = tiny_tot_vm::vm::VM::new(program.clone());
+    stack_vm.run().expect("Stack VM run failed");
+    let Some(Value::Exception { stack_trace: expected, .. }) = stack_vm.stack.last() else {
+        panic!("Stack VM did not catch the exception");
+    };
+    assert_eq!(expected.len(), 3, "{:?}", expected);
+
+    for optimize in [false, true] {
+        let (_, _, result) = run_lowered(&program, optimize);
+        match result.expect("IR run failed") {
+            Some(Value::Exception { stack_trace, .. }) => assert_eq!(&stack_trace, expected),
+            other => panic!("Expected the caught exception, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_ir_finally_matches_stack_vm() {
+    // The catch block rethrows, so the cleanup runs and passes the exception to the outer handler
+    let (program, _) = parse_source("\
+PUSH_STR \"\"
+STORE log
+TRY outer
+TRY cleanup
+TRY handler
+PUSH_STR \"boom\"
+THROW
+END_TRY
+LABEL handler
+CATCH
+THROW
+END_TRY
+LABEL cleanup
+FINALLY
+LOAD log
+PUSH_STR \"finally,\"
+CONCAT
+STORE log
+END_FINALLY
+END_TRY
+HALT
+LABEL outer
+CATCH
+EXCEPTION_MESSAGE
+LOAD log
+CONCAT
+STORE log
+HALT
+").expect("Failed to parse program");
+
+    let mut stack_vm = tiny_tot_vm::vm::VM::new(program.clone());
+    stack_vm.run().expect("Stack VM run failed");
+    let expected = Value::Str("boomfinally,".to_string());
+    assert_eq!(stack_vm.variables[0].get("log"), Some(&expected));
+
+    for optimize in [false, true] {
+        let (_, vm, result) = run_lowered(&program, optimize);
+        result.expect("IR run failed");
+        assert_eq!(vm.variables.get("log"), Some(&expected), "optimize: {}", optimize);
+    }
+}
+
+#[test]
+fn test_ir_rethrow_matches_stack_vm() {
+    let (program, _) = parse_source("\
+TRY outer
+TRY inner
+PUSH_STR \"bad input\"
+THROW
+END_TRY
+END_TRY
+HALT
+LABEL inner
+CATCH
+RETHROW
+LABEL outer
+CATCH
+HALT
+").expect("Failed to parse program");
+
+    let mut stack_vm = tiny_tot_vm::vm::VM::new(program.clone());
+    stack_vm.run().expect("Stack VM run failed");
+    let Some(expected @ Value::Exception { .. }) = stack_vm.stack.last() else {
+        panic!("Stack VM did not catch the exception");
+    };
+
+    for optimize in [false, true] {
+        let (_, _, result) = run_lowered(&program, optimize);
+        assert_eq!(result.expect("IR run failed").as_ref(), Some(expected), "optimize: {}", optimize);
+    }
+}
+
+#[test]
+fn test_ir_float_semantics_match_stack_vm() {
+    let program = vec![
+        OpCode::PushFloat(f64::NAN),
+        OpCode::PushFloat(f64::NAN),
+        OpCode::EqF,
+        OpCode::Store("nan_eq".to_string()),
+        OpCode::PushFloat(f64::INFINITY),
+        OpCode::PushFloat(1.0),
+        OpCode::GtF,
+        OpCode::Store("inf_gt".to_string()),
+        OpCode::PushFloat(f64::INFINITY),
+        OpCode::PushFloat(f64::INFINITY),
+        OpCode::SubF,
+        OpCode::Halt,
+    ];
+    for optimize in [false, true] {
+        let (_, vm, result) = run_lowered(&program, optimize);
+        assert!(matches!(result, Err(VMError::NotANumber { .. })), "Expected NotANumber, got {:?}", result);
+        assert_eq!(vm.variables.get("nan_eq"), Some(&Value::Int(0)));
+        assert_eq!(vm.variables.get("inf_gt"), Some(&Value::Int(1)));
+    }
+}
+
+#[test]
+fn test_ir_assert_matches_the_stack_vm() {
+    let passing = vec![OpCode::PushInt(5), OpCode::Assert(None), OpCode::PushInt(1), OpCode::Store("ok".to_string()), OpCode::Halt];
+    let (_, vm, result) = run_lowered(&passing, true);
+    result.expect("Truthy assertion failed");
+    assert_eq!(vm.variables.get("ok"), Some(&Value::Int(1)));
+
+    let failing = vec![OpCode::Null, OpCode::Assert(Some("never null".to_string())), OpCode::Halt];
+    let (_, _, result) = run_lowered(&failing, true);
+    assert!(matches!(&result, Err(VMError::AssertionFailed { message }) if message == "never null"), "Got {:?}", result);
+}
+
+#[test]
+fn test_register_vm_destructures_into_variables() {
+    let pattern = DestructurePattern::List(
+        vec![DestructurePattern::Bind("head".to_string())],
+        Some("tail".to_string()),
+    );
+    let program = vec![
+        OpCode::PushInt(1),
+        OpCode::PushInt(2),
+        OpCode::PushInt(3),
+        OpCode::MakeList(3),
+        OpCode::Destructure(pattern.clone()),
+        OpCode::PushInt(4),
+        OpCode::Destructure(pattern),
+        OpCode::Halt,
+    ];
+
+    let (_, vm, result) = run_lowered(&program, false);
+    assert!(matches!(result, Err(VMError::PatternMismatch { .. })), "{:?}", result);
+    assert_eq!(vm.variables.get("head"), Some(&Value::Int(1)));
+    assert_eq!(vm.variables.get("tail"), Some(&Value::List(vec![Value::Int(2), Value::Int(3)].into())));
+}
+
+#[test]
+fn test_ir_checks_typed_function_calls() {
+    let source = "PUSH_INT 4\nMAKE_FUNCTION double n:int ->int\nCALL_FUNCTION\nSTORE result\nHALT\nLABEL double\nLOAD n\nLOAD n\nADD\nRET\n";
+    let (program, _) = parse_source(source).expect("Failed to parse program");
+    let (_, vm, result) = run_lowered(&program, false);
+    result.expect("Block failed");
+    assert_eq!(vm.variables.get("result"), Some(&Value::Int(8)));
+
+    let bad_argument = source.replace("PUSH_INT 4", "PUSH_FLOAT 4.0");
+    let bad_result = source.replace("->int", "->bool");
+    for (source, operation) in [(bad_argument, "CALL_FUNCTION"), (bad_result, "RET")] {
+        let (program, _) = parse_source(&source).expect("Failed to parse program");
+        let (_, _, result) = run_lowered(&program, false);
+        match result {
+            Err(VMError::TypeMismatch { operation: failed, .. }) => assert_eq!(failed, operation),
+            other => panic!("Expected a type mismatch from {}, got {:?}", operation, other),
+        }
+    }
+}
+
+#[test]
+fn test_bench_times_both_engines() {
+    let output = Command::new("cargo")
+        .args(["run", "--quiet", "--", "bench", "examples/countdown.ttvm", "3"])
+        .output()
+        .expect("Failed to run bench");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "bench failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("3 runs per engine"), "Missing run count:\n{}", stdout);
+
+    for engine in ["Stack VM", "Register IR VM"] {
+        let row = stdout.lines().find(|line| line.contains(&format!(" {} ", engine)))
+            .unwrap_or_else(|| panic!("No row for {}:\n{}", engine, stdout));
+        // Median, min, max, instructions and instructions/sec, after the engine name
+        let numbers: Vec<f64> = row.split('│')
+            .filter_map(|cell| cell.trim().parse().ok())
+            .collect();
+        assert_eq!(numbers.len(), 5, "Unexpected row: {}", row);
+        let [median, min, max, instructions, _] = numbers[..] else { unreachable!() };
+        assert!(min <= median && median <= max, "Timings out of order: {}", row);
+        assert!(instructions > 0.0, "No instructions counted: {}", row);
+    }
+    // The program's own output is discarded while timing
+    assert!(!stdout.lines().any(|line| line.trim() == "5"), "Program output leaked:\n{}", stdout);
+}
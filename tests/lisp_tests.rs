@@ -0,0 +1,90 @@
+use std::fs;
+use std::process::Command;
+
+// Compile Lisp source with compile-lisp, run the result on the stack VM and return its stdout
+fn run_lisp(name: &str, source: &str) -> String {
+    let dir = std::env::temp_dir();
+    let (input, output) = (dir.join(format!("{}.lisp", name)), dir.join(format!("{}.ttvm", name)));
+    fs::write(&input, source).unwrap();
+
+    let ttvm = |args: &[&str]| {
+        let result = Command::new("cargo")
+            .args(["run", "--quiet", "--"])
+            .args(args)
+            .output()
+            .expect("Failed to run command");
+        assert!(result.status.success(), "ttvm {:?} failed:\n{}", args, String::from_utf8_lossy(&result.stderr));
+        String::from_utf8_lossy(&result.stdout).into_owned()
+    };
+    let (input, output) = (input.to_str().unwrap(), output.to_str().unwrap());
+    ttvm(&["compile-lisp", input, output]);
+    ttvm(&["--no-smp", output])
+}
+
+fn printed(stdout: &str) -> Vec<&str> {
+    stdout.lines().filter(|line| !line.starts_with("Debug:") && !line.starts_with("SMP enabled")).collect()
+}
+
+// Classifies n with a three-way cond inside a print
+fn classify(n: i64) -> String {
+    format!(
+        "(define n {})\n(print (cond ((< n 0) \"negative\")\n             ((= n 0) \"zero\")\n             (else \"positive\")))\n",
+        n
+    )
+}
+
+#[test]
+fn test_cond_runs_the_first_matching_clause() {
+    let stdout = run_lisp("ttvm_cond_first", &classify(-4));
+    assert_eq!(printed(&stdout), vec!["negative"], "stdout:\n{}", stdout);
+
+    // Later clauses that also match are skipped
+    let source = "(print (cond ((> 2 1) \"first\") ((> 3 1) \"second\") (else \"neither\")))\n";
+    let stdout = run_lisp("ttvm_cond_first_of_two", source);
+    assert_eq!(printed(&stdout), vec!["first"], "stdout:\n{}", stdout);
+}
+
+#[test]
+fn test_cond_falls_through_to_a_middle_clause_and_else() {
+    let stdout = run_lisp("ttvm_cond_middle", &classify(0));
+    assert_eq!(printed(&stdout), vec!["zero"], "stdout:\n{}", stdout);
+
+    let stdout = run_lisp("ttvm_cond_else", &classify(9));
+    assert_eq!(printed(&stdout), vec!["positive"], "stdout:\n{}", stdout);
+}
+
+#[test]
+fn test_cond_with_only_else_and_nested_conds() {
+    let stdout = run_lisp("ttvm_cond_else_only", "(print (cond (else 42)))\n");
+    assert_eq!(printed(&stdout), vec!["42"], "stdout:\n{}", stdout);
+
+    // The inner conds need labels of their own
+    let source = "(define x 5)\n\
+                  (print (cond ((< x 3) (cond ((< x 1) \"tiny\") (else \"small\")))\n\
+                               (else (cond ((< x 10) \"medium\") (else \"large\")))))\n\
+                  (print (cond ((> x 100) \"huge\")))\n";
+    let stdout = run_lisp("ttvm_cond_nested", source);
+    assert_eq!(printed(&stdout), vec!["medium", "null"], "stdout:\n{}", stdout);
+}
+
+#[test]
+fn test_and_or_skip_the_right_operand_once_decided() {
+    // Running the skipped print would show its text (and then underflow the stack)
+    let source = "(print (and (= 1 2) (print \"and ran its right side\")))\n\
+                  (print (or (= 1 1) (print \"or ran its right side\")))\n";
+    let stdout = run_lisp("ttvm_short_circuit", source);
+    assert_eq!(printed(&stdout), vec!["false", "true"], "stdout:\n{}", stdout);
+}
+
+#[test]
+fn test_and_or_evaluate_further_operands_when_needed() {
+    let source = "(define x 5)\n\
+                  (print (and (> x 1) (< x 10) (= x 5)))\n\
+                  (print (and (> x 1) (> x 10)))\n\
+                  (print (or (< x 1) (> x 10) (= x 5)))\n\
+                  (print (or (< x 1) (> x 10)))\n\
+                  (print (and))\n\
+                  (print (or))\n";
+    let stdout = run_lisp("ttvm_and_or_values", source);
+    assert_eq!(printed(&stdout), vec!["true", "false", "true", "false", "true", "false"], "stdout:\n{}", stdout);
+}
@@ -0,0 +1,109 @@
+use tiny_tot_vm::concurrency::TinyProc;
+use tiny_tot_vm::vm::VM;
+use tiny_tot_vm::{OpCode, Value, VMError};
+
+fn unary(operand: Value, op: OpCode) -> Result<Value, VMError> {
+    apply(vec![operand], op)
+}
+
+fn binary(a: Value, b: Value, op: OpCode) -> Result<Value, VMError> {
+    apply(vec![a, b], op)
+}
+
+fn apply(operands: Vec<Value>, op: OpCode) -> Result<Value, VMError> {
+    let mut program: Vec<OpCode> = operands.into_iter().map(|operand| match operand {
+        Value::Int(n) => OpCode::PushInt(n),
+        Value::Float(n) => OpCode::PushFloat(n),
+        Value::Str(s) => OpCode::PushStr(s),
+        other => panic!("Can't push {:?}", other),
+    }).collect();
+    program.extend([op, OpCode::Halt]);
+    let mut vm = VM::new(program);
+    vm.run()?;
+    Ok(vm.stack.pop().unwrap())
+}
+
+#[test]
+fn test_abs_keeps_ints_and_floats_apart() {
+    assert_eq!(unary(Value::Int(-7), OpCode::Abs).unwrap(), Value::Int(7));
+    assert_eq!(unary(Value::Int(7), OpCode::Abs).unwrap(), Value::Int(7));
+    assert_eq!(unary(Value::Float(-2.5), OpCode::Abs).unwrap(), Value::Float(2.5));
+    assert_eq!(unary(Value::Float(-0.0), OpCode::Abs).unwrap(), Value::Float(0.0));
+    assert!(matches!(unary(Value::Int(i64::MIN), OpCode::Abs), Err(VMError::IntegerOverflow { operation }) if operation == "ABS"));
+    assert!(matches!(unary(Value::Str("-1".to_string()), OpCode::Abs), Err(VMError::TypeMismatch { .. })));
+}
+
+#[test]
+fn test_pow_is_exact_for_whole_int_powers() {
+    assert_eq!(binary(Value::Int(2), Value::Int(10), OpCode::Pow).unwrap(), Value::Int(1024));
+    assert_eq!(binary(Value::Int(-3), Value::Int(3), OpCode::Pow).unwrap(), Value::Int(-27));
+    assert_eq!(binary(Value::Int(5), Value::Int(0), OpCode::Pow).unwrap(), Value::Int(1));
+    assert_eq!(binary(Value::Int(2), Value::Int(-2), OpCode::Pow).unwrap(), Value::Float(0.25));
+    assert_eq!(binary(Value::Float(9.0), Value::Float(0.5), OpCode::Pow).unwrap(), Value::Float(3.0));
+    assert_eq!(binary(Value::Int(4), Value::Float(1.5), OpCode::Pow).unwrap(), Value::Float(8.0));
+
+    assert!(matches!(binary(Value::Int(2), Value::Int(63), OpCode::Pow), Err(VMError::IntegerOverflow { operation }) if operation == "POW"));
+    assert_eq!(binary(Value::Float(10.0), Value::Int(400), OpCode::Pow).unwrap(), Value::Float(f64::INFINITY));
+    assert!(matches!(binary(Value::Float(-8.0), Value::Float(0.5), OpCode::Pow), Err(VMError::NotANumber { operation }) if operation == "POW"));
+}
+
+#[test]
+fn test_sqrt_is_a_float_and_rejects_negatives() {
+    assert_eq!(unary(Value::Int(16), OpCode::Sqrt).unwrap(), Value::Float(4.0));
+    assert_eq!(unary(Value::Float(2.25), OpCode::Sqrt).unwrap(), Value::Float(1.5));
+    assert!(matches!(unary(Value::Float(-1.0), OpCode::Sqrt), Err(VMError::NotANumber { operation }) if operation == "SQRT"));
+}
+
+#[test]
+fn test_floor_ceil_and_round() {
+    for (n, floor, ceil, round) in [(2.5, 2.0, 3.0, 3.0), (-2.5, -3.0, -2.0, -3.0), (1.2, 1.0, 2.0, 1.0), (-0.4, -1.0, -0.0, -0.0)] {
+        assert_eq!(unary(Value::Float(n), OpCode::Floor).unwrap(), Value::Float(floor), "FLOOR {}", n);
+        assert_eq!(unary(Value::Float(n), OpCode::Ceil).unwrap(), Value::Float(ceil), "CEIL {}", n);
+        assert_eq!(unary(Value::Float(n), OpCode::Round).unwrap(), Value::Float(round), "ROUND {}", n);
+    }
+    assert_eq!(unary(Value::Int(-9), OpCode::Round).unwrap(), Value::Int(-9));
+    assert!(matches!(unary(Value::Str("x".to_string()), OpCode::Floor), Err(VMError::TypeMismatch { operation, .. }) if operation == "FLOOR"));
+}
+
+#[test]
+fn test_neg_keeps_the_type() {
+    assert_eq!(unary(Value::Int(5), OpCode::Neg).unwrap(), Value::Int(-5));
+    assert_eq!(unary(Value::Int(-5), OpCode::Neg).unwrap(), Value::Int(5));
+    assert_eq!(unary(Value::Int(i64::MAX), OpCode::Neg).unwrap(), Value::Int(-i64::MAX));
+    assert_eq!(unary(Value::Float(1.5), OpCode::Neg).unwrap(), Value::Float(-1.5));
+    assert_eq!(unary(Value::Float(f64::NEG_INFINITY), OpCode::Neg).unwrap(), Value::Float(f64::INFINITY));
+
+    // -i64::MIN doesn't fit
+    assert!(matches!(unary(Value::Int(i64::MIN), OpCode::Neg), Err(VMError::IntegerOverflow { operation }) if operation == "NEG"));
+    assert!(matches!(unary(Value::Str("1".to_string()), OpCode::Neg), Err(VMError::TypeMismatch { operation, .. }) if operation == "NEG"));
+
+    let (mut proc, _) = TinyProc::new(1, vec![OpCode::PushInt(5), OpCode::Neg, OpCode::PushFloat(1.5), OpCode::Neg, OpCode::Halt]);
+    proc.run_until_yield().unwrap();
+    assert_eq!(proc.stack, vec![Value::Int(-5), Value::Float(-1.5)]);
+}
+
+// Processes under the SMP scheduler, the default engine, run the same opcodes
+#[test]
+fn test_processes_do_the_same_math() {
+    let (mut proc, _) = TinyProc::new(1, vec![
+        OpCode::PushInt(2),
+        OpCode::PushInt(10),
+        OpCode::Pow,
+        OpCode::PushInt(16),
+        OpCode::Sqrt,
+        OpCode::PushInt(-7),
+        OpCode::Abs,
+        OpCode::PushFloat(2.5),
+        OpCode::Floor,
+        OpCode::PushFloat(2.5),
+        OpCode::Ceil,
+        OpCode::PushFloat(2.5),
+        OpCode::Round,
+        OpCode::Halt,
+    ]);
+    proc.run_until_yield().unwrap();
+    assert_eq!(proc.stack, vec![Value::Int(1024), Value::Float(4.0), Value::Int(7), Value::Float(2.0), Value::Float(3.0), Value::Float(3.0)]);
+
+    let (mut proc, _) = TinyProc::new(1, vec![OpCode::PushInt(2), OpCode::PushInt(63), OpCode::Pow, OpCode::Halt]);
+    assert!(matches!(proc.run_until_yield(), Err(VMError::IntegerOverflow { operation }) if operation == "POW"));
+}
@@ -0,0 +1,226 @@
+use std::fs;
+use std::process::Command;
+use tiny_tot_vm::bytecode::parse_source;
+use tiny_tot_vm::vm::{OutputBuffer, OutputSink, VM};
+use tiny_tot_vm::{OpCode, Value, VMError};
+
+const MATH_MODULE: &str = "examples/math_module.ttvm";
+
+// Run a .ttvm program through the CLI and return its stdout
+fn run_program(args: &[&str], path: &str) -> String {
+    let output = Command::new("cargo")
+        .args(["run", "--quiet", "--"])
+        .args(args)
+        .arg(path)
+        .output()
+        .expect("Failed to run command");
+    assert!(output.status.success(), "Program failed:\n{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn test_module_imported_by_two_paths_runs_once() {
+    let dir = std::env::temp_dir().join("ttvm_module_key");
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("counter.ttvm"), "PUSH_STR \"counter module loaded\"\nPRINT\nPUSH_INT 7\nSTORE seven\nEXPORT seven\nHALT\n").unwrap();
+
+    let dir = dir.to_string_lossy();
+    let main = format!(
+        "IMPORT {dir}/counter.ttvm\nIMPORT {dir}/./counter.ttvm\nIMPORT {dir}/sub/../counter.ttvm\nLOAD seven\nPRINT\nHALT\n"
+    );
+    let main_path = format!("{}/main.ttvm", dir);
+    fs::write(&main_path, main).unwrap();
+
+    let stdout = run_program(&["--no-smp"], &main_path);
+    assert_eq!(stdout.matches("counter module loaded").count(), 1, "Stack VM printed:\n{}", stdout);
+    assert!(stdout.lines().any(|line| line == "7"), "Exports missing:\n{}", stdout);
+
+    // The SMP scheduler's processes load modules on their own
+    let stdout = run_program(&[], &main_path);
+    assert_eq!(stdout.matches("counter module loaded").count(), 1, "SMP run printed:\n{}", stdout);
+}
+
+fn run_vm(program: Vec<OpCode>) -> (VM, Result<(), VMError>) {
+    let mut vm = VM::new(program);
+    let result = vm.run();
+    (vm, result)
+}
+
+#[test]
+fn test_import_as_binds_exports_under_prefix() {
+    let (vm, result) = run_vm(vec![
+        OpCode::ImportAs(MATH_MODULE.to_string(), "math".to_string()),
+        OpCode::PushInt(5),
+        OpCode::PushInt(3),
+        OpCode::Load("math.add".to_string()),
+        OpCode::CallFunction,
+        OpCode::Load("math.PI".to_string()),
+        OpCode::Halt,
+    ]);
+
+    result.expect("Program failed");
+    assert!(matches!(vm.stack[..], [Value::Int(8), Value::Float(_)]), "Got {:?}", vm.stack);
+    let mut names: Vec<&String> = vm.variables[0].keys().collect();
+    names.sort();
+    assert_eq!(names, ["math.PI", "math.add", "math.multiply", "math.square"]);
+}
+
+#[test]
+fn test_import_from_binds_only_named_exports() {
+    let (vm, result) = run_vm(vec![
+        OpCode::ImportFrom(MATH_MODULE.to_string(), vec!["square".to_string()]),
+        OpCode::PushFloat(3.0),
+        OpCode::Load("square".to_string()),
+        OpCode::CallFunction,
+        OpCode::Load("add".to_string()),
+        OpCode::Halt,
+    ]);
+
+    assert!(matches!(result, Err(VMError::UndefinedVariable(ref name)) if name == "add"), "Got {:?}", result);
+    assert_eq!(vm.stack, vec![Value::Float(9.0)]);
+    assert_eq!(vm.variables[0].keys().collect::<Vec<_>>(), ["square"]);
+}
+
+#[test]
+fn test_explicit_import_refuses_to_overwrite() {
+    let (vm, result) = run_vm(vec![
+        OpCode::PushStr("mine".to_string()),
+        OpCode::Store("square".to_string()),
+        OpCode::ImportFrom(MATH_MODULE.to_string(), vec!["square".to_string()]),
+        OpCode::Halt,
+    ]);
+    assert!(matches!(result, Err(VMError::RuntimeError(ref message)) if message.contains("square is already defined")), "Got {:?}", result);
+    assert_eq!(vm.variables[0].get("square"), Some(&Value::Str("mine".to_string())));
+
+    let (_, result) = run_vm(vec![OpCode::ImportFrom(MATH_MODULE.to_string(), vec!["cube".to_string()]), OpCode::Halt]);
+    assert!(matches!(result, Err(VMError::RuntimeError(ref message)) if message.contains("does not export cube")), "Got {:?}", result);
+}
+
+#[test]
+fn test_module_top_level_runs_once_in_merged_space() {
+    let dir = std::env::temp_dir().join("ttvm_module_once");
+    fs::create_dir_all(&dir).unwrap();
+    let module = dir.join("greeter.ttvm");
+    fs::write(&module, "PUSH_STR \"greeter loaded\"\nPRINT\nJMP skip\nLABEL greet\nPUSH_STR \"hello\"\nRET\nLABEL skip\nMAKE_FUNCTION greet\nSTORE greet\nEXPORT greet\nHALT\n").unwrap();
+
+    let mut vm = VM::new(vec![
+        OpCode::PushInt(1),
+        OpCode::Import(module.to_string_lossy().into_owned()),
+        OpCode::Load("greet".to_string()),
+        OpCode::CallFunction,
+        OpCode::Halt,
+    ]);
+    let output = OutputBuffer::new();
+    vm.set_output(OutputSink::new(output.clone()));
+    vm.run().expect("Program failed");
+
+    assert_eq!(output.contents(), "greeter loaded\n");
+    assert_eq!(vm.stack, vec![Value::Int(1), Value::Str("hello".to_string())]);
+    // The module was appended once and its function points into the appended code
+    assert_eq!(vm.instructions.len(), 5 + 9);
+    assert!(matches!(vm.variables[0].get("greet"), Some(Value::Function { addr, .. }) if *addr >= 5));
+}
+
+#[test]
+fn test_import_compiled_module() {
+    let dir = std::env::temp_dir().join("ttvm_module_ttb");
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("adder.ttvm");
+    let compiled = dir.join("adder.ttb");
+    fs::write(&source, "JMP skip\nLABEL add\nLOAD a\nLOAD b\nADD\nRET\nLABEL skip\nMAKE_FUNCTION add a b\nSTORE add\nEXPORT add\nHALT\n").unwrap();
+    let status = Command::new("cargo")
+        .args(["run", "--quiet", "--", "compile"])
+        .arg(&source)
+        .arg(&compiled)
+        .status()
+        .expect("Failed to run compiler");
+    assert!(status.success(), "Compiling the module failed");
+
+    // Found by extension, and by the bytecode header when the extension says nothing
+    let unlabelled = dir.join("adder.module");
+    fs::copy(&compiled, &unlabelled).unwrap();
+    for path in [&compiled, &unlabelled] {
+        let (vm, result) = run_vm(vec![
+            OpCode::PushInt(100),
+            OpCode::Import(path.to_string_lossy().into_owned()),
+            OpCode::PushInt(2),
+            OpCode::PushInt(3),
+            OpCode::Load("add".to_string()),
+            OpCode::CallFunction,
+            OpCode::Halt,
+        ]);
+        result.unwrap_or_else(|e| panic!("Importing {} failed: {}", path.display(), e));
+        assert_eq!(vm.stack, vec![Value::Int(100), Value::Int(5)]);
+    }
+}
+
+#[test]
+fn test_export_as_exports_function_value() {
+    let dir = std::env::temp_dir().join("ttvm_module_export_as");
+    fs::create_dir_all(&dir).unwrap();
+    let module = dir.join("doubler.ttvm");
+    fs::write(&module, "JMP skip\nLABEL double\nLOAD x\nLOAD x\nADD\nRET\nLABEL skip\nMAKE_FUNCTION double x\nEXPORT_AS double\nHALT\n").unwrap();
+    let main = dir.join("main.ttvm");
+    fs::write(&main, format!("IMPORT {}\nPUSH_INT 21\nLOAD double\nCALL_FUNCTION\nPRINT\nHALT\n", module.display())).unwrap();
+
+    let stdout = run_program(&["--no-smp"], &main.to_string_lossy());
+    assert_eq!(stdout.lines().last(), Some("42"), "Program printed:\n{}", stdout);
+
+    // The exported value never went through a variable
+    let (vm, result) = run_vm(vec![OpCode::Import(module.to_string_lossy().into_owned()), OpCode::Halt]);
+    result.expect("Program failed");
+    assert!(vm.variables[0].contains_key("double"));
+    assert!(vm.stack.is_empty());
+}
+
+#[test]
+fn test_reload_module_switches_new_calls_to_the_new_version() {
+    let dir = std::env::temp_dir().join("ttvm_module_reload");
+    fs::create_dir_all(&dir).unwrap();
+    let module = dir.join("versioned.ttvm");
+    let module_path = module.to_string_lossy();
+    // `run` calls back into the importer before answering with its version
+    let version_1 = "JMP skip\nLABEL version\nPUSH_INT 1\nRET\nLABEL run\nLOAD callback\nCALL_FUNCTION\nPUSH_INT 1\nRET\n\
+        LABEL skip\nMAKE_FUNCTION version\nEXPORT_AS version\nMAKE_FUNCTION run callback\nEXPORT_AS run\nHALT\n";
+    let version_2 = version_1.replace("PUSH_INT 1", "PUSH_INT 2");
+    fs::write(&module, version_1).unwrap();
+
+    // `upgrade` rewrites the module and reloads it while the first `run` is still in flight
+    let source = format!("\
+IMPORT {module_path}
+LOAD version
+STORE old_version
+MAKE_FUNCTION upgrade
+LOAD run
+CALL_FUNCTION
+LOAD version
+CALL_FUNCTION
+LOAD old_version
+CALL_FUNCTION
+MAKE_FUNCTION noop
+LOAD run
+CALL_FUNCTION
+HALT
+LABEL upgrade
+PUSH_STR \"{}\"
+PUSH_STR \"{module_path}\"
+WRITE_FILE
+RELOAD_MODULE {module_path}
+RET
+LABEL noop
+RET
+", version_2.replace('\n', "\\n"));
+    let (program, _) = parse_source(&source).expect("Failed to parse program");
+    let (vm, result) = run_vm(program);
+    result.expect("Program failed");
+
+    // The in-flight call finished on version 1 and the value taken before the reload still
+    // runs it; calls through the rebound names run version 2
+    assert_eq!(vm.stack, vec![Value::Int(1), Value::Int(2), Value::Int(1), Value::Int(2)]);
+
+    let (_, result) = run_vm(vec![OpCode::ReloadModule(module_path.into_owned()), OpCode::Halt]);
+    match result {
+        Err(VMError::RuntimeError(message)) => assert!(message.contains("has not been imported"), "{}", message),
+        other => panic!("Expected a reload of an unimported module to fail, got {:?}", other),
+    }
+}
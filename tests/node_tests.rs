@@ -0,0 +1,110 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crossbeam::channel::{unbounded, Receiver};
+use indexmap::IndexMap;
+use tiny_tot_vm::concurrency::node::send_remote;
+use tiny_tot_vm::concurrency::{Message, NodeListener, ProcessRegistry, SchedulerPool};
+use tiny_tot_vm::{OpCode, Value};
+
+// A node on a free loopback port with one name registered, whose messages land in the
+// returned channel instead of a running process
+fn node_with_inbox(name: &str, pid: u64) -> (NodeListener, Receiver<Message>) {
+    let registry = Arc::new(Mutex::new(ProcessRegistry::new()));
+    let (sender, receiver) = unbounded();
+    {
+        let mut registry = registry.lock().unwrap();
+        registry.register_process(pid, sender).unwrap();
+        registry.register_name(name.to_string(), pid).unwrap();
+    }
+    let node = NodeListener::start("127.0.0.1:0", registry).expect("Failed to start node");
+    (node, receiver)
+}
+
+fn received_value(inbox: &Receiver<Message>) -> Value {
+    match inbox.recv_timeout(Duration::from_secs(5)).expect("No message arrived") {
+        Message::Value(value) => value,
+        other => panic!("Expected a value message, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_two_nodes_exchange_messages() {
+    let (node_a, replies) = node_with_inbox("replies", 900);
+    let (node_b, inbox) = node_with_inbox("inbox", 901);
+
+    // A process on node A sends to `inbox` on node B
+    let target = format!("inbox@{}", node_b.address);
+    let mut pool = SchedulerPool::new_with_threads(1);
+    pool.spawn_process(vec![
+        OpCode::PushStr("ping".to_string()),
+        OpCode::SendNamed(target.clone()),
+        OpCode::Halt,
+    ]);
+    pool.run().unwrap();
+    pool.wait_for_completion();
+    assert_eq!(received_value(&inbox), Value::Str("ping".to_string()));
+
+    // Structured values survive the trip with their field order
+    let message = Value::Object(IndexMap::from([
+        ("kind".to_string(), Value::Str("batch".to_string())),
+        ("payload".to_string(), Value::List(vec![Value::Int(-1), Value::Float(2.5), Value::Null, Value::Bool(true)].into())),
+        ("meta".to_string(), Value::Object(IndexMap::from([("from".to_string(), Value::Str("a".to_string()))]).into())),
+    ]).into());
+    send_remote(&target, &message).expect("Sending the object failed");
+    let Value::Object(received) = received_value(&inbox) else { panic!("Expected an object") };
+    assert_eq!(Value::Object(received.clone()), message);
+    assert_eq!(received.keys().collect::<Vec<_>>(), ["kind", "payload", "meta"]);
+
+    // Node B answers node A
+    send_remote(&format!("replies@{}", node_a.address), &Value::Str("pong".to_string())).expect("Reply failed");
+    assert_eq!(received_value(&replies), Value::Str("pong".to_string()));
+
+    node_a.shutdown();
+    node_b.shutdown();
+}
+
+#[test]
+fn test_remote_message_wakes_receiving_process() {
+    let (node_a, replies) = node_with_inbox("replies", 903);
+
+    // Node B runs a process that waits for one message and forwards it to node A
+    let mut pool = SchedulerPool::new_with_threads(1);
+    let node_b = NodeListener::start("127.0.0.1:0", pool.process_registry.clone()).expect("Failed to start node");
+    pool.spawn_process(vec![
+        OpCode::Register("echo".to_string()),
+        OpCode::Receive,
+        OpCode::SendNamed(format!("replies@{}", node_a.address)),
+        OpCode::Halt,
+    ]);
+
+    // The process may not have registered yet when the first sends arrive
+    let target = format!("echo@{}", node_b.address);
+    let mut attempts = 0;
+    while let Err(error) = send_remote(&target, &Value::Int(42)) {
+        attempts += 1;
+        assert!(attempts < 200, "Node B never accepted the message: {}", error);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    pool.run().unwrap();
+    pool.wait_for_completion();
+    assert_eq!(received_value(&replies), Value::Int(42));
+
+    node_a.shutdown();
+    node_b.shutdown();
+}
+
+#[test]
+fn test_remote_send_failures_are_reported() {
+    let (node, _inbox) = node_with_inbox("inbox", 902);
+
+    let error = send_remote(&format!("nobody@{}", node.address), &Value::Int(1)).unwrap_err();
+    assert!(error.contains("nobody"), "Unexpected error: {}", error);
+
+    let error = send_remote(&format!("inbox@{}", node.address), &Value::Connection("db:x".to_string())).unwrap_err();
+    assert!(error.contains("cannot serialize"), "Unexpected error: {}", error);
+
+    // Nothing listens on a port that was just released
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let error = send_remote(&format!("inbox@127.0.0.1:{}", port), &Value::Int(1)).unwrap_err();
+    assert!(error.contains("cannot reach node"), "Unexpected error: {}", error);
+}
@@ -0,0 +1,379 @@
+use tiny_tot_vm::optimizer::{OptimizationOptions, Optimizer};
+use tiny_tot_vm::vm::VM;
+use tiny_tot_vm::{OpCode, Value};
+
+fn no_passes() -> OptimizationOptions {
+    OptimizationOptions {
+        dead_code_elimination: false,
+        constant_folding: false,
+        tail_call_optimization: false,
+        memory_layout_optimization: false,
+        peephole_optimizations: false,
+        constant_propagation: false,
+        instruction_combining: false,
+        jump_threading: false,
+        common_subexpression_elimination: false,
+        function_inlining: false,
+        dead_store_elimination: false,
+    }
+}
+
+fn only_cse() -> OptimizationOptions {
+    OptimizationOptions { common_subexpression_elimination: true, ..no_passes() }
+}
+
+fn store(name: &str, value: i64) -> [OpCode; 2] {
+    [OpCode::PushInt(value), OpCode::Store(name.to_string())]
+}
+
+// (a + b) * c
+fn expression() -> [OpCode; 5] {
+    [
+        OpCode::Load("a".to_string()),
+        OpCode::Load("b".to_string()),
+        OpCode::Add,
+        OpCode::Load("c".to_string()),
+        OpCode::Mul,
+    ]
+}
+
+fn global(vm: &VM, name: &str) -> Option<Value> {
+    vm.variables[0].get(name).cloned()
+}
+
+fn run(program: Vec<OpCode>) -> VM {
+    let mut vm = VM::new(program);
+    vm.run().expect("Program failed");
+    vm
+}
+
+#[test]
+fn test_cse_computes_a_repeated_expression_once() {
+    let mut program: Vec<OpCode> = [store("a", 2), store("b", 3), store("c", 4)].concat();
+    for result in ["r1", "r2", "r3"] {
+        program.extend(expression());
+        program.push(OpCode::Store(result.to_string()));
+    }
+    program.push(OpCode::Halt);
+
+    let (optimized, stats) = Optimizer::new(only_cse()).optimize(program.clone());
+    assert_eq!(stats.common_subexpressions_eliminated, 2);
+    // Two reuses save four instructions each; keeping the first result costs DUP and STORE
+    assert_eq!(optimized.len(), program.len() - 6);
+
+    let (plain, optimized) = (run(program), run(optimized));
+    for result in ["r1", "r2", "r3"] {
+        assert_eq!(global(&optimized, result), Some(Value::Int(20)));
+        assert_eq!(global(&optimized, result), global(&plain, result));
+    }
+}
+
+#[test]
+fn test_cse_respects_stores_to_operands() {
+    let mut program: Vec<OpCode> = [store("a", 2), store("b", 3), store("c", 4)].concat();
+    program.extend(expression());
+    program.push(OpCode::Store("r1".to_string()));
+    program.extend(store("a", 10));
+    for result in ["r2", "r3"] {
+        program.extend(expression());
+        program.push(OpCode::Store(result.to_string()));
+    }
+    program.push(OpCode::Halt);
+
+    let (optimized, stats) = Optimizer::new(only_cse()).optimize(program.clone());
+    // Only r2 and r3 share a value
+    assert_eq!(stats.common_subexpressions_eliminated, 1);
+    assert!(optimized.len() < program.len());
+
+    let vm = run(optimized);
+    assert_eq!(global(&vm, "r1"), Some(Value::Int(20)));
+    assert_eq!(global(&vm, "r2"), Some(Value::Int(52)));
+    assert_eq!(global(&vm, "r3"), Some(Value::Int(52)));
+}
+
+#[test]
+fn test_cse_keeps_jump_targets_pointing_at_the_same_code() {
+    let mut program: Vec<OpCode> = [store("a", 2), store("b", 3), store("c", 4)].concat();
+    program.extend(expression());
+    program.push(OpCode::Store("r1".to_string()));
+    program.extend(expression());
+    program.push(OpCode::Store("r2".to_string()));
+    let skip_to = program.len() + 3;
+    program.extend([
+        OpCode::Jmp(skip_to),
+        OpCode::PushStr("skipped".to_string()),
+        OpCode::Store("missed".to_string()),
+        OpCode::PushStr("reached".to_string()), // skip_to
+        OpCode::Store("landed".to_string()),
+        OpCode::Halt,
+    ]);
+
+    let (optimized, stats) = Optimizer::new(only_cse()).optimize(program.clone());
+    assert_eq!(stats.common_subexpressions_eliminated, 1);
+    assert_eq!(optimized.len(), program.len() - 2);
+
+    let vm = run(optimized);
+    assert_eq!(global(&vm, "r2"), Some(Value::Int(20)));
+    assert_eq!(global(&vm, "missed"), None);
+    assert_eq!(global(&vm, "landed"), Some(Value::Str("reached".to_string())));
+}
+
+#[test]
+fn test_cse_does_not_reuse_across_basic_blocks() {
+    let mut program: Vec<OpCode> = [store("a", 2), store("b", 3), store("c", 4)].concat();
+    for result in ["r1", "r2"] {
+        program.extend(expression());
+        program.push(OpCode::Store(result.to_string()));
+    }
+    // The third copy starts a block that a jump can reach
+    let target = program.len() + 1;
+    program.push(OpCode::Jmp(target));
+    program.extend(expression());
+    program.push(OpCode::Store("r3".to_string()));
+    program.push(OpCode::Halt);
+
+    let (_, stats) = Optimizer::new(only_cse()).optimize(program);
+    assert_eq!(stats.common_subexpressions_eliminated, 1);
+}
+
+fn call(addr: usize, params: &[&str]) -> OpCode {
+    OpCode::Call { addr, params: params.iter().map(|p| p.to_string()).collect() }
+}
+
+#[test]
+fn test_inlining_replaces_calls_to_a_small_adder() {
+    let program = vec![
+        OpCode::PushInt(2),
+        OpCode::PushInt(3),
+        call(9, &["a", "b"]),
+        OpCode::Store("r1".to_string()),
+        OpCode::PushInt(10),
+        OpCode::PushInt(20),
+        call(9, &["a", "b"]),
+        OpCode::Store("r2".to_string()),
+        OpCode::Halt,
+        OpCode::Load("a".to_string()), // 9: add(a, b)
+        OpCode::Load("b".to_string()),
+        OpCode::Add,
+        OpCode::Ret,
+    ];
+    let options = OptimizationOptions { function_inlining: true, dead_code_elimination: true, ..no_passes() };
+    let (optimized, stats) = Optimizer::new(options).optimize(program.clone());
+
+    assert_eq!(stats.inlined_calls, 2);
+    assert!(!optimized.iter().any(|op| matches!(op, OpCode::Call { .. } | OpCode::Ret)), "{:?}", optimized);
+
+    let (plain, optimized) = (run(program), run(optimized));
+    assert_eq!(global(&optimized, "r1"), Some(Value::Int(5)));
+    assert_eq!(global(&optimized, "r2"), Some(Value::Int(30)));
+    assert_eq!(global(&optimized, "r2"), global(&plain, "r2"));
+    // Parameters never land in the caller's scope under their own names
+    assert_eq!(global(&optimized, "a"), None);
+}
+
+#[test]
+fn test_inlining_remaps_jumps_inside_the_callee() {
+    let program = vec![
+        OpCode::PushInt(5),
+        call(11, &["x"]),
+        OpCode::Store("positive".to_string()),
+        OpCode::PushInt(-3),
+        call(11, &["x"]),
+        OpCode::Store("negative".to_string()),
+        OpCode::Jmp(8),
+        OpCode::Halt,
+        OpCode::PushStr("after".to_string()), // 8
+        OpCode::Store("done".to_string()),
+        OpCode::Halt,
+        OpCode::PushInt(0), // 11: relu(x) = if 0 < x { x } else { 0 }
+        OpCode::Load("x".to_string()),
+        OpCode::Lt,
+        OpCode::Jz(17),
+        OpCode::Load("x".to_string()),
+        OpCode::Jmp(18),
+        OpCode::PushInt(0), // 17
+        OpCode::Ret,        // 18
+    ];
+
+    let options = OptimizationOptions { function_inlining: true, ..no_passes() };
+    let (optimized, stats) = Optimizer::new(options).optimize(program.clone());
+    assert_eq!(stats.inlined_calls, 2);
+
+    let vm = run(optimized);
+    assert_eq!(global(&vm, "positive"), Some(Value::Int(5)));
+    assert_eq!(global(&vm, "negative"), Some(Value::Int(0)));
+    assert_eq!(global(&vm, "done"), Some(Value::Str("after".to_string())));
+}
+
+#[test]
+fn test_inlining_skips_recursive_and_global_reading_functions() {
+    let program = vec![
+        OpCode::PushInt(1),
+        call(5, &["n"]),
+        OpCode::PushInt(1),
+        call(8, &["n"]),
+        OpCode::Halt,
+        OpCode::Load("n".to_string()), // 5: calls itself
+        call(5, &["n"]),
+        OpCode::Ret,
+        OpCode::Load("n".to_string()), // 8: reads a global
+        OpCode::Load("scale".to_string()),
+        OpCode::Mul,
+        OpCode::Ret,
+    ];
+    let options = OptimizationOptions { function_inlining: true, ..no_passes() };
+    let (optimized, stats) = Optimizer::new(options).optimize(program.clone());
+
+    assert_eq!(stats.inlined_calls, 0);
+    assert_eq!(optimized, program);
+}
+
+fn only_dse() -> OptimizationOptions {
+    OptimizationOptions { dead_store_elimination: true, ..no_passes() }
+}
+
+#[test]
+fn test_dse_removes_a_shadowed_store_and_its_expression() {
+    let mut program: Vec<OpCode> = store("a", 2).to_vec();
+    program.extend([
+        OpCode::Load("a".to_string()),
+        OpCode::PushInt(10),
+        OpCode::Mul,
+        OpCode::Store("x".to_string()), // overwritten before anyone reads it
+    ]);
+    program.extend(store("x", 7));
+    program.extend([OpCode::Load("x".to_string()), OpCode::Store("y".to_string())]);
+    program.extend([OpCode::PushInt(3), call(14, &["n"]), OpCode::Store("r".to_string()), OpCode::Halt]);
+    program.extend([
+        OpCode::Load("n".to_string()), // 14: the local is dropped by RET
+        OpCode::PushInt(1),
+        OpCode::Add,
+        OpCode::Store("unused".to_string()),
+        OpCode::Load("n".to_string()),
+        OpCode::Ret,
+    ]);
+
+    let (optimized, stats) = Optimizer::new(only_dse()).optimize(program.clone());
+    assert_eq!(stats.dead_stores_removed, 2);
+    assert_eq!(optimized.len(), program.len() - 8);
+
+    let vm = run(optimized);
+    assert_eq!(global(&vm, "x"), Some(Value::Int(7)));
+    assert_eq!(global(&vm, "y"), Some(Value::Int(7)));
+    assert_eq!(global(&vm, "r"), Some(Value::Int(3)));
+}
+
+#[test]
+fn test_dse_keeps_stores_to_exported_variables() {
+    let mut program: Vec<OpCode> = [store("version", 1), store("version", 2)].concat();
+    program.extend([OpCode::Export("version".to_string()), OpCode::Halt]);
+
+    let (optimized, stats) = Optimizer::new(only_dse()).optimize(program.clone());
+    assert_eq!(stats.dead_stores_removed, 0);
+    assert_eq!(optimized, program);
+    assert_eq!(run(optimized).exports.get("version"), Some(&Value::Int(2)));
+}
+
+#[test]
+fn test_dse_keeps_globals_a_call_can_read() {
+    let mut program: Vec<OpCode> = store("g", 1).to_vec();
+    program.extend([call(7, &[]), OpCode::Store("seen".to_string())]);
+    program.extend(store("g", 2));
+    program.push(OpCode::Halt);
+    program.extend([OpCode::Load("g".to_string()), OpCode::Ret]); // 7: reads the global
+
+    let (optimized, stats) = Optimizer::new(only_dse()).optimize(program.clone());
+    assert_eq!(stats.dead_stores_removed, 0);
+    assert_eq!(global(&run(optimized), "seen"), Some(Value::Int(1)));
+}
+
+fn only_peephole() -> OptimizationOptions {
+    OptimizationOptions { peephole_optimizations: true, ..no_passes() }
+}
+
+#[test]
+fn test_peephole_collapses_constant_arithmetic_across_dup() {
+    let program = vec![
+        OpCode::PushInt(4),
+        OpCode::Dup,
+        OpCode::Mul,
+        OpCode::PushInt(3),
+        OpCode::Add,
+        OpCode::PushInt(2),
+        OpCode::Sub,
+        OpCode::Store("x".to_string()),
+        OpCode::Halt,
+    ];
+    let (optimized, stats) = Optimizer::new(only_peephole()).optimize(program.clone());
+
+    assert_eq!(stats.peephole_optimizations_applied, 3);
+    assert_eq!(optimized, vec![OpCode::PushInt(17), OpCode::Store("x".to_string()), OpCode::Halt]);
+    assert_eq!(global(&run(optimized), "x"), global(&run(program), "x"));
+}
+
+#[test]
+fn test_peephole_keeps_jumps_on_the_same_code() {
+    let mut program = vec![OpCode::PushInt(2), OpCode::Dup, OpCode::Add, OpCode::Store("a".to_string())];
+    program.extend([
+        OpCode::Jmp(7),
+        OpCode::PushStr("skipped".to_string()),
+        OpCode::Store("missed".to_string()),
+        OpCode::PushStr("reached".to_string()), // 7
+        OpCode::Store("landed".to_string()),
+        OpCode::Halt,
+    ]);
+    let (optimized, stats) = Optimizer::new(only_peephole()).optimize(program);
+    assert_eq!(stats.peephole_optimizations_applied, 1);
+
+    let vm = run(optimized);
+    assert_eq!(global(&vm, "a"), Some(Value::Int(4)));
+    assert_eq!(global(&vm, "missed"), None);
+    assert_eq!(global(&vm, "landed"), Some(Value::Str("reached".to_string())));
+}
+
+#[test]
+fn test_peephole_does_not_collapse_into_a_loop_head() {
+    let program = vec![
+        OpCode::PushInt(0),
+        OpCode::PushInt(5), // 1: loop adding 5 until the total reaches 15
+        OpCode::Add,
+        OpCode::Dup,
+        OpCode::PushInt(15),
+        OpCode::Lt,
+        OpCode::Jz(8),
+        OpCode::Jmp(1),
+        OpCode::Store("total".to_string()), // 8
+        OpCode::Halt,
+    ];
+    let (optimized, stats) = Optimizer::new(only_peephole()).optimize(program.clone());
+    assert_eq!(stats.peephole_optimizations_applied, 0);
+    assert_eq!(optimized, program);
+    assert_eq!(global(&run(optimized), "total"), Some(Value::Int(15)));
+}
+
+#[test]
+fn test_code_after_jmp_and_halt_is_reported_and_removed() {
+    let program = vec![
+        OpCode::PushInt(1),
+        OpCode::Jmp(4),
+        OpCode::PushInt(2), // 2: skipped by the JMP
+        OpCode::Print,
+        OpCode::Store("x".to_string()), // 4
+        OpCode::MakeFunction { addr: 8, params: vec![], returns: None },
+        OpCode::Store("f".to_string()),
+        OpCode::Halt,
+        OpCode::Ret, // 8: only reachable through the function pointer
+        OpCode::Print, // 9: after RET
+    ];
+    let optimizer = Optimizer::new(no_passes());
+    assert_eq!(optimizer.unreachable_instructions(&program), vec![2, 3, 9]);
+
+    let options = OptimizationOptions { dead_code_elimination: true, ..no_passes() };
+    let (optimized, stats) = Optimizer::new(options).optimize(program.clone());
+    assert_eq!(stats.unreachable_instructions, 3);
+    assert_eq!(stats.dead_instructions_removed, 3);
+    // Addresses follow the instructions they pointed at
+    assert_eq!(optimized[1], OpCode::Jmp(2));
+    assert_eq!(optimized[3], OpCode::MakeFunction { addr: 6, params: vec![], returns: None });
+    assert_eq!(global(&run(optimized), "x"), Some(Value::Int(1)));
+}
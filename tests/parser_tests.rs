@@ -0,0 +1,647 @@
+use std::fs;
+use tiny_tot_vm::bytecode::{opcode_to_text, parse_program, MNEMONICS};
+use tiny_tot_vm::{DestructurePattern, MessagePattern, OpCode, Value, VMError};
+
+// One instance of every opcode. Extend this list (and `ensure_variant_listed`)
+// whenever a new opcode is added.
+fn every_opcode() -> Vec<OpCode> {
+    vec![
+        OpCode::PushInt(-42),
+        OpCode::PushFloat(2.5),
+        OpCode::PushStr("say \"hi\"\n\tback\\slash\r".to_string()),
+        OpCode::PushBool(true),
+        OpCode::Add,
+        OpCode::AddF,
+        OpCode::Sub,
+        OpCode::SubF,
+        OpCode::Mul,
+        OpCode::MulF,
+        OpCode::Div,
+        OpCode::DivF,
+        OpCode::Neg,
+        OpCode::Pow,
+        OpCode::Sqrt,
+        OpCode::Abs,
+        OpCode::Floor,
+        OpCode::Ceil,
+        OpCode::Round,
+        OpCode::Concat,
+        OpCode::Print,
+        OpCode::PrintRaw,
+        OpCode::PrintErr,
+        OpCode::DebugPrint,
+        OpCode::Assert(None),
+        OpCode::Assert(Some("x is \"positive\"".to_string())),
+        OpCode::Halt,
+        OpCode::Jmp(3),
+        OpCode::Jz(7),
+        OpCode::Call { addr: 5, params: vec!["a".to_string(), "b".to_string()] },
+        OpCode::Ret,
+        OpCode::RetN(2),
+        OpCode::Dup,
+        OpCode::Store("x".to_string()),
+        OpCode::Load("x".to_string()),
+        OpCode::Delete("x".to_string()),
+        OpCode::Eq,
+        OpCode::Ne,
+        OpCode::Gt,
+        OpCode::Lt,
+        OpCode::Ge,
+        OpCode::Le,
+        OpCode::EqF,
+        OpCode::NeF,
+        OpCode::GtF,
+        OpCode::LtF,
+        OpCode::GeF,
+        OpCode::LeF,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Not,
+        OpCode::And,
+        OpCode::Or,
+        OpCode::Null,
+        OpCode::MakeList(3),
+        OpCode::Len,
+        OpCode::Index,
+        OpCode::DumpScope,
+        OpCode::ReadFile,
+        OpCode::WriteFile,
+        OpCode::ReadLine,
+        OpCode::ReadChar,
+        OpCode::ReadInput,
+        OpCode::AppendFile,
+        OpCode::FileExists,
+        OpCode::FileSize,
+        OpCode::DeleteFile,
+        OpCode::ListDir,
+        OpCode::ListDirRecursive,
+        OpCode::Stat,
+        OpCode::ReadBytes,
+        OpCode::WriteBytes,
+        OpCode::GetCwd,
+        OpCode::SetCwd,
+        OpCode::PathJoin,
+        OpCode::PathBasename,
+        OpCode::PathDirname,
+        OpCode::GetEnv,
+        OpCode::SetEnv,
+        OpCode::GetArgs,
+        OpCode::EnvVars,
+        OpCode::Exec,
+        OpCode::ExecCapture,
+        OpCode::Exit,
+        OpCode::GetTime,
+        OpCode::Sleep,
+        OpCode::FormatTime,
+        OpCode::ParseTime,
+        OpCode::RandomInt,
+        OpCode::RandomFloat,
+        OpCode::RandomSeed,
+        OpCode::Uuid,
+        OpCode::HttpGet,
+        OpCode::HttpPost,
+        OpCode::HttpServe,
+        OpCode::HttpAccept,
+        OpCode::HttpRespond,
+        OpCode::TcpConnect,
+        OpCode::TcpListen,
+        OpCode::TcpSend,
+        OpCode::TcpRecv,
+        OpCode::UdpBind,
+        OpCode::UdpSend,
+        OpCode::UdpRecv,
+        OpCode::DnsResolve,
+        OpCode::AsyncRead,
+        OpCode::AsyncWrite,
+        OpCode::Await,
+        OpCode::StreamCreate,
+        OpCode::StreamJsonLines,
+        OpCode::StreamCsv,
+        OpCode::StreamRead,
+        OpCode::StreamWrite,
+        OpCode::StreamClose,
+        OpCode::JsonParse,
+        OpCode::JsonStringify,
+        OpCode::CsvParse,
+        OpCode::CsvWrite,
+        OpCode::RegexMatch,
+        OpCode::RegexFind,
+        OpCode::RegexReplace,
+        OpCode::Compress,
+        OpCode::Decompress,
+        OpCode::Encrypt,
+        OpCode::Decrypt,
+        OpCode::Hash,
+        OpCode::DbConnect,
+        OpCode::DbQuery,
+        OpCode::DbExec,
+        OpCode::MakeObject,
+        OpCode::SetField("name".to_string()),
+        OpCode::GetField("name".to_string()),
+        OpCode::HasField("name".to_string()),
+        OpCode::DeleteField("name".to_string()),
+        OpCode::Keys,
+        OpCode::SetFieldMut { var: "person".to_string(), field: "name".to_string() },
+        OpCode::Values,
+        OpCode::Entries,
+        OpCode::MakeFunction { addr: 9, params: vec!["n".to_string()], returns: None },
+        OpCode::MakeFunction { addr: 9, params: vec!["n:int".to_string(), "...rest:number".to_string()], returns: Some("list".to_string()) },
+        OpCode::CallFunction,
+        OpCode::MakeLambda { addr: 11, params: vec![] },
+        OpCode::Capture("y".to_string()),
+        OpCode::WeakRef,
+        OpCode::WeakGet,
+        OpCode::Try { catch_addr: 4 },
+        OpCode::Catch,
+        OpCode::Throw,
+        OpCode::Rethrow,
+        OpCode::EndTry,
+        OpCode::Finally,
+        OpCode::EndFinally,
+        OpCode::ExceptionMessage,
+        OpCode::ExceptionTrace,
+        OpCode::Import("examples/math_module.ttvm".to_string()),
+        OpCode::ImportAs("examples/math_module.ttvm".to_string(), "math".to_string()),
+        OpCode::ImportFrom("examples/math_module.ttvm".to_string(), vec!["square".to_string(), "cube".to_string()]),
+        OpCode::Export("square".to_string()),
+        OpCode::ExportAs("square".to_string()),
+        OpCode::ReloadModule("examples/math_module.ttvm".to_string()),
+        OpCode::Destructure(DestructurePattern::Object(vec![
+            ("user".to_string(), DestructurePattern::Object(vec![("name".to_string(), DestructurePattern::Bind("name".to_string()))])),
+            ("tags".to_string(), DestructurePattern::List(vec![DestructurePattern::Bind("_".to_string())], Some("rest".to_string()))),
+            ("id".to_string(), DestructurePattern::Bind("user_id".to_string())),
+        ])),
+        OpCode::Spawn,
+        OpCode::SpawnRegistered("service".to_string()),
+        OpCode::Receive,
+        OpCode::ReceiveMatch(vec![
+            MessagePattern::Any,
+            MessagePattern::Value(Value::Int(7)),
+            MessagePattern::Value(Value::Str("two words".to_string())),
+            MessagePattern::Value(Value::Float(1.0)),
+            MessagePattern::Value(Value::Bool(false)),
+            MessagePattern::Value(Value::Null),
+            MessagePattern::Signal("shutdown".to_string()),
+            MessagePattern::Exit(None),
+            MessagePattern::Exit(Some(3)),
+            MessagePattern::Down(None, None),
+            MessagePattern::Down(Some(4), None),
+            MessagePattern::Down(None, Some("normal".to_string())),
+            MessagePattern::Link(Some(5)),
+            MessagePattern::Type("int".to_string()),
+            MessagePattern::Guard("ready".to_string()),
+        ]),
+        OpCode::ReceiveMatchAfter(vec![MessagePattern::Type("int".to_string())], 250),
+        OpCode::Yield,
+        OpCode::SelfPid,
+        OpCode::ParentPid,
+        OpCode::Send(2),
+        OpCode::Monitor(3),
+        OpCode::Demonitor("ref_1".to_string()),
+        OpCode::Link(4),
+        OpCode::Unlink(5),
+        OpCode::TrapExit,
+        OpCode::ProcCall,
+        OpCode::ProcReply,
+        OpCode::PutDict,
+        OpCode::GetDict,
+        OpCode::EraseDict,
+        OpCode::Broadcast,
+        OpCode::SendAfter,
+        OpCode::CancelTimer,
+        OpCode::TableNew,
+        OpCode::TableInsert,
+        OpCode::TableLookup,
+        OpCode::TableDelete,
+        OpCode::Register("worker".to_string()),
+        OpCode::Unregister("worker".to_string()),
+        OpCode::Whereis("worker".to_string()),
+        OpCode::SendNamed("worker".to_string()),
+        OpCode::StartSupervisor,
+        OpCode::SuperviseChild("child".to_string()),
+        OpCode::RestartChild("child".to_string()),
+    ]
+}
+
+// Fails to compile when an opcode is added without updating `every_opcode`
+fn ensure_variant_listed(op: &OpCode) {
+    match op {
+        OpCode::PushInt(_) => {}
+        OpCode::PushFloat(_) => {}
+        OpCode::PushStr(_) => {}
+        OpCode::PushBool(_) => {}
+        OpCode::Add => {}
+        OpCode::AddF => {}
+        OpCode::Sub => {}
+        OpCode::SubF => {}
+        OpCode::Mul => {}
+        OpCode::MulF => {}
+        OpCode::Div => {}
+        OpCode::DivF => {}
+        OpCode::Neg => {}
+        OpCode::Pow => {}
+        OpCode::Sqrt => {}
+        OpCode::Abs => {}
+        OpCode::Floor => {}
+        OpCode::Ceil => {}
+        OpCode::Round => {}
+        OpCode::Concat => {}
+        OpCode::Print => {}
+        OpCode::PrintRaw => {}
+        OpCode::PrintErr => {}
+        OpCode::DebugPrint => {}
+        OpCode::Assert(_) => {}
+        OpCode::Halt => {}
+        OpCode::Jmp(_) => {}
+        OpCode::Jz(_) => {}
+        OpCode::Call { .. } => {}
+        OpCode::Ret => {}
+        OpCode::RetN(_) => {}
+        OpCode::Dup => {}
+        OpCode::Store(_) => {}
+        OpCode::Load(_) => {}
+        OpCode::Delete(_) => {}
+        OpCode::Eq => {}
+        OpCode::Ne => {}
+        OpCode::Gt => {}
+        OpCode::Lt => {}
+        OpCode::Ge => {}
+        OpCode::Le => {}
+        OpCode::EqF => {}
+        OpCode::NeF => {}
+        OpCode::GtF => {}
+        OpCode::LtF => {}
+        OpCode::GeF => {}
+        OpCode::LeF => {}
+        OpCode::True => {}
+        OpCode::False => {}
+        OpCode::Not => {}
+        OpCode::And => {}
+        OpCode::Or => {}
+        OpCode::Null => {}
+        OpCode::MakeList(_) => {}
+        OpCode::Len => {}
+        OpCode::Index => {}
+        OpCode::DumpScope => {}
+        OpCode::ReadFile => {}
+        OpCode::WriteFile => {}
+        OpCode::ReadLine => {}
+        OpCode::ReadChar => {}
+        OpCode::ReadInput => {}
+        OpCode::AppendFile => {}
+        OpCode::FileExists => {}
+        OpCode::FileSize => {}
+        OpCode::DeleteFile => {}
+        OpCode::ListDir => {}
+        OpCode::ListDirRecursive => {}
+        OpCode::Stat => {}
+        OpCode::ReadBytes => {}
+        OpCode::WriteBytes => {}
+        OpCode::GetCwd => {}
+        OpCode::SetCwd => {}
+        OpCode::PathJoin => {}
+        OpCode::PathBasename => {}
+        OpCode::PathDirname => {}
+        OpCode::GetEnv => {}
+        OpCode::SetEnv => {}
+        OpCode::GetArgs => {}
+        OpCode::EnvVars => {}
+        OpCode::Exec => {}
+        OpCode::ExecCapture => {}
+        OpCode::Exit => {}
+        OpCode::GetTime => {}
+        OpCode::Sleep => {}
+        OpCode::FormatTime => {}
+        OpCode::ParseTime => {}
+        OpCode::RandomInt => {}
+        OpCode::RandomFloat => {}
+        OpCode::RandomSeed => {}
+        OpCode::Uuid => {}
+        OpCode::HttpGet => {}
+        OpCode::HttpPost => {}
+        OpCode::HttpServe => {}
+        OpCode::HttpAccept => {}
+        OpCode::HttpRespond => {}
+        OpCode::TcpConnect => {}
+        OpCode::TcpListen => {}
+        OpCode::TcpSend => {}
+        OpCode::TcpRecv => {}
+        OpCode::UdpBind => {}
+        OpCode::UdpSend => {}
+        OpCode::UdpRecv => {}
+        OpCode::DnsResolve => {}
+        OpCode::AsyncRead => {}
+        OpCode::AsyncWrite => {}
+        OpCode::Await => {}
+        OpCode::StreamCreate => {}
+        OpCode::StreamJsonLines => {}
+        OpCode::StreamCsv => {}
+        OpCode::StreamRead => {}
+        OpCode::StreamWrite => {}
+        OpCode::StreamClose => {}
+        OpCode::JsonParse => {}
+        OpCode::JsonStringify => {}
+        OpCode::CsvParse => {}
+        OpCode::CsvWrite => {}
+        OpCode::RegexMatch => {}
+        OpCode::RegexFind => {}
+        OpCode::RegexReplace => {}
+        OpCode::Compress => {}
+        OpCode::Decompress => {}
+        OpCode::Encrypt => {}
+        OpCode::Decrypt => {}
+        OpCode::Hash => {}
+        OpCode::DbConnect => {}
+        OpCode::DbQuery => {}
+        OpCode::DbExec => {}
+        OpCode::MakeObject => {}
+        OpCode::SetField(_) => {}
+        OpCode::GetField(_) => {}
+        OpCode::HasField(_) => {}
+        OpCode::DeleteField(_) => {}
+        OpCode::Keys => {}
+        OpCode::SetFieldMut { .. } => {}
+        OpCode::Values => {}
+        OpCode::Entries => {}
+        OpCode::MakeFunction { .. } => {}
+        OpCode::CallFunction => {}
+        OpCode::MakeLambda { .. } => {}
+        OpCode::Capture(_) => {}
+        OpCode::WeakRef => {}
+        OpCode::WeakGet => {}
+        OpCode::Try { .. } => {}
+        OpCode::Catch => {}
+        OpCode::Throw => {}
+        OpCode::Rethrow => {}
+        OpCode::EndTry => {}
+        OpCode::Finally => {}
+        OpCode::EndFinally => {}
+        OpCode::ExceptionMessage => {}
+        OpCode::ExceptionTrace => {}
+        OpCode::Import(_) => {}
+        OpCode::ImportAs(..) => {}
+        OpCode::ImportFrom(..) => {}
+        OpCode::Export(_) => {}
+        OpCode::ExportAs(_) => {}
+        OpCode::ReloadModule(_) => {}
+        OpCode::Destructure(_) => {}
+        OpCode::Spawn => {}
+        OpCode::SpawnRegistered(_) => {}
+        OpCode::Receive => {}
+        OpCode::ReceiveMatch(_) => {}
+        OpCode::ReceiveMatchAfter(..) => {}
+        OpCode::Yield => {}
+        OpCode::SelfPid => {}
+        OpCode::ParentPid => {}
+        OpCode::Send(_) => {}
+        OpCode::Monitor(_) => {}
+        OpCode::Demonitor(_) => {}
+        OpCode::Link(_) => {}
+        OpCode::Unlink(_) => {}
+        OpCode::TrapExit => {}
+        OpCode::ProcCall => {}
+        OpCode::ProcReply => {}
+        OpCode::PutDict => {}
+        OpCode::GetDict => {}
+        OpCode::EraseDict => {}
+        OpCode::Broadcast => {}
+        OpCode::SendAfter => {}
+        OpCode::CancelTimer => {}
+        OpCode::TableNew => {}
+        OpCode::TableInsert => {}
+        OpCode::TableLookup => {}
+        OpCode::TableDelete => {}
+        OpCode::Register(_) => {}
+        OpCode::Unregister(_) => {}
+        OpCode::Whereis(_) => {}
+        OpCode::SendNamed(_) => {}
+        OpCode::StartSupervisor => {}
+        OpCode::SuperviseChild(_) => {}
+        OpCode::RestartChild(_) => {}
+    }
+}
+
+fn try_parse_source(name: &str, source: &str) -> Result<Vec<OpCode>, VMError> {
+    let path = std::env::temp_dir().join(format!("{}.ttvm", name));
+    fs::write(&path, source).expect("Failed to write source");
+    parse_program(path.to_str().unwrap())
+}
+
+fn parse_source(name: &str, source: &str) -> Vec<OpCode> {
+    try_parse_source(name, source).expect("Failed to parse program")
+}
+
+#[test]
+fn test_every_opcode_round_trips_through_text() {
+    let opcodes = every_opcode();
+    opcodes.iter().for_each(ensure_variant_listed);
+
+    // Trailing HALTs give jump and call targets somewhere to land
+    let padding = "\nHALT".repeat(opcodes.len());
+    for op in &opcodes {
+        let text = opcode_to_text(op);
+        let parsed = parse_source("round_trip_opcode", &(text.clone() + &padding));
+        assert_eq!(parsed.len(), 1 + opcodes.len(), "{:?} serialized as {:?} parsed into {:?}", op, text, parsed);
+        assert_eq!(&parsed[0], op, "{:?} serialized as {:?}", op, text);
+        let mnemonic = text.split(' ').next().unwrap();
+        assert!(MNEMONICS.contains(&mnemonic), "{} is missing from MNEMONICS", mnemonic);
+    }
+}
+
+#[test]
+fn test_whole_program_round_trips_through_text() {
+    let opcodes = every_opcode();
+    let text: String = opcodes.iter().map(|op| opcode_to_text(op) + "\n").collect();
+
+    assert_eq!(parse_source("round_trip_program", &text), opcodes);
+}
+
+#[test]
+fn test_push_str_decodes_escapes() {
+    let program = parse_source("escapes", "PUSH_STR \"she said \\\"hi\\\"\"\nPUSH_STR \"line one\\nline two\\t!\"\nPUSH_STR \"C:\\\\temp\"\n");
+
+    assert_eq!(program, vec![
+        OpCode::PushStr("she said \"hi\"".to_string()),
+        OpCode::PushStr("line one\nline two\t!".to_string()),
+        OpCode::PushStr("C:\\temp".to_string()),
+    ]);
+}
+
+#[test]
+fn test_comment_markers_inside_strings_are_kept() {
+    let program = parse_source("string_semicolons", "PUSH_STR \"a;b\" ; a real comment\nPUSH_STR \"say \\\"x;y\\\" /* not a comment */\"\nHALT\n");
+
+    assert_eq!(program, vec![
+        OpCode::PushStr("a;b".to_string()),
+        OpCode::PushStr("say \"x;y\" /* not a comment */".to_string()),
+        OpCode::Halt,
+    ]);
+}
+
+#[test]
+fn test_block_comments_span_lines() {
+    let source = "PUSH_INT 1\n/* skipped:\nPUSH_INT 2\nADD */ PUSH_INT 3 /* inline */\nPRINT\nPUSH_FLOAT nope\n";
+    match try_parse_source("block_comment", source) {
+        // Lines inside the comment still count, so errors after it point at the right line
+        Err(VMError::ParseError { line, .. }) => assert_eq!(line, 6),
+        other => panic!("Expected ParseError, got {:?}", other),
+    }
+
+    let program = parse_source("block_comment", source.trim_end_matches("PUSH_FLOAT nope\n"));
+    assert_eq!(program, vec![OpCode::PushInt(1), OpCode::PushInt(3), OpCode::Print]);
+
+    match try_parse_source("unterminated_comment", "PUSH_INT 1\n/* never closed\nHALT\n") {
+        Err(VMError::ParseError { line, instruction }) => {
+            assert_eq!(line, 2);
+            assert!(instruction.contains("unterminated"), "{}", instruction);
+        }
+        other => panic!("Expected ParseError, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_push_str_escapes_round_trip() {
+    let op = OpCode::PushStr("quote \" newline \n tab \t".to_string());
+    let text = opcode_to_text(&op);
+
+    assert_eq!(text, "PUSH_STR \"quote \\\" newline \\n tab \\t\"");
+    assert_eq!(parse_source("escape_round_trip", &text), vec![op]);
+}
+
+#[test]
+fn test_malformed_escape_reports_line() {
+    for (name, source) in [
+        ("bad_escape", "PUSH_INT 1\nPUSH_STR \"bad \\q escape\"\n"),
+        ("unterminated", "PUSH_INT 1\nPUSH_STR \"never closed\n"),
+    ] {
+        match try_parse_source(name, source) {
+            Err(VMError::ParseError { line, .. }) => assert_eq!(line, 2, "{}", name),
+            other => panic!("Expected ParseError for {}, got {:?}", name, other),
+        }
+    }
+}
+
+#[test]
+fn test_receive_match_after_clause() {
+    assert_eq!(
+        parse_source("after", "RECEIVE_MATCH type:int after:0x10"),
+        vec![OpCode::ReceiveMatchAfter(vec![MessagePattern::Type("int".to_string())], 16)]
+    );
+    for source in ["RECEIVE_MATCH any after:soon", "RECEIVE_MATCH any after:-1", "RECEIVE_MATCH any after:1 after:2"] {
+        assert!(matches!(try_parse_source("bad_after", source), Err(VMError::ParseError { .. })), "{}", source);
+    }
+}
+
+#[test]
+fn test_selective_import_operands() {
+    assert_eq!(
+        parse_source("imports", "IMPORT_AS \"lib/m.ttvm\" m\nIMPORT_FROM lib/m.ttvm add sub"),
+        vec![
+            OpCode::ImportAs("lib/m.ttvm".to_string(), "m".to_string()),
+            OpCode::ImportFrom("lib/m.ttvm".to_string(), vec!["add".to_string(), "sub".to_string()]),
+        ]
+    );
+    for source in ["IMPORT_AS lib/m.ttvm", "IMPORT_AS lib/m.ttvm a b", "IMPORT_FROM lib/m.ttvm"] {
+        assert!(matches!(try_parse_source("bad_import", source), Err(VMError::ParseError { .. })), "{}", source);
+    }
+}
+
+#[test]
+fn test_int_literals_in_every_radix() {
+    let program = parse_source("int_radix", "PUSH_INT 0xFF\nPUSH_INT 0b1010\nPUSH_INT 0o17\nPUSH_INT -0x10\nPUSH_INT 1_000_000\nPUSH_INT 0xFFFF_FFFF\nPUSH_INT 42\nMAKE_LIST 0x2\n");
+
+    assert_eq!(program, vec![
+        OpCode::PushInt(255),
+        OpCode::PushInt(10),
+        OpCode::PushInt(15),
+        OpCode::PushInt(-16),
+        OpCode::PushInt(1_000_000),
+        OpCode::PushInt(0xFFFF_FFFF),
+        OpCode::PushInt(42),
+        OpCode::MakeList(2),
+    ]);
+}
+
+#[test]
+fn test_int_literal_extremes() {
+    let program = parse_source("int_extremes", "PUSH_INT -9223372036854775808\nPUSH_INT 0x7FFF_FFFF_FFFF_FFFF\n");
+    assert_eq!(program, vec![OpCode::PushInt(i64::MIN), OpCode::PushInt(i64::MAX)]);
+}
+
+#[test]
+fn test_invalid_int_literals_report_text() {
+    for literal in ["0xZZ", "0b102", "1__", "_1", "0x", "0x8000_0000_0000_0000"] {
+        let source = format!("PUSH_INT {}\n", literal);
+        match try_parse_source("invalid_int", &source) {
+            Err(VMError::ParseError { line, instruction }) => {
+                assert_eq!(line, 1);
+                assert!(instruction.contains(literal), "{} not in {}", literal, instruction);
+            }
+            other => panic!("Expected ParseError for {}, got {:?}", literal, other),
+        }
+    }
+}
+
+#[test]
+fn test_out_of_range_targets_are_parse_errors() {
+    for (name, source, line, target) in [
+        ("jump_past_end", "PUSH_INT 1\nJMP 40\nHALT\n", 2, "40"),
+        ("call_unknown_label", "CALL missing_function x\nHALT\n", 1, "missing_function"),
+        ("try_past_end", "TRY 9\nHALT\n", 1, "9"),
+    ] {
+        match try_parse_source(name, source) {
+            Err(VMError::ParseError { line: reported, instruction }) => {
+                assert_eq!(reported, line, "{}", name);
+                assert!(instruction.contains(target), "{} not in {}", target, instruction);
+            }
+            other => panic!("Expected ParseError for {}, got {:?}", name, other),
+        }
+    }
+
+    // Just past the last instruction is where a trailing LABEL points
+    assert_eq!(parse_source("jump_to_end", "JMP done\nPUSH_INT 1\nLABEL done\n"), vec![OpCode::Jmp(2), OpCode::PushInt(1)]);
+}
+
+#[test]
+fn test_misspelled_opcode_suggests_the_closest_mnemonic() {
+    for (typo, suggestion) in [("PRITN", Some("PRINT")), ("print", Some("PRINT")), ("STOER x", Some("STORE")), ("FROBNICATE", None)] {
+        let source = format!("PUSH_INT 1\n{}\nHALT\n", typo);
+        let error = try_parse_source("misspelled_opcode", &source).expect_err("Parsed a misspelled opcode");
+        match &error {
+            VMError::UnknownOpcode { line, mnemonic, suggestion: suggested } => {
+                assert_eq!(*line, 2);
+                assert_eq!(mnemonic, typo.split(' ').next().unwrap());
+                assert_eq!(suggested.as_deref(), suggestion, "for {}", typo);
+                if let Some(suggestion) = suggestion {
+                    assert!(error.to_string().contains(&format!("did you mean {}?", suggestion)), "{}", error);
+                }
+            }
+            other => panic!("Expected UnknownOpcode for {}, got {:?}", typo, other),
+        }
+    }
+}
+
+#[test]
+fn test_known_opcode_with_bad_operand_is_still_a_parse_error() {
+    match try_parse_source("bad_operand", "PUSH_INT 1\nPUSH_FLOAT nope\nHALT\n") {
+        Err(VMError::ParseError { line, instruction }) => {
+            assert_eq!(line, 2);
+            assert!(instruction.contains("nope"), "{}", instruction);
+        }
+        other => panic!("Expected ParseError, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_invalid_destructure_patterns_report_text() {
+    for pattern in ["", "[a", "[a ...rest b]", "[...]", "{a:}", "{a} b", "...rest", "[a]]"] {
+        let source = format!("DESTRUCTURE {}\n", pattern);
+        match try_parse_source("invalid_destructure", &source) {
+            Err(VMError::ParseError { line, instruction }) => {
+                assert_eq!(line, 1);
+                assert!(instruction.contains(pattern), "{} not in {}", pattern, instruction);
+            }
+            other => panic!("Expected ParseError for {:?}, got {:?}", pattern, other),
+        }
+    }
+}
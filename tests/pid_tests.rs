@@ -0,0 +1,55 @@
+use std::fs;
+use std::time::Duration;
+use crossbeam::channel::unbounded;
+use tiny_tot_vm::concurrency::{Message, SchedulerPool};
+use tiny_tot_vm::{OpCode, Value};
+
+#[test]
+fn test_spawned_child_replies_to_its_parent() {
+    let dir = std::env::temp_dir().join("ttvm_parent_pid");
+    fs::create_dir_all(&dir).unwrap();
+    // Reports its own pid, then sends a greeting to whoever spawned it
+    fs::write(dir.join("child.ttvm"), "\
+SELF_PID
+SENDNAMED \"results\"
+PARENT_PID
+PUSH_INT 0
+PUSH_STR \"hello parent\"
+SEND_AFTER
+HALT
+").unwrap();
+
+    let mut pool = SchedulerPool::new_with_threads(1);
+    let (sender, results) = unbounded();
+    {
+        let mut registry = pool.process_registry.lock().unwrap();
+        registry.register_process(9000, sender).unwrap();
+        registry.register_name("results".to_string(), 9000).unwrap();
+    }
+
+    let (parent, _) = pool.spawn_process(vec![
+        OpCode::ParentPid,
+        OpCode::SendNamed("results".to_string()),
+        OpCode::PushStr(dir.join("child").to_string_lossy().into_owned()),
+        OpCode::Spawn,
+        OpCode::SendNamed("results".to_string()),
+        OpCode::Receive,
+        OpCode::SendNamed("results".to_string()),
+        OpCode::Halt,
+    ]);
+
+    let next = || match results.recv_timeout(Duration::from_secs(5)).expect("No result arrived") {
+        Message::Value(value) => value,
+        other => panic!("Expected a value message, got {:?}", other),
+    };
+    // Spawned from outside the pool, so it has no parent
+    assert_eq!(next(), Value::Null);
+    let spawned = next();
+    let child_reported = next();
+    assert_eq!(spawned, child_reported, "SPAWN and SELF_PID disagree");
+    assert_ne!(spawned, Value::Int(parent as i64));
+    assert_eq!(next(), Value::Str("hello parent".to_string()));
+
+    pool.run().unwrap();
+    pool.wait_for_completion();
+}
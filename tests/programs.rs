@@ -2,6 +2,14 @@ use std::fs;
 use std::path::Path;
 use std::process::Command;
 
+// Each waits for a message no other process sends, so the run ends in a deadlock error
+const EXPECTED_DEADLOCKS: &[&str] = &[
+    "05_trap_exit_test.ttvm",
+    "barista_worker.ttvm",
+    "cashier_worker.ttvm",
+    "test_examples.ttvm",
+];
+
 #[test]
 fn run_all_ttvms() {
     let dir = Path::new("examples");
@@ -16,6 +24,17 @@ fn run_all_ttvms() {
                 .output()
                 .expect("Failed to run command");
 
+            let name = path.file_name().and_then(|s| s.to_str()).unwrap_or_default();
+            if EXPECTED_DEADLOCKS.contains(&name) {
+                assert!(
+                    !output.status.success() && String::from_utf8_lossy(&output.stderr).contains("Deadlock"),
+                    "Program {} was expected to deadlock:\nstderr:\n{}",
+                    file_name,
+                    String::from_utf8_lossy(&output.stderr)
+                );
+                continue;
+            }
+
             assert!(
                 output.status.success(),
                 "Program {} failed:\nstdout:\n{}\nstderr:\n{}",
@@ -0,0 +1,109 @@
+use tiny_tot_vm::concurrency::TinyProc;
+use tiny_tot_vm::vm::VM;
+use tiny_tot_vm::{OpCode, Value, VMError};
+
+// Draws dice rolls and floats, alternating, after seeding with `seed`
+fn draws(seed: i64) -> Vec<OpCode> {
+    let mut program = vec![OpCode::PushInt(seed), OpCode::RandomSeed];
+    for _ in 0..5 {
+        program.extend([OpCode::PushInt(1), OpCode::PushInt(6), OpCode::RandomInt, OpCode::RandomFloat]);
+    }
+    program
+}
+
+fn run(program: Vec<OpCode>) -> Result<Vec<Value>, VMError> {
+    let mut vm = VM::new(program);
+    vm.run()?;
+    Ok(vm.stack)
+}
+
+#[test]
+fn test_reseeding_repeats_the_sequence() {
+    let mut program = draws(42);
+    program.extend(draws(42));
+    program.push(OpCode::Halt);
+    let stack = run(program).unwrap();
+
+    let (first, second) = stack.split_at(10);
+    assert_eq!(first, second);
+    for pair in first.chunks(2) {
+        assert!(matches!(pair[0], Value::Int(1..=6)), "Roll out of range: {:?}", pair[0]);
+        assert!(matches!(pair[1], Value::Float(f) if (0.0..1.0).contains(&f)), "Float out of range: {:?}", pair[1]);
+    }
+
+    // Another seed gives another sequence, and a separate VM the same one
+    let mut other_seed = draws(7);
+    other_seed.push(OpCode::Halt);
+    assert_ne!(run(other_seed).unwrap(), first);
+    let mut same_seed = draws(42);
+    same_seed.push(OpCode::Halt);
+    assert_eq!(run(same_seed).unwrap(), first);
+}
+
+#[test]
+fn test_random_int_covers_both_bounds() {
+    let mut program = vec![OpCode::PushInt(3), OpCode::RandomSeed];
+    for _ in 0..200 {
+        program.extend([OpCode::PushInt(-1), OpCode::PushInt(1), OpCode::RandomInt]);
+    }
+    program.push(OpCode::Halt);
+    let stack = run(program).unwrap();
+
+    for bound in [-1, 0, 1] {
+        assert!(stack.contains(&Value::Int(bound)), "Never drew {}", bound);
+    }
+    assert!(stack.iter().all(|value| matches!(value, Value::Int(-1..=1))));
+}
+
+#[test]
+fn test_empty_range_is_an_error() {
+    match run(vec![OpCode::PushInt(5), OpCode::PushInt(1), OpCode::RandomInt, OpCode::Halt]) {
+        Err(VMError::TypeMismatch { operation, .. }) => assert_eq!(operation, "RANDOM_INT"),
+        other => panic!("Expected a type mismatch, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_uuids_are_well_formed_and_distinct() {
+    // Seeding must not make UUIDs repeat
+    let mut program = Vec::new();
+    for _ in 0..2 {
+        program.extend([OpCode::PushInt(42), OpCode::RandomSeed]);
+        program.extend(std::iter::repeat_n(OpCode::Uuid, 10));
+    }
+    program.push(OpCode::Halt);
+    let stack = run(program).unwrap();
+
+    let mut seen = std::collections::HashSet::new();
+    for value in &stack {
+        let Value::Str(uuid) = value else { panic!("Expected a string, got {:?}", value) };
+        let groups: Vec<&str> = uuid.split('-').collect();
+        assert_eq!(groups.iter().map(|group| group.len()).collect::<Vec<_>>(), [8, 4, 4, 4, 12], "Malformed {}", uuid);
+        assert!(uuid.chars().all(|c| c == '-' || c.is_ascii_digit() || ('a'..='f').contains(&c)), "Malformed {}", uuid);
+        assert!(groups[2].starts_with('4'), "Not version 4: {}", uuid);
+        assert!(groups[3].starts_with(['8', '9', 'a', 'b']), "Wrong variant: {}", uuid);
+        assert!(seen.insert(uuid.clone()), "Repeated {}", uuid);
+    }
+    assert_eq!(seen.len(), 20);
+}
+
+// Processes under the SMP scheduler, the default engine, run the same opcodes
+#[test]
+fn test_processes_make_uuids() {
+    let (mut proc, _) = TinyProc::new(1, vec![OpCode::PushInt(2), OpCode::Uuid, OpCode::Uuid, OpCode::Halt]);
+    proc.run_until_yield().unwrap();
+
+    let [Value::Int(2), Value::Str(first), Value::Str(second)] = &proc.stack[..] else { panic!("Expected two UUIDs, got {:?}", proc.stack) };
+    assert_eq!(first.len(), 36, "Malformed {}", first);
+    assert_ne!(first, second);
+}
+
+#[test]
+fn test_processes_draw_the_same_seeded_numbers() {
+    let mut program = draws(7);
+    program.push(OpCode::Halt);
+    let (mut proc, _) = TinyProc::new(1, program.clone());
+    proc.run_until_yield().unwrap();
+
+    assert_eq!(proc.stack, run(program).unwrap());
+}
@@ -0,0 +1,75 @@
+use std::time::{Duration, Instant};
+use crossbeam::channel::{unbounded, Receiver};
+use tiny_tot_vm::concurrency::{Message, SchedulerPool};
+use tiny_tot_vm::{MessagePattern, OpCode, Value};
+
+// A pool whose processes can report values to the returned channel via SENDNAMED results
+fn pool_with_results() -> (SchedulerPool, Receiver<Message>) {
+    let pool = SchedulerPool::new_with_threads(1);
+    let (sender, receiver) = unbounded();
+    let mut registry = pool.process_registry.lock().unwrap();
+    registry.register_process(9000, sender).unwrap();
+    registry.register_name("results".to_string(), 9000).unwrap();
+    drop(registry);
+    (pool, receiver)
+}
+
+fn next_result(results: &Receiver<Message>) -> Value {
+    match results.recv_timeout(Duration::from_secs(5)).expect("No result arrived") {
+        Message::Value(value) => value,
+        other => panic!("Expected a value message, got {:?}", other),
+    }
+}
+
+fn report() -> OpCode {
+    OpCode::SendNamed("results".to_string())
+}
+
+#[test]
+fn test_receive_match_after_times_out_with_null() {
+    let (mut pool, results) = pool_with_results();
+    let int_pattern = vec![MessagePattern::Type("int".to_string())];
+
+    // Waits for an int that never comes, then reads the strings it skipped over
+    let (_, mailbox) = pool.spawn_process(vec![
+        OpCode::ReceiveMatchAfter(int_pattern, 150),
+        report(),
+        OpCode::Receive,
+        report(),
+        OpCode::Receive,
+        report(),
+        OpCode::Halt,
+    ]);
+    let started = Instant::now();
+    mailbox.send(Message::Value(Value::Str("first".to_string()))).unwrap();
+    mailbox.send(Message::Value(Value::Str("second".to_string()))).unwrap();
+
+    assert_eq!(next_result(&results), Value::Null);
+    assert!(started.elapsed() >= Duration::from_millis(150), "Gave up after {:?}", started.elapsed());
+    assert_eq!(next_result(&results), Value::Str("first".to_string()));
+    assert_eq!(next_result(&results), Value::Str("second".to_string()));
+
+    pool.run().unwrap();
+    pool.wait_for_completion();
+}
+
+#[test]
+fn test_receive_match_after_takes_a_match_that_arrives_in_time() {
+    let (mut pool, results) = pool_with_results();
+
+    let (_, mailbox) = pool.spawn_process(vec![
+        OpCode::ReceiveMatchAfter(vec![MessagePattern::Type("int".to_string())], 5000),
+        report(),
+        OpCode::Halt,
+    ]);
+    let started = Instant::now();
+    mailbox.send(Message::Value(Value::Str("skipped".to_string()))).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    mailbox.send(Message::Value(Value::Int(7))).unwrap();
+
+    assert_eq!(next_result(&results), Value::Int(7));
+    assert!(started.elapsed() < Duration::from_secs(5), "Waited for the timeout");
+
+    pool.run().unwrap();
+    pool.wait_for_completion();
+}
@@ -0,0 +1,99 @@
+use tiny_tot_vm::concurrency::TinyProc;
+use tiny_tot_vm::vm::VM;
+use tiny_tot_vm::{OpCode, Value, VMError};
+
+fn run(program: Vec<OpCode>) -> Result<Vec<Value>, VMError> {
+    let mut vm = VM::new(program);
+    vm.run()?;
+    Ok(vm.stack)
+}
+
+fn strings(values: &[&str]) -> Value {
+    Value::List(values.iter().map(|value| Value::Str(value.to_string())).collect())
+}
+
+#[test]
+fn test_find_lists_capture_groups_of_every_match() {
+    let stack = run(vec![
+        OpCode::PushStr("GET /index 200, POST /login 403".to_string()),
+        OpCode::PushStr(r"(GET|POST) (/\w+) (\d+)(!)?".to_string()),
+        OpCode::RegexFind,
+        OpCode::PushStr("no digits here".to_string()),
+        OpCode::PushStr(r"\d+".to_string()),
+        OpCode::RegexFind,
+        OpCode::PushStr("a1b22c333".to_string()),
+        OpCode::PushStr(r"\d+".to_string()),
+        OpCode::RegexFind,
+        OpCode::Halt,
+    ]).unwrap();
+
+    let request = |method: &str, path: &str, status: &str| Value::List(vec![
+        Value::Str(format!("{} {} {}", method, path, status)),
+        Value::Str(method.to_string()),
+        Value::Str(path.to_string()),
+        Value::Str(status.to_string()),
+        Value::Null,
+    ].into());
+    assert_eq!(stack, vec![
+        Value::List(vec![request("GET", "/index", "200"), request("POST", "/login", "403")].into()),
+        Value::List(vec![].into()),
+        strings(&["1", "22", "333"]),
+    ]);
+}
+
+#[test]
+fn test_replace_rewrites_every_match() {
+    let stack = run(vec![
+        OpCode::PushStr("2024-01-05 and 2025-12-31".to_string()),
+        OpCode::PushStr(r"(?P<y>\d{4})-(\d{2})-(\d{2})".to_string()),
+        OpCode::PushStr("$3/$2/${y}".to_string()),
+        OpCode::RegexReplace,
+        OpCode::PushStr("tiny tot vm".to_string()),
+        OpCode::PushStr(r"^tiny\s".to_string()),
+        OpCode::RegexMatch,
+        OpCode::PushStr("tiny tot vm".to_string()),
+        OpCode::PushStr(r"^tot".to_string()),
+        OpCode::RegexMatch,
+        OpCode::Halt,
+    ]).unwrap();
+
+    assert_eq!(stack, vec![
+        Value::Str("05/01/2024 and 31/12/2025".to_string()),
+        Value::Bool(true),
+        Value::Bool(false),
+    ]);
+}
+
+#[test]
+fn test_invalid_pattern_is_an_error() {
+    match run(vec![OpCode::PushStr("text".to_string()), OpCode::PushStr("(unclosed".to_string()), OpCode::RegexMatch, OpCode::Halt]) {
+        Err(VMError::InvalidPattern { pattern, error }) => {
+            assert_eq!(pattern, "(unclosed");
+            assert_eq!(error, "unclosed group");
+        }
+        other => panic!("Expected an invalid pattern error, got {:?}", other),
+    }
+}
+
+// Processes under the SMP scheduler, the default engine, run the same opcodes
+#[test]
+fn test_processes_run_regex_opcodes() {
+    let (mut proc, _) = TinyProc::new(1, vec![
+        OpCode::PushStr("a1b22c333".to_string()),
+        OpCode::PushStr("[0-9]+".to_string()),
+        OpCode::RegexFind,
+        OpCode::PushStr("a1b22".to_string()),
+        OpCode::PushStr("^a".to_string()),
+        OpCode::RegexMatch,
+        OpCode::PushStr("a1b22".to_string()),
+        OpCode::PushStr("[0-9]".to_string()),
+        OpCode::PushStr("#".to_string()),
+        OpCode::RegexReplace,
+        OpCode::Halt,
+    ]);
+    proc.run_until_yield().unwrap();
+    assert_eq!(proc.stack, vec![strings(&["1", "22", "333"]), Value::Bool(true), Value::Str("a#b##".to_string())]);
+
+    let (mut proc, _) = TinyProc::new(1, vec![OpCode::PushStr("text".to_string()), OpCode::PushStr("(unclosed".to_string()), OpCode::RegexFind, OpCode::Halt]);
+    assert!(matches!(proc.run_until_yield(), Err(VMError::InvalidPattern { .. })));
+}
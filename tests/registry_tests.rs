@@ -0,0 +1,146 @@
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crossbeam::channel::{unbounded, Receiver};
+use tiny_tot_vm::concurrency::pool::SchedulerPoolMessageSender;
+use tiny_tot_vm::concurrency::{Message, ProcessRegistry, SchedulerPool, TinyProc};
+use tiny_tot_vm::{OpCode, VMError, Value};
+
+// A pool whose processes can report values to the returned channel via SENDNAMED results
+fn pool_with_results() -> (SchedulerPool, Receiver<Message>) {
+    let pool = SchedulerPool::new_with_threads(1);
+    let (sender, receiver) = unbounded();
+    let mut registry = pool.process_registry.lock().unwrap();
+    registry.register_process(9000, sender).unwrap();
+    registry.register_name("results".to_string(), 9000).unwrap();
+    drop(registry);
+    (pool, receiver)
+}
+
+fn next_result(results: &Receiver<Message>) -> Value {
+    match results.recv_timeout(Duration::from_secs(5)).expect("No result arrived") {
+        Message::Value(value) => value,
+        other => panic!("Expected a value message, got {:?}", other),
+    }
+}
+
+fn report() -> OpCode {
+    OpCode::SendNamed("results".to_string())
+}
+
+#[test]
+fn test_spawn_registered_is_found_before_it_runs() {
+    let (mut pool, _results) = pool_with_results();
+
+    let (pid, _) = pool.spawn_registered("service", vec![OpCode::Receive, OpCode::Halt]).unwrap();
+    assert_eq!(pool.process_registry.lock().unwrap().whereis("service"), Some(pid));
+
+    // The name stays with the first process
+    let error = pool.spawn_registered("service", vec![OpCode::Halt]).unwrap_err();
+    assert!(error.contains("'service' already registered"), "Unclear error: {}", error);
+    assert_eq!(pool.process_registry.lock().unwrap().whereis("service"), Some(pid));
+
+    pool.process_registry.lock().unwrap().send_to_named("service", Message::Value(Value::Null)).unwrap();
+    pool.run().unwrap();
+    pool.wait_for_completion();
+}
+
+#[test]
+fn test_spawn_registered_opcode_resolves_without_yielding() {
+    let dir = std::env::temp_dir().join("ttvm_spawn_registered");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("service.ttvm"), "RECEIVE\nHALT\n").unwrap();
+    let (mut pool, results) = pool_with_results();
+
+    pool.spawn_process(vec![
+        OpCode::PushStr(dir.join("service").to_string_lossy().into_owned()),
+        OpCode::SpawnRegistered("service".to_string()),
+        report(),
+        OpCode::Whereis("service".to_string()),
+        report(),
+        OpCode::PushStr("stop".to_string()),
+        OpCode::SendNamed("service".to_string()),
+        OpCode::Halt,
+    ]);
+
+    let spawned = next_result(&results);
+    assert!(matches!(spawned, Value::Int(pid) if pid > 0), "Got {:?}", spawned);
+    assert_eq!(next_result(&results), spawned);
+
+    pool.run().unwrap();
+    pool.wait_for_completion();
+}
+
+#[test]
+fn test_whereis_pushes_pid_or_null() {
+    let (mut pool, results) = pool_with_results();
+
+    let (pid, _) = pool.spawn_process(vec![
+        OpCode::Register("lookup".to_string()),
+        OpCode::Whereis("lookup".to_string()),
+        report(),
+        OpCode::Whereis("nobody".to_string()),
+        report(),
+        OpCode::Halt,
+    ]);
+
+    assert_eq!(next_result(&results), Value::Int(pid as i64));
+    assert_eq!(next_result(&results), Value::Null);
+
+    pool.run().unwrap();
+    pool.wait_for_completion();
+}
+
+#[test]
+fn test_sendnamed_to_unregistered_name_is_an_error() {
+    let registry = Arc::new(Mutex::new(ProcessRegistry::new()));
+    let (mut proc, _) = TinyProc::new(1, vec![
+        OpCode::PushStr("hello".to_string()),
+        OpCode::SendNamed("nobody".to_string()),
+        OpCode::Halt,
+    ]);
+    proc.name_registry = Some(Arc::new(SchedulerPoolMessageSender { process_registry: registry }));
+
+    match proc.run_until_yield() {
+        Err(VMError::RuntimeError(message)) => assert!(message.contains("'nobody'"), "Unclear error: {}", message),
+        other => panic!("Expected an error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_names_are_released_when_process_exits() {
+    let (mut pool, results) = pool_with_results();
+
+    let (pid, mailbox) = pool.spawn_process(vec![OpCode::Register("short_lived".to_string()), OpCode::Halt]);
+    mailbox.send(Message::Monitor(9000, "watch".to_string())).unwrap();
+    pool.run().unwrap();
+
+    // Gone by the time anyone hears about the exit
+    match results.recv_timeout(Duration::from_secs(5)).expect("No down message arrived") {
+        Message::Down(down, _, _) => assert_eq!(down, pid),
+        other => panic!("Expected a down message, got {:?}", other),
+    }
+    assert_eq!(pool.process_registry.lock().unwrap().whereis("short_lived"), None);
+
+    let process_registry = pool.process_registry.clone();
+    pool.wait_for_completion();
+
+    let (mut lookup, _) = TinyProc::new(2, vec![OpCode::Whereis("short_lived".to_string()), OpCode::Halt]);
+    lookup.name_registry = Some(Arc::new(SchedulerPoolMessageSender { process_registry }));
+    lookup.run_until_yield().unwrap();
+    assert_eq!(lookup.stack, vec![Value::Null]);
+}
+
+#[test]
+fn test_spawn_with_a_taken_pid_exits_instead_of_panicking() {
+    let (mut pool, _results) = pool_with_results();
+    // Something outside the pool already holds the id the next spawn would get
+    let taken = *pool.next_proc_id.lock().unwrap();
+    let (outside, _) = unbounded();
+    pool.process_registry.lock().unwrap().register_process(taken, outside).unwrap();
+
+    let (pid, _) = pool.spawn_process(vec![OpCode::Halt]);
+    assert_ne!(pid, taken);
+    assert!(pool.running_processes.lock().unwrap().is_empty(), "A process that failed to register was started");
+    pool.run().expect("Pool failed");
+}
@@ -0,0 +1,79 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Start the REPL and feed it a script of input lines on stdin
+fn run_repl(script: &str) -> (bool, String) {
+    let mut child = Command::new("cargo")
+        .args(["run", "--quiet", "--", "repl"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn repl");
+
+    child
+        .stdin
+        .take()
+        .expect("Failed to open stdin")
+        .write_all(script.as_bytes())
+        .expect("Failed to write repl input");
+
+    let output = child.wait_with_output().expect("Failed to wait for repl");
+    (output.status.success(), String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+// Results printed after each input, in order
+fn results(stdout: &str) -> Vec<String> {
+    stdout
+        .split("ttvm> ")
+        .flat_map(|chunk| chunk.lines())
+        .filter(|line| line.starts_with("=> ") || line.starts_with("Error: "))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+#[test]
+fn test_repl_keeps_state_between_lines() {
+    let (success, stdout) = run_repl(
+        "PUSH_INT 40\nPUSH_INT 2\nADD\nSTORE answer\nLOAD answer\nPUSH_STR \"done\"\n",
+    );
+
+    assert!(success, "stdout:\n{}", stdout);
+    assert_eq!(results(&stdout), vec!["=> 40", "=> 2", "=> 42", "=> (empty stack)", "=> 42", "=> done"]);
+}
+
+#[test]
+fn test_repl_lists_and_objects() {
+    let (success, stdout) = run_repl(
+        "PUSH_INT 1\nPUSH_INT 2\nPUSH_INT 3\nMAKE_LIST 3\nLEN\nMAKE_OBJECT\nPUSH_STR \"bob\"\nSET_FIELD name\nGET_FIELD name\n",
+    );
+
+    assert!(success, "stdout:\n{}", stdout);
+    let results = results(&stdout);
+    assert_eq!(results[3], "=> [1, 2, 3]");
+    assert_eq!(results[4], "=> 3");
+    assert_eq!(results[8], "=> bob");
+}
+
+#[test]
+fn test_repl_survives_errors() {
+    let (success, stdout) = run_repl("LOAD missing\nBOGUS\nPUSH_INT 7\nPRINT\n.stack\n.exit\nPUSH_INT 8\n");
+
+    assert!(success, "stdout:\n{}", stdout);
+    let results = results(&stdout);
+    assert!(results[0].starts_with("Error: Undefined variable: missing"), "{:?}", results);
+    assert!(results[1].starts_with("Error: Unknown opcode on line 1: BOGUS"), "{:?}", results);
+    assert_eq!(results[2], "=> 7");
+    assert!(stdout.contains("Stack: []"), "stdout:\n{}", stdout);
+    assert!(!stdout.contains("=> 8"), "stdout:\n{}", stdout);
+}
+
+#[test]
+fn test_repl_jump_targets_are_relative_to_their_line() {
+    // JMP 1 points just past its own line; left unshifted it would loop back to PUSH_INT 6
+    let (success, stdout) = run_repl("PUSH_INT 5\nPUSH_INT 6\nJMP 1\nPUSH_INT 7\n.stack\n");
+
+    assert!(success, "stdout:\n{}", stdout);
+    assert_eq!(results(&stdout), vec!["=> 5", "=> 6", "=> 6", "=> 7"]);
+    assert!(stdout.contains("Stack: [Int(5), Int(6), Int(7)]"), "stdout:\n{}", stdout);
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tiny_tot_vm::concurrency::{Priority, ProcessRegistry, Scheduler, SchedulerPool, SchedulingPolicy, TinyProc};
+use tiny_tot_vm::vm::{OutputBuffer, OutputSink};
+use tiny_tot_vm::{OpCode, ProcState, VMError};
+
+// Instructions each process ran after `loops` passes of a scheduler over a low and a high
+// priority process that never finish on their own
+fn instructions_run(policy: SchedulingPolicy, loops: usize) -> (usize, usize) {
+    let mut scheduler = Scheduler::with_policy(0, policy);
+    let running = Arc::new(Mutex::new(HashMap::new()));
+    let registry = Arc::new(Mutex::new(ProcessRegistry::new()));
+
+    let mut procs = Vec::new();
+    for (pid, priority) in [(1, Priority::Low), (2, Priority::High)] {
+        let (mut proc, _) = TinyProc::new(pid, (0..100_000).map(OpCode::PushInt).collect());
+        proc.priority = priority;
+        let proc = Arc::new(Mutex::new(proc));
+        scheduler.add_process(proc.clone());
+        procs.push(proc);
+    }
+
+    for _ in 0..loops {
+        assert!(scheduler.run_next(running.clone(), registry.clone()), "The run queue ran dry");
+    }
+    let count = |proc: &Arc<Mutex<TinyProc>>| proc.lock().unwrap().instruction_count;
+    (count(&procs[0]), count(&procs[1]))
+}
+
+#[test]
+fn test_priority_policy_gives_high_priority_more_reductions() {
+    let (low, high) = instructions_run(SchedulingPolicy::Priority, 10);
+    // Both got five slices: half the normal budget for low, double for high
+    assert_eq!((low, high), (5 * 500, 5 * 2000));
+    assert!(high > low);
+}
+
+#[test]
+fn test_round_robin_ignores_priority() {
+    let (low, high) = instructions_run(SchedulingPolicy::RoundRobin, 10);
+    assert_eq!((low, high), (5 * 1000, 5 * 1000));
+}
+
+#[test]
+fn test_reduction_budget_scales_with_priority() {
+    let policy = SchedulingPolicy::Priority;
+    assert_eq!(policy.reduction_budget(1000, Priority::Low), 500);
+    assert_eq!(policy.reduction_budget(1000, Priority::Normal), 1000);
+    assert_eq!(policy.reduction_budget(1000, Priority::High), 2000);
+    assert_eq!(policy.reduction_budget(1, Priority::Low), 1);
+    assert_eq!(SchedulingPolicy::RoundRobin.reduction_budget(1000, Priority::High), 1000);
+}
+
+#[test]
+fn test_process_print_output_can_be_captured() {
+    let (mut proc, _) = TinyProc::new(1, vec![OpCode::PushStr("hello".to_string()), OpCode::Print, OpCode::Halt]);
+    let output = OutputBuffer::new();
+    proc.set_output(OutputSink::new(output.clone()));
+    proc.run_until_yield().expect("Process failed");
+
+    assert_eq!(output.contents(), "hello\n");
+}
+
+#[test]
+fn test_pool_reduction_slice_sets_yield_frequency() {
+    // No scheduler threads, so the test runs the process itself
+    let mut pool = SchedulerPool::new();
+    pool.max_reductions = 10;
+    let (pid, _) = pool.spawn_process((0..100).map(OpCode::PushInt).collect());
+    let proc_arc = pool.running_processes.lock().unwrap()[&pid].clone();
+    let mut proc = proc_arc.lock().unwrap();
+
+    assert_eq!(proc.run_until_yield().expect("Process failed"), ProcState::Waiting);
+    assert_eq!(proc.instruction_count, 10);
+    proc.run_until_yield().expect("Process failed");
+    assert_eq!(proc.instruction_count, 20);
+}
+
+#[test]
+fn test_pool_stack_limit_stops_process() {
+    let mut pool = SchedulerPool::new();
+    pool.stack_limit = Some(3);
+    let (pid, _) = pool.spawn_process((0..10).map(OpCode::PushInt).collect());
+    let proc_arc = pool.running_processes.lock().unwrap()[&pid].clone();
+    let mut proc = proc_arc.lock().unwrap();
+
+    assert!(matches!(proc.run_until_yield(), Err(VMError::StackOverflow { limit: 3 })));
+}
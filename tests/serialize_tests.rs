@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use indexmap::IndexMap;
+use tiny_tot_vm::vm::{serialize_value, deserialize_value};
+use tiny_tot_vm::{Value, VMError};
+
+fn object(fields: Vec<(&str, Value)>) -> Value {
+    Value::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect::<IndexMap<_, _>>().into())
+}
+
+#[test]
+fn test_nested_value_round_trips() {
+    let value = object(vec![
+        ("name", Value::Str("snapshot ✓".to_string())),
+        ("entries", Value::List(vec![
+            object(vec![
+                ("id", Value::Int(i64::MIN)),
+                ("ratio", Value::Float(-0.125)),
+                ("tags", Value::List(vec![Value::Str(String::new()), Value::Null].into())),
+            ]),
+            object(vec![
+                ("id", Value::Int(7)),
+                ("raw", Value::Bytes(vec![0, 255, 10])),
+                ("done", Value::Bool(true)),
+            ]),
+        ].into())),
+        ("empty", object(vec![])),
+    ]);
+
+    let bytes = serialize_value(&value).expect("Serializing failed");
+    let decoded = deserialize_value(&bytes).expect("Deserializing failed");
+    assert_eq!(decoded, value);
+
+    // Field order is part of the value
+    let Value::Object(fields) = decoded else { panic!("Expected an object") };
+    assert_eq!(fields.keys().collect::<Vec<_>>(), ["name", "entries", "empty"]);
+}
+
+#[test]
+fn test_vm_handles_are_not_serializable() {
+    let result = serialize_value(&Value::Connection("db:app.db#1".to_string()));
+    assert!(matches!(result, Err(VMError::UnsupportedOperation(_))), "Got {:?}", result);
+
+    // Also when buried inside a structure
+    let closure = Value::Closure { addr: 3, params: vec![], captured: HashMap::new().into() };
+    let result = serialize_value(&Value::List(vec![Value::Int(1), object(vec![("f", closure)])].into()));
+    assert!(matches!(result, Err(VMError::UnsupportedOperation(_))), "Got {:?}", result);
+}
+
+#[test]
+fn test_malformed_input_is_an_error() {
+    let bytes = serialize_value(&Value::List(vec![Value::Str("abc".to_string())].into())).unwrap();
+    for len in 0..bytes.len() {
+        assert!(deserialize_value(&bytes[..len]).is_err(), "Truncated to {} bytes", len);
+    }
+    assert!(deserialize_value(&[bytes.as_slice(), &[0]].concat()).is_err());
+    assert!(deserialize_value(&[99]).is_err());
+}
+
+#[test]
+fn test_deep_nesting_is_refused() {
+    let nested = |depth: usize| (0..depth).fold(Value::Null, |inner, _| Value::List(vec![inner].into()));
+    let bytes = serialize_value(&nested(128)).expect("Serializing failed");
+    assert_eq!(deserialize_value(&bytes).expect("Deserializing failed"), nested(128));
+    assert!(matches!(serialize_value(&nested(129)), Err(VMError::UnsupportedOperation(_))));
+
+    // Half a million one-item list headers, as a hostile node might send
+    let hostile: Vec<u8> = [5, 0, 0, 0, 1].repeat(500_000);
+    match deserialize_value(&hostile) {
+        Err(error) => assert!(error.to_string().contains("nesting too deep"), "{}", error),
+        Ok(_) => panic!("Deserialized a value nested 500000 deep"),
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::{Duration, Instant};
+use crossbeam::channel::unbounded;
+use tiny_tot_vm::concurrency::{Message, SchedulerPool};
+use tiny_tot_vm::{OpCode, Value};
+
+#[test]
+fn test_shutdown_lets_work_finish_and_terminates_the_rest() {
+    let pool = SchedulerPool::new_with_threads(2);
+    let (sender, results) = unbounded();
+    {
+        let mut registry = pool.process_registry.lock().unwrap();
+        registry.register_process(9000, sender).unwrap();
+        registry.register_name("results".to_string(), 9000).unwrap();
+    }
+
+    // Takes several reduction slices, then finishes on its own
+    let mut busy: Vec<OpCode> = (0..5000).map(OpCode::PushInt).collect();
+    busy.extend([OpCode::PushStr("finished".to_string()), OpCode::SendNamed("results".to_string()), OpCode::Halt]);
+    let (busy_pid, _) = pool.spawn_process(busy);
+
+    // Wait for messages that never come
+    let (stuck_pid, stuck_mailbox) = pool.spawn_process(vec![OpCode::Receive, OpCode::Halt]);
+    let (other_stuck_pid, _) = pool.spawn_process(vec![OpCode::Receive, OpCode::Receive, OpCode::Halt]);
+
+    // The results channel monitors one of the stuck processes
+    stuck_mailbox.send(Message::Monitor(9000, "watch_stuck".to_string())).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+
+    let started = Instant::now();
+    let terminated = pool.shutdown(Duration::from_millis(300));
+    assert!(started.elapsed() < Duration::from_secs(2), "Shutdown took {:?}", started.elapsed());
+
+    assert_eq!(terminated, vec![stuck_pid, other_stuck_pid]);
+    assert!(!terminated.contains(&busy_pid));
+
+    let mut reported: Vec<String> = results.try_iter().map(|msg| format!("{:?}", msg)).collect();
+    reported.sort();
+    assert_eq!(reported, vec![
+        format!("{:?}", Message::Down(stuck_pid, "watch_stuck".to_string(), "shutdown".to_string())),
+        format!("{:?}", Message::Value(Value::Str("finished".to_string()))),
+    ]);
+}
+
+#[test]
+fn test_shutdown_of_idle_pool_returns_at_once() {
+    let pool = SchedulerPool::new_with_threads(1);
+    let (pid, _) = pool.spawn_process(vec![OpCode::PushInt(1), OpCode::Halt]);
+    std::thread::sleep(Duration::from_millis(20));
+
+    let started = Instant::now();
+    assert!(pool.shutdown(Duration::from_secs(5)).is_empty(), "Process {} was terminated", pid);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_kill_stops_busy_process_and_signals_links() {
+    let pool = SchedulerPool::new_with_threads(2);
+    let (sender, linked) = unbounded();
+    pool.process_registry.lock().unwrap().register_process(9000, sender).unwrap();
+
+    // Loops forever and traps exits, neither of which may keep a kill from landing
+    let (busy_pid, busy_mailbox) = pool.spawn_process(vec![OpCode::Jmp(0)]);
+    busy_mailbox.send(Message::TrapExit(true)).unwrap();
+    busy_mailbox.send(Message::Link(9000)).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(matches!(linked.try_recv(), Ok(Message::Link(pid)) if pid == busy_pid));
+
+    pool.kill(busy_pid, "timeout").unwrap();
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while pool.running_processes.lock().unwrap().contains_key(&busy_pid) {
+        assert!(Instant::now() < deadline, "Process {} is still running", busy_pid);
+        std::thread::sleep(Duration::from_millis(5));
+    }
+
+    assert!(matches!(linked.recv_timeout(Duration::from_secs(1)), Ok(Message::Exit(pid)) if pid == busy_pid));
+    assert!(pool.shutdown(Duration::from_millis(100)).is_empty());
+}
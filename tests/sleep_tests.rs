@@ -0,0 +1,44 @@
+use std::time::{Duration, Instant};
+use crossbeam::channel::unbounded;
+use tiny_tot_vm::concurrency::{Message, SchedulerPool};
+use tiny_tot_vm::{OpCode, Value};
+
+#[test]
+fn test_sleeping_process_lets_others_run_on_its_thread() {
+    let mut pool = SchedulerPool::new_with_threads(1);
+    let (sender, results) = unbounded();
+    {
+        let mut registry = pool.process_registry.lock().unwrap();
+        registry.register_process(9000, sender).unwrap();
+        registry.register_name("results".to_string(), 9000).unwrap();
+    }
+
+    let started = Instant::now();
+    pool.spawn_process(vec![
+        OpCode::PushInt(300),
+        OpCode::Sleep,
+        OpCode::PushStr("awake".to_string()),
+        OpCode::SendNamed("results".to_string()),
+        OpCode::Halt,
+    ]);
+
+    // Needs several reduction slices on the same scheduler thread
+    let mut busy: Vec<OpCode> = (0..5000).map(OpCode::PushInt).collect();
+    busy.extend([OpCode::PushStr("busy done".to_string()), OpCode::SendNamed("results".to_string()), OpCode::Halt]);
+    pool.spawn_process(busy);
+
+    let next = || match results.recv_timeout(Duration::from_secs(5)).expect("No result arrived") {
+        Message::Value(value) => (value, started.elapsed()),
+        other => panic!("Expected a value message, got {:?}", other),
+    };
+    let (first, busy_elapsed) = next();
+    assert_eq!(first, Value::Str("busy done".to_string()));
+    assert!(busy_elapsed < Duration::from_millis(300), "Busy process waited for the sleeper: {:?}", busy_elapsed);
+
+    let (second, awake_elapsed) = next();
+    assert_eq!(second, Value::Str("awake".to_string()));
+    assert!(awake_elapsed >= Duration::from_millis(300), "Woke up after only {:?}", awake_elapsed);
+
+    pool.run().unwrap();
+    pool.wait_for_completion();
+}
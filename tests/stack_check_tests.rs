@@ -0,0 +1,75 @@
+use tiny_tot_vm::bytecode::parse_source;
+use tiny_tot_vm::ir::analysis::{check_stack_balance, StackProblem};
+
+fn check(source: &str) -> Vec<StackProblem> {
+    let (program, _) = parse_source(source).unwrap();
+    check_stack_balance(&program)
+}
+
+#[test]
+fn test_add_with_one_operand_underflows() {
+    let problems = check("PUSH_INT 1\nPUSH_INT 2\nADD\nPUSH_INT 3\nADD\nADD\nPRINT\nHALT\n");
+    assert_eq!(problems, vec![StackProblem::Underflow { addr: 5, needed: 2, depth: 1 }]);
+}
+
+#[test]
+fn test_balanced_program_passes() {
+    let problems = check("\
+PUSH_INT 5
+CALL double x
+STORE n
+LABEL loop
+LOAD n
+JZ done
+LOAD n
+PUSH_INT 1
+SUB
+STORE n
+JMP loop
+LABEL done
+TRY handler
+PUSH_STR \"oops\"
+THROW
+LABEL handler
+CATCH
+PRINT
+END_TRY
+HALT
+LABEL double
+LOAD x
+LOAD x
+ADD
+RET
+");
+    assert_eq!(problems, vec![]);
+}
+
+#[test]
+fn test_branches_leaving_different_depths_are_reported() {
+    // The taken branch skips the extra push, so `join` is reached with 1 or 2 values
+    let problems = check("\
+PUSH_INT 1
+PUSH_BOOL true
+JZ join
+PUSH_INT 2
+LABEL join
+PRINT
+HALT
+");
+    assert_eq!(problems, vec![StackProblem::DepthMismatch { addr: 4, from: 3, expected: 1, depth: 2 }]);
+}
+
+#[test]
+fn test_callee_results_carry_into_the_caller() {
+    // `pair` leaves two values, so the caller can add them
+    let problems = check("CALL pair\nADD\nADD\nHALT\nLABEL pair\nPUSH_INT 1\nPUSH_INT 2\nRET\n");
+    assert_eq!(problems, vec![StackProblem::Underflow { addr: 2, needed: 2, depth: 1 }]);
+}
+
+#[test]
+fn test_ret_n_with_the_wrong_depth_is_reported() {
+    let problems = check("PUSH_INT 2\nCALL pair x\nPRINT\nHALT\nLABEL pair\nLOAD x\nRET 2\n");
+    assert_eq!(problems, vec![StackProblem::ReturnCount { addr: 5, declared: 2, depth: 1 }]);
+
+    assert!(check("PUSH_INT 2\nCALL pair x\nADD\nPRINT\nHALT\nLABEL pair\nLOAD x\nDUP\nRET 2\n").is_empty());
+}
@@ -0,0 +1,127 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Run `ttvm <args> -`, piping the program source on stdin
+fn run_from_stdin(args: &[&str], source: &str) -> (bool, String, String) {
+    run_with_program_args(args, &[], source)
+}
+
+// Run `ttvm <args> - <program_args>`, piping the program source on stdin
+fn run_with_program_args(args: &[&str], program_args: &[&str], source: &str) -> (bool, String, String) {
+    let mut child = Command::new("cargo")
+        .args(["run", "--quiet", "--"])
+        .args(args)
+        .arg("-")
+        .args(program_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn ttvm");
+
+    child
+        .stdin
+        .take()
+        .expect("Failed to open stdin")
+        .write_all(source.as_bytes())
+        .expect("Failed to write program");
+
+    let output = child.wait_with_output().expect("Failed to wait for ttvm");
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    )
+}
+
+#[test]
+fn test_program_read_from_stdin() {
+    let source = "PUSH_INT 6\nSTORE x\nLOAD x\nPUSH_INT 7\nMUL\nPRINT\nPUSH_STR \"piped\"\nPRINT\nHALT\n";
+    let (success, stdout, stderr) = run_from_stdin(&["--no-smp"], source);
+
+    assert!(success, "stderr:\n{}", stderr);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines.contains(&"42"), "stdout:\n{}", stdout);
+    assert!(lines.contains(&"piped"), "stdout:\n{}", stdout);
+}
+
+#[test]
+fn test_stdin_errors_report_source_line() {
+    let (success, _, stderr) = run_from_stdin(&["--no-smp"], "PUSH_INT 1\nPUSH_STR \"a\"\nADD\n");
+
+    assert!(!success);
+    assert!(stderr.contains("(line 3, instruction 2)"), "stderr:\n{}", stderr);
+}
+
+#[test]
+fn test_print_raw_builds_one_line() {
+    let source = "PUSH_STR \"total: \"\nPRINT_RAW\nPUSH_INT 42\nPRINT_RAW\nPUSH_STR \"warning\"\nPRINT_ERR\n\
+                  PUSH_STR \"!\"\nPRINT\nPUSH_STR \"next\"\nPRINT\nHALT\n";
+    for args in [&["--no-smp"][..], &["--no-smp", "--use-ir"], &[]] {
+        let (success, stdout, stderr) = run_from_stdin(args, source);
+
+        assert!(success, "{:?} stderr:\n{}", args, stderr);
+        // The SMP scheduler's startup line can land in the middle of the first PRINT_RAW
+        let stdout = stdout.replace("Main process spawned with ID: 1\n", "");
+        let lines: Vec<&str> = stdout.lines().collect();
+        let at = lines.iter().position(|line| *line == "total: 42!")
+            .unwrap_or_else(|| panic!("{:?} stdout:\n{}", args, stdout));
+        assert_eq!(lines.get(at + 1), Some(&"next"), "{:?} stdout:\n{}", args, stdout);
+        assert!(!stdout.contains("warning"), "{:?} stdout:\n{}", args, stdout);
+        assert!(stderr.lines().any(|line| line == "warning"), "{:?} stderr:\n{}", args, stderr);
+    }
+}
+
+#[test]
+fn test_float_precision_flag() {
+    let source = "PUSH_FLOAT 3.14159\nPRINT\nPUSH_FLOAT 2.0\nPRINT\nPUSH_FLOAT 1e21\nPRINT\nHALT\n";
+    for engine in [&["--no-smp"][..], &["--no-smp", "--use-ir"], &[]] {
+        let args = [engine, &["--float-precision", "2"]].concat();
+        let (success, stdout, stderr) = run_from_stdin(&args, source);
+
+        assert!(success, "{:?} stderr:\n{}", args, stderr);
+        let lines: Vec<&str> = stdout.lines().collect();
+        for expected in ["3.14", "2.00", "1.00e21"] {
+            assert!(lines.contains(&expected), "{:?} missing {} in stdout:\n{}", args, expected, stdout);
+        }
+    }
+
+    let (success, _, stderr) = run_from_stdin(&["--float-precision", "two"], source);
+    assert!(!success);
+    assert!(stderr.contains("Invalid float precision: two"), "stderr:\n{}", stderr);
+}
+
+#[test]
+fn test_get_args_sees_only_program_arguments() {
+    let source = "GET_ARGS\nPRINT\nHALT\n";
+    for args in [&["--no-smp"][..], &["--no-smp", "--use-ir"], &[]] {
+        let (success, stdout, stderr) = run_with_program_args(args, &["input.txt", "--verbose", "-n", "3"], source);
+
+        assert!(success, "{:?} stderr:\n{}", args, stderr);
+        let lines: Vec<&str> = stdout.lines().collect();
+        // Flags after the program file belong to the program, not the VM
+        assert!(lines.contains(&"[input.txt, --verbose, -n, 3]"), "{:?} stdout:\n{}", args, stdout);
+    }
+
+    let (success, stdout, stderr) = run_from_stdin(&["--no-smp"], source);
+    assert!(success, "stderr:\n{}", stderr);
+    assert!(stdout.lines().any(|line| line == "[]"), "stdout:\n{}", stdout);
+}
+
+#[test]
+fn test_set_cwd_changes_where_files_are_read_from() {
+    let dir = std::env::temp_dir().join("ttvm_set_cwd_test");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("note.txt"), "found me").unwrap();
+
+    let source = format!(
+        "PUSH_STR {:?}\nSET_CWD\nPUSH_STR \"note.txt\"\nREAD_FILE\nPRINT\nGET_CWD\nPATH_BASENAME\nPRINT\nHALT\n",
+        dir.to_string_lossy()
+    );
+    let (success, stdout, stderr) = run_from_stdin(&["--no-smp"], &source);
+
+    assert!(success, "stderr:\n{}", stderr);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines.contains(&"found me"), "stdout:\n{}", stdout);
+    assert!(lines.contains(&"ttvm_set_cwd_test"), "stdout:\n{}", stdout);
+}
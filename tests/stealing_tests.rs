@@ -0,0 +1,50 @@
+use std::sync::{Arc, Mutex};
+use tiny_tot_vm::concurrency::{SchedulerPool, Scheduler, TinyProc};
+use tiny_tot_vm::OpCode;
+
+fn short_process(pid: u64) -> Arc<Mutex<TinyProc>> {
+    let (proc, _) = TinyProc::new(pid, vec![OpCode::PushInt(1), OpCode::Halt]);
+    Arc::new(Mutex::new(proc))
+}
+
+#[test]
+fn test_idle_scheduler_steals_half_of_a_busy_queue() {
+    let busy = Scheduler::new(0);
+    for pid in 1..=10 {
+        busy.add_process(short_process(pid));
+    }
+    let mut idle = Scheduler::new(1);
+    idle.remote_stealers.push(busy.local_queue.stealer());
+
+    let stolen = idle.steal_from_others().expect("Nothing was stolen");
+    let stolen_pid = stolen.lock().unwrap().id;
+    assert!(!idle.local_queue.is_empty(), "Only one process was taken");
+    assert!(busy.local_queue.len() >= 4, "Took too much: {} left", busy.local_queue.len());
+    assert_eq!(busy.local_queue.len() + idle.local_queue.len() + 1, 10);
+
+    // Every process is in exactly one place
+    let mut pids = vec![stolen_pid];
+    while let Some(proc) = busy.get_next_process().or_else(|| idle.get_next_process()) {
+        pids.push(proc.lock().unwrap().id);
+    }
+    pids.sort();
+    assert_eq!(pids, (1..=10).collect::<Vec<_>>());
+
+    assert!(idle.steal_from_others().is_none());
+}
+
+#[test]
+fn test_many_processes_spread_across_scheduler_threads() {
+    let mut pool = SchedulerPool::new_with_threads(4);
+    for _ in 0..64 {
+        pool.spawn_process((0..5000).map(OpCode::PushInt).collect());
+    }
+    pool.run().unwrap();
+    let slices = pool.slices_per_scheduler();
+    pool.wait_for_completion();
+
+    // Each process needs several slices
+    assert!(slices.iter().sum::<usize>() >= 64 * 5, "Slices: {:?}", slices);
+    let busy_threads = slices.iter().filter(|&&count| count > 0).count();
+    assert!(busy_threads >= 2, "All work ran on one thread: {:?}", slices);
+}
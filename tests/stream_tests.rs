@@ -0,0 +1,141 @@
+use std::fs;
+use tiny_tot_vm::vm::VM;
+use tiny_tot_vm::{OpCode, Value, VMError};
+
+fn run(program: Vec<OpCode>) -> Result<Vec<Value>, VMError> {
+    let mut vm = VM::new(program);
+    vm.run()?;
+    Ok(vm.stack)
+}
+
+fn read_next() -> [OpCode; 2] {
+    [OpCode::Load("lines".to_string()), OpCode::StreamRead]
+}
+
+#[test]
+fn test_stream_reads_a_file_line_by_line_until_eof() {
+    let path = std::env::temp_dir().join("ttvm_stream_lines.txt");
+    fs::write(&path, "first\nsecond\r\n\nlast").unwrap();
+
+    let mut program = vec![
+        OpCode::PushStr(path.to_string_lossy().into_owned()),
+        OpCode::StreamCreate,
+        OpCode::Store("lines".to_string()),
+    ];
+    for _ in 0..5 {
+        program.extend(read_next());
+    }
+    program.extend([
+        OpCode::Load("lines".to_string()),
+        OpCode::StreamClose,
+        OpCode::Load("lines".to_string()),
+        OpCode::StreamClose,
+        OpCode::Halt,
+    ]);
+
+    let stack = run(program).unwrap();
+    assert_eq!(stack, vec![
+        Value::Str("first".to_string()),
+        Value::Str("second".to_string()),
+        Value::Str("".to_string()),
+        Value::Str("last".to_string()),
+        Value::Null,
+        Value::Bool(true),
+        Value::Bool(false),
+    ]);
+}
+
+#[test]
+fn test_stream_read_after_close_is_an_error() {
+    let path = std::env::temp_dir().join("ttvm_stream_closed.txt");
+    fs::write(&path, "only line\n").unwrap();
+
+    let mut program = vec![
+        OpCode::PushStr(path.to_string_lossy().into_owned()),
+        OpCode::StreamCreate,
+        OpCode::Store("lines".to_string()),
+        OpCode::Load("lines".to_string()),
+        OpCode::StreamClose,
+    ];
+    program.extend(read_next());
+    program.push(OpCode::Halt);
+
+    match run(program) {
+        Err(VMError::FileError { error, .. }) => assert!(error.contains("not open"), "Unclear error: {}", error),
+        other => panic!("Expected a file error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_stream_create_on_missing_file_is_an_error() {
+    let missing = std::env::temp_dir().join("ttvm_stream_missing.txt");
+    let _ = fs::remove_file(&missing);
+
+    match run(vec![OpCode::PushStr(missing.to_string_lossy().into_owned()), OpCode::StreamCreate, OpCode::Halt]) {
+        Err(VMError::FileError { filename, .. }) => assert!(filename.ends_with("ttvm_stream_missing.txt")),
+        other => panic!("Expected a file error, got {:?}", other),
+    }
+}
+
+// Open `path` with `open`, then read it `reads` times
+fn read_stream(path: &std::path::Path, open: OpCode, reads: usize) -> Result<Vec<Value>, VMError> {
+    let mut program = vec![
+        OpCode::PushStr(path.to_string_lossy().into_owned()),
+        open,
+        OpCode::Store("lines".to_string()),
+    ];
+    for _ in 0..reads {
+        program.extend(read_next());
+    }
+    program.push(OpCode::Halt);
+    run(program)
+}
+
+fn object(fields: &[(&str, Value)]) -> Value {
+    Value::Object(fields.iter().map(|(key, value)| (key.to_string(), value.clone())).collect())
+}
+
+#[test]
+fn test_json_lines_stream_parses_one_value_per_read() {
+    let path = std::env::temp_dir().join("ttvm_stream_records.jsonl");
+    fs::write(&path, "{\"id\": 1, \"name\": \"Ada\"}\n\n{\"id\": 2, \"tags\": [\"x\", null], \"score\": 9.5}\r\n[true, false]\n").unwrap();
+
+    let stack = read_stream(&path, OpCode::StreamJsonLines, 4).unwrap();
+    assert_eq!(stack, vec![
+        object(&[("id", Value::Int(1)), ("name", Value::Str("Ada".to_string()))]),
+        object(&[
+            ("id", Value::Int(2)),
+            ("tags", Value::List(vec![Value::Str("x".to_string()), Value::Null].into())),
+            ("score", Value::Float(9.5)),
+        ]),
+        Value::List(vec![Value::Bool(true), Value::Bool(false)].into()),
+        Value::Null,
+    ]);
+}
+
+#[test]
+fn test_json_lines_stream_reports_the_bad_line() {
+    let path = std::env::temp_dir().join("ttvm_stream_bad.jsonl");
+    fs::write(&path, "{\"ok\": true}\n{\"broken\": }\n").unwrap();
+
+    match read_stream(&path, OpCode::StreamJsonLines, 2) {
+        Err(VMError::RuntimeError(message)) => assert!(message.contains("line 2"), "Unclear error: {}", message),
+        other => panic!("Expected a runtime error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_csv_stream_reads_one_row_per_read() {
+    let path = std::env::temp_dir().join("ttvm_stream_rows.csv");
+    fs::write(&path, "name,city\nAda,\"London, UK\"\n\"Multi\nline\",Paris\nshort\n").unwrap();
+
+    let row = |fields: &[&str]| Value::List(fields.iter().map(|field| Value::Str(field.to_string())).collect());
+    let stack = read_stream(&path, OpCode::StreamCsv, 5).unwrap();
+    assert_eq!(stack, vec![
+        row(&["name", "city"]),
+        row(&["Ada", "London, UK"]),
+        row(&["Multi\nline", "Paris"]),
+        row(&["short"]),
+        Value::Null,
+    ]);
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+use crossbeam::channel::unbounded;
+use tiny_tot_vm::concurrency::{Message, SchedulerPool, SharedTables};
+use tiny_tot_vm::{OpCode, Value, VMError};
+
+fn inserts(table: &Value, prefix: &str, count: i64) -> Vec<OpCode> {
+    let Value::Int(table) = table else { panic!("Table ids are ints") };
+    (0..count).flat_map(|i| [
+        OpCode::PushInt(*table),
+        OpCode::PushStr(format!("{}{}", prefix, i)),
+        OpCode::PushInt(i),
+        OpCode::TableInsert,
+    ]).collect()
+}
+
+fn lookup(table: &Value, key: &str) -> [OpCode; 3] {
+    let Value::Int(table) = table else { panic!("Table ids are ints") };
+    [OpCode::PushInt(*table), OpCode::PushStr(key.to_string()), OpCode::TableLookup]
+}
+
+#[test]
+fn test_processes_share_a_table() {
+    let mut pool = SchedulerPool::new_with_threads(2);
+    let table = pool.tables.new_table();
+
+    let (sender, results) = unbounded();
+    {
+        let mut registry = pool.process_registry.lock().unwrap();
+        registry.register_process(9000, sender).unwrap();
+        registry.register_name("results".to_string(), 9000).unwrap();
+    }
+
+    // Writes its own keys, then waits to hear that the other writer is done and reads one of its keys
+    let mut reader = inserts(&table, "b", 200);
+    reader.push(OpCode::Receive);
+    reader.extend(lookup(&table, "a199"));
+    reader.extend([OpCode::SendNamed("results".to_string()), OpCode::Halt]);
+    let (reader_pid, _) = pool.spawn_process(reader);
+
+    let mut writer = inserts(&table, "a", 200);
+    writer.extend([OpCode::PushStr("done".to_string()), OpCode::Send(reader_pid), OpCode::Halt]);
+    pool.spawn_process(writer);
+
+    let seen = match results.recv_timeout(Duration::from_secs(5)).expect("Reader reported nothing") {
+        Message::Value(value) => value,
+        other => panic!("Expected a value, got {:?}", other),
+    };
+    assert_eq!(seen, Value::Int(199));
+
+    pool.run().unwrap();
+    let tables = pool.tables.clone();
+    pool.wait_for_completion();
+    for i in 0..200 {
+        for prefix in ["a", "b"] {
+            let key = Value::Str(format!("{}{}", prefix, i));
+            assert_eq!(tables.lookup(&table, &key).unwrap(), Value::Int(i), "Wrong value for {:?}", key);
+        }
+    }
+}
+
+#[test]
+fn test_table_lookup_delete_and_errors() {
+    let tables = SharedTables::new();
+    let table = tables.new_table();
+    let key = Value::List(vec![Value::Str("user".to_string()), Value::Int(7)].into());
+
+    assert_eq!(tables.lookup(&table, &key).unwrap(), Value::Null);
+    tables.insert(&table, &key, &Value::Str("ada".to_string())).unwrap();
+    assert_eq!(tables.lookup(&table, &key).unwrap(), Value::Str("ada".to_string()));
+    tables.delete(&table, &key).unwrap();
+    assert_eq!(tables.lookup(&table, &key).unwrap(), Value::Null);
+
+    // Only plain values can be stored
+    let result = tables.insert(&table, &key, &Value::Function { addr: 0, params: vec![], returns: None });
+    assert!(matches!(result, Err(VMError::UnsupportedOperation(_))), "Got {:?}", result);
+
+    let result = tables.lookup(&Value::Int(99), &key);
+    assert!(matches!(result, Err(VMError::RuntimeError(_))), "Got {:?}", result);
+    let result = tables.lookup(&Value::Str("t".to_string()), &key);
+    assert!(matches!(result, Err(VMError::TypeMismatch { .. })), "Got {:?}", result);
+}
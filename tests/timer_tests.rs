@@ -0,0 +1,63 @@
+use std::time::{Duration, Instant};
+use crossbeam::channel::unbounded;
+use tiny_tot_vm::concurrency::{Message, SchedulerPool, Timers};
+use tiny_tot_vm::{OpCode, Value};
+
+fn me() -> OpCode {
+    OpCode::Whereis("ticker".to_string())
+}
+
+#[test]
+fn test_send_after_delivers_a_delayed_self_message() {
+    let mut pool = SchedulerPool::new_with_threads(2);
+    let (sender, results) = unbounded();
+    {
+        let mut registry = pool.process_registry.lock().unwrap();
+        registry.register_process(9000, sender).unwrap();
+        registry.register_name("results".to_string(), 9000).unwrap();
+    }
+
+    let started = Instant::now();
+    pool.spawn_process(vec![
+        OpCode::Register("ticker".to_string()),
+        me(),
+        OpCode::PushInt(200),
+        OpCode::PushStr("tick".to_string()),
+        OpCode::SendAfter,
+        // A cancelled timer never delivers, even though it would have been due first
+        me(),
+        OpCode::PushInt(50),
+        OpCode::PushStr("cancelled".to_string()),
+        OpCode::SendAfter,
+        OpCode::CancelTimer,
+        OpCode::SendNamed("results".to_string()),
+        OpCode::Receive,
+        OpCode::SendNamed("results".to_string()),
+        OpCode::Halt,
+    ]);
+
+    let next = || match results.recv_timeout(Duration::from_secs(5)).expect("No result arrived") {
+        Message::Value(value) => (value, started.elapsed()),
+        other => panic!("Expected a value message, got {:?}", other),
+    };
+    assert_eq!(next().0, Value::Bool(true));
+    let (received, elapsed) = next();
+    assert_eq!(received, Value::Str("tick".to_string()));
+    assert!(elapsed >= Duration::from_millis(200), "Delivered after only {:?}", elapsed);
+
+    pool.run().unwrap();
+    pool.wait_for_completion();
+}
+
+#[test]
+fn test_cancel_timer_only_succeeds_once() {
+    let timers = Timers::new();
+    let first = timers.send_after(1, Duration::from_secs(60), Message::Signal("later".to_string()));
+    let second = timers.send_after(1, Duration::from_secs(60), Message::Signal("later".to_string()));
+    assert_ne!(first, second);
+
+    assert!(timers.cancel(first));
+    assert!(!timers.cancel(first));
+    assert!(!timers.cancel(99));
+    assert!(timers.cancel(second));
+}
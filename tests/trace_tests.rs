@@ -0,0 +1,85 @@
+use std::process::Command;
+use tiny_tot_vm::vm::VM;
+use tiny_tot_vm::OpCode;
+
+fn trace_path(name: &str) -> String {
+    std::env::temp_dir().join(format!("{}.trace", name)).to_str().unwrap().to_string()
+}
+
+#[test]
+fn test_trace_file_has_one_line_per_executed_instruction() {
+    // Counts i down from 3, so the loop body runs several times
+    let program = vec![
+        OpCode::PushInt(3),
+        OpCode::Store("i".to_string()),
+        OpCode::Load("i".to_string()),   // 2: loop
+        OpCode::Jz(9),
+        OpCode::Load("i".to_string()),
+        OpCode::PushInt(1),
+        OpCode::Sub,
+        OpCode::Store("i".to_string()),
+        OpCode::Jmp(2),
+        OpCode::Halt,                    // 9
+    ];
+    let path = trace_path("ttvm_trace_loop");
+    let mut vm = VM::new(program);
+    vm.set_trace_file(&path).expect("Failed to open trace file");
+    vm.run().expect("Program failed");
+
+    let trace = std::fs::read_to_string(&path).expect("No trace file");
+    let lines: Vec<Vec<&str>> = trace.lines().map(|line| line.split('\t').collect()).collect();
+    assert_eq!(lines.len(), vm.instruction_count);
+    assert_eq!(lines[0], vec!["0", "0", "PUSH_INT 3"]);
+    assert_eq!(lines[3], vec!["3", "1", "JZ 9"]);
+    assert_eq!(lines.last().unwrap(), &vec!["9", "0", "HALT"]);
+    // 2 setup instructions, 3 passes through the 7-instruction body, then the last LOAD, JZ and HALT
+    assert_eq!(lines.len(), 2 + 3 * 7 + 3);
+}
+
+#[test]
+fn test_trace_out_keeps_print_on_stdout() {
+    let path = trace_path("ttvm_trace_cli");
+    let output = Command::new("cargo")
+        .args(["run", "--quiet", "--", "--trace-out", &path, "examples/debugger_test.ttvm"])
+        .output()
+        .expect("Failed to run ttvm");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "stderr:\n{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.lines().any(|line| line == "42"), "stdout:\n{}", stdout);
+    assert!(!stdout.contains("PUSH_INT") && !stdout.contains("[trace]"), "Trace leaked to stdout:\n{}", stdout);
+
+    let trace = std::fs::read_to_string(&path).expect("No trace file");
+    let instructions: Vec<&str> = trace.lines().map(|line| line.rsplit('\t').next().unwrap()).collect();
+    assert_eq!(instructions, vec!["PUSH_INT 10", "STORE x", "PUSH_INT 32", "LOAD x", "ADD", "PRINT", "HALT"]);
+}
+
+#[test]
+fn test_flamegraph_out_writes_nested_call_paths() {
+    // main calls outer at 3, which adds 1 to what inner at 7 returns (not a tail call, so both frames stay)
+    let source = "CALL 3\nPRINT\nHALT\nCALL 7\nPUSH_INT 1\nADD\nRET\nPUSH_INT 7\nRET\n";
+    let program_path = std::env::temp_dir().join("ttvm_flamegraph_nested.ttvm");
+    std::fs::write(&program_path, source).expect("Failed to write program");
+    let path = trace_path("ttvm_flamegraph_nested");
+    let output = Command::new("cargo")
+        .args(["run", "--quiet", "--", "--flamegraph-out", &path, program_path.to_str().unwrap()])
+        .output()
+        .expect("Failed to run ttvm");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "stderr:\n{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.lines().any(|line| line == "8"), "stdout:\n{}", stdout);
+
+    let folded = std::fs::read_to_string(&path).expect("No flamegraph file");
+    let stacks: Vec<(&str, usize)> = folded
+        .lines()
+        .map(|line| {
+            let (stack, count) = line.rsplit_once(' ').expect("Line without a count");
+            (stack, count.parse().expect("Count is not a number"))
+        })
+        .collect();
+    let paths: Vec<&str> = stacks.iter().map(|(stack, _)| *stack).collect();
+    assert_eq!(paths, vec!["main", "main;fn@0x0003", "main;fn@0x0003;fn@0x0007"], "folded:\n{}", folded);
+    // Every executed instruction is counted under exactly one path
+    assert_eq!(stacks.iter().map(|(_, count)| count).sum::<usize>(), 9);
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use indexmap::IndexMap;
+use tiny_tot_vm::vm::value::format_scope;
+use tiny_tot_vm::vm::{OutputBuffer, OutputSink, VM};
+use tiny_tot_vm::{OpCode, VMConfig, Value};
+
+fn shown(n: f64) -> String {
+    Value::Float(n).to_string()
+}
+
+#[test]
+fn test_whole_floats_keep_a_decimal_point() {
+    assert_eq!(shown(1.0), "1.0");
+    assert_eq!(shown(-42.0), "-42.0");
+    assert_eq!(shown(0.0), "0.0");
+    assert_eq!(shown(2.5), "2.5");
+    assert_eq!(shown(0.1 + 0.2), "0.30000000000000004");
+    assert_eq!(Value::List(vec![Value::Int(1), Value::Float(1.0)].into()).to_string(), "[1, 1.0]");
+}
+
+#[test]
+fn test_very_small_and_very_large_floats_use_an_exponent() {
+    assert_eq!(shown(0.00001), "0.00001");
+    assert_eq!(shown(0.000001), "1.0e-6");
+    assert_eq!(shown(-2.5e-7), "-2.5e-7");
+    assert_eq!(shown(1e15), "1000000000000000.0");
+    assert_eq!(shown(1e16), "1.0e16");
+    assert_eq!(shown(1e21), "1.0e21");
+    assert_eq!(shown(f64::MAX), "1.7976931348623157e308");
+    assert_eq!(shown(f64::MIN_POSITIVE), "2.2250738585072014e-308");
+}
+
+#[test]
+fn test_float_precision_is_per_vm() {
+    let program = vec![
+        OpCode::PushFloat(1.23456),
+        OpCode::Print,
+        OpCode::PushFloat(1e21),
+        OpCode::PushFloat(2.0),
+        OpCode::MakeList(2),
+        OpCode::Print,
+        OpCode::Halt,
+    ];
+    let printed = |float_precision: Option<usize>| {
+        let config = VMConfig { float_precision, ..VMConfig::default() };
+        let mut vm = VM::with_config(program.clone(), &config);
+        let output = OutputBuffer::new();
+        vm.set_output(OutputSink::new(output.clone()));
+        vm.run().expect("Program failed");
+        String::from_utf8(output.bytes()).unwrap()
+    };
+
+    // Neither VM's setting leaks into the other, or into plain Display
+    assert_eq!(printed(Some(2)), "1.23\n[1.00e21, 2.00]\n");
+    assert_eq!(printed(None), "1.23456\n[1.0e21, 2.0]\n");
+    assert_eq!(Value::Float(1.23456).display(Some(1)).to_string(), "1.2");
+    assert_eq!(shown(1.23456), "1.23456");
+}
+
+fn nested() -> Value {
+    let mut address = IndexMap::new();
+    address.insert("city".to_string(), Value::Str("Paris".to_string()));
+    address.insert("zip".to_string(), Value::Null);
+    let mut person = IndexMap::new();
+    person.insert("name".to_string(), Value::Str("Ada \"the first\"".to_string()));
+    person.insert("scores".to_string(), Value::List(vec![Value::Int(3), Value::Float(2.5), Value::List(vec![].into())].into()));
+    person.insert("address".to_string(), Value::Object(address.into()));
+    person.insert("active".to_string(), Value::Bool(true));
+    Value::Object(person.into())
+}
+
+#[test]
+fn test_pretty_dump_of_nested_values() {
+    assert_eq!(nested().pretty(), "\
+object (4 fields) {
+  name: str \"Ada \\\"the first\\\"\"
+  scores: list (3 items) [
+    [0]: int 3
+    [1]: float 2.5
+    [2]: list []
+  ]
+  address: object (2 fields) {
+    city: str \"Paris\"
+    zip: null
+  }
+  active: bool true
+}");
+    assert_eq!(Value::List(vec![Value::Object(IndexMap::new().into())].into()).pretty(), "list (1 item) [\n  [0]: object {}\n]");
+}
+
+#[test]
+fn test_scope_dump_uses_the_pretty_layout() {
+    let mut scope = HashMap::new();
+    scope.insert("total".to_string(), Value::Int(7));
+    scope.insert("items".to_string(), Value::List(vec![Value::Str("a".to_string())].into()));
+    assert_eq!(format_scope(&scope), "Current scope:\n  items: list (1 item) [\n    [0]: str \"a\"\n  ]\n  total: int 7");
+    assert_eq!(format_scope(&HashMap::new()), "Current scope: (empty)");
+}
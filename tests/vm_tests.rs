@@ -0,0 +1,1370 @@
+use tiny_tot_vm::concurrency::TinyProc;
+use tiny_tot_vm::vm::{OutputBuffer, OutputSink, RunStatus, VM};
+use tiny_tot_vm::bytecode::{parse_program_with_lines, parse_source};
+use tiny_tot_vm::{OpCode, Value, VMError};
+
+fn run_program(program: Vec<OpCode>) -> VM {
+    let mut vm = VM::new(program);
+    vm.run().expect("Program failed");
+    vm
+}
+
+#[test]
+fn test_function_reads_global() {
+    let vm = run_program(vec![
+        OpCode::PushInt(42),
+        OpCode::Store("answer".to_string()),
+        OpCode::Call { addr: 4, params: vec![] },
+        OpCode::Halt,
+        OpCode::Load("answer".to_string()), // 4: reads the global
+        OpCode::Ret,
+    ]);
+
+    assert_eq!(vm.stack, vec![Value::Int(42)]);
+}
+
+#[test]
+fn test_local_shadows_global() {
+    let vm = run_program(vec![
+        OpCode::PushInt(1),
+        OpCode::Store("x".to_string()),
+        OpCode::Call { addr: 5, params: vec![] },
+        OpCode::Load("x".to_string()),
+        OpCode::Halt,
+        OpCode::PushInt(2), // 5
+        OpCode::Store("x".to_string()),
+        OpCode::Load("x".to_string()),
+        OpCode::Ret,
+    ]);
+
+    // The function sees its local, while the global is left unchanged
+    assert_eq!(vm.stack, vec![Value::Int(2), Value::Int(1)]);
+}
+
+#[test]
+fn test_caller_locals_are_not_visible() {
+    let mut vm = VM::new(vec![
+        OpCode::Call { addr: 2, params: vec![] },
+        OpCode::Halt,
+        OpCode::PushInt(7), // 2: outer function local
+        OpCode::Store("outer_local".to_string()),
+        OpCode::Call { addr: 6, params: vec![] },
+        OpCode::Ret,
+        OpCode::Load("outer_local".to_string()), // 6: inner function
+        OpCode::Ret,
+    ]);
+
+    match vm.run() {
+        Err(VMError::UndefinedVariable(name)) => assert_eq!(name, "outer_local"),
+        other => panic!("Expected UndefinedVariable, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_tail_recursive_countdown_reuses_frame() {
+    let n = "n".to_string();
+    let vm = run_program(vec![
+        OpCode::PushInt(100_000),
+        OpCode::Call { addr: 3, params: vec![n.clone()] },
+        OpCode::Halt,
+        OpCode::Load(n.clone()), // 3: countdown(n)
+        OpCode::Jz(10),
+        OpCode::Load(n.clone()),
+        OpCode::PushInt(1),
+        OpCode::Sub,
+        OpCode::Call { addr: 3, params: vec![n.clone()] },
+        OpCode::Ret,
+        OpCode::PushStr("done".to_string()), // 10
+        OpCode::Ret,
+    ]);
+
+    assert_eq!(vm.stack, vec![Value::Str("done".to_string())]);
+    assert_eq!(vm.max_call_depth, 1);
+    assert!(vm.call_stack.is_empty());
+    assert_eq!(vm.variables.len(), 1);
+}
+
+#[test]
+fn test_tail_call_closure_keeps_captures() {
+    let n = "n".to_string();
+    let vm = run_program(vec![
+        OpCode::PushInt(2),
+        OpCode::Store("step".to_string()),
+        OpCode::Capture("step".to_string()),
+        OpCode::MakeLambda { addr: 9, params: vec![n.clone()] },
+        OpCode::Store("countdown".to_string()),
+        OpCode::PushInt(10_000),
+        OpCode::Load("countdown".to_string()),
+        OpCode::CallFunction,
+        OpCode::Halt,
+        OpCode::Load(n.clone()), // 9: closure body
+        OpCode::Jz(17),
+        OpCode::Load(n.clone()),
+        OpCode::Load("step".to_string()),
+        OpCode::Sub,
+        OpCode::Load("countdown".to_string()),
+        OpCode::CallFunction,
+        OpCode::Ret,
+        OpCode::Load("step".to_string()), // 17
+        OpCode::Ret,
+    ]);
+
+    assert_eq!(vm.stack, vec![Value::Int(2)]);
+    assert_eq!(vm.max_call_depth, 1);
+}
+
+#[test]
+fn test_non_tail_call_grows_call_stack() {
+    let n = "n".to_string();
+    let vm = run_program(vec![
+        OpCode::PushInt(5),
+        OpCode::Call { addr: 3, params: vec![n.clone()] },
+        OpCode::Halt,
+        OpCode::Load(n.clone()), // 3: sum(n) = n + sum(n - 1)
+        OpCode::Jz(12),
+        OpCode::Load(n.clone()),
+        OpCode::Load(n.clone()),
+        OpCode::PushInt(1),
+        OpCode::Sub,
+        OpCode::Call { addr: 3, params: vec![n.clone()] },
+        OpCode::Add,
+        OpCode::Ret,
+        OpCode::PushInt(0), // 12
+        OpCode::Ret,
+    ]);
+
+    assert_eq!(vm.stack, vec![Value::Int(15)]);
+    assert_eq!(vm.max_call_depth, 6);
+}
+
+// recurse(): PUSH_NULL; CALL recurse; PRINT; RET -- never terminates, never in tail position
+fn runaway_recursion(prefix: Vec<OpCode>) -> Vec<OpCode> {
+    let base = prefix.len();
+    let mut program = prefix;
+    program.extend(vec![
+        OpCode::Null,
+        OpCode::Call { addr: base, params: vec![] },
+        OpCode::Print,
+        OpCode::Ret,
+    ]);
+    program
+}
+
+#[test]
+fn test_call_depth_limit_raises_overflow() {
+    let mut vm = VM::new(runaway_recursion(vec![]));
+    vm.call_depth_limit = 500;
+
+    match vm.run() {
+        Err(VMError::CallStackOverflow { depth }) => assert_eq!(depth, 500),
+        other => panic!("Expected CallStackOverflow, got {:?}", other),
+    }
+    assert_eq!(vm.call_stack.len(), 500);
+}
+
+#[test]
+fn test_call_stack_overflow_is_catchable() {
+    let vm = run_program(runaway_recursion(vec![
+        OpCode::Try { catch_addr: 3 },
+        OpCode::Call { addr: 5, params: vec![] },
+        OpCode::Halt,
+        OpCode::Catch, // 3
+        OpCode::Halt,
+    ]));
+
+    match &vm.stack[..] {
+        [Value::Exception { message, .. }] => assert!(message.contains("Call stack overflow"), "{}", message),
+        other => panic!("Expected a caught exception, got {:?}", other),
+    }
+    assert!(vm.call_stack.is_empty());
+}
+
+fn expect_overflow(a: i64, b: i64, op: OpCode, expected_operation: &str) {
+    let mut vm = VM::new(vec![OpCode::PushInt(a), OpCode::PushInt(b), op, OpCode::Halt]);
+    match vm.run() {
+        Err(VMError::IntegerOverflow { operation }) => assert_eq!(operation, expected_operation),
+        other => panic!("Expected IntegerOverflow, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_integer_overflow_is_detected() {
+    expect_overflow(i64::MAX, 1, OpCode::Add, "ADD");
+    expect_overflow(i64::MIN, 1, OpCode::Sub, "SUB");
+    expect_overflow(i64::MAX, 2, OpCode::Mul, "MUL");
+    expect_overflow(i64::MIN, -1, OpCode::Div, "DIV");
+}
+
+#[test]
+fn test_integer_overflow_is_catchable() {
+    let vm = run_program(vec![
+        OpCode::Try { catch_addr: 5 },
+        OpCode::PushInt(i64::MAX),
+        OpCode::PushInt(1),
+        OpCode::Add,
+        OpCode::Halt,
+        OpCode::Catch, // 5
+        OpCode::Halt,
+    ]);
+
+    match &vm.stack[..] {
+        [Value::Exception { message, .. }] => assert!(message.contains("Integer overflow during ADD"), "{}", message),
+        other => panic!("Expected a caught exception, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_float_arithmetic_does_not_overflow_error() {
+    let vm = run_program(vec![
+        OpCode::PushFloat(f64::MAX),
+        OpCode::PushFloat(f64::MAX),
+        OpCode::Add,
+        OpCode::Halt,
+    ]);
+
+    assert_eq!(vm.stack, vec![Value::Float(f64::INFINITY)]);
+}
+
+#[test]
+fn test_stats_report_live_heap() {
+    // rows = [[1, 2], [3, 4]], user = { name: "ada", rows: rows }
+    let vm = run_program(vec![
+        OpCode::PushInt(1),
+        OpCode::PushInt(2),
+        OpCode::MakeList(2),
+        OpCode::PushInt(3),
+        OpCode::PushInt(4),
+        OpCode::MakeList(2),
+        OpCode::MakeList(2),
+        OpCode::Store("rows".to_string()),
+        OpCode::MakeObject,
+        OpCode::PushStr("ada".to_string()),
+        OpCode::SetField("name".to_string()),
+        OpCode::Load("rows".to_string()),
+        OpCode::SetField("rows".to_string()),
+        OpCode::Store("user".to_string()),
+        OpCode::Halt,
+    ]);
+
+    let (_, _, final_stack, live_objects, heap_bytes) = vm.get_stats();
+    assert_eq!(final_stack, 0);
+    // Three lists under "rows", another copy of them plus the object under "user"
+    assert_eq!(live_objects, 7);
+    let slot = std::mem::size_of::<Value>();
+    assert_eq!(heap_bytes, 2 * 6 * slot + "ada".len() + "name".len() + "rows".len() + 2 * slot);
+
+    let gc_stats = vm.get_gc_stats();
+    assert_eq!((gc_stats.live_objects, gc_stats.heap_bytes), (live_objects, heap_bytes));
+    assert_eq!(run_program(vec![OpCode::PushInt(1), OpCode::Halt]).get_stats().3, 0);
+}
+
+fn compare_floats(a: f64, b: f64, op: OpCode) -> Value {
+    let vm = run_program(vec![OpCode::PushFloat(a), OpCode::PushFloat(b), op, OpCode::Halt]);
+    vm.stack[0].clone()
+}
+
+#[test]
+fn test_float_comparisons_are_exact_and_false_for_nan() {
+    assert_eq!(compare_floats(f64::NAN, f64::NAN, OpCode::EqF), Value::Int(0));
+    assert_eq!(compare_floats(f64::NAN, f64::NAN, OpCode::Eq), Value::Int(0));
+    assert_eq!(compare_floats(f64::NAN, f64::NAN, OpCode::NeF), Value::Int(1));
+    assert_eq!(compare_floats(f64::NAN, 1.0, OpCode::LtF), Value::Int(0));
+    assert_eq!(compare_floats(f64::NAN, 1.0, OpCode::GeF), Value::Int(0));
+    assert_eq!(compare_floats(f64::INFINITY, 1.0, OpCode::GtF), Value::Int(1));
+    assert_eq!(compare_floats(f64::NEG_INFINITY, f64::MIN, OpCode::LtF), Value::Int(1));
+    // No tolerance: tiny values that differ are not equal, and 0.1 + 0.2 is not 0.3
+    assert_eq!(compare_floats(1e-20, 2e-20, OpCode::EqF), Value::Int(0));
+    assert_eq!(compare_floats(0.1 + 0.2, 0.3, OpCode::Eq), Value::Int(0));
+    assert_eq!(compare_floats(1e300, 1e300, OpCode::EqF), Value::Int(1));
+}
+
+#[test]
+fn test_float_arithmetic_never_produces_nan() {
+    let mut vm = VM::new(vec![OpCode::PushFloat(0.0), OpCode::PushFloat(0.0), OpCode::DivF, OpCode::Halt]);
+    assert!(matches!(vm.run(), Err(VMError::TypeMismatch { .. })));
+
+    for (a, b, op, name) in [
+        (f64::INFINITY, f64::INFINITY, OpCode::SubF, "SUB_F"),
+        (f64::INFINITY, 0.0, OpCode::Mul, "MUL"),
+        (f64::NAN, 1.0, OpCode::AddF, "ADD_F"),
+    ] {
+        let mut vm = VM::new(vec![OpCode::PushFloat(a), OpCode::PushFloat(b), op, OpCode::Halt]);
+        match vm.run() {
+            Err(VMError::NotANumber { operation }) => assert_eq!(operation, name),
+            other => panic!("Expected NotANumber from {}, got {:?}", name, other),
+        }
+    }
+}
+
+fn run_source(name: &str, source: &str) -> (VM, Result<(), VMError>) {
+    let path = std::env::temp_dir().join(format!("{}.ttvm", name));
+    std::fs::write(&path, source).expect("Failed to write source");
+    let (program, lines) = parse_program_with_lines(path.to_str().unwrap()).expect("Failed to parse program");
+    let mut vm = VM::new(program);
+    vm.set_source_lines(lines);
+    let result = vm.run();
+    (vm, result)
+}
+
+#[test]
+fn test_runtime_error_reports_source_line() {
+    let source = "; adds a string to an int\n\nPUSH_INT 1\nLABEL here\nPUSH_STR \"two\"   ; not a number\nADD\nPRINT\n";
+    let (_, result) = run_source("type_error_line", source);
+
+    match result {
+        Err(VMError::AtSourceLine { line, ip, error }) => {
+            assert_eq!(line, 6);
+            assert_eq!(ip, 2);
+            assert!(matches!(*error, VMError::TypeMismatch { .. }));
+        }
+        other => panic!("Expected located TypeMismatch, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_caught_error_trace_includes_source_line() {
+    let source = "TRY catch\nPUSH_INT 1\nPUSH_STR \"x\"\nADD\nEND_TRY\nHALT\nLABEL catch\nCATCH\nHALT\n";
+    let (vm, result) = run_source("caught_error_line", source);
+
+    result.expect("Program failed");
+    match vm.stack.last() {
+        Some(Value::Exception { stack_trace, .. }) => {
+            assert_eq!(stack_trace, &vec!["at instruction 3 (line 4)".to_string()]);
+        }
+        other => panic!("Expected exception on stack, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_runtime_error_without_source_lines_is_unchanged() {
+    let mut vm = VM::new(vec![OpCode::PushInt(1), OpCode::PushStr("two".to_string()), OpCode::Add]);
+    assert!(matches!(vm.run(), Err(VMError::TypeMismatch { .. })));
+}
+
+#[test]
+fn test_exit_returns_status_instead_of_exiting() {
+    let (vm, result) = run_source("exit_status", "PUSH_INT 7\nPUSH_INT 3\nEXIT\nPUSH_INT 9\n");
+
+    assert!(matches!(result, Err(VMError::ProcessExit(3))), "got {:?}", result);
+    assert_eq!(vm.stack, vec![Value::Int(7)]);
+}
+
+#[test]
+fn test_exit_is_not_caught_by_try() {
+    let source = "TRY catch\nPUSH_INT 3\nEXIT\nEND_TRY\nHALT\nLABEL catch\nCATCH\nHALT\n";
+    let (_, result) = run_source("exit_in_try", source);
+
+    assert!(matches!(result, Err(VMError::ProcessExit(3))), "got {:?}", result);
+}
+
+#[test]
+fn test_print_output_can_be_captured() {
+    let mut vm = VM::new(vec![
+        OpCode::PushInt(42),
+        OpCode::Print,
+        OpCode::PushStr("no newline".to_string()),
+        OpCode::PrintRaw,
+        OpCode::Halt,
+    ]);
+    let output = OutputBuffer::new();
+    vm.set_output(OutputSink::new(output.clone()));
+    vm.run().expect("Program failed");
+
+    assert_eq!(output.bytes(), b"42\nno newline".to_vec());
+}
+
+#[test]
+fn test_stack_limit_raises_overflow() {
+    let mut vm = VM::new((0..100).map(OpCode::PushInt).collect());
+    vm.stack_limit = Some(10);
+
+    match vm.run() {
+        Err(VMError::StackOverflow { limit }) => assert_eq!(limit, 10),
+        other => panic!("Expected StackOverflow, got {:?}", other),
+    }
+    assert_eq!(vm.ip, 10);
+}
+
+#[test]
+fn test_stack_overflow_is_catchable() {
+    let mut program = vec![OpCode::Try { catch_addr: 1 + 20 + 2 }];
+    program.extend((0..20).map(OpCode::PushInt));
+    program.extend(vec![OpCode::EndTry, OpCode::Halt, OpCode::Catch, OpCode::Halt]);
+    let mut vm = VM::new(program);
+    vm.stack_limit = Some(5);
+    vm.run().expect("Program failed");
+
+    match &vm.stack[..] {
+        [Value::Exception { message, .. }] => assert!(message.contains("Stack overflow"), "{}", message),
+        other => panic!("Expected a caught exception, got {:?}", other),
+    }
+}
+
+// Run one time instruction on two pushed operands and return what it left on the stack
+fn run_time_op(operand: Value, format: &str, op: OpCode) -> Result<Value, VMError> {
+    let mut vm = VM::new(vec![OpCode::PushStr(format.to_string()), op, OpCode::Halt]);
+    vm.stack.push(operand);
+    vm.run()?;
+    Ok(vm.stack.pop().expect("Time operation left nothing on the stack"))
+}
+
+#[test]
+fn test_format_time_uses_format_string() {
+    let format = |timestamp: i64, format: &str| run_time_op(Value::Int(timestamp), format, OpCode::FormatTime);
+
+    assert_eq!(format(0, "%Y-%m-%d %H:%M:%S").unwrap(), Value::Str("1970-01-01 00:00:00".to_string()));
+    assert_eq!(format(1_700_000_000, "%Y-%m-%dT%H:%M:%SZ").unwrap(), Value::Str("2023-11-14T22:13:20Z".to_string()));
+    assert_eq!(format(1_709_210_096, "%a %d %b %Y, %I:%M %p").unwrap(), Value::Str("Thu 29 Feb 2024, 12:34 PM".to_string()));
+    assert_eq!(format(-86_400, "%j/%y").unwrap(), Value::Str("365/69".to_string()));
+}
+
+#[test]
+fn test_format_time_rejects_invalid_format() {
+    let result = run_time_op(Value::Int(0), "%Y-%Q", OpCode::FormatTime);
+    assert!(matches!(result, Err(VMError::RuntimeError(ref message)) if message.contains("%Y-%Q")), "Got {:?}", result);
+
+    let result = run_time_op(Value::Int(i64::MAX), "%Y", OpCode::FormatTime);
+    assert!(matches!(result, Err(VMError::RuntimeError(_))), "Got {:?}", result);
+}
+
+#[test]
+fn test_parse_time_inverts_format_time() {
+    let parse = |text: &str, format: &str| run_time_op(Value::Str(text.to_string()), format, OpCode::ParseTime);
+
+    assert_eq!(parse("2023-11-14 22:13:20", "%Y-%m-%d %H:%M:%S").unwrap(), Value::Int(1_700_000_000));
+    // Dates without a time of day are midnight UTC
+    assert_eq!(parse("2024-02-29", "%Y-%m-%d").unwrap(), Value::Int(1_709_164_800));
+
+    let formatted = run_time_op(Value::Int(1_234_567_890), "%d/%m/%Y %H:%M:%S", OpCode::FormatTime).unwrap();
+    let Value::Str(text) = formatted else { panic!("FORMAT_TIME did not push a string") };
+    assert_eq!(parse(&text, "%d/%m/%Y %H:%M:%S").unwrap(), Value::Int(1_234_567_890));
+
+    assert!(matches!(parse("not a date", "%Y-%m-%d"), Err(VMError::RuntimeError(_))));
+}
+
+fn run_csv_op(operand: Value, op: OpCode) -> Result<Value, VMError> {
+    let mut vm = VM::new(vec![op, OpCode::Halt]);
+    vm.stack.push(operand);
+    vm.run()?;
+    Ok(vm.stack.pop().expect("CSV operation left nothing on the stack"))
+}
+
+fn csv_row(fields: &[&str]) -> Value {
+    Value::List(fields.iter().map(|field| Value::Str(field.to_string())).collect())
+}
+
+#[test]
+fn test_csv_parse_handles_quoting() {
+    let text = "name,quote\n\"Smith, Ann\",\"a,\"\"b\"\",c\"\n\"multi\nline\",plain\nshort";
+    let parsed = run_csv_op(Value::Str(text.to_string()), OpCode::CsvParse).unwrap();
+    assert_eq!(parsed, Value::List(vec![
+        csv_row(&["name", "quote"]),
+        csv_row(&["Smith, Ann", "a,\"b\",c"]),
+        csv_row(&["multi\nline", "plain"]),
+        csv_row(&["short"]),
+    ].into()));
+}
+
+#[test]
+fn test_csv_write_quotes_and_round_trips() {
+    let rows = Value::List(vec![
+        csv_row(&["id", "text"]),
+        csv_row(&["1", "a,\"b\",c"]),
+        csv_row(&["2", "two\nlines"]),
+        Value::List(vec![Value::Int(3), Value::Bool(true)].into()),
+    ].into());
+    let written = run_csv_op(rows, OpCode::CsvWrite).unwrap();
+    assert_eq!(written, Value::Str("id,text\n1,\"a,\"\"b\"\",c\"\n2,\"two\nlines\"\n3,true".to_string()));
+
+    let reparsed = run_csv_op(written, OpCode::CsvParse).unwrap();
+    assert_eq!(reparsed, Value::List(vec![
+        csv_row(&["id", "text"]),
+        csv_row(&["1", "a,\"b\",c"]),
+        csv_row(&["2", "two\nlines"]),
+        csv_row(&["3", "true"]),
+    ].into()));
+
+    let result = run_csv_op(Value::List(vec![Value::Int(1)].into()), OpCode::CsvWrite);
+    assert!(matches!(result, Err(VMError::TypeMismatch { .. })), "Got {:?}", result);
+}
+
+// An object with a dozen fields, followed by KEYS, VALUES and ENTRIES of that same object
+fn object_iteration_program() -> Vec<OpCode> {
+    let mut program = vec![OpCode::MakeObject];
+    for i in 0..12 {
+        program.push(OpCode::PushInt(i * 10));
+        program.push(OpCode::SetField(format!("field{}", i)));
+    }
+    program.extend([
+        OpCode::Store("obj".to_string()),
+        OpCode::Load("obj".to_string()),
+        OpCode::Keys,
+        OpCode::Load("obj".to_string()),
+        OpCode::Values,
+        OpCode::Load("obj".to_string()),
+        OpCode::Entries,
+        OpCode::Halt,
+    ]);
+    program
+}
+
+fn list(value: &Value) -> &Vec<Value> {
+    match value {
+        Value::List(items) => items,
+        other => panic!("Expected a list, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_keys_values_entries_share_ordering() {
+    let vm = run_program(object_iteration_program());
+    let (keys, values, entries) = (list(&vm.stack[0]), list(&vm.stack[1]), list(&vm.stack[2]));
+    assert_eq!(keys.len(), 12);
+    assert_eq!(values.len(), 12);
+    assert_eq!(entries.len(), 12);
+
+    for ((key, value), entry) in keys.iter().zip(values).zip(entries) {
+        assert_eq!(list(entry), &vec![key.clone(), value.clone()]);
+        let Value::Str(name) = key else { panic!("Key {:?} is not a string", key) };
+        let index: i64 = name.trim_start_matches("field").parse().unwrap();
+        assert_eq!(value, &Value::Int(index * 10));
+    }
+}
+
+#[test]
+fn test_keys_follow_insertion_order() {
+    let vm = run_program(parse_source("\
+MAKE_OBJECT
+PUSH_INT 1
+SET_FIELD zebra
+PUSH_INT 2
+SET_FIELD apple
+PUSH_INT 3
+SET_FIELD mango
+PUSH_INT 4
+SET_FIELD kiwi
+PUSH_INT 5
+SET_FIELD banana
+STORE fruit
+PUSH_INT 20
+SET_FIELD_MUT fruit apple
+LOAD fruit
+DELETE_FIELD mango
+PUSH_INT 6
+SET_FIELD cherry
+STORE fruit
+LOAD fruit
+KEYS
+LOAD fruit
+HALT
+").expect("Failed to parse program").0);
+
+    // Overwriting a field keeps its position; deleting one leaves the rest in order
+    let names = ["zebra", "apple", "kiwi", "banana", "cherry"];
+    assert_eq!(vm.stack[0], Value::List(names.iter().map(|n| Value::Str(n.to_string())).collect()));
+    assert_eq!(vm.stack[1].to_string(), "{zebra: 1, apple: 20, kiwi: 4, banana: 5, cherry: 6}");
+}
+
+// An object with scrambled key names, built one SET_FIELD at a time, then its KEYS and its JSON
+// text, and that text parsed back
+fn keys_and_json_program() -> Vec<OpCode> {
+    let mut program = vec![OpCode::MakeObject];
+    for i in 0..32 {
+        program.push(OpCode::PushInt(i));
+        program.push(OpCode::SetField(format!("key{}", (i * 7) % 32)));
+    }
+    program.push(OpCode::PushStr("say \"hi\"\n".to_string()));
+    program.push(OpCode::PushFloat(2.0));
+    program.push(OpCode::MakeList(2));
+    program.push(OpCode::SetField("mixed".to_string()));
+    program.extend([
+        OpCode::Store("obj".to_string()),
+        OpCode::Load("obj".to_string()),
+        OpCode::Keys,
+        OpCode::Load("obj".to_string()),
+        OpCode::JsonStringify,
+        OpCode::Dup,
+        OpCode::JsonParse,
+        OpCode::Load("obj".to_string()),
+        OpCode::Halt,
+    ]);
+    program
+}
+
+#[test]
+fn test_keys_and_json_stringify_are_deterministic() {
+    let first = run_program(keys_and_json_program());
+    // A second VM that collects after every allocation, so the object is cloned and traced
+    let mut second = VM::new(keys_and_json_program());
+    second.set_gc_threshold(1);
+    second.run().expect("Program failed");
+
+    assert_eq!(first.stack, second.stack);
+    let mut names: Vec<String> = (0..32).map(|i| format!("key{}", (i * 7) % 32)).collect();
+    names.push("mixed".to_string());
+    assert_eq!(first.stack[0], Value::List(names.iter().map(|n| Value::Str(n.clone())).collect()));
+
+    let json = &first.stack[1];
+    let expected_start = "{\"key0\":0,\"key7\":1,\"key14\":2,";
+    assert!(matches!(json, Value::Str(text) if text.starts_with(expected_start)), "{:?}", json);
+    assert!(matches!(json, Value::Str(text) if text.ends_with(",\"mixed\":[\"say \\\"hi\\\"\\n\",2.0]}")), "{:?}", json);
+    // The text parses back into the same object, float included
+    assert_eq!(first.stack[2], first.stack[3]);
+}
+
+#[test]
+fn test_json_parse_limits_nesting() {
+    let parse = |text: String| {
+        let mut vm = VM::new(vec![OpCode::PushStr(text), OpCode::JsonParse, OpCode::Halt]);
+        vm.run().map(|_| vm.stack)
+    };
+    let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+    assert!(parse(nested(128)).is_ok());
+    // Deep enough to overflow the stack without the limit
+    for text in [nested(129), "[".repeat(200_000), "{\"a\":".repeat(200_000)] {
+        match parse(text) {
+            Err(VMError::RuntimeError(message)) => assert!(message.contains("nesting deeper than 128"), "{}", message),
+            other => panic!("Expected JSON_PARSE to fail, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_json_stringify_rejects_values_without_a_json_form() {
+    let mut vm = VM::new(vec![OpCode::MakeFunction { addr: 0, params: vec![], returns: None }, OpCode::JsonStringify, OpCode::Halt]);
+    match vm.run() {
+        Err(VMError::RuntimeError(message)) => assert!(message.contains("cannot be converted to JSON"), "{}", message),
+        other => panic!("Expected JSON_STRINGIFY to fail, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_values_and_entries_reject_non_objects() {
+    for op in [OpCode::Values, OpCode::Entries] {
+        let mut vm = VM::new(vec![OpCode::PushInt(1), OpCode::MakeList(1), op.clone(), OpCode::Halt]);
+        let result = vm.run();
+        assert!(matches!(result, Err(VMError::TypeMismatch { .. })), "{:?} gave {:?}", op, result);
+    }
+}
+
+// Build an object with `fields` fields held in variable `m`, either through SET_FIELD_MUT or
+// the LOAD / SET_FIELD / STORE round trip, which copies the whole object on every LOAD
+fn object_build_program(fields: usize, in_place: bool) -> Vec<OpCode> {
+    let mut program = vec![OpCode::MakeObject, OpCode::Store("m".to_string())];
+    for i in 0..fields {
+        let field = format!("f{}", i);
+        if in_place {
+            program.push(OpCode::PushInt(i as i64));
+            program.push(OpCode::SetFieldMut { var: "m".to_string(), field });
+        } else {
+            program.push(OpCode::Load("m".to_string()));
+            program.push(OpCode::PushInt(i as i64));
+            program.push(OpCode::SetField(field));
+            program.push(OpCode::Store("m".to_string()));
+        }
+    }
+    program.extend([OpCode::Load("m".to_string()), OpCode::Len, OpCode::Halt]);
+    program
+}
+
+#[test]
+fn test_set_field_mut_updates_variable_in_place() {
+    let vm = run_program(parse_source("\
+MAKE_OBJECT
+PUSH_STR \"Ada\"
+SET_FIELD name
+STORE person
+PUSH_INT 36
+SET_FIELD_MUT person age
+PUSH_STR \"Grace\"
+SET_FIELD_MUT person name
+LOAD person
+HALT
+").expect("Failed to parse program").0);
+
+    let Some(Value::Object(person)) = vm.stack.last() else { panic!("Expected an object, got {:?}", vm.stack) };
+    assert_eq!(person.get("name"), Some(&Value::Str("Grace".to_string())));
+    assert_eq!(person.get("age"), Some(&Value::Int(36)));
+    assert_eq!(vm.stack.len(), 1, "SET_FIELD_MUT should not push anything");
+
+    let mut vm = VM::new(vec![
+        OpCode::PushInt(1),
+        OpCode::Store("n".to_string()),
+        OpCode::PushInt(2),
+        OpCode::SetFieldMut { var: "n".to_string(), field: "x".to_string() },
+        OpCode::Halt,
+    ]);
+    assert!(matches!(vm.run(), Err(VMError::TypeMismatch { .. })));
+}
+
+// 10k-field build loop. On a release build the copying version takes about 3.9s and
+// SET_FIELD_MUT under 20ms; debug builds are slower but the gap is just as wide.
+// Wall-clock timing is at the mercy of the machine, so run it on demand with --ignored.
+#[test]
+#[ignore = "benchmark"]
+fn test_set_field_mut_builds_large_objects_in_linear_time() {
+    use std::time::Instant;
+    const FIELDS: usize = 10_000;
+
+    let time_build = |in_place: bool| {
+        let start = Instant::now();
+        let vm = run_program(object_build_program(FIELDS, in_place));
+        let elapsed = start.elapsed();
+        assert_eq!(vm.stack.last(), Some(&Value::Int(FIELDS as i64)));
+        elapsed
+    };
+    let copying = time_build(false);
+    let in_place = time_build(true);
+    println!("{}-field build: LOAD/SET_FIELD/STORE {:?}, SET_FIELD_MUT {:?}", FIELDS, copying, in_place);
+    assert!(in_place * 10 < copying, "SET_FIELD_MUT took {:?}, copying took {:?}", in_place, copying);
+}
+
+// Sums 1..=n into "total"
+fn sum_loop(n: i64) -> Vec<OpCode> {
+    let (i, total) = ("i".to_string(), "total".to_string());
+    vec![
+        OpCode::PushInt(n),
+        OpCode::Store(i.clone()),
+        OpCode::PushInt(0),
+        OpCode::Store(total.clone()),
+        OpCode::Load(i.clone()), // 4
+        OpCode::Jz(15),
+        OpCode::Load(total.clone()),
+        OpCode::Load(i.clone()),
+        OpCode::Add,
+        OpCode::Store(total.clone()),
+        OpCode::Load(i.clone()),
+        OpCode::PushInt(1),
+        OpCode::Sub,
+        OpCode::Store(i.clone()),
+        OpCode::Jmp(4),
+        OpCode::Load(total.clone()), // 15
+        OpCode::Halt,
+    ]
+}
+
+#[test]
+fn test_run_n_resumes_where_it_stopped() {
+    let expected = run_program(sum_loop(50));
+    assert_eq!(expected.stack, vec![Value::Int(1275)]);
+
+    let mut vm = VM::new(sum_loop(50));
+    assert_eq!(vm.run_n(0).unwrap(), RunStatus::Running);
+    assert_eq!(vm.run_n(300).unwrap(), RunStatus::Yielded(true));
+    assert!(vm.stack.is_empty());
+    assert_eq!(vm.run_n(usize::MAX).unwrap(), RunStatus::Halted);
+
+    assert_eq!(vm.stack, expected.stack);
+    assert_eq!(vm.instruction_count, expected.instruction_count);
+    assert_eq!(vm.run_n(10).unwrap(), RunStatus::Halted);
+}
+
+#[test]
+fn test_catch_block_reads_exception_fields() {
+    let source = "TRY catch\nPUSH_STR \"disk full\"\nTHROW\nEND_TRY\nHALT\nLABEL catch\nCATCH\nDUP\n\
+                  EXCEPTION_MESSAGE\nSTORE message\nEXCEPTION_TRACE\nLOAD message\nHALT\n";
+    let (vm, result) = run_source("exception_fields", source);
+
+    result.expect("Program failed");
+    assert_eq!(vm.stack, vec![
+        Value::List(vec![Value::Str("at instruction 2 (line 3)".to_string())].into()),
+        Value::Str("disk full".to_string()),
+    ]);
+}
+
+#[test]
+fn test_exception_message_rejects_other_values() {
+    let mut vm = VM::new(vec![OpCode::PushStr("not thrown".to_string()), OpCode::ExceptionMessage, OpCode::Halt]);
+    match vm.run() {
+        Err(VMError::TypeMismatch { operation, .. }) => assert_eq!(operation, "EXCEPTION_MESSAGE"),
+        other => panic!("Expected TypeMismatch, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_exception_trace_lists_every_call_frame() {
+    let source = "TRY catch\nCALL outer\nEND_TRY\nHALT\n\
+                  LABEL outer\nPUSH_INT 1\nCALL inner\nADD\nRET\n\
+                  LABEL inner\nPUSH_STR \"deep failure\"\nTHROW\nRET\n\
+                  LABEL catch\nCATCH\nHALT\n";
+    let (vm, result) = run_source("nested_trace", source);
+
+    result.expect("Program failed");
+    match vm.stack.last() {
+        Some(Value::Exception { message, stack_trace }) => {
+            assert_eq!(message, "deep failure");
+            assert_eq!(stack_trace, &vec![
+                "at instruction 9 (line 12) in fn@0x0008".to_string(),
+                "at instruction 5 (line 7) in fn@0x0004".to_string(),
+                "at instruction 1 (line 2)".to_string(),
+            ]);
+        }
+        other => panic!("Expected exception on stack, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_runtime_error_trace_includes_callers() {
+    let vm = run_program(vec![
+        OpCode::Try { catch_addr: 3 },
+        OpCode::Call { addr: 5, params: vec![] },
+        OpCode::Halt,
+        OpCode::Catch, // 3
+        OpCode::Halt,
+        OpCode::PushInt(1), // 5
+        OpCode::PushStr("x".to_string()),
+        OpCode::Add,
+        OpCode::Ret,
+    ]);
+
+    match vm.stack.last() {
+        Some(Value::Exception { stack_trace, .. }) => assert_eq!(stack_trace, &vec![
+            "at instruction 7 in fn@0x0005".to_string(),
+            "at instruction 1".to_string(),
+        ]),
+        other => panic!("Expected exception on stack, got {:?}", other),
+    }
+}
+
+// Source lines that append `entry` to the string in `log`
+fn log(entry: &str) -> String {
+    format!("LOAD log\nPUSH_STR \"{}\"\nCONCAT\nSTORE log\n", entry)
+}
+
+// Run `code` with an empty `log` defined
+fn run_logged(name: &str, code: &str) -> (VM, Result<(), VMError>) {
+    run_source(name, &format!("PUSH_STR \"\"\nSTORE log\n{}HALT\n", code))
+}
+
+// TRY/CATCH/FINALLY: the outer TRY targets the FINALLY block, so the cleanup also runs
+// when the catch block itself throws
+fn try_catch_finally(body: &str, handler: &str) -> String {
+    format!(
+        "TRY cleanup\nTRY handler\n{body}END_TRY\nJMP after_catch\n\
+         LABEL handler\nCATCH\n{handler}LABEL after_catch\nEND_TRY\n\
+         LABEL cleanup\nFINALLY\n{cleanup}END_FINALLY\n{done}",
+        cleanup = log("finally,"),
+        done = log("done,"),
+    )
+}
+
+fn global(vm: &VM, name: &str) -> Value {
+    vm.variables[0].get(name).cloned().unwrap_or_else(|| panic!("{} is not set", name))
+}
+
+#[test]
+fn test_finally_runs_on_normal_exit() {
+    let (vm, result) = run_logged("finally_normal", &try_catch_finally(&log("body,"), &log("catch,")));
+    result.expect("Program failed");
+    assert_eq!(global(&vm, "log"), Value::Str("body,finally,done,".to_string()));
+    assert!(vm.try_stack.is_empty() && vm.finally_stack.is_empty());
+}
+
+#[test]
+fn test_finally_runs_after_caught_exception() {
+    let body = format!("PUSH_STR \"boom\"\nTHROW\n{}", log("unreachable,"));
+    let handler = format!("EXCEPTION_MESSAGE\nSTORE error\n{}", log("catch,"));
+    let (vm, result) = run_logged("finally_caught", &try_catch_finally(&body, &handler));
+
+    result.expect("Program failed");
+    assert_eq!(global(&vm, "log"), Value::Str("catch,finally,done,".to_string()));
+    assert_eq!(global(&vm, "error"), Value::Str("boom".to_string()));
+    assert!(vm.stack.is_empty());
+}
+
+#[test]
+fn test_finally_rethrows_to_enclosing_handler() {
+    // The catch block rethrows; FINALLY runs, then END_FINALLY hands the exception on
+    let inner = try_catch_finally("PUSH_STR \"boom\"\nTHROW\n", &format!("{}THROW\n", log("catch,")));
+    let code = format!(
+        "TRY outer\n{inner}END_TRY\nHALT\nLABEL outer\nCATCH\nEXCEPTION_MESSAGE\nSTORE error\n{}",
+        log("outer,"),
+    );
+    let (vm, result) = run_logged("finally_rethrown", &code);
+
+    result.expect("Program failed");
+    assert_eq!(global(&vm, "log"), Value::Str("catch,finally,outer,".to_string()));
+    assert_eq!(global(&vm, "error"), Value::Str("boom".to_string()));
+    assert!(vm.try_stack.is_empty() && vm.finally_stack.is_empty());
+}
+
+#[test]
+fn test_uncaught_exception_still_runs_finally() {
+    let code = format!("TRY cleanup\nPUSH_STR \"boom\"\nTHROW\nEND_TRY\nLABEL cleanup\nFINALLY\n{}END_FINALLY\n", log("finally,"));
+    let (vm, result) = run_logged("finally_uncaught", &code);
+
+    assert_eq!(global(&vm, "log"), Value::Str("finally,".to_string()));
+    match result {
+        Err(error) => assert!(error.to_string().contains("Unhandled exception: boom"), "{}", error),
+        Ok(()) => panic!("The exception was swallowed"),
+    }
+}
+
+#[test]
+fn test_rethrow_keeps_the_original_trace() {
+    let source = "TRY outer\nTRY inner\nCALL fail\nEND_TRY\nEND_TRY\nHALT\n\
+                  LABEL inner\nCATCH\nDUP\nEXCEPTION_MESSAGE\nSTORE logged\nRETHROW\n\
+                  LABEL outer\nCATCH\nHALT\n\
+                  LABEL fail\nPUSH_STR \"bad input\"\nTHROW\nRET\n";
+    let (vm, result) = run_source("rethrow", source);
+
+    result.expect("Program failed");
+    assert_eq!(global(&vm, "logged"), Value::Str("bad input".to_string()));
+    match &vm.stack[..] {
+        [Value::Exception { message, stack_trace }] => {
+            assert_eq!(message, "bad input");
+            assert_eq!(stack_trace, &vec![
+                "at instruction 14 (line 18) in fn@0x000D".to_string(),
+                "at instruction 2 (line 3)".to_string(),
+                "rethrown at instruction 10 (line 12)".to_string(),
+            ]);
+        }
+        other => panic!("Expected the rethrown exception, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_rethrow_needs_an_exception() {
+    let mut vm = VM::new(vec![OpCode::PushStr("plain".to_string()), OpCode::Rethrow]);
+    assert!(matches!(vm.run(), Err(VMError::TypeMismatch { .. })));
+}
+
+#[test]
+fn test_assert_passes_truthy_values_and_fails_on_falsy_ones() {
+    let source = "PUSH_INT 3\nASSERT \"three is truthy\"\nPUSH_STR \"\"\nASSERT\nTRUE\nASSERT\n\
+                  PUSH_INT 2\nPUSH_INT 2\nEQ\nASSERT \"2 == 2\"\nPUSH_INT 1\nSTORE done\nHALT\n";
+    let (vm, result) = run_source("assert_passes", source);
+    result.expect("Truthy assertions failed");
+    assert_eq!(global(&vm, "done"), Value::Int(1));
+    assert!(vm.stack.is_empty());
+
+    let (_, result) = run_source("assert_message", "PUSH_INT 1\nPUSH_INT 2\nGT\nASSERT \"1 > 2\"\nHALT\n");
+    match result {
+        Err(VMError::AtSourceLine { line: 4, error, .. }) => match *error {
+            VMError::AssertionFailed { message } => assert_eq!(message, "1 > 2"),
+            other => panic!("Expected AssertionFailed, got {:?}", other),
+        },
+        other => panic!("Expected a failed assertion on line 4, got {:?}", other),
+    }
+
+    for falsy in [OpCode::PushInt(0), OpCode::PushBool(false), OpCode::Null] {
+        let mut vm = VM::new(vec![falsy.clone(), OpCode::Assert(None), OpCode::Halt]);
+        match vm.run() {
+            Err(VMError::AssertionFailed { message }) => assert!(message.starts_with("got "), "{}", message),
+            other => panic!("{:?} passed ASSERT: {:?}", falsy, other),
+        }
+    }
+}
+
+#[test]
+fn test_debug_print_consumes_its_value() {
+    let vm = run_program(vec![
+        OpCode::PushInt(1),
+        OpCode::PushStr("shown".to_string()),
+        OpCode::PushInt(2),
+        OpCode::MakeList(2),
+        OpCode::DebugPrint,
+        OpCode::Halt,
+    ]);
+    assert_eq!(vm.stack, vec![Value::Int(1)]);
+}
+
+#[test]
+fn test_env_vars_pushes_every_variable() {
+    std::env::set_var("TTVM_ENV_VARS_TEST", "visible");
+    let vm = run_program(vec![OpCode::EnvVars, OpCode::Halt]);
+
+    let Some(Value::Object(vars)) = vm.stack.last() else { panic!("Got {:?}", vm.stack) };
+    assert_eq!(vars.get("TTVM_ENV_VARS_TEST"), Some(&Value::Str("visible".to_string())));
+    let names: Vec<&String> = vars.keys().collect();
+    assert!(names.windows(2).all(|pair| pair[0] <= pair[1]), "Not sorted: {:?}", names);
+}
+
+#[test]
+fn test_get_args_returns_program_args() {
+    let mut vm = VM::new(vec![OpCode::GetArgs, OpCode::Halt]);
+    vm.program_args = vec!["data.csv".to_string(), "--strict".to_string()];
+    vm.run().unwrap();
+    assert_eq!(vm.stack, vec![Value::List(vec![Value::Str("data.csv".to_string()), Value::Str("--strict".to_string())].into())]);
+
+    // Without any, the test binary's own arguments don't leak in
+    let vm = run_program(vec![OpCode::GetArgs, OpCode::Halt]);
+    assert_eq!(vm.stack, vec![Value::List(vec![].into())]);
+}
+
+#[test]
+fn test_path_join_and_decompose() {
+    let vm = run_program(vec![
+        OpCode::PushStr("data/in".to_string()),
+        OpCode::PushStr("report.csv".to_string()),
+        OpCode::PathJoin,
+        OpCode::Dup,
+        OpCode::Dup,
+        OpCode::PathBasename,
+        OpCode::Store("base".to_string()),
+        OpCode::PathDirname,
+        OpCode::Store("dir".to_string()),
+        OpCode::Store("joined".to_string()),
+        // A root has neither a basename nor a parent
+        OpCode::PushStr("/".to_string()),
+        OpCode::Dup,
+        OpCode::PathBasename,
+        OpCode::Store("root_base".to_string()),
+        OpCode::PathDirname,
+        OpCode::Store("root_dir".to_string()),
+        OpCode::Halt,
+    ]);
+    let joined = std::path::Path::new("data/in").join("report.csv");
+    assert_eq!(global(&vm, "joined"), Value::Str(joined.to_string_lossy().into_owned()));
+    assert_eq!(global(&vm, "base"), Value::Str("report.csv".to_string()));
+    assert_eq!(global(&vm, "dir"), Value::Str("data/in".to_string()));
+    assert_eq!(global(&vm, "root_base"), Value::Str(String::new()));
+    assert_eq!(global(&vm, "root_dir"), Value::Str(String::new()));
+}
+
+#[test]
+fn test_set_cwd_to_missing_directory_fails() {
+    let before = std::env::current_dir().unwrap();
+    let mut vm = VM::new(vec![OpCode::PushStr("/no/such/ttvm/dir".to_string()), OpCode::SetCwd, OpCode::Halt]);
+    let result = vm.run();
+    assert!(matches!(result, Err(VMError::FileError { ref filename, .. }) if filename == "/no/such/ttvm/dir"), "Got {:?}", result);
+    assert_eq!(std::env::current_dir().unwrap(), before);
+}
+
+// A fresh directory under the system temp dir holding a.txt, sub/b.txt and sub/deeper/c.txt
+fn file_tree(name: &str) -> std::path::PathBuf {
+    let root = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("sub").join("deeper")).unwrap();
+    std::fs::write(root.join("a.txt"), "hello").unwrap();
+    std::fs::write(root.join("sub").join("b.txt"), "").unwrap();
+    std::fs::write(root.join("sub").join("deeper").join("c.txt"), "").unwrap();
+    root
+}
+
+#[test]
+fn test_list_dir_recursive_returns_relative_paths() {
+    let root = file_tree("ttvm_list_dir_recursive");
+    let vm = run_program(vec![
+        OpCode::PushStr(root.to_string_lossy().into_owned()),
+        OpCode::ListDirRecursive,
+        OpCode::Halt,
+    ]);
+
+    let expected: Vec<Value> = ["a.txt", "sub", "sub/b.txt", "sub/deeper", "sub/deeper/c.txt"].iter()
+        .map(|path| Value::Str(std::path::Path::new(path).to_string_lossy().into_owned()))
+        .collect();
+    assert_eq!(vm.stack, vec![Value::List(expected.into())]);
+}
+
+#[test]
+fn test_stat_reports_file_metadata() {
+    let root = file_tree("ttvm_stat");
+    let file = root.join("a.txt").to_string_lossy().into_owned();
+    let vm = run_program(vec![
+        OpCode::PushStr(file.clone()),
+        OpCode::Stat,
+        OpCode::PushStr(root.join("sub").to_string_lossy().into_owned()),
+        OpCode::Stat,
+        OpCode::Halt,
+    ]);
+
+    let Value::Object(stat) = &vm.stack[0] else { panic!("Got {:?}", vm.stack) };
+    assert_eq!(stat.get("is_file"), Some(&Value::Bool(true)));
+    assert_eq!(stat.get("is_dir"), Some(&Value::Bool(false)));
+    assert_eq!(stat.get("size"), Some(&Value::Int(5)));
+    let written = std::fs::metadata(&file).unwrap().modified().unwrap()
+        .duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+    assert_eq!(stat.get("modified"), Some(&Value::Int(written)));
+
+    let Value::Object(stat) = &vm.stack[1] else { panic!("Got {:?}", vm.stack) };
+    assert_eq!(stat.get("is_dir"), Some(&Value::Bool(true)));
+    assert_eq!(stat.get("is_file"), Some(&Value::Bool(false)));
+
+    let mut vm = VM::new(vec![OpCode::PushStr(root.join("missing").to_string_lossy().into_owned()), OpCode::Stat]);
+    let result = vm.run();
+    assert!(matches!(result, Err(VMError::FileError { .. })), "Got {:?}", result);
+}
+
+const SUM_AND_PRODUCT: &str = "\
+PUSH_INT 17
+PUSH_INT 5
+CALL sum_and_product a b
+STORE product
+STORE sum
+LOAD sum
+LOAD product
+HALT
+LABEL sum_and_product
+LOAD a
+LOAD b
+ADD
+LOAD a
+LOAD b
+MUL
+RET 2
+";
+
+#[test]
+fn test_ret_n_hands_both_values_to_the_caller() {
+    let (vm, result) = run_source("ret_two_values", SUM_AND_PRODUCT);
+    result.expect("Program failed");
+    assert_eq!(vm.stack, vec![Value::Int(22), Value::Int(85)]);
+}
+
+#[test]
+fn test_ret_n_with_the_wrong_number_of_values_fails() {
+    // The caller's own values below the frame don't count towards the function's results
+    let source = SUM_AND_PRODUCT.replace("PUSH_INT 17\n", "PUSH_STR \"keep\"\nPUSH_INT 17\n").replace("MUL\n", "MUL\nADD\n");
+    let (vm, result) = run_source("ret_wrong_count", &source);
+
+    match result {
+        Err(VMError::AtSourceLine { line, error, .. }) => {
+            assert_eq!(line, 18);
+            match *error {
+                VMError::ReturnCountMismatch { declared, left } => assert_eq!((declared, left), (2, 1)),
+                other => panic!("Expected ReturnCountMismatch, got {:?}", other),
+            }
+        }
+        other => panic!("Expected a located error, got {:?}", other),
+    }
+    assert_eq!(vm.stack[0], Value::Str("keep".to_string()));
+}
+
+// Processes under the SMP scheduler, the default engine, return the same way
+#[test]
+fn test_ret_n_in_a_process() {
+    let program = |results: Vec<i64>| {
+        let mut program = vec![
+            OpCode::PushStr("keep".to_string()),
+            OpCode::Call { addr: 3, params: vec![] },
+            OpCode::Halt,
+        ];
+        program.extend(results.into_iter().map(OpCode::PushInt));
+        program.push(OpCode::RetN(2));
+        program
+    };
+
+    let (mut proc, _) = TinyProc::new(1, program(vec![22, 85]));
+    proc.run_until_yield().unwrap();
+    assert_eq!(proc.stack, vec![Value::Str("keep".to_string()), Value::Int(22), Value::Int(85)]);
+
+    let (mut proc, _) = TinyProc::new(1, program(vec![22]));
+    match proc.run_until_yield() {
+        Err(VMError::ReturnCountMismatch { declared, left }) => assert_eq!((declared, left), (2, 1)),
+        other => panic!("Expected ReturnCountMismatch, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_variadic_parameter_collects_extra_arguments() {
+    // Each call pushes its extra arguments, then how many there are
+    let source = "\
+PUSH_STR \"none\"
+PUSH_INT 0
+CALL describe label ...rest
+PUSH_STR \"one\"
+PUSH_INT 7
+PUSH_INT 1
+CALL describe label ...rest
+PUSH_STR \"many\"
+PUSH_INT 1
+PUSH_STR \"two\"
+PUSH_INT 3
+PUSH_INT 3
+CALL describe label ...rest
+HALT
+LABEL describe
+LOAD label
+LOAD rest
+MAKE_LIST 2
+RET
+";
+    let (vm, result) = run_source("variadic_call", source);
+    result.expect("Program failed");
+
+    let described = |label: &str, rest: Vec<Value>| Value::List(vec![Value::Str(label.to_string()), Value::List(rest.into())].into());
+    assert_eq!(vm.stack, vec![
+        described("none", vec![]),
+        described("one", vec![Value::Int(7)]),
+        described("many", vec![Value::Int(1), Value::Str("two".to_string()), Value::Int(3)]),
+    ]);
+}
+
+#[test]
+fn test_variadic_function_value_and_bad_counts() {
+    let source = "PUSH_INT 4\nPUSH_INT 5\nPUSH_INT 2\nMAKE_FUNCTION count ...xs\nCALL_FUNCTION\nHALT\nLABEL count\nLOAD xs\nLEN\nRET\n";
+    let (vm, result) = run_source("variadic_function_value", source);
+    result.expect("Program failed");
+    assert_eq!(vm.stack, vec![Value::Int(2)]);
+
+    // A count that isn't a non-negative int, or more extras than the stack holds
+    for (name, count) in [("variadic_no_count", "PUSH_STR \"2\""), ("variadic_negative", "PUSH_INT -1")] {
+        let (_, result) = run_source(name, &source.replace("PUSH_INT 2\n", &format!("{}\n", count)));
+        match result {
+            Err(VMError::AtSourceLine { error, .. }) => assert!(matches!(*error, VMError::TypeMismatch { .. }), "{}: {:?}", name, error),
+            other => panic!("{}: expected a type mismatch, got {:?}", name, other),
+        }
+    }
+    let (_, result) = run_source("variadic_short", &source.replace("PUSH_INT 2\n", "PUSH_INT 5\n"));
+    match result {
+        Err(VMError::AtSourceLine { error, .. }) => assert!(matches!(*error, VMError::InsufficientStackItems { needed: 5, available: 2 }), "{:?}", error),
+        other => panic!("Expected too few arguments, got {:?}", other),
+    }
+
+    match parse_source("CALL f ...rest x\nHALT\nLABEL f\nRET\n") {
+        Err(VMError::ParseError { line, instruction }) => {
+            assert_eq!(line, 1);
+            assert!(instruction.contains("...rest"), "{}", instruction);
+        }
+        other => panic!("Expected ParseError, got {:?}", other.map(|_| ())),
+    }
+}
+
+fn expect_type_mismatch(name: &str, source: &str, message: &str) {
+    let (_, result) = run_source(name, source);
+    match result {
+        Err(VMError::AtSourceLine { error, .. }) => {
+            assert!(matches!(*error, VMError::TypeMismatch { .. }), "{}: {:?}", name, error);
+            assert_eq!(error.to_string(), message, "{}", name);
+        }
+        other => panic!("{}: expected a type mismatch, got {:?}", name, other),
+    }
+}
+
+#[test]
+fn test_typed_function_checks_arguments_and_result() {
+    let source = "PUSH_INT 2\nPUSH_FLOAT 1.5\nMAKE_FUNCTION scale n:int by:number ->float\nCALL_FUNCTION\nHALT\nLABEL scale\nLOAD n\nLOAD by\nMUL\nRET\n";
+    let (vm, result) = run_source("typed_function", source);
+    result.expect("Program failed");
+    assert_eq!(vm.stack, vec![Value::Float(3.0)]);
+
+    // `number` takes ints as well
+    let (vm, result) = run_source("typed_function_int", &source.replace("PUSH_FLOAT 1.5", "PUSH_INT 3").replace("->float", "->int"));
+    result.expect("Program failed");
+    assert_eq!(vm.stack, vec![Value::Int(6)]);
+
+    // The call fails at the boundary, before the body runs
+    expect_type_mismatch("typed_function_bad_arg", &source.replace("PUSH_INT 2", "PUSH_STR \"2\""),
+        "CALL_FUNCTION expects int for parameter n but got Str(\"2\")");
+    expect_type_mismatch("typed_function_bad_result", &source.replace("->float", "->string"),
+        "RET expects string as the return value but got Float(3.0)");
+    expect_type_mismatch("typed_direct_call", "PUSH_INT 1\nPUSH_BOOL true\nCALL f a:int b:list\nHALT\nLABEL f\nRET\n",
+        "CALL expects list for parameter b but got Bool(true)");
+    expect_type_mismatch("typed_rest", "PUSH_INT 1\nPUSH_STR \"x\"\nPUSH_INT 2\nCALL f ...xs:int\nHALT\nLABEL f\nRET\n",
+        "CALL expects int for parameter xs but got Str(\"x\")");
+
+    // Unannotated parameters accept anything, and a mismatch is catchable
+    let (vm, result) = run_source("typed_catch", "TRY catch\nPUSH_INT 1\nPUSH_INT 2\nCALL f a b:object\nEND_TRY\nLABEL catch\nCATCH\nEXCEPTION_MESSAGE\nHALT\nLABEL f\nRET\n");
+    result.expect("Program failed");
+    assert!(matches!(vm.stack.last(), Some(Value::Str(message)) if message.contains("object for parameter b")), "{:?}", vm.stack);
+
+    match parse_source("MAKE_FUNCTION f n:integer\nHALT\nLABEL f\nRET\n") {
+        Err(VMError::ParseError { line, instruction }) => {
+            assert_eq!(line, 1);
+            assert!(instruction.contains("unknown type integer"), "{}", instruction);
+        }
+        other => panic!("Expected ParseError, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_dump_scope_shows_ip_next_instruction_and_stack() {
+    let source = "PUSH_STR \"kept\"\nPUSH_INT 3\nCALL show n\nHALT\nLABEL show\nPUSH_INT 5\nDUMP_SCOPE\nSTORE five\nRET\n";
+    let path = std::env::temp_dir().join("dump_scope_state.ttvm");
+    std::fs::write(&path, source).expect("Failed to write source");
+    let (program, lines) = parse_program_with_lines(path.to_str().unwrap()).expect("Failed to parse program");
+    let mut vm = VM::new(program);
+    vm.set_source_lines(lines);
+    while vm.instructions[vm.ip] != OpCode::DumpScope {
+        vm.run_n(1).unwrap();
+    }
+
+    let ip = vm.ip;
+    assert_eq!(vm.dump_state().unwrap(), format!("\
+ip: {} (line 7)
+next: STORE five
+call depth: 1
+stack (2 values, top last):
+  [0]: str \"kept\"
+  [1]: int 5
+Current scope:
+  n: int 3", ip));
+    vm.run().unwrap();
+    assert_eq!(vm.stack, vec![Value::Str("kept".to_string())]);
+}
+
+#[test]
+fn test_destructure_binds_nested_parts() {
+    let vm = run_program(parse_source("\
+MAKE_OBJECT
+MAKE_OBJECT
+PUSH_STR \"Ada\"
+SET_FIELD name
+SET_FIELD user
+PUSH_STR \"a\"
+PUSH_STR \"b\"
+PUSH_STR \"c\"
+MAKE_LIST 3
+SET_FIELD tags
+PUSH_INT 36
+SET_FIELD age
+DESTRUCTURE {user:{name} tags:[first _ ...rest] age:years}
+PUSH_INT 1
+PUSH_INT 2
+MAKE_LIST 2
+DESTRUCTURE [x y]
+HALT
+").expect("Failed to parse program").0);
+
+    let scope = &vm.variables[0];
+    assert_eq!(scope.get("name"), Some(&Value::Str("Ada".to_string())));
+    assert_eq!(scope.get("first"), Some(&Value::Str("a".to_string())));
+    assert_eq!(scope.get("rest"), Some(&Value::List(vec![Value::Str("c".to_string())].into())));
+    assert_eq!(scope.get("years"), Some(&Value::Int(36)));
+    assert_eq!(scope.get("x"), Some(&Value::Int(1)));
+    assert_eq!(scope.get("y"), Some(&Value::Int(2)));
+    assert!(!scope.contains_key("_") && !scope.contains_key("user") && !scope.contains_key("age"));
+    assert!(vm.stack.is_empty());
+}
+
+#[test]
+fn test_destructure_shape_mismatch() {
+    let cases = [
+        ("PUSH_INT 1\nPUSH_INT 2\nPUSH_INT 3\nMAKE_LIST 3\nDESTRUCTURE [a b]\n", "expected 2 items but the list has 3"),
+        ("MAKE_OBJECT\nPUSH_INT 1\nSET_FIELD a\nDESTRUCTURE {a b}\n", "object has no key b"),
+        ("PUSH_INT 1\nMAKE_LIST 1\nDESTRUCTURE {a}\n", "expected an object"),
+    ];
+    for (source, reason) in cases {
+        let mut vm = VM::new(parse_source(source).expect("Failed to parse program").0);
+        match vm.run() {
+            Err(error @ VMError::PatternMismatch { .. }) => assert!(error.to_string().contains(reason), "{}", error),
+            other => panic!("Expected PatternMismatch for {:?}, got {:?}", source, other),
+        }
+        // Nothing is bound when the match fails part way
+        assert!(!vm.variables[0].contains_key("a"), "{:?} bound a", source);
+    }
+
+    // A mismatch is catchable like any other runtime error
+    let vm = run_program(parse_source("\
+TRY catch
+PUSH_INT 5
+DESTRUCTURE [n]
+END_TRY
+LABEL catch
+CATCH
+EXCEPTION_MESSAGE
+HALT
+").expect("Failed to parse program").0);
+    assert!(matches!(vm.stack.last(), Some(Value::Str(message)) if message.contains("expected a list")), "{:?}", vm.stack);
+}
+
+#[test]
+fn test_execute_native_halt_is_an_error() {
+    // Only run() knows how to stop, so HALT handed to execute_native used to hit an unreachable!
+    let mut vm = VM::new(vec![]);
+    match vm.execute_native(&OpCode::Halt, vec![]) {
+        Err(VMError::UnsupportedOperation(reason)) => assert!(reason.contains("HALT"), "{}", reason),
+        other => panic!("Expected UnsupportedOperation, got {:?}", other),
+    }
+}